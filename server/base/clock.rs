@@ -145,6 +145,35 @@ where
     }
 }
 
+/// Maps monotonic times to realtime via an offset fixed at construction.
+///
+/// Times derived this way advance steadily even if the realtime clock is stepped, as by NTP or an
+/// administrator. [`RealtimeOffset::check`] detects such a step, so that the caller can switch to
+/// a fresh offset at a convenient point.
+#[derive(Copy, Clone, Debug)]
+pub struct RealtimeOffset(Duration);
+
+impl RealtimeOffset {
+    pub fn new<C: Clocks + ?Sized>(clocks: &C) -> Self {
+        RealtimeOffset(clocks.realtime() - clocks.monotonic())
+    }
+
+    pub fn to_realtime(&self, monotonic: Timespec) -> Timespec {
+        monotonic + self.0
+    }
+
+    /// Returns how far the realtime clock has been stepped since construction, if more than
+    /// `threshold` in either direction.
+    pub fn check<C: Clocks + ?Sized>(&self, clocks: &C, threshold: Duration) -> Option<Duration> {
+        let step = (clocks.realtime() - clocks.monotonic()) - self.0;
+        if step > threshold || step < -threshold {
+            Some(step)
+        } else {
+            None
+        }
+    }
+}
+
 /// Simulated clock for testing.
 #[derive(Clone)]
 pub struct SimulatedClocks(Arc<SimulatedClocksInner>);
@@ -152,6 +181,9 @@ pub struct SimulatedClocks(Arc<SimulatedClocksInner>);
 struct SimulatedClocksInner {
     boot: Timespec,
     uptime: Mutex<Duration>,
+
+    /// The total of steps applied via [`SimulatedClocks::step_realtime`].
+    realtime_steps: Mutex<Duration>,
 }
 
 impl SimulatedClocks {
@@ -159,13 +191,20 @@ impl SimulatedClocks {
         SimulatedClocks(Arc::new(SimulatedClocksInner {
             boot,
             uptime: Mutex::new(Duration::seconds(0)),
+            realtime_steps: Mutex::new(Duration::seconds(0)),
         }))
     }
+
+    /// Steps the realtime clock (but not the monotonic clock), as NTP or an administrator might.
+    pub fn step_realtime(&self, by: Duration) {
+        let mut l = self.0.realtime_steps.lock().unwrap();
+        *l = *l + by;
+    }
 }
 
 impl Clocks for SimulatedClocks {
     fn realtime(&self) -> Timespec {
-        self.0.boot + *self.0.uptime.lock().unwrap()
+        self.0.boot + *self.0.uptime.lock().unwrap() + *self.0.realtime_steps.lock().unwrap()
     }
     fn monotonic(&self) -> Timespec {
         Timespec::new(0, 0) + *self.0.uptime.lock().unwrap()
@@ -0,0 +1,291 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Process-wide accounting of memory used for buffering, with an optional budget.
+//!
+//! Buffers which grow with load (rather than with configuration) are charged to [`BUDGET`] via
+//! [`Charge`] guards. When the total exceeds the configured limit, optional work sheds load:
+//! live viewers are disconnected, new sample file reads are rejected, and recent frame buffers
+//! shrink. Recording itself is never shed; the budget exists to keep the OOM killer from taking
+//! it down along with everything else.
+//!
+//! The accounting covers only these buffers, not the whole process, so the limit should be set
+//! well below the memory actually available.
+//!
+//! Separately, [`BufPool`] reuses short-lived buffers to reduce allocator churn.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// A kind of buffer charged to the budget.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Category {
+    /// Each stream's recent live frames, kept for resuming live viewers.
+    RecentFrames,
+
+    /// Indexes of recordings being written.
+    PendingWrites,
+
+    /// Sample file chunks read for HTTP responses and not yet sent.
+    ReaderChunks,
+
+    /// Live segments being sent to viewers.
+    LiveSegments,
+
+    /// Each preview rendition's frames since its latest key frame.
+    Previews,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Category::RecentFrames,
+        Category::PendingWrites,
+        Category::ReaderChunks,
+        Category::LiveSegments,
+        Category::Previews,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::RecentFrames => "recent_frames",
+            Category::PendingWrites => "pending_writes",
+            Category::ReaderChunks => "reader_chunks",
+            Category::LiveSegments => "live_segments",
+            Category::Previews => "previews",
+        }
+    }
+}
+
+/// Memory usage by [`Category`], with an optional limit on the total.
+pub struct Budget {
+    /// The limit in bytes, or 0 for none.
+    limit: AtomicU64,
+    used: [AtomicU64; Category::ALL.len()],
+}
+
+/// The process-wide budget.
+pub static BUDGET: Budget = Budget::new();
+
+impl Budget {
+    pub const fn new() -> Self {
+        Budget {
+            limit: AtomicU64::new(0),
+            used: [const { AtomicU64::new(0) }; Category::ALL.len()],
+        }
+    }
+
+    /// Sets the limit on total usage, or removes it with `None`.
+    pub fn set_limit(&self, limit: Option<u64>) {
+        self.limit.store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        match self.limit.load(Ordering::Relaxed) {
+            0 => None,
+            l => Some(l),
+        }
+    }
+
+    /// Returns the bytes currently charged to `category`.
+    pub fn used(&self, category: Category) -> u64 {
+        self.used[category as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes currently charged to all categories.
+    pub fn total(&self) -> u64 {
+        self.used.iter().map(|u| u.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns true if there's a limit and usage exceeds it, so optional work should be shed.
+    pub fn is_exceeded(&self) -> bool {
+        self.limit().is_some_and(|l| self.total() > l)
+    }
+
+    /// Charges `bytes` to `category` until the returned guard is dropped.
+    pub fn charge(&self, category: Category, bytes: u64) -> Charge<'_> {
+        self.used[category as usize].fetch_add(bytes, Ordering::Relaxed);
+        Charge {
+            budget: self,
+            category,
+            bytes,
+        }
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bytes charged to a [`Budget`], released on drop.
+pub struct Charge<'a> {
+    budget: &'a Budget,
+    category: Category,
+    bytes: u64,
+}
+
+impl Charge<'_> {
+    /// Changes the charged amount, as when the buffer it accounts for grows or shrinks.
+    pub fn set(&mut self, bytes: u64) {
+        let used = &self.budget.used[self.category as usize];
+        if bytes > self.bytes {
+            used.fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            used.fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl std::fmt::Debug for Charge<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Charge")
+            .field("category", &self.category)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+impl Drop for Charge<'_> {
+    fn drop(&mut self) {
+        self.budget.used[self.category as usize].fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// A pool of reusable byte buffers.
+///
+/// This is for buffers which are built, used briefly, and discarded at a high rate, such as
+/// while many clients scrub through video. Reusing them avoids repeatedly asking the allocator
+/// for similar large blocks, which can fragment the heap and return memory to the OS only to
+/// request it again.
+pub struct BufPool {
+    bufs: Mutex<Vec<Vec<u8>>>,
+
+    /// The maximum number of idle buffers to keep.
+    max_bufs: usize,
+
+    /// The maximum capacity of a buffer to keep. Larger ones are freed on drop.
+    max_capacity: usize,
+}
+
+impl BufPool {
+    pub const fn new(max_bufs: usize, max_capacity: usize) -> Self {
+        BufPool {
+            bufs: Mutex::new(Vec::new()),
+            max_bufs,
+            max_capacity,
+        }
+    }
+
+    /// Returns an empty buffer with at least the given capacity, reusing an idle one if possible.
+    pub fn get(&self, capacity: usize) -> PooledBuf<'_> {
+        let mut buf = self.bufs.lock().unwrap().pop().unwrap_or_default();
+        buf.reserve(capacity);
+        PooledBuf { pool: self, buf }
+    }
+
+    /// Returns the number of idle buffers.
+    pub fn idle(&self) -> usize {
+        self.bufs.lock().unwrap().len()
+    }
+}
+
+/// A buffer from a [`BufPool`], returned to it on drop.
+pub struct PooledBuf<'a> {
+    pool: &'a BufPool,
+    buf: Vec<u8>,
+}
+
+impl Deref for PooledBuf<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl std::fmt::Debug for PooledBuf<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.buf.len())
+            .field("capacity", &self.buf.capacity())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuf<'_> {
+    fn drop(&mut self) {
+        if self.buf.capacity() == 0 || self.buf.capacity() > self.pool.max_capacity {
+            return;
+        }
+        let mut bufs = self.pool.bufs.lock().unwrap();
+        if bufs.len() < self.pool.max_bufs {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.clear();
+            bufs.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges() {
+        let b = Budget::new();
+        assert!(!b.is_exceeded());
+        let mut c = b.charge(Category::ReaderChunks, 100);
+        let d = b.charge(Category::LiveSegments, 50);
+        assert_eq!(b.total(), 150);
+        assert!(!b.is_exceeded()); // no limit.
+        b.set_limit(Some(120));
+        assert!(b.is_exceeded());
+        c.set(60);
+        assert_eq!(b.used(Category::ReaderChunks), 60);
+        assert!(!b.is_exceeded());
+        c.set(80);
+        assert!(b.is_exceeded());
+        drop(d);
+        assert!(!b.is_exceeded());
+        drop(c);
+        assert_eq!(b.total(), 0);
+    }
+
+    #[test]
+    fn buf_pool() {
+        let p = BufPool::new(1, 1024);
+        let mut a = p.get(100);
+        a.extend_from_slice(b"hello");
+        let a_ptr = a.as_ptr();
+        let b = p.get(100);
+        drop(a);
+        drop(b); // the pool is already full.
+        assert_eq!(p.idle(), 1);
+
+        // The idle buffer is reused, empty.
+        let c = p.get(10);
+        assert!(c.is_empty());
+        assert_eq!(c.as_ptr(), a_ptr);
+        drop(c);
+
+        // Oversized buffers aren't kept.
+        let d = p.get(2048);
+        assert_eq!(p.idle(), 0);
+        drop(d);
+        assert_eq!(p.idle(), 0);
+    }
+}
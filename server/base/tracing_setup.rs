@@ -5,16 +5,29 @@
 //! Logic for setting up a `tracing` subscriber according to our preferences
 //! and [OpenTelemetry conventions](https://opentelemetry.io/docs/reference/specification/logs/).
 
-use tracing::error;
-use tracing_core::{Event, Level, Subscriber};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+use tracing_core::{callsite, field, Event, Level, Subscriber};
 use tracing_log::NormalizeEvent;
 use tracing_subscriber::{
     fmt::{format::Writer, time::FormatTime, FmtContext, FormatFields, FormattedFields},
-    layer::SubscriberExt,
+    layer::{Context, SubscriberExt},
     registry::LookupSpan,
     Layer,
 };
 
+/// The name of the thread which logs [`Dedup`] summaries. Its own events are never suppressed.
+const DEDUP_THREAD_NAME: &str = "log-dedup";
+
+/// The default interval for [`Dedup`], overridable via `MOONFIRE_LOG_DEDUP_SEC`.
+const DEFAULT_DEDUP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How often the dedup thread checks for expired entries.
+const DEDUP_TICK: Duration = Duration::from_secs(1);
+
 struct FormatSystemd;
 
 struct ChronoTimer;
@@ -85,6 +98,189 @@ where
     }
 }
 
+/// Layer which collapses repeated identical warnings and errors, such as a camera refusing
+/// connections every second for hours, into periodic summaries.
+///
+/// Events are identical if they come from the same thread (each stream has its own), call site,
+/// and field values. The first is logged; repeats within the following interval are suppressed,
+/// then summarized with a count once the interval elapses.
+struct Dedup {
+    interval: Duration,
+    state: Arc<Mutex<DedupState>>,
+}
+
+#[derive(Default)]
+struct DedupState {
+    entries: HashMap<DedupKey, DedupEntry>,
+
+    /// Summaries of entries which expired in `Dedup::event_enabled`, to be logged by the dedup
+    /// thread. Logging from within the subscriber isn't safe.
+    summaries: Vec<DedupSummary>,
+}
+
+#[derive(Eq, Hash, PartialEq)]
+struct DedupKey {
+    thread: String,
+    callsite: callsite::Identifier,
+    fields: String,
+}
+
+struct DedupEntry {
+    level: Level,
+    first: Instant,
+    suppressed: u64,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct DedupSummary {
+    level: Level,
+    thread: String,
+    fields: String,
+    suppressed: u64,
+    elapsed: Duration,
+}
+
+impl DedupSummary {
+    fn new(k: &DedupKey, e: &DedupEntry, now: Instant) -> Self {
+        DedupSummary {
+            level: e.level,
+            thread: k.thread.clone(),
+            fields: k.fields.clone(),
+            suppressed: e.suppressed,
+            elapsed: now - e.first,
+        }
+    }
+
+    fn log(&self) {
+        let (n, secs, fields) = (self.suppressed, self.elapsed.as_secs(), &self.fields);
+        if self.level == Level::ERROR {
+            error!(thread = %self.thread, "suppressed {n} repeats within {secs} s of: {fields}");
+        } else {
+            warn!(thread = %self.thread, "suppressed {n} repeats within {secs} s of: {fields}");
+        }
+    }
+}
+
+impl DedupState {
+    /// Returns summaries of expired entries with suppressed repeats, removing all expired entries.
+    fn take_summaries(&mut self, now: Instant, interval: Duration) -> Vec<DedupSummary> {
+        let mut out = std::mem::take(&mut self.summaries);
+        self.entries.retain(|k, e| {
+            if now < e.first + interval {
+                return true;
+            }
+            if e.suppressed > 0 {
+                out.push(DedupSummary::new(k, e, now));
+            }
+            false
+        });
+        out
+    }
+}
+
+/// Collects an event's fields as a string, for comparison and summaries.
+struct FieldsVisitor(String);
+
+impl field::Visit for FieldsVisitor {
+    fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+        use std::fmt::Write as _;
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = if field.name() == "message" {
+            write!(self.0, "{value:?}")
+        } else {
+            write!(self.0, "{}={value:?}", field.name())
+        };
+    }
+}
+
+impl Dedup {
+    /// Creates the layer and starts the thread which logs its summaries.
+    fn start(interval: Duration) -> Self {
+        let state = Arc::new(Mutex::new(DedupState::default()));
+        std::thread::Builder::new()
+            .name(DEDUP_THREAD_NAME.to_owned())
+            .spawn({
+                let state = state.clone();
+                move || loop {
+                    std::thread::sleep(DEDUP_TICK);
+                    let summaries = state
+                        .lock()
+                        .unwrap()
+                        .take_summaries(Instant::now(), interval);
+                    for s in summaries {
+                        s.log();
+                    }
+                }
+            })
+            .expect("can spawn log-dedup thread");
+        Dedup { interval, state }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Dedup {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        let meta = event.metadata();
+        if *meta.level() > Level::WARN {
+            return true;
+        }
+        let thread = std::thread::current();
+        let thread = thread.name().unwrap_or("unnamed-thread");
+        if thread == DEDUP_THREAD_NAME {
+            return true;
+        }
+        let mut fields = FieldsVisitor(String::new());
+        event.record(&mut fields);
+        let key = DedupKey {
+            thread: thread.to_owned(),
+            callsite: meta.callsite(),
+            fields: fields.0,
+        };
+        let now = Instant::now();
+        let new_entry = DedupEntry {
+            level: *meta.level(),
+            first: now,
+            suppressed: 0,
+        };
+        let mut l = self.state.lock().unwrap();
+        let l = &mut *l;
+        match l.entries.entry(key) {
+            Entry::Vacant(v) => {
+                v.insert(new_entry);
+                true
+            }
+            Entry::Occupied(mut o) => {
+                if now < o.get().first + self.interval {
+                    o.get_mut().suppressed += 1;
+                    return false;
+                }
+                if o.get().suppressed > 0 {
+                    l.summaries.push(DedupSummary::new(o.key(), o.get(), now));
+                }
+                o.insert(new_entry);
+                true
+            }
+        }
+    }
+}
+
+/// Returns the [`Dedup`] layer as configured by `MOONFIRE_LOG_DEDUP_SEC`, or `None` if disabled.
+fn dedup_from_env() -> Option<Dedup> {
+    let interval = match std::env::var("MOONFIRE_LOG_DEDUP_SEC") {
+        Ok(s) => match s.parse::<u64>() {
+            Ok(0) => return None,
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!("ignoring unparseable MOONFIRE_LOG_DEDUP_SEC={s:?}");
+                DEFAULT_DEDUP_INTERVAL
+            }
+        },
+        Err(_) => DEFAULT_DEDUP_INTERVAL,
+    };
+    Some(Dedup::start(interval))
+}
+
 /// Custom panic hook that logs instead of directly writing to stderr.
 ///
 /// This means it includes a timestamp, follows [OpenTelemetry Semantic
@@ -113,10 +309,11 @@ pub fn install() {
         .with_env_var("MOONFIRE_LOG")
         .from_env_lossy();
     tracing_log::LogTracer::init().unwrap();
+    let dedup = dedup_from_env();
 
     match std::env::var("MOONFIRE_FORMAT") {
         Ok(s) if s == "systemd" => {
-            let sub = tracing_subscriber::registry().with(
+            let sub = tracing_subscriber::registry().with(dedup).with(
                 tracing_subscriber::fmt::Layer::new()
                     .with_writer(std::io::stderr)
                     .with_ansi(false)
@@ -126,7 +323,7 @@ pub fn install() {
             tracing::subscriber::set_global_default(sub).unwrap();
         }
         Ok(s) if s == "json" => {
-            let sub = tracing_subscriber::registry().with(
+            let sub = tracing_subscriber::registry().with(dedup).with(
                 tracing_subscriber::fmt::Layer::new()
                     .with_writer(std::io::stderr)
                     .with_thread_names(true)
@@ -136,7 +333,7 @@ pub fn install() {
             tracing::subscriber::set_global_default(sub).unwrap();
         }
         _ => {
-            let sub = tracing_subscriber::registry().with(
+            let sub = tracing_subscriber::registry().with(dedup).with(
                 tracing_subscriber::fmt::Layer::new()
                     .with_writer(std::io::stderr)
                     .with_timer(ChronoTimer)
@@ -170,3 +367,54 @@ pub fn install_for_tests() {
     );
     tracing::subscriber::set_global_default(sub).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Layer which counts the events it sees.
+    struct Count(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Count {
+        fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn dedup() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let state = Arc::new(Mutex::new(DedupState::default()));
+        let interval = Duration::from_secs(60);
+        let sub = tracing_subscriber::registry()
+            .with(Dedup {
+                interval,
+                state: state.clone(),
+            })
+            .with(Count(count.clone()));
+        tracing::subscriber::with_default(sub, || {
+            for _ in 0..3 {
+                warn!(stream = "driveway-main", "connection refused");
+            }
+            warn!(stream = "courtyard-main", "connection refused");
+            for _ in 0..3 {
+                tracing::info!("not deduplicated");
+            }
+        });
+        assert_eq!(count.load(Ordering::Relaxed), 5);
+
+        let mut l = state.lock().unwrap();
+        let now = Instant::now();
+        assert!(l.take_summaries(now, interval).is_empty());
+        assert_eq!(l.entries.len(), 2);
+        let summaries = l.take_summaries(now + interval, interval);
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].suppressed, 2);
+        assert_eq!(
+            summaries[0].fields,
+            r#"connection refused stream="driveway-main""#
+        );
+        assert!(l.entries.is_empty());
+    }
+}
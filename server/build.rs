@@ -31,7 +31,6 @@ struct File {
     ///
     /// E.g. `ui_files/index.html.gz`.
     include_path: String,
-    encoding: FileEncoding,
     etag: blake3::Hash,
 }
 
@@ -39,6 +38,7 @@ struct File {
 enum FileEncoding {
     Uncompressed,
     Gzipped,
+    Brotli,
 }
 
 impl FileEncoding {
@@ -46,26 +46,55 @@ impl FileEncoding {
         match self {
             Self::Uncompressed => "FileEncoding::Uncompressed",
             Self::Gzipped => "FileEncoding::Gzipped",
+            Self::Brotli => "FileEncoding::Brotli",
         }
     }
 }
 
-/// Map of "bare path" to the best representation.
+/// The representations of a file found in the UI dir.
+#[derive(Default)]
+struct FileSet {
+    uncompressed: Option<File>,
+    gzipped: Option<File>,
+    brotli: Option<File>,
+}
+
+impl FileSet {
+    /// Returns the representations to bundle: the uncompressed one is omitted if there's a
+    /// gzipped one, as the server can decompress that at startup.
+    fn bundled(&self) -> impl Iterator<Item = (FileEncoding, &File)> {
+        [
+            (FileEncoding::Brotli, self.brotli.as_ref()),
+            (FileEncoding::Gzipped, self.gzipped.as_ref()),
+            (
+                FileEncoding::Uncompressed,
+                self.uncompressed
+                    .as_ref()
+                    .filter(|_| self.gzipped.is_none()),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(e, f)| f.map(|f| (e, f)))
+    }
+}
+
+/// Map of "bare path" to its representations.
 ///
 /// A "bare path" has no prefix for the root and no suffix for encoding, e.g.
 /// `favicons/blah.ico` rather than `../../ui/dist/favicons/blah.ico.gz`.
-///
-/// The best representation is gzipped if available, uncompressed otherwise.
-type FileMap = std::collections::HashMap<String, File, ahash::RandomState>;
+type FileMap = std::collections::HashMap<String, FileSet, ahash::RandomState>;
 
 fn stringify_files(files: &FileMap) -> Result<String, std::fmt::Error> {
     let mut buf = String::new();
-    writeln!(buf, "const FILES: [BuildFile; {}] = [", files.len())?;
-    for (bare_path, file) in files {
-        let include_path = &file.include_path;
-        let etag = file.etag.to_hex();
-        let encoding = file.encoding.to_str();
-        writeln!(buf, "    BuildFile {{ bare_path: {bare_path:?}, data: include_bytes!({include_path:?}), etag: {etag:?}, encoding: {encoding} }},")?;
+    let len: usize = files.values().map(|s| s.bundled().count()).sum();
+    writeln!(buf, "const FILES: [BuildFile; {len}] = [")?;
+    for (bare_path, set) in files {
+        for (encoding, file) in set.bundled() {
+            let include_path = &file.include_path;
+            let etag = file.etag.to_hex();
+            let encoding = encoding.to_str();
+            writeln!(buf, "    BuildFile {{ bare_path: {bare_path:?}, data: include_bytes!({include_path:?}), etag: {etag:?}, encoding: {encoding} }},")?;
+        }
     }
     writeln!(buf, "];")?;
     Ok(buf)
@@ -104,31 +133,35 @@ fn handle_bundled_ui() -> Result<(), BoxError> {
             .strip_prefix(&abs_ui_dir)
             .expect("walkdir should return root-prefixed entries");
         let path = path.to_str().expect("ui file paths should be valid UTF-8");
-        let (bare_path, encoding);
-        match path.strip_suffix(".gz") {
-            Some(p) => {
-                bare_path = p;
-                encoding = FileEncoding::Gzipped;
-            }
-            None => {
-                bare_path = path;
-                encoding = FileEncoding::Uncompressed;
-                if files.contains_key(bare_path) {
-                    continue; // don't replace with suboptimal encoding.
-                }
-            }
-        }
+        let (bare_path, encoding) = if let Some(p) = path.strip_suffix(".gz") {
+            (p, FileEncoding::Gzipped)
+        } else if let Some(p) = path.strip_suffix(".br") {
+            (p, FileEncoding::Brotli)
+        } else {
+            (path, FileEncoding::Uncompressed)
+        };
 
         let contents = std::fs::read(entry.path()).expect("ui files should be readable");
         let etag = blake3::hash(&contents);
         let include_path = format!("ui_files/{path}");
-        files.insert(
-            bare_path.to_owned(),
-            File {
-                include_path,
-                encoding,
-                etag,
-            },
+        let set = files.entry(bare_path.to_owned()).or_default();
+        let slot = match encoding {
+            FileEncoding::Uncompressed => &mut set.uncompressed,
+            FileEncoding::Gzipped => &mut set.gzipped,
+            FileEncoding::Brotli => &mut set.brotli,
+        };
+        *slot = Some(File { include_path, etag });
+    }
+
+    // The server can't decompress brotli, so it needs another representation for clients
+    // which don't accept it.
+    if let Some(p) = files
+        .iter()
+        .find(|(_, s)| s.uncompressed.is_none() && s.gzipped.is_none())
+        .map(|(p, _)| p)
+    {
+        return Err(
+            format!("{p:?} in {ui_dir:?} has neither a gzipped nor uncompressed version").into(),
         );
     }
 
@@ -0,0 +1,162 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Cache of [`crate::Database::list_aggregated_recordings`] results.
+//!
+//! Timeline views repeatedly request the same days' aggregated recordings. Listing and
+//! aggregating months of recordings is slow on small machines, and the result for a time range
+//! only changes when a flush commits or deletes recordings within it. So results which don't
+//! involve uncommitted recordings are kept until a flush changes an overlapping time range.
+//!
+//! Entries are checked against a per-stream generation to avoid caching a result listed
+//! before a flush but inserted after that flush's invalidation.
+
+use crate::db::ListAggregatedRecordingsRow;
+use crate::recording;
+use base::FastHashMap;
+use hashlink::LinkedHashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+/// The maximum total number of cached rows. Least-recently-used entries are evicted beyond this.
+const MAX_ROWS: usize = 50_000;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Key {
+    stream_id: i32,
+    time_90k: Range<i64>,
+    forced_split_90k: i64,
+}
+
+impl Key {
+    fn new(
+        stream_id: i32,
+        time: &Range<recording::Time>,
+        forced_split: recording::Duration,
+    ) -> Self {
+        Key {
+            stream_id,
+            time_90k: time.start.0..time.end.0,
+            forced_split_90k: forced_split.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Cache(Mutex<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    entries: LinkedHashMap<Key, Arc<[ListAggregatedRecordingsRow]>, base::RandomState>,
+    rows: usize,
+
+    /// The number of invalidations of each stream so far, excluding `clears`.
+    generations: FastHashMap<i32, u64>,
+
+    /// The number of calls to [`Cache::clear`] so far.
+    clears: u64,
+}
+
+impl Inner {
+    fn generation(&self, stream_id: i32) -> u64 {
+        self.clears + self.generations.get(&stream_id).copied().unwrap_or(0)
+    }
+
+    fn remove_where(&mut self, mut pred: impl FnMut(&Key) -> bool) {
+        let rows = &mut self.rows;
+        self.entries.retain(|k, v| {
+            if pred(k) {
+                *rows -= v.len();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl Cache {
+    /// Returns the cached rows for the given query, if any.
+    pub(crate) fn get(
+        &self,
+        stream_id: i32,
+        time: &Range<recording::Time>,
+        forced_split: recording::Duration,
+    ) -> Option<Arc<[ListAggregatedRecordingsRow]>> {
+        use hashlink::linked_hash_map::RawEntryMut;
+        let key = Key::new(stream_id, time, forced_split);
+        let mut l = self.0.lock().unwrap();
+        match l.entries.raw_entry_mut().from_key(&key) {
+            RawEntryMut::Occupied(mut occupied) => {
+                occupied.to_back();
+                Some(occupied.get().clone())
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    /// Returns the stream's current generation, to pass to [`Cache::insert`]. This must be
+    /// called before listing.
+    pub(crate) fn generation(&self, stream_id: i32) -> u64 {
+        self.0.lock().unwrap().generation(stream_id)
+    }
+
+    /// Caches the rows for the given query, unless the stream has been invalidated since
+    /// `generation` was returned.
+    pub(crate) fn insert(
+        &self,
+        stream_id: i32,
+        time: &Range<recording::Time>,
+        forced_split: recording::Duration,
+        generation: u64,
+        rows: Vec<ListAggregatedRecordingsRow>,
+    ) {
+        if rows.len() > MAX_ROWS {
+            return;
+        }
+        let mut l = self.0.lock().unwrap();
+        if l.generation(stream_id) != generation {
+            return;
+        }
+        l.rows += rows.len();
+        let key = Key::new(stream_id, &time, forced_split);
+        if let Some(old) = l.entries.insert(key, rows.into()) {
+            l.rows -= old.len();
+        }
+        while l.rows > MAX_ROWS {
+            let (_, evicted) = l.entries.pop_front().expect("rows > 0 implies entries");
+            l.rows -= evicted.len();
+        }
+    }
+
+    /// Drops cached results for the stream which overlap any of `changed`, as after a flush
+    /// which committed or deleted recordings in those ranges.
+    pub(crate) fn invalidate(&self, stream_id: i32, changed: &[Range<recording::Time>]) {
+        let mut l = self.0.lock().unwrap();
+        *l.generations.entry(stream_id).or_default() += 1;
+
+        // Overlap is conservatively inclusive of the ends.
+        l.remove_where(|k| {
+            k.stream_id == stream_id
+                && changed
+                    .iter()
+                    .any(|c| c.start.0 <= k.time_90k.end && k.time_90k.start <= c.end.0)
+        });
+    }
+
+    /// Drops all cached results, as after changes which may affect any stream's recordings.
+    pub(crate) fn clear(&self) {
+        let mut l = self.0.lock().unwrap();
+        l.clears += 1;
+        l.entries.clear();
+        l.rows = 0;
+    }
+
+    /// Returns the number of cached results and their total number of rows.
+    #[cfg(test)]
+    pub(crate) fn stats(&self) -> (usize, usize) {
+        let l = self.0.lock().unwrap();
+        (l.entries.len(), l.rows)
+    }
+}
@@ -4,7 +4,7 @@
 
 //! Authentication schema: users and sessions/cookies.
 
-use crate::json::UserConfig;
+use crate::json::{GlobalConfig, PasswordHashConfig, UserConfig};
 use crate::schema::Permissions;
 use base::FastHashMap;
 use base::{bail, err, strutil, Error, ErrorKind, ResultExt as _};
@@ -12,47 +12,74 @@ use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
 use protobuf::Message;
 use ring::rand::{SecureRandom, SystemRandom};
 use rusqlite::{named_params, params, Connection, Transaction};
-use scrypt::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use scrypt::password_hash::{PasswordHash, PasswordHasher, SaltString};
 use std::collections::BTreeMap;
 use std::fmt;
 use std::net::IpAddr;
 use std::str::FromStr;
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::info;
 
-/// Wrapper around [`scrypt::Params`].
-///
-/// `scrypt::Params` does not implement `PartialEq`; so for the benefit of `set_test_config`
-/// error handling, keep track of whether these params are the recommended
-/// production ones or the cheap test ones.
-struct Params {
-    actual: scrypt::Params,
-    is_test: bool,
+/// Set by [`set_test_config`] to use fast but insecure hashes by default.
+static TEST_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// For testing only: use fast but insecure hashes unless parameters are explicitly configured.
+/// Call via `testutil::init()`.
+pub(crate) fn set_test_config() {
+    TEST_CONFIG.store(true, Ordering::Relaxed);
 }
 
-static PARAMS: OnceLock<Params> = OnceLock::new();
+/// Returns the Argon2id parameters for new password hashes, as configured by `config`.
+///
+/// Absent parameters use the `argon2` crate's defaults, which follow OWASP's recommendations.
+pub(crate) fn password_params(
+    config: Option<&PasswordHashConfig>,
+) -> Result<argon2::Params, base::Error> {
+    let Some(c) = config else {
+        if TEST_CONFIG.load(Ordering::Relaxed) {
+            return Ok(argon2::Params::new(
+                argon2::Params::MIN_M_COST,
+                argon2::Params::MIN_T_COST,
+                argon2::Params::MIN_P_COST,
+                None,
+            )
+            .expect("test params should be valid"));
+        }
+        return Ok(argon2::Params::default());
+    };
+    argon2::Params::new(
+        c.memory_kib.unwrap_or(argon2::Params::DEFAULT_M_COST),
+        c.iterations.unwrap_or(argon2::Params::DEFAULT_T_COST),
+        c.parallelism.unwrap_or(argon2::Params::DEFAULT_P_COST),
+        None,
+    )
+    .map_err(|e| err!(InvalidArgument, msg("bad passwordHash config: {e}")))
+}
 
-fn params() -> &'static Params {
-    PARAMS.get_or_init(|| Params {
-        actual: scrypt::Params::recommended(),
-        is_test: false,
-    })
+fn hash_password(params: &argon2::Params, password: &str) -> String {
+    let salt = SaltString::generate(&mut scrypt::password_hash::rand_core::OsRng);
+    argon2::Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        params.clone(),
+    )
+    .hash_password(password.as_bytes(), &salt)
+    .expect("hashing with valid params should succeed")
+    .to_string()
 }
 
-/// For testing only: use fast but insecure hashes.
-/// Call via `testutil::init()`.
-pub(crate) fn set_test_config() {
-    let test_params = scrypt::Params::new(8, 8, 1, scrypt::Params::RECOMMENDED_LEN)
-        .expect("test params should be valid");
-    if let Err(existing_params) = PARAMS.set(Params {
-        actual: test_params,
-        is_test: true,
-    }) {
-        assert!(
-            existing_params.is_test,
-            "set_test_config must be called before any use of the parameters"
-        );
-    }
+/// Returns true if `hash` was made with Argon2id and the given `params`, so it needn't be
+/// upgraded.
+fn is_hash_current(hash: &str, params: &argon2::Params) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    hash.algorithm == argon2::ARGON2ID_IDENT
+        && hash.version == Some(argon2::Version::V0x13.into())
+        && argon2::Params::try_from(&hash).is_ok_and(|p| {
+            (p.m_cost(), p.t_cost(), p.p_cost())
+                == (params.m_cost(), params.t_cost(), params.p_cost())
+        })
 }
 
 #[derive(Debug)]
@@ -65,9 +92,18 @@ pub struct User {
     pub password_failure_count: i64,
     pub permissions: Permissions,
 
+    /// The day (since epoch, UTC) `download_bytes` applies to.
+    pub download_day: i64,
+
+    /// Bytes of video downloaded on `download_day`. Flushed lazily.
+    pub download_bytes: i64,
+
+    /// The [`PermissionTemplate`] from which `permissions` is copied, if any.
+    pub permission_template_id: Option<i32>,
+
     /// True iff this `User` has changed since the last flush.
-    /// Only a couple things are flushed lazily: `password_failure_count` and (on upgrade to a new
-    /// algorithm) `password_hash`.
+    /// Only a few things are flushed lazily: `password_failure_count`, the download counters, and
+    /// (on upgrade to new hashing parameters at login) `password_hash`.
     dirty: bool,
 }
 
@@ -77,8 +113,10 @@ impl User {
             id: Some(self.id),
             username: self.username.clone(),
             config: self.config.clone(),
+            set_password: None,
             set_password_hash: None,
             permissions: self.permissions.clone(),
+            permission_template_id: self.permission_template_id,
         }
     }
 
@@ -86,6 +124,49 @@ impl User {
         self.password_hash.is_some()
     }
 
+    /// Counts `bytes` of video as downloaded at `now_sec`, failing with `PermissionDenied` (and
+    /// counting nothing) if that would exceed the user's daily quota.
+    pub fn charge_download(&mut self, now_sec: i64, bytes: u64) -> Result<(), base::Error> {
+        let day = now_sec.div_euclid(86_400);
+        let used = if day == self.download_day {
+            self.download_bytes
+        } else {
+            0
+        };
+        let bytes = i64::try_from(bytes).unwrap_or(i64::MAX);
+        let total = used.saturating_add(bytes);
+        if let Some(quota) = self.config.download_quota_bytes_per_day {
+            if u64::try_from(total).unwrap_or(0) > quota {
+                bail!(
+                    PermissionDenied,
+                    msg(
+                        "download quota exceeded: {used} of {quota} bytes used today; \
+                         request is {bytes} bytes"
+                    )
+                );
+            }
+        }
+        self.download_day = day;
+        self.download_bytes = total;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Returns true if the account has expired as of `now_sec`, or if `now_sec` is unknown and
+    /// the account has any expiration time.
+    pub fn is_expired(&self, now_sec: Option<i64>) -> bool {
+        match (self.config.expiration_time_sec, now_sec) {
+            (None, _) => false,
+            (Some(e), Some(now)) => now >= e,
+            (Some(_), None) => true,
+        }
+    }
+
+    /// Returns the stored password hash in PHC string format, if any.
+    pub fn password_hash(&self) -> Option<&str> {
+        self.password_hash.as_deref()
+    }
+
     /// Checks if the user's password hash matches the supplied password.
     ///
     /// As a side effect, increments `password_failure_count` and sets `dirty`
@@ -104,7 +185,8 @@ impl User {
                 source(e),
             )
         })?;
-        match scrypt::Scrypt.verify_password(password.as_bytes(), &hash) {
+        // Hashes from before the switch to Argon2id use scrypt.
+        match hash.verify_password(&[&argon2::Argon2::default(), &scrypt::Scrypt], password) {
             Ok(()) => Ok(true),
             Err(scrypt::password_hash::errors::Error::Password) => {
                 self.dirty = true;
@@ -131,8 +213,12 @@ pub struct UserChange {
     id: Option<i32>,
     pub username: String,
     pub config: UserConfig,
+    set_password: Option<Password>,
     set_password_hash: Option<Option<String>>,
     pub permissions: Permissions,
+
+    /// If set, `permissions` is replaced with this template's when the change is applied.
+    pub permission_template_id: Option<i32>,
 }
 
 impl UserChange {
@@ -141,23 +227,42 @@ impl UserChange {
             id: None,
             username,
             config: UserConfig::default(),
+            set_password: None,
             set_password_hash: None,
             permissions: Permissions::default(),
+            permission_template_id: None,
         }
     }
 
+    /// Sets the password, which is hashed when the change is applied.
     pub fn set_password(&mut self, pwd: String) {
-        let salt = SaltString::generate(&mut scrypt::password_hash::rand_core::OsRng);
-        let params = params();
-        let hash = scrypt::Scrypt
-            .hash_password_customized(pwd.as_bytes(), None, None, params.actual, &salt)
-            .unwrap();
-        self.set_password_hash = Some(Some(hash.to_string()));
+        self.set_password = Some(Password(pwd));
+        self.set_password_hash = None;
     }
 
     pub fn clear_password(&mut self) {
+        self.set_password = None;
         self.set_password_hash = Some(None);
     }
+
+    /// Sets an already-hashed password, as from [`User::password_hash`].
+    pub fn set_password_hash(&mut self, hash: String) -> Result<(), base::Error> {
+        PasswordHash::new(&hash)
+            .map_err(|e| err!(InvalidArgument, msg("bad password hash"), source(e)))?;
+        self.set_password = None;
+        self.set_password_hash = Some(Some(hash));
+        Ok(())
+    }
+}
+
+/// A plaintext password, redacted from `Debug` output.
+#[derive(Clone)]
+struct Password(String);
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str("Password(<redacted>)")
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -223,6 +328,7 @@ pub enum SessionFlag {
     Secure = 2,
     SameSite = 4,
     SameSiteStrict = 8,
+    SameSiteNone = 16,
 }
 
 impl FromStr for SessionFlag {
@@ -234,6 +340,7 @@ impl FromStr for SessionFlag {
             "secure" => Ok(Self::Secure),
             "same-site" => Ok(Self::SameSite),
             "same-site-strict" => Ok(Self::SameSiteStrict),
+            "same-site-none" => Ok(Self::SameSiteNone),
             _ => bail!(InvalidArgument, msg("No such session flag {s:?}")),
         }
     }
@@ -243,6 +350,7 @@ impl FromStr for SessionFlag {
 pub enum RevocationReason {
     LoggedOut = 1,
     AlgorithmChange = 2,
+    AdminRevoked = 3,
 }
 
 #[allow(dead_code)] // Some of these fields are currently only used in Debug. That's fine.
@@ -376,9 +484,68 @@ impl rusqlite::types::FromSql for Seed {
     }
 }
 
+/// Limits how long a session remains valid.
+#[derive(Copy, Clone, Debug)]
+pub struct SessionExpiry {
+    /// The maximum age of a session, in seconds.
+    pub max_age_sec: i64,
+
+    /// If true, measures the age from the session's last use rather than its creation.
+    pub sliding: bool,
+}
+
+/// Sizes of the authentication tables, as of the last [`State::purge_sessions`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TableStats {
+    pub users: usize,
+
+    /// Rows in `user_session`, including revoked and expired ones not yet purged.
+    pub sessions: u64,
+    pub revoked_sessions: u64,
+
+    /// Sessions purged since startup.
+    pub purged_sessions: u64,
+}
+
+/// A named set of permissions which may be assigned to users, as in the `permission_template`
+/// table.
+#[derive(Clone, Debug)]
+pub struct PermissionTemplate {
+    pub id: i32,
+    pub name: String,
+    pub permissions: Permissions,
+}
+
+/// A WebAuthn credential (passkey or security key) registered to a user, as in the
+/// `user_webauthn_credential` table.
+#[derive(Clone, Debug)]
+pub struct WebAuthnCredential {
+    pub id: i32,
+    pub user_id: i32,
+
+    /// The authenticator-chosen credential id.
+    pub credential_id: Vec<u8>,
+
+    /// The public key, as a COSE_Key. Signatures are verified by the caller; this module only
+    /// stores it.
+    pub public_key: Vec<u8>,
+
+    /// The signature counter as of the last login, or 0 if the authenticator doesn't have one.
+    pub sign_count: u32,
+
+    pub name: String,
+    pub creation_time_sec: i64,
+    pub last_use_time_sec: Option<i64>,
+}
+
 pub(crate) struct State {
     users_by_id: BTreeMap<i32, User>,
     users_by_name: BTreeMap<String, i32>,
+    templates_by_id: BTreeMap<i32, PermissionTemplate>,
+    webauthn_credentials_by_id: BTreeMap<i32, WebAuthnCredential>,
+
+    /// Maps `WebAuthnCredential::credential_id` to `WebAuthnCredential::id`.
+    webauthn_ids_by_credential_id: FastHashMap<Vec<u8>, i32>,
 
     /// Some of the sessions stored in the database.
     /// Guaranteed to contain all "dirty" sessions (ones with unflushed changes); may contain
@@ -389,15 +556,28 @@ pub(crate) struct State {
     /// (and accept more frequent database accesses).
     sessions: FastHashMap<SessionHash, Session>,
 
+    expiry: Option<SessionExpiry>,
+
+    table_stats: TableStats,
+
+    /// Parameters for new password hashes; see `passwordHash` in [`GlobalConfig`].
+    password_params: argon2::Params,
+
     rand: SystemRandom,
 }
 
 impl State {
-    pub fn init(conn: &Connection) -> Result<Self, Error> {
+    pub fn init(conn: &Connection, config: &GlobalConfig) -> Result<Self, base::Error> {
         let mut state = State {
             users_by_id: BTreeMap::new(),
             users_by_name: BTreeMap::new(),
+            templates_by_id: BTreeMap::new(),
+            webauthn_credentials_by_id: BTreeMap::new(),
+            webauthn_ids_by_credential_id: FastHashMap::default(),
             sessions: FastHashMap::default(),
+            expiry: None,
+            table_stats: TableStats::default(),
+            password_params: password_params(config.password_hash.as_ref())?,
             rand: ring::rand::SystemRandom::new(),
         };
         let mut stmt = conn.prepare(
@@ -409,7 +589,10 @@ impl State {
                 password_hash,
                 password_id,
                 password_failure_count,
-                permissions
+                permissions,
+                permission_template_id,
+                download_day,
+                download_bytes
             from
                 user
             "#,
@@ -433,14 +616,80 @@ impl State {
                     password_failure_count: row.get(5)?,
                     dirty: false,
                     permissions,
+                    permission_template_id: row.get(7)?,
+                    download_day: row.get(8)?,
+                    download_bytes: row.get(9)?,
                 },
             );
             state.users_by_name.insert(name, id);
         }
+        let mut stmt = conn.prepare("select id, name, permissions from permission_template")?;
+        let mut rows = stmt.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let id = row.get(0)?;
+            let mut permissions = Permissions::new();
+            permissions
+                .merge_from_bytes(row.get_ref(2)?.as_blob()?)
+                .err_kind(ErrorKind::DataLoss)?;
+            state.templates_by_id.insert(
+                id,
+                PermissionTemplate {
+                    id,
+                    name: row.get(1)?,
+                    permissions,
+                },
+            );
+        }
+        let mut stmt = conn.prepare(
+            r#"
+            select
+                id,
+                user_id,
+                credential_id,
+                public_key,
+                sign_count,
+                name,
+                creation_time_sec,
+                last_use_time_sec
+            from
+                user_webauthn_credential
+            "#,
+        )?;
+        let mut rows = stmt.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let c = WebAuthnCredential {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                credential_id: row.get(2)?,
+                public_key: row.get(3)?,
+                sign_count: row.get(4)?,
+                name: row.get(5)?,
+                creation_time_sec: row.get(6)?,
+                last_use_time_sec: row.get(7)?,
+            };
+            state
+                .webauthn_ids_by_credential_id
+                .insert(c.credential_id.clone(), c.id);
+            state.webauthn_credentials_by_id.insert(c.id, c);
+        }
         Ok(state)
     }
 
-    pub fn apply(&mut self, conn: &Connection, change: UserChange) -> Result<&User, base::Error> {
+    pub fn apply(
+        &mut self,
+        conn: &Connection,
+        mut change: UserChange,
+    ) -> Result<&User, base::Error> {
+        if let Some(p) = change.set_password.take() {
+            change.set_password_hash = Some(Some(hash_password(&self.password_params, &p.0)));
+        }
+        if let Some(t) = change.permission_template_id {
+            let t = self
+                .templates_by_id
+                .get(&t)
+                .ok_or_else(|| err!(InvalidArgument, msg("permission template {t} not found")))?;
+            change.permissions = t.permissions.clone();
+        }
         if let Some(id) = change.id {
             self.update_user(conn, id, change)
         } else {
@@ -452,6 +701,20 @@ impl State {
         &self.users_by_id
     }
 
+    /// Returns true if `u` has a password hash which will be upgraded at the user's next
+    /// password login, as it wasn't made with Argon2id and the current parameters.
+    pub fn password_needs_rehash(&self, u: &User) -> bool {
+        u.password_hash
+            .as_deref()
+            .is_some_and(|h| !is_hash_current(h, &self.password_params))
+    }
+
+    /// Changes the parameters for new password hashes, as after `passwordHash` in
+    /// [`GlobalConfig`] is changed.
+    pub fn set_password_params(&mut self, params: argon2::Params) {
+        self.password_params = params;
+    }
+
     pub fn get_user_by_id_mut(&mut self, id: i32) -> Option<&mut User> {
         self.users_by_id.get_mut(&id)
     }
@@ -471,7 +734,8 @@ impl State {
                 password_id = :password_id,
                 password_failure_count = :password_failure_count,
                 config = :config,
-                permissions = :permissions
+                permissions = :permissions,
+                permission_template_id = :permission_template_id
             where
                 id = :id
             "#,
@@ -501,6 +765,7 @@ impl State {
                 ":config": &change.config,
                 ":id": &id,
                 ":permissions": &permissions,
+                ":permission_template_id": &change.permission_template_id,
             })?;
         }
         let u = e.into_mut();
@@ -516,14 +781,17 @@ impl State {
         }
         u.config = change.config;
         u.permissions = change.permissions;
+        u.permission_template_id = change.permission_template_id;
         Ok(u)
     }
 
     fn add_user(&mut self, conn: &Connection, change: UserChange) -> Result<&User, base::Error> {
         let mut stmt = conn.prepare_cached(
             r#"
-            insert into user (username,  password_hash,  config,  permissions)
-                      values (:username, :password_hash, :config, :permissions)
+            insert into user (username,  password_hash,  config,  permissions,
+                              permission_template_id)
+                      values (:username, :password_hash, :config, :permissions,
+                              :permission_template_id)
             "#,
         )?;
         let password_hash = change.set_password_hash.unwrap_or(None);
@@ -536,6 +804,7 @@ impl State {
             ":password_hash": &password_hash,
             ":config": &change.config,
             ":permissions": &permissions,
+            ":permission_template_id": &change.permission_template_id,
         })?;
         let id = conn.last_insert_rowid() as i32;
         self.users_by_name.insert(change.username.clone(), id);
@@ -553,12 +822,155 @@ impl State {
             password_failure_count: 0,
             dirty: false,
             permissions: change.permissions,
+            download_day: 0,
+            download_bytes: 0,
+            permission_template_id: change.permission_template_id,
         }))
     }
 
+    /// Sets `disabled` on all enabled users which have expired as of `now_sec`, returning their
+    /// names.
+    pub fn disable_expired_users(
+        &mut self,
+        conn: &Connection,
+        now_sec: i64,
+    ) -> Result<Vec<String>, base::Error> {
+        let expired: Vec<i32> = self
+            .users_by_id
+            .values()
+            .filter(|u| !u.config.disabled && u.is_expired(Some(now_sec)))
+            .map(|u| u.id)
+            .collect();
+        let mut names = Vec::with_capacity(expired.len());
+        for id in expired {
+            let mut change = self.users_by_id[&id].change();
+            change.config.disabled = true;
+            names.push(self.update_user(conn, id, change)?.username.clone());
+        }
+        Ok(names)
+    }
+
+    pub fn permission_templates_by_id(&self) -> &BTreeMap<i32, PermissionTemplate> {
+        &self.templates_by_id
+    }
+
+    fn check_template_name(&self, id: Option<i32>, name: &str) -> Result<(), base::Error> {
+        if name.is_empty() {
+            bail!(
+                InvalidArgument,
+                msg("permission template name must be non-empty")
+            );
+        }
+        if self
+            .templates_by_id
+            .values()
+            .any(|t| t.name == name && Some(t.id) != id)
+        {
+            bail!(
+                AlreadyExists,
+                msg("permission template {name:?} already exists")
+            );
+        }
+        Ok(())
+    }
+
+    pub fn add_permission_template(
+        &mut self,
+        conn: &Connection,
+        name: String,
+        permissions: Permissions,
+    ) -> Result<&PermissionTemplate, base::Error> {
+        self.check_template_name(None, &name)?;
+        let bytes = permissions
+            .write_to_bytes()
+            .expect("proto3->vec is infallible");
+        conn.execute(
+            "insert into permission_template (name, permissions) values (?, ?)",
+            params![&name, &bytes],
+        )?;
+        let id = conn.last_insert_rowid() as i32;
+        Ok(self
+            .templates_by_id
+            .entry(id)
+            .or_insert(PermissionTemplate {
+                id,
+                name,
+                permissions,
+            }))
+    }
+
+    /// Updates a permission template, also replacing the permissions of all users assigned to it.
+    pub fn update_permission_template(
+        &mut self,
+        conn: &mut Connection,
+        id: i32,
+        name: String,
+        permissions: Permissions,
+    ) -> Result<&PermissionTemplate, base::Error> {
+        if !self.templates_by_id.contains_key(&id) {
+            bail!(NotFound, msg("permission template {id} not found"));
+        }
+        self.check_template_name(Some(id), &name)?;
+        let bytes = permissions
+            .write_to_bytes()
+            .expect("proto3->vec is infallible");
+        let tx = conn.transaction()?;
+        tx.execute(
+            "update permission_template set name = ?, permissions = ? where id = ?",
+            params![&name, &bytes, id],
+        )?;
+        tx.execute(
+            "update user set permissions = ? where permission_template_id = ?",
+            params![&bytes, id],
+        )?;
+        tx.commit()?;
+        for u in self.users_by_id.values_mut() {
+            if u.permission_template_id == Some(id) {
+                u.permissions = permissions.clone();
+            }
+        }
+        let t = self
+            .templates_by_id
+            .get_mut(&id)
+            .expect("template checked above");
+        t.name = name;
+        t.permissions = permissions;
+        Ok(t)
+    }
+
+    /// Deletes a permission template, which must not be assigned to any user.
+    pub fn delete_permission_template(
+        &mut self,
+        conn: &Connection,
+        id: i32,
+    ) -> Result<(), base::Error> {
+        if let Some(u) = self
+            .users_by_id
+            .values()
+            .find(|u| u.permission_template_id == Some(id))
+        {
+            bail!(
+                FailedPrecondition,
+                msg(
+                    "permission template {id} is assigned to user {:?}",
+                    u.username
+                )
+            );
+        }
+        if conn.execute("delete from permission_template where id = ?", params![id])? != 1 {
+            bail!(NotFound, msg("permission template {id} not found"));
+        }
+        self.templates_by_id.remove(&id);
+        Ok(())
+    }
+
     pub fn delete_user(&mut self, conn: &mut Connection, id: i32) -> Result<(), base::Error> {
         let tx = conn.transaction()?;
         tx.execute("delete from user_session where user_id = ?", params![id])?;
+        tx.execute(
+            "delete from user_webauthn_credential where user_id = ?",
+            params![id],
+        )?;
         {
             let mut user_stmt = tx.prepare_cached("delete from user where id = ?")?;
             if user_stmt.execute(params![id])? != 1 {
@@ -571,6 +983,14 @@ impl State {
             .remove(&name)
             .expect("users_by_name should be consistent with users_by_id");
         self.sessions.retain(|_k, ref mut v| v.user_id != id);
+        let webauthn_ids_by_credential_id = &mut self.webauthn_ids_by_credential_id;
+        self.webauthn_credentials_by_id.retain(|_, c| {
+            if c.user_id != id {
+                return true;
+            }
+            webauthn_ids_by_credential_id.remove(&c.credential_id);
+            false
+        });
         Ok(())
     }
 
@@ -602,9 +1022,23 @@ impl State {
         if u.config.disabled {
             bail!(Unauthenticated, msg("user {username:?} is disabled"));
         }
+        if u.is_expired(req.when_sec) {
+            bail!(Unauthenticated, msg("user {username:?} has expired"));
+        }
         if !u.check_password(Some(&password))? {
             bail!(Unauthenticated, msg("incorrect password"));
         }
+        if !u
+            .password_hash
+            .as_deref()
+            .is_some_and(|h| is_hash_current(h, &self.password_params))
+        {
+            // This is the only time the plaintext is available to upgrade the hash. It's flushed
+            // lazily; the password itself is unchanged, so `password_id` stays the same.
+            info!("upgrading password hash for user {username:?}");
+            u.password_hash = Some(hash_password(&self.password_params, &password));
+            u.dirty = true;
+        }
         let password_id = u.password_id;
         State::make_session_int(
             &self.rand,
@@ -619,6 +1053,174 @@ impl State {
         )
     }
 
+    pub fn webauthn_credentials_by_id(&self) -> &BTreeMap<i32, WebAuthnCredential> {
+        &self.webauthn_credentials_by_id
+    }
+
+    pub fn get_webauthn_credential(&self, credential_id: &[u8]) -> Option<&WebAuthnCredential> {
+        self.webauthn_ids_by_credential_id
+            .get(credential_id)
+            .map(|id| {
+                self.webauthn_credentials_by_id
+                    .get(id)
+                    .expect("webauthn_ids_by_credential_id implies webauthn_credentials_by_id")
+            })
+    }
+
+    /// Registers a WebAuthn credential for a user. The caller is responsible for verifying the
+    /// authenticator's response.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_webauthn_credential(
+        &mut self,
+        conn: &Connection,
+        user_id: i32,
+        credential_id: Vec<u8>,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        name: String,
+        now_sec: i64,
+    ) -> Result<&WebAuthnCredential, base::Error> {
+        if !self.users_by_id.contains_key(&user_id) {
+            bail!(NotFound, msg("user {user_id} not found"));
+        }
+        if self
+            .webauthn_ids_by_credential_id
+            .contains_key(&credential_id)
+        {
+            bail!(AlreadyExists, msg("credential is already registered"));
+        }
+        let mut stmt = conn.prepare_cached(
+            r#"
+            insert into user_webauthn_credential (user_id,  credential_id,  public_key,  sign_count,
+                                                  name,  creation_time_sec)
+                                          values (:user_id, :credential_id, :public_key, :sign_count,
+                                                  :name, :creation_time_sec)
+            "#,
+        )?;
+        stmt.execute(named_params! {
+            ":user_id": user_id,
+            ":credential_id": &credential_id,
+            ":public_key": &public_key,
+            ":sign_count": sign_count,
+            ":name": &name,
+            ":creation_time_sec": now_sec,
+        })?;
+        let id = conn.last_insert_rowid() as i32;
+        self.webauthn_ids_by_credential_id
+            .insert(credential_id.clone(), id);
+        let e = match self.webauthn_credentials_by_id.entry(id) {
+            ::std::collections::btree_map::Entry::Vacant(e) => e,
+            ::std::collections::btree_map::Entry::Occupied(_) => {
+                panic!("WebAuthn credential {id} conflict!")
+            }
+        };
+        Ok(e.insert(WebAuthnCredential {
+            id,
+            user_id,
+            credential_id,
+            public_key,
+            sign_count,
+            name,
+            creation_time_sec: now_sec,
+            last_use_time_sec: None,
+        }))
+    }
+
+    pub fn delete_webauthn_credential(
+        &mut self,
+        conn: &Connection,
+        id: i32,
+    ) -> Result<(), base::Error> {
+        if conn.execute(
+            "delete from user_webauthn_credential where id = ?",
+            params![id],
+        )? != 1
+        {
+            bail!(NotFound, msg("WebAuthn credential {id} not found"));
+        }
+        let c = self
+            .webauthn_credentials_by_id
+            .remove(&id)
+            .expect("credential in database should be in webauthn_credentials_by_id");
+        self.webauthn_ids_by_credential_id.remove(&c.credential_id);
+        Ok(())
+    }
+
+    /// Makes a session for the owner of a WebAuthn credential, after the caller has verified an
+    /// assertion signed by it with signature counter `sign_count`.
+    ///
+    /// The counter is checked and stored immediately rather than on flush, so that a cloned
+    /// authenticator can't replay it after a crash.
+    pub fn login_by_webauthn(
+        &mut self,
+        conn: &Connection,
+        req: Request,
+        credential_id: &[u8],
+        sign_count: u32,
+        domain: Option<Vec<u8>>,
+        session_flags: i32,
+    ) -> Result<(RawSessionId, &Session), base::Error> {
+        let id = *self
+            .webauthn_ids_by_credential_id
+            .get(credential_id)
+            .ok_or_else(|| err!(Unauthenticated, msg("no such WebAuthn credential")))?;
+        let c = self
+            .webauthn_credentials_by_id
+            .get_mut(&id)
+            .expect("webauthn_ids_by_credential_id implies webauthn_credentials_by_id");
+        let u = self
+            .users_by_id
+            .get_mut(&c.user_id)
+            .expect("credential's user should exist");
+        if u.config.disabled {
+            bail!(Unauthenticated, msg("user {:?} is disabled", u.username));
+        }
+        if u.is_expired(req.when_sec) {
+            bail!(Unauthenticated, msg("user {:?} has expired", u.username));
+        }
+
+        // A counter that doesn't advance suggests the credential has been cloned. Both zero means
+        // the authenticator doesn't implement the counter.
+        if (sign_count != 0 || c.sign_count != 0) && sign_count <= c.sign_count {
+            bail!(
+                Unauthenticated,
+                msg(
+                    "WebAuthn credential {id} signature counter went from {} to {sign_count}; \
+                     authenticator may be cloned",
+                    c.sign_count,
+                ),
+            );
+        }
+        let mut stmt = conn.prepare_cached(
+            r#"
+            update user_webauthn_credential
+            set
+                sign_count = :sign_count,
+                last_use_time_sec = :last_use_time_sec
+            where
+                id = :id
+            "#,
+        )?;
+        stmt.execute(named_params! {
+            ":sign_count": sign_count,
+            ":last_use_time_sec": req.when_sec,
+            ":id": id,
+        })?;
+        c.sign_count = sign_count;
+        c.last_use_time_sec = req.when_sec;
+        State::make_session_int(
+            &self.rand,
+            conn,
+            req,
+            u,
+            domain,
+            None,
+            session_flags,
+            &mut self.sessions,
+            u.permissions.clone(),
+        )
+    }
+
     /// Makes a session directly (no password required).
     pub fn make_session<'s>(
         &'s mut self,
@@ -636,6 +1238,9 @@ impl State {
         if u.config.disabled {
             bail!(FailedPrecondition, msg("user is disabled"));
         }
+        if u.is_expired(creation.when_sec) {
+            bail!(FailedPrecondition, msg("user has expired"));
+        }
         State::make_session_int(
             &self.rand,
             conn,
@@ -712,6 +1317,94 @@ impl State {
         Ok((session_id, session))
     }
 
+    pub fn set_session_expiry(&mut self, expiry: Option<SessionExpiry>) {
+        self.expiry = expiry;
+    }
+
+    /// Deletes sessions which are revoked or expired and have had no activity (creation, use, or
+    /// revocation) in the `retention_sec` before `now_sec`, returning the number deleted.
+    ///
+    /// Sessions with unflushed use are kept. This also refreshes [`State::table_stats`].
+    pub fn purge_sessions(
+        &mut self,
+        conn: &mut Connection,
+        now_sec: i64,
+        retention_sec: i64,
+    ) -> Result<usize, base::Error> {
+        let mut purge = Vec::new();
+        {
+            let mut stmt = conn.prepare(
+                r#"
+                select
+                    session_id_hash,
+                    creation_time_sec,
+                    last_use_time_sec,
+                    revocation_time_sec,
+                    revocation_reason
+                from
+                    user_session
+                "#,
+            )?;
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                let Ok(hash) = <[u8; 24]>::try_from(row.get_ref(0)?.as_blob()?) else {
+                    continue;
+                };
+                let hash = SessionHash(hash);
+                let creation: i64 = row.get(1)?;
+                let last_use: Option<i64> = row.get(2)?;
+                let revocation: Option<i64> = row.get(3)?;
+                let revoked = row.get::<_, Option<i32>>(4)?.is_some();
+                let last_activity = creation
+                    .max(last_use.unwrap_or(creation))
+                    .max(revocation.unwrap_or(creation));
+                if now_sec - last_activity <= retention_sec
+                    || self.sessions.get(&hash).is_some_and(|s| s.dirty)
+                {
+                    continue;
+                }
+                let expired = self.expiry.is_some_and(|e| {
+                    let since = if e.sliding {
+                        last_use.unwrap_or(creation)
+                    } else {
+                        creation
+                    };
+                    now_sec - since > e.max_age_sec
+                });
+                if revoked || expired {
+                    purge.push(hash);
+                }
+            }
+        }
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("delete from user_session where session_id_hash = ?")?;
+            for hash in &purge {
+                stmt.execute(params![&hash.0[..]])?;
+            }
+        }
+        let (sessions, revoked_sessions) = tx.query_row(
+            "select count(*), count(revocation_reason) from user_session",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        tx.commit()?;
+        for hash in &purge {
+            self.sessions.remove(hash);
+        }
+        self.table_stats = TableStats {
+            users: self.users_by_id.len(),
+            sessions,
+            revoked_sessions,
+            purged_sessions: self.table_stats.purged_sessions + purge.len() as u64,
+        };
+        Ok(purge.len())
+    }
+
+    pub fn table_stats(&self) -> TableStats {
+        self.table_stats
+    }
+
     pub fn authenticate_session(
         &mut self,
         conn: &Connection,
@@ -740,12 +1433,26 @@ impl State {
                 msg("session is no longer valid (reason={r})")
             );
         }
+        if let (Some(e), Some(now)) = (self.expiry, req.when_sec) {
+            let since = if e.sliding {
+                s.last_use.when_sec.or(s.creation.when_sec)
+            } else {
+                s.creation.when_sec
+            };
+            if since.is_some_and(|t| now - t > e.max_age_sec) {
+                bail!(Unauthenticated, msg("session has expired"));
+            }
+        }
+        let expired = u.is_expired(req.when_sec);
         s.last_use = req;
         s.use_count += 1;
         s.dirty = true;
         if u.config.disabled {
             bail!(Unauthenticated, msg("user {:?} is disabled", &u.username));
         }
+        if expired {
+            bail!(Unauthenticated, msg("user {:?} has expired", &u.username));
+        }
         Ok((s, u))
     }
 
@@ -791,6 +1498,52 @@ impl State {
         Ok(())
     }
 
+    /// Revokes all of a user's sessions which aren't already revoked, returning the number revoked.
+    pub fn revoke_user_sessions(
+        &mut self,
+        conn: &Connection,
+        user_id: i32,
+        reason: RevocationReason,
+        detail: Option<String>,
+        req: Request,
+    ) -> Result<usize, Error> {
+        if !self.users_by_id.contains_key(&user_id) {
+            bail!(NotFound, msg("no such uid {user_id:?}"));
+        }
+        let mut stmt = conn.prepare_cached(
+            r#"
+            update user_session
+            set
+                revocation_time_sec = ?,
+                revocation_user_agent = ?,
+                revocation_peer_addr = ?,
+                revocation_reason = ?,
+                revocation_reason_detail = ?
+            where
+                user_id = ? and
+                revocation_reason is null
+            "#,
+        )?;
+        let addr = req.addr_buf();
+        let addr: Option<&[u8]> = addr.as_ref().map(|a| a.as_ref());
+        let n = stmt.execute(params![
+            req.when_sec,
+            req.user_agent,
+            addr,
+            reason as i32,
+            detail,
+            user_id,
+        ])?;
+        for s in self.sessions.values_mut() {
+            if s.user_id == user_id && s.revocation_reason.is_none() {
+                s.revocation = req.clone();
+                s.revocation_reason = Some(reason as i32);
+                s.revocation_reason_detail.clone_from(&detail);
+            }
+        }
+        Ok(n)
+    }
+
     /// Flushes all pending database changes to the given transaction.
     ///
     /// The caller is expected to call `post_flush` afterward if the transaction is
@@ -801,7 +1554,9 @@ impl State {
             update user
             set
                 password_failure_count = :password_failure_count,
-                password_hash = :password_hash
+                password_hash = :password_hash,
+                download_day = :download_day,
+                download_bytes = :download_bytes
             where
                 id = :id
             "#,
@@ -822,13 +1577,12 @@ impl State {
             if !u.dirty {
                 continue;
             }
-            info!(
-                "flushing user with hash: {}",
-                u.password_hash.as_ref().unwrap()
-            );
+            info!("flushing user {:?}", &u.username);
             u_stmt.execute(named_params! {
                 ":password_failure_count": &u.password_failure_count,
                 ":password_hash": &u.password_hash,
+                ":download_day": &u.download_day,
+                ":download_bytes": &u.download_bytes,
                 ":id": &id,
             })?;
         }
@@ -946,7 +1700,68 @@ mod tests {
         set_test_config();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        State::init(&conn).unwrap();
+        State::init(&conn, &GlobalConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn rehash_on_login() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let req = Request::default();
+
+        // Start with a hash from before the switch to Argon2id.
+        let salt = SaltString::generate(&mut scrypt::password_hash::rand_core::OsRng);
+        let scrypt_hash = scrypt::Scrypt
+            .hash_password_customized(
+                b"hunter2",
+                None,
+                None,
+                scrypt::Params::new(8, 8, 1, scrypt::Params::RECOMMENDED_LEN).unwrap(),
+                &salt,
+            )
+            .unwrap()
+            .to_string();
+        let uid = {
+            let mut c = UserChange::add_user("slamb".to_owned());
+            c.set_password_hash(scrypt_hash.clone()).unwrap();
+            state.apply(&conn, c).unwrap().id
+        };
+        assert!(state.password_needs_rehash(&state.users_by_id()[&uid]));
+        let login = |state: &mut State| {
+            state
+                .login_by_password(&conn, req.clone(), "slamb", "hunter2".to_owned(), None, 0)
+                .unwrap()
+                .0
+        };
+        let sid = login(&mut state);
+        let u = &state.users_by_id()[&uid];
+        assert!(u.password_hash().unwrap().starts_with("$argon2id$"));
+        assert!(u.dirty);
+        assert_eq!(u.password_id, 0);
+        assert!(!state.password_needs_rehash(u));
+
+        // Changing the parameters upgrades again, without invalidating existing sessions.
+        state.set_password_params(argon2::Params::new(16, 1, 1, None).unwrap());
+        assert!(state.password_needs_rehash(&state.users_by_id()[&uid]));
+        login(&mut state);
+        let u = &state.users_by_id()[&uid];
+        assert!(u.password_hash().unwrap().contains("m=16,t=1,p=1"));
+        assert!(!state.password_needs_rehash(u));
+        state
+            .authenticate_session(&conn, req.clone(), &sid.hash())
+            .unwrap();
+
+        // New passwords are hashed with the current parameters, and wrong ones still fail.
+        let mut c = state.users_by_id()[&uid].change();
+        c.set_password("hunter3".to_owned());
+        assert!(!format!("{c:?}").contains("hunter3"));
+        state.apply(&conn, c).unwrap();
+        let u = state.get_user_by_id_mut(uid).unwrap();
+        assert!(u.password_hash().unwrap().contains("m=16,t=1,p=1"));
+        assert!(!u.check_password(Some("hunter2")).unwrap());
+        assert!(u.check_password(Some("hunter3")).unwrap());
     }
 
     #[test]
@@ -954,7 +1769,7 @@ mod tests {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let req = Request {
             when_sec: Some(42),
             addr: Some(::std::net::IpAddr::V4(::std::net::Ipv4Addr::new(
@@ -1031,7 +1846,7 @@ mod tests {
 
         // Everything should persist across reload.
         drop(state);
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let e = state
             .authenticate_session(&conn, req, &sid.hash())
             .unwrap_err();
@@ -1044,7 +1859,7 @@ mod tests {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let req = Request {
             when_sec: Some(42),
             addr: Some(::std::net::IpAddr::V4(::std::net::Ipv4Addr::new(
@@ -1080,7 +1895,7 @@ mod tests {
 
         // Everything should persist across reload.
         drop(state);
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let (s, _u) = state.authenticate_session(&conn, req, &sid.hash()).unwrap();
         assert_eq!(s.use_count, 2);
     }
@@ -1090,7 +1905,7 @@ mod tests {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let req = Request {
             when_sec: Some(42),
             addr: Some(::std::net::IpAddr::V4(::std::net::Ipv4Addr::new(
@@ -1120,7 +1935,7 @@ mod tests {
 
         // Reload.
         drop(state);
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         state
             .revoke_session(
                 &conn,
@@ -1137,12 +1952,192 @@ mod tests {
         assert_eq!(e.msg().unwrap(), "session is no longer valid (reason=1)");
     }
 
+    #[test]
+    fn revoke_user_sessions() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let req = Request {
+            when_sec: Some(42),
+            addr: None,
+            user_agent: None,
+        };
+        let uid = {
+            let mut c = UserChange::add_user("slamb".to_owned());
+            c.set_password("hunter2".to_owned());
+            state.apply(&conn, c).unwrap().id
+        };
+        let login = |state: &mut State| {
+            state
+                .login_by_password(
+                    &conn,
+                    req.clone(),
+                    "slamb",
+                    "hunter2".to_owned(),
+                    Some(b"nvr.example.com".to_vec()),
+                    0,
+                )
+                .unwrap()
+                .0
+        };
+        let cached = login(&mut state);
+        let uncached = login(&mut state);
+
+        // Reload so that only one session is in the cache.
+        drop(state);
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        state
+            .authenticate_session(&conn, req.clone(), &cached.hash())
+            .unwrap();
+        assert_eq!(
+            state
+                .revoke_user_sessions(
+                    &conn,
+                    uid,
+                    RevocationReason::AdminRevoked,
+                    None,
+                    req.clone()
+                )
+                .unwrap(),
+            2
+        );
+        for sid in [cached, uncached] {
+            let e = state
+                .authenticate_session(&conn, req.clone(), &sid.hash())
+                .unwrap_err();
+            assert_eq!(e.msg().unwrap(), "session is no longer valid (reason=3)");
+        }
+
+        // Sessions created afterward are unaffected.
+        let sid = login(&mut state);
+        state
+            .authenticate_session(&conn, req.clone(), &sid.hash())
+            .unwrap();
+        assert_eq!(
+            state
+                .revoke_user_sessions(&conn, uid, RevocationReason::AdminRevoked, None, req)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let at = |when_sec| Request {
+            when_sec: Some(when_sec),
+            addr: None,
+            user_agent: None,
+        };
+        {
+            let mut c = UserChange::add_user("slamb".to_owned());
+            c.set_password("hunter2".to_owned());
+            state.apply(&conn, c).unwrap();
+        }
+        let sid = state
+            .login_by_password(&conn, at(0), "slamb", "hunter2".to_owned(), None, 0)
+            .unwrap()
+            .0;
+        let hash = sid.hash();
+
+        // Fixed expiry is measured from creation, regardless of use.
+        state.set_session_expiry(Some(SessionExpiry {
+            max_age_sec: 100,
+            sliding: false,
+        }));
+        state.authenticate_session(&conn, at(90), &hash).unwrap();
+        let e = state
+            .authenticate_session(&conn, at(101), &hash)
+            .unwrap_err();
+        assert_eq!(e.msg().unwrap(), "session has expired");
+
+        // Sliding expiry is measured from the last use.
+        state.set_session_expiry(Some(SessionExpiry {
+            max_age_sec: 100,
+            sliding: true,
+        }));
+        state.authenticate_session(&conn, at(180), &hash).unwrap();
+        state.authenticate_session(&conn, at(270), &hash).unwrap();
+        let e = state
+            .authenticate_session(&conn, at(371), &hash)
+            .unwrap_err();
+        assert_eq!(e.msg().unwrap(), "session has expired");
+    }
+
+    #[test]
+    fn purge_sessions() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let at = |when_sec| Request {
+            when_sec: Some(when_sec),
+            addr: None,
+            user_agent: None,
+        };
+        {
+            let mut c = UserChange::add_user("slamb".to_owned());
+            c.set_password("hunter2".to_owned());
+            state.apply(&conn, c).unwrap();
+        }
+        let mut login = |state: &mut State, when_sec| {
+            state
+                .login_by_password(&conn, at(when_sec), "slamb", "hunter2".to_owned(), None, 0)
+                .unwrap()
+                .0
+                .hash()
+        };
+        let expired = login(&mut state, 0);
+        let revoked = login(&mut state, 0);
+        let recent = login(&mut state, 1000);
+        state
+            .revoke_session(&conn, RevocationReason::LoggedOut, None, at(50), &revoked)
+            .unwrap();
+        state.set_session_expiry(Some(SessionExpiry {
+            max_age_sec: 100,
+            sliding: false,
+        }));
+
+        // Nothing is purged within the retention period.
+        assert_eq!(state.purge_sessions(&mut conn, 500, 1000).unwrap(), 0);
+        let stats = state.table_stats();
+        assert_eq!((stats.sessions, stats.revoked_sessions), (3, 1));
+
+        // Then the expired and revoked sessions are, but not the recently created one, even
+        // though it has also expired.
+        assert_eq!(state.purge_sessions(&mut conn, 1200, 1000).unwrap(), 2);
+        let stats = state.table_stats();
+        assert_eq!(
+            (
+                stats.users,
+                stats.sessions,
+                stats.revoked_sessions,
+                stats.purged_sessions
+            ),
+            (1, 1, 0, 2)
+        );
+        for hash in [expired, revoked] {
+            let e = state
+                .authenticate_session(&conn, at(1200), &hash)
+                .unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Unauthenticated);
+        }
+        let e = state
+            .authenticate_session(&conn, at(1200), &recent)
+            .unwrap_err();
+        assert_eq!(e.msg().unwrap(), "session has expired");
+    }
+
     #[test]
     fn disable() {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let req = Request {
             when_sec: Some(42),
             addr: Some(::std::net::IpAddr::V4(::std::net::Ipv4Addr::new(
@@ -1199,7 +2194,7 @@ mod tests {
 
         // The user should still be disabled after reload.
         drop(state);
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let e = state
             .authenticate_session(&conn, req, &sid.hash())
             .unwrap_err();
@@ -1207,12 +2202,56 @@ mod tests {
         assert_eq!(e.msg().unwrap(), "user \"slamb\" is disabled");
     }
 
+    #[test]
+    fn expired_user() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let req = |when_sec| Request {
+            when_sec: Some(when_sec),
+            addr: None,
+            user_agent: None,
+        };
+        let uid = {
+            let mut c = UserChange::add_user("guest".to_owned());
+            c.set_password("hunter2".to_owned());
+            c.config.expiration_time_sec = Some(100);
+            state.apply(&conn, c).unwrap().id
+        };
+        let sid = state
+            .login_by_password(&conn, req(42), "guest", "hunter2".to_owned(), None, 0)
+            .unwrap()
+            .0;
+        state
+            .authenticate_session(&conn, req(99), &sid.hash())
+            .unwrap();
+
+        // Expiry is enforced before the user is disabled.
+        let e = state
+            .authenticate_session(&conn, req(100), &sid.hash())
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unauthenticated);
+        assert_eq!(e.msg().unwrap(), "user \"guest\" has expired");
+        let e = state
+            .login_by_password(&conn, req(100), "guest", "hunter2".to_owned(), None, 0)
+            .unwrap_err();
+        assert_eq!(e.msg().unwrap(), "user \"guest\" has expired");
+
+        assert!(state.disable_expired_users(&conn, 99).unwrap().is_empty());
+        assert_eq!(state.disable_expired_users(&conn, 100).unwrap(), ["guest"]);
+        assert!(state.disable_expired_users(&conn, 101).unwrap().is_empty());
+        drop(state);
+        let state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        assert!(state.users_by_id()[&uid].config.disabled);
+    }
+
     #[test]
     fn change() {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let uid = {
             let mut c = UserChange::add_user("slamb".to_owned());
             c.set_password("hunter2".to_owned());
@@ -1233,7 +2272,7 @@ mod tests {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let req = Request {
             when_sec: Some(42),
             addr: Some(::std::net::IpAddr::V4(::std::net::Ipv4Addr::new(
@@ -1269,7 +2308,7 @@ mod tests {
 
         // The user should still be deleted after reload.
         drop(state);
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         assert!(state.users_by_id().get(&uid).is_none());
         let e = state
             .authenticate_session(&conn, req, &sid.hash())
@@ -1278,23 +2317,97 @@ mod tests {
         assert_eq!(e.msg().unwrap(), "no such session");
     }
 
+    #[test]
+    fn webauthn() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let req = Request {
+            when_sec: Some(42),
+            addr: None,
+            user_agent: None,
+        };
+        let uid = state
+            .apply(&conn, UserChange::add_user("slamb".to_owned()))
+            .unwrap()
+            .id;
+        let id = state
+            .add_webauthn_credential(
+                &conn,
+                uid,
+                b"cred".to_vec(),
+                b"key".to_vec(),
+                5,
+                "YubiKey".to_owned(),
+                40,
+            )
+            .unwrap()
+            .id;
+        let e = state
+            .add_webauthn_credential(
+                &conn,
+                uid,
+                b"cred".to_vec(),
+                b"key".to_vec(),
+                0,
+                "dup".to_owned(),
+                40,
+            )
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+
+        // The counter must advance.
+        let e = state
+            .login_by_webauthn(&conn, req.clone(), b"cred", 5, None, 0)
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unauthenticated);
+        let (sid, _) = state
+            .login_by_webauthn(&conn, req.clone(), b"cred", 6, None, 0)
+            .unwrap();
+        let (s, u) = state
+            .authenticate_session(&conn, req.clone(), &sid.hash())
+            .unwrap();
+        assert_eq!(s.creation_password_id, None);
+        assert_eq!(u.id, uid);
+
+        // The new counter and last use should be persisted immediately.
+        drop(state);
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let c = state.get_webauthn_credential(b"cred").unwrap();
+        assert_eq!(c.id, id);
+        assert_eq!(c.sign_count, 6);
+        assert_eq!(c.last_use_time_sec, Some(42));
+
+        // Deleting the user deletes its credentials.
+        state.delete_user(&mut conn, uid).unwrap();
+        assert!(state.get_webauthn_credential(b"cred").is_none());
+        assert!(state.webauthn_credentials_by_id().is_empty());
+        let n: i64 = conn
+            .query_row("select count(*) from user_webauthn_credential", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+
     #[test]
     fn permissions() {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let mut change = UserChange::add_user("slamb".to_owned());
-        change.permissions.view_video = true;
+        change.permissions.view_live = true;
         let u = state.apply(&conn, change).unwrap();
-        assert!(u.permissions.view_video);
+        assert!(u.permissions.view_live);
         assert!(!u.permissions.update_signals);
         let mut change = u.change();
-        assert!(change.permissions.view_video);
+        assert!(change.permissions.view_live);
         assert!(!change.permissions.update_signals);
         change.permissions.update_signals = true;
         let u = state.apply(&conn, change).unwrap();
-        assert!(u.permissions.view_video);
+        assert!(u.permissions.view_live);
         assert!(u.permissions.update_signals);
         let uid = u.id;
 
@@ -1303,9 +2416,9 @@ mod tests {
             state.flush(&tx).unwrap();
             tx.commit().unwrap();
         }
-        let state = State::init(&conn).unwrap();
+        let state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let u = state.users_by_id().get(&uid).unwrap();
-        assert!(u.permissions.view_video);
+        assert!(u.permissions.view_live);
         assert!(u.permissions.update_signals);
     }
 
@@ -1314,7 +2427,7 @@ mod tests {
         testutil::init();
         let mut conn = Connection::open_in_memory().unwrap();
         db::init(&mut conn).unwrap();
-        let mut state = State::init(&conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let mut change = UserChange::add_user("slamb".to_owned());
         change
             .config
@@ -1336,9 +2449,79 @@ mod tests {
             state.flush(&tx).unwrap();
             tx.commit().unwrap();
         }
-        let state = State::init(&conn).unwrap();
+        let state = State::init(&conn, &GlobalConfig::default()).unwrap();
         let u = state.users_by_id().get(&uid).unwrap();
         assert_eq!(u.config.preferences.get("foo"), Some(&42.into()));
         assert_eq!(u.config.preferences.get("bar"), Some(&26.into()));
     }
+
+    #[test]
+    fn permission_templates() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let names: Vec<_> = state
+            .permission_templates_by_id()
+            .values()
+            .map(|t| t.name.as_str())
+            .collect();
+        assert_eq!(names, ["viewer", "operator", "admin"]);
+        let viewer = state.permission_templates_by_id().values().next().unwrap();
+        assert!(
+            viewer.permissions.view_live
+                && viewer.permissions.download_recordings
+                && !viewer.permissions.admin_users
+        );
+
+        let tid = state
+            .add_permission_template(
+                &conn,
+                "guard".to_owned(),
+                Permissions {
+                    view_live: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .id;
+        let e = state
+            .add_permission_template(&conn, "guard".to_owned(), Permissions::default())
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+
+        // Assigning a template copies its permissions.
+        let mut c = UserChange::add_user("slamb".to_owned());
+        c.permission_template_id = Some(tid);
+        let uid = state.apply(&conn, c).unwrap().id;
+        assert!(state.users_by_id()[&uid].permissions.view_live);
+
+        // Updating the template updates its users, in memory and in the database.
+        state
+            .update_permission_template(
+                &mut conn,
+                tid,
+                "guard".to_owned(),
+                Permissions {
+                    view_live: true,
+                    update_signals: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(state.users_by_id()[&uid].permissions.update_signals);
+        state = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let u = &state.users_by_id()[&uid];
+        assert!(u.permissions.update_signals);
+        assert_eq!(u.permission_template_id, Some(tid));
+
+        // A template in use can't be deleted.
+        let e = state.delete_permission_template(&conn, tid).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::FailedPrecondition);
+        let mut c = state.users_by_id()[&uid].change();
+        c.permission_template_id = None;
+        state.apply(&conn, c).unwrap();
+        state.delete_permission_template(&conn, tid).unwrap();
+        assert!(!state.permission_templates_by_id().contains_key(&tid));
+    }
 }
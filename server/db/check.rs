@@ -11,11 +11,11 @@ use crate::json::SampleFileDirConfig;
 use crate::raw;
 use crate::recording;
 use crate::schema;
-use base::{err, Error};
+use base::{bail, err, Error};
 use base::{FastHashMap, FastHashSet};
-use nix::fcntl::AtFlags;
-use rusqlite::params;
-use std::os::unix::io::AsRawFd;
+use rusqlite::{params, OptionalExtension};
+use std::io::Read as _;
+use std::sync::Arc;
 use tracing::{error, info, warn};
 
 pub struct Options {
@@ -23,14 +23,44 @@ pub struct Options {
     pub trash_orphan_sample_files: bool,
     pub delete_orphan_rows: bool,
     pub trash_corrupt_rows: bool,
+    pub adopt_orphan_sample_files: bool,
+
+    /// Validates each frame of every `video_index`, beyond what's needed to summarize it.
+    pub indexes: bool,
+
+    /// Rewrites `recording` rows whose summary doesn't match their `video_index`.
+    pub repair_summaries: bool,
+
+    /// If set, encrypted sample files are checked for decryptability with this key.
+    pub sample_file_key: Option<dir::crypt::Key>,
 }
 
 #[derive(Default)]
 pub struct Context {
     rows_to_delete: FastHashSet<CompositeId>,
     files_to_trash: FastHashSet<(i32, CompositeId)>, // (dir_id, composite_id)
+    adoptions: Vec<Adoption>,
+
+    /// Recording rows to rewrite from their `video_index`; see [`Options::repair_summaries`].
+    summaries_to_repair: Vec<(CompositeId, RecordingSummary)>,
+
+    /// The number of `video_index` values checked with [`Options::indexes`].
+    indexes_checked: usize,
+}
+
+/// Recordings to create for a stream's orphaned sample files; see [`adopt_orphans`].
+struct Adoption {
+    stream_id: i32,
+    open: db::Open,
+    recordings: Vec<(CompositeId, db::RecordingToInsert)>,
+    cum_recordings: i32,
+    cum_media_duration_90k: i64,
+    cum_runs: i32,
 }
 
+/// The `end_reason` of recordings created by [`adopt_orphans`].
+const ADOPTED_END_REASON: &str = "adopted by moonfire-nvr check; times are estimated";
+
 pub fn run(conn: &mut rusqlite::Connection, opts: &Options) -> Result<i32, Error> {
     let mut printed_error = false;
 
@@ -78,8 +108,33 @@ pub fn run(conn: &mut rusqlite::Connection, opts: &Options) -> Result<i32, Error
 
     let (db_uuid, _config) = raw::read_meta(conn)?;
 
+    // Check the sample file key, if any.
+    let key_check: Option<Vec<u8>> = conn
+        .query_row("select key_check from sample_file_key", params![], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    let key = match (&opts.sample_file_key, key_check) {
+        (Some(k), Some(c)) if c[..] == k.check_value()[..] => Some(k),
+        (Some(_), Some(_)) => {
+            error!("Sample file key doesn't match the one used previously.");
+            printed_error = true;
+            None
+        }
+        (Some(_), None) => {
+            warn!("Sample file key supplied, but sample file encryption isn't enabled.");
+            None
+        }
+        (None, Some(_)) => {
+            info!("Sample file encryption is enabled; supply the key to verify encrypted files.");
+            None
+        }
+        (None, None) => None,
+    };
+
     // Scan directories.
     let mut dirs_by_id: FastHashMap<i32, Dir> = FastHashMap::default();
+    let mut sample_file_dirs: FastHashMap<i32, Arc<dir::SampleFileDir>> = FastHashMap::default();
     {
         let mut dir_stmt = conn.prepare(
             r#"
@@ -119,6 +174,7 @@ pub fn run(conn: &mut rusqlite::Connection, opts: &Options) -> Result<i32, Error
                     .garbage_row = true;
             }
             dirs_by_id.insert(dir_id, streams);
+            sample_file_dirs.insert(dir_id, dir);
         }
     }
 
@@ -147,7 +203,16 @@ pub fn run(conn: &mut rusqlite::Connection, opts: &Options) -> Result<i32, Error
                 Some(d) => d.remove(&stream_id).unwrap_or_default(),
             };
             stream.cum_recordings = Some(cum_recordings);
-            printed_error |= compare_stream(conn, dir_id, stream_id, opts, stream, &mut ctx)?;
+            printed_error |= compare_stream(
+                conn,
+                dir_id,
+                sample_file_dirs.get(&dir_id).map(|d| &**d),
+                key,
+                stream_id,
+                opts,
+                stream,
+                &mut ctx,
+            )?;
         }
     }
 
@@ -171,17 +236,27 @@ pub fn run(conn: &mut rusqlite::Connection, opts: &Options) -> Result<i32, Error
         }
     }
 
-    if !ctx.rows_to_delete.is_empty() || !ctx.files_to_trash.is_empty() {
+    if opts.indexes {
+        info!("Checked {} video indexes.", ctx.indexes_checked);
+    }
+
+    if !ctx.rows_to_delete.is_empty()
+        || !ctx.files_to_trash.is_empty()
+        || !ctx.adoptions.is_empty()
+        || !ctx.summaries_to_repair.is_empty()
+    {
         let tx = conn.transaction()?;
         if !ctx.rows_to_delete.is_empty() {
             info!("Deleting {} recording rows", ctx.rows_to_delete.len());
             let mut d1 = tx.prepare("delete from recording_playback where composite_id = ?")?;
             let mut d2 = tx.prepare("delete from recording_integrity where composite_id = ?")?;
-            let mut d3 = tx.prepare("delete from recording where composite_id = ?")?;
+            let mut d3 = tx.prepare("delete from recording_rtsp_session where composite_id = ?")?;
+            let mut d4 = tx.prepare("delete from recording where composite_id = ?")?;
             for &id in &ctx.rows_to_delete {
                 d1.execute(params![id.0])?;
                 d2.execute(params![id.0])?;
                 d3.execute(params![id.0])?;
+                d4.execute(params![id.0])?;
             }
         }
         if !ctx.files_to_trash.is_empty() {
@@ -193,13 +268,75 @@ pub fn run(conn: &mut rusqlite::Connection, opts: &Options) -> Result<i32, Error
                 g.execute(params![dir_id, composite_id.0])?;
             }
         }
+        if !ctx.summaries_to_repair.is_empty() {
+            info!(
+                "Repairing {} recording summaries",
+                ctx.summaries_to_repair.len()
+            );
+            let mut stmt = tx.prepare(
+                r#"
+                update recording
+                set
+                  flags = (flags & ~:summary_flags) | :flags,
+                  sample_file_bytes = :sample_file_bytes,
+                  media_duration_delta_90k = :media_duration_90k - wall_duration_90k,
+                  video_samples = :video_samples,
+                  video_sync_samples = :video_sync_samples
+                where
+                  composite_id = :composite_id
+                "#,
+            )?;
+            for (id, s) in &ctx.summaries_to_repair {
+                stmt.execute(rusqlite::named_params! {
+                    ":summary_flags": SUMMARY_FLAGS,
+                    ":flags": s.flags & SUMMARY_FLAGS,
+                    ":sample_file_bytes": s.bytes as i64,
+                    ":media_duration_90k": s.media_duration,
+                    ":video_samples": s.video_samples,
+                    ":video_sync_samples": s.video_sync_samples,
+                    ":composite_id": id.0,
+                })?;
+            }
+        }
+        for a in &ctx.adoptions {
+            info!(
+                "Adopting {} orphan sample files for stream {}",
+                a.recordings.len(),
+                a.stream_id
+            );
+            for (id, r) in &a.recordings {
+                raw::insert_recording(&tx, &a.open, *id, r)?;
+            }
+            let n = tx.execute(
+                r#"
+                update stream
+                set cum_recordings = ?, cum_media_duration_90k = ?, cum_runs = ?
+                where id = ?
+                "#,
+                params![
+                    a.cum_recordings,
+                    a.cum_media_duration_90k,
+                    a.cum_runs,
+                    a.stream_id
+                ],
+            )?;
+            if n != 1 {
+                bail!(Internal, msg("unable to update stream {}", a.stream_id));
+            }
+        }
         tx.commit()?;
     }
 
     Ok(if printed_error { 1 } else { 0 })
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// The `recording.flags` bits derived from the `video_index`, which
+/// [`Options::repair_summaries`] may rewrite. `CompositionOffsets` isn't among them, as it
+/// determines how the index is decoded.
+const SUMMARY_FLAGS: i32 =
+    db::RecordingFlags::TrailingZero as i32 | db::RecordingFlags::Reordered as i32;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 struct RecordingSummary {
     bytes: u64,
     video_samples: i32,
@@ -225,6 +362,9 @@ struct Recording {
 
     /// True iff a `garbage` row is present.
     garbage_row: bool,
+
+    /// True iff the `recording` row says the sample file is encrypted.
+    encrypted: bool,
 }
 
 #[derive(Default)]
@@ -235,57 +375,85 @@ struct Stream {
 
 type Dir = FastHashMap<i32, Stream>;
 
-fn summarize_index(video_index: &[u8]) -> Result<RecordingSummary, Error> {
-    let mut it = recording::SampleIndexIterator::default();
+/// Summarizes `video_index`, interpreting it according to the recording row's `flags`.
+fn summarize_index(video_index: &[u8], flags: i32) -> Result<RecordingSummary, Error> {
+    let mut it = recording::SampleIndexIterator::new(flags);
+    let mut reordered = false;
     let mut media_duration = 0;
     let mut video_samples = 0;
     let mut video_sync_samples = 0;
     let mut bytes = 0;
     while it.next(video_index)? {
         bytes += it.bytes as u64;
-        media_duration += it.duration_90k;
+        media_duration = i32::checked_add(media_duration, it.duration_90k)
+            .ok_or_else(|| err!(DataLoss, msg("media duration overflows")))?;
         video_samples += 1;
         video_sync_samples += it.is_key() as i32;
+        reordered |= it.composition_offset_90k != 0;
     }
     Ok(RecordingSummary {
         bytes,
         video_samples,
         video_sync_samples,
         media_duration,
-        flags: if it.duration_90k == 0 {
-            db::RecordingFlags::TrailingZero as i32
-        } else {
-            0
-        },
+        flags: (flags & db::RecordingFlags::CompositionOffsets as i32)
+            | if reordered {
+                db::RecordingFlags::Reordered as i32
+            } else {
+                0
+            }
+            | if it.duration_90k == 0 {
+                db::RecordingFlags::TrailingZero as i32
+            } else {
+                0
+            },
     })
 }
 
+/// Validates each frame of `video_index`, for [`Options::indexes`]. This checks properties the
+/// writer guarantees but [`summarize_index`] doesn't need.
+fn check_index(video_index: &[u8], flags: i32) -> Result<(), Error> {
+    let mut it = recording::SampleIndexIterator::new(flags);
+    let mut i = 0;
+    while it.next(video_index)? {
+        if i == 0 && !it.is_key() {
+            bail!(DataLoss, msg("first frame isn't a key frame"));
+        }
+        if it.composition_offset_90k < 0 {
+            bail!(
+                DataLoss,
+                msg(
+                    "frame {i} has negative composition offset {}",
+                    it.composition_offset_90k
+                )
+            );
+        }
+        i += 1;
+    }
+    if i == 0 {
+        bail!(DataLoss, msg("no frames"));
+    }
+    Ok(())
+}
+
 /// Reads through the given sample file directory.
 /// Logs unexpected files and creates a hash map of the files found there.
 /// If `opts.compare_lens` is set, the values are lengths; otherwise they're insignificant.
 fn read_dir(d: &dir::SampleFileDir, opts: &Options) -> Result<Dir, Error> {
     let mut dir = Dir::default();
-    let mut d = d.opendir()?;
-    let fd = d.as_raw_fd();
-    for e in d.iter() {
-        let e = e?;
-        let f = e.file_name();
-        match f.to_bytes() {
-            b"." | b".." | b"meta" => continue,
-            _ => {}
-        };
-        let id = match dir::parse_id(f.to_bytes()) {
+    d.list(&mut |f| {
+        let id = match dir::parse_id(f) {
             Ok(id) => id,
             Err(_) => {
                 error!(
                     "sample file directory contains file {:?} which isn't an id",
-                    f
+                    f.escape_ascii().to_string()
                 );
-                continue;
+                return Ok(());
             }
         };
         let len = if opts.compare_lens {
-            nix::sys::stat::fstatat(fd, f, AtFlags::empty())?.st_size as u64
+            d.file_len(id)?
         } else {
             0
         };
@@ -295,14 +463,18 @@ fn read_dir(d: &dir::SampleFileDir, opts: &Options) -> Result<Dir, Error> {
             .entry(id.recording())
             .or_insert_with(Recording::default)
             .file = Some(len);
-    }
+        Ok(())
+    })?;
     Ok(dir)
 }
 
 /// Looks through a known stream for errors.
+#[allow(clippy::too_many_arguments)]
 fn compare_stream(
     conn: &rusqlite::Connection,
     dir_id: i32,
+    dir: Option<&dir::SampleFileDir>,
+    key: Option<&dir::crypt::Key>,
     stream_id: i32,
     opts: &Options,
     mut stream: Stream,
@@ -335,18 +507,18 @@ fn compare_stream(
         let mut rows = stmt.query(params![start.0, end.0])?;
         while let Some(row) = rows.next()? {
             let id = CompositeId(row.get(0)?);
+            let flags: i32 = row.get(1)?;
+            let encrypted = db::RecordingFlags::Encrypted as i32;
             let s = RecordingSummary {
-                flags: row.get(1)?,
+                flags: flags & !encrypted,
                 bytes: row.get::<_, i64>(2)? as u64,
                 media_duration: row.get(3)?,
                 video_samples: row.get(4)?,
                 video_sync_samples: row.get(5)?,
             };
-            stream
-                .recordings
-                .entry(id.recording())
-                .or_default()
-                .recording_row = Some(s);
+            let r = stream.recordings.entry(id.recording()).or_default();
+            r.recording_row = Some(s);
+            r.encrypted = (flags & encrypted) != 0;
         }
     }
 
@@ -367,7 +539,19 @@ fn compare_stream(
         while let Some(row) = rows.next()? {
             let id = CompositeId(row.get(0)?);
             let video_index: Vec<u8> = row.get(1)?;
-            let s = match summarize_index(&video_index) {
+            let flags = stream
+                .recordings
+                .get(&id.recording())
+                .and_then(|r| r.recording_row.as_ref())
+                .map_or(0, |r| r.flags);
+            let s = summarize_index(&video_index, flags).and_then(|s| {
+                if opts.indexes {
+                    ctx.indexes_checked += 1;
+                    check_index(&video_index, flags)?;
+                }
+                Ok(s)
+            });
+            let s = match s {
                 Ok(s) => s,
                 Err(e) => {
                     error!("id {} has bad video_index: {}", id, e);
@@ -456,6 +640,9 @@ fn compare_stream(
                         id, recording
                     );
                     printed_error = true;
+                    if opts.repair_summaries {
+                        ctx.summaries_to_repair.push((id, p.clone()));
+                    }
                 }
             }
             None => {
@@ -472,10 +659,21 @@ fn compare_stream(
         }
         match recording.file {
             Some(len) => {
-                if opts.compare_lens && r.bytes != len {
+                let expected_len = if recording.encrypted {
+                    dir::crypt::encrypted_len(r.bytes, r.video_samples as u64)
+                } else {
+                    r.bytes
+                };
+                if opts.compare_lens && expected_len != len {
                     error!("Recording {} length mismatch: {:#?}", id, recording);
                     printed_error = true;
                 }
+                if let (true, Some(dir), Some(key)) = (recording.encrypted, dir, key) {
+                    if let Err(e) = check_decryptable(dir, key, id, r.bytes) {
+                        error!(err = %e.chain(), "Recording {id} isn't decryptable");
+                        printed_error = true;
+                    }
+                }
             }
             None => {
                 error!("Recording {} missing file: {:#?}", id, recording);
@@ -487,5 +685,351 @@ fn compare_stream(
         }
     }
 
+    if let (true, Some(dir)) = (opts.adopt_orphan_sample_files, dir) {
+        printed_error |= adopt_orphans(conn, dir, stream_id, &stream, ctx)?;
+    }
+
     Ok(printed_error)
 }
+
+/// Checks that the given encrypted sample file decrypts to the expected number of bytes.
+fn check_decryptable(
+    dir: &dir::SampleFileDir,
+    key: &dir::crypt::Key,
+    id: CompositeId,
+    expected_bytes: u64,
+) -> Result<(), Error> {
+    let mut data = Vec::new();
+    dir.open_file_sync(id)?.read_to_end(&mut data)?;
+    let plaintext = key.decrypt_file(id, &data)?;
+    if plaintext.len() as u64 != expected_bytes {
+        bail!(
+            DataLoss,
+            msg(
+                "decrypted to {} bytes; expected {expected_bytes}",
+                plaintext.len()
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Plans recording rows for sample files written after the stream's last database commit, as
+/// when the database has been restored from a backup older than the sample files. Normally
+/// these files are deleted on startup.
+///
+/// Sample files don't contain timestamps, so these are estimated: each adopted recording is
+/// assumed to start when the previous one ended and to have frames at the same average rate as
+/// the stream's last committed recording. Each becomes its own run.
+fn adopt_orphans(
+    conn: &rusqlite::Connection,
+    dir: &dir::SampleFileDir,
+    stream_id: i32,
+    stream: &Stream,
+    ctx: &mut Context,
+) -> Result<bool, Error> {
+    let cum_recordings = stream
+        .cum_recordings
+        .expect("cum_recordings must be set on known stream");
+
+    // Only a contiguous sequence starting at cum_recordings can be adopted.
+    let mut ids = Vec::new();
+    while let Some(r) = stream.recordings.get(&(cum_recordings + ids.len() as i32)) {
+        if r.file.is_none()
+            || r.recording_row.is_some()
+            || r.playback_row.is_some()
+            || r.integrity_row
+            || r.garbage_row
+        {
+            break;
+        }
+        ids.push(CompositeId::new(
+            stream_id,
+            cum_recordings + ids.len() as i32,
+        ));
+    }
+    let next = cum_recordings + ids.len() as i32;
+    let stray = stream
+        .recordings
+        .iter()
+        .filter(|&(&id, r)| id > next && r.file.is_some() && !r.garbage_row)
+        .count();
+    if stray > 0 {
+        warn!(
+            "stream {stream_id}: {stray} sample files after a gap at recording {next} can't be \
+             adopted"
+        );
+    }
+    if ids.is_empty() {
+        return Ok(false);
+    }
+
+    // Base everything on the most recent committed recording.
+    let prev = conn
+        .query_row(
+            r#"
+            select
+              r.open_id,
+              o.uuid,
+              r.start_time_90k + r.wall_duration_90k,
+              r.wall_duration_90k + r.media_duration_delta_90k,
+              r.video_samples,
+              r.video_sample_entry_id,
+              v.rfc6381_codec,
+              s.cum_media_duration_90k,
+              s.cum_runs
+            from
+              recording r
+              join open o on (r.open_id = o.id)
+              join video_sample_entry v on (r.video_sample_entry_id = v.id)
+              join stream s on (r.stream_id = s.id)
+            where
+              r.composite_id between ? and ?
+            order by r.composite_id desc
+            limit 1
+            "#,
+            params![
+                CompositeId::new(stream_id, 0).0,
+                CompositeId::new(stream_id, i32::MAX).0
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, SqlUuid>(1)?,
+                    recording::Time(row.get(2)?),
+                    row.get::<_, i32>(3)?,
+                    row.get::<_, i32>(4)?,
+                    row.get::<_, i32>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, i64>(7)?,
+                    row.get::<_, i32>(8)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((
+        open_id,
+        open_uuid,
+        mut start,
+        prev_media_duration,
+        prev_video_samples,
+        video_sample_entry_id,
+        codec,
+        mut cum_media_duration_90k,
+        mut cum_runs,
+    )) = prev
+    else {
+        error!(
+            "stream {stream_id}: can't adopt {} sample files without a previous recording to \
+             base them on",
+            ids.len()
+        );
+        return Ok(true);
+    };
+    if !codec.starts_with("avc1.") || prev_video_samples == 0 {
+        error!("stream {stream_id}: can't adopt sample files with codec {codec}");
+        return Ok(true);
+    }
+    let frame_duration_90k = prev_media_duration / prev_video_samples;
+    let mut printed_error = false;
+    let mut recordings = Vec::with_capacity(ids.len());
+    for id in ids {
+        let mut data = Vec::new();
+        dir.open_file_sync(id)?
+            .read_to_end(&mut data)
+            .map_err(|e| err!(e, msg("unable to read {id}")))?;
+        if dir::crypt::is_encrypted(&data) {
+            error!("{id}: can't adopt encrypted sample file");
+            printed_error = true;
+            break;
+        }
+        let frames = match split_frames(&data) {
+            Ok(f) if f.first().is_some_and(|f| f.is_key) => f,
+            Ok(_) => {
+                error!("{id}: can't adopt sample file which doesn't start with a key frame");
+                printed_error = true;
+                break;
+            }
+            Err(e) => {
+                error!(err = %e.chain(), "{id}: can't adopt unparseable sample file");
+                printed_error = true;
+                break;
+            }
+        };
+        let mut r = db::RecordingToInsert {
+            start,
+            prev_media_duration: recording::Duration(cum_media_duration_90k),
+            prev_runs: cum_runs,
+            video_sample_entry_id,
+            sample_file_blake3: Some(*blake3::hash(&data).as_bytes()),
+            end_reason: Some(ADOPTED_END_REASON.to_owned()),
+            ..Default::default()
+        };
+        let mut encoder = recording::SampleIndexEncoder::default();
+        for f in &frames {
+            encoder.add_sample(frame_duration_90k, f.bytes, f.is_key, &mut r);
+        }
+        r.wall_duration_90k = r.media_duration_90k;
+        start += recording::Duration(i64::from(r.wall_duration_90k));
+        cum_media_duration_90k += i64::from(r.media_duration_90k);
+        cum_runs += 1;
+        recordings.push((id, r));
+    }
+    if recordings.is_empty() {
+        return Ok(printed_error);
+    }
+    info!(
+        "stream {stream_id}: will adopt {} orphan sample files with estimated times",
+        recordings.len()
+    );
+    ctx.adoptions.push(Adoption {
+        stream_id,
+        open: db::Open {
+            id: open_id,
+            uuid: open_uuid.0,
+        },
+        cum_recordings: cum_recordings + recordings.len() as i32,
+        recordings,
+        cum_media_duration_90k,
+        cum_runs,
+    });
+    Ok(printed_error)
+}
+
+/// A frame found by [`split_frames`].
+#[derive(Debug, Eq, PartialEq)]
+struct Frame {
+    bytes: i32,
+    is_key: bool,
+}
+
+/// Splits H.264 sample data, as written by the streamer (NAL units with 4-byte big-endian
+/// length prefixes), into frames.
+///
+/// A frame starts with an access unit delimiter, SEI, SPS, or PPS NAL unit, or with a slice
+/// whose `first_mb_in_slice` is 0, after the previous frame's slices.
+fn split_frames(data: &[u8]) -> Result<Vec<Frame>, Error> {
+    struct Cur {
+        start: usize,
+        have_slice: bool,
+        is_key: bool,
+    }
+    let mut frames = Vec::new();
+    let mut cur: Option<Cur> = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let Some(len) = data.get(pos..pos + 4) else {
+            bail!(DataLoss, msg("truncated NAL length at offset {pos}"));
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let Some(nal) = data.get(pos + 4..pos + 4 + len) else {
+            bail!(DataLoss, msg("truncated NAL at offset {pos}"));
+        };
+        let Some(&header) = nal.first() else {
+            bail!(DataLoss, msg("empty NAL at offset {pos}"));
+        };
+        let nal_type = header & 0x1f;
+        let is_slice = matches!(nal_type, 1 | 5);
+        let starts_frame = match nal_type {
+            // first_mb_in_slice is an Exp-Golomb value; a leading 1 bit means 0.
+            1 | 5 => nal.get(1).is_some_and(|&b| b & 0x80 != 0),
+            6..=9 => true,
+            _ => false,
+        };
+        match cur {
+            Some(ref c) if c.have_slice && starts_frame => {
+                frames.push(Frame {
+                    bytes: (pos - c.start) as i32,
+                    is_key: c.is_key,
+                });
+                cur = None;
+            }
+            _ => {}
+        }
+        let c = cur.get_or_insert(Cur {
+            start: pos,
+            have_slice: false,
+            is_key: false,
+        });
+        c.have_slice |= is_slice;
+        c.is_key |= nal_type == 5;
+        pos += 4 + len;
+    }
+    match cur {
+        Some(c) if c.have_slice => frames.push(Frame {
+            bytes: (pos - c.start) as i32,
+            is_key: c.is_key,
+        }),
+        Some(_) => bail!(DataLoss, msg("trailing NALs without a slice")),
+        None => {}
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nal(data: &[u8]) -> Vec<u8> {
+        let mut v = (data.len() as u32).to_be_bytes().to_vec();
+        v.extend_from_slice(data);
+        v
+    }
+
+    #[test]
+    fn index_checks() {
+        let encode = |frames: &[(i32, i32, bool)]| {
+            let mut r = db::RecordingToInsert::default();
+            let mut e = recording::SampleIndexEncoder::default();
+            for &(duration_90k, bytes, is_key) in frames {
+                e.add_sample(duration_90k, bytes, is_key, &mut r);
+            }
+            r.video_index
+        };
+        let good = encode(&[(3000, 100, true), (3000, 10, false), (0, 10, false)]);
+        check_index(&good, 0).unwrap();
+        assert_eq!(
+            summarize_index(&good, 0).unwrap(),
+            RecordingSummary {
+                bytes: 120,
+                video_samples: 3,
+                video_sync_samples: 1,
+                media_duration: 6000,
+                flags: db::RecordingFlags::TrailingZero as i32,
+            }
+        );
+        let e = check_index(&encode(&[(3000, 10, false), (3000, 100, true)]), 0).unwrap_err();
+        assert!(e.to_string().contains("first frame"), "{e}");
+        assert!(check_index(&[], 0).is_err());
+    }
+
+    #[test]
+    fn split() {
+        let mut data = Vec::new();
+        data.extend(nal(b"\x67\x4d\x00\x2a")); // SPS
+        data.extend(nal(b"\x68\xee\x3c\x80")); // PPS
+        data.extend(nal(b"\x65\x88\x84")); // IDR slice, first_mb_in_slice=0
+        data.extend(nal(b"\x65\x40\x84")); // IDR slice, first_mb_in_slice=1
+        data.extend(nal(b"\x41\x9a\x02")); // non-IDR slice, first_mb_in_slice=0
+        data.extend(nal(b"\x41\x9a\x03\x04")); // non-IDR slice, first_mb_in_slice=0
+        assert_eq!(
+            split_frames(&data).unwrap(),
+            vec![
+                Frame {
+                    bytes: 8 + 8 + 7 + 7,
+                    is_key: true
+                },
+                Frame {
+                    bytes: 7,
+                    is_key: false
+                },
+                Frame {
+                    bytes: 8,
+                    is_key: false
+                },
+            ]
+        );
+        assert!(split_frames(&data[..data.len() - 1]).is_err());
+        assert!(split_frames(&nal(b"\x67\x4d")).is_err());
+    }
+}
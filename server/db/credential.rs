@@ -0,0 +1,196 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Encryption of camera credentials at rest.
+//!
+//! A database with encryption enabled has a random data key, stored in the `credential_key`
+//! table encrypted ("wrapped") with a key supplied by the administrator. Camera credentials are
+//! encrypted with the data key into `CameraConfig::encrypted_credentials`, with the camera's
+//! uuid as associated data so that encrypted credentials can't be moved between cameras.
+//! See `LockedDatabase::set_credential_key`.
+
+use base::{bail, err, Error};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ring::aead;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::json::CameraConfig;
+
+/// The length of a key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// An AES-256-GCM key.
+pub struct Key(aead::LessSafeKey);
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(<redacted>)")
+    }
+}
+
+/// The plaintext of `CameraConfig::encrypted_credentials`.
+#[derive(Deserialize, Serialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Key {
+    /// Parses a base64-encoded key, as generated by `openssl rand -base64 32`.
+    pub fn parse(encoded: &str) -> Result<Self, Error> {
+        let raw = STANDARD.decode(encoded.trim()).map_err(|e| {
+            err!(
+                InvalidArgument,
+                msg("credential key isn't valid base64"),
+                source(e)
+            )
+        })?;
+        Self::from_bytes(&raw)
+    }
+
+    pub(crate) fn from_bytes(raw: &[u8]) -> Result<Self, Error> {
+        if raw.len() != KEY_LEN {
+            bail!(
+                InvalidArgument,
+                msg("credential key must be {KEY_LEN} bytes; got {}", raw.len())
+            );
+        }
+        let k = aead::UnboundKey::new(&aead::AES_256_GCM, raw)
+            .map_err(|_| err!(Internal, msg("unable to create AES-256-GCM key")))?;
+        Ok(Key(aead::LessSafeKey::new(k)))
+    }
+
+    /// Generates a random key, returning it along with its raw bytes.
+    pub(crate) fn generate() -> Result<(Self, [u8; KEY_LEN]), Error> {
+        let mut raw = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut raw)
+            .map_err(|_| err!(Internal, msg("unable to generate credential key")))?;
+        Ok((Self::from_bytes(&raw)?, raw))
+    }
+
+    /// Encrypts `plaintext`, returning the nonce followed by the ciphertext and tag.
+    pub(crate) fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| err!(Internal, msg("unable to generate nonce")))?;
+        let mut out = Vec::with_capacity(aead::NONCE_LEN + plaintext.len() + aead::MAX_TAG_LEN);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(plaintext);
+        let tag = self
+            .0
+            .seal_in_place_separate_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(aad),
+                &mut out[aead::NONCE_LEN..],
+            )
+            .map_err(|_| err!(Internal, msg("unable to encrypt")))?;
+        out.extend_from_slice(tag.as_ref());
+        Ok(out)
+    }
+
+    /// Decrypts the output of [`Key::seal`].
+    pub(crate) fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        if sealed.len() < aead::NONCE_LEN + aead::MAX_TAG_LEN {
+            bail!(DataLoss, msg("encrypted value is too short"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(aead::NONCE_LEN);
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| err!(Internal, msg("bad nonce length")))?;
+        let mut buf = ciphertext.to_vec();
+        let len = self
+            .0
+            .open_in_place(nonce, aead::Aad::from(aad), &mut buf)
+            .map_err(|_| {
+                err!(
+                    PermissionDenied,
+                    msg("unable to decrypt; wrong key or corrupt data")
+                )
+            })?
+            .len();
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+/// Moves `config`'s username and password into `encrypted_credentials`.
+pub(crate) fn encrypt(
+    key: &Key,
+    camera_uuid: Uuid,
+    config: &mut CameraConfig,
+) -> Result<(), Error> {
+    if config.username.is_empty() && config.password.is_empty() {
+        config.encrypted_credentials = None;
+        return Ok(());
+    }
+    let plaintext = serde_json::to_vec(&Credentials {
+        username: std::mem::take(&mut config.username),
+        password: std::mem::take(&mut config.password),
+    })
+    .map_err(|e| err!(Internal, source(e)))?;
+    let sealed = key.seal(camera_uuid.as_bytes(), &plaintext)?;
+    config.encrypted_credentials = Some(STANDARD.encode(sealed));
+    Ok(())
+}
+
+/// Moves `config`'s `encrypted_credentials`, if any, into its username and password.
+pub(crate) fn decrypt(
+    key: &Key,
+    camera_uuid: Uuid,
+    config: &mut CameraConfig,
+) -> Result<(), Error> {
+    let Some(encrypted) = config.encrypted_credentials.take() else {
+        return Ok(());
+    };
+    let sealed = STANDARD
+        .decode(&encrypted)
+        .map_err(|e| err!(DataLoss, msg("bad encrypted credentials"), source(e)))?;
+    let plaintext = key.open(camera_uuid.as_bytes(), &sealed)?;
+    let c: Credentials = serde_json::from_slice(&plaintext)
+        .map_err(|e| err!(DataLoss, msg("bad decrypted credentials"), source(e)))?;
+    config.username = c.username;
+    config.password = c.password;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let (key, raw) = Key::generate().unwrap();
+        let key2 = Key::parse(&STANDARD.encode(raw)).unwrap();
+        let uuid = Uuid::new_v4();
+        let mut config = CameraConfig {
+            username: "admin".to_owned(),
+            password: "hunter2".to_owned(),
+            ..Default::default()
+        };
+        encrypt(&key, uuid, &mut config).unwrap();
+        assert!(config.username.is_empty());
+        assert!(config.password.is_empty());
+        let encrypted = config.clone();
+        decrypt(&key2, uuid, &mut config).unwrap();
+        assert_eq!(config.username, "admin");
+        assert_eq!(config.password, "hunter2");
+        assert_eq!(config.encrypted_credentials, None);
+
+        // The wrong camera or key can't decrypt.
+        let mut c = encrypted.clone();
+        assert!(decrypt(&key, Uuid::new_v4(), &mut c).is_err());
+        let (other, _) = Key::generate().unwrap();
+        let mut c = encrypted;
+        assert!(decrypt(&other, uuid, &mut c).is_err());
+    }
+
+    #[test]
+    fn parse_bad_key() {
+        assert!(Key::parse("not base64!").is_err());
+        assert!(Key::parse(&STANDARD.encode([0u8; 16])).is_err());
+    }
+}
@@ -13,6 +13,7 @@ use std::convert::TryFrom;
 use std::io::Write;
 use std::ops::Range;
 use std::str;
+use std::sync::Mutex;
 use tracing::{error, trace};
 
 /// A calendar day in `YYYY-mm-dd` format.
@@ -57,6 +58,58 @@ impl std::fmt::Debug for Key {
     }
 }
 
+/// The number of days kept in [`BOUNDS_CACHE`].
+const BOUNDS_CACHE_LEN: usize = 8;
+
+/// Recently used days and their bounds, most recent last.
+///
+/// Finding a day from a time takes comparatively expensive time zone conversions. Flushes adjust
+/// the same few days (those of the newest and oldest recordings) over and over, so this keeps
+/// those conversions out of the common case. See [`clear_bounds_cache`].
+static BOUNDS_CACHE: Mutex<Vec<(Key, Range<Time>)>> = Mutex::new(Vec::new());
+
+/// Forgets cached day bounds. This must be called after the local time zone changes.
+pub(crate) fn clear_bounds_cache() {
+    BOUNDS_CACHE.lock().unwrap().clear();
+}
+
+/// Returns the day containing `t` and that day's bounds.
+fn day_containing(t: Time) -> Result<(Key, Range<Time>), Error> {
+    let mut cache = BOUNDS_CACHE.lock().unwrap();
+    if let Some(i) = cache.iter().rposition(|(_, b)| b.contains(&t)) {
+        let (day, bounds) = &cache[i];
+        return Ok((*day, bounds.clone()));
+    }
+    let mut my_tm = time::at(time::Timespec {
+        sec: t.unix_seconds(),
+        nsec: 0,
+    });
+    let day = Key::new(my_tm)?;
+
+    // Use my_tm to hold non-normalized representations of the boundaries.
+    my_tm.tm_isdst = -1;
+    my_tm.tm_hour = 0;
+    my_tm.tm_min = 0;
+    my_tm.tm_sec = 0;
+    let start = Time(my_tm.to_timespec().sec * TIME_UNITS_PER_SEC);
+    my_tm.tm_mday += 1;
+    let end = Time(my_tm.to_timespec().sec * TIME_UNITS_PER_SEC);
+
+    // Only cache bounds which match the day exactly. In some zones, a time zone transition at
+    // midnight means the nominal start isn't within the day.
+    let start_day = Key::new(time::at(time::Timespec {
+        sec: start.unix_seconds(),
+        nsec: 0,
+    }))?;
+    if start <= t && start_day == day {
+        if cache.len() == BOUNDS_CACHE_LEN {
+            cache.remove(0);
+        }
+        cache.push((day, start..end));
+    }
+    Ok((day, start..end))
+}
+
 pub trait Value: std::fmt::Debug + Default {
     type Change: std::fmt::Debug;
 
@@ -98,6 +151,17 @@ pub struct SignalValue {
     pub states: SmallVec<[u64; 4]>,
 }
 
+impl SignalValue {
+    /// Adds `duration` (in 90 kHz units) of non-zero `state`.
+    pub(crate) fn add(&mut self, state: u16, duration: u64) {
+        let i = usize::from(state) - 1;
+        if self.states.len() <= i {
+            self.states.resize(i + 1, 0);
+        }
+        self.states[i] += duration;
+    }
+}
+
 impl Value for SignalValue {
     type Change = SignalChange;
 
@@ -197,6 +261,13 @@ impl<'a, V: Value> IntoIterator for &'a Map<V> {
 }
 
 impl Map<StreamValue> {
+    /// Adds all of `other`'s days to `self`.
+    pub(crate) fn add_all(&mut self, other: Map<StreamValue>) {
+        for (day, v) in other.0 {
+            self.adjust_day(day, v);
+        }
+    }
+
     /// Adjusts `self` to reflect the range of the given recording.
     /// Note that the specified range may span two days. It will never span more because the maximum
     /// length of a recording entry is less than a day (even a 23-hour "spring forward" day).
@@ -204,58 +275,42 @@ impl Map<StreamValue> {
     /// This function swallows/logs date formatting errors because they shouldn't happen and there's
     /// not much that can be done about them. (The database operation has already gone through.)
     pub(crate) fn adjust(&mut self, r: Range<Time>, sign: i64) {
-        // Find first day key.
-        let sec = r.start.unix_seconds();
-        let mut my_tm = time::at(time::Timespec { sec, nsec: 0 });
-        let day = match Key::new(my_tm) {
+        let (day, bounds) = match day_containing(r.start) {
             Ok(d) => d,
             Err(ref e) => {
                 error!(
-                    "Unable to fill first day key from {:?}->{:?}: {}; will ignore.",
-                    r, my_tm, e
+                    "Unable to fill first day key from {:?}: {}; will ignore.",
+                    r, e
                 );
                 return;
             }
         };
 
-        // Determine the start of the next day.
-        // Use mytm to hold a non-normalized representation of the boundary.
-        my_tm.tm_isdst = -1;
-        my_tm.tm_hour = 0;
-        my_tm.tm_min = 0;
-        my_tm.tm_sec = 0;
-        my_tm.tm_mday += 1;
-        let boundary = my_tm.to_timespec();
-        let boundary_90k = boundary.sec * TIME_UNITS_PER_SEC;
-
         // Adjust the first day.
         let first_day_delta = StreamValue {
             recordings: sign,
-            duration: Duration(sign * (cmp::min(r.end.0, boundary_90k) - r.start.0)),
+            duration: Duration(sign * (cmp::min(r.end, bounds.end) - r.start).0),
         };
         self.adjust_day(day, first_day_delta);
 
-        if r.end.0 <= boundary_90k {
+        if r.end <= bounds.end {
             return;
         }
 
-        // Fill day with the second day. This requires a normalized representation so recalculate.
-        // (The C mktime(3) already normalized for us once, but .to_timespec() discarded that
-        // result.)
-        let my_tm = time::at(boundary);
-        let day = match Key::new(my_tm) {
-            Ok(d) => d,
+        // Fill day with the second day.
+        let day = match day_containing(bounds.end) {
+            Ok((d, _)) => d,
             Err(ref e) => {
                 error!(
                     "Unable to fill second day key from {:?}: {}; will ignore.",
-                    my_tm, e
+                    bounds.end, e
                 );
                 return;
             }
         };
         let second_day_delta = StreamValue {
             recordings: sign,
-            duration: Duration(sign * (r.end.0 - boundary_90k)),
+            duration: Duration(sign * (r.end - bounds.end).0),
         };
         self.adjust_day(day, second_day_delta);
     }
@@ -268,33 +323,17 @@ impl Map<SignalValue> {
     /// This function swallows/logs date formatting errors because they shouldn't happen and there's
     /// not much that can be done about them. (The database operation has already gone through.)
     pub(crate) fn adjust(&mut self, mut r: Range<Time>, old_state: u16, new_state: u16) {
-        // Find first day key.
-        let sec = r.start.unix_seconds();
-        let mut my_tm = time::at(time::Timespec { sec, nsec: 0 });
-        let mut day = match Key::new(my_tm) {
-            Ok(d) => d,
-            Err(ref e) => {
-                error!(
-                    "Unable to fill first day key from {:?}->{:?}: {}; will ignore.",
-                    r, my_tm, e
-                );
-                return;
-            }
-        };
-
-        // Determine the start of the next day.
-        // Use mytm to hold a non-normalized representation of the boundary.
-        my_tm.tm_isdst = -1;
-        my_tm.tm_hour = 0;
-        my_tm.tm_min = 0;
-        my_tm.tm_sec = 0;
-
         loop {
-            my_tm.tm_mday += 1;
-            let boundary_90k = my_tm.to_timespec().sec * TIME_UNITS_PER_SEC;
+            let (day, bounds) = match day_containing(r.start) {
+                Ok(d) => d,
+                Err(ref e) => {
+                    error!("Unable to fill day key from {:?}: {}; will ignore.", r, e);
+                    return;
+                }
+            };
 
             // Adjust this day.
-            let duration = Duration(cmp::min(r.end.0, boundary_90k) - r.start.0);
+            let duration = cmp::min(r.end, bounds.end) - r.start;
             self.adjust_day(
                 day,
                 SignalChange {
@@ -304,39 +343,43 @@ impl Map<SignalValue> {
                 },
             );
 
-            if r.end.0 <= boundary_90k {
+            if r.end <= bounds.end {
                 return;
             }
-
-            // Fill day with the next day. This requires a normalized representation so
-            // recalculate. (The C mktime(3) already normalized for us once, but .to_timespec()
-            // discarded that result.)
-            let my_tm = time::at(time::Timespec {
-                sec: Time(boundary_90k).unix_seconds(),
-                nsec: 0,
-            });
-            day = match Key::new(my_tm) {
-                Ok(d) => d,
-                Err(ref e) => {
-                    error!(
-                        "Unable to fill day key from {:?}: {}; will ignore.",
-                        my_tm, e
-                    );
-                    return;
-                }
-            };
-            r.start.0 = boundary_90k;
+            r.start = bounds.end;
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Key, Map, SignalValue, StreamValue};
+    use super::{day_containing, Key, Map, SignalValue, StreamValue};
     use crate::testutil;
     use base::time::{Duration, Time, TIME_UNITS_PER_SEC};
     use smallvec::smallvec;
 
+    #[test]
+    fn test_day_containing() {
+        testutil::init();
+
+        // 2016-03-13 is a 23-hour "spring forward" day (Pacific).
+        let start = Time(1457856000 * TIME_UNITS_PER_SEC);
+        let end = Time(1457938800 * TIME_UNITS_PER_SEC);
+        let noon = Time(1457895600 * TIME_UNITS_PER_SEC);
+        let expected = (Key(*b"2016-03-13"), start..end);
+
+        // The second lookup of each is likely cached; the results should be the same.
+        for _ in 0..2 {
+            assert_eq!(day_containing(noon).unwrap(), expected);
+            assert_eq!(day_containing(start).unwrap(), expected);
+            assert_eq!(day_containing(end - Duration(1)).unwrap(), expected);
+            let (next, next_bounds) = day_containing(end).unwrap();
+            assert_eq!(next, Key(*b"2016-03-14"));
+            assert_eq!(next_bounds.start, end);
+            assert_eq!(next_bounds.end - end, Duration(86400 * TIME_UNITS_PER_SEC));
+        }
+    }
+
     #[test]
     fn test_adjust_stream() {
         testutil::init();
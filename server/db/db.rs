@@ -26,10 +26,13 @@
 //!     A list of mutations is built up in-memory and occasionally flushed to reduce SSD write
 //!     cycles.
 
+use crate::aggregates;
 use crate::auth;
+use crate::credential;
 use crate::days;
 use crate::dir;
-use crate::json::SampleFileDirConfig;
+use crate::event;
+use crate::json::{CameraConfig, SampleFileDirConfig};
 use crate::raw;
 use crate::recording;
 use crate::schema;
@@ -40,7 +43,7 @@ use base::{bail, err, Error};
 use base::{FastHashMap, FastHashSet};
 use hashlink::LinkedHashMap;
 use itertools::Itertools;
-use rusqlite::{named_params, params};
+use rusqlite::{named_params, params, OptionalExtension};
 use smallvec::SmallVec;
 use std::cell::RefCell;
 use std::cmp;
@@ -51,6 +54,7 @@ use std::ops::Range;
 use std::path::PathBuf;
 use std::str;
 use std::string::String;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::{Mutex, MutexGuard};
 use std::vec::Vec;
@@ -59,7 +63,7 @@ use tracing::{error, info, trace};
 use uuid::Uuid;
 
 /// Expected schema version. See `guide/schema.md` for more information.
-pub const EXPECTED_SCHEMA_VERSION: i32 = 7;
+pub const EXPECTED_SCHEMA_VERSION: i32 = 8;
 
 /// Length of the video index cache.
 /// The actual data structure is one bigger than this because we insert before we remove.
@@ -73,6 +77,19 @@ const VIDEO_INDEX_CACHE_LEN: usize = 1023;
 /// not a good experience for subscribers to fall too far behind.
 const LIVE_SEGMENTS_BUF_LEN: usize = 128;
 
+/// The default duration of live frames to keep in [`RecentFrames`], in seconds.
+/// Overridden by `StreamConfig::recent_frames_sec`.
+const DEFAULT_RECENT_FRAMES_SEC: u32 = 10;
+
+/// The maximum number of live frames to keep in [`RecentFrames`], regardless of configuration.
+const RECENT_FRAMES_MAX_LEN: usize = 1 << 16;
+
+/// How far an uncommitted recording's start may move earlier as it grows, for invalidating
+/// [`Database::list_aggregated_recordings`] results. Wall time is adjusted by at most 500 ppm,
+/// so the maximum recording duration is more than enough.
+const UNCOMMITTED_START_SLACK: recording::Duration =
+    recording::Duration(recording::MAX_RECORDING_WALL_DURATION);
+
 const GET_RECORDING_PLAYBACK_SQL: &str = r#"
     select
       video_index
@@ -208,6 +225,10 @@ pub struct ListRecordingsRow {
     /// `list_recordings_by_time` would be inefficient.)
     pub prev_media_duration_and_runs: Option<(recording::Duration, i32)>,
     pub end_reason: Option<String>,
+
+    /// Packet loss statistics; see `schema.sql`.
+    pub packets_lost: i32,
+    pub damaged_frames: i32,
 }
 
 /// A row used in `list_aggregated_recordings`.
@@ -226,6 +247,21 @@ pub struct ListAggregatedRecordingsRow {
     pub growing: bool,
     pub has_trailing_zero: bool,
     pub end_reason: Option<String>,
+    pub packets_lost: i64,
+    pub damaged_frames: i64,
+
+    /// Points within `ids` where the video sample entry changes, e.g. because the camera switched
+    /// resolution. `video_sample_entry_id` is that of the first recording.
+    pub video_sample_entry_changes: Vec<VideoSampleEntryChange>,
+}
+
+/// A change of video sample entry within a [`ListAggregatedRecordingsRow`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VideoSampleEntryChange {
+    /// The first recording with the new entry.
+    pub recording_id: i32,
+    pub start: recording::Time,
+    pub video_sample_entry_id: i32,
 }
 
 impl ListAggregatedRecordingsRow {
@@ -251,8 +287,18 @@ impl ListAggregatedRecordingsRow {
             growing,
             has_trailing_zero: (row.flags & RecordingFlags::TrailingZero as i32) != 0,
             end_reason: row.end_reason,
+            packets_lost: i64::from(row.packets_lost),
+            damaged_frames: i64::from(row.damaged_frames),
+            video_sample_entry_changes: Vec::new(),
         }
     }
+
+    /// Returns the video sample entry id of the last recording.
+    pub fn last_video_sample_entry_id(&self) -> i32 {
+        self.video_sample_entry_changes
+            .last()
+            .map_or(self.video_sample_entry_id, |c| c.video_sample_entry_id)
+    }
 }
 
 /// Select fields from the `recordings_playback` table. Retrieve with `with_recording_playback`.
@@ -266,12 +312,23 @@ pub struct RecordingPlayback<'a> {
 pub enum RecordingFlags {
     TrailingZero = 1,
 
+    /// The sample file is encrypted; see [`crate::dir::crypt`].
+    Encrypted = 2,
+
+    /// The video index includes a composition offset for each sample, as needed for streams with
+    /// B-frames. See [`crate::recording::SampleIndexIterator::new`].
+    CompositionOffsets = 4,
+
+    /// At least one sample has a non-zero composition offset, so frames are decoded in a different
+    /// order than they're presented. Set only with `CompositionOffsets`.
+    Reordered = 8,
+
     // These values (starting from high bit on down) are never written to the database.
     Growing = 1 << 30,
     Uncommitted = 1 << 31,
 }
 
-/// A recording to pass to `LockedDatabase::add_recording` and `raw::insert_recording`.
+/// A recording to pass to `LockedDatabase::add_recording` and `raw::insert_recordings`.
 #[derive(Clone, Debug, Default)]
 pub struct RecordingToInsert {
     pub run_offset: i32,
@@ -294,6 +351,19 @@ pub struct RecordingToInsert {
     pub video_index: Vec<u8>,
     pub sample_file_blake3: Option<[u8; 32]>,
     pub end_reason: Option<String>,
+    pub packets_lost: i32,
+    pub damaged_frames: i32,
+
+    /// Details of the RTSP session which produced the run, for its first recording only.
+    pub rtsp_session: Option<Arc<RtspSession>>,
+}
+
+/// Details of the RTSP session which produced a run; see the `recording_rtsp_session` table.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RtspSession {
+    pub sdp: Vec<u8>,
+    pub transport: Option<String>,
+    pub server: Option<String>,
 }
 
 impl RecordingToInsert {
@@ -312,6 +382,8 @@ impl RecordingToInsert {
             flags: self.flags | RecordingFlags::Uncommitted as i32,
             prev_media_duration_and_runs: Some((self.prev_media_duration, self.prev_runs)),
             end_reason: self.end_reason.clone(),
+            packets_lost: self.packets_lost,
+            damaged_frames: self.damaged_frames,
         }
     }
 }
@@ -333,6 +405,15 @@ pub struct SampleFileDir {
     dir: Option<Arc<dir::SampleFileDir>>,
     last_complete_open: Option<Open>,
 
+    /// The reader configuration to use on next open; see
+    /// `LockedDatabase::set_sample_file_dir_reader_config`.
+    reader_config: dir::ReaderConfig,
+
+    /// The free space to keep on the directory's filesystem, deleting recordings beyond what
+    /// their streams' `retain_bytes` limits require if necessary. 0 means no floor. See
+    /// `LockedDatabase::set_sample_file_dir_min_free_bytes`.
+    pub(crate) min_free_bytes: i64,
+
     /// ids which are in the `garbage` database table (rather than `recording`) as of last commit
     /// but may still exist on disk. These can't be safely removed from the database yet.
     pub(crate) garbage_needs_unlink: FastHashSet<CompositeId>,
@@ -374,11 +455,13 @@ impl SampleFileDir {
     }
 }
 
+pub use crate::auth::PermissionTemplate;
 pub use crate::auth::RawSessionId;
 pub use crate::auth::Request;
 pub use crate::auth::Session;
 pub use crate::auth::User;
 pub use crate::auth::UserChange;
+pub use crate::auth::WebAuthnCredential;
 
 /// In-memory state about a camera.
 #[derive(Debug)]
@@ -486,6 +569,11 @@ pub struct Stream {
     /// that day.
     pub committed_days: days::Map<days::StreamValue>,
 
+    /// True once the committed recordings have been loaded into `duration`, `sample_file_bytes`,
+    /// `fs_bytes`, and `committed_days`. Until then, these reflect only changes since the
+    /// database was opened. `range` is always accurate. See [`Database::new_lazy`].
+    pub loaded: bool,
+
     /// The `cum_recordings` currently committed to the database.
     pub(crate) cum_recordings: i32,
 
@@ -502,12 +590,58 @@ pub struct Stream {
     /// `cum_recordings` should be advanced when one is committed to maintain this invariant.
     ///
     /// TODO: alter the serving path to show these just as if they were already committed.
-    uncommitted: VecDeque<Arc<Mutex<RecordingToInsert>>>,
+    pub(crate) uncommitted: VecDeque<Arc<Mutex<RecordingToInsert>>>,
 
     /// The number of recordings in `uncommitted` which are synced and ready to commit.
-    synced_recordings: usize,
+    pub(crate) synced_recordings: usize,
 
     live_segments: tokio::sync::broadcast::Sender<LiveFrame>,
+    recent_frames: RecentFrames,
+
+    /// The streamer's most recent failure, cleared once it's receiving frames again. This is
+    /// in-memory only.
+    pub last_error: Option<StreamError>,
+}
+
+/// A failure of a stream's connection to its camera; see [`Stream::last_error`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamError {
+    pub time: recording::Time,
+    pub kind: StreamErrorKind,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StreamErrorKind {
+    /// The camera rejected the configured credentials or authentication scheme.
+    Auth,
+
+    /// The camera couldn't be reached or stopped responding.
+    Network,
+
+    Other,
+}
+
+impl StreamErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StreamErrorKind::Auth => "auth",
+            StreamErrorKind::Network => "network",
+            StreamErrorKind::Other => "other",
+        }
+    }
+
+    pub fn from_error_kind(kind: base::ErrorKind) -> Self {
+        match kind {
+            base::ErrorKind::Unauthenticated | base::ErrorKind::PermissionDenied => {
+                StreamErrorKind::Auth
+            }
+            base::ErrorKind::Unavailable | base::ErrorKind::DeadlineExceeded => {
+                StreamErrorKind::Network
+            }
+            _ => StreamErrorKind::Other,
+        }
+    }
 }
 
 /// Bounds of a live view frame.
@@ -517,8 +651,15 @@ pub struct Stream {
 /// looked up within the database.
 #[derive(Clone, Debug)]
 pub struct LiveFrame {
+    /// A sequence number, unique within the stream and open id. Assigned by
+    /// `LockedDatabase::send_live_segment`; the caller should supply 0.
+    pub seq: u64,
+
     pub recording: i32,
 
+    /// The size of this frame's sample data.
+    pub bytes: i32,
+
     /// If the segment's one frame is a key frame.
     pub is_key: bool,
 
@@ -527,6 +668,96 @@ pub struct LiveFrame {
     pub media_off_90k: Range<i32>,
 }
 
+/// The most recent live frames of a stream, so that a live viewer which briefly loses its
+/// connection can resume where it left off.
+#[derive(Debug, Default)]
+struct RecentFrames {
+    frames: VecDeque<LiveFrame>,
+
+    /// The total media duration of `frames`, in 90 kHz units.
+    duration_90k: i64,
+
+    /// The total size of `frames`' sample data.
+    bytes: u64,
+    next_seq: u64,
+
+    /// Accounts for `frames` in [`base::mem::BUDGET`].
+    charge: Option<base::mem::Charge<'static>>,
+}
+
+impl RecentFrames {
+    /// Assigns `f` a sequence number and appends it, dropping the oldest frames as necessary
+    /// to stay within the limits of `config`. The newest frame is always kept.
+    fn push(&mut self, config: &crate::json::StreamConfig, mut f: LiveFrame) -> LiveFrame {
+        let max_duration_90k = i64::from(
+            config
+                .recent_frames_sec
+                .unwrap_or(DEFAULT_RECENT_FRAMES_SEC),
+        ) * recording::TIME_UNITS_PER_SEC;
+        let max_bytes = config.recent_frames_bytes.unwrap_or(u64::MAX);
+        f.seq = self.next_seq;
+        self.next_seq += 1;
+        self.duration_90k += i64::from(f.media_off_90k.end - f.media_off_90k.start);
+        self.bytes += u64::try_from(f.bytes).unwrap_or(0);
+        self.frames.push_back(f.clone());
+        // When over the memory budget, keep only the newest frame. Viewers then can't resume,
+        // but live viewing continues.
+        let over_budget = base::mem::BUDGET.is_exceeded();
+        while self.frames.len() > RECENT_FRAMES_MAX_LEN
+            || (self.frames.len() > 1
+                && (over_budget || self.duration_90k > max_duration_90k || self.bytes > max_bytes))
+        {
+            let old = self.frames.pop_front().expect("frames is non-empty");
+            self.duration_90k -= i64::from(old.media_off_90k.end - old.media_off_90k.start);
+            self.bytes -= u64::try_from(old.bytes).unwrap_or(0);
+        }
+        let charged = (self.frames.capacity() * mem::size_of::<LiveFrame>()) as u64;
+        self.charge
+            .get_or_insert_with(|| base::mem::BUDGET.charge(base::mem::Category::RecentFrames, 0))
+            .set(charged);
+        f
+    }
+
+    /// Returns all frames after `seq`, or `None` if any have been dropped or `seq` was never
+    /// assigned.
+    fn after(&self, seq: u64) -> Option<Vec<LiveFrame>> {
+        if seq >= self.next_seq {
+            return None;
+        }
+        let first = self.frames.front().map(|f| f.seq).unwrap_or(self.next_seq);
+        if seq + 1 < first {
+            return None;
+        }
+        Some(
+            self.frames
+                .iter()
+                .filter(|f| f.seq > seq)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Returns the most recent frames spanning at least `duration_90k` of media, or all of them
+    /// if fewer are buffered.
+    fn last(&self, duration_90k: i64) -> Vec<LiveFrame> {
+        let mut total = 0;
+        let n = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|f| {
+                let more = total < duration_90k;
+                total += i64::from(f.media_off_90k.end - f.media_off_90k.start);
+                more
+            })
+            .count();
+        self.frames
+            .range(self.frames.len() - n..)
+            .cloned()
+            .collect()
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct StreamChange {
     pub sample_file_dir_id: Option<i32>,
@@ -558,6 +789,15 @@ impl Stream {
         self.committed_days.adjust(r, 1);
     }
 
+    /// Adds totals of committed recordings, as read by [`read_recording_totals`]. This doesn't
+    /// touch `range`.
+    fn add_totals(&mut self, t: RecordingTotals) {
+        self.duration += t.duration;
+        self.sample_file_bytes += t.sample_file_bytes;
+        self.fs_bytes += t.fs_bytes;
+        self.committed_days.add_all(t.days);
+    }
+
     /// Returns a days map including unflushed recordings.
     pub fn days(&self) -> days::Map<days::StreamValue> {
         let mut days = self.committed_days.clone();
@@ -572,9 +812,20 @@ impl Stream {
     }
 }
 
+/// Totals of a stream's committed recordings, as in the like-named [`Stream`] fields.
+#[derive(Default)]
+struct RecordingTotals {
+    recordings: usize,
+    range: Option<Range<recording::Time>>,
+    duration: recording::Duration,
+    sample_file_bytes: i64,
+    fs_bytes: i64,
+    days: days::Map<days::StreamValue>,
+}
+
 /// Initializes the recordings associated with the given camera.
 fn init_recordings(
-    conn: &mut rusqlite::Connection,
+    conn: &rusqlite::Connection,
     stream_id: i32,
     camera: &Camera,
     stream: &mut Stream,
@@ -583,6 +834,22 @@ fn init_recordings(
         "Loading recordings for camera {} stream {:?}",
         camera.short_name, stream.type_
     );
+    let t = read_recording_totals(conn, stream_id)?;
+    info!(
+        "Loaded {} recordings for camera {} stream {:?}",
+        t.recordings, camera.short_name, stream.type_
+    );
+    stream.range = t.range.clone();
+    stream.add_totals(t);
+    stream.loaded = true;
+    Ok(())
+}
+
+/// Reads the totals of the given stream's committed recordings.
+fn read_recording_totals(
+    conn: &rusqlite::Connection,
+    stream_id: i32,
+) -> Result<RecordingTotals, Error> {
     let mut stmt = conn.prepare(
         r#"
         select
@@ -596,19 +863,23 @@ fn init_recordings(
         "#,
     )?;
     let mut rows = stmt.query(named_params! {":stream_id": stream_id})?;
-    let mut i = 0;
+    let mut t = RecordingTotals::default();
     while let Some(row) = rows.next()? {
         let start = recording::Time(row.get(0)?);
         let duration = recording::Duration(row.get(1)?);
-        let bytes = row.get(2)?;
-        stream.add_recording(start..start + duration, bytes);
-        i += 1;
+        let bytes: i32 = row.get(2)?;
+        let end = start + duration;
+        t.recordings += 1;
+        t.range = Some(match t.range {
+            Some(r) => cmp::min(r.start, start)..cmp::max(r.end, end),
+            None => start..end,
+        });
+        t.duration += duration;
+        t.sample_file_bytes += i64::from(bytes);
+        t.fs_bytes += round_up(i64::from(bytes));
+        t.days.adjust(start..end, 1);
     }
-    info!(
-        "Loaded {} recordings for camera {} stream {:?}",
-        i, camera.short_name, stream.type_
-    );
-    Ok(())
+    Ok(t)
 }
 
 pub struct LockedDatabase {
@@ -625,14 +896,57 @@ pub struct LockedDatabase {
 
     auth: auth::State,
     signal: signal::State,
+    event: event::State,
 
     sample_file_dirs_by_id: BTreeMap<i32, SampleFileDir>,
     cameras_by_id: BTreeMap<i32, Camera>,
     streams_by_id: BTreeMap<i32, Stream>,
     cameras_by_uuid: BTreeMap<Uuid, i32>, // values are ids.
     video_sample_entries_by_id: BTreeMap<i32, Arc<VideoSampleEntry>>,
+
+    /// Ids removed by [`LockedDatabase::gc_video_sample_entries`] in favor of identical entries,
+    /// so that recording rows read before the merge and clients holding `/api/init/<id>.mp4`
+    /// URLs still resolve.
+    video_sample_entry_aliases: FastHashMap<i32, i32>,
+
+    /// Ids returned by [`LockedDatabase::insert_video_sample_entry`] since the database was
+    /// opened. These may be in use by streamers which haven't written a recording yet, so they're
+    /// never garbage-collected.
+    issued_video_sample_entries: FastHashSet<i32>,
     video_index_cache: RefCell<LinkedHashMap<i64, Box<[u8]>, base::RandomState>>,
     on_flush: Vec<Box<dyn Fn() + Send>>,
+
+    /// True iff the database has a `credential_key` row, so camera credentials should be stored
+    /// encrypted.
+    credentials_encrypted: bool,
+
+    /// The data key for camera credentials, as supplied via `set_credential_key`.
+    ///
+    /// When set, cameras' credentials are decrypted in memory and encrypted on write.
+    credential_key: Option<credential::Key>,
+
+    /// True iff the database has a `sample_file_key` row, so some sample files may be encrypted.
+    sample_files_encrypted: bool,
+
+    /// The master key for sample files, as supplied via `set_sample_file_key`.
+    ///
+    /// When set, new recordings are encrypted.
+    sample_file_key: Option<Arc<dir::crypt::Key>>,
+
+    /// The latest [`ReadIndex`], shared with [`Database`]. See [`LockedDatabase::publish`].
+    read_index: Arc<Mutex<Arc<ReadIndex>>>,
+
+    /// Cached [`Database::list_aggregated_recordings`] results, shared with [`Database`].
+    /// Changes to recordings must invalidate overlapping entries after publishing.
+    aggregates: Arc<aggregates::Cache>,
+
+    /// Incremented after any change to which directory a stream uses or which directories are
+    /// open, shared with [`Database`]. See [`Database::stream_dirs_generation`].
+    stream_dirs_generation: Arc<AtomicU64>,
+
+    /// The IANA name of the local time zone (e.g. `America/Los_Angeles`), as supplied via
+    /// `set_time_zone_name`. Days maps are in this zone.
+    time_zone_name: String,
 }
 
 /// Represents a row of the `open` database table.
@@ -826,12 +1140,15 @@ impl StreamStateChanger {
                         fs_bytes_to_add: 0,
                         duration: recording::Duration(0),
                         committed_days: days::Map::default(),
+                        loaded: true,
                         cum_recordings: 0,
                         cum_media_duration: recording::Duration(0),
                         cum_runs: 0,
                         uncommitted: VecDeque::new(),
                         synced_recordings: 0,
                         live_segments: tokio::sync::broadcast::channel(LIVE_SEGMENTS_BUF_LEN).0,
+                        recent_frames: RecentFrames::default(),
+                        last_error: None,
                     });
                 }
                 (Entry::Vacant(_), None) => {}
@@ -865,6 +1182,53 @@ impl LockedDatabase {
         &self.sample_file_dirs_by_id
     }
 
+    /// Returns true iff the database has `auto_vacuum = incremental`, as required by
+    /// [`LockedDatabase::incremental_vacuum`]. Existing databases can only be switched via a full
+    /// `vacuum`.
+    pub fn incremental_vacuum_enabled(&self) -> Result<bool, Error> {
+        let mode: i32 = self
+            .conn
+            .query_row("pragma auto_vacuum", params![], |row| row.get(0))?;
+        Ok(mode == 2)
+    }
+
+    /// Returns the number of unused pages in the database file.
+    pub fn freelist_count(&self) -> Result<u32, Error> {
+        Ok(self
+            .conn
+            .query_row("pragma freelist_count", params![], |row| row.get(0))?)
+    }
+
+    /// Returns up to `pages` unused pages to the filesystem, returning the number freed.
+    ///
+    /// This is a no-op unless [`LockedDatabase::incremental_vacuum_enabled`].
+    pub fn incremental_vacuum(&mut self, pages: u32) -> Result<u32, Error> {
+        let before = self.freelist_count()?;
+        self.conn
+            .execute_batch(&format!("pragma incremental_vacuum({pages});"))?;
+        Ok(before.saturating_sub(self.freelist_count()?))
+    }
+
+    /// Gathers statistics for the query planner.
+    pub fn analyze(&mut self) -> Result<(), Error> {
+        self.conn.execute_batch("analyze;")?;
+        Ok(())
+    }
+
+    /// Copies the write-ahead log into the database file and truncates it, returning the number
+    /// of log frames copied. Frames still needed by concurrent readers are left in place.
+    pub fn checkpoint(&mut self) -> Result<i64, Error> {
+        let (busy, checkpointed): (i32, i64) =
+            self.conn
+                .query_row("pragma wal_checkpoint(truncate)", params![], |row| {
+                    Ok((row.get(0)?, row.get(2)?))
+                })?;
+        if busy != 0 {
+            warn!("WAL checkpoint was blocked by readers; log not truncated");
+        }
+        Ok(checkpointed)
+    }
+
     /// Returns the number of completed database flushes since startup.
     pub fn flushes(&self) -> usize {
         self.flush_count
@@ -906,8 +1270,16 @@ impl LockedDatabase {
                 r.prev_runs = stream.cum_runs;
             }
         };
+        let start = r.start;
         let recording = Arc::new(Mutex::new(r));
         stream.uncommitted.push_back(Arc::clone(&recording));
+        self.publish();
+
+        // Cached results near or after the new recording's start may become stale as it grows.
+        self.aggregates.invalidate(
+            stream_id,
+            &[start - UNCOMMITTED_START_SLACK..recording::Time::MAX],
+        );
         Ok((id, recording))
     }
 
@@ -982,11 +1354,43 @@ impl LockedDatabase {
         Ok(s.live_segments.subscribe())
     }
 
+    /// Returns the buffered live frames of the given stream after `seq`, or `None` if they're
+    /// no longer all available.
+    ///
+    /// A caller which also calls `watch_live` without releasing the lock in between will
+    /// receive each subsequent frame exactly once.
+    pub fn recent_live_frames_after(
+        &self,
+        stream_id: i32,
+        seq: u64,
+    ) -> Result<Option<Vec<LiveFrame>>, Error> {
+        let s = match self.streams_by_id.get(&stream_id) {
+            None => bail!(NotFound, msg("no such stream {stream_id}")),
+            Some(s) => s,
+        };
+        Ok(s.recent_frames.after(seq))
+    }
+
+    /// Returns the buffered live frames of the given stream spanning the last `duration_90k`
+    /// of media, or as much as is buffered.
+    pub fn recent_live_frames(
+        &self,
+        stream_id: i32,
+        duration_90k: i64,
+    ) -> Result<Vec<LiveFrame>, Error> {
+        let s = match self.streams_by_id.get(&stream_id) {
+            None => bail!(NotFound, msg("no such stream {stream_id}")),
+            Some(s) => s,
+        };
+        Ok(s.recent_frames.last(duration_90k))
+    }
+
     pub(crate) fn send_live_segment(&mut self, stream: i32, l: LiveFrame) -> Result<(), Error> {
         let s = match self.streams_by_id.get_mut(&stream) {
             None => bail!(Internal, msg("no such stream {stream}")),
             Some(s) => s,
         };
+        let l = s.recent_frames.push(&s.config, l);
 
         // Note that `send` will fail if there are no active receivers.
         // That's fine, so ignore this error.
@@ -994,6 +1398,20 @@ impl LockedDatabase {
         Ok(())
     }
 
+    /// Sets or clears the given stream's [`Stream::last_error`].
+    pub fn set_stream_error(
+        &mut self,
+        stream_id: i32,
+        error: Option<StreamError>,
+    ) -> Result<(), Error> {
+        let s = match self.streams_by_id.get_mut(&stream_id) {
+            None => bail!(NotFound, msg("no such stream {stream_id}")),
+            Some(s) => s,
+        };
+        s.last_error = error;
+        Ok(())
+    }
+
     /// Helper for `DatabaseGuard::flush()` and `Database::drop()`.
     ///
     /// The public API is in `DatabaseGuard::flush()`; it supplies the `Clocks` to this function.
@@ -1004,23 +1422,32 @@ impl LockedDatabase {
             None => bail!(Internal, msg("database is read-only")),
             Some(o) => o,
         };
+        // Everything is committed in a single transaction, as each is expensive on slow storage
+        // such as SD cards.
         let tx = self.conn.transaction()?;
         let mut new_ranges =
             FastHashMap::with_capacity_and_hasher(self.streams_by_id.len(), Default::default());
         {
+            // Gather all streams' additions, so they can be inserted in batches.
+            let mut added = Vec::new();
+            for (&stream_id, s) in &self.streams_by_id {
+                for (i, u) in s.uncommitted.iter().take(s.synced_recordings).enumerate() {
+                    added.push((
+                        CompositeId::new(stream_id, s.cum_recordings + i as i32),
+                        u.lock().unwrap(),
+                    ));
+                }
+            }
+            added.sort_unstable_by_key(|(id, _)| id.0);
+            let rows: Vec<_> = added.iter().map(|(id, l)| (*id, &**l)).collect();
+            raw::insert_recordings(&tx, o, &rows)?;
+
             let mut stmt = tx.prepare_cached(UPDATE_STREAM_COUNTERS_SQL)?;
             for (&stream_id, s) in &self.streams_by_id {
                 // Process additions.
                 let mut new_duration = 0;
                 let mut new_runs = 0;
-                for i in 0..s.synced_recordings {
-                    let l = s.uncommitted[i].lock().unwrap();
-                    raw::insert_recording(
-                        &tx,
-                        o,
-                        CompositeId::new(stream_id, s.cum_recordings + i as i32),
-                        &l,
-                    )?;
+                for (_, l) in added.iter().filter(|(id, _)| id.stream() == stream_id) {
                     new_duration += i64::from(l.wall_duration_90k);
                     new_runs += if l.run_offset == 0 { 1 } else { 0 };
                 }
@@ -1065,8 +1492,8 @@ impl LockedDatabase {
                 }
             }
         }
-        for dir in self.sample_file_dirs_by_id.values() {
-            raw::mark_sample_files_deleted(&tx, &dir.garbage_unlinked)?;
+        for (&dir_id, dir) in &self.sample_file_dirs_by_id {
+            raw::mark_sample_files_deleted(&tx, dir_id, &dir.garbage_unlinked)?;
         }
         for (&stream_id, r) in &mut new_ranges {
             *r = raw::get_range(&tx, stream_id)?;
@@ -1086,6 +1513,7 @@ impl LockedDatabase {
         }
         self.auth.flush(&tx)?;
         self.signal.flush(&tx)?;
+        self.event.flush(&tx)?;
         tx.commit()?;
 
         #[derive(Default)]
@@ -1097,6 +1525,8 @@ impl LockedDatabase {
             deleted_bytes: i64,
         }
         let mut dir_logs: FastHashMap<i32, DirLog> = FastHashMap::default();
+        let mut changed: Vec<(i32, Vec<Range<recording::Time>>)> =
+            Vec::with_capacity(new_ranges.len());
 
         // Process delete_garbage.
         for (&id, dir) in &mut self.sample_file_dirs_by_id {
@@ -1114,6 +1544,7 @@ impl LockedDatabase {
             let dir_id = s.sample_file_dir_id.unwrap();
             let dir = self.sample_file_dirs_by_id.get_mut(&dir_id).unwrap();
             let log = dir_logs.entry(dir_id).or_default();
+            let mut stream_changed = Vec::with_capacity(s.to_delete.len() + s.synced_recordings);
 
             // Process delete_oldest_recordings.
             s.sample_file_bytes -= s.bytes_to_delete;
@@ -1128,6 +1559,7 @@ impl LockedDatabase {
                 let d = recording::Duration(i64::from(row.wall_duration_90k));
                 s.duration -= d;
                 s.committed_days.adjust(row.start..row.start + d, -1);
+                stream_changed.push(row.start..row.start + d);
             }
 
             // Process add_recordings.
@@ -1147,15 +1579,22 @@ impl LockedDatabase {
                 s.cum_runs += if l.run_offset == 0 { 1 } else { 0 };
                 let end = l.start + wall_dur;
                 s.add_recording(l.start..end, l.sample_file_bytes);
+                stream_changed.push(l.start..end);
             }
             s.synced_recordings = 0;
+            changed.push((stream_id, stream_changed));
 
             // Fix the range.
             s.range = new_range;
         }
         self.auth.post_flush();
         self.signal.post_flush();
+        self.event.post_flush();
         self.flush_count += 1;
+        self.publish();
+        for (stream_id, ranges) in &changed {
+            self.aggregates.invalidate(*stream_id, ranges);
+        }
         let mut log_msg = String::with_capacity(256);
         for (&dir_id, log) in &dir_logs {
             let dir = self.sample_file_dirs_by_id.get(&dir_id).unwrap();
@@ -1207,8 +1646,8 @@ impl LockedDatabase {
     /// update to the directories' on-disk metadata.
     ///
     /// Note this violates the principle of never accessing disk while holding the database lock.
-    /// Currently this only happens at startup (or during configuration), so this isn't a problem
-    /// in practice.
+    /// Currently this only happens at startup, during configuration, or when starting to move a
+    /// stream to a new directory, so this isn't a problem in practice.
     pub fn open_sample_file_dirs(&mut self, ids: &[i32]) -> Result<(), Error> {
         let mut in_progress = FastHashMap::with_capacity_and_hasher(ids.len(), Default::default());
         for &id in ids {
@@ -1231,8 +1670,15 @@ impl LockedDatabase {
                 open.id = o.id;
                 open.uuid.extend_from_slice(&o.uuid.as_bytes()[..]);
             }
-            let d = dir::SampleFileDir::open(&dir.path, &expected_meta)
-                .map_err(|e| err!(e, msg("Failed to open dir {}", dir.path.display())))?;
+            let d = dir::SampleFileDir::open_with_reader_config(
+                &dir.path,
+                &expected_meta,
+                &dir.reader_config,
+            )
+            .map_err(|e| err!(e, msg("Failed to open dir {}", dir.path.display())))?;
+            if let Some(k) = &self.sample_file_key {
+                d.set_sample_file_key(k.clone());
+            }
             if self.open.is_none() {
                 // read-only mode; it's already fully opened.
                 dir.dir = Some(d);
@@ -1243,7 +1689,11 @@ impl LockedDatabase {
         }
 
         let o = match self.open.as_ref() {
-            None => return Ok(()), // read-only mode; all done.
+            None => {
+                // read-only mode; all done.
+                self.stream_dirs_changed();
+                return Ok(());
+            }
             Some(o) => o,
         };
 
@@ -1268,7 +1718,170 @@ impl LockedDatabase {
             d.write_meta(&meta)?;
             dir.dir = Some(d);
         }
+        self.stream_dirs_changed();
+
+        Ok(())
+    }
+
+    /// Notes a change to which directory a stream uses or which directories are open.
+    fn stream_dirs_changed(&self) {
+        self.stream_dirs_generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Sets the reader configuration of the given sample file directory.
+    ///
+    /// This must be called before the directory is opened with `open_sample_file_dirs`.
+    pub fn set_sample_file_dir_reader_config(
+        &mut self,
+        id: i32,
+        config: dir::ReaderConfig,
+    ) -> Result<(), Error> {
+        let dir = self
+            .sample_file_dirs_by_id
+            .get_mut(&id)
+            .ok_or_else(|| err!(NotFound, msg("no such dir {id}")))?;
+        if dir.dir.is_some() {
+            bail!(
+                FailedPrecondition,
+                msg("dir {} is already open", dir.path.display())
+            );
+        }
+        dir.reader_config = config;
+        Ok(())
+    }
+
+    /// Sets the free space the given sample file directory's syncer should keep on its
+    /// filesystem. This takes effect as of the syncer's next save.
+    pub fn set_sample_file_dir_min_free_bytes(
+        &mut self,
+        id: i32,
+        min_free_bytes: i64,
+    ) -> Result<(), Error> {
+        if min_free_bytes < 0 {
+            bail!(
+                InvalidArgument,
+                msg("min free bytes must be non-negative; got {min_free_bytes}")
+            );
+        }
+        let dir = self
+            .sample_file_dirs_by_id
+            .get_mut(&id)
+            .ok_or_else(|| err!(NotFound, msg("no such dir {id}")))?;
+        dir.min_free_bytes = min_free_bytes;
+        Ok(())
+    }
+
+    pub fn time_zone_name(&self) -> &str {
+        &self.time_zone_name
+    }
+
+    /// Sets the name of the local time zone reported to clients. This doesn't affect the days
+    /// maps; see [`LockedDatabase::rebuild_days`].
+    pub fn set_time_zone_name(&mut self, name: String) {
+        self.time_zone_name = name;
+    }
+
+    /// Re-reads the local time zone and recomputes all days maps in it.
+    ///
+    /// Days maps are computed incrementally as recordings and signal changes come and go, so
+    /// they're otherwise in whatever zone was in effect when the database was opened. This picks
+    /// up changes to `TZ`, `/etc/localtime`, or the zone's rules (via `tzset(3)`). Committed
+    /// recordings are re-read from the database, much as on open.
+    pub fn rebuild_days(&mut self) -> Result<(), Error> {
+        if self.streams_by_id.values().any(|s| !s.loaded) {
+            bail!(
+                Unavailable,
+                msg("recordings are still loading; try again later")
+            );
+        }
+        time::tzset();
+        days::clear_bounds_cache();
+        let mut days_by_stream: BTreeMap<i32, days::Map<days::StreamValue>> = BTreeMap::new();
+        {
+            let mut stmt = self.conn.prepare(
+                r#"
+                select
+                  stream_id,
+                  start_time_90k,
+                  wall_duration_90k
+                from
+                  recording
+                "#,
+            )?;
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                let start = recording::Time(row.get(1)?);
+                let duration = recording::Duration(row.get(2)?);
+                days_by_stream
+                    .entry(row.get(0)?)
+                    .or_default()
+                    .adjust(start..start + duration, 1);
+            }
+        }
+        for (id, s) in &mut self.streams_by_id {
+            s.committed_days = days_by_stream.remove(id).unwrap_or_default();
+        }
+        self.signal.rebuild_days();
+        Ok(())
+    }
 
+    /// Points the given stream at a new sample file directory, as the last step of moving its
+    /// recordings. See [`crate::mover`].
+    ///
+    /// `ids` must be exactly the stream's committed recordings, and their sample files must
+    /// already be present and synced in the new directory. The stream must have no uncommitted
+    /// recordings or pending deletions. The copies in the old directory become garbage, to be
+    /// unlinked by its syncer.
+    pub(crate) fn switch_stream_dir(
+        &mut self,
+        stream_id: i32,
+        new_dir_id: i32,
+        ids: &[CompositeId],
+    ) -> Result<(), Error> {
+        let s = self
+            .streams_by_id
+            .get_mut(&stream_id)
+            .ok_or_else(|| err!(NotFound, msg("no such stream {stream_id}")))?;
+        let Some(old_dir_id) = s.sample_file_dir_id else {
+            bail!(
+                FailedPrecondition,
+                msg("stream {stream_id} has no sample file dir")
+            );
+        };
+        if !s.uncommitted.is_empty() || !s.to_delete.is_empty() {
+            bail!(
+                FailedPrecondition,
+                msg("stream {stream_id} has unflushed recordings")
+            );
+        }
+        if !self.sample_file_dirs_by_id.contains_key(&new_dir_id) {
+            bail!(NotFound, msg("no such dir {new_dir_id}"));
+        }
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                r#"
+                update stream set sample_file_dir_id = :new where id = :id and sample_file_dir_id = :old
+                "#,
+            )?;
+            let rows = stmt.execute(named_params! {
+                ":new": new_dir_id,
+                ":id": stream_id,
+                ":old": old_dir_id,
+            })?;
+            if rows != 1 {
+                bail!(Internal, msg("unable to update stream {stream_id}"));
+            }
+        }
+        raw::insert_garbage(&tx, old_dir_id, ids)?;
+        tx.commit()?;
+        s.sample_file_dir_id = Some(new_dir_id);
+        self.stream_dirs_changed();
+        self.sample_file_dirs_by_id
+            .get_mut(&old_dir_id)
+            .unwrap()
+            .garbage_needs_unlink
+            .extend(ids.iter().copied());
         Ok(())
     }
 
@@ -1281,6 +1894,17 @@ impl LockedDatabase {
         &self.video_sample_entries_by_id
     }
 
+    /// Returns the given video sample entry, following any merge by
+    /// [`LockedDatabase::gc_video_sample_entries`]. The returned entry's id may differ from `id`.
+    pub fn video_sample_entry(&self, id: i32) -> Option<&Arc<VideoSampleEntry>> {
+        let id = self
+            .video_sample_entry_aliases
+            .get(&id)
+            .copied()
+            .unwrap_or(id);
+        self.video_sample_entries_by_id.get(&id)
+    }
+
     /// Gets a given camera by uuid.
     pub fn get_camera(&self, uuid: Uuid) -> Option<&Camera> {
         self.cameras_by_uuid.get(&uuid).map(|id| {
@@ -1306,25 +1930,52 @@ impl LockedDatabase {
             Some(s) => s,
         };
         raw::list_recordings_by_time(&self.conn, stream_id, desired_time.clone(), f)?;
-        for (i, u) in s.uncommitted.iter().enumerate() {
-            let row = {
-                let l = u.lock().unwrap();
-                if l.video_samples > 0 {
-                    let end = l.start + recording::Duration(l.wall_duration_90k as i64);
-                    if l.start > desired_time.end || end < desired_time.start {
-                        continue; // there's no overlap with the requested range.
-                    }
-                    l.to_list_row(
-                        CompositeId::new(stream_id, s.cum_recordings + i as i32),
-                        self.open.unwrap().id,
+        list_uncommitted_by_time(
+            stream_id,
+            s.cum_recordings,
+            s.uncommitted.iter(),
+            self.open.map(|o| o.id),
+            &desired_time,
+            s.cum_recordings,
+            f,
+        )
+    }
+
+    /// Publishes a new [`ReadIndex`] reflecting the current cameras, streams, and uncommitted
+    /// recordings. This should be called after each change to them.
+    fn publish(&self) {
+        let index = ReadIndex {
+            open_id: self.open.map(|o| o.id),
+            cameras_by_uuid: self
+                .cameras_by_id
+                .values()
+                .map(|c| {
+                    (
+                        c.uuid,
+                        IndexedCamera {
+                            id: c.id,
+                            short_name: c.short_name.clone(),
+                            streams: c.streams,
+                        },
                     )
-                } else {
-                    continue;
-                }
-            };
-            f(row)?;
-        }
-        Ok(())
+                })
+                .collect(),
+            streams_by_id: self
+                .streams_by_id
+                .iter()
+                .map(|(&id, s)| {
+                    (
+                        id,
+                        IndexedStream {
+                            cum_recordings: s.cum_recordings,
+                            uncommitted: s.uncommitted.iter().cloned().collect(),
+                        },
+                    )
+                })
+                .collect(),
+        };
+        let old = mem::replace(&mut *self.read_index.lock().unwrap(), Arc::new(index));
+        drop(old); // outside the lock.
     }
 
     /// Lists the specified recordings in ascending order by id.
@@ -1365,100 +2016,91 @@ impl LockedDatabase {
         Ok(())
     }
 
-    /// Calls `list_recordings_by_time` and aggregates consecutive recordings.
-    /// Rows are given to the callback in arbitrary order. Callers which care about ordering
-    /// should do their own sorting.
-    pub fn list_aggregated_recordings(
+    /// Returns the RTSP session details of the run whose first recording is `id`, if recorded.
+    ///
+    /// These are kept only as long as the run's first recording.
+    pub fn get_rtsp_session(
         &self,
-        stream_id: i32,
-        desired_time: Range<recording::Time>,
-        forced_split: recording::Duration,
-        f: &mut dyn FnMut(ListAggregatedRecordingsRow) -> Result<(), base::Error>,
-    ) -> Result<(), base::Error> {
-        // Iterate, maintaining a map from a recording_id to the aggregated row for the latest
-        // batch of recordings from the run starting at that id. Runs can be split into multiple
-        // batches for a few reasons:
-        //
-        // * forced split (when exceeding a duration limit)
-        // * a missing id (one that was deleted out of order)
-        // * video_sample_entry mismatch (if the parameters changed during a RTSP session)
-        //
-        // This iteration works because in a run, the start_time+duration of recording id r
-        // is equal to the start_time of recording id r+1. Thus ascending times guarantees
-        // ascending ids within a run. (Different runs, however, can be arbitrarily interleaved if
-        // their timestamps overlap. Tracking all active runs prevents that interleaving from
-        // causing problems.) list_recordings_by_time also returns uncommitted recordings in
-        // ascending order by id, and after any committed recordings with lower ids.
-        let mut aggs: BTreeMap<i32, ListAggregatedRecordingsRow> = BTreeMap::new();
-        self.list_recordings_by_time(stream_id, desired_time, &mut |row| {
-            let recording_id = row.id.recording();
-            let run_start_id = recording_id - row.run_offset;
-            let uncommitted = (row.flags & RecordingFlags::Uncommitted as i32) != 0;
-            let growing = (row.flags & RecordingFlags::Growing as i32) != 0;
-            let has_trailing_zero = (row.flags & RecordingFlags::TrailingZero as i32) != 0;
-            use std::collections::btree_map::Entry;
-            match aggs.entry(run_start_id) {
-                Entry::Occupied(mut e) => {
-                    let a = e.get_mut();
-                    let new_dur = a.time.end - a.time.start
-                        + recording::Duration(row.wall_duration_90k as i64);
-                    let needs_flush = a.ids.end != recording_id
-                        || row.video_sample_entry_id != a.video_sample_entry_id
-                        || new_dur >= forced_split;
-                    if needs_flush {
-                        // flush then start a new entry.
-                        f(std::mem::replace(a, ListAggregatedRecordingsRow::from(row)))?;
-                    } else {
-                        // append.
-                        if a.time.end != row.start {
-                            bail!(
-                                Internal,
-                                msg(
-                                    "stream {} recording {} ends at {} but {} starts at {}",
-                                    stream_id,
-                                    a.ids.end - 1,
-                                    a.time.end,
-                                    row.id,
-                                    row.start,
-                                ),
-                            );
-                        }
-                        if a.open_id != row.open_id {
-                            bail!(
-                                Internal,
-                                msg(
-                                    "stream {} recording {} has open id {} but {} has {}",
-                                    stream_id,
-                                    a.ids.end - 1,
-                                    a.open_id,
-                                    row.id,
-                                    row.open_id,
-                                ),
-                            );
-                        }
-                        a.time.end.0 += row.wall_duration_90k as i64;
-                        a.ids.end = recording_id + 1;
-                        a.video_samples += row.video_samples as i64;
-                        a.video_sync_samples += row.video_sync_samples as i64;
-                        a.sample_file_bytes += row.sample_file_bytes as i64;
-                        if uncommitted {
-                            a.first_uncommitted = a.first_uncommitted.or(Some(recording_id));
-                        }
-                        a.growing = growing;
-                        a.has_trailing_zero = has_trailing_zero;
-                        a.end_reason = row.end_reason;
-                    }
-                }
-                Entry::Vacant(e) => {
-                    e.insert(ListAggregatedRecordingsRow::from(row));
-                }
-            }
-            Ok(())
-        })?;
-        for a in aggs.into_values() {
-            f(a)?;
+        id: CompositeId,
+    ) -> Result<Option<Arc<RtspSession>>, base::Error> {
+        let s = match self.streams_by_id.get(&id.stream()) {
+            None => bail!(NotFound, msg("no such stream {}", id.stream())),
+            Some(s) => s,
+        };
+        if id.recording() >= s.cum_recordings {
+            let i = (id.recording() - s.cum_recordings) as usize;
+            return Ok(s
+                .uncommitted
+                .get(i)
+                .and_then(|r| r.lock().unwrap().rtsp_session.clone()));
         }
-        Ok(())
+        Ok(raw::get_rtsp_session(&self.conn, id)?.map(Arc::new))
+    }
+
+    /// Returns the ids of the surviving recordings in the run which began with `run_start_id`.
+    ///
+    /// Retention may have deleted the start of the run, so the returned range may begin after
+    /// `run_start_id`. Fails with `NotFound` if no recording in the run remains.
+    pub fn run_ids(&self, stream_id: i32, run_start_id: i32) -> Result<Range<i32>, base::Error> {
+        // Runs can be arbitrarily long, so look at a chunk of ids at a time.
+        const CHUNK: i32 = 1024;
+        let end_id = match self.streams_by_id.get(&stream_id) {
+            None => bail!(NotFound, msg("no such stream {stream_id}")),
+            Some(s) => s.cum_recordings + s.uncommitted.len() as i32,
+        };
+        let mut ids: Option<Range<i32>> = None;
+        let mut next = run_start_id;
+        while next < end_id {
+            let chunk_end = cmp::min(next.saturating_add(CHUNK), end_id);
+            let mut done = false;
+            self.list_recordings_by_id(stream_id, next..chunk_end, &mut |r| {
+                let id = r.id.recording();
+                if done {
+                    return Ok(());
+                }
+                let in_run = r.run_offset == id - run_start_id
+                    && ids.as_ref().map_or(true, |ids| ids.end == id);
+                if !in_run {
+                    done = true;
+                    return Ok(());
+                }
+                ids.get_or_insert(id..id).end = id + 1;
+                Ok(())
+            })?;
+            match ids {
+                // Continue past deleted recordings at the start of the run, or into the next
+                // chunk if the run fills this one.
+                None if !done => next = chunk_end,
+                Some(ref ids) if !done && ids.end == chunk_end => next = chunk_end,
+                _ => break,
+            }
+        }
+        ids.ok_or_else(|| {
+            err!(
+                NotFound,
+                msg(
+                    "no recordings in run {}",
+                    CompositeId::new(stream_id, run_start_id)
+                )
+            )
+        })
+    }
+
+    /// Calls `list_recordings_by_time` and aggregates consecutive recordings.
+    /// Rows are given to the callback in arbitrary order. Callers which care about ordering
+    /// should do their own sorting.
+    pub fn list_aggregated_recordings(
+        &self,
+        stream_id: i32,
+        desired_time: Range<recording::Time>,
+        forced_split: recording::Duration,
+        f: &mut dyn FnMut(ListAggregatedRecordingsRow) -> Result<(), base::Error>,
+    ) -> Result<(), base::Error> {
+        aggregate_recordings(
+            &mut |g| self.list_recordings_by_time(stream_id, desired_time.clone(), g),
+            forced_split,
+            f,
+        )
     }
 
     /// Calls `f` with a single `recording_playback` row.
@@ -1631,6 +2273,8 @@ impl LockedDatabase {
                     path: config.path,
                     dir: None,
                     last_complete_open,
+                    reader_config: dir::ReaderConfig::default(),
+                    min_free_bytes: 0,
                     garbage_needs_unlink: raw::list_garbage(&self.conn, id)?,
                     garbage_unlinked: Vec::new(),
                 },
@@ -1726,12 +2370,15 @@ impl LockedDatabase {
                     fs_bytes_to_add: 0,
                     duration: recording::Duration(0),
                     committed_days: days::Map::default(),
+                    loaded: false,
                     cum_recordings: row.get(5)?,
                     cum_media_duration: recording::Duration(row.get(6)?),
                     cum_runs: row.get(7)?,
                     uncommitted: VecDeque::new(),
                     synced_recordings: 0,
                     live_segments: tokio::sync::broadcast::channel(LIVE_SEGMENTS_BUF_LEN).0,
+                    recent_frames: RecentFrames::default(),
+                    last_error: None,
                 },
             );
             c.streams[type_.index()] = Some(id);
@@ -1761,6 +2408,7 @@ impl LockedDatabase {
                         msg("video_sample_entry id {id}: existing entry {v:?}, new {entry:?}"),
                     );
                 }
+                self.issued_video_sample_entries.insert(id);
                 return Ok(id);
             }
         }
@@ -1777,6 +2425,7 @@ impl LockedDatabase {
         .map_err(|e| err!(e, msg("Unable to insert {entry:#?}")))?;
 
         let id = self.conn.last_insert_rowid() as i32;
+        self.issued_video_sample_entries.insert(id);
         self.video_sample_entries_by_id.insert(
             id,
             Arc::new(VideoSampleEntry {
@@ -1793,6 +2442,93 @@ impl LockedDatabase {
         Ok(id)
     }
 
+    /// Merges identical video sample entries and deletes ones no recording references, returning
+    /// the number of each. Cameras which changed resolution or codec settings over the years can
+    /// leave many behind.
+    ///
+    /// Merged recordings are remapped to the lowest id of their identical entries, and the
+    /// removed ids remain resolvable via [`LockedDatabase::video_sample_entry`]. Entries issued by
+    /// [`LockedDatabase::insert_video_sample_entry`] since the database was opened or referenced
+    /// by uncommitted recordings are left alone.
+    pub fn gc_video_sample_entries(&mut self) -> Result<(usize, usize), Error> {
+        let mut protected = self.issued_video_sample_entries.clone();
+        for s in self.streams_by_id.values() {
+            for u in &s.uncommitted {
+                protected.insert(u.lock().unwrap().video_sample_entry_id);
+            }
+        }
+
+        // Entries are visited in id order, so the first of each identical set is kept.
+        let mut kept: Vec<&VideoSampleEntry> = Vec::new();
+        let mut merges: Vec<(i32, i32)> = Vec::new(); // (from, to)
+        for v in self.video_sample_entries_by_id.values() {
+            let existing = kept.iter().find(|k| {
+                k.data == v.data
+                    && k.rfc6381_codec == v.rfc6381_codec
+                    && k.width == v.width
+                    && k.height == v.height
+                    && k.pasp_h_spacing == v.pasp_h_spacing
+                    && k.pasp_v_spacing == v.pasp_v_spacing
+            });
+            match existing {
+                Some(k) if !protected.contains(&v.id) => merges.push((v.id, k.id)),
+                _ => kept.push(v),
+            }
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut update = tx.prepare(
+                "update recording set video_sample_entry_id = :to \
+                 where video_sample_entry_id = :from",
+            )?;
+            let mut delete = tx.prepare("delete from video_sample_entry where id = :id")?;
+            for &(from, to) in &merges {
+                update.execute(named_params! {":from": from, ":to": to})?;
+                delete.execute(named_params! {":id": from})?;
+            }
+        }
+        let mut referenced = protected;
+        {
+            let mut stmt = tx.prepare("select distinct video_sample_entry_id from recording")?;
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                let id: Option<i32> = row.get(0)?;
+                referenced.extend(id);
+            }
+        }
+        let unreferenced: Vec<i32> = kept
+            .iter()
+            .map(|k| k.id)
+            .filter(|id| !referenced.contains(id))
+            .collect();
+        {
+            let mut delete = tx.prepare("delete from video_sample_entry where id = :id")?;
+            for &id in &unreferenced {
+                delete.execute(named_params! {":id": id})?;
+            }
+        }
+        tx.commit()?;
+
+        for &(from, to) in &merges {
+            self.video_sample_entries_by_id.remove(&from);
+            self.video_sample_entry_aliases.insert(from, to);
+        }
+        for id in &unreferenced {
+            self.video_sample_entries_by_id.remove(id);
+        }
+        if !merges.is_empty() {
+            // Runs may no longer be split where the merged entries changed.
+            self.aggregates.clear();
+        }
+
+        // Earlier aliases to a now-deleted entry are no longer useful.
+        let entries = &self.video_sample_entries_by_id;
+        self.video_sample_entry_aliases
+            .retain(|_, to| entries.contains_key(to));
+        Ok((merges.len(), unreferenced.len()))
+    }
+
     pub fn add_sample_file_dir(&mut self, path: PathBuf) -> Result<i32, Error> {
         let mut meta = schema::DirMeta::default();
         let uuid = Uuid::new_v4();
@@ -1812,6 +2548,9 @@ impl LockedDatabase {
         }
 
         let dir = dir::SampleFileDir::create(&path, &meta)?;
+        if let Some(k) = &self.sample_file_key {
+            dir.set_sample_file_key(k.clone());
+        }
         let config = SampleFileDirConfig {
             path: path.clone(),
             ..Default::default()
@@ -1833,6 +2572,8 @@ impl LockedDatabase {
                 uuid,
                 dir: Some(dir),
                 last_complete_open: Some(*o),
+                reader_config: dir::ReaderConfig::default(),
+                min_free_bytes: 0,
                 garbage_needs_unlink: FastHashSet::default(),
                 garbage_unlinked: Vec::new(),
             }),
@@ -1906,6 +2647,7 @@ impl LockedDatabase {
     pub fn add_camera(&mut self, mut camera: CameraChange) -> Result<i32, Error> {
         let uuid = Uuid::new_v4();
         let uuid_bytes = &uuid.as_bytes()[..];
+        let stored_config = self.stored_camera_config(uuid, &camera.config)?;
         let tx = self.conn.transaction()?;
         let streams;
         let camera_id;
@@ -1919,7 +2661,7 @@ impl LockedDatabase {
             stmt.execute(named_params! {
                 ":uuid": uuid_bytes,
                 ":short_name": &camera.short_name,
-                ":config": &camera.config,
+                ":config": &stored_config,
             })?;
             camera_id = tx.last_insert_rowid() as i32;
             streams =
@@ -1927,6 +2669,7 @@ impl LockedDatabase {
         }
         tx.commit()?;
         let streams = streams.apply(&mut self.streams_by_id);
+        self.stream_dirs_changed();
         self.cameras_by_id.insert(
             camera_id,
             Camera {
@@ -1938,6 +2681,7 @@ impl LockedDatabase {
             },
         );
         self.cameras_by_uuid.insert(uuid, camera_id);
+        self.publish();
         Ok(camera_id)
     }
 
@@ -1972,11 +2716,16 @@ impl LockedDatabase {
 
     /// Updates a camera.
     pub fn update_camera(&mut self, camera_id: i32, mut camera: CameraChange) -> Result<(), Error> {
-        let tx = self.conn.transaction()?;
-        let streams;
-        let Some(c) = self.cameras_by_id.get_mut(&camera_id) else {
+        let Some(uuid) = self.cameras_by_id.get(&camera_id).map(|c| c.uuid) else {
             bail!(Internal, msg("no such camera {camera_id}"));
         };
+        let stored_config = self.stored_camera_config(uuid, &camera.config)?;
+        let tx = self.conn.transaction()?;
+        let streams;
+        let c = self
+            .cameras_by_id
+            .get_mut(&camera_id)
+            .expect("camera existence checked above");
         {
             streams =
                 StreamStateChanger::new(&tx, camera_id, Some(c), &self.streams_by_id, &mut camera)?;
@@ -1992,7 +2741,7 @@ impl LockedDatabase {
             let rows = stmt.execute(named_params! {
                 ":id": camera_id,
                 ":short_name": &camera.short_name,
-                ":config": &camera.config,
+                ":config": &stored_config,
             })?;
             if rows != 1 {
                 bail!(Internal, msg("camera {camera_id} missing from database"));
@@ -2002,9 +2751,164 @@ impl LockedDatabase {
         c.short_name = camera.short_name;
         c.config = camera.config;
         c.streams = streams.apply(&mut self.streams_by_id);
+        self.stream_dirs_changed();
+        self.publish();
+        Ok(())
+    }
+
+    /// Returns `config` as it should be stored in the database, with credentials encrypted if
+    /// appropriate.
+    fn stored_camera_config(
+        &self,
+        uuid: Uuid,
+        config: &CameraConfig,
+    ) -> Result<CameraConfig, Error> {
+        let mut stored = config.clone();
+        match self.credential_key {
+            Some(ref k) => credential::encrypt(k, uuid, &mut stored)?,
+            None if self.credentials_encrypted
+                && (!config.username.is_empty() || !config.password.is_empty()) =>
+            {
+                bail!(
+                    FailedPrecondition,
+                    msg("credential key must be supplied to store camera credentials")
+                );
+            }
+            None => {}
+        }
+        Ok(stored)
+    }
+
+    /// Supplies the key which encrypts camera credentials at rest.
+    ///
+    /// If the database doesn't yet have credential encryption enabled, this enables it (unless
+    /// read-only), encrypting all existing credentials. Afterward, cameras' credentials are
+    /// available in memory as plaintext and are encrypted on write.
+    pub fn set_credential_key(&mut self, key: &credential::Key) -> Result<(), Error> {
+        let wrapped: Option<Vec<u8>> = self
+            .conn
+            .query_row("select wrapped_key from credential_key", params![], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let read_write = self.open.is_some();
+        let tx = self.conn.transaction()?;
+        let data_key = match wrapped {
+            Some(w) => {
+                let raw = key.open(self.uuid.as_bytes(), &w).map_err(|e| {
+                    err!(
+                        e,
+                        msg("credential key doesn't match the one used previously")
+                    )
+                })?;
+                credential::Key::from_bytes(&raw)?
+            }
+            None if !read_write => {
+                // Nothing is encrypted, and encryption can't be enabled now.
+                return Ok(());
+            }
+            None => {
+                let (data_key, raw) = credential::Key::generate()?;
+                tx.execute(
+                    "insert into credential_key (id, wrapped_key) values (1, ?)",
+                    params![key.seal(self.uuid.as_bytes(), &raw)?],
+                )?;
+                data_key
+            }
+        };
+        let mut configs = Vec::with_capacity(self.cameras_by_id.len());
+        {
+            let mut stmt = tx.prepare_cached("update camera set config = ? where id = ?")?;
+            for c in self.cameras_by_id.values() {
+                let mut config = c.config.clone();
+                if config.encrypted_credentials.is_some() {
+                    credential::decrypt(&data_key, c.uuid, &mut config).map_err(|e| {
+                        err!(
+                            e,
+                            msg("unable to decrypt credentials of camera {}", c.short_name)
+                        )
+                    })?;
+                } else if read_write && (!config.username.is_empty() || !config.password.is_empty())
+                {
+                    let mut stored = config.clone();
+                    credential::encrypt(&data_key, c.uuid, &mut stored)?;
+                    stmt.execute(params![&stored, c.id])?;
+                }
+                configs.push((c.id, config));
+            }
+        }
+        tx.commit()?;
+        for (id, config) in configs {
+            self.cameras_by_id
+                .get_mut(&id)
+                .expect("camera ids unchanged")
+                .config = config;
+        }
+        self.credentials_encrypted = true;
+        self.credential_key = Some(data_key);
+        Ok(())
+    }
+
+    /// Returns true if any camera's credentials are encrypted and can't be used until
+    /// `set_credential_key` is called.
+    pub fn has_locked_credentials(&self) -> bool {
+        self.cameras_by_id
+            .values()
+            .any(|c| c.config.encrypted_credentials.is_some())
+    }
+
+    /// Supplies the master key for encrypting sample files at rest.
+    ///
+    /// If the database doesn't yet have sample file encryption enabled, this enables it (unless
+    /// read-only). Afterward, new recordings are encrypted, and encrypted recordings are
+    /// decrypted transparently by [`dir::SampleFileDir::open_file`]. Existing plaintext
+    /// recordings are left as is.
+    pub fn set_sample_file_key(&mut self, key: dir::crypt::Key) -> Result<(), Error> {
+        let check: Option<Vec<u8>> = self
+            .conn
+            .query_row("select key_check from sample_file_key", params![], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match check {
+            Some(c) if c[..] != key.check_value()[..] => bail!(
+                PermissionDenied,
+                msg("sample file key doesn't match the one used previously")
+            ),
+            Some(_) => {}
+            None if self.open.is_none() => {
+                // Nothing is encrypted, and encryption can't be enabled now.
+                return Ok(());
+            }
+            None => {
+                self.conn.execute(
+                    "insert into sample_file_key (id, key_check) values (1, ?)",
+                    params![&key.check_value()[..]],
+                )?;
+            }
+        }
+        let key = Arc::new(key);
+        for d in self.sample_file_dirs_by_id.values() {
+            if let Some(d) = &d.dir {
+                d.set_sample_file_key(key.clone());
+            }
+        }
+        self.sample_files_encrypted = true;
+        self.sample_file_key = Some(key);
         Ok(())
     }
 
+    /// Returns the master key for sample files, if encryption is enabled.
+    pub fn sample_file_key(&self) -> Option<&Arc<dir::crypt::Key>> {
+        self.sample_file_key.as_ref()
+    }
+
+    /// Returns true if sample files may be encrypted but can't be read until
+    /// `set_sample_file_key` is called.
+    pub fn has_locked_sample_files(&self) -> bool {
+        self.sample_files_encrypted && self.sample_file_key.is_none()
+    }
+
     /// Deletes a camera and its streams. The camera must have no recordings.
     pub fn delete_camera(&mut self, id: i32) -> Result<(), Error> {
         // TODO: also verify there are no uncommitted recordings.
@@ -2038,11 +2942,18 @@ impl LockedDatabase {
             }
         }
         tx.commit()?;
-        for id in streams_to_delete {
+        for &id in &streams_to_delete {
             self.streams_by_id.remove(&id);
         }
+        self.stream_dirs_changed();
         self.cameras_by_id.remove(&id);
         self.cameras_by_uuid.remove(&uuid);
+        self.publish();
+        for id in streams_to_delete {
+            // The id may be reused by a later stream.
+            self.aggregates
+                .invalidate(id, &[recording::Time::MIN..recording::Time::MAX]);
+        }
         Ok(())
     }
 
@@ -2101,10 +3012,78 @@ impl LockedDatabase {
         self.auth.apply(&self.conn, change)
     }
 
+    /// Returns true if `u`'s password hash will be upgraded at the user's next password login.
+    pub fn password_needs_rehash(&self, u: &User) -> bool {
+        self.auth.password_needs_rehash(u)
+    }
+
+    pub fn password_hash_config(
+        &self,
+    ) -> Result<Option<crate::json::PasswordHashConfig>, base::Error> {
+        Ok(raw::read_meta(&self.conn)?.1.password_hash)
+    }
+
+    /// Stores `passwordHash` in [`crate::json::GlobalConfig`] and uses it for subsequent hashes.
+    pub fn set_password_hash_config(
+        &mut self,
+        config: crate::json::PasswordHashConfig,
+    ) -> Result<(), base::Error> {
+        let params = auth::password_params(Some(&config))?;
+        let (_, mut global) = raw::read_meta(&self.conn)?;
+        global.password_hash = Some(config);
+        raw::write_meta_config(&self.conn, &global)?;
+        self.auth.set_password_params(params);
+        Ok(())
+    }
+
     pub fn delete_user(&mut self, id: i32) -> Result<(), base::Error> {
         self.auth.delete_user(&mut self.conn, id)
     }
 
+    pub fn disable_expired_users(&mut self, now_sec: i64) -> Result<Vec<String>, base::Error> {
+        self.auth.disable_expired_users(&self.conn, now_sec)
+    }
+
+    pub fn purge_sessions(
+        &mut self,
+        now_sec: i64,
+        retention_sec: i64,
+    ) -> Result<usize, base::Error> {
+        self.auth
+            .purge_sessions(&mut self.conn, now_sec, retention_sec)
+    }
+
+    pub fn auth_table_stats(&self) -> auth::TableStats {
+        self.auth.table_stats()
+    }
+
+    pub fn permission_templates_by_id(&self) -> &BTreeMap<i32, PermissionTemplate> {
+        self.auth.permission_templates_by_id()
+    }
+
+    pub fn add_permission_template(
+        &mut self,
+        name: String,
+        permissions: schema::Permissions,
+    ) -> Result<&PermissionTemplate, base::Error> {
+        self.auth
+            .add_permission_template(&self.conn, name, permissions)
+    }
+
+    pub fn update_permission_template(
+        &mut self,
+        id: i32,
+        name: String,
+        permissions: schema::Permissions,
+    ) -> Result<&PermissionTemplate, base::Error> {
+        self.auth
+            .update_permission_template(&mut self.conn, id, name, permissions)
+    }
+
+    pub fn delete_permission_template(&mut self, id: i32) -> Result<(), base::Error> {
+        self.auth.delete_permission_template(&self.conn, id)
+    }
+
     pub fn get_user(&self, username: &str) -> Option<&User> {
         self.auth.get_user(username)
     }
@@ -2121,6 +3100,56 @@ impl LockedDatabase {
             .login_by_password(&self.conn, req, username, password, domain, session_flags)
     }
 
+    pub fn webauthn_credentials_by_id(&self) -> &BTreeMap<i32, WebAuthnCredential> {
+        self.auth.webauthn_credentials_by_id()
+    }
+
+    pub fn get_webauthn_credential(&self, credential_id: &[u8]) -> Option<&WebAuthnCredential> {
+        self.auth.get_webauthn_credential(credential_id)
+    }
+
+    pub fn add_webauthn_credential(
+        &mut self,
+        user_id: i32,
+        credential_id: Vec<u8>,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        name: String,
+        now_sec: i64,
+    ) -> Result<&WebAuthnCredential, base::Error> {
+        self.auth.add_webauthn_credential(
+            &self.conn,
+            user_id,
+            credential_id,
+            public_key,
+            sign_count,
+            name,
+            now_sec,
+        )
+    }
+
+    pub fn delete_webauthn_credential(&mut self, id: i32) -> Result<(), base::Error> {
+        self.auth.delete_webauthn_credential(&self.conn, id)
+    }
+
+    pub fn login_by_webauthn(
+        &mut self,
+        req: auth::Request,
+        credential_id: &[u8],
+        sign_count: u32,
+        domain: Option<Vec<u8>>,
+        session_flags: i32,
+    ) -> Result<(RawSessionId, &Session), base::Error> {
+        self.auth.login_by_webauthn(
+            &self.conn,
+            req,
+            credential_id,
+            sign_count,
+            domain,
+            session_flags,
+        )
+    }
+
     pub fn make_session(
         &mut self,
         creation: Request,
@@ -2141,6 +3170,10 @@ impl LockedDatabase {
         self.auth.authenticate_session(&self.conn, req, sid)
     }
 
+    pub fn set_session_expiry(&mut self, expiry: Option<auth::SessionExpiry>) {
+        self.auth.set_session_expiry(expiry)
+    }
+
     pub fn revoke_session(
         &mut self,
         reason: auth::RevocationReason,
@@ -2152,6 +3185,17 @@ impl LockedDatabase {
             .revoke_session(&self.conn, reason, detail, req, hash)
     }
 
+    pub fn revoke_user_sessions(
+        &mut self,
+        user_id: i32,
+        reason: auth::RevocationReason,
+        detail: Option<String>,
+        req: auth::Request,
+    ) -> Result<usize, base::Error> {
+        self.auth
+            .revoke_user_sessions(&self.conn, user_id, reason, detail, req)
+    }
+
     // ---- signal ----
 
     pub fn signals_by_id(&self) -> &BTreeMap<u32, signal::Signal> {
@@ -2175,6 +3219,58 @@ impl LockedDatabase {
     ) -> Result<(), base::Error> {
         self.signal.update_signals(when, signals, states)
     }
+    pub fn list_signal_hours(
+        &self,
+        desired_time: Range<recording::Time>,
+    ) -> BTreeMap<(recording::Time, u32), days::SignalValue> {
+        self.signal.list_hours(desired_time)
+    }
+    pub fn put_signal_type(
+        &mut self,
+        uuid: Uuid,
+        config: crate::json::SignalTypeConfig,
+    ) -> Result<(), base::Error> {
+        self.signal.put_type(&self.conn, uuid, config)
+    }
+    pub fn delete_signal_type(&mut self, uuid: Uuid) -> Result<(), base::Error> {
+        self.signal.delete_type(&self.conn, uuid)
+    }
+
+    /// Creates or replaces the signal `uuid`, after checking that its associated cameras exist.
+    pub fn put_signal(
+        &mut self,
+        uuid: Uuid,
+        type_: Uuid,
+        config: crate::json::SignalConfig,
+    ) -> Result<u32, base::Error> {
+        for camera_id in config.camera_associations.keys() {
+            if !self.cameras_by_id.contains_key(camera_id) {
+                bail!(InvalidArgument, msg("no such camera {camera_id}"));
+            }
+        }
+        self.signal.put_signal(&self.conn, uuid, type_, config)
+    }
+    pub fn delete_signal(&mut self, uuid: Uuid) -> Result<(), base::Error> {
+        self.signal.delete_signal(&self.conn, uuid)
+    }
+
+    /// Adds an event to the journal, to be written on the next flush.
+    ///
+    /// In read-only mode, events are discarded.
+    pub fn add_event(&mut self, event: event::Event) {
+        if self.open.is_some() {
+            self.event.add(event);
+        }
+    }
+
+    /// Supplies `f` with journal events in `desired_time`, in ascending order of time.
+    pub fn list_events(
+        &self,
+        desired_time: Range<recording::Time>,
+        f: &mut dyn FnMut(&event::Event),
+    ) -> Result<(), base::Error> {
+        self.event.list(&self.conn, desired_time, f)
+    }
 }
 
 /// Pragmas for full database integrity.
@@ -2309,6 +3405,21 @@ pub struct Database<C: Clocks + Clone = clock::RealClocks> {
     /// access it. It doesn't need a `Mutex` anyway; it's `Sync`, and all operations work on
     /// `&self`.
     clocks: C,
+
+    lock_stats: LockStats,
+
+    /// The latest [`ReadIndex`], shared with the [`LockedDatabase`].
+    read_index: Arc<Mutex<Arc<ReadIndex>>>,
+
+    /// Cached [`Database::list_aggregated_recordings`] results, shared with the
+    /// [`LockedDatabase`].
+    aggregates: Arc<aggregates::Cache>,
+
+    /// Idle connections added via [`Database::add_reader`].
+    readers: Mutex<Vec<rusqlite::Connection>>,
+
+    /// See [`Database::stream_dirs_generation`]; shared with the [`LockedDatabase`].
+    stream_dirs_generation: Arc<AtomicU64>,
 }
 
 impl<C: Clocks + Clone> Drop for Database<C> {
@@ -2324,42 +3435,300 @@ impl<C: Clocks + Clone> Drop for Database<C> {
     }
 }
 
-// Helpers for Database::lock(). Closures don't implement Fn.
-fn acquisition() -> &'static str {
-    "database lock acquisition"
+/// Supplies `f` with uncommitted recordings which overlap `desired_time`, as the latter part of
+/// [`LockedDatabase::list_recordings_by_time`]. Recordings with ids below `first_id` are
+/// skipped.
+fn list_uncommitted_by_time<'a>(
+    stream_id: i32,
+    cum_recordings: i32,
+    uncommitted: impl Iterator<Item = &'a Arc<Mutex<RecordingToInsert>>>,
+    open_id: Option<u32>,
+    desired_time: &Range<recording::Time>,
+    first_id: i32,
+    f: &mut dyn FnMut(ListRecordingsRow) -> Result<(), base::Error>,
+) -> Result<(), base::Error> {
+    for (i, u) in uncommitted.enumerate() {
+        let id = cum_recordings + i as i32;
+        if id < first_id {
+            continue;
+        }
+        let row = {
+            let l = u.lock().unwrap();
+            if l.video_samples > 0 {
+                let end = l.start + recording::Duration(l.wall_duration_90k as i64);
+                if l.start > desired_time.end || end < desired_time.start {
+                    continue; // there's no overlap with the requested range.
+                }
+                l.to_list_row(CompositeId::new(stream_id, id), open_id.unwrap())
+            } else {
+                continue;
+            }
+        };
+        f(row)?;
+    }
+    Ok(())
 }
-fn operation() -> &'static str {
-    "database operation"
+
+/// A snapshot of cameras and uncommitted recordings for read paths which shouldn't wait for the
+/// database lock, as returned by [`Database::read_index`].
+///
+/// [`LockedDatabase`] publishes a new snapshot after each relevant change. Uncommitted recordings
+/// are shared rather than copied, so a growing recording's latest state is visible.
+#[derive(Default)]
+pub struct ReadIndex {
+    open_id: Option<u32>,
+    cameras_by_uuid: FastHashMap<Uuid, IndexedCamera>,
+    streams_by_id: FastHashMap<i32, IndexedStream>,
 }
 
-impl<C: Clocks + Clone> Database<C> {
-    /// Creates the database from a caller-supplied SQLite connection.
-    pub fn new(
-        clocks: C,
-        mut conn: rusqlite::Connection,
-        read_write: bool,
-    ) -> Result<Database<C>, Error> {
-        check_sqlite_version()?;
-        set_integrity_pragmas(&mut conn)?;
-        check_schema_version(&conn)?;
+/// A camera within a [`ReadIndex`].
+#[derive(Clone, Debug)]
+pub struct IndexedCamera {
+    pub id: i32,
+    pub short_name: String,
+    pub streams: [Option<i32>; NUM_STREAM_TYPES],
+}
 
-        // Note: the meta check comes after the version check to improve the error message when
-        // trying to open a version 0 or version 1 database (which lacked the meta table).
-        let (db_uuid, config) = raw::read_meta(&conn)?;
-        let open_monotonic = recording::Time::new(clocks.monotonic());
-        let open = if read_write {
-            let real = recording::Time::new(clocks.realtime());
-            let mut stmt = conn
-                .prepare(" insert into open (uuid, start_time_90k, boot_uuid) values (?, ?, ?)")?;
-            let open_uuid = SqlUuid(Uuid::new_v4());
-            let boot_uuid = match get_boot_uuid() {
-                Err(e) => {
-                    warn!(err = %e.chain(), "unable to get boot uuid");
-                    None
-                }
-                Ok(id) => id.map(SqlUuid),
-            };
-            stmt.execute(params![open_uuid, real.0, boot_uuid])?;
+struct IndexedStream {
+    cum_recordings: i32,
+    uncommitted: Vec<Arc<Mutex<RecordingToInsert>>>,
+}
+
+impl ReadIndex {
+    pub fn get_camera(&self, uuid: Uuid) -> Option<&IndexedCamera> {
+        self.cameras_by_uuid.get(&uuid)
+    }
+
+    /// Lists recordings as in [`LockedDatabase::list_recordings_by_time`], using `conn` for
+    /// committed recordings.
+    fn list_recordings_by_time(
+        &self,
+        conn: &rusqlite::Connection,
+        stream_id: i32,
+        desired_time: Range<recording::Time>,
+        f: &mut dyn FnMut(ListRecordingsRow) -> Result<(), base::Error>,
+    ) -> Result<(), base::Error> {
+        let Some(s) = self.streams_by_id.get(&stream_id) else {
+            bail!(NotFound, msg("no such stream {stream_id}"));
+        };
+
+        // Recordings may have been committed since this snapshot was published. Flushes commit
+        // before publishing, so `conn` returns any such recordings; skip them below.
+        let mut first_uncommitted = s.cum_recordings;
+        raw::list_recordings_by_time(conn, stream_id, desired_time.clone(), &mut |row| {
+            first_uncommitted = cmp::max(first_uncommitted, row.id.recording() + 1);
+            f(row)
+        })?;
+        list_uncommitted_by_time(
+            stream_id,
+            s.cum_recordings,
+            s.uncommitted.iter(),
+            self.open_id,
+            &desired_time,
+            first_uncommitted,
+            f,
+        )
+    }
+}
+
+/// Aggregates consecutive recordings, as described at
+/// [`LockedDatabase::list_aggregated_recordings`]. `list` should supply recordings as
+/// [`LockedDatabase::list_recordings_by_time`] does.
+fn aggregate_recordings(
+    list: &mut dyn FnMut(
+        &mut dyn FnMut(ListRecordingsRow) -> Result<(), base::Error>,
+    ) -> Result<(), base::Error>,
+    forced_split: recording::Duration,
+    f: &mut dyn FnMut(ListAggregatedRecordingsRow) -> Result<(), base::Error>,
+) -> Result<(), base::Error> {
+    // Iterate, maintaining a map from a recording_id to the aggregated row for the latest
+    // batch of recordings from the run starting at that id. Runs can be split into multiple
+    // batches for a few reasons:
+    //
+    // * forced split (when exceeding a duration limit)
+    // * a missing id (one that was deleted out of order)
+    //
+    // A video_sample_entry mismatch (if the parameters changed during a RTSP session) doesn't
+    // split the batch; `.mp4` files can switch sample entries mid-track. It's noted in
+    // `video_sample_entry_changes` instead.
+    //
+    // This iteration works because in a run, the start_time+duration of recording id r
+    // is equal to the start_time of recording id r+1. Thus ascending times guarantees
+    // ascending ids within a run. (Different runs, however, can be arbitrarily interleaved if
+    // their timestamps overlap. Tracking all active runs prevents that interleaving from
+    // causing problems.) list_recordings_by_time also returns uncommitted recordings in
+    // ascending order by id, and after any committed recordings with lower ids.
+    let mut aggs: BTreeMap<i32, ListAggregatedRecordingsRow> = BTreeMap::new();
+    list(&mut |row| {
+        let recording_id = row.id.recording();
+        let run_start_id = recording_id - row.run_offset;
+        let uncommitted = (row.flags & RecordingFlags::Uncommitted as i32) != 0;
+        let growing = (row.flags & RecordingFlags::Growing as i32) != 0;
+        let has_trailing_zero = (row.flags & RecordingFlags::TrailingZero as i32) != 0;
+        use std::collections::btree_map::Entry;
+        match aggs.entry(run_start_id) {
+            Entry::Occupied(mut e) => {
+                let a = e.get_mut();
+                let new_dur =
+                    a.time.end - a.time.start + recording::Duration(row.wall_duration_90k as i64);
+                let needs_flush = a.ids.end != recording_id || new_dur >= forced_split;
+                if needs_flush {
+                    // flush then start a new entry.
+                    f(std::mem::replace(a, ListAggregatedRecordingsRow::from(row)))?;
+                } else {
+                    // append.
+                    if a.time.end != row.start {
+                        bail!(
+                            Internal,
+                            msg(
+                                "stream {} recording {} ends at {} but {} starts at {}",
+                                row.id.stream(),
+                                a.ids.end - 1,
+                                a.time.end,
+                                row.id,
+                                row.start,
+                            ),
+                        );
+                    }
+                    if a.open_id != row.open_id {
+                        bail!(
+                            Internal,
+                            msg(
+                                "stream {} recording {} has open id {} but {} has {}",
+                                row.id.stream(),
+                                a.ids.end - 1,
+                                a.open_id,
+                                row.id,
+                                row.open_id,
+                            ),
+                        );
+                    }
+                    if row.video_sample_entry_id != a.last_video_sample_entry_id() {
+                        a.video_sample_entry_changes.push(VideoSampleEntryChange {
+                            recording_id,
+                            start: row.start,
+                            video_sample_entry_id: row.video_sample_entry_id,
+                        });
+                    }
+                    a.time.end.0 += row.wall_duration_90k as i64;
+                    a.ids.end = recording_id + 1;
+                    a.video_samples += row.video_samples as i64;
+                    a.video_sync_samples += row.video_sync_samples as i64;
+                    a.sample_file_bytes += row.sample_file_bytes as i64;
+                    a.packets_lost += i64::from(row.packets_lost);
+                    a.damaged_frames += i64::from(row.damaged_frames);
+                    if uncommitted {
+                        a.first_uncommitted = a.first_uncommitted.or(Some(recording_id));
+                    }
+                    a.growing = growing;
+                    a.has_trailing_zero = has_trailing_zero;
+                    a.end_reason = row.end_reason;
+                }
+            }
+            Entry::Vacant(e) => {
+                e.insert(ListAggregatedRecordingsRow::from(row));
+            }
+        }
+        Ok(())
+    })?;
+    for a in aggs.into_values() {
+        f(a)?;
+    }
+    Ok(())
+}
+
+// Helpers for Database::lock(). Closures don't implement Fn.
+/// Lock waits and holds at least this long are logged, along with the caller of
+/// [`Database::lock`].
+const SLOW_LOCK: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// SQLite statements which take at least this long to execute are logged.
+const SLOW_STATEMENT: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Statement durations across all connections opened via [`Database::new`].
+///
+/// This is process-wide because SQLite's profiling callback can't carry state.
+static STATEMENT_LATENCY: dir::AtomicHistogram = dir::AtomicHistogram::new();
+
+/// Records a statement's execution time; installed via [`rusqlite::Connection::profile`].
+fn profile_statement(sql: &str, elapsed: std::time::Duration) {
+    STATEMENT_LATENCY.record(elapsed);
+    if elapsed >= SLOW_STATEMENT {
+        let sql = sql.split_whitespace().join(" ");
+        warn!(%sql, "SQLite statement took {elapsed:?}");
+    }
+}
+
+/// Statistics on use of the database lock and SQLite statement execution, as returned by
+/// [`Database::stats`].
+#[derive(Clone, Debug, Default)]
+pub struct DatabaseStats {
+    /// Time spent waiting to acquire the database lock.
+    pub lock_wait: dir::LatencyHistogram,
+
+    /// Time the database lock was held.
+    pub lock_hold: dir::LatencyHistogram,
+
+    /// Time spent executing SQLite statements (process-wide).
+    pub statements: dir::LatencyHistogram,
+}
+
+#[derive(Debug, Default)]
+struct LockStats {
+    wait: dir::AtomicHistogram,
+    hold: dir::AtomicHistogram,
+}
+
+impl<C: Clocks + Clone> Database<C> {
+    /// Creates the database from a caller-supplied SQLite connection.
+    pub fn new(
+        clocks: C,
+        conn: rusqlite::Connection,
+        read_write: bool,
+    ) -> Result<Database<C>, Error> {
+        Self::open(clocks, conn, read_write, false)
+    }
+
+    /// Creates the database as in [`Database::new`], but without reading every stream's
+    /// committed recordings, which can take minutes with a large database. Each stream's `range`
+    /// is available immediately; its other totals and days map are incomplete until
+    /// [`Database::load_recordings`] finishes. See [`Stream::loaded`].
+    pub fn new_lazy(
+        clocks: C,
+        conn: rusqlite::Connection,
+        read_write: bool,
+    ) -> Result<Database<C>, Error> {
+        Self::open(clocks, conn, read_write, true)
+    }
+
+    fn open(
+        clocks: C,
+        mut conn: rusqlite::Connection,
+        read_write: bool,
+        lazy: bool,
+    ) -> Result<Database<C>, Error> {
+        check_sqlite_version()?;
+        set_integrity_pragmas(&mut conn)?;
+        check_schema_version(&conn)?;
+        conn.profile(Some(profile_statement));
+
+        // Note: the meta check comes after the version check to improve the error message when
+        // trying to open a version 0 or version 1 database (which lacked the meta table).
+        let (db_uuid, config) = raw::read_meta(&conn)?;
+        let open_monotonic = recording::Time::new(clocks.monotonic());
+        let open = if read_write {
+            let real = recording::Time::new(clocks.realtime());
+            let mut stmt = conn
+                .prepare(" insert into open (uuid, start_time_90k, boot_uuid) values (?, ?, ?)")?;
+            let open_uuid = SqlUuid(Uuid::new_v4());
+            let boot_uuid = match get_boot_uuid() {
+                Err(e) => {
+                    warn!(err = %e.chain(), "unable to get boot uuid");
+                    None
+                }
+                Ok(id) => id.map(SqlUuid),
+            };
+            stmt.execute(params![open_uuid, real.0, boot_uuid])?;
             let id = conn.last_insert_rowid() as u32;
             Some(Open {
                 id,
@@ -2368,8 +3737,19 @@ impl<C: Clocks + Clone> Database<C> {
         } else {
             None
         };
-        let auth = auth::State::init(&conn)?;
+        let auth = auth::State::init(&conn, &config)?;
         let signal = signal::State::init(&conn, &config)?;
+        let credentials_encrypted = conn
+            .query_row("select 1 from credential_key", params![], |_| Ok(()))
+            .optional()?
+            .is_some();
+        let sample_files_encrypted = conn
+            .query_row("select 1 from sample_file_key", params![], |_| Ok(()))
+            .optional()?
+            .is_some();
+        let read_index = Arc::new(Mutex::new(Arc::new(ReadIndex::default())));
+        let aggregates = Arc::new(aggregates::Cache::default());
+        let stream_dirs_generation = Arc::new(AtomicU64::new(0));
         let db = Database {
             db: Some(Mutex::new(LockedDatabase {
                 conn,
@@ -2379,18 +3759,34 @@ impl<C: Clocks + Clone> Database<C> {
                 open_monotonic,
                 auth,
                 signal,
+                event: event::State::default(),
                 sample_file_dirs_by_id: BTreeMap::new(),
                 cameras_by_id: BTreeMap::new(),
                 cameras_by_uuid: BTreeMap::new(),
                 streams_by_id: BTreeMap::new(),
                 video_sample_entries_by_id: BTreeMap::new(),
+                video_sample_entry_aliases: FastHashMap::default(),
+                issued_video_sample_entries: FastHashSet::default(),
                 video_index_cache: RefCell::new(LinkedHashMap::with_capacity_and_hasher(
                     VIDEO_INDEX_CACHE_LEN + 1,
                     Default::default(),
                 )),
                 on_flush: Vec::new(),
+                credentials_encrypted,
+                credential_key: None,
+                sample_files_encrypted,
+                sample_file_key: None,
+                read_index: read_index.clone(),
+                aggregates: aggregates.clone(),
+                stream_dirs_generation: stream_dirs_generation.clone(),
+                time_zone_name: String::new(),
             })),
             clocks,
+            lock_stats: LockStats::default(),
+            read_index,
+            aggregates,
+            readers: Mutex::new(Vec::new()),
+            stream_dirs_generation,
         };
         {
             let l = &mut *db.lock();
@@ -2399,33 +3795,187 @@ impl<C: Clocks + Clone> Database<C> {
             l.init_cameras()?;
             l.init_streams()?;
             for (&stream_id, ref mut stream) in &mut l.streams_by_id {
-                // TODO: we could use one thread per stream if we had multiple db conns.
+                if lazy {
+                    stream.range = raw::get_range(&l.conn, stream_id)?;
+                    continue;
+                }
                 let camera = l.cameras_by_id.get(&stream.camera_id).unwrap();
-                init_recordings(&mut l.conn, stream_id, camera, stream)?;
+                init_recordings(&l.conn, stream_id, camera, stream)?;
             }
+            l.publish();
         }
         Ok(db)
     }
 
+    /// Loads the committed recordings of streams not yet loaded, as after [`Database::new_lazy`].
+    ///
+    /// This reads via `conn`, a separate connection to the same database, holding the database
+    /// lock only briefly at the start and after each stream. Recording and serving continue
+    /// meanwhile.
+    pub fn load_recordings(&self, mut conn: rusqlite::Connection) -> Result<(), Error> {
+        let tx = conn.transaction()?;
+        let streams: Vec<(i32, String)> = {
+            let mut l = self.lock();
+            let l = &mut *l;
+
+            // Start the read transaction's snapshot while holding the lock, so it includes
+            // exactly the flushes so far. Later flushes adjust the totals as usual; clear the
+            // earlier adjustments, which the snapshot already includes.
+            tx.query_row("select 1 from meta", params![], |_| Ok(()))?;
+            let mut streams = Vec::new();
+            for (&id, s) in l.streams_by_id.iter_mut().filter(|(_, s)| !s.loaded) {
+                s.duration = recording::Duration(0);
+                s.sample_file_bytes = 0;
+                s.fs_bytes = 0;
+                s.committed_days = days::Map::default();
+                let camera = &l.cameras_by_id[&s.camera_id];
+                streams.push((id, format!("{}-{}", camera.short_name, s.type_)));
+            }
+            streams
+        };
+        for (id, name) in streams {
+            let t = read_recording_totals(&tx, id)?;
+            info!("Loaded {} recordings for stream {name}", t.recordings);
+            let mut l = self.lock();
+            if let Some(s) = l.streams_by_id.get_mut(&id) {
+                s.add_totals(t);
+                s.loaded = true;
+            }
+        }
+        Ok(())
+    }
+
     #[inline(always)]
     pub fn clocks(&self) -> C {
         self.clocks.clone()
     }
 
+    /// Returns a number which changes whenever a stream's sample file directory may have
+    /// changed, for callers which cache each stream's directory. This doesn't take the lock.
+    pub fn stream_dirs_generation(&self) -> u64 {
+        self.stream_dirs_generation.load(Ordering::Acquire)
+    }
+
     /// Locks the database; the returned reference is the only way to perform (read or write)
     /// operations.
+    ///
+    /// Slow lock acquisitions and long holds are logged with the caller's location.
+    #[track_caller]
     pub fn lock(&self) -> DatabaseGuard<C> {
-        let timer = clock::TimerGuard::new(&self.clocks, acquisition);
+        let caller = std::panic::Location::caller();
+        let start = std::time::Instant::now();
         let db = self.db.as_ref().unwrap().lock().unwrap();
-        drop(timer);
-        let _timer = clock::TimerGuard::<C, &'static str, fn() -> &'static str>::new(
-            &self.clocks,
-            operation,
-        );
+        let locked = std::time::Instant::now();
+        let wait = locked - start;
+        self.lock_stats.wait.record(wait);
+        if wait >= SLOW_LOCK {
+            warn!(%caller, "database lock acquisition took {wait:?}");
+        }
         DatabaseGuard {
             clocks: &self.clocks,
             db,
-            _timer,
+            stats: &self.lock_stats,
+            caller,
+            locked,
+        }
+    }
+
+    /// Adds a connection which read paths can use without holding the database lock.
+    ///
+    /// The connection must be to the same database file, in a mode which sees commits made by
+    /// the primary connection as they happen: WAL mode with a VFS which shares the wal-index.
+    /// Each connection serves one read at a time; reads fall back to the primary connection when
+    /// none are idle.
+    pub fn add_reader(&self, mut conn: rusqlite::Connection) -> Result<(), Error> {
+        conn.profile(Some(profile_statement));
+        conn.execute_batch("pragma query_only = true;")?;
+        self.readers.lock().unwrap().push(conn);
+        Ok(())
+    }
+
+    /// Returns the latest [`ReadIndex`] without waiting for the database lock.
+    pub fn read_index(&self) -> Arc<ReadIndex> {
+        self.read_index.lock().unwrap().clone()
+    }
+
+    /// Lists recordings as in [`LockedDatabase::list_recordings_by_time`].
+    ///
+    /// If a reader connection is idle (see [`Database::add_reader`]), this doesn't wait for the
+    /// database lock, so it isn't delayed by a concurrent flush.
+    pub fn list_recordings_by_time(
+        &self,
+        stream_id: i32,
+        desired_time: Range<recording::Time>,
+        f: &mut dyn FnMut(ListRecordingsRow) -> Result<(), base::Error>,
+    ) -> Result<(), base::Error> {
+        let Some(conn) = self.readers.lock().unwrap().pop() else {
+            return self
+                .lock()
+                .list_recordings_by_time(stream_id, desired_time, f);
+        };
+        let result = self
+            .read_index()
+            .list_recordings_by_time(&conn, stream_id, desired_time, f);
+        self.readers.lock().unwrap().push(conn);
+        result
+    }
+
+    /// Lists aggregated recordings as in [`LockedDatabase::list_aggregated_recordings`], using
+    /// [`Database::list_recordings_by_time`].
+    ///
+    /// Results involving only committed recordings are cached until a flush changes recordings
+    /// in an overlapping time range.
+    pub fn list_aggregated_recordings(
+        &self,
+        stream_id: i32,
+        desired_time: Range<recording::Time>,
+        forced_split: recording::Duration,
+        f: &mut dyn FnMut(ListAggregatedRecordingsRow) -> Result<(), base::Error>,
+    ) -> Result<(), base::Error> {
+        if let Some(rows) = self.aggregates.get(stream_id, &desired_time, forced_split) {
+            for row in rows.iter() {
+                f(row.clone())?;
+            }
+            return Ok(());
+        }
+
+        // Results which may involve uncommitted recordings change without a flush, so they
+        // aren't cached. A growing recording's start may move earlier, so this is conservative.
+        let generation = self.aggregates.generation(stream_id);
+        let cacheable = self
+            .read_index()
+            .streams_by_id
+            .get(&stream_id)
+            .map(|s| {
+                s.uncommitted
+                    .iter()
+                    .all(|u| u.lock().unwrap().start - UNCOMMITTED_START_SLACK > desired_time.end)
+            })
+            .unwrap_or(false);
+        let mut rows = Vec::new();
+        aggregate_recordings(
+            &mut |g| self.list_recordings_by_time(stream_id, desired_time.clone(), g),
+            forced_split,
+            &mut |row| {
+                if cacheable {
+                    rows.push(row.clone());
+                }
+                f(row)
+            },
+        )?;
+        if cacheable {
+            self.aggregates
+                .insert(stream_id, &desired_time, forced_split, generation, rows);
+        }
+        Ok(())
+    }
+
+    /// Returns statistics on lock use and statement execution.
+    pub fn stats(&self) -> DatabaseStats {
+        DatabaseStats {
+            lock_wait: self.lock_stats.wait.snapshot(),
+            lock_hold: self.lock_stats.hold.snapshot(),
+            statements: STATEMENT_LATENCY.snapshot(),
         }
     }
 
@@ -2441,7 +3991,19 @@ impl<C: Clocks + Clone> Database<C> {
 pub struct DatabaseGuard<'db, C: Clocks> {
     clocks: &'db C,
     db: MutexGuard<'db, LockedDatabase>,
-    _timer: clock::TimerGuard<'db, C, &'static str, fn() -> &'static str>,
+    stats: &'db LockStats,
+    caller: &'static std::panic::Location<'static>,
+    locked: std::time::Instant,
+}
+
+impl<'db, C: Clocks> Drop for DatabaseGuard<'db, C> {
+    fn drop(&mut self) {
+        let hold = self.locked.elapsed();
+        self.stats.hold.record(hold);
+        if hold >= SLOW_LOCK {
+            warn!(caller = %self.caller, "database lock held for {hold:?}");
+        }
+    }
 }
 
 impl<'db, C: Clocks + Clone> DatabaseGuard<'db, C> {
@@ -2454,7 +4016,7 @@ impl<'db, C: Clocks + Clone> DatabaseGuard<'db, C> {
     ///
     /// On success, for each affected sample file directory with a flush watcher set, sends a
     /// `Flush` event.
-    pub(crate) fn flush(&mut self, reason: &str) -> Result<(), Error> {
+    pub fn flush(&mut self, reason: &str) -> Result<(), Error> {
         self.db.flush(self.clocks, reason)
     }
 }
@@ -2539,6 +4101,14 @@ mod tests {
         }
 
         // TODO(slamb): test that the days logic works correctly.
+        {
+            // Rebuilding the days maps, as after a time zone change, should match the
+            // incrementally maintained ones.
+            let mut db = db.lock();
+            let days = db.streams_by_id().get(&stream_id).unwrap().days();
+            db.rebuild_days().unwrap();
+            assert_eq!(days, db.streams_by_id().get(&stream_id).unwrap().days());
+        }
 
         let mut rows = 0;
         let mut recording_id = None;
@@ -2601,13 +4171,13 @@ mod tests {
     fn test_version_too_old() {
         testutil::init();
         let c = setup_conn();
-        c.execute_batch("delete from version; insert into version values (6, 0, '');")
+        c.execute_batch("delete from version; insert into version values (7, 0, '');")
             .unwrap();
         let e = Database::new(clock::RealClocks {}, c, false).err().unwrap();
         assert!(
             e.msg()
                 .unwrap()
-                .starts_with("database schema version 6 is too old (expected 7)"),
+                .starts_with("database schema version 7 is too old (expected 8)"),
             "got: {e:?}"
         );
     }
@@ -2616,13 +4186,13 @@ mod tests {
     fn test_version_too_new() {
         testutil::init();
         let c = setup_conn();
-        c.execute_batch("delete from version; insert into version values (8, 0, '');")
+        c.execute_batch("delete from version; insert into version values (9, 0, '');")
             .unwrap();
         let e = Database::new(clock::RealClocks {}, c, false).err().unwrap();
         assert!(
             e.msg()
                 .unwrap()
-                .starts_with("database schema version 8 is too new (expected 7)"),
+                .starts_with("database schema version 9 is too new (expected 8)"),
             "got: {e:?}"
         );
     }
@@ -2638,6 +4208,191 @@ mod tests {
     }
 
     /// Basic test of the full lifecycle of recording. Does not exercise error cases.
+    /// Tests listing recordings via a reader connection, without the database lock.
+    #[test]
+    fn reader() {
+        testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let db_path = tmpdir.path().join("db");
+        let mut conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch("pragma journal_mode = wal;").unwrap();
+        super::init(&mut conn).unwrap();
+        let db = Database::new(clock::RealClocks {}, conn, true).unwrap();
+        let reader =
+            Connection::open_with_flags(&db_path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .unwrap();
+        db.add_reader(reader).unwrap();
+        let sample_file_dir_id = { db.lock() }
+            .add_sample_file_dir(tmpdir.path().join("sample"))
+            .unwrap();
+        let camera_id = db
+            .lock()
+            .add_camera(CameraChange {
+                short_name: "testcam".to_owned(),
+                config: crate::json::CameraConfig::default(),
+                streams: [
+                    StreamChange {
+                        sample_file_dir_id: Some(sample_file_dir_id),
+                        config: crate::json::StreamConfig {
+                            mode: crate::json::STREAM_MODE_RECORD.to_owned(),
+                            ..Default::default()
+                        },
+                    },
+                    StreamChange::default(),
+                    StreamChange::default(),
+                ],
+            })
+            .unwrap();
+        let uuid = db.lock().cameras_by_id().get(&camera_id).unwrap().uuid;
+        let index = db.read_index();
+        let camera = index.get_camera(uuid).unwrap();
+        assert_eq!(camera.short_name, "testcam");
+        let stream_id = camera.streams[0].unwrap();
+        let vse_id = db
+            .lock()
+            .insert_video_sample_entry(VideoSampleEntryToInsert {
+                width: 1920,
+                height: 1080,
+                pasp_h_spacing: 1,
+                pasp_v_spacing: 1,
+                data: include_bytes!("testdata/avc1").to_vec(),
+                rfc6381_codec: "avc1.4d0029".to_owned(),
+            })
+            .unwrap();
+        let start = recording::Time(1430006400 * TIME_UNITS_PER_SEC);
+        let (id, _) = db
+            .lock()
+            .add_recording(
+                stream_id,
+                RecordingToInsert {
+                    start,
+                    wall_duration_90k: TIME_UNITS_PER_SEC.try_into().unwrap(),
+                    media_duration_90k: TIME_UNITS_PER_SEC.try_into().unwrap(),
+                    video_samples: 1,
+                    video_sync_samples: 1,
+                    video_sample_entry_id: vse_id,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let list = || {
+            let mut ids = Vec::new();
+            db.list_recordings_by_time(
+                stream_id,
+                start..start + recording::Duration(1),
+                &mut |r| {
+                    ids.push(r.id);
+                    Ok(())
+                },
+            )
+            .unwrap();
+            ids
+        };
+
+        // The uncommitted recording comes from the index, and the committed one from the reader,
+        // exactly once either way.
+        assert_eq!(list(), vec![id]);
+        db.lock().mark_synced(id).unwrap();
+        db.lock().flush("reader test").unwrap();
+        assert_eq!(list(), vec![id]);
+        assert_eq!(
+            db.read_index()
+                .streams_by_id
+                .get(&stream_id)
+                .unwrap()
+                .cum_recordings,
+            1
+        );
+    }
+
+    /// Tests a flush which inserts more recordings than fit in a single batch, across streams.
+    #[test]
+    fn flush_many() {
+        testutil::init();
+        let db = Database::new(clock::RealClocks {}, setup_conn(), true).unwrap();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let sample_file_dir_id = { db.lock() }
+            .add_sample_file_dir(tmpdir.path().to_owned())
+            .unwrap();
+        let record = StreamChange {
+            sample_file_dir_id: Some(sample_file_dir_id),
+            config: crate::json::StreamConfig {
+                mode: crate::json::STREAM_MODE_RECORD.to_owned(),
+                ..Default::default()
+            },
+        };
+        let mut l = db.lock();
+        let camera_id = l
+            .add_camera(CameraChange {
+                short_name: "testcam".to_owned(),
+                config: crate::json::CameraConfig::default(),
+                streams: [record.clone(), record, StreamChange::default()],
+            })
+            .unwrap();
+        let streams = l.cameras_by_id().get(&camera_id).unwrap().streams;
+        let vse_id = l
+            .insert_video_sample_entry(VideoSampleEntryToInsert {
+                width: 1920,
+                height: 1080,
+                pasp_h_spacing: 1,
+                pasp_v_spacing: 1,
+                data: include_bytes!("testdata/avc1").to_vec(),
+                rfc6381_codec: "avc1.4d0029".to_owned(),
+            })
+            .unwrap();
+        const N: i32 = 50;
+        for stream_id in streams.iter().take(2).map(|s| s.unwrap()) {
+            for i in 0..N {
+                let (id, _) = l
+                    .add_recording(
+                        stream_id,
+                        RecordingToInsert {
+                            run_offset: i,
+                            start: recording::Time(
+                                1430006400 * TIME_UNITS_PER_SEC + i64::from(i) * TIME_UNITS_PER_SEC,
+                            ),
+                            wall_duration_90k: TIME_UNITS_PER_SEC.try_into().unwrap(),
+                            media_duration_90k: TIME_UNITS_PER_SEC.try_into().unwrap(),
+                            video_samples: 1,
+                            video_sync_samples: 1,
+                            video_sample_entry_id: vse_id,
+                            video_index: vec![i as u8],
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+                l.mark_synced(id).unwrap();
+            }
+        }
+        l.flush("flush_many test").unwrap();
+        for stream_id in streams.iter().take(2).map(|s| s.unwrap()) {
+            let s = l.streams_by_id().get(&stream_id).unwrap();
+            assert_eq!(s.cum_recordings, N);
+            assert_eq!(s.cum_runs, 1);
+            let mut n = 0;
+            l.list_recordings_by_id(stream_id, 0..N, &mut |r| {
+                assert_eq!(r.id, CompositeId::new(stream_id, n));
+                assert_eq!(r.run_offset, n);
+                n += 1;
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(n, N);
+        }
+        let playback = l
+            .with_recording_playback(CompositeId::new(streams[1].unwrap(), N - 1), &mut |p| {
+                Ok(p.video_index.to_vec())
+            })
+            .unwrap();
+        assert_eq!(playback, vec![(N - 1) as u8]);
+    }
+
     #[test]
     fn test_full_lifecycle() {
         testutil::init();
@@ -2787,6 +4542,9 @@ mod tests {
             video_index: [0u8; 100].to_vec(),
             sample_file_blake3: None,
             end_reason: None,
+            packets_lost: 0,
+            damaged_frames: 0,
+            rtsp_session: None,
         };
         let id = {
             let mut db = db.lock();
@@ -2872,6 +4630,480 @@ mod tests {
         assert_eq!(&g, &[]);
     }
 
+    #[test]
+    fn lazy_load() {
+        testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let path = tmpdir.path().join("db");
+        let mut conn = Connection::open(&path).unwrap();
+        super::init(&mut conn).unwrap();
+        let db = Database::new(clock::RealClocks {}, conn, true).unwrap();
+        let sample_file_dir_id = { db.lock() }
+            .add_sample_file_dir(tmpdir.path().join("sample"))
+            .unwrap();
+        let camera_id = db
+            .lock()
+            .add_camera(CameraChange {
+                short_name: "testcam".to_owned(),
+                config: crate::json::CameraConfig::default(),
+                streams: [
+                    StreamChange {
+                        sample_file_dir_id: Some(sample_file_dir_id),
+                        config: crate::json::StreamConfig {
+                            mode: crate::json::STREAM_MODE_RECORD.to_owned(),
+                            ..Default::default()
+                        },
+                    },
+                    StreamChange::default(),
+                    StreamChange::default(),
+                ],
+            })
+            .unwrap();
+        let stream_id = db.lock().cameras_by_id()[&camera_id].streams[0].unwrap();
+        let vse_id = db
+            .lock()
+            .insert_video_sample_entry(VideoSampleEntryToInsert {
+                width: 1920,
+                height: 1080,
+                pasp_h_spacing: 1,
+                pasp_v_spacing: 1,
+                data: include_bytes!("testdata/avc1").to_vec(),
+                rfc6381_codec: "avc1.4d0029".to_owned(),
+            })
+            .unwrap();
+        let mut recording = RecordingToInsert {
+            sample_file_bytes: 42,
+            start: recording::Time(1430006400 * TIME_UNITS_PER_SEC),
+            wall_duration_90k: TIME_UNITS_PER_SEC.try_into().unwrap(),
+            media_duration_90k: TIME_UNITS_PER_SEC.try_into().unwrap(),
+            video_samples: 1,
+            video_sync_samples: 1,
+            video_sample_entry_id: vse_id,
+            video_index: [0u8; 100].to_vec(),
+            ..Default::default()
+        };
+        let add = |db: &Database, r: &RecordingToInsert| {
+            let mut l = db.lock();
+            let (id, _) = l.add_recording(stream_id, r.clone()).unwrap();
+            l.mark_synced(id).unwrap();
+            l.flush("add test").unwrap();
+        };
+        add(&db, &recording);
+        recording.start += recording::Duration(TIME_UNITS_PER_SEC);
+        add(&db, &recording);
+        let (eager_duration, eager_days) = {
+            let l = db.lock();
+            let s = &l.streams_by_id()[&stream_id];
+            assert!(s.loaded);
+            (s.duration, s.days())
+        };
+
+        // A lazily opened database knows the range but not yet the totals.
+        let conn = db.close();
+        let db = Database::new_lazy(clock::RealClocks {}, conn, true).unwrap();
+        {
+            let mut l = db.lock();
+            let s = &l.streams_by_id()[&stream_id];
+            assert!(!s.loaded);
+            assert_eq!(
+                s.range.as_ref().unwrap().start.0,
+                1430006400 * TIME_UNITS_PER_SEC
+            );
+            assert_eq!(s.duration, recording::Duration(0));
+            l.rebuild_days().unwrap_err();
+        }
+
+        // Recordings added before loading are counted once.
+        recording.start += recording::Duration(TIME_UNITS_PER_SEC);
+        add(&db, &recording);
+        db.load_recordings(Connection::open(&path).unwrap())
+            .unwrap();
+        let mut l = db.lock();
+        let s = &l.streams_by_id()[&stream_id];
+        assert!(s.loaded);
+        assert_eq!(
+            s.duration,
+            eager_duration + recording::Duration(TIME_UNITS_PER_SEC)
+        );
+        assert_eq!(s.sample_file_bytes, 3 * 42);
+        let days = s.days();
+        assert_ne!(days, eager_days);
+        l.rebuild_days().unwrap();
+        assert_eq!(days, l.streams_by_id()[&stream_id].days());
+    }
+
+    #[test]
+    fn credential_encryption() {
+        testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let path = tmpdir.path().join("db");
+        let mut conn = Connection::open(&path).unwrap();
+        super::init(&mut conn).unwrap();
+        let db = Database::new(clock::RealClocks {}, conn, true).unwrap();
+        let c = CameraChange {
+            short_name: "testcam".to_owned(),
+            config: crate::json::CameraConfig {
+                username: "foo".to_owned(),
+                password: "bar".to_owned(),
+                ..Default::default()
+            },
+            streams: Default::default(),
+        };
+        let camera_id = db.lock().add_camera(c.clone()).unwrap();
+        let stored_config = |db: &Database| -> String {
+            db.lock()
+                .conn
+                .query_row(
+                    "select config from camera where id = ?",
+                    params![camera_id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert!(stored_config(&db).contains("bar"));
+
+        // Enabling encryption encrypts the existing credentials.
+        let (key, _) = credential::Key::generate().unwrap();
+        db.lock().set_credential_key(&key).unwrap();
+        let stored = stored_config(&db);
+        assert!(!stored.contains("bar"), "{stored}");
+        assert!(stored.contains("encryptedCredentials"), "{stored}");
+        assert_eq!(db.lock().cameras_by_id()[&camera_id].config.password, "bar");
+
+        // Later writes are encrypted as well.
+        let mut c2 = c.clone();
+        c2.config.password = "baz".to_owned();
+        db.lock().update_camera(camera_id, c2).unwrap();
+        assert!(!stored_config(&db).contains("baz"));
+
+        // Reopen; the credentials are locked until the key is supplied again.
+        drop(db);
+        let conn = Connection::open(&path).unwrap();
+        let db = Database::new(clock::RealClocks {}, conn, true).unwrap();
+        assert!(db.lock().has_locked_credentials());
+        assert!(db.lock().update_camera(camera_id, c).is_err());
+        let (wrong_key, _) = credential::Key::generate().unwrap();
+        assert!(db.lock().set_credential_key(&wrong_key).is_err());
+        db.lock().set_credential_key(&key).unwrap();
+        assert!(!db.lock().has_locked_credentials());
+        assert_eq!(db.lock().cameras_by_id()[&camera_id].config.password, "baz");
+    }
+
+    #[test]
+    fn sample_file_key() {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        testutil::init();
+        let key = |b: u8| dir::crypt::Key::parse(&STANDARD.encode([b; 32])).unwrap();
+        let db = Database::new(clock::RealClocks {}, setup_conn(), true).unwrap();
+        assert!(!db.lock().has_locked_sample_files());
+        db.lock().set_sample_file_key(key(1)).unwrap();
+        assert!(db.lock().sample_file_key().is_some());
+        let e = db.lock().set_sample_file_key(key(2)).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::PermissionDenied);
+        db.lock().set_sample_file_key(key(1)).unwrap();
+    }
+
+    #[test]
+    fn recent_frames() {
+        let frame = |recording, media_off_90k: Range<i32>| LiveFrame {
+            seq: 0,
+            recording,
+            bytes: 1000,
+            is_key: false,
+            media_off_90k,
+        };
+        let config = crate::json::StreamConfig::default();
+        let mut r = RecentFrames::default();
+        assert_eq!(r.after(0).map(|v| v.len()), None); // never assigned.
+        assert_eq!(r.push(&config, frame(1, 0..3000)).seq, 0);
+        assert_eq!(r.after(0).map(|v| v.len()), Some(0));
+        assert_eq!(r.push(&config, frame(1, 3000..6000)).seq, 1);
+        assert_eq!(r.after(0).unwrap()[0].seq, 1);
+
+        // Push past the default duration limit; the oldest frames are dropped.
+        let max_duration_90k = i64::from(DEFAULT_RECENT_FRAMES_SEC) * TIME_UNITS_PER_SEC;
+        let mut off = 6000;
+        while i64::from(off) < 6000 + max_duration_90k {
+            r.push(&config, frame(2, off..off + 3000));
+            off += 3000;
+        }
+        assert!(r.after(0).is_none());
+        let first = r.frames.front().unwrap().seq;
+        assert_eq!(r.after(first - 1).unwrap().len(), r.frames.len());
+        assert!(r.duration_90k <= max_duration_90k);
+        assert_eq!(r.last(0).len(), 0);
+        assert_eq!(r.last(1).len(), 1);
+        assert_eq!(r.last(6000).len(), 2);
+        assert_eq!(r.last(6001).len(), 3);
+        assert_eq!(r.last(i64::MAX).len(), r.frames.len());
+
+        // A byte limit applies too.
+        let config = crate::json::StreamConfig {
+            recent_frames_bytes: Some(10_000),
+            ..Default::default()
+        };
+        r.push(&config, frame(2, off..off + 3000));
+        assert_eq!(r.frames.len(), 10);
+        assert_eq!(r.bytes, 10_000);
+
+        // A shorter duration limit applies on the next push. The newest frame is always kept.
+        let config = crate::json::StreamConfig {
+            recent_frames_sec: Some(0),
+            ..Default::default()
+        };
+        off += 3000;
+        let last = r.push(&config, frame(2, off..off + 3000)).seq;
+        assert_eq!(r.frames.len(), 1);
+        assert_eq!(r.after(last).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn aggregate_across_video_sample_entry_change() {
+        let row = |id: i32, video_sample_entry_id: i32| ListRecordingsRow {
+            start: recording::Time(i64::from(id) * TIME_UNITS_PER_SEC),
+            video_sample_entry_id,
+            id: CompositeId::new(1, id),
+            wall_duration_90k: TIME_UNITS_PER_SEC as i32,
+            media_duration_90k: TIME_UNITS_PER_SEC as i32,
+            video_samples: 30,
+            video_sync_samples: 1,
+            sample_file_bytes: 1000,
+            run_offset: id,
+            open_id: 1,
+            flags: 0,
+            prev_media_duration_and_runs: None,
+            end_reason: None,
+            packets_lost: 0,
+            damaged_frames: 0,
+        };
+        let mut aggs = Vec::new();
+        aggregate_recordings(
+            &mut |f| {
+                for (id, vse) in [(0, 1), (1, 1), (2, 2), (3, 1)] {
+                    f(row(id, vse))?;
+                }
+                Ok(())
+            },
+            recording::Duration(i64::MAX),
+            &mut |a| {
+                aggs.push(a);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(aggs.len(), 1);
+        let a = &aggs[0];
+        assert_eq!(a.ids, 0..4);
+        assert_eq!(a.video_sample_entry_id, 1);
+        assert_eq!(
+            a.video_sample_entry_changes,
+            [
+                VideoSampleEntryChange {
+                    recording_id: 2,
+                    start: recording::Time(2 * TIME_UNITS_PER_SEC),
+                    video_sample_entry_id: 2,
+                },
+                VideoSampleEntryChange {
+                    recording_id: 3,
+                    start: recording::Time(3 * TIME_UNITS_PER_SEC),
+                    video_sample_entry_id: 1,
+                },
+            ]
+        );
+        assert_eq!(a.last_video_sample_entry_id(), 1);
+    }
+
+    #[test]
+    fn gc_video_sample_entries() {
+        testutil::init();
+        let tdb = testutil::TestDb::new(clock::RealClocks {});
+        testutil::add_dummy_recordings_to_db(&tdb.db, 1);
+        let mut db = tdb.db.lock();
+        let orig = *db.video_sample_entries_by_id().keys().next().unwrap();
+
+        // Add a duplicate of the original entry and an unrelated entry, as an older version of
+        // Moonfire NVR might have, and point the recording at the duplicate.
+        let insert = |db: &LockedDatabase, width: u16| {
+            db.conn
+                .execute(
+                    INSERT_VIDEO_SAMPLE_ENTRY_SQL,
+                    named_params! {
+                        ":width": width,
+                        ":height": 1080,
+                        ":pasp_h_spacing": 1,
+                        ":pasp_v_spacing": 1,
+                        ":rfc6381_codec": "avc1.000000",
+                        ":data": &[0u8; 100][..],
+                    },
+                )
+                .unwrap();
+            db.conn.last_insert_rowid() as i32
+        };
+        let dup = insert(&db, 1920);
+        let unused = insert(&db, 1280);
+        db.conn
+            .execute(
+                "update recording set video_sample_entry_id = ?",
+                params![dup],
+            )
+            .unwrap();
+        db.init_video_sample_entries().unwrap();
+        assert_eq!(db.video_sample_entries_by_id().len(), 3);
+
+        // While the duplicate's id is issued to a (hypothetical) streamer, it's left alone.
+        db.issued_video_sample_entries.insert(dup);
+        assert_eq!(db.gc_video_sample_entries().unwrap(), (0, 1));
+        assert!(db.video_sample_entry(unused).is_none());
+        assert_eq!(db.video_sample_entry(dup).unwrap().id, dup);
+
+        // Afterward, it's merged into the original.
+        db.issued_video_sample_entries.remove(&dup);
+        assert_eq!(db.gc_video_sample_entries().unwrap(), (1, 0));
+        assert_eq!(
+            db.video_sample_entries_by_id()
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            [orig]
+        );
+        assert_eq!(db.video_sample_entry(dup).unwrap().id, orig);
+        let mut rows = Vec::new();
+        db.list_recordings_by_time(
+            testutil::TEST_STREAM_ID,
+            recording::Time::MIN..recording::Time::MAX,
+            &mut |r| {
+                rows.push(r.video_sample_entry_id);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(rows, [orig]);
+
+        // Running again is a no-op.
+        assert_eq!(db.gc_video_sample_entries().unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn run_ids() {
+        testutil::init();
+        let tdb = testutil::TestDb::new(clock::RealClocks {});
+        let mut db = tdb.db.lock();
+        for run_offset in [0, 1, 2, 0, 1] {
+            db.add_recording(
+                testutil::TEST_STREAM_ID,
+                RecordingToInsert {
+                    run_offset,
+                    video_samples: 1,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        let s = testutil::TEST_STREAM_ID;
+        assert_eq!(db.run_ids(s, 0).unwrap(), 0..3);
+        assert_eq!(db.run_ids(s, 3).unwrap(), 3..5);
+        let e = db.run_ids(s, 1).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::NotFound);
+        assert_eq!(
+            db.run_ids(s, 5).unwrap_err().kind(),
+            base::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn rtsp_session() {
+        testutil::init();
+        let tdb = testutil::TestDb::new(clock::RealClocks {});
+        let session = Arc::new(RtspSession {
+            sdp: b"v=0\r\n".to_vec(),
+            transport: Some("TCP, interleaved channels 0-1".to_owned()),
+            server: Some("Server: Rtsp Server/3.0".to_owned()),
+        });
+        let mut r = RecordingToInsert {
+            rtsp_session: Some(session.clone()),
+            ..Default::default()
+        };
+        crate::recording::SampleIndexEncoder::default().add_sample(1, 1, true, &mut r);
+        let committed = tdb.insert_recording_from_encoder(r);
+        let mut db = tdb.db.lock();
+        assert_eq!(db.get_rtsp_session(committed.id).unwrap(), Some(session));
+
+        // A later recording in the same run has none; nor does a nonexistent one.
+        let (uncommitted, _) = db
+            .add_recording(
+                testutil::TEST_STREAM_ID,
+                RecordingToInsert {
+                    run_offset: 1,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(db.get_rtsp_session(uncommitted).unwrap(), None);
+        assert_eq!(
+            db.get_rtsp_session(CompositeId::new(testutil::TEST_STREAM_ID, 5))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn aggregated_recordings_cache() {
+        testutil::init();
+        let tdb = testutil::TestDb::new(clock::RealClocks {});
+        let list = || {
+            let mut rows = Vec::new();
+            tdb.db
+                .list_aggregated_recordings(
+                    testutil::TEST_STREAM_ID,
+                    recording::Time::MIN..recording::Time::MAX,
+                    recording::Duration(i64::MAX),
+                    &mut |r| {
+                        rows.push(r.ids);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+            rows
+        };
+        let encode = || {
+            let mut r = RecordingToInsert::default();
+            crate::recording::SampleIndexEncoder::default().add_sample(1, 1, true, &mut r);
+            tdb.insert_recording_from_encoder(r);
+        };
+        encode();
+        assert_eq!(list(), [0..1]);
+        assert_eq!(tdb.db.aggregates.stats(), (1, 1));
+        assert_eq!(list(), [0..1]);
+
+        // Flushing an overlapping recording invalidates the cached result.
+        encode();
+        assert_eq!(tdb.db.aggregates.stats(), (0, 0));
+        assert_eq!(list(), [0..1, 1..2]);
+        assert_eq!(tdb.db.aggregates.stats(), (1, 2));
+
+        // Results which may soon include an uncommitted recording aren't cached, even before it
+        // has any samples.
+        tdb.db
+            .lock()
+            .add_recording(
+                testutil::TEST_STREAM_ID,
+                RecordingToInsert {
+                    start: recording::Time(1430006400 * recording::TIME_UNITS_PER_SEC),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(tdb.db.aggregates.stats(), (0, 0));
+        assert_eq!(list(), [0..1, 1..2]);
+        assert_eq!(tdb.db.aggregates.stats(), (0, 0));
+    }
+
     #[test]
     fn round_up() {
         assert_eq!(super::round_up(0), 0);
@@ -0,0 +1,179 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception
+
+//! Storage backends for sample file directories.
+//!
+//! A [`super::SampleFileDir`] keeps its files and metadata in a [`Backend`]. The writer, syncer,
+//! reader threads, retention, and `moonfire-nvr check` all go through it rather than assuming a
+//! local POSIX directory. [`Local`] is the only backend today; see `design/storage.md` for how
+//! others, such as an object store behind a local write-back cache, would fit.
+
+use super::{CompositeIdPath, Fd};
+use crate::db::CompositeId;
+use crate::schema;
+use base::Error;
+use nix::fcntl::{AtFlags, FlockArg, OFlag};
+use nix::sys::stat::Mode;
+use std::fs;
+use std::path::Path;
+
+/// Where a sample file directory's files live.
+///
+/// Sample files are handed out as [`fs::File`]s. A remote backend would serve them from local
+/// storage and make new ones durable remotely in [`Backend::sync`].
+pub trait Backend: std::fmt::Debug + Send + Sync {
+    /// Creates a new sample file for writing, failing with `EEXIST` if it already exists.
+    fn create_file(&self, id: CompositeId) -> Result<fs::File, nix::Error>;
+
+    /// Opens an existing sample file for reading.
+    fn open_file(&self, id: CompositeId) -> Result<fs::File, nix::Error>;
+
+    /// Deletes a sample file. The deletion is durable only after the next [`Backend::sync`].
+    fn unlink_file(&self, id: CompositeId) -> Result<(), nix::Error>;
+
+    /// Makes all prior file creations and deletions durable.
+    fn sync(&self) -> Result<(), nix::Error>;
+
+    /// Calls `f` with the name of each file other than the metadata.
+    ///
+    /// Sample files are named as by [`CompositeIdPath`]; anything else is unexpected.
+    fn list(&self, f: &mut dyn FnMut(&[u8]) -> Result<(), Error>) -> Result<(), Error>;
+
+    /// Returns the length of the given sample file.
+    fn file_len(&self, id: CompositeId) -> Result<u64, nix::Error>;
+
+    /// Takes the lock which keeps two databases, or two read-write opens, from using the
+    /// directory at once: exclusive for read-write use, shared for read-only use. Fails rather
+    /// than waits if the lock is held.
+    fn lock(&self, exclusive: bool) -> Result<(), nix::Error>;
+
+    /// Reads the directory's metadata. If none is found, returns an empty proto.
+    fn read_meta(&self) -> Result<schema::DirMeta, Error>;
+
+    /// Durably writes the directory's metadata, clobbering existing data.
+    fn write_meta(&self, meta: &schema::DirMeta) -> Result<(), Error>;
+
+    /// Returns the bytes available for new sample files.
+    fn free_bytes(&self) -> Result<i64, nix::Error>;
+}
+
+/// A local POSIX directory.
+#[derive(Debug)]
+pub struct Local(Fd);
+
+impl Local {
+    /// Opens the given path as a directory, first creating it if `create` is true.
+    pub fn open(path: &Path, create: bool) -> Result<Self, nix::Error> {
+        Ok(Local(Fd::open(path, create)?))
+    }
+}
+
+impl Backend for Local {
+    fn create_file(&self, id: CompositeId) -> Result<fs::File, nix::Error> {
+        let p = CompositeIdPath::from(id);
+        crate::fs::openat(
+            self.0 .0,
+            &p,
+            OFlag::O_WRONLY | OFlag::O_EXCL | OFlag::O_CREAT,
+            Mode::S_IRUSR | Mode::S_IWUSR,
+        )
+    }
+
+    fn open_file(&self, id: CompositeId) -> Result<fs::File, nix::Error> {
+        let p = CompositeIdPath::from(id);
+        crate::fs::openat(self.0 .0, &p, OFlag::O_RDONLY, Mode::empty())
+    }
+
+    fn unlink_file(&self, id: CompositeId) -> Result<(), nix::Error> {
+        let p = CompositeIdPath::from(id);
+        nix::unistd::unlinkat(Some(self.0 .0), &p, nix::unistd::UnlinkatFlags::NoRemoveDir)
+    }
+
+    fn sync(&self) -> Result<(), nix::Error> {
+        self.0.sync()
+    }
+
+    fn list(&self, f: &mut dyn FnMut(&[u8]) -> Result<(), Error>) -> Result<(), Error> {
+        let mut dir = nix::dir::Dir::openat(
+            self.0 .0,
+            ".",
+            OFlag::O_DIRECTORY | OFlag::O_RDONLY,
+            Mode::empty(),
+        )?;
+        for e in dir.iter() {
+            let e = e?;
+            match e.file_name().to_bytes() {
+                b"." | b".." | b"meta" => continue,
+                name => f(name)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn file_len(&self, id: CompositeId) -> Result<u64, nix::Error> {
+        let p = CompositeIdPath::from(id);
+        Ok(nix::sys::stat::fstatat(self.0 .0, &p, AtFlags::empty())?.st_size as u64)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<(), nix::Error> {
+        self.0.lock(if exclusive {
+            FlockArg::LockExclusiveNonblock
+        } else {
+            FlockArg::LockSharedNonblock
+        })
+    }
+
+    fn read_meta(&self) -> Result<schema::DirMeta, Error> {
+        super::read_meta(&self.0)
+    }
+
+    fn write_meta(&self, meta: &schema::DirMeta) -> Result<(), Error> {
+        super::write_meta(self.0 .0, meta)
+    }
+
+    fn free_bytes(&self) -> Result<i64, nix::Error> {
+        let stat = self.0.statfs()?;
+        Ok(stat.block_size() as i64 * stat.blocks_available() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local() {
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let b = Local::open(&tmpdir.path().join("dir"), true).unwrap();
+        let id = CompositeId::new(1, 2);
+        assert_eq!(b.read_meta().unwrap(), schema::DirMeta::default());
+        let mut meta = schema::DirMeta::default();
+        meta.db_uuid.extend_from_slice(&[1; 16]);
+        b.write_meta(&meta).unwrap();
+        assert_eq!(b.read_meta().unwrap(), meta);
+
+        std::io::Write::write_all(&mut b.create_file(id).unwrap(), b"abc").unwrap();
+        assert_eq!(
+            b.create_file(id).unwrap_err(),
+            nix::Error::EEXIST,
+            "create_file must not clobber"
+        );
+        b.sync().unwrap();
+        assert_eq!(b.file_len(id).unwrap(), 3);
+        let mut names = Vec::new();
+        b.list(&mut |n| {
+            names.push(n.to_owned());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(names, [b"0000000100000002".to_vec()]);
+
+        b.unlink_file(id).unwrap();
+        assert_eq!(b.open_file(id).unwrap_err(), nix::Error::ENOENT);
+        b.lock(true).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception
+
+//! Encryption of sample files at rest.
+//!
+//! An encrypted sample file starts with a header of a magic number and a random salt. Each
+//! recording has its own AES-256-GCM key, derived via HKDF-SHA256 from the master key, that salt,
+//! and the recording's composite id. The random salt ensures a key is never reused, even when a
+//! recording id is reused after a crash.
+//!
+//! The header is followed by a sequence of records, typically one per frame:
+//!
+//! *   the plaintext length, as a 32-bit big-endian integer. This is authenticated as associated
+//!     data.
+//! *   the ciphertext, of the same length.
+//! *   the 16-byte authentication tag.
+//!
+//! The nonce of each record is its zero-based index within the file, so records can't be
+//! reordered. Sealing each frame separately means that frames of a recording which is still being
+//! written can be served, as for live view. The database's `sample_file_bytes` and video index
+//! describe the plaintext; readers find a byte offset by walking the record lengths.
+//!
+//! The magic number's first byte can't start a plaintext sample file: with any supported video
+//! codec it would either be a length prefix of over 4 GiB or (for MJPEG) be followed by `0xd8`.
+
+use base::{bail, err, Error};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{aead, hkdf};
+
+use crate::db::CompositeId;
+
+/// The length of a master key in bytes.
+pub const KEY_LEN: usize = 32;
+
+const MAGIC: [u8; 8] = *b"\xffMNVRenc";
+const SALT_LEN: usize = 16;
+
+/// The length of an encrypted sample file's header.
+pub const HEADER_LEN: usize = MAGIC.len() + SALT_LEN;
+
+/// The bytes added to each record, beyond its plaintext.
+pub const RECORD_OVERHEAD: usize = 4 + aead::MAX_TAG_LEN;
+
+const FILE_KEY_INFO: &[u8] = b"moonfire-nvr sample file key";
+const CHECK_INFO: &[u8] = b"moonfire-nvr sample file key check";
+
+/// A master key from which per-recording keys are derived.
+pub struct Key([u8; KEY_LEN]);
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(<redacted>)")
+    }
+}
+
+/// Returns true iff the given sample file prefix is the header of an encrypted file.
+pub fn is_encrypted(prefix: &[u8]) -> bool {
+    prefix.starts_with(&MAGIC)
+}
+
+/// Returns the on-disk length of an encrypted sample file with the given plaintext length and
+/// number of records.
+pub fn encrypted_len(plaintext_len: u64, records: u64) -> u64 {
+    HEADER_LEN as u64 + plaintext_len + records * RECORD_OVERHEAD as u64
+}
+
+impl Key {
+    /// Parses a base64-encoded key, as generated by `openssl rand -base64 32`.
+    pub fn parse(encoded: &str) -> Result<Self, Error> {
+        let raw = STANDARD.decode(encoded.trim()).map_err(|e| {
+            err!(
+                InvalidArgument,
+                msg("sample file key isn't valid base64"),
+                source(e)
+            )
+        })?;
+        let raw = <[u8; KEY_LEN]>::try_from(&raw[..]).map_err(|_| {
+            err!(
+                InvalidArgument,
+                msg("sample file key must be {KEY_LEN} bytes; got {}", raw.len())
+            )
+        })?;
+        Ok(Key(raw))
+    }
+
+    /// Returns a value which identifies this key without revealing it, for storage in the
+    /// database's `sample_file_key` table.
+    pub fn check_value(&self) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+            .extract(&self.0)
+            .expand(&[CHECK_INFO], hkdf::HKDF_SHA256)
+            .and_then(|okm| okm.fill(&mut out))
+            .expect("HKDF output length is valid");
+        out
+    }
+
+    fn file_key(&self, id: CompositeId, salt: &[u8]) -> aead::LessSafeKey {
+        let id = id.0.to_be_bytes();
+        let okm = hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+            .extract(&self.0)
+            .expand(&[FILE_KEY_INFO, &id], &aead::AES_256_GCM)
+            .expect("HKDF output length is valid");
+        aead::LessSafeKey::new(aead::UnboundKey::from(okm))
+    }
+
+    /// Starts a new sample file, returning its header and a sealer for its records.
+    pub(crate) fn new_file(&self, id: CompositeId) -> Result<(Vec<u8>, Sealer), Error> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(&MAGIC);
+        header.resize(HEADER_LEN, 0);
+        SystemRandom::new()
+            .fill(&mut header[MAGIC.len()..])
+            .map_err(|_| err!(Internal, msg("unable to generate salt")))?;
+        let key = self.file_key(id, &header[MAGIC.len()..]);
+        Ok((header, Sealer { key, next: 0 }))
+    }
+
+    /// Returns an opener for the records of the sample file with the given header.
+    pub(crate) fn opener(&self, id: CompositeId, header: &[u8]) -> Result<Opener, Error> {
+        if header.len() < HEADER_LEN || !is_encrypted(header) {
+            bail!(DataLoss, msg("{id} has no encryption header"));
+        }
+        Ok(Opener {
+            key: self.file_key(id, &header[MAGIC.len()..HEADER_LEN]),
+        })
+    }
+
+    /// Decrypts an entire sample file.
+    pub fn decrypt_file(&self, id: CompositeId, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let opener = self.opener(id, data)?;
+        let mut out = Vec::with_capacity(data.len());
+        let mut pos = HEADER_LEN;
+        let mut record = 0;
+        while pos < data.len() {
+            let (plaintext, len) = opener.open(record, &data[pos..])?;
+            out.extend_from_slice(&plaintext);
+            pos += len;
+            record += 1;
+        }
+        Ok(out)
+    }
+}
+
+fn nonce(record: u64) -> aead::Nonce {
+    let mut n = [0u8; aead::NONCE_LEN];
+    n[aead::NONCE_LEN - 8..].copy_from_slice(&record.to_be_bytes());
+    aead::Nonce::assume_unique_for_key(n)
+}
+
+/// Seals successive records of a single sample file.
+pub(crate) struct Sealer {
+    key: aead::LessSafeKey,
+    next: u64,
+}
+
+impl Sealer {
+    /// Returns the record for `plaintext`, to be written in full before sealing another.
+    pub(crate) fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let len = u32::try_from(plaintext.len())
+            .map_err(|_| err!(OutOfRange, msg("record of {} bytes", plaintext.len())))?
+            .to_be_bytes();
+        let mut out = Vec::with_capacity(RECORD_OVERHEAD + plaintext.len());
+        out.extend_from_slice(&len);
+        out.extend_from_slice(plaintext);
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(nonce(self.next), aead::Aad::from(len), &mut out[4..])
+            .map_err(|_| err!(Internal, msg("unable to encrypt")))?;
+        out.extend_from_slice(tag.as_ref());
+        self.next += 1;
+        Ok(out)
+    }
+}
+
+/// Opens records of a single sample file.
+pub(crate) struct Opener {
+    key: aead::LessSafeKey,
+}
+
+/// Returns the plaintext length of the record at the start of `data`.
+pub(crate) fn plaintext_len(data: &[u8]) -> Result<usize, Error> {
+    let Some(len) = data.get(..4) else {
+        bail!(OutOfRange, msg("truncated record header"));
+    };
+    Ok(u32::from_be_bytes(len.try_into().unwrap()) as usize)
+}
+
+impl Opener {
+    /// Decrypts the record with the given index at the start of `data`, returning its plaintext
+    /// and the record's total length.
+    pub(crate) fn open(&self, record: u64, data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        let len = plaintext_len(data)?;
+        let total = RECORD_OVERHEAD + len;
+        let Some(sealed) = data.get(4..total) else {
+            bail!(OutOfRange, msg("truncated record {record}"));
+        };
+        let mut buf = sealed.to_vec();
+        let plaintext_len = self
+            .key
+            .open_in_place(nonce(record), aead::Aad::from(&data[..4]), &mut buf)
+            .map_err(|_| {
+                err!(
+                    DataLoss,
+                    msg("unable to decrypt record {record}; wrong key or corrupt data")
+                )
+            })?
+            .len();
+        buf.truncate(plaintext_len);
+        Ok((buf, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = Key::parse(&STANDARD.encode([1u8; KEY_LEN])).unwrap();
+        let id = CompositeId::new(1, 2);
+        let (mut file, mut sealer) = key.new_file(id).unwrap();
+        for frame in [&b"first"[..], b"", b"second"] {
+            file.extend_from_slice(&sealer.seal(frame).unwrap());
+        }
+        assert!(is_encrypted(&file));
+        assert_eq!(file.len() as u64, encrypted_len(11, 3));
+        assert_eq!(key.decrypt_file(id, &file).unwrap(), b"firstsecond");
+
+        // Another recording's id, another key, or a flipped bit can't decrypt.
+        assert!(key.decrypt_file(CompositeId::new(1, 3), &file).is_err());
+        let other = Key::parse(&STANDARD.encode([2u8; KEY_LEN])).unwrap();
+        assert!(other.decrypt_file(id, &file).is_err());
+        assert_ne!(key.check_value(), other.check_value());
+        let mut corrupt = file.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(key.decrypt_file(id, &corrupt).is_err());
+        assert!(key.decrypt_file(id, &file[..file.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn parse_bad_key() {
+        assert!(Key::parse("not base64!").is_err());
+        assert!(Key::parse(&STANDARD.encode([0u8; 16])).is_err());
+    }
+}
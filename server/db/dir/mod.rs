@@ -7,14 +7,19 @@
 //! This mostly includes opening a directory and looking for recordings within it.
 //! Updates to the directory happen through [crate::writer].
 
+mod backend;
+pub mod crypt;
 mod reader;
 
+pub use backend::{Backend, Local};
+pub(crate) use reader::AtomicHistogram;
+pub use reader::{LatencyHistogram, ReaderStats, LATENCY_BUCKETS_SEC};
+
 use crate::coding;
 use crate::db::CompositeId;
 use crate::schema;
-use base::{bail, err, Error};
+use base::{bail, err, Error, FastHashMap};
 use cstr::cstr;
-use nix::sys::statvfs::Statvfs;
 use nix::{
     fcntl::{FlockArg, OFlag},
     sys::stat::Mode,
@@ -26,10 +31,11 @@ use std::fs;
 use std::io::{Read, Write};
 use std::ops::Range;
 use std::os::fd::{AsFd, BorrowedFd};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::RawFd;
 use std::path::Path;
-use std::sync::Arc;
-use tracing::warn;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tracing::{info, warn};
 
 /// The fixed length of a directory's `meta` file.
 ///
@@ -45,13 +51,120 @@ const FIXED_DIR_META_LEN: usize = 512;
 /// `design/schema.md`.
 #[derive(Debug)]
 pub struct SampleFileDir {
-    /// The open file descriptor for the directory. The worker created by
-    /// [crate::writer::start_syncer] uses it to create files and sync the
-    /// directory. Other threads use it to open sample files for reading during
-    /// video serving.
-    pub(crate) fd: Arc<Fd>,
+    /// Where the files live. The worker created by [crate::writer::start_syncer]
+    /// uses it to create files and sync the directory. The reader threads use it
+    /// to open sample files for reading during video serving.
+    backend: Arc<dyn Backend>,
 
     reader: reader::Reader,
+
+    /// The key for reading encrypted sample files, as supplied via
+    /// [`crate::db::LockedDatabase::set_sample_file_key`].
+    sample_file_key: OnceLock<Arc<crypt::Key>>,
+
+    /// Progress of [`SampleFileDir::abandon_files`].
+    scan: ScanState,
+}
+
+/// Progress of a scan for files to abandon, as returned by [`SampleFileDir::scan_progress`].
+#[derive(Clone, Debug, Default)]
+pub struct ScanProgress {
+    /// Directory entries examined so far.
+    pub files_scanned: u64,
+
+    /// Files found to abandon so far. They may not yet be deleted.
+    pub files_abandoned: u64,
+}
+
+#[derive(Debug, Default)]
+struct ScanState {
+    in_progress: AtomicBool,
+    files_scanned: AtomicU64,
+    files_abandoned: AtomicU64,
+}
+
+/// Log scan progress after each this many files.
+const SCAN_LOG_INTERVAL: u64 = 100_000;
+
+/// Configuration of a sample file directory's reader threads.
+///
+/// Reads happen in dedicated threads rather than tokio's IO threads; see the `reader` module.
+/// A directory on a slow disk, such as a USB archive drive, may be given fewer threads and lower
+/// priorities than one on the main recording drive.
+#[derive(Clone, Debug)]
+pub struct ReaderConfig {
+    /// The number of reader threads. Must be at least 1.
+    pub threads: usize,
+
+    /// If set, the `nice` value applied to each reader thread. See `setpriority(2)`.
+    pub nice: Option<i32>,
+
+    /// If set, the I/O scheduling class and priority applied to each reader thread.
+    /// See `ioprio_set(2)`.
+    pub io_priority: Option<IoPriority>,
+
+    /// If set, the maximum number of commands which may be queued for or in progress on the
+    /// reader threads before opens of new files are rejected with `ResourceExhausted`.
+    /// Reads of already-open files are never rejected.
+    pub max_queue_depth: Option<usize>,
+
+    /// The length of the first chunk read from each opened file. Must be at least 1.
+    ///
+    /// Short reads, such as from seeks, typically need only this chunk.
+    pub chunk_len: usize,
+
+    /// The maximum chunk length. Each chunk after the first doubles in length up to this
+    /// limit, so long sequential reads such as downloads use fewer, larger reads. Must be at
+    /// least `chunk_len`. Each open file holds at most one chunk in memory at a time.
+    pub max_chunk_len: usize,
+
+    /// If true, asks the kernel to read the next chunk of an unencrypted file into the page
+    /// cache (via `madvise(MADV_WILLNEED)`) while the current one is being sent.
+    pub read_ahead: bool,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        ReaderConfig {
+            threads: 1,
+            nice: None,
+            io_priority: None,
+            max_queue_depth: None,
+            chunk_len: 1 << 16,
+            max_chunk_len: 1 << 20,
+            read_ahead: true,
+        }
+    }
+}
+
+/// An I/O scheduling class and priority level, as in `ionice(1)`.
+///
+/// Levels range from 0 (highest priority) to 7 (lowest priority).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IoPriority {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl IoPriority {
+    /// Returns the raw value as expected by the `ioprio_set` system call.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn to_raw(self) -> Result<libc::c_int, Error> {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let (class, level) = match self {
+            IoPriority::Realtime(l) => (1, l),
+            IoPriority::BestEffort(l) => (2, l),
+            IoPriority::Idle => (3, 0),
+        };
+        if level > 7 {
+            bail!(
+                InvalidArgument,
+                msg("I/O priority level {level} is out of range [0, 7]")
+            );
+        }
+        Ok((class << IOPRIO_CLASS_SHIFT) | libc::c_int::from(level))
+    }
 }
 
 /// The on-disk filename of a recording file within the sample file directory.
@@ -224,15 +337,41 @@ impl SampleFileDir {
     /// `db_meta.in_progress_open` should be filled if the directory should be opened in read/write
     /// mode; absent in read-only mode.
     pub fn open(path: &Path, expected_meta: &schema::DirMeta) -> Result<Arc<SampleFileDir>, Error> {
+        SampleFileDir::open_with_reader_config(path, expected_meta, &ReaderConfig::default())
+    }
+
+    /// As [`SampleFileDir::open`], with non-default reader threads.
+    pub fn open_with_reader_config(
+        path: &Path,
+        expected_meta: &schema::DirMeta,
+        reader_config: &ReaderConfig,
+    ) -> Result<Arc<SampleFileDir>, Error> {
+        let backend = Arc::new(Local::open(path, false)?);
+        SampleFileDir::open_backend(
+            &path.display().to_string(),
+            backend,
+            expected_meta,
+            reader_config,
+        )
+    }
+
+    /// As [`SampleFileDir::open_with_reader_config`], with files in the given backend rather
+    /// than a local directory. `name` identifies the directory in logs and thread names.
+    pub fn open_backend(
+        name: &str,
+        backend: Arc<dyn Backend>,
+        expected_meta: &schema::DirMeta,
+        reader_config: &ReaderConfig,
+    ) -> Result<Arc<SampleFileDir>, Error> {
         let read_write = expected_meta.in_progress_open.is_some();
-        let s = SampleFileDir::open_self(path, false)?;
-        s.fd.lock(if read_write {
-            FlockArg::LockExclusiveNonblock
-        } else {
-            FlockArg::LockSharedNonblock
-        })
-        .map_err(|e| err!(e, msg("unable to lock dir {}", path.display())))?;
-        let dir_meta = read_meta(&s.fd).map_err(|e| err!(e, msg("unable to read meta file")))?;
+        let s = SampleFileDir::new(name, backend, reader_config)?;
+        s.backend
+            .lock(read_write)
+            .map_err(|e| err!(e, msg("unable to lock dir {name}")))?;
+        let dir_meta = s
+            .backend
+            .read_meta()
+            .map_err(|e| err!(e, msg("unable to read meta file")))?;
         if let Err(e) = SampleFileDir::check_consistent(expected_meta, &dir_meta) {
             bail!(
                 Internal,
@@ -285,10 +424,16 @@ impl SampleFileDir {
         path: &Path,
         db_meta: &schema::DirMeta,
     ) -> Result<Arc<SampleFileDir>, Error> {
-        let s = SampleFileDir::open_self(path, true)?;
-        s.fd.lock(FlockArg::LockExclusiveNonblock)
+        let backend = Arc::new(Local::open(path, true)?);
+        let s = SampleFileDir::new(
+            &path.display().to_string(),
+            backend,
+            &ReaderConfig::default(),
+        )?;
+        s.backend
+            .lock(true)
             .map_err(|e| err!(e, msg("unable to lock dir {}", path.display())))?;
-        let old_meta = read_meta(&s.fd)?;
+        let old_meta = s.backend.read_meta()?;
 
         // Verify metadata. We only care that it hasn't been completely opened.
         // Partial opening by this or another database is fine; we won't overwrite anything.
@@ -315,67 +460,194 @@ impl SampleFileDir {
         Ok(s)
     }
 
-    pub(crate) fn opendir(&self) -> Result<nix::dir::Dir, nix::Error> {
-        nix::dir::Dir::openat(
-            self.fd.as_fd().as_raw_fd(),
-            ".",
-            OFlag::O_DIRECTORY | OFlag::O_RDONLY,
-            Mode::empty(),
-        )
+    /// Calls `f` with the name of each file in the directory, aside from metadata.
+    pub(crate) fn list(&self, f: &mut dyn FnMut(&[u8]) -> Result<(), Error>) -> Result<(), Error> {
+        self.backend.list(f)
     }
 
     /// Determines if the directory is empty, aside form metadata.
     pub(crate) fn is_empty(&self) -> Result<bool, Error> {
-        let mut dir = self.opendir()?;
-        for e in dir.iter() {
-            let e = e?;
-            match e.file_name().to_bytes() {
-                b"." | b".." => continue,
-                b"meta" => continue, // existing metadata is fine.
-                _ => return Ok(false),
-            }
+        let mut empty = true;
+        self.backend.list(&mut |_| {
+            empty = false;
+            Ok(())
+        })?;
+        Ok(empty)
+    }
+
+    fn new(
+        name: &str,
+        backend: Arc<dyn Backend>,
+        reader_config: &ReaderConfig,
+    ) -> Result<Arc<SampleFileDir>, Error> {
+        if reader_config.threads == 0 {
+            bail!(
+                InvalidArgument,
+                msg("dir {name} must have at least one reader thread")
+            );
+        }
+        if let Some(p) = reader_config.io_priority {
+            p.to_raw()?;
         }
-        Ok(true)
+        if reader_config.chunk_len == 0 || reader_config.max_chunk_len < reader_config.chunk_len {
+            bail!(
+                InvalidArgument,
+                msg(
+                    "dir {name} has invalid reader chunk lengths {}..={}",
+                    reader_config.chunk_len,
+                    reader_config.max_chunk_len,
+                )
+            );
+        }
+        let reader = reader::Reader::spawn(name, backend.clone(), reader_config);
+        Ok(Arc::new(SampleFileDir {
+            backend,
+            reader,
+            sample_file_key: OnceLock::new(),
+            scan: ScanState::default(),
+        }))
     }
 
-    fn open_self(path: &Path, create: bool) -> Result<Arc<SampleFileDir>, Error> {
-        let fd = Arc::new(Fd::open(path, create)?);
-        let reader = reader::Reader::spawn(path, fd.clone());
-        Ok(Arc::new(SampleFileDir { fd, reader }))
+    /// Sets the key used to read encrypted sample files. Only the first call has any effect.
+    pub(crate) fn set_sample_file_key(&self, key: Arc<crypt::Key>) {
+        let _ = self.sample_file_key.set(key);
     }
 
     /// Opens the given sample file for reading.
+    ///
+    /// `range` is in terms of the plaintext; encrypted files are decrypted transparently.
     pub fn open_file(&self, composite_id: CompositeId, range: Range<u64>) -> reader::FileStream {
-        self.reader.open_file(composite_id, range)
+        self.reader
+            .open_file(composite_id, range, self.sample_file_key.get().cloned())
+    }
+
+    /// Returns whether to start serving a new playback from this directory, counting it as shed
+    /// if not.
+    ///
+    /// Callers check this once before starting a response. `open_file` itself never sheds, so a
+    /// response which spans several sample files doesn't fail partway through.
+    pub fn admit_playback(&self) -> bool {
+        self.reader.admit()
+    }
+
+    /// Runs `f` on one of this directory's reader threads.
+    ///
+    /// This is for blocking work related to serving from this directory, such as building
+    /// `.mp4` indexes, which should be bounded by its reader thread count and queued along with
+    /// its reads.
+    pub fn run_on_reader(&self, f: impl FnOnce() + Send + 'static) {
+        self.reader.run(Box::new(f))
+    }
+
+    /// Returns a snapshot of the reader threads' statistics.
+    pub fn reader_stats(&self) -> ReaderStats {
+        self.reader.stats()
     }
 
     pub fn create_file(&self, composite_id: CompositeId) -> Result<fs::File, nix::Error> {
-        let p = CompositeIdPath::from(composite_id);
-        crate::fs::openat(
-            self.fd.0,
-            &p,
-            OFlag::O_WRONLY | OFlag::O_EXCL | OFlag::O_CREAT,
-            Mode::S_IRUSR | Mode::S_IWUSR,
-        )
+        self.backend.create_file(composite_id)
+    }
+
+    /// Opens the given sample file for synchronous reading, bypassing the reader threads.
+    ///
+    /// This is meant for offline tools such as `moonfire-nvr check`.
+    pub(crate) fn open_file_sync(&self, composite_id: CompositeId) -> Result<fs::File, nix::Error> {
+        self.backend.open_file(composite_id)
+    }
+
+    /// Returns the length of the given sample file.
+    pub(crate) fn file_len(&self, composite_id: CompositeId) -> Result<u64, nix::Error> {
+        self.backend.file_len(composite_id)
     }
 
     pub(crate) fn write_meta(&self, meta: &schema::DirMeta) -> Result<(), Error> {
-        write_meta(self.fd.0, meta)
+        self.backend.write_meta(meta)
     }
 
-    pub fn statfs(&self) -> Result<Statvfs, nix::Error> {
-        self.fd.statfs()
+    /// Returns the bytes available for new sample files.
+    pub fn free_bytes(&self) -> Result<i64, nix::Error> {
+        self.backend.free_bytes()
     }
 
     /// Unlinks the given sample file within this directory.
     pub(crate) fn unlink_file(&self, id: CompositeId) -> Result<(), nix::Error> {
-        let p = CompositeIdPath::from(id);
-        nix::unistd::unlinkat(Some(self.fd.0), &p, nix::unistd::UnlinkatFlags::NoRemoveDir)
+        self.backend.unlink_file(id)
+    }
+
+    /// Deletes files which were created but never committed to the database, as after an
+    /// unclean shutdown: those with a recording id at or beyond their stream's entry in
+    /// `streams_to_next`. Files of other streams are left alone.
+    ///
+    /// This thread lists the directory while the reader threads unlink files, so a directory
+    /// with many files to abandon is worked on in parallel. Progress is available from
+    /// [`SampleFileDir::scan_progress`] meanwhile. Returns the number of files which couldn't
+    /// be deleted; failures are logged.
+    pub(crate) fn abandon_files(
+        &self,
+        streams_to_next: &FastHashMap<i32, i32>,
+    ) -> Result<usize, Error> {
+        let s = &self.scan;
+        s.files_scanned.store(0, Ordering::Relaxed);
+        s.files_abandoned.store(0, Ordering::Relaxed);
+        s.in_progress.store(true, Ordering::Relaxed);
+        let r = self.abandon_files_inner(streams_to_next);
+        s.in_progress.store(false, Ordering::Relaxed);
+        r
+    }
+
+    fn abandon_files_inner(&self, streams_to_next: &FastHashMap<i32, i32>) -> Result<usize, Error> {
+        let s = &self.scan;
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.backend.list(&mut |name| {
+            let scanned = s.files_scanned.fetch_add(1, Ordering::Relaxed) + 1;
+            if scanned % SCAN_LOG_INTERVAL == 0 {
+                info!(
+                    "dir: scanned {scanned} files, {} to abandon so far",
+                    s.files_abandoned.load(Ordering::Relaxed)
+                );
+            }
+            let Ok(id) = parse_id(name) else {
+                return Ok(());
+            };
+            let Some(&next) = streams_to_next.get(&id.stream()) else {
+                return Ok(()); // unknown stream.
+            };
+            if id.recording() >= next {
+                s.files_abandoned.fetch_add(1, Ordering::Relaxed);
+                self.reader.unlink_file(id, tx.clone());
+            }
+            Ok(())
+        })?;
+        drop(tx);
+        let mut undeletable = 0;
+        for (id, r) in rx {
+            match r {
+                Ok(()) => {}
+                Err(nix::Error::ENOENT) => warn!(%id, "dir: abandoned recording already deleted"),
+                Err(err) => {
+                    warn!(%err, %id, "dir: unable to unlink abandoned recording");
+                    undeletable += 1;
+                }
+            }
+        }
+        Ok(undeletable)
+    }
+
+    /// Returns the progress of an ongoing [`SampleFileDir::abandon_files`], if any.
+    pub fn scan_progress(&self) -> Option<ScanProgress> {
+        let s = &self.scan;
+        if !s.in_progress.load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(ScanProgress {
+            files_scanned: s.files_scanned.load(Ordering::Relaxed),
+            files_abandoned: s.files_abandoned.load(Ordering::Relaxed),
+        })
     }
 
     /// Syncs the directory itself.
     pub(crate) fn sync(&self) -> Result<(), nix::Error> {
-        self.fd.sync()
+        self.backend.sync()
     }
 }
 
@@ -413,6 +685,34 @@ mod tests {
         parse_id(b"000000010000000x").unwrap_err();
     }
 
+    #[test]
+    fn abandon_files() {
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let d = SampleFileDir::create(tmpdir.path(), &schema::DirMeta::default()).unwrap();
+        let ids = [
+            CompositeId::new(1, 0),
+            CompositeId::new(1, 1),
+            CompositeId::new(1, 2),
+            CompositeId::new(2, 5),
+        ];
+        for &id in &ids {
+            d.create_file(id).unwrap();
+        }
+        assert!(d.scan_progress().is_none());
+
+        // Stream 1's files from recording 1 onward are abandoned. Stream 2 is unknown, so its
+        // file is left alone, as is the `meta` file.
+        let streams_to_next = [(1, 1)].into_iter().collect();
+        assert_eq!(d.abandon_files(&streams_to_next).unwrap(), 0);
+        let exists = |id| d.open_file_sync(id).is_ok();
+        assert_eq!(ids.map(exists), [true, false, false, true]);
+        assert!(d.scan_progress().is_none());
+    }
+
     /// Ensures that a DirMeta with all fields filled fits within the maximum size.
     #[test]
     fn max_len_meta() {
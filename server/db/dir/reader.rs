@@ -2,13 +2,14 @@
 // Copyright (C) 2021 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception
 
-//! Reads sample files in a dedicated thread.
+//! Reads sample files in dedicated threads.
 //!
 //! Typically sample files are on spinning disk where IO operations take
 //! ~10 ms on success. When disks fail, operations can stall for arbitrarily
 //! long. POSIX doesn't have good support for asynchronous disk IO,
-//! so it's desirable to do this from a dedicated thread for each disk rather
-//! than stalling the tokio IO threads or (as when using `tokio::fs`) creating
+//! so it's desirable to do this from a dedicated thread (or a small, fixed
+//! number of threads; see [`super::ReaderConfig`]) for each disk rather than
+//! stalling the tokio IO threads or (as when using `tokio::fs`) creating
 //! unbounded numbers of workers.
 //!
 //! This also has some minor theoretical efficiency advantages over
@@ -19,33 +20,143 @@
 //! *   it has fewer thread handoffs because it batches operations on open
 //!     (open, fstat, mmap, madvise, close, memcpy first chunk) and close
 //!     (memcpy last chunk, munmap).
+//!
+//! Every read uses `mmap`, including the small, random reads of live view
+//! catch-up and other short ranges. A read no longer than
+//! [`ReaderConfig::chunk_len`](super::ReaderConfig::chunk_len) completes
+//! entirely within the open command, so it costs one thread handoff and no
+//! separate read or close commands.
+//!
+//! Encrypted sample files (see [`super::crypt`]) are decrypted here too, when a key is
+//! supplied.
 
 use std::convert::TryFrom;
 use std::future::Future;
-use std::path::Path;
+use std::os::unix::fs::FileExt as _;
 use std::{
     ops::Range,
     pin::Pin,
-    sync::Arc,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use base::bail;
 use base::clock::{RealClocks, TimerGuard};
 use base::{err, Error, ErrorKind, ResultExt};
-use nix::{fcntl::OFlag, sys::stat::Mode};
 
+use super::crypt;
 use crate::CompositeId;
 
-/// Handle for a reader thread, used to send it commands.
+/// Upper bounds of the buckets in [`LatencyHistogram`], in seconds.
+pub const LATENCY_BUCKETS_SEC: [f64; 8] = [0.001, 0.004, 0.016, 0.064, 0.256, 1.024, 4.096, 16.384];
+
+/// A snapshot of a latency histogram, e.g. for one kind of reader command.
+///
+/// For reader commands, latency is measured from when the command is enqueued to when it
+/// completes, so it includes time spent waiting in the queue.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    /// Count of commands which completed within each of [`LATENCY_BUCKETS_SEC`]. As in
+    /// Prometheus, these are cumulative: every command counted in a bucket is also counted
+    /// in all the following buckets.
+    pub buckets: [u64; LATENCY_BUCKETS_SEC.len()],
+
+    /// Total count of commands, including those over the final bucket's bound.
+    pub count: u64,
+
+    /// Total latency of all commands.
+    pub sum: Duration,
+}
+
+/// A snapshot of a directory's reader statistics, as returned by
+/// [`super::SampleFileDir::reader_stats`].
+#[derive(Clone, Debug, Default)]
+pub struct ReaderStats {
+    /// Commands currently queued or in progress.
+    pub queue_depth: usize,
+
+    /// New playbacks rejected because the queue was at `ReaderConfig::max_queue_depth` or the
+    /// server was over its memory budget.
+    pub shed_opens: u64,
+
+    pub open: LatencyHistogram,
+    pub read_chunk: LatencyHistogram,
+    pub close: LatencyHistogram,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AtomicHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_SEC.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl AtomicHistogram {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS_SEC.len()],
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record(&self, latency: Duration) {
+        let sec = latency.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS_SEC.iter().zip(&self.buckets) {
+            if sec <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(
+            u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Statistics shared between a [`Reader`] and its threads.
+#[derive(Debug, Default)]
+struct Metrics {
+    queue_depth: AtomicUsize,
+    shed_opens: AtomicU64,
+    open: AtomicHistogram,
+    read_chunk: AtomicHistogram,
+    close: AtomicHistogram,
+}
+
+/// Handle for a directory's reader threads, used to send them commands.
 ///
-/// The reader will shut down after the last handle is closed.
+/// The readers will shut down after the last handle is closed.
 #[derive(Clone, Debug)]
-pub(super) struct Reader(tokio::sync::mpsc::UnboundedSender<ReaderCommand>);
+pub(super) struct Reader {
+    tx: tokio::sync::mpsc::UnboundedSender<(Instant, ReaderCommand)>,
+    metrics: Arc<Metrics>,
+    max_queue_depth: Option<usize>,
+}
 
 impl Reader {
-    pub(super) fn spawn(path: &Path, dir: Arc<super::Fd>) -> Self {
+    pub(super) fn spawn(
+        name: &str,
+        dir: Arc<dyn super::Backend>,
+        config: &super::ReaderConfig,
+    ) -> Self {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let metrics = Arc::new(Metrics::default());
+
+        // The threads take turns waiting on the shared receiver. Any thread may handle any
+        // command; all the state of an open file is in `OpenFile`.
+        let rx = Arc::new(Mutex::new(rx));
         let page_size = usize::try_from(
             nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
                 .expect("PAGE_SIZE fetch must succeed")
@@ -53,22 +164,82 @@ impl Reader {
         )
         .expect("PAGE_SIZE fits in usize");
         assert_eq!(page_size.count_ones(), 1, "invalid page size {page_size}");
-        let span = tracing::info_span!("reader", path = %path.display());
-        std::thread::Builder::new()
-            .name(format!("r-{}", path.display()))
-            .spawn(move || {
-                let _guard = span.enter();
-                ReaderInt { dir, page_size }.run(rx)
-            })
-            .expect("unable to create reader thread");
-        Self(tx)
-    }
-
-    pub(super) fn open_file(&self, composite_id: CompositeId, range: Range<u64>) -> FileStream {
+        for i in 0..config.threads {
+            let span = tracing::info_span!("reader", path = %name);
+            let thread_name = if config.threads == 1 {
+                format!("r-{name}")
+            } else {
+                format!("r{i}-{name}")
+            };
+            let dir = dir.clone();
+            let rx = rx.clone();
+            let config = config.clone();
+            let metrics = metrics.clone();
+            std::thread::Builder::new()
+                .name(thread_name)
+                .spawn(move || {
+                    let _guard = span.enter();
+                    set_priority(&config);
+                    ReaderInt {
+                        dir,
+                        page_size,
+                        chunk_len: config.chunk_len,
+                        max_chunk_len: config.max_chunk_len,
+                        read_ahead: config.read_ahead,
+                        metrics,
+                    }
+                    .run(&rx)
+                })
+                .expect("unable to create reader thread");
+        }
+        Self {
+            tx,
+            metrics,
+            max_queue_depth: config.max_queue_depth,
+        }
+    }
+
+    /// Returns true if the queue is at its configured maximum depth.
+    pub(super) fn is_saturated(&self) -> bool {
+        self.max_queue_depth
+            .is_some_and(|m| self.metrics.queue_depth.load(Ordering::Relaxed) >= m)
+    }
+
+    /// Returns whether to start a new playback, counting it as shed if not.
+    ///
+    /// Playback is shed when the queue is saturated or the server is over its memory budget.
+    /// This happens only before a response starts; `open_file` never fails for these reasons,
+    /// as a response which has already been partially sent can't be retried cleanly.
+    pub(super) fn admit(&self) -> bool {
+        if self.is_saturated() || base::mem::BUDGET.is_exceeded() {
+            self.metrics.shed_opens.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    pub(super) fn stats(&self) -> ReaderStats {
+        let m = &self.metrics;
+        ReaderStats {
+            queue_depth: m.queue_depth.load(Ordering::Relaxed),
+            shed_opens: m.shed_opens.load(Ordering::Relaxed),
+            open: m.open.snapshot(),
+            read_chunk: m.read_chunk.snapshot(),
+            close: m.close.snapshot(),
+        }
+    }
+
+    pub(super) fn open_file(
+        &self,
+        composite_id: CompositeId,
+        range: Range<u64>,
+        key: Option<Arc<crypt::Key>>,
+    ) -> FileStream {
         if range.is_empty() {
             return FileStream {
                 state: FileStreamState::Invalid,
-                reader: Reader(self.0.clone()),
+                reader: self.clone(),
+                charge: None,
             };
         }
         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -76,25 +247,46 @@ impl Reader {
             span: tracing::Span::current(),
             composite_id,
             range,
+            key,
             tx,
         });
         FileStream {
             state: FileStreamState::Reading(rx),
-            reader: Reader(self.0.clone()),
+            reader: self.clone(),
+            charge: None,
         }
     }
 
+    /// Unlinks a file, sending the result to `tx`.
+    pub(super) fn unlink_file(&self, composite_id: CompositeId, tx: UnlinkSender) {
+        self.send(ReaderCommand::UnlinkFile { composite_id, tx });
+    }
+
+    /// Runs `f` on one of the reader threads, after the commands already queued.
+    pub(super) fn run(&self, f: Box<dyn FnOnce() + Send>) {
+        self.send(ReaderCommand::Run(f));
+    }
+
     fn send(&self, cmd: ReaderCommand) {
-        self.0
-            .send(cmd)
-            .map_err(|_| ())
+        self.try_send(cmd)
             .expect("reader thread panicked; see logs.");
     }
+
+    fn try_send(&self, cmd: ReaderCommand) -> Result<(), ()> {
+        self.metrics.queue_depth.fetch_add(1, Ordering::Relaxed);
+        self.tx.send((Instant::now(), cmd)).map_err(|_| {
+            self.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
 }
 
 pub struct FileStream {
     state: FileStreamState,
     reader: Reader,
+
+    /// Accounts for the most recently returned chunk in [`base::mem::BUDGET`] until the next
+    /// poll, which suggests the consumer has sent it on.
+    charge: Option<base::mem::Charge<'static>>,
 }
 
 type ReadReceiver = tokio::sync::oneshot::Receiver<Result<SuccessfulRead, Error>>;
@@ -106,6 +298,11 @@ enum FileStreamState {
 }
 
 impl FileStream {
+    fn charge_chunk(&mut self, chunk: &[u8]) {
+        self.charge =
+            Some(base::mem::BUDGET.charge(base::mem::Category::ReaderChunks, chunk.len() as u64));
+    }
+
     /// Helper for reading during `poll_next`.
     fn read(
         mut self: Pin<&mut Self>,
@@ -129,10 +326,12 @@ impl FileStream {
                 file: Some(file),
             }))) => {
                 self.state = FileStreamState::Idle(file);
+                self.charge_chunk(&chunk);
                 Poll::Ready(Some(Ok(chunk)))
             }
             Poll::Ready(Ok(Ok(SuccessfulRead { chunk, file: None }))) => {
                 self.state = FileStreamState::Invalid;
+                self.charge_chunk(&chunk);
                 Poll::Ready(Some(Ok(chunk)))
             }
             Poll::Pending => {
@@ -147,6 +346,7 @@ impl futures::stream::Stream for FileStream {
     type Item = Result<Vec<u8>, Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.charge = None;
         match std::mem::replace(&mut self.state, FileStreamState::Invalid) {
             FileStreamState::Idle(file) => {
                 let (tx, rx) = tokio::sync::oneshot::channel();
@@ -169,7 +369,7 @@ impl Drop for FileStream {
             // This will succeed unless reader has panicked. If that happened,
             // the logfiles will be loud anyway; no need to add additional
             // error messages.
-            let _ = self.reader.0.send(ReaderCommand::CloseFile(file));
+            let _ = self.reader.try_send(ReaderCommand::CloseFile(file));
         }
     }
 }
@@ -193,6 +393,36 @@ struct OpenFile {
     /// The length of the memory mapping. This may be less than the length of
     /// the file.
     map_len: usize,
+
+    /// For an encrypted file, the decryption state. In this case the whole file is mapped, and
+    /// `map_pos` is the position of the next record to decrypt.
+    decrypt: Option<Decrypt>,
+
+    /// The approximate length of the next chunk to return. This starts at
+    /// [`ReaderConfig::chunk_len`](super::ReaderConfig::chunk_len) and grows with each chunk.
+    next_chunk_len: usize,
+}
+
+struct Decrypt {
+    opener: crypt::Opener,
+
+    /// The index of the record at `map_pos`.
+    record: u64,
+
+    /// The number of plaintext bytes to skip from the start of the record at `map_pos`.
+    skip: usize,
+
+    /// The number of plaintext bytes left to return.
+    remaining: u64,
+}
+
+impl OpenFile {
+    /// Returns the mapped region starting at `map_pos`.
+    fn rest(&self) -> &[u8] {
+        // SAFETY: see the safety comment in `ReaderInt::chunk`.
+        let data = unsafe { std::slice::from_raw_parts(self.map_ptr as *const u8, self.map_len) };
+        data.get(self.map_pos..).unwrap_or_default()
+    }
 }
 
 // Rust makes us manually state these because of the `*mut` ptr above.
@@ -227,6 +457,7 @@ enum ReaderCommand {
         span: tracing::Span,
         composite_id: CompositeId,
         range: std::ops::Range<u64>,
+        key: Option<Arc<crypt::Key>>,
         tx: tokio::sync::oneshot::Sender<Result<SuccessfulRead, Error>>,
     },
 
@@ -238,58 +469,145 @@ enum ReaderCommand {
 
     /// Closes the file early, as when the [FileStream] is dropped before completing.
     CloseFile(OpenFile),
+
+    /// Unlinks a file, as when abandoning files on startup; see
+    /// [`super::SampleFileDir::abandon_files`].
+    UnlinkFile {
+        composite_id: CompositeId,
+        tx: UnlinkSender,
+    },
+
+    /// Runs other work which should share the reader threads; see [`Reader::run`].
+    Run(Box<dyn FnOnce() + Send>),
 }
 
+/// Receives the results of [`Reader::unlink_file`].
+pub(super) type UnlinkSender = std::sync::mpsc::Sender<(CompositeId, Result<(), nix::Error>)>;
+
 struct ReaderInt {
-    /// File descriptor of the sample file directory.
-    dir: Arc<super::Fd>,
+    /// The sample file directory's backend.
+    dir: Arc<dyn super::Backend>,
 
     /// The page size as returned by `sysconf`; guaranteed to be a power of two.
     page_size: usize,
+
+    /// See [`super::ReaderConfig`].
+    chunk_len: usize,
+    max_chunk_len: usize,
+    read_ahead: bool,
+
+    metrics: Arc<Metrics>,
+}
+
+/// Applies the configured scheduling priorities to the calling thread.
+///
+/// Failures are logged but otherwise ignored; reads will still work at the default priority.
+#[cfg(target_os = "linux")]
+fn set_priority(config: &super::ReaderConfig) {
+    // On Linux, `PRIO_PROCESS` and `IOPRIO_WHO_PROCESS` with an id of 0 apply to the
+    // calling thread only, not the whole process.
+    if let Some(nice) = config.nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+            let err = std::io::Error::last_os_error();
+            tracing::warn!(%err, nice, "unable to set reader thread's nice value");
+        }
+    }
+    if let Some(io_priority) = config.io_priority {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        let raw = io_priority
+            .to_raw()
+            .expect("io_priority is validated on open");
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, raw) } != 0 {
+            let err = std::io::Error::last_os_error();
+            tracing::warn!(%err, ?io_priority, "unable to set reader thread's I/O priority");
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_priority(config: &super::ReaderConfig) {
+    if config.nice.is_some() || config.io_priority.is_some() {
+        tracing::warn!("reader thread priorities are only supported on Linux; ignoring");
+    }
 }
 
 impl ReaderInt {
-    fn run(self, mut rx: tokio::sync::mpsc::UnboundedReceiver<ReaderCommand>) {
-        while let Some(cmd) = rx.blocking_recv() {
-            // OpenFile's Drop implementation takes care of closing the file on error paths and
-            // the CloseFile operation.
-            match cmd {
-                ReaderCommand::OpenFile {
-                    span,
-                    composite_id,
-                    range,
-                    tx,
-                } => {
-                    if tx.is_closed() {
-                        // avoid spending effort on expired commands
-                        continue;
-                    }
-                    let span2 = span.clone();
-                    let _span_enter = span2.enter();
-                    let _timer_guard =
-                        TimerGuard::new(&RealClocks {}, || format!("open {composite_id}"));
-                    let _ = tx.send(self.open(span, composite_id, range));
-                }
-                ReaderCommand::ReadNextChunk { file, tx } => {
-                    if tx.is_closed() {
-                        // avoid spending effort on expired commands
-                        continue;
-                    }
-                    let composite_id = file.composite_id;
-                    let span2 = file.span.clone();
-                    let _span_enter = span2.enter();
-                    let _guard =
-                        TimerGuard::new(&RealClocks {}, || format!("read from {composite_id}"));
-                    let _ = tx.send(Ok(self.chunk(file)));
+    fn run(self, rx: &Mutex<tokio::sync::mpsc::UnboundedReceiver<(Instant, ReaderCommand)>>) {
+        loop {
+            // Hold the lock only while waiting for a command, not while executing it.
+            let Some((enqueued, cmd)) = rx.lock().unwrap().blocking_recv() else {
+                return;
+            };
+            self.handle(enqueued, cmd);
+        }
+    }
+
+    /// Records a command's completion in the metrics.
+    ///
+    /// This is called before sending any reply, so that a caller who has received its reply
+    /// sees up-to-date statistics.
+    fn done(&self, histogram: &AtomicHistogram, enqueued: Instant) {
+        histogram.record(enqueued.elapsed());
+        self.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn handle(&self, enqueued: Instant, cmd: ReaderCommand) {
+        // OpenFile's Drop implementation takes care of closing the file on error paths and
+        // the CloseFile operation.
+        match cmd {
+            ReaderCommand::OpenFile {
+                span,
+                composite_id,
+                range,
+                key,
+                tx,
+            } => {
+                if tx.is_closed() {
+                    // avoid spending effort on expired commands
+                    self.done(&self.metrics.open, enqueued);
+                    return;
                 }
-                ReaderCommand::CloseFile(mut file) => {
-                    let composite_id = file.composite_id;
-                    let span = std::mem::replace(&mut file.span, tracing::Span::none());
-                    let _span_enter = span.enter();
-                    let _guard =
-                        TimerGuard::new(&RealClocks {}, || format!("close {composite_id}"));
-                    drop(file);
+                let span2 = span.clone();
+                let _span_enter = span2.enter();
+                let _timer_guard =
+                    TimerGuard::new(&RealClocks {}, || format!("open {composite_id}"));
+                let result = self.open(span, composite_id, range, key.as_deref());
+                self.done(&self.metrics.open, enqueued);
+                let _ = tx.send(result);
+            }
+            ReaderCommand::ReadNextChunk { file, tx } => {
+                if tx.is_closed() {
+                    // avoid spending effort on expired commands
+                    self.done(&self.metrics.read_chunk, enqueued);
+                    return;
                 }
+                let composite_id = file.composite_id;
+                let span2 = file.span.clone();
+                let _span_enter = span2.enter();
+                let _guard =
+                    TimerGuard::new(&RealClocks {}, || format!("read from {composite_id}"));
+                let result = self.chunk(file);
+                self.done(&self.metrics.read_chunk, enqueued);
+                let _ = tx.send(result);
+            }
+            ReaderCommand::CloseFile(mut file) => {
+                let composite_id = file.composite_id;
+                let span = std::mem::replace(&mut file.span, tracing::Span::none());
+                let _span_enter = span.enter();
+                let _guard = TimerGuard::new(&RealClocks {}, || format!("close {composite_id}"));
+                drop(file);
+                self.done(&self.metrics.close, enqueued);
+            }
+            ReaderCommand::UnlinkFile { composite_id, tx } => {
+                let result = self.dir.unlink_file(composite_id);
+                self.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                let _ = tx.send((composite_id, result));
+            }
+            ReaderCommand::Run(f) => {
+                // A panic mustn't cost the pool a thread or leave the queue depth inflated.
+                // The panic hook has already logged it.
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+                self.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
             }
         }
     }
@@ -299,9 +617,8 @@ impl ReaderInt {
         span: tracing::Span,
         composite_id: CompositeId,
         range: Range<u64>,
+        key: Option<&crypt::Key>,
     ) -> Result<SuccessfulRead, Error> {
-        let p = super::CompositeIdPath::from(composite_id);
-
         // Reader::open_file checks for an empty range, but check again right
         // before the unsafe block to make it easier to audit the safety constraints.
         assert!(range.start < range.end);
@@ -327,13 +644,37 @@ impl ReaderInt {
         })?;
         let map_len = std::num::NonZeroUsize::new(map_len).expect("range is non-empty");
 
-        let file = crate::fs::openat(self.dir.0, &p, OFlag::O_RDONLY, Mode::empty())
+        let file = self
+            .dir
+            .open_file(composite_id)
             .err_kind(ErrorKind::Unknown)?;
 
         // Check the actual on-disk file length. It's an error (a bug or filesystem corruption)
         // for it to be less than the requested read. Check for this now rather than crashing
         // with a SIGBUS or reading bad data at the end of the last page later.
         let metadata = file.metadata().err_kind(ErrorKind::Unknown)?;
+
+        // Only look for an encryption header when there's a key. Otherwise there can't be
+        // encrypted files, and the extra read would add a seek to every open.
+        if let Some(key) = key {
+            let mut header = [0u8; crypt::HEADER_LEN];
+            if metadata.len() >= crypt::HEADER_LEN as u64 {
+                file.read_exact_at(&mut header, 0)
+                    .err_kind(ErrorKind::Unknown)?;
+                if crypt::is_encrypted(&header) {
+                    let opener = key.opener(composite_id, &header)?;
+                    return self.open_encrypted(
+                        span,
+                        composite_id,
+                        range,
+                        file,
+                        metadata.len(),
+                        opener,
+                    );
+                }
+            }
+        }
+
         if metadata.len() < u64::try_from(offset).unwrap() + u64::try_from(map_len.get()).unwrap() {
             bail!(
                 OutOfRange,
@@ -379,21 +720,104 @@ impl ReaderInt {
             );
         }
 
-        Ok(self.chunk(OpenFile {
+        self.chunk(OpenFile {
             span,
             composite_id,
             map_ptr,
             map_pos: unaligned,
             map_len: map_len.get(),
-        }))
+            decrypt: None,
+            next_chunk_len: self.chunk_len,
+        })
     }
 
-    fn chunk(&self, mut file: OpenFile) -> SuccessfulRead {
-        // Read a chunk that's large enough to minimize thread handoffs but
-        // short enough to keep memory usage under control. It's hopefully
-        // unnecessary to worry about disk seeks; the madvise call should cause
-        // the kernel to read ahead.
-        let end = std::cmp::min(file.map_len, file.map_pos.saturating_add(1 << 16));
+    /// Opens an encrypted file, mapping it in full and finding the record containing
+    /// `range.start`.
+    fn open_encrypted(
+        &self,
+        span: tracing::Span,
+        composite_id: CompositeId,
+        range: Range<u64>,
+        file: std::fs::File,
+        file_len: u64,
+        opener: crypt::Opener,
+    ) -> Result<SuccessfulRead, Error> {
+        let map_len = usize::try_from(file_len).map_err(|e| {
+            err!(
+                OutOfRange,
+                msg("file {composite_id}'s len {file_len} exceeds usize::MAX"),
+                source(e),
+            )
+        })?;
+        let map_len = std::num::NonZeroUsize::new(map_len).expect("file has a header");
+        let map_ptr = unsafe {
+            nix::sys::mman::mmap(
+                None,
+                map_len,
+                nix::sys::mman::ProtFlags::PROT_READ,
+                nix::sys::mman::MapFlags::MAP_SHARED,
+                Some(&file),
+                0,
+            )
+        }
+        .map_err(|e| err!(e, msg("mmap failed for {composite_id} len={map_len}")))?;
+        if let Err(err) = unsafe {
+            nix::sys::mman::madvise(
+                map_ptr,
+                map_len.get(),
+                nix::sys::mman::MmapAdvise::MADV_SEQUENTIAL,
+            )
+        } {
+            tracing::warn!(%err, %composite_id, map_len, "madvise(MADV_SEQUENTIAL) failed");
+        }
+        let mut file = OpenFile {
+            span,
+            composite_id,
+            map_ptr,
+            map_pos: crypt::HEADER_LEN,
+            map_len: map_len.get(),
+            decrypt: None,
+            next_chunk_len: self.chunk_len,
+        };
+
+        // Skip records which end before the range starts.
+        let mut record = 0;
+        let mut record_start = 0;
+        loop {
+            let len = crypt::plaintext_len(file.rest())
+                .map_err(|e| err!(e, msg("file {composite_id}, range {range:?}")))?;
+            if record_start + len as u64 > range.start {
+                break;
+            }
+            record_start += len as u64;
+            file.map_pos += crypt::RECORD_OVERHEAD + len;
+            record += 1;
+        }
+        file.decrypt = Some(Decrypt {
+            opener,
+            record,
+            skip: usize::try_from(range.start - record_start).expect("skip is within a record"),
+            remaining: range.end - range.start,
+        });
+        self.chunk(file)
+    }
+
+    /// Returns the length to use for `file`'s current chunk, growing the following one.
+    fn take_chunk_len(&self, file: &mut OpenFile) -> usize {
+        let len = file.next_chunk_len;
+        file.next_chunk_len = std::cmp::min(len.saturating_mul(2), self.max_chunk_len);
+        len
+    }
+
+    fn chunk(&self, mut file: OpenFile) -> Result<SuccessfulRead, Error> {
+        if let Some(d) = file.decrypt.take() {
+            return self.decrypted_chunk(file, d);
+        }
+
+        // The MADV_SEQUENTIAL advice from open should cause the kernel to read ahead somewhat;
+        // `read_ahead` below asks for the whole next chunk.
+        let chunk_len = self.take_chunk_len(&mut file);
+        let end = std::cmp::min(file.map_len, file.map_pos.saturating_add(chunk_len));
         let mut chunk = Vec::new();
         let len = end.checked_sub(file.map_pos).unwrap();
         chunk.reserve_exact(len);
@@ -417,9 +841,64 @@ impl ReaderInt {
             None
         } else {
             file.map_pos = end;
+            if self.read_ahead {
+                self.advise_will_need(&file);
+            }
             Some(file)
         };
-        SuccessfulRead { chunk, file }
+        Ok(SuccessfulRead { chunk, file })
+    }
+
+    /// Asks the kernel to start reading `file`'s next chunk, so that disk I/O overlaps with
+    /// sending the current chunk.
+    fn advise_will_need(&self, file: &OpenFile) {
+        // madvise addresses must be aligned to page size boundaries; `map_ptr` already is.
+        let start = file.map_pos & !(self.page_size - 1);
+        let end = std::cmp::min(
+            file.map_len,
+            file.map_pos.saturating_add(file.next_chunk_len),
+        );
+        // SAFETY: [start, end) is within the mapping, and MADV_WILLNEED doesn't change its
+        // contents.
+        if let Err(err) = unsafe {
+            nix::sys::mman::madvise(
+                file.map_ptr.add(start),
+                end - start,
+                nix::sys::mman::MmapAdvise::MADV_WILLNEED,
+            )
+        } {
+            // This shouldn't happen but is "just" a performance problem.
+            tracing::warn!(
+                %err,
+                composite_id = %file.composite_id,
+                "madvise(MADV_WILLNEED) failed",
+            );
+        }
+    }
+
+    fn decrypted_chunk(&self, mut file: OpenFile, mut d: Decrypt) -> Result<SuccessfulRead, Error> {
+        let chunk_len = self.take_chunk_len(&mut file);
+        let mut chunk = Vec::new();
+        while d.remaining > 0 && chunk.len() < chunk_len {
+            let (plaintext, record_len) = d
+                .opener
+                .open(d.record, file.rest())
+                .map_err(|e| err!(e, msg("unable to read {}", file.composite_id)))?;
+            let plaintext = plaintext.get(d.skip..).unwrap_or_default();
+            let n = std::cmp::min(plaintext.len() as u64, d.remaining) as usize;
+            chunk.extend_from_slice(&plaintext[..n]);
+            d.remaining -= n as u64;
+            d.skip = 0;
+            d.record += 1;
+            file.map_pos += record_len;
+        }
+        let file = if d.remaining == 0 {
+            None
+        } else {
+            file.decrypt = Some(d);
+            Some(file)
+        };
+        Ok(SuccessfulRead { chunk, file })
     }
 }
 
@@ -434,10 +913,217 @@ mod tests {
             .prefix("moonfire-db-test-reader")
             .tempdir()
             .unwrap();
-        let fd = std::sync::Arc::new(super::super::Fd::open(tmpdir.path(), false).unwrap());
-        let reader = super::Reader::spawn(tmpdir.path(), fd);
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let reader = super::Reader::spawn("test", dir, &Default::default());
         std::fs::write(tmpdir.path().join("0123456789abcdef"), b"blah blah").unwrap();
-        let f = reader.open_file(crate::CompositeId(0x0123_4567_89ab_cdef), 1..8);
+        let f = reader.open_file(crate::CompositeId(0x0123_4567_89ab_cdef), 1..8, None);
+        assert_eq!(f.try_concat().await.unwrap(), b"lah bla");
+    }
+
+    #[tokio::test]
+    async fn multiple_threads() {
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-db-test-reader")
+            .tempdir()
+            .unwrap();
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let config = super::super::ReaderConfig {
+            threads: 3,
+            nice: Some(5),
+            io_priority: Some(super::super::IoPriority::BestEffort(7)),
+            max_queue_depth: None,
+            chunk_len: 1 << 16,
+            max_chunk_len: 1 << 16,
+            read_ahead: true,
+        };
+        let reader = super::Reader::spawn("test", dir, &config);
+
+        // Make the file larger than a chunk so reads go back and forth between threads.
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        std::fs::write(tmpdir.path().join("0123456789abcdef"), &data).unwrap();
+        let id = crate::CompositeId(0x0123_4567_89ab_cdef);
+        let (a, b) = futures::join!(
+            reader.open_file(id, 1..150_000, None).try_concat(),
+            reader.open_file(id, 5..200_000, None).try_concat(),
+        );
+        assert_eq!(a.unwrap(), &data[1..150_000]);
+        assert_eq!(b.unwrap(), &data[5..200_000]);
+        let stats = reader.stats();
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.open.count, 2);
+        assert_eq!(stats.read_chunk.count, 5); // 2 + 3 64 KiB chunks after the opens' chunks.
+    }
+
+    #[tokio::test]
+    async fn encrypted() {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-db-test-reader")
+            .tempdir()
+            .unwrap();
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let reader = super::Reader::spawn("test", dir, &Default::default());
+        let key =
+            std::sync::Arc::new(super::crypt::Key::parse(&STANDARD.encode([7u8; 32])).unwrap());
+
+        // Write records of varying sizes, some larger than a chunk.
+        let id = crate::CompositeId(0x0123_4567_89ab_cdef);
+        let data: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+        let (mut file, mut sealer) = key.new_file(id).unwrap();
+        for r in data.chunks(70_000).flat_map(|c| c.chunks(30_001)) {
+            file.extend_from_slice(&sealer.seal(r).unwrap());
+        }
+        std::fs::write(tmpdir.path().join("0123456789abcdef"), &file).unwrap();
+        for range in [
+            0..300_000,
+            1..8,
+            30_000..30_002,
+            29_999..250_000,
+            299_999..300_000,
+        ] {
+            let got = reader
+                .open_file(id, range.clone(), Some(key.clone()))
+                .try_concat()
+                .await
+                .unwrap();
+            assert!(
+                got == data[range.start as usize..range.end as usize],
+                "{range:?}"
+            );
+        }
+
+        // Reading past the end fails rather than returning short.
+        reader
+            .open_file(id, 299_999..300_001, Some(key.clone()))
+            .try_concat()
+            .await
+            .unwrap_err();
+
+        // Plaintext files are still readable when a key is supplied.
+        let id = crate::CompositeId(0x0123_4567_89ab_cdf0);
+        std::fs::write(tmpdir.path().join("0123456789abcdf0"), b"blah blah").unwrap();
+        let f = reader.open_file(id, 1..8, Some(key));
         assert_eq!(f.try_concat().await.unwrap(), b"lah bla");
     }
+
+    #[tokio::test]
+    async fn growing_chunks() {
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-db-test-reader")
+            .tempdir()
+            .unwrap();
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let config = super::super::ReaderConfig {
+            chunk_len: 1 << 12,
+            max_chunk_len: 1 << 14,
+            ..Default::default()
+        };
+        let reader = super::Reader::spawn("test", dir, &config);
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        std::fs::write(tmpdir.path().join("0123456789abcdef"), &data).unwrap();
+        let id = crate::CompositeId(0x0123_4567_89ab_cdef);
+        let chunks: Vec<Vec<u8>> = reader
+            .open_file(id, 3..100_000, None)
+            .try_collect()
+            .await
+            .unwrap();
+        let lens: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(
+            &lens[..4],
+            &[1 << 12, 1 << 13, 1 << 14, 1 << 14],
+            "chunk lens: {lens:?}"
+        );
+        assert_eq!(chunks.concat(), &data[3..]);
+
+        // Each file starts with a short chunk again.
+        let chunks: Vec<Vec<u8>> = reader
+            .open_file(id, 50_000..60_000, None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(chunks[0].len(), 1 << 12);
+        assert_eq!(chunks.concat(), &data[50_000..60_000]);
+    }
+
+    #[tokio::test]
+    async fn short_reads_complete_on_open() {
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-db-test-reader")
+            .tempdir()
+            .unwrap();
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let reader = super::Reader::spawn("test", dir, &Default::default());
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        std::fs::write(tmpdir.path().join("0123456789abcdef"), &data).unwrap();
+        let id = crate::CompositeId(0x0123_4567_89ab_cdef);
+        for range in [90_000..90_100, 4_095..4_097, 0..(1 << 16)] {
+            let got = reader.open_file(id, range.clone(), None).try_concat().await;
+            assert_eq!(
+                got.unwrap(),
+                &data[range.start as usize..range.end as usize]
+            );
+        }
+        let stats = reader.stats();
+        assert_eq!(stats.open.count, 3);
+        assert_eq!(stats.read_chunk.count, 0);
+        assert_eq!(stats.close.count, 0);
+    }
+
+    #[tokio::test]
+    async fn run_survives_panic() {
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-db-test-reader")
+            .tempdir()
+            .unwrap();
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let config = super::super::ReaderConfig {
+            threads: 1,
+            ..Default::default()
+        };
+        let reader = super::Reader::spawn("test", dir, &config);
+        reader.run(Box::new(|| panic!("intentional panic")));
+
+        // The lone thread is still alive and handles the next command.
+        std::fs::write(tmpdir.path().join("0123456789abcdef"), b"blah blah").unwrap();
+        let f = reader
+            .open_file(crate::CompositeId(0x0123_4567_89ab_cdef), 1..8, None)
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(f, b"lah bla");
+        assert_eq!(reader.stats().queue_depth, 0);
+    }
+
+    #[tokio::test]
+    async fn sheds_opens() {
+        crate::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-db-test-reader")
+            .tempdir()
+            .unwrap();
+        let dir = std::sync::Arc::new(super::super::Local::open(tmpdir.path(), false).unwrap());
+        let config = super::super::ReaderConfig {
+            max_queue_depth: Some(0),
+            ..Default::default()
+        };
+        let reader = super::Reader::spawn("test", dir, &config);
+        std::fs::write(tmpdir.path().join("0123456789abcdef"), b"blah blah").unwrap();
+        assert!(reader.is_saturated());
+        assert!(!reader.admit());
+        assert_eq!(reader.stats().shed_opens, 1);
+
+        // Opens for playback which has already been admitted still proceed.
+        let f = reader
+            .open_file(crate::CompositeId(0x0123_4567_89ab_cdef), 1..8, None)
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(f, b"lah bla");
+        assert_eq!(reader.stats().shed_opens, 1);
+    }
 }
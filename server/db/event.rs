@@ -0,0 +1,216 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Journal of notable server events, such as starts and stops and stream connection changes.
+//! See the `event` table within `schema.sql` for more information.
+
+use crate::recording;
+use base::{err, Error};
+use rusqlite::{params, Connection, Transaction};
+use std::ops::Range;
+
+/// The number of events to retain in the database. Older ones are deleted on flush.
+const MAX_EVENTS: i64 = 10_000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    /// The server started in read-write mode.
+    ServerStart,
+
+    /// The server shut down gracefully. A start without a preceding stop suggests a crash or
+    /// power loss.
+    ServerStop,
+
+    /// A sample file directory was opened.
+    DirOpen,
+
+    /// A stream received its first key frame after connecting.
+    StreamUp,
+
+    /// A stream's connection failed or was lost.
+    StreamDown,
+
+    /// A database flush failed, delaying recordings' commit.
+    FlushError,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::ServerStart => "serverStart",
+            EventKind::ServerStop => "serverStop",
+            EventKind::DirOpen => "dirOpen",
+            EventKind::StreamUp => "streamUp",
+            EventKind::StreamDown => "streamDown",
+            EventKind::FlushError => "flushError",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "serverStart" => EventKind::ServerStart,
+            "serverStop" => EventKind::ServerStop,
+            "dirOpen" => EventKind::DirOpen,
+            "streamUp" => EventKind::StreamUp,
+            "streamDown" => EventKind::StreamDown,
+            "flushError" => EventKind::FlushError,
+            _ => return None,
+        })
+    }
+}
+
+/// A row of the `event` table, or one not yet flushed to it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub time: recording::Time,
+    pub kind: EventKind,
+
+    /// The stream concerned, if any. This may refer to a since-deleted stream.
+    pub stream_id: Option<i32>,
+    pub message: String,
+}
+
+/// All state associated with events. This is the entry point to this module.
+#[derive(Default)]
+pub(crate) struct State {
+    /// Events added since the last flush, in the order added.
+    pending: Vec<Event>,
+}
+
+impl State {
+    pub(crate) fn add(&mut self, event: Event) {
+        self.pending.push(event);
+    }
+
+    /// Supplies `f` with events in `desired_time`, in ascending order of time, including those
+    /// not yet flushed.
+    pub(crate) fn list(
+        &self,
+        conn: &Connection,
+        desired_time: Range<recording::Time>,
+        f: &mut dyn FnMut(&Event),
+    ) -> Result<(), Error> {
+        let mut stmt = conn.prepare_cached(
+            r#"
+            select
+                time_90k,
+                kind,
+                stream_id,
+                message
+            from
+                event
+            where
+                ? <= time_90k and time_90k < ?
+            order by
+                time_90k, id
+            "#,
+        )?;
+        let mut rows = stmt.query(params![desired_time.start.0, desired_time.end.0])?;
+        let mut pending: Vec<&Event> = self
+            .pending
+            .iter()
+            .filter(|e| desired_time.contains(&e.time))
+            .collect();
+        pending.sort_by_key(|e| e.time);
+        let mut pending = pending.into_iter().peekable();
+        while let Some(row) = rows.next()? {
+            let kind: String = row.get(1)?;
+            let e = Event {
+                time: recording::Time(row.get(0)?),
+                kind: EventKind::parse(&kind)
+                    .ok_or_else(|| err!(DataLoss, msg("unknown event kind {kind:?}")))?,
+                stream_id: row.get(2)?,
+                message: row.get(3)?,
+            };
+            while let Some(p) = pending.next_if(|p| p.time < e.time) {
+                f(p);
+            }
+            f(&e);
+        }
+        pending.for_each(f);
+        Ok(())
+    }
+
+    /// Writes pending events within the flush transaction and trims the oldest ones.
+    pub(crate) fn flush(&mut self, tx: &Transaction) -> Result<(), Error> {
+        let mut stmt = tx.prepare_cached(
+            r#"
+            insert into event (time_90k, kind, stream_id, message)
+                       values (?,        ?,    ?,         ?)
+            "#,
+        )?;
+        for e in &self.pending {
+            stmt.execute(params![e.time.0, e.kind.as_str(), e.stream_id, &e.message])?;
+        }
+        if !self.pending.is_empty() {
+            tx.execute(
+                "delete from event where id <= (select max(id) from event) - ?",
+                params![MAX_EVENTS],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Marks that the previous `flush` was completed successfully.
+    pub(crate) fn post_flush(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, testutil};
+
+    fn event(time: i64, kind: EventKind) -> Event {
+        Event {
+            time: recording::Time(time),
+            kind,
+            stream_id: None,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn flush_and_list() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut s = State::default();
+        s.add(event(2, EventKind::ServerStart));
+        s.add(event(5, EventKind::DirOpen));
+        {
+            let tx = conn.transaction().unwrap();
+            s.flush(&tx).unwrap();
+            tx.commit().unwrap();
+        }
+        s.post_flush();
+        s.add(event(7, EventKind::StreamUp));
+
+        // Unflushed events are merged with flushed ones in time order.
+        s.add(event(3, EventKind::FlushError));
+        let mut all = Vec::new();
+        s.list(
+            &conn,
+            recording::Time::MIN..recording::Time::MAX,
+            &mut |e| all.push(e.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            all,
+            [
+                event(2, EventKind::ServerStart),
+                event(3, EventKind::FlushError),
+                event(5, EventKind::DirOpen),
+                event(7, EventKind::StreamUp),
+            ]
+        );
+        let mut some = Vec::new();
+        s.list(&conn, recording::Time(3)..recording::Time(7), &mut |e| {
+            some.push(e.kind)
+        })
+        .unwrap();
+        assert_eq!(some, [EventKind::FlushError, EventKind::DirOpen]);
+    }
+}
@@ -0,0 +1,92 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! The bits of H.264 parsing the writer needs to handle frame reordering (B-frames), plus
+//! sample entry access for serving other containers.
+
+use byteorder::{BigEndian, ByteOrder};
+use h264_reader::avcc::AvcDecoderConfigurationRecord;
+use h264_reader::nal::sps::PicOrderCntType;
+
+/// `profile_idc` of the Baseline profile, which doesn't allow B slices.
+const PROFILE_BASELINE: u8 = 66;
+
+/// Returns the `avcC` box contents within an `avc1` sample entry box, if present.
+pub fn avcc(sample_entry: &[u8]) -> Option<&[u8]> {
+    if sample_entry.len() < 94 || &sample_entry[4..8] != b"avc1" || &sample_entry[90..94] != b"avcC"
+    {
+        return None;
+    }
+    let len = usize::try_from(BigEndian::read_u32(&sample_entry[86..90])).ok()?;
+    sample_entry.get(94..86usize.checked_add(len)?)
+}
+
+/// Returns the maximum number of frames which may precede a frame in decode order but follow it
+/// in presentation order, given an `avc1` sample entry: `Some(0)` if the stream can't reorder
+/// frames, `None` if it can but doesn't say how much.
+///
+/// Entries which aren't parseable H.264 (such as MJPEG streams) are assumed not to reorder.
+pub(crate) fn max_reorder_frames(sample_entry: &[u8]) -> Option<u32> {
+    let Some(ctx) = avcc(sample_entry)
+        .and_then(|a| AvcDecoderConfigurationRecord::try_from(a).ok())
+        .and_then(|a| a.create_context().ok())
+    else {
+        return Some(0);
+    };
+    let Some(sps) = ctx.sps_by_id(h264_reader::nal::pps::ParamSetId::from_u32(0).unwrap()) else {
+        return Some(0);
+    };
+    if u8::from(sps.profile_idc) == PROFILE_BASELINE
+        || matches!(sps.pic_order_cnt, PicOrderCntType::TypeTwo)
+    {
+        // Picture order count type 2 means output order is decode order.
+        return Some(0);
+    }
+    sps.vui_parameters
+        .as_ref()
+        .and_then(|v| v.bitstream_restrictions.as_ref())
+        .map(|b| b.max_num_reorder_frames)
+}
+
+#[cfg(test)]
+pub(crate) mod testutil {
+    /// Returns an `avc1` sample entry for a 320x240 Main profile stream whose SPS uses picture
+    /// order count type 0 and has no VUI, so it may have B-frames of unspecified depth.
+    pub(crate) fn reordering_sample_entry() -> Vec<u8> {
+        let sps = b"\x67\x4d\x00\x1e\xf4\x0a\x0f\xc8";
+        let pps = b"\x68\xee\x3c\x80";
+        let mut avcc = b"\x00\x00\x00\x00avcC\x01\x4d\x00\x1e\xff\xe1".to_vec();
+        avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(sps);
+        avcc.push(1);
+        avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(pps);
+        let avcc_len = avcc.len() as u32;
+        avcc[0..4].copy_from_slice(&avcc_len.to_be_bytes());
+        let mut data = crate::testutil::TEST_VIDEO_SAMPLE_ENTRY_DATA[..86].to_vec();
+        data.extend_from_slice(&avcc);
+        let len = data.len() as u32;
+        data[0..4].copy_from_slice(&len.to_be_bytes());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_reorder_frames() {
+        // The test entry's SPS uses picture order count type 2.
+        assert_eq!(
+            super::max_reorder_frames(crate::testutil::TEST_VIDEO_SAMPLE_ENTRY_DATA),
+            Some(0)
+        );
+        assert_eq!(super::max_reorder_frames(&[0u8; 100]), Some(0));
+        assert_eq!(
+            super::max_reorder_frames(&testutil::reordering_sample_entry()),
+            None
+        );
+    }
+}
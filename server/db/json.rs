@@ -85,11 +85,36 @@ pub struct GlobalConfig {
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub signals: BTreeMap<u32, SignalConfig>,
 
+    /// Parameters for hashing new passwords. Existing hashes made with other parameters are
+    /// upgraded at each user's next password login. Set via `moonfire-nvr users rehash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<PasswordHashConfig>,
+
     #[serde(flatten)]
     pub unknown: BTreeMap<String, Value>,
 }
 sql!(GlobalConfig);
 
+/// Argon2id password hashing parameters, as in [`GlobalConfig::password_hash`].
+///
+/// Absent parameters use the `argon2` crate's defaults, which follow OWASP's recommendations:
+/// 19 MiB of memory, 2 iterations, and no parallelism.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordHashConfig {
+    /// The memory size, in KiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_kib: Option<u32>,
+
+    /// The number of passes over memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+
+    /// The degree of parallelism.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<u32>,
+}
+
 /// Sample file directory configuration, used in the `config` column of the `sample_file_dir` table.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -104,6 +129,10 @@ sql!(SampleFileDirConfig);
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SignalTypeConfig {
+    /// A human-readable name for the type, such as `motion` or `door`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+
     /// Information about possible enumeration values of this signal type.
     ///
     /// 0 always means `unknown`. Other values may be specified here to set
@@ -155,6 +184,22 @@ pub struct CameraConfig {
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
 
+    /// Where the camera is, as free-form text, e.g. `garage, north wall`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub location: String,
+
+    /// The camera's position, for map views.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coordinates: Option<Coordinates>,
+
+    /// The compass direction the camera faces, in degrees clockwise from true north (0-359).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction_deg: Option<u16>,
+
+    /// Labels for grouping and filtering cameras in clients, e.g. `outdoor`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
     /// The base URL for accessing ONVIF; `device_service` will be joined on
     /// automatically to form the device management service URL.
     /// Eg with `onvif_base=http://192.168.1.110:85`, the full
@@ -163,6 +208,19 @@ pub struct CameraConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub onvif_base_url: Option<Url>,
 
+    /// A URL which returns a JPEG of the camera's current view, such as
+    /// `http://192.168.1.110/cgi-bin/snapshot.cgi`. It's fetched with `username` and
+    /// `password` via HTTP basic authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_url: Option<Url>,
+
+    /// The maximum number of this camera's streams which may be connecting (from RTSP
+    /// `DESCRIBE` through `PLAY`) at once; others wait their turn. This helps cameras which
+    /// crash when all their streams reconnect simultaneously, e.g. after a network outage.
+    /// Unset or 0 means no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_connects: Option<u32>,
+
     /// The username to use when accessing the camera.
     /// If empty, no username or password will be supplied.
     #[serde(default, skip_serializing_if = "String::is_empty")]
@@ -172,6 +230,49 @@ pub struct CameraConfig {
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub password: String,
 
+    /// The name of an environment variable holding the password, as an alternative to
+    /// `password` which keeps it out of the database. It's read on each connection attempt.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password_env: String,
+
+    /// A file holding the password (minus any trailing newline), such as a Docker secret, as an
+    /// alternative to `password`. It's read on each connection attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+
+    /// How to authenticate to the camera's RTSP server: `auto` (the default, same as empty),
+    /// `basic`, `digest`, or `none`.
+    ///
+    /// `none` never sends credentials, even if `username` is set. `basic` and `digest` require
+    /// a `username`; the scheme is still chosen by the camera's challenge, and a failure names
+    /// the configured policy to help diagnose firmwares which only support one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rtsp_auth: String,
+
+    /// How to read the camera's streams: `retina` (the default, same as empty) or `ffmpeg`.
+    ///
+    /// `ffmpeg` runs the `ffmpeg` binary, for cameras whose RTSP servers are too broken for
+    /// Retina. It requires building with `--features=ffmpeg` and supports only H.264.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rtsp_backend: String,
+
+    /// The local IP address to connect to the camera from, for hosts on several networks where
+    /// the default route would send the camera's traffic the wrong way. Requires `rtsp_backend`
+    /// `ffmpeg`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<std::net::IpAddr>,
+
+    /// The network interface to connect to the camera through, such as `eth1`, as with Linux's
+    /// `SO_BINDTODEVICE`. Requires `rtsp_backend` `ffmpeg` and Linux.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub bind_interface: String,
+
+    /// The username and password, encrypted with the database's credential
+    /// key (see `crate::credential`). When set, `username` and `password` are
+    /// empty as stored in the database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_credentials: Option<String>,
+
     #[serde(flatten)]
     pub unknown: BTreeMap<String, Value>,
 }
@@ -180,13 +281,47 @@ sql!(CameraConfig);
 impl CameraConfig {
     pub fn is_empty(&self) -> bool {
         self.description.is_empty()
+            && self.location.is_empty()
+            && self.coordinates.is_none()
+            && self.direction_deg.is_none()
+            && self.tags.is_empty()
             && self.onvif_base_url.is_none()
+            && self.snapshot_url.is_none()
+            && self.max_concurrent_connects.is_none()
             && self.username.is_empty()
             && self.password.is_empty()
+            && self.password_env.is_empty()
+            && self.password_file.is_none()
+            && self.rtsp_auth.is_empty()
+            && self.rtsp_backend.is_empty()
+            && self.bind_address.is_none()
+            && self.bind_interface.is_empty()
+            && self.encrypted_credentials.is_none()
             && self.unknown.is_empty()
     }
 }
 
+/// A position in WGS 84 decimal degrees, as used by GPS and most map services.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, Value>,
+}
+
+// JSON can't represent NaN, so the `f64`s are never NaN and equality is reflexive.
+impl Eq for Coordinates {}
+
+impl Coordinates {
+    /// Returns true if the latitude is within [-90, 90] and the longitude within [-180, 180].
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+}
+
 /// Stream configuration, used in the `config` column of the `stream` table.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -199,7 +334,10 @@ pub struct StreamConfig {
     pub mode: String,
 
     /// The `rtsp://` URL to use for this stream, excluding username and
-    /// password.
+    /// password. `http://` and `https://` URLs of MJPEG streams are also
+    /// supported via the `ffmpeg` backend; see `CameraConfig::rtsp_backend`.
+    /// So are `v4l2:` URLs naming a local capture device, such as
+    /// `v4l2:///dev/video0?videoSize=1280x720`.
     ///
     /// In the future, this might support additional protocols such as `rtmp://`
     /// or even a private use URI scheme for the [Baichuan
@@ -234,11 +372,98 @@ pub struct StreamConfig {
     #[serde(default)]
     pub flush_if_sec: u32,
 
+    /// The number of seconds of recent frames to remember for live viewers
+    /// which reconnect with `live.m4s?resume=...`. Defaults to 10 seconds.
+    ///
+    /// Larger values let viewers ride out longer disconnections on reliable
+    /// hosts; smaller values save memory on small ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recent_frames_sec: Option<u32>,
+
+    /// The maximum total size of the recent frames remembered as above, in
+    /// bytes of video. Defaults to no limit beyond `recent_frames_sec`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recent_frames_bytes: Option<u64>,
+
+    /// The clockwise rotation to apply on playback, in degrees: 0, 90, 180, or 270.
+    ///
+    /// This is written to the track header of `.mp4` files so that, e.g., video from a
+    /// ceiling-mounted camera plays upright. Other values are treated as 0.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rotation: u16,
+
+    /// The lowest acceptable frame rate, averaged over 30 seconds. Lower rates are reported as
+    /// degraded output. Absent or 0 means no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_fps: Option<u32>,
+
+    /// The longest acceptable interval between key frames, in seconds. Longer intervals are
+    /// reported as degraded output. Absent or 0 means no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_key_frame_interval_sec: Option<u32>,
+
+    /// If present, dumps the raw bytes of each RTSP session for debugging.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtsp_capture: Option<RtspCaptureConfig>,
+
+    /// Overrides of RTSP library options, for cameras which need them. See
+    /// `guide/troubleshooting.md` for the supported keys.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rtsp_options: BTreeMap<String, String>,
+
+    /// If present, measures the level of the camera's audio and raises a signal when it's loud.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_level: Option<AudioLevelConfig>,
+
+    /// If true, also transcodes a low-resolution, low-frame-rate rendition of this stream, kept
+    /// only in RAM, for `live.m4s?preview=true`. This opens a second connection to the camera.
+    /// Requires building with `--features=ffmpeg`, a recorded stream, and a network URL.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preview: bool,
+
     #[serde(flatten)]
     pub unknown: BTreeMap<String, Value>,
 }
 sql!(StreamConfig);
 
+/// Configuration for capturing a stream's RTSP sessions, so that problems with a camera's output
+/// can be reproduced offline with `moonfire-nvr replay`.
+///
+/// While capturing, the stream always uses the `tcp` transport.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtspCaptureConfig {
+    /// The directory in which to write dumps, one per session.
+    pub dir: PathBuf,
+
+    /// The number of most recent sessions' dumps to keep. Defaults to 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sessions: Option<u32>,
+
+    /// The maximum size of each dump; bytes beyond this are not captured. Defaults to 64 MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_session_bytes: Option<u64>,
+}
+
+/// Configuration for raising a signal on loud audio, as from breaking glass or shouting.
+///
+/// The audio must be G.711 (PCMU or PCMA) or L16, and is measured only with the default RTSP
+/// backend. Audio isn't recorded.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioLevelConfig {
+    /// The signal to set to state 2 during each second in which the RMS audio level is at least
+    /// `threshold_dbfs`. Its type must define state 2.
+    pub signal_id: u32,
+
+    /// The threshold level, in dB relative to full scale, e.g. `-20`.
+    pub threshold_dbfs: i32,
+}
+
+fn is_zero(v: &u16) -> bool {
+    *v == 0
+}
+
 pub const STREAM_MODE_RECORD: &str = "record";
 
 impl StreamConfig {
@@ -247,6 +472,15 @@ impl StreamConfig {
             && self.url.is_none()
             && self.retain_bytes == 0
             && self.flush_if_sec == 0
+            && self.recent_frames_sec.is_none()
+            && self.recent_frames_bytes.is_none()
+            && self.rotation == 0
+            && self.min_fps.is_none()
+            && self.max_key_frame_interval_sec.is_none()
+            && self.rtsp_capture.is_none()
+            && self.rtsp_options.is_empty()
+            && self.audio_level.is_none()
+            && !self.preview
             && self.unknown.is_empty()
     }
 }
@@ -301,6 +535,24 @@ pub struct UserConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub unix_uid: Option<u64>,
 
+    /// If set, the time (in seconds since the epoch) at which the account expires, as for a guest.
+    ///
+    /// Authentication fails from then on, and the `run` subcommand also sets `disabled` soon
+    /// after.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration_time_sec: Option<i64>,
+
+    /// If set, the most bytes of recorded video the user may download per day (UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_quota_bytes_per_day: Option<u64>,
+
+    /// If true, `.mp4` downloads by this user always include a subtitle track naming them.
+    ///
+    /// This tags downloads rather than visibly watermarking them: players typically hide
+    /// subtitle tracks by default, and the track is easily removed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tag_downloads: bool,
+
     /// Preferences controlled by the user.
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub preferences: UserPreferences,
@@ -13,15 +13,20 @@
 
 #![cfg_attr(all(feature = "nightly", test), feature(test))]
 
+mod aggregates;
 pub mod auth;
 pub mod check;
 mod coding;
 mod compare;
+pub mod credential;
 pub mod days;
 pub mod db;
 pub mod dir;
+pub mod event;
 mod fs;
+pub mod h264;
 pub mod json;
+pub mod mover;
 mod proto {
     include!(concat!(env!("OUT_DIR"), "/mod.rs"));
 }
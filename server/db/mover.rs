@@ -0,0 +1,396 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Moving a stream's recordings to another sample file directory.
+//!
+//! A move happens in two steps:
+//!
+//! 1.  [`Move::copy_committed`] copies the stream's committed recordings to the
+//!     new directory. This may take a long time, but recording continues
+//!     meanwhile. Sample files are read via the old directory's reader threads,
+//!     so they're subject to its priority settings.
+//! 2.  [`Move::finish`] copies anything committed since, then in a single
+//!     transaction points the stream at the new directory and marks the old
+//!     copies as garbage. The caller must ensure the stream's writer doesn't
+//!     start new recordings until this returns. Usually only one or two
+//!     recordings are left to copy, so this is quick.
+//!
+//! If the move is interrupted before the switch, the new directory may hold
+//! stray copies. Retrying the move overwrites them.
+
+use crate::db::{self, CompositeId};
+use crate::dir;
+use base::clock::Clocks;
+use base::{bail, err, Error, FastHashSet};
+use std::io::Write as _;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Interval at which [`Move::finish`] polls for the stream's last recording to be synced.
+const SYNC_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Number of times [`Move::finish`] will retry copying before giving up.
+const MAX_FINISH_PASSES: usize = 5;
+
+/// A move of one stream's recordings to a new sample file directory.
+pub struct Move<C: Clocks + Clone> {
+    db: Arc<db::Database<C>>,
+    stream_id: i32,
+    src_dir_id: i32,
+    dst_dir_id: i32,
+    src: Arc<dir::SampleFileDir>,
+    dst: Arc<dir::SampleFileDir>,
+
+    /// Recordings which have been copied and synced to `dst`.
+    copied: FastHashSet<CompositeId>,
+}
+
+impl<C: Clocks + Clone> Move<C> {
+    /// Starts a move of `stream_id` to `dst_dir_id`, opening the destination directory if
+    /// necessary.
+    pub fn new(db: Arc<db::Database<C>>, stream_id: i32, dst_dir_id: i32) -> Result<Self, Error> {
+        let (src_dir_id, src, dst);
+        {
+            let mut l = db.lock();
+            let s = l
+                .streams_by_id()
+                .get(&stream_id)
+                .ok_or_else(|| err!(NotFound, msg("no such stream {stream_id}")))?;
+            src_dir_id = s.sample_file_dir_id.ok_or_else(|| {
+                err!(
+                    FailedPrecondition,
+                    msg("stream {stream_id} has no sample file dir")
+                )
+            })?;
+            if src_dir_id == dst_dir_id {
+                bail!(
+                    InvalidArgument,
+                    msg("stream {stream_id} is already in dir {dst_dir_id}")
+                );
+            }
+            l.open_sample_file_dirs(&[src_dir_id, dst_dir_id])?;
+            check_no_pending_garbage(&l, stream_id, dst_dir_id)?;
+            let dirs = l.sample_file_dirs_by_id();
+            src = dirs.get(&src_dir_id).unwrap().get()?;
+            dst = dirs.get(&dst_dir_id).unwrap().get()?;
+        }
+        Ok(Move {
+            db,
+            stream_id,
+            src_dir_id,
+            dst_dir_id,
+            src,
+            dst,
+            copied: FastHashSet::default(),
+        })
+    }
+
+    pub fn src_dir_id(&self) -> i32 {
+        self.src_dir_id
+    }
+
+    pub fn dst_dir_id(&self) -> i32 {
+        self.dst_dir_id
+    }
+
+    /// Returns the destination directory, for use by the stream's writer after the move.
+    pub fn dst(&self) -> &Arc<dir::SampleFileDir> {
+        &self.dst
+    }
+
+    /// Copies all committed recordings which haven't been copied already.
+    ///
+    /// The database lock is held only while listing recordings, not while copying.
+    pub fn copy_committed(&mut self) -> Result<(), Error> {
+        let mut to_copy = Vec::new();
+        {
+            let l = self.db.lock();
+            let cum_recordings = l
+                .streams_by_id()
+                .get(&self.stream_id)
+                .ok_or_else(|| err!(NotFound, msg("no such stream {}", self.stream_id)))?
+                .cum_recordings;
+            l.list_recordings_by_id(self.stream_id, 0..cum_recordings, &mut |r| {
+                if !self.copied.contains(&r.id) {
+                    let encrypted = (r.flags & db::RecordingFlags::Encrypted as i32) != 0;
+                    to_copy.push((r.id, r.sample_file_bytes, encrypted));
+                }
+                Ok(())
+            })?;
+        }
+        if to_copy.is_empty() {
+            return Ok(());
+        }
+        info!(
+            stream_id = self.stream_id,
+            "copying {} recordings to dir {}",
+            to_copy.len(),
+            self.dst_dir_id
+        );
+        let mut copied = Vec::with_capacity(to_copy.len());
+        for (id, len, encrypted) in to_copy {
+            if let Err(e) = copy_file(&self.src, &self.dst, id, len, encrypted) {
+                if self.still_committed(id)? {
+                    return Err(e);
+                }
+
+                // Retention deleted the recording while it was being copied. The file has been
+                // unlinked from `src`, so this one is unneeded too.
+                warn!(%id, "recording deleted during move");
+                let _ = self.dst.unlink_file(id);
+                continue;
+            }
+            copied.push(id);
+        }
+        self.dst.sync()?;
+        self.copied.extend(copied);
+        Ok(())
+    }
+
+    /// Returns true iff `id` is still a committed recording, rather than having been deleted.
+    fn still_committed(&self, id: CompositeId) -> Result<bool, Error> {
+        let mut found = false;
+        self.db.lock().list_recordings_by_id(
+            self.stream_id,
+            id.recording()..id.recording() + 1,
+            &mut |_| {
+                found = true;
+                Ok(())
+            },
+        )?;
+        Ok(found)
+    }
+
+    /// Finishes the move.
+    ///
+    /// The caller must ensure the stream's writer has closed its last recording (if any) and
+    /// won't start another until this returns. This waits for that recording to be synced,
+    /// flushes the database, copies whatever remains, and switches the stream.
+    pub fn finish(mut self, shutdown_rx: &base::shutdown::Receiver) -> Result<(), Error> {
+        let mut passes = 0;
+        loop {
+            {
+                let mut l = self.db.lock();
+                let s = l
+                    .streams_by_id()
+                    .get(&self.stream_id)
+                    .ok_or_else(|| err!(NotFound, msg("no such stream {}", self.stream_id)))?;
+                if s.sample_file_dir_id != Some(self.src_dir_id) {
+                    bail!(
+                        Aborted,
+                        msg("stream {} changed dirs during move", self.stream_id)
+                    );
+                }
+                if s.uncommitted.len() > s.synced_recordings {
+                    // Wait for the syncer to sync the writer's final recording. Then the flush
+                    // below will commit it.
+                    let unsynced = s.uncommitted.len() - s.synced_recordings;
+                    drop(l);
+                    shutdown_rx
+                        .check()
+                        .map_err(|e| err!(Cancelled, source(e)))?;
+                    tracing::trace!(unsynced, "waiting for recordings to sync");
+                    std::thread::sleep(SYNC_POLL_INTERVAL);
+                    continue;
+                }
+                l.flush("move stream")?;
+                check_no_pending_garbage(&l, self.stream_id, self.dst_dir_id)?;
+                let cum_recordings = l
+                    .streams_by_id()
+                    .get(&self.stream_id)
+                    .unwrap()
+                    .cum_recordings;
+                let mut ids = Vec::new();
+                let mut missing = false;
+                l.list_recordings_by_id(self.stream_id, 0..cum_recordings, &mut |r| {
+                    missing |= !self.copied.contains(&r.id);
+                    ids.push(r.id);
+                    Ok(())
+                })?;
+                if !missing {
+                    l.switch_stream_dir(self.stream_id, self.dst_dir_id, &ids)?;
+                    drop(l);
+
+                    // Remove copies of recordings which retention deleted during the move.
+                    let ids: FastHashSet<_> = ids.into_iter().collect();
+                    for &id in self.copied.difference(&ids) {
+                        if let Err(err) = self.dst.unlink_file(id) {
+                            warn!(%err, %id, "unable to unlink stale copy");
+                        }
+                    }
+                    info!(
+                        stream_id = self.stream_id,
+                        "moved {} recordings from dir {} to dir {}",
+                        ids.len(),
+                        self.src_dir_id,
+                        self.dst_dir_id
+                    );
+                    return Ok(());
+                }
+            }
+            passes += 1;
+            if passes == MAX_FINISH_PASSES {
+                bail!(
+                    Unavailable,
+                    msg(
+                        "stream {}'s recordings kept changing during move; try again",
+                        self.stream_id
+                    )
+                );
+            }
+            self.copy_committed()?;
+        }
+    }
+}
+
+/// Ensures `dir_id` has no garbage from `stream_id`, as left by a previous move away from it.
+/// Copying over such a file would be lost when the garbage is collected.
+fn check_no_pending_garbage(
+    l: &db::LockedDatabase,
+    stream_id: i32,
+    dir_id: i32,
+) -> Result<(), Error> {
+    let d = l
+        .sample_file_dirs_by_id()
+        .get(&dir_id)
+        .ok_or_else(|| err!(NotFound, msg("no such dir {dir_id}")))?;
+    if d.garbage_needs_unlink
+        .iter()
+        .chain(&d.garbage_unlinked)
+        .any(|id| id.stream() == stream_id)
+    {
+        bail!(
+            FailedPrecondition,
+            msg(
+                "dir {} has garbage from stream {stream_id} pending deletion; try again later",
+                d.path.display()
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Copies a sample file of `len` bytes from `src` to `dst`, replacing any stray copy from an
+/// earlier attempt, and syncs it.
+///
+/// Encrypted files are copied as is rather than through the (decrypting) reader threads.
+fn copy_file(
+    src: &dir::SampleFileDir,
+    dst: &dir::SampleFileDir,
+    id: CompositeId,
+    len: i32,
+    encrypted: bool,
+) -> Result<(), Error> {
+    let len = u64::try_from(len).map_err(|_| err!(DataLoss, msg("{id} has negative length")))?;
+    let mut f = match dst.create_file(id) {
+        Err(nix::Error::EEXIST) => {
+            dst.unlink_file(id)?;
+            dst.create_file(id)?
+        }
+        r => r?,
+    };
+    if encrypted {
+        let mut s = src.open_file_sync(id)?;
+        std::io::copy(&mut s, &mut f).map_err(|e| err!(e, msg("unable to copy {id}")))?;
+    } else if len > 0 {
+        for chunk in futures::executor::block_on_stream(src.open_file(id, 0..len)) {
+            f.write_all(&chunk?)
+                .map_err(|e| err!(e, msg("unable to write {id}")))?;
+        }
+    }
+    f.sync_all()
+        .map_err(|e| err!(e, msg("unable to sync {id}")))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{self, TIME_UNITS_PER_SEC};
+    use crate::testutil::{self, TestDb, TEST_STREAM_ID};
+    use base::clock;
+
+    fn add_recording(tdb: &TestDb<clock::RealClocks>, data: &[u8]) -> CompositeId {
+        let mut l = tdb.db.lock();
+        let video_sample_entry_id = l
+            .insert_video_sample_entry(db::VideoSampleEntryToInsert {
+                width: 1920,
+                height: 1080,
+                pasp_h_spacing: 1,
+                pasp_v_spacing: 1,
+                data: [0u8; 100].to_vec(),
+                rfc6381_codec: "avc1.000000".to_owned(),
+            })
+            .unwrap();
+        let (id, _) = l
+            .add_recording(
+                TEST_STREAM_ID,
+                db::RecordingToInsert {
+                    start: recording::Time(1430006400i64 * TIME_UNITS_PER_SEC),
+                    video_sample_entry_id,
+                    sample_file_bytes: data.len() as i32,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let dir = tdb.dirs_by_stream_id.get(&TEST_STREAM_ID).unwrap();
+        let mut f = dir.create_file(id).unwrap();
+        f.write_all(data).unwrap();
+        l.mark_synced(id).unwrap();
+        id
+    }
+
+    #[test]
+    fn move_stream() {
+        testutil::init();
+        let tdb = TestDb::new(clock::RealClocks {});
+        let dst_tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let (src_dir_id, dst_dir_id) = {
+            let mut l = tdb.db.lock();
+            let src_dir_id = l
+                .streams_by_id()
+                .get(&TEST_STREAM_ID)
+                .unwrap()
+                .sample_file_dir_id
+                .unwrap();
+            let dst_dir_id = l.add_sample_file_dir(dst_tmpdir.path().to_owned()).unwrap();
+            (src_dir_id, dst_dir_id)
+        };
+        let first = add_recording(&tdb, b"first");
+        tdb.db.lock().flush("test").unwrap();
+
+        let mut m = Move::new(tdb.db.clone(), TEST_STREAM_ID, dst_dir_id).unwrap();
+        m.copy_committed().unwrap();
+
+        // This recording is committed only by the flush within `finish`.
+        let second = add_recording(&tdb, b"second");
+        let dst = m.dst().clone();
+        let generation = tdb.db.stream_dirs_generation();
+        m.finish(&tdb.shutdown_rx).unwrap();
+        assert_ne!(tdb.db.stream_dirs_generation(), generation);
+
+        let l = tdb.db.lock();
+        let s = l.streams_by_id().get(&TEST_STREAM_ID).unwrap();
+        assert_eq!(s.sample_file_dir_id, Some(dst_dir_id));
+        let src = l.sample_file_dirs_by_id().get(&src_dir_id).unwrap();
+        for id in [first, second] {
+            // The syncer may have already collected the garbage.
+            assert!(
+                src.garbage_needs_unlink.contains(&id) || src.garbage_unlinked.contains(&id),
+                "{id}"
+            );
+        }
+        drop(l);
+        for (id, expected) in [(first, &b"first"[..]), (second, &b"second"[..])] {
+            let data: Vec<u8> =
+                futures::executor::block_on_stream(dst.open_file(id, 0..expected.len() as u64))
+                    .flat_map(|c| c.unwrap())
+                    .collect();
+            assert_eq!(&data[..], expected);
+        }
+    }
+}
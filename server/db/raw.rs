@@ -27,7 +27,9 @@ const LIST_RECORDINGS_BY_TIME_SQL: &str = r#"
         recording.video_sync_samples,
         recording.video_sample_entry_id,
         recording.open_id,
-        recording.end_reason
+        recording.end_reason,
+        recording.packets_lost,
+        recording.damaged_frames
     from
         recording
     where
@@ -53,6 +55,8 @@ const LIST_RECORDINGS_BY_ID_SQL: &str = r#"
         recording.video_sample_entry_id,
         recording.open_id,
         recording.end_reason,
+        recording.packets_lost,
+        recording.damaged_frames,
         recording.prev_media_duration_90k,
         recording.prev_runs
     from
@@ -161,11 +165,13 @@ fn list_recordings_inner(
             video_sample_entry_id: row.get(9).err_kind(ErrorKind::Internal)?,
             open_id: row.get(10).err_kind(ErrorKind::Internal)?,
             end_reason: row.get(11).err_kind(ErrorKind::Internal)?,
+            packets_lost: row.get(12).err_kind(ErrorKind::Internal)?,
+            damaged_frames: row.get(13).err_kind(ErrorKind::Internal)?,
             prev_media_duration_and_runs: match include_prev {
                 false => None,
                 true => Some((
-                    recording::Duration(row.get(12).err_kind(ErrorKind::Internal)?),
-                    row.get(13).err_kind(ErrorKind::Internal)?,
+                    recording::Duration(row.get(14).err_kind(ErrorKind::Internal)?),
+                    row.get(15).err_kind(ErrorKind::Internal)?,
                 )),
             },
         })?;
@@ -185,84 +191,188 @@ pub(crate) fn read_meta(conn: &rusqlite::Connection) -> Result<(Uuid, GlobalConf
     )?)
 }
 
-/// Inserts the specified recording (for from `try_flush` only).
-pub(crate) fn insert_recording(
+pub(crate) fn write_meta_config(
+    conn: &rusqlite::Connection,
+    config: &GlobalConfig,
+) -> Result<(), Error> {
+    conn.execute("update meta set config = ?", params![config])?;
+    Ok(())
+}
+
+/// The maximum number of rows in each multi-row `insert` statement of [`insert_recordings`].
+///
+/// This keeps the bound parameters below SQLite's historical limit of 999 per statement.
+const INSERT_BATCH_ROWS: usize = 32;
+
+const INSERT_RECORDING_COLUMNS: &[&str] = &[
+    "composite_id",
+    "stream_id",
+    "open_id",
+    "run_offset",
+    "flags",
+    "sample_file_bytes",
+    "start_time_90k",
+    "prev_media_duration_90k",
+    "prev_runs",
+    "wall_duration_90k",
+    "media_duration_delta_90k",
+    "video_samples",
+    "video_sync_samples",
+    "video_sample_entry_id",
+    "end_reason",
+    "packets_lost",
+    "damaged_frames",
+];
+
+const INSERT_RECORDING_INTEGRITY_COLUMNS: &[&str] =
+    &["composite_id", "local_time_delta_90k", "sample_file_blake3"];
+
+const INSERT_RECORDING_PLAYBACK_COLUMNS: &[&str] = &["composite_id", "video_index"];
+
+/// Returns SQL to insert `rows` rows of the given columns into `table`.
+fn multi_row_insert_sql(table: &str, columns: &[&str], rows: usize) -> String {
+    let row = format!("({})", vec!["?"; columns.len()].join(", "));
+    format!(
+        "insert into {table} ({}) values {}",
+        columns.join(", "),
+        vec![row; rows].join(", ")
+    )
+}
+
+/// Binds successive positional parameters of a multi-row statement.
+struct Binder<'a, 'conn> {
+    stmt: &'a mut rusqlite::Statement<'conn>,
+    n: usize,
+}
+
+impl<'a, 'conn> Binder<'a, 'conn> {
+    fn new(stmt: &'a mut rusqlite::Statement<'conn>) -> Self {
+        Binder { stmt, n: 0 }
+    }
+
+    fn bind<T: rusqlite::ToSql>(&mut self, value: T) -> rusqlite::Result<()> {
+        self.n += 1;
+        self.stmt.raw_bind_parameter(self.n, value)
+    }
+}
+
+/// Inserts the specified recordings (for `flush` only).
+///
+/// Rows are inserted in batches of up to `INSERT_BATCH_ROWS` per statement, which is
+/// significantly faster than a statement per row on slow storage.
+pub(crate) fn insert_recordings(
     tx: &rusqlite::Transaction,
     o: &db::Open,
-    id: CompositeId,
-    r: &db::RecordingToInsert,
+    recordings: &[(CompositeId, &db::RecordingToInsert)],
 ) -> Result<(), Error> {
-    let mut stmt = tx.prepare_cached(
-        r#"
-            insert into recording (composite_id, stream_id, open_id, run_offset, flags,
-                               sample_file_bytes, start_time_90k, prev_media_duration_90k,
-                               prev_runs, wall_duration_90k, media_duration_delta_90k,
-                               video_samples, video_sync_samples, video_sample_entry_id,
-                               end_reason)
-                       values (:composite_id, :stream_id, :open_id, :run_offset, :flags,
-                               :sample_file_bytes, :start_time_90k, :prev_media_duration_90k,
-                               :prev_runs, :wall_duration_90k, :media_duration_delta_90k,
-                               :video_samples, :video_sync_samples, :video_sample_entry_id,
-                               :end_reason)
+    for chunk in recordings.chunks(INSERT_BATCH_ROWS) {
+        let ids = || format!("{}..={}", chunk[0].0, chunk[chunk.len() - 1].0);
+        let mut stmt = tx.prepare_cached(&multi_row_insert_sql(
+            "recording",
+            INSERT_RECORDING_COLUMNS,
+            chunk.len(),
+        ))?;
+        let mut b = Binder::new(&mut stmt);
+        for &(id, r) in chunk {
+            b.bind(id.0)?;
+            b.bind(i64::from(id.stream()))?;
+            b.bind(o.id)?;
+            b.bind(r.run_offset)?;
+            b.bind(r.flags)?;
+            b.bind(r.sample_file_bytes)?;
+            b.bind(r.start.0)?;
+            b.bind(r.prev_media_duration.0)?;
+            b.bind(r.prev_runs)?;
+            b.bind(r.wall_duration_90k)?;
+            b.bind(r.media_duration_90k - r.wall_duration_90k)?;
+            b.bind(r.video_samples)?;
+            b.bind(r.video_sync_samples)?;
+            b.bind(r.video_sample_entry_id)?;
+            b.bind(r.end_reason.as_deref())?;
+            b.bind(r.packets_lost)?;
+            b.bind(r.damaged_frames)?;
+        }
+        stmt.raw_execute()
+            .map_err(|e| err!(e, msg("unable to insert recordings {}", ids())))?;
+
+        let mut stmt = tx.prepare_cached(&multi_row_insert_sql(
+            "recording_integrity",
+            INSERT_RECORDING_INTEGRITY_COLUMNS,
+            chunk.len(),
+        ))?;
+        let mut b = Binder::new(&mut stmt);
+        for &(id, r) in chunk {
+            let delta = match r.run_offset {
+                0 => None,
+                _ => Some(r.local_time_delta.0),
+            };
+            b.bind(id.0)?;
+            b.bind(delta)?;
+            b.bind(r.sample_file_blake3.as_ref().map(|h| &h[..]))?;
+        }
+        stmt.raw_execute()
+            .map_err(|e| err!(e, msg("unable to insert recording_integrity for {}", ids())))?;
+
+        let mut stmt = tx.prepare_cached(&multi_row_insert_sql(
+            "recording_playback",
+            INSERT_RECORDING_PLAYBACK_COLUMNS,
+            chunk.len(),
+        ))?;
+        let mut b = Binder::new(&mut stmt);
+        for &(id, r) in chunk {
+            b.bind(id.0)?;
+            b.bind(&r.video_index)?;
+        }
+        stmt.raw_execute()
+            .map_err(|e| err!(e, msg("unable to insert recording_playback for {}", ids())))?;
+    }
+
+    // Sessions are one per run rather than per recording, so they're rare enough not to batch.
+    for &(id, r) in recordings {
+        let Some(s) = r.rtsp_session.as_ref() else {
+            continue;
+        };
+        let mut stmt = tx.prepare_cached(
+            r#"
+            insert into recording_rtsp_session (composite_id, sdp, transport, server)
+                                        values (?,            ?,   ?,         ?)
             "#,
-    )?;
-    stmt.execute(named_params! {
-        ":composite_id": id.0,
-        ":stream_id": i64::from(id.stream()),
-        ":open_id": o.id,
-        ":run_offset": r.run_offset,
-        ":flags": r.flags,
-        ":sample_file_bytes": r.sample_file_bytes,
-        ":start_time_90k": r.start.0,
-        ":wall_duration_90k": r.wall_duration_90k,
-        ":media_duration_delta_90k": r.media_duration_90k - r.wall_duration_90k,
-        ":prev_media_duration_90k": r.prev_media_duration.0,
-        ":prev_runs": r.prev_runs,
-        ":video_samples": r.video_samples,
-        ":video_sync_samples": r.video_sync_samples,
-        ":video_sample_entry_id": r.video_sample_entry_id,
-        ":end_reason": r.end_reason.as_deref(),
-    })
-    .map_err(|e| {
-        err!(
-            e,
-            msg("unable to insert recording for recording {id} {r:#?}")
-        )
-    })?;
+        )?;
+        stmt.execute(params![id.0, &s.sdp, &s.transport, &s.server])
+            .map_err(|e| err!(e, msg("unable to insert recording_rtsp_session for {id}")))?;
+    }
+    Ok(())
+}
 
-    let mut stmt = tx.prepare_cached(
+/// Gets the RTSP session details of the run whose first recording is `id`, if recorded.
+pub(crate) fn get_rtsp_session(
+    conn: &rusqlite::Connection,
+    id: CompositeId,
+) -> Result<Option<db::RtspSession>, Error> {
+    let mut stmt = conn.prepare_cached(
         r#"
-            insert into recording_integrity (composite_id,  local_time_delta_90k,
-                                             sample_file_blake3)
-                                     values (:composite_id, :local_time_delta_90k,
-                                             :sample_file_blake3)
-            "#,
+        select sdp, transport, server from recording_rtsp_session where composite_id = ?
+        "#,
     )?;
-    let blake3 = r.sample_file_blake3.as_ref().map(|b| &b[..]);
-    let delta = match r.run_offset {
-        0 => None,
-        _ => Some(r.local_time_delta.0),
+    let mut rows = stmt.query(params![id.0])?;
+    let Some(row) = rows.next()? else {
+        return Ok(None);
     };
-    stmt.execute(named_params! {
-        ":composite_id": id.0,
-        ":local_time_delta_90k": delta,
-        ":sample_file_blake3": blake3,
-    })
-    .map_err(|e| err!(e, msg("unable to insert recording_integrity for {r:#?}")))?;
-
-    let mut stmt = tx.prepare_cached(
-        r#"
-            insert into recording_playback (composite_id,  video_index)
-                                    values (:composite_id, :video_index)
-            "#,
-    )?;
-    stmt.execute(named_params! {
-        ":composite_id": id.0,
-        ":video_index": &r.video_index,
-    })
-    .map_err(|e| err!(e, msg("unable to insert recording_playback for {r:#?}")))?;
+    Ok(Some(db::RtspSession {
+        sdp: row.get(0)?,
+        transport: row.get(1)?,
+        server: row.get(2)?,
+    }))
+}
 
-    Ok(())
+/// Inserts a single recording, as in [`insert_recordings`].
+pub(crate) fn insert_recording(
+    tx: &rusqlite::Transaction,
+    o: &db::Open,
+    id: CompositeId,
+    r: &db::RecordingToInsert,
+) -> Result<(), Error> {
+    insert_recordings(tx, o, &[(id, r)])
 }
 
 /// Transfers the given recording range from the `recording` and associated tables to the `garbage`
@@ -303,6 +413,14 @@ pub(crate) fn delete_recordings(
           composite_id < :end
         "#,
     )?;
+    let mut del_rtsp_session = tx.prepare_cached(
+        r#"
+        delete from recording_rtsp_session
+        where
+          :start <= composite_id and
+          composite_id < :end
+        "#,
+    )?;
     let mut del_main = tx.prepare_cached(
         r#"
         delete from recording
@@ -343,6 +461,14 @@ pub(crate) fn delete_recordings(
             ),
         );
     }
+    let n_rtsp_session = del_rtsp_session.execute(p)?;
+    if n_rtsp_session > n {
+        // fewer is okay; there's at most one per run.
+        bail!(
+            Internal,
+            msg("inserted {n} garbage rows but deleted {n_rtsp_session} recording_rtsp_session rows!"),
+        );
+    }
     let n_main = del_main.execute(p)?;
     if n_main != n {
         bail!(
@@ -357,14 +483,16 @@ pub(crate) fn delete_recordings(
 /// been `unlink()`ed and the parent directory `fsync()`ed.
 pub(crate) fn mark_sample_files_deleted(
     tx: &rusqlite::Transaction,
+    dir_id: i32,
     ids: &[CompositeId],
 ) -> Result<(), Error> {
     if ids.is_empty() {
         return Ok(());
     }
-    let mut stmt = tx.prepare_cached("delete from garbage where composite_id = ?")?;
+    let mut stmt =
+        tx.prepare_cached("delete from garbage where sample_file_dir_id = ? and composite_id = ?")?;
     for &id in ids {
-        let changes = stmt.execute(params![id.0])?;
+        let changes = stmt.execute(params![dir_id, id.0])?;
         if changes != 1 {
             // panic rather than return error. Errors get retried indefinitely, but there's no
             // recovery from this condition.
@@ -378,6 +506,21 @@ pub(crate) fn mark_sample_files_deleted(
     Ok(())
 }
 
+/// Marks the given sample files as garbage within the given directory, without touching the
+/// `recording` table. This is used when the recordings have been copied to another directory.
+pub(crate) fn insert_garbage(
+    tx: &rusqlite::Transaction,
+    dir_id: i32,
+    ids: &[CompositeId],
+) -> Result<(), Error> {
+    let mut stmt =
+        tx.prepare_cached("insert into garbage (sample_file_dir_id, composite_id) values (?, ?)")?;
+    for &id in ids {
+        stmt.execute(params![dir_id, id.0])?;
+    }
+    Ok(())
+}
+
 /// Gets the time range of recordings for the given stream.
 pub(crate) fn get_range(
     conn: &rusqlite::Connection,
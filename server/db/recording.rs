@@ -44,12 +44,15 @@ pub fn rescale(from_off_90k: i32, from_duration_90k: i32, to_duration_90k: i32)
     .unwrap()
 }
 
+/// Bit within `SampleIndexIterator::i_and_is_key` set if the index has composition offsets.
+const HAS_COMPOSITION_OFFSETS: u32 = 0x4000_0000;
+
 /// An iterator through a sample index (as described in `design/recording.md`).
 /// Initially invalid; call `next()` before each read.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct SampleIndexIterator {
-    /// The index byte position of the next sample to read (low 31 bits) and if the current
-    /// same is a key frame (high bit).
+    /// The index byte position of the next sample to read (low 30 bits), if the index has
+    /// composition offsets (bit 30), and if the current same is a key frame (high bit).
     i_and_is_key: u32,
 
     /// The starting data byte position of this sample within the segment.
@@ -64,16 +67,35 @@ pub struct SampleIndexIterator {
     /// The byte length of this frame.
     pub bytes: i32,
 
+    /// The difference between this sample's presentation time and its (decode) start time, in
+    /// 90 kHz units. Always zero unless the index has composition offsets.
+    pub composition_offset_90k: i32,
+
     /// The byte length of the last frame of the "other" type: if this one is key, the last
     /// non-key; if this one is non-key, the last key.
     bytes_other: i32,
 }
 
 impl SampleIndexIterator {
+    /// Returns an iterator for the index of a recording with the given flags. The index has a
+    /// third varint per sample iff `flags` includes [`db::RecordingFlags::CompositionOffsets`].
+    /// `default()` is equivalent to `new(0)`.
+    pub fn new(flags: i32) -> Self {
+        let has_offsets = (flags & db::RecordingFlags::CompositionOffsets as i32) != 0;
+        SampleIndexIterator {
+            i_and_is_key: if has_offsets {
+                HAS_COMPOSITION_OFFSETS
+            } else {
+                0
+            },
+            ..Default::default()
+        }
+    }
+
     pub fn next(&mut self, data: &[u8]) -> Result<bool, Error> {
         self.pos += self.bytes;
         self.start_90k += self.duration_90k;
-        let i = (self.i_and_is_key & 0x7FFF_FFFF) as usize;
+        let i = (self.i_and_is_key & 0x3FFF_FFFF) as usize;
         if i == data.len() {
             return Ok(false);
         }
@@ -81,10 +103,19 @@ impl SampleIndexIterator {
             Ok(tuple) => tuple,
             Err(()) => bail!(DataLoss, msg("bad varint 1 at offset {i}")),
         };
-        let (raw2, i2) = match decode_varint32(data, i1) {
+        let (raw2, mut i2) = match decode_varint32(data, i1) {
             Ok(tuple) => tuple,
             Err(()) => bail!(DataLoss, msg("bad varint 2 at offset {i1}")),
         };
+        let has_offsets = self.i_and_is_key & HAS_COMPOSITION_OFFSETS;
+        if has_offsets != 0 {
+            let (raw3, i3) = match decode_varint32(data, i2) {
+                Ok(tuple) => tuple,
+                Err(()) => bail!(DataLoss, msg("bad varint 3 at offset {i2}")),
+            };
+            self.composition_offset_90k = unzigzag32(raw3);
+            i2 = i3;
+        }
         let duration_90k_delta = unzigzag32(raw1 >> 1);
         self.duration_90k += duration_90k_delta;
         if self.duration_90k < 0 {
@@ -110,7 +141,7 @@ impl SampleIndexIterator {
             true => (self.bytes, self.bytes_other),
             false => (self.bytes_other, self.bytes),
         };
-        self.i_and_is_key = (i2 as u32) | ((raw1 & 1) << 31);
+        self.i_and_is_key = (i2 as u32) | has_offsets | ((raw1 & 1) << 31);
         let bytes_delta = unzigzag32(raw2);
         if self.is_key() {
             self.bytes = prev_bytes_key + bytes_delta;
@@ -156,6 +187,22 @@ impl SampleIndexEncoder {
         is_key: bool,
         r: &mut db::RecordingToInsert,
     ) {
+        self.add_sample_with_composition_offset(duration_90k, 0, bytes, is_key, r)
+    }
+
+    /// Adds a sample whose presentation time is `composition_offset_90k` after its start time.
+    /// The offset is stored only if `r.flags` includes
+    /// [`db::RecordingFlags::CompositionOffsets`]; otherwise it must be zero.
+    pub fn add_sample_with_composition_offset(
+        &mut self,
+        duration_90k: i32,
+        composition_offset_90k: i32,
+        bytes: i32,
+        is_key: bool,
+        r: &mut db::RecordingToInsert,
+    ) {
+        let has_offsets = (r.flags & db::RecordingFlags::CompositionOffsets as i32) != 0;
+        debug_assert!(has_offsets || composition_offset_90k == 0);
         let duration_delta = duration_90k - self.prev_duration_90k;
         self.prev_duration_90k = duration_90k;
         r.media_duration_90k += duration_90k;
@@ -177,6 +224,12 @@ impl SampleIndexEncoder {
             &mut r.video_index,
         );
         append_varint32(zigzag32(bytes_delta), &mut r.video_index);
+        if has_offsets {
+            append_varint32(zigzag32(composition_offset_90k), &mut r.video_index);
+            if composition_offset_90k != 0 {
+                r.flags |= db::RecordingFlags::Reordered as i32;
+            }
+        }
     }
 }
 
@@ -198,6 +251,13 @@ pub struct Segment {
     pub frames: u16,
     pub key_frames: u16,
     video_sample_entry_id_and_trailing_zero: i32,
+
+    /// If the index has composition offsets, as in [`db::RecordingFlags::CompositionOffsets`].
+    composition_offsets: bool,
+
+    /// If any offset is non-zero, as in [`db::RecordingFlags::Reordered`]. This is snapshotted
+    /// along with `frames`; a growing recording may become reordered only in later frames.
+    reordered: bool,
 }
 
 impl Segment {
@@ -230,6 +290,9 @@ impl Segment {
             video_sample_entry_id_and_trailing_zero: recording.video_sample_entry_id
                 | ((((recording.flags & db::RecordingFlags::TrailingZero as i32) != 0) as i32)
                     << 31),
+            composition_offsets: (recording.flags & db::RecordingFlags::CompositionOffsets as i32)
+                != 0,
+            reordered: (recording.flags & db::RecordingFlags::Reordered as i32) != 0,
         };
 
         #[allow(clippy::suspicious_operation_groupings)]
@@ -267,7 +330,7 @@ impl Segment {
         db.with_recording_playback(self_.id, &mut |playback| {
             let mut begin = Box::<SampleIndexIterator>::default();
             let data = &playback.video_index;
-            let mut it = SampleIndexIterator::default();
+            let mut it = SampleIndexIterator::new(recording.flags);
             if !it.next(data)? {
                 bail!(Internal, msg("no index"));
             }
@@ -286,6 +349,7 @@ impl Segment {
                 desired_media_range_90k.end
             };
 
+            let mut last_duration_90k = 0;
             loop {
                 if it.start_90k <= desired_media_range_90k.start && (!start_at_key || it.is_key()) {
                     // new start candidate.
@@ -298,6 +362,7 @@ impl Segment {
                 }
                 self_.frames += 1;
                 self_.key_frames += it.is_key() as u16;
+                last_duration_90k = it.duration_90k;
                 if !it.next(data)? {
                     break;
                 }
@@ -305,7 +370,7 @@ impl Segment {
             self_.begin = Some(begin);
             self_.file_end = it.pos;
             self_.video_sample_entry_id_and_trailing_zero =
-                recording.video_sample_entry_id | (((it.duration_90k == 0) as i32) << 31);
+                recording.video_sample_entry_id | (((last_duration_90k == 0) as i32) << 31);
             Ok(())
         })?;
         Ok(self_)
@@ -319,6 +384,11 @@ impl Segment {
         self.video_sample_entry_id_and_trailing_zero < 0
     }
 
+    /// Returns true if any of the segment's samples may have a non-zero composition offset.
+    pub fn has_composition_offsets(&self) -> bool {
+        self.reordered
+    }
+
     /// Returns the byte range within the sample file of data associated with this segment.
     pub fn sample_file_range(&self) -> Range<u64> {
         self.begin.as_ref().map(|b| b.pos as u64).unwrap_or(0)..self.file_end as u64
@@ -346,7 +416,11 @@ impl Segment {
         let mut it = match self.begin {
             Some(ref b) => **b,
             None => {
-                let mut it = SampleIndexIterator::default();
+                let mut it = SampleIndexIterator::new(if self.composition_offsets {
+                    db::RecordingFlags::CompositionOffsets as i32
+                } else {
+                    0
+                });
                 if !it.next(data)? {
                     bail!(Internal, msg("recording {} has no frames", self.id));
                 }
@@ -490,6 +564,38 @@ mod tests {
         assert!(!it.next(&r.video_index).unwrap());
     }
 
+    /// Tests a round trip of an index with composition offsets, as written for B-frames.
+    #[test]
+    fn test_round_trip_composition_offsets() {
+        testutil::init();
+        let flags = db::RecordingFlags::CompositionOffsets as i32;
+        let mut r = db::RecordingToInsert {
+            flags,
+            ..Default::default()
+        };
+        let mut e = SampleIndexEncoder::default();
+        let samples = [(3000, 0, true), (3000, 6000, false), (3000, -3000, false)];
+        for &(duration_90k, offset_90k, is_key) in &samples {
+            e.add_sample_with_composition_offset(duration_90k, offset_90k, 100, is_key, &mut r);
+        }
+        let mut it = SampleIndexIterator::new(flags);
+        for &(duration_90k, offset_90k, is_key) in &samples {
+            assert!(it.next(&r.video_index).unwrap());
+            assert_eq!(
+                (it.duration_90k, it.composition_offset_90k, it.is_key()),
+                (duration_90k, offset_90k, is_key)
+            );
+        }
+        assert!(!it.next(&r.video_index).unwrap());
+
+        // Missing offset.
+        let mut it = SampleIndexIterator::new(flags);
+        assert_eq!(
+            it.next(b"\x01\x02").unwrap_err().msg().unwrap(),
+            "bad varint 3 at offset 2"
+        );
+    }
+
     /// Tests that `SampleIndexIterator` spots several classes of errors.
     /// TODO: test and fix overflow cases.
     #[test]
@@ -599,6 +705,22 @@ mod tests {
         assert_eq!(&get_frames(&db.db, &segment, |it| it.bytes), &[2, 3]);
     }
 
+    /// A segment which stops short of a trailing zero-duration frame doesn't have a trailing zero.
+    #[test]
+    fn test_segment_clipping_before_trailing_zero() {
+        testutil::init();
+        let mut r = db::RecordingToInsert::default();
+        let mut encoder = SampleIndexEncoder::default();
+        encoder.add_sample(2, 1, true, &mut r);
+        encoder.add_sample(2, 2, true, &mut r);
+        encoder.add_sample(0, 3, true, &mut r);
+        let db = TestDb::new(RealClocks {});
+        let row = db.insert_recording_from_encoder(r);
+        let segment = Segment::new(&db.db.lock(), &row, 0..3, true).unwrap();
+        assert_eq!(&get_frames(&db.db, &segment, |it| it.bytes), &[1, 2]);
+        assert!(!segment.have_trailing_zero());
+    }
+
     /// Even if the desired duration is 0, there should still be a frame.
     #[test]
     fn test_segment_zero_desired_duration() {
@@ -133,6 +133,12 @@ create table recording (
   -- * 1, or "trailing zero", indicates that this recording is the last in a
   --   stream. As the duration of a sample is not known until the next sample
   --   is received, the final sample in this recording will have duration 0.
+  -- * 2, or "encrypted", indicates that the sample file is encrypted with the
+  --   key identified by the sample_file_key table.
+  -- * 4, or "composition offsets", indicates that the video_index has a
+  --   composition offset for each sample, as described in design/schema.md.
+  -- * 8, or "reordered", indicates that at least one of those composition
+  --   offsets is non-zero.
   flags integer not null,
 
   sample_file_bytes integer not null check (sample_file_bytes > 0),
@@ -168,7 +174,20 @@ create table recording (
 
   -- The reason this run ended. Absent if there are more recordings in this
   -- run or if this recording predates schema version 7.
-  end_reason text
+  end_reason text,
+
+  -- Comments from here to the end of the columns must not contain commas; see
+  -- the similar note in the user table.
+
+  -- The number of RTP packets the camera sent but which never arrived (as
+  -- detected by gaps in sequence numbers). Frames which were incomplete as a
+  -- result are discarded rather than stored.
+  packets_lost integer not null default 0 check (packets_lost >= 0),
+
+  -- The number of stored frames which (directly or indirectly) reference a
+  -- discarded frame and thus likely decode with visible corruption. These are
+  -- the non-key frames from a packet loss until the next key frame.
+  damaged_frames integer not null default 0 check (damaged_frames >= 0),
 
   check (composite_id >> 32 = stream_id)
 );
@@ -245,6 +264,26 @@ create table recording_playback (
   -- audio_index could be added here in the future.
 );
 
+-- Details of the RTSP session which produced a run, for debugging
+-- interoperability problems with cameras after the fact. There's at most one
+-- row per run, which is deleted along with the run's first recording.
+create table recording_rtsp_session (
+  -- The composite id of the run's first recording (the one with run_offset=0).
+  composite_id integer primary key references recording (composite_id),
+
+  -- The session description (SDP) returned by the camera's DESCRIBE, exactly
+  -- as received.
+  sdp blob not null,
+
+  -- The video stream's negotiated transport, as described by the RTSP
+  -- library, such as the RTP/RTCP channels or ports and the SSRC.
+  transport text,
+
+  -- The camera's self-description, from its RTSP Server header or its SDP's
+  -- tool attribute.
+  server text
+);
+
 -- Files which are to be deleted (may or may not still exist).
 -- Note that besides these files, for each stream, any recordings >= its
 -- cum_recordings should be discarded on startup.
@@ -313,7 +352,21 @@ create table user (
 
   -- Permissions available for newly created tokens or when authenticating via
   -- unix_uid above. A serialized "Permissions" protobuf.
-  permissions blob not null default X''
+  permissions blob not null default X'',
+
+  -- Comments from here to the end of the table must not contain commas:
+  -- reverting to schema version 7 drops the following columns and SQLite finds
+  -- the end of the preceding column definition by searching back for one.
+
+  -- The id of the permission_template (if any) from which permissions is
+  -- copied. Changes to the template are applied to all of its users.
+  permission_template_id integer,
+
+  -- Bytes of video downloaded on download_day (days since the Unix epoch in
+  -- UTC) for enforcing json.UserConfig.downloadQuotaBytesPerDay. Updated
+  -- lazily on database flush.
+  download_day integer not null default 0,
+  download_bytes integer not null default 0
 );
 
 -- A single session, whether for browser or robot use.
@@ -363,6 +416,7 @@ create table user_session (
   -- text detail. Enumeration values:
   -- 1: logout link clicked (i.e. from within the session itself)
   -- 2: obsoleted by a change in hashing algorithm (eg schema 5->6 upgrade)
+  -- 3: revoked by an administrator (eg `moonfire-nvr users revoke-sessions`)
   --
   -- This might be extended for a variety of other reasons:
   -- x: user revoked (while authenticated in another way)
@@ -423,5 +477,111 @@ create table signal_change (
   changes blob not null
 );
 
+-- Per-hour summaries of signal states, compacted from signal_change rows which
+-- were garbage-collected to stay within json.GlobalConfig.maxSignalChanges.
+create table signal_hour (
+  -- The start of the hour, in 90 kHz units since 1970-01-01 00:00:00Z
+  -- excluding leap seconds. Always a multiple of 3600 * 90000.
+  start_time_90k integer not null check (start_time_90k % 324000000 = 0),
+
+  signal_id integer not null references signal (id),
+
+  -- A blob of varints representing the time (in 90 kHz units) the signal
+  -- spent in state 1, state 2, and so on during the hour. The signal was in
+  -- state 0 (unknown) for the remainder of the hour.
+  states blob not null,
+
+  primary key (start_time_90k, signal_id)
+) without rowid;
+
+-- A journal of notable server events: starts and stops, sample file directory
+-- opens, stream connection changes, and flush errors. This explains gaps in
+-- recordings. Only the most recent 10,000 rows are retained.
+create table event (
+  id integer primary key,
+
+  -- The time of the event, in 90 kHz units since 1970-01-01 00:00:00Z
+  -- excluding leap seconds.
+  time_90k integer not null,
+
+  -- The kind of event, such as "serverStart" or "streamDown"; see
+  -- db::event::EventKind.
+  kind text not null,
+
+  -- The stream concerned, if any. This deliberately isn't a foreign key, so
+  -- events outlive deleted streams.
+  stream_id integer,
+
+  -- Human-readable detail, such as an error message.
+  message text not null
+);
+create index event_time on event (time_90k);
+
+-- The key used to encrypt camera credentials (see
+-- json.CameraConfig.encryptedCredentials), itself encrypted with a key supplied
+-- via the configuration file. There's a row iff credential encryption is
+-- enabled.
+create table credential_key (
+  id integer primary key check (id = 1),
+
+  -- The AES-256-GCM nonce (12 bytes), followed by the encrypted 32-byte key
+  -- and tag (16 bytes). The associated data is meta.uuid.
+  wrapped_key blob not null check (length(wrapped_key) = 60)
+);
+
+-- Identifies the master key used to encrypt sample files (see the
+-- recording.flags Encrypted bit). The key itself is supplied via the
+-- configuration file. There's a row iff sample file encryption has been
+-- enabled.
+create table sample_file_key (
+  id integer primary key check (id = 1),
+
+  -- A 32-byte value derived from the key via HKDF-SHA256, used to check that
+  -- the key supplied matches the one used previously.
+  key_check blob not null check (length(key_check) = 32)
+);
+
+-- A named set of permissions which may be assigned to users.
+create table permission_template (
+  id integer primary key,
+  name text unique not null,
+
+  -- A serialized "Permissions" protobuf.
+  permissions blob not null default X''
+);
+
+-- Default templates: viewer (view_live and download_recordings), operator
+-- (also read_camera_configs and update_signals), and admin (also admin_users).
+insert into permission_template (name,       permissions)
+                         values ('viewer',   X'28013001'),
+                                ('operator', X'1001180128013001'),
+                                ('admin',    X'10011801200128013001');
+
+-- A WebAuthn credential (passkey or security key) with which a user may log in
+-- instead of with a password.
+create table user_webauthn_credential (
+  id integer primary key,
+  user_id integer references user (id) not null,
+
+  -- The authenticator-chosen credential id, unique across all users.
+  credential_id blob unique not null,
+
+  -- The credential's public key, as a COSE_Key (RFC 9052 section 7).
+  public_key blob not null,
+
+  -- The authenticator's signature counter as of the last login, for detecting
+  -- cloned authenticators. Authenticators which don't implement a counter
+  -- always report 0.
+  sign_count integer not null default 0 check (sign_count >= 0),
+
+  -- A user-supplied description, such as "YubiKey" or "phone".
+  name text not null,
+
+  creation_time_sec integer not null,  -- sec since epoch
+  last_use_time_sec integer            -- sec since epoch
+);
+
+create index user_webauthn_credential_uid on user_webauthn_credential (user_id);
+
 insert into version (id, unix_time,                           notes)
-             values (7,  cast(strftime('%s', 'now') as int), 'db creation');
+             values (8,  cast(strftime('%s', 'now') as int), 'db creation');
@@ -38,9 +38,53 @@ pub(crate) struct State {
     /// These either have a matching `points_by_time` entry or represent a removal.
     dirty_by_time: BTreeSet<recording::Time>,
 
+    /// Summaries of the time before the first point, which `gc` has compacted away, by hour
+    /// start and signal. Each matches a `signal_hour` table row (when flushed).
+    hours: BTreeMap<(recording::Time, u32), days::SignalValue>,
+
+    /// Keys of `hours` which need to be flushed to the database.
+    dirty_hours: BTreeSet<(recording::Time, u32)>,
+
     max_signal_changes: Option<u32>,
 }
 
+/// The span of each `signal_hour` row, in 90 kHz units.
+const HOUR_90K: i64 = 3600 * recording::TIME_UNITS_PER_SEC;
+
+fn floor_hour(t: recording::Time) -> recording::Time {
+    recording::Time(t.0.saturating_sub(t.0.rem_euclid(HOUR_90K)))
+}
+
+fn ceil_hour(t: recording::Time) -> recording::Time {
+    match t.0.rem_euclid(HOUR_90K) {
+        0 => t,
+        r => recording::Time(t.0.saturating_add(HOUR_90K - r)),
+    }
+}
+
+/// Adds the time `signal` spent in non-zero `state` during `r` to `hours`, split at hour
+/// boundaries, also noting the affected keys in `dirty` if supplied.
+fn add_hours(
+    hours: &mut BTreeMap<(recording::Time, u32), days::SignalValue>,
+    mut dirty: Option<&mut BTreeSet<(recording::Time, u32)>>,
+    signal: u32,
+    state: u16,
+    mut r: Range<recording::Time>,
+) {
+    while r.start < r.end {
+        let hour = floor_hour(r.start);
+        let end = std::cmp::min(recording::Time(hour.0.saturating_add(HOUR_90K)), r.end);
+        hours
+            .entry((hour, signal))
+            .or_default()
+            .add(state, (end - r.start).0 as u64);
+        if let Some(ref mut d) = dirty {
+            d.insert((hour, signal));
+        }
+        r.start = end;
+    }
+}
+
 /// Representation of all signals at a point in time.
 /// Each point matches a `signal_change` table row (when flushed). However, the in-memory
 /// representation keeps not only the changes as of that time but also the complete prior state.
@@ -207,6 +251,7 @@ impl State {
     pub fn init(conn: &Connection, config: &crate::json::GlobalConfig) -> Result<Self, Error> {
         let mut signals_by_id = State::init_signals(conn)?;
         let mut points_by_time = BTreeMap::new();
+        let hours = State::init_hours(conn, &mut signals_by_id)?;
         State::fill_points(conn, &mut points_by_time, &mut signals_by_id)?;
         let s = State {
             max_signal_changes: config.max_signal_changes,
@@ -214,6 +259,8 @@ impl State {
             types_by_uuid: State::init_types(conn)?,
             points_by_time,
             dirty_by_time: BTreeSet::new(),
+            hours,
+            dirty_hours: BTreeSet::new(),
         };
         s.debug_assert_point_invariants();
         Ok(s)
@@ -277,7 +324,54 @@ impl State {
         Ok(())
     }
 
+    /// Returns per-hour summaries of each signal's states within the hours overlapping
+    /// `desired_time`, combining those compacted by garbage collection with those computed from
+    /// the remaining points. As with the days indexes, a state is counted only once it has ended,
+    /// so the current state after the latest change isn't included.
+    pub fn list_hours(
+        &self,
+        desired_time: Range<recording::Time>,
+    ) -> BTreeMap<(recording::Time, u32), days::SignalValue> {
+        let start = floor_hour(desired_time.start);
+        let end = ceil_hour(desired_time.end);
+        if end <= start {
+            return BTreeMap::new();
+        }
+        let mut out: BTreeMap<_, _> = self
+            .hours
+            .range((start, 0)..(end, 0))
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        let mut cur = self
+            .points_by_time
+            .range(..start)
+            .next_back()
+            .map(|(_, p)| p.after())
+            .unwrap_or_default();
+        let mut prev_time = start;
+        for (&t, p) in self.points_by_time.range(start..) {
+            for (&signal, &state) in &cur {
+                add_hours(
+                    &mut out,
+                    None,
+                    signal,
+                    state,
+                    prev_time..std::cmp::min(t, end),
+                );
+            }
+            if t >= end {
+                break;
+            }
+            p.changes().update_map(&mut cur);
+            prev_time = t;
+        }
+        out
+    }
+
     /// Performs garbage collection if the number of points exceeds `max_signal_changes`.
+    ///
+    /// The removed points are compacted into `hours` rather than discarded outright, so that
+    /// days indexes and hourly summaries still reflect them.
     fn gc(&mut self) {
         let max = match self.max_signal_changes {
             None => return,
@@ -294,7 +388,7 @@ impl State {
             to_remove
         );
 
-        self.gc_days(to_remove);
+        self.gc_hours(to_remove);
         let remove: smallvec::SmallVec<[recording::Time; 4]> = self
             .points_by_time
             .keys()
@@ -319,24 +413,56 @@ impl State {
         self.debug_assert_point_invariants();
     }
 
-    /// Adjusts each signal's days index to reflect garbage-collecting the first `to_remove` points.
-    fn gc_days(&mut self, to_remove: usize) {
+    /// Recomputes each signal's days index from `hours` and `points_by_time`, as after the local
+    /// time zone changes.
+    pub(crate) fn rebuild_days(&mut self) {
+        for s in self.signals_by_id.values_mut() {
+            s.days = days::Map::default();
+        }
+        for (&(hour, signal), v) in &self.hours {
+            let s = self
+                .signals_by_id
+                .get_mut(&signal)
+                .expect("in-mem hour signals valid");
+            add_hour_to_days(&mut s.days, hour, v);
+        }
+        let mut sig_last_state = BTreeMap::new();
+        for (&time_90k, p) in &self.points_by_time {
+            let mut it = p.changes();
+            while let Some((signal, state)) = it.next().expect("in-mem changes is valid") {
+                if let Some((prev_time, prev_state)) = sig_last_state.remove(&signal) {
+                    self.signals_by_id
+                        .get_mut(&signal)
+                        .expect("in-mem point signals valid")
+                        .days
+                        .adjust(prev_time..time_90k, 0, prev_state);
+                }
+                if state != 0 {
+                    sig_last_state.insert(signal, (time_90k, state));
+                }
+            }
+        }
+    }
+
+    /// Compacts the time covered by the first `to_remove` points (up to the following point) into
+    /// `hours`, in preparation for garbage-collecting them.
+    fn gc_hours(&mut self, to_remove: usize) {
         let mut it = self.points_by_time.iter().take(to_remove + 1);
-        let (mut prev_time, mut prev_state) = match it.next() {
+        let (mut prev_time, mut cur) = match it.next() {
             None => return, // nothing to do.
             Some(p) => (*p.0, p.1.after()),
         };
         for (&new_time, point) in it {
-            let mut changes = point.changes();
-            while let Some((signal, state)) = changes.next().expect("in-mem points valid") {
-                let s = self
-                    .signals_by_id
-                    .get_mut(&signal)
-                    .expect("in-mem point signals valid");
-                let prev_state = prev_state.entry(signal).or_default();
-                s.days.adjust(prev_time..new_time, *prev_state, state);
-                *prev_state = state;
+            for (&signal, &state) in &cur {
+                add_hours(
+                    &mut self.hours,
+                    Some(&mut self.dirty_hours),
+                    signal,
+                    state,
+                    prev_time..new_time,
+                );
             }
+            point.changes().update_map(&mut cur);
             prev_time = new_time;
         }
     }
@@ -646,6 +772,23 @@ impl State {
                 }
             }
         }
+        let mut h_stmt = tx.prepare(
+            r#"
+            insert or replace into signal_hour (start_time_90k, signal_id, states)
+                                        values (?, ?, ?)
+            "#,
+        )?;
+        for k in &self.dirty_hours {
+            let v = &self.hours[k];
+            let mut states = Vec::with_capacity(4 * v.states.len());
+            for &d in &v.states {
+                coding::append_varint32(
+                    u32::try_from(d).expect("hour durations fit in u32"),
+                    &mut states,
+                );
+            }
+            h_stmt.execute(params![k.0 .0, k.1, &states])?;
+        }
         Ok(())
     }
 
@@ -654,6 +797,7 @@ impl State {
     /// See notes there.
     pub fn post_flush(&mut self) {
         self.dirty_by_time.clear();
+        self.dirty_hours.clear();
     }
 
     fn init_signals(conn: &Connection) -> Result<BTreeMap<u32, Signal>, Error> {
@@ -691,6 +835,57 @@ impl State {
         Ok(signals)
     }
 
+    /// Loads `hours` from the database, also filling the `days` index of each signal.
+    fn init_hours(
+        conn: &Connection,
+        signals_by_id: &mut BTreeMap<u32, Signal>,
+    ) -> Result<BTreeMap<(recording::Time, u32), days::SignalValue>, Error> {
+        let mut hours = BTreeMap::new();
+        let mut stmt = conn.prepare(
+            r#"
+            select
+                start_time_90k,
+                signal_id,
+                states
+            from
+                signal_hour
+            "#,
+        )?;
+        let mut rows = stmt.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let hour = recording::Time(row.get(0)?);
+            let signal: u32 = row.get(1)?;
+            let data = row.get_ref(2)?.as_blob()?;
+            let mut v = days::SignalValue::default();
+            let mut pos = 0;
+            while pos < data.len() {
+                let (d, p) = coding::decode_varint32(data, pos).map_err(|()| {
+                    err!(
+                        DataLoss,
+                        msg("bad signal_hour states for signal {signal} at {hour}")
+                    )
+                })?;
+                if i64::from(d) > HOUR_90K {
+                    bail!(
+                        DataLoss,
+                        msg("signal_hour duration {d} for signal {signal} at {hour} too long")
+                    );
+                }
+                v.states.push(u64::from(d));
+                pos = p;
+            }
+            let Some(s) = signals_by_id.get_mut(&signal) else {
+                bail!(
+                    DataLoss,
+                    msg("signal_hour at {hour} references invalid signal {signal}")
+                );
+            };
+            add_hour_to_days(&mut s.days, hour, &v);
+            hours.insert((hour, signal), v);
+        }
+        Ok(hours)
+    }
+
     fn init_types(conn: &Connection) -> Result<FastHashMap<Uuid, Type>, Error> {
         let mut types = FastHashMap::default();
         let mut stmt = conn.prepare(
@@ -705,24 +900,7 @@ impl State {
         let mut rows = stmt.query(params![])?;
         while let Some(row) = rows.next()? {
             let uuid: SqlUuid = row.get(0)?;
-            let mut type_ = Type {
-                valid_states: 1, // bit 0 (unknown state) is always valid.
-                config: row.get(1)?,
-            };
-            for &value in type_.config.values.keys() {
-                if value == 0 || value >= 16 {
-                    bail!(
-                        OutOfRange,
-                        msg(
-                            "signal type {} value {} out of accepted range [0, 16)",
-                            uuid.0,
-                            value,
-                        ),
-                    );
-                }
-                type_.valid_states |= 1 << value;
-            }
-            types.insert(uuid.0, type_);
+            types.insert(uuid.0, Type::new(uuid.0, row.get(1)?)?);
         }
         Ok(types)
     }
@@ -794,6 +972,175 @@ impl State {
         &self.types_by_uuid
     }
 
+    /// Creates or replaces the signal type `uuid`, as declared by an external system.
+    ///
+    /// This is stricter than what `init` accepts from the database: the type and each of its
+    /// values must be named, value names must be unique, and colors must be valid.
+    pub fn put_type(
+        &mut self,
+        conn: &Connection,
+        uuid: Uuid,
+        config: SignalTypeConfig,
+    ) -> Result<(), Error> {
+        if config.name.is_empty() {
+            bail!(InvalidArgument, msg("signal type name must be non-empty"));
+        }
+        let mut names = BTreeSet::new();
+        for (value, v) in &config.values {
+            if v.name.is_empty() {
+                bail!(
+                    InvalidArgument,
+                    msg("signal type value {value} name must be non-empty")
+                );
+            }
+            if !names.insert(&v.name) {
+                bail!(
+                    InvalidArgument,
+                    msg("signal type value name {:?} is not unique", v.name)
+                );
+            }
+            if !is_valid_color(&v.color) {
+                bail!(
+                    InvalidArgument,
+                    msg("signal type value {value} has invalid color {:?}", v.color)
+                );
+            }
+        }
+        let type_ = Type::new(uuid, config)?;
+        conn.execute(
+            "insert or replace into signal_type (uuid, config) values (?, ?)",
+            params![SqlUuid(uuid), &type_.config],
+        )?;
+        self.types_by_uuid.insert(uuid, type_);
+        Ok(())
+    }
+
+    /// Deletes the signal type `uuid`, which must not be used by any signal.
+    pub fn delete_type(&mut self, conn: &Connection, uuid: Uuid) -> Result<(), Error> {
+        if !self.types_by_uuid.contains_key(&uuid) {
+            bail!(NotFound, msg("no such signal type {uuid}"));
+        }
+        if let Some(s) = self.signals_by_id.values().find(|s| s.type_ == uuid) {
+            bail!(
+                FailedPrecondition,
+                msg("signal type {uuid} is used by signal {}", s.id)
+            );
+        }
+        conn.execute(
+            "delete from signal_type where uuid = ?",
+            params![SqlUuid(uuid)],
+        )?;
+        self.types_by_uuid.remove(&uuid);
+        Ok(())
+    }
+
+    /// Creates or replaces the signal `uuid`, returning its id.
+    ///
+    /// The caller is responsible for checking that the associated cameras exist. A signal's type
+    /// can't be changed once it has recorded changes, as they may not be valid for the new type.
+    pub fn put_signal(
+        &mut self,
+        conn: &Connection,
+        uuid: Uuid,
+        type_: Uuid,
+        config: SignalConfig,
+    ) -> Result<u32, Error> {
+        if config.short_name.is_empty() {
+            bail!(InvalidArgument, msg("signal short name must be non-empty"));
+        }
+        if !self.types_by_uuid.contains_key(&type_) {
+            bail!(InvalidArgument, msg("no such signal type {type_}"));
+        }
+        for (camera_id, association) in &config.camera_associations {
+            if association != "direct" && association != "indirect" {
+                bail!(
+                    InvalidArgument,
+                    msg("camera {camera_id} has invalid association {association:?}")
+                );
+            }
+        }
+        let mut existing = None;
+        for s in self.signals_by_id.values() {
+            if s.uuid == uuid {
+                existing = Some(s);
+            } else if s.config.short_name == config.short_name {
+                bail!(
+                    AlreadyExists,
+                    msg("signal short name {:?} is in use", config.short_name)
+                );
+            }
+        }
+        let id = match existing {
+            Some(s) => {
+                if s.type_ != type_ && self.has_changes(s.id) {
+                    bail!(
+                        FailedPrecondition,
+                        msg("can't change type of signal {} with recorded changes", s.id)
+                    );
+                }
+                conn.execute(
+                    "update signal set type_uuid = ?, config = ? where id = ?",
+                    params![SqlUuid(type_), &config, s.id],
+                )?;
+                s.id
+            }
+            None => {
+                let id = self
+                    .signals_by_id
+                    .last_key_value()
+                    .map(|(&id, _)| id + 1)
+                    .unwrap_or(1);
+                conn.execute(
+                    "insert into signal (id, uuid, type_uuid, config) values (?, ?, ?, ?)",
+                    params![id, SqlUuid(uuid), SqlUuid(type_), &config],
+                )?;
+                id
+            }
+        };
+        let s = self.signals_by_id.entry(id).or_insert_with(|| Signal {
+            id,
+            uuid,
+            type_,
+            days: days::Map::default(),
+            config: SignalConfig::default(),
+        });
+        s.type_ = type_;
+        s.config = config;
+        Ok(id)
+    }
+
+    /// Deletes the signal `uuid`, which must have no recorded changes.
+    pub fn delete_signal(&mut self, conn: &Connection, uuid: Uuid) -> Result<(), Error> {
+        let Some(id) = self
+            .signals_by_id
+            .values()
+            .find(|s| s.uuid == uuid)
+            .map(|s| s.id)
+        else {
+            bail!(NotFound, msg("no such signal {uuid}"));
+        };
+        if self.has_changes(id) {
+            bail!(FailedPrecondition, msg("signal {id} has recorded changes"));
+        }
+        conn.execute("delete from signal where id = ?", params![id])?;
+        self.signals_by_id.remove(&id);
+        Ok(())
+    }
+
+    /// Returns true if any point or hour (flushed or not) refers to signal `id`.
+    fn has_changes(&self, id: u32) -> bool {
+        self.hours.keys().any(|&(_, signal)| signal == id)
+            || self.points_by_time.values().any(|p| {
+                let mut it = p.changes();
+                while let Some((signal, _)) = it.next().expect("in-mem changes is valid") {
+                    if signal == id {
+                        return true;
+                    }
+                }
+                false
+            })
+    }
+
     #[cfg(not(debug_assertions))]
     fn debug_assert_point_invariants(&self) {}
 
@@ -830,6 +1177,53 @@ pub struct Type {
     pub config: SignalTypeConfig,
 }
 
+impl Type {
+    fn new(uuid: Uuid, config: SignalTypeConfig) -> Result<Self, Error> {
+        let mut valid_states = 1; // bit 0 (unknown state) is always valid.
+        for &value in config.values.keys() {
+            if value == 0 || value >= 16 {
+                bail!(
+                    OutOfRange,
+                    msg("signal type {uuid} value {value} out of accepted range [0, 16)"),
+                );
+            }
+            valid_states |= 1 << value;
+        }
+        Ok(Type {
+            valid_states,
+            config,
+        })
+    }
+}
+
+/// Adds an hour's summary to a days index.
+///
+/// The summary doesn't say when within the hour each state occurred, so this attributes each to
+/// the start of the hour. That's exact in time zones whose offsets are whole hours.
+fn add_hour_to_days(
+    days: &mut days::Map<days::SignalValue>,
+    hour: recording::Time,
+    v: &days::SignalValue,
+) {
+    for (i, &d) in v.states.iter().enumerate() {
+        if d > 0 {
+            let state = u16::try_from(i + 1).expect("states are u16");
+            days.adjust(hour..hour + recording::Duration(d as i64), 0, state);
+        }
+    }
+}
+
+/// Returns true if `color` is empty, an HTML hex color (`#rgb` or `#rrggbb`), or plausibly a
+/// named color (ASCII letters only).
+fn is_valid_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => {
+            (hex.len() == 3 || hex.len() == 6) && hex.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        None => color.len() <= 32 && color.bytes().all(|b| b.is_ascii_alphabetic()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1012,6 +1406,42 @@ mod tests {
         });
         assert_eq!(&rows[..], EXPECTED2);
 
+        // The dropped time was compacted into an hourly summary. NOW starts the next hour.
+        let hour = floor_hour(START);
+        let minute = (NOW - START).0 as u64;
+        let expected_hours: BTreeMap<_, _> = [
+            ((hour, 1), smallvec![0, minute]),
+            ((hour, 2), smallvec![minute]),
+        ]
+        .into_iter()
+        .map(|(k, states)| (k, days::SignalValue { states }))
+        .collect();
+        assert_eq!(s.hours, expected_hours);
+        let mut expected_list = expected_hours.clone();
+        expected_list.insert(
+            (NOW, 1),
+            days::SignalValue {
+                states: smallvec![minute],
+            },
+        );
+        expected_list.insert(
+            (NOW, 2),
+            days::SignalValue {
+                states: smallvec![0, minute],
+            },
+        );
+        assert_eq!(
+            s.list_hours(recording::Time::MIN..recording::Time::MAX),
+            expected_list
+        );
+        assert_eq!(s.list_hours(NOW..NOW + recording::Duration(1)).len(), 2);
+
+        // Rebuilding the days maps from the hours and points, as after a time zone change, should
+        // match the incrementally maintained ones, even after GC.
+        let days: Vec<_> = s.signals_by_id.values().map(|s| s.days.clone()).collect();
+        s.rebuild_days();
+        assert!(s.signals_by_id.values().map(|s| &s.days).eq(days.iter()));
+
         {
             let tx = conn.transaction().unwrap();
             s.flush(&tx).unwrap();
@@ -1024,5 +1454,109 @@ mod tests {
             rows.push(*r)
         });
         assert_eq!(&rows[..], EXPECTED2);
+        assert_eq!(s.hours, expected_hours);
+        assert!(s.signals_by_id.values().map(|s| &s.days).eq(days.iter()));
+    }
+
+    #[test]
+    fn put_and_delete() {
+        testutil::init();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::init(&mut conn).unwrap();
+        let mut s = State::init(&conn, &GlobalConfig::default()).unwrap();
+        let type_uuid = Uuid::parse_str("ee66270f-d9c6-4819-8b33-9720d4cbca6b").unwrap();
+        let signal_uuid = Uuid::parse_str("1b3889c0-a59f-400d-a24c-94ebeb19cc3a").unwrap();
+        let value = |name: &str, color: &str| SignalTypeValueConfig {
+            name: name.to_owned(),
+            color: color.to_owned(),
+            ..Default::default()
+        };
+        let mut type_config = SignalTypeConfig {
+            name: "door".to_owned(),
+            ..Default::default()
+        };
+        type_config.values.insert(1, value("closed", "#888"));
+        type_config.values.insert(2, value("open", "orange"));
+
+        // Validation.
+        let mut bad = type_config.clone();
+        bad.values.insert(3, value("open", ""));
+        let e = s.put_type(&conn, type_uuid, bad).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::InvalidArgument);
+        let mut bad = type_config.clone();
+        bad.values.insert(3, value("ajar", "#12345"));
+        let e = s.put_type(&conn, type_uuid, bad).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::InvalidArgument);
+        let mut bad = type_config.clone();
+        bad.values.insert(16, value("ajar", ""));
+        let e = s.put_type(&conn, type_uuid, bad).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::OutOfRange);
+
+        s.put_type(&conn, type_uuid, type_config.clone()).unwrap();
+        let signal_config = SignalConfig {
+            short_name: "front door".to_owned(),
+            ..Default::default()
+        };
+        let id = s
+            .put_signal(&conn, signal_uuid, type_uuid, signal_config.clone())
+            .unwrap();
+        assert_eq!(id, 1);
+        let e = s
+            .put_signal(&conn, Uuid::nil(), type_uuid, signal_config.clone())
+            .unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::AlreadyExists);
+        let e = s.delete_type(&conn, type_uuid).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::FailedPrecondition);
+
+        // Declarations survive a reload.
+        drop(s);
+        let mut s = State::init(&conn, &GlobalConfig::default()).unwrap();
+        assert_eq!(s.types_by_uuid()[&type_uuid].config, type_config);
+        assert_eq!(s.types_by_uuid()[&type_uuid].valid_states, 0b111);
+        assert_eq!(s.signals_by_id()[&1].config, signal_config);
+
+        // A signal with changes can't be deleted or change type.
+        const START: recording::Time = recording::Time(140067462600000);
+        const END: recording::Time = recording::Time(140067468000000);
+        s.update_signals(START..END, &[1], &[2]).unwrap();
+        let other_type_uuid = Uuid::parse_str("a4a73d9a-5342-4ebc-b9f6-366f1e5617fa").unwrap();
+        type_config.name = "window".to_owned();
+        s.put_type(&conn, other_type_uuid, type_config).unwrap();
+        let e = s
+            .put_signal(&conn, signal_uuid, other_type_uuid, signal_config.clone())
+            .unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::FailedPrecondition);
+        let e = s.delete_signal(&conn, signal_uuid).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::FailedPrecondition);
+
+        // A signal without changes can.
+        let id = s
+            .put_signal(
+                &conn,
+                Uuid::nil(),
+                type_uuid,
+                SignalConfig {
+                    short_name: "back door".to_owned(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(id, 2);
+        s.put_signal(
+            &conn,
+            Uuid::nil(),
+            other_type_uuid,
+            SignalConfig {
+                short_name: "back window".to_owned(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(s.signals_by_id()[&2].type_, other_type_uuid);
+        s.delete_signal(&conn, Uuid::nil()).unwrap();
+        assert!(!s.signals_by_id().contains_key(&2));
+        drop(s);
+        let s = State::init(&conn, &GlobalConfig::default()).unwrap();
+        assert!(!s.signals_by_id().contains_key(&2));
     }
 }
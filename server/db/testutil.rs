@@ -7,11 +7,13 @@
 
 use crate::db;
 use crate::dir;
-use crate::writer;
+use crate::writer::{self, DirWriter, FileWriter};
 use base::clock::Clocks;
 use base::FastHashMap;
+use std::collections::VecDeque;
 use std::env;
-use std::sync::Arc;
+use std::io;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use tempfile::TempDir;
 use uuid::Uuid;
@@ -45,12 +47,12 @@ pub fn init() {
     });
 }
 
-pub struct TestDb<C: Clocks + Clone> {
+pub struct TestDb<C: Clocks + Clone, F: FileWriter = ::std::fs::File> {
     pub db: Arc<db::Database<C>>,
     pub dirs_by_stream_id: Arc<FastHashMap<i32, Arc<dir::SampleFileDir>>>,
     pub shutdown_tx: base::shutdown::Sender,
     pub shutdown_rx: base::shutdown::Receiver,
-    pub syncer_channel: writer::SyncerChannel<::std::fs::File>,
+    pub syncer_channel: writer::SyncerChannel<F>,
     pub syncer_join: thread::JoinHandle<()>,
     pub tmpdir: TempDir,
     pub test_camera_uuid: Uuid,
@@ -63,6 +65,24 @@ impl<C: Clocks + Clone> TestDb<C> {
     }
 
     pub(crate) fn new_with_flush_if_sec(clocks: C, flush_if_sec: u32) -> Self {
+        Self::new_inner(clocks, flush_if_sec, |d| d)
+    }
+}
+
+impl<C: Clocks + Clone> TestDb<C, FaultyFile<C>> {
+    /// Creates a test database as in [`TestDb::new`] whose syncer injects the given faults.
+    pub fn new_with_faults(clocks: C, faults: Arc<Faults>) -> Self {
+        let c = clocks.clone();
+        Self::new_inner(clocks, 0, move |d| FaultyDir::new(d, faults, c))
+    }
+}
+
+impl<C: Clocks + Clone, F: FileWriter> TestDb<C, F> {
+    fn new_inner<D, W>(clocks: C, flush_if_sec: u32, wrap: W) -> Self
+    where
+        D: DirWriter<File = F>,
+        W: FnOnce(Arc<dir::SampleFileDir>) -> D,
+    {
         let tmpdir = tempfile::Builder::new()
             .prefix("moonfire-nvr-test")
             .tempdir()
@@ -119,8 +139,13 @@ impl<C: Clocks + Clone> TestDb<C> {
         let mut dirs_by_stream_id = FastHashMap::default();
         dirs_by_stream_id.insert(TEST_STREAM_ID, dir);
         let (shutdown_tx, shutdown_rx) = base::shutdown::channel();
-        let (syncer_channel, syncer_join) =
-            writer::start_syncer(db.clone(), shutdown_rx.clone(), sample_file_dir_id).unwrap();
+        let (syncer_channel, syncer_join) = writer::start_syncer_with_dir(
+            db.clone(),
+            shutdown_rx.clone(),
+            sample_file_dir_id,
+            wrap,
+        )
+        .unwrap();
         TestDb {
             db,
             dirs_by_stream_id: Arc::new(dirs_by_stream_id),
@@ -176,6 +201,160 @@ impl<C: Clocks + Clone> TestDb<C> {
     }
 }
 
+/// A kind of operation on a sample file directory, for fault injection with [`Faults`].
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FaultOp {
+    CreateFile,
+    Write,
+    SyncFile,
+    SyncDir,
+    UnlinkFile,
+}
+
+/// Faults to inject via [`FaultyDir`], shared between the test and the directory.
+#[derive(Default)]
+pub struct Faults(Mutex<FaultsInner>);
+
+#[derive(Default)]
+struct FaultsInner {
+    /// Errors to return from the next operations of each kind, in order.
+    pending: FastHashMap<FaultOp, VecDeque<nix::Error>>,
+
+    /// Number of attempted operations of each kind, including failed ones.
+    attempts: FastHashMap<FaultOp, usize>,
+
+    /// How long each file or directory sync takes, on the directory's clocks.
+    sync_delay: Option<time::Duration>,
+}
+
+impl Faults {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Makes the next `n` operations of kind `op` fail with `err`, after any failures already
+    /// pending. E.g. `ENOSPC` for a full disk or `EIO` for a failing one.
+    pub fn fail(&self, op: FaultOp, n: usize, err: nix::Error) {
+        let mut l = self.0.lock().unwrap();
+        l.pending
+            .entry(op)
+            .or_default()
+            .extend(std::iter::repeat(err).take(n));
+    }
+
+    /// Makes every file or directory sync take `delay`, as with an overloaded disk.
+    pub fn set_sync_delay(&self, delay: Option<time::Duration>) {
+        self.0.lock().unwrap().sync_delay = delay;
+    }
+
+    /// Returns the number of attempted operations of kind `op`.
+    pub fn attempts(&self, op: FaultOp) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .attempts
+            .get(&op)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the number of injected failures of kind `op` which haven't happened yet.
+    pub fn pending(&self, op: FaultOp) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .pending
+            .get(&op)
+            .map_or(0, VecDeque::len)
+    }
+
+    /// Notes an attempt at `op`, returning the injected error if any.
+    fn check<C: Clocks>(&self, op: FaultOp, clocks: &C) -> Result<(), nix::Error> {
+        let (delay, result) = {
+            let mut l = self.0.lock().unwrap();
+            *l.attempts.entry(op).or_default() += 1;
+            let delay = match op {
+                FaultOp::SyncFile | FaultOp::SyncDir => l.sync_delay,
+                _ => None,
+            };
+            let result = match l.pending.get_mut(&op).and_then(VecDeque::pop_front) {
+                Some(e) => Err(e),
+                None => Ok(()),
+            };
+            (delay, result)
+        };
+        if let Some(d) = delay {
+            clocks.sleep(d);
+        }
+        result
+    }
+}
+
+/// A [`DirWriter`] which wraps a real sample file directory, failing or delaying operations as
+/// configured in a [`Faults`]. Delays use the supplied clocks, so with
+/// [`base::clock::SimulatedClocks`] they're deterministic and instantaneous.
+pub struct FaultyDir<C: Clocks + Clone> {
+    inner: Arc<dir::SampleFileDir>,
+    faults: Arc<Faults>,
+    clocks: C,
+}
+
+impl<C: Clocks + Clone> FaultyDir<C> {
+    pub fn new(inner: Arc<dir::SampleFileDir>, faults: Arc<Faults>, clocks: C) -> Self {
+        FaultyDir {
+            inner,
+            faults,
+            clocks,
+        }
+    }
+}
+
+impl<C: Clocks + Clone> DirWriter for FaultyDir<C> {
+    type File = FaultyFile<C>;
+
+    fn create_file(&self, id: db::CompositeId) -> Result<Self::File, nix::Error> {
+        self.faults.check(FaultOp::CreateFile, &self.clocks)?;
+        Ok(FaultyFile {
+            inner: self.inner.create_file(id)?,
+            faults: self.faults.clone(),
+            clocks: self.clocks.clone(),
+        })
+    }
+
+    fn sync(&self) -> Result<(), nix::Error> {
+        self.faults.check(FaultOp::SyncDir, &self.clocks)?;
+        self.inner.sync()
+    }
+
+    fn unlink_file(&self, id: db::CompositeId) -> Result<(), nix::Error> {
+        self.faults.check(FaultOp::UnlinkFile, &self.clocks)?;
+        self.inner.unlink_file(id)
+    }
+
+    fn free_bytes(&self) -> Result<i64, nix::Error> {
+        self.inner.free_bytes()
+    }
+}
+
+/// A sample file created by a [`FaultyDir`].
+pub struct FaultyFile<C: Clocks + Clone> {
+    inner: ::std::fs::File,
+    faults: Arc<Faults>,
+    clocks: C,
+}
+
+impl<C: Clocks + Clone> FileWriter for FaultyFile<C> {
+    fn sync_all(&self) -> Result<(), io::Error> {
+        self.faults.check(FaultOp::SyncFile, &self.clocks)?;
+        self.inner.sync_all()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        self.faults.check(FaultOp::Write, &self.clocks)?;
+        io::Write::write(&mut self.inner, buf)
+    }
+}
+
 // For benchmarking
 #[cfg(feature = "nightly")]
 pub fn add_dummy_recordings_to_db(db: &db::Database, num: usize) {
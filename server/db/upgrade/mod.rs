@@ -12,7 +12,7 @@ use nix::NixPath;
 use rusqlite::params;
 use std::ffi::CStr;
 use std::io::Write;
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 mod v0_to_v1;
@@ -22,6 +22,7 @@ mod v3_to_v4;
 mod v4_to_v5;
 mod v5_to_v6;
 mod v6_to_v7;
+mod v7_to_v8;
 
 #[derive(Debug)]
 pub struct Args<'a> {
@@ -30,6 +31,264 @@ pub struct Args<'a> {
     pub no_vacuum: bool,
 }
 
+/// A step from one schema version to the next.
+struct Step {
+    upgrade: fn(&Args, &rusqlite::Transaction) -> Result<(), Error>,
+
+    /// Reverts the step, if supported.
+    revert: Option<fn(&rusqlite::Transaction) -> Result<(), Error>>,
+
+    /// A short description of the changes, for `--dry-run`.
+    summary: &'static str,
+
+    /// A short description of the data `revert` discards, for `--dry-run` and the downgrade's
+    /// log. Empty if `revert` is `None`.
+    discards: &'static str,
+
+    /// True iff the step also changes sample file directories, so a database backup alone is
+    /// insufficient to restore.
+    touches_sample_files: bool,
+}
+
+/// The steps to each schema version, indexed by the version they start from.
+const STEPS: [Step; EXPECTED_SCHEMA_VERSION as usize] = [
+    Step {
+        upgrade: v0_to_v1::run,
+        revert: None,
+        summary: "restructures recording tables and indices",
+        discards: "",
+        touches_sample_files: false,
+    },
+    Step {
+        upgrade: v1_to_v2::run,
+        revert: None,
+        summary: "adds users, streams, and multiple sample file directories",
+        discards: "",
+        touches_sample_files: true,
+    },
+    Step {
+        upgrade: v2_to_v3::run,
+        revert: None,
+        summary: "renames sample files to recording ids",
+        discards: "",
+        touches_sample_files: true,
+    },
+    Step {
+        upgrade: v3_to_v4::run,
+        revert: None,
+        summary: "adds permissions and signals",
+        discards: "",
+        touches_sample_files: false,
+    },
+    Step {
+        upgrade: v4_to_v5::run,
+        revert: None,
+        summary: "updates sample file directory metadata",
+        discards: "",
+        touches_sample_files: true,
+    },
+    Step {
+        upgrade: v5_to_v6::run,
+        revert: None,
+        summary: "adds pixel aspect ratios, cumulative durations, and Blake3 hashes; \
+                  revokes sessions",
+        discards: "",
+        touches_sample_files: false,
+    },
+    Step {
+        upgrade: v6_to_v7::run,
+        revert: None,
+        summary: "moves configuration into JSON columns",
+        discards: "",
+        touches_sample_files: false,
+    },
+    Step {
+        upgrade: v7_to_v8::run,
+        revert: Some(v7_to_v8::revert),
+        summary: "adds credential and sample file encryption keys, recordings' packet loss and \
+                  damaged frame counts and composition offsets, runs' RTSP session details, \
+                  permission templates, user download quotas, WebAuthn credentials, hourly \
+                  signal summaries, and an event journal; splits view_video into view_live and \
+                  download_recordings",
+        discards: "packet loss and damaged frame counts, composition offsets (so recordings \
+                   with B-frames play out of order), RTSP session details, permission \
+                   templates, download quota usage, WebAuthn credentials, hourly signal \
+                   summaries, and the event journal; keeps view_video only for users and \
+                   sessions with both view_live and download_recordings",
+        touches_sample_files: false,
+    },
+];
+
+/// As a rule of thumb, each step takes about this long per GiB of database on a Raspberry Pi 4,
+/// as does the final vacuum. See `guide/schema.md`.
+const SECS_PER_GIB_PER_STEP: u64 = 240;
+
+/// A step within a [`Plan`].
+#[derive(Debug)]
+pub struct PlannedStep {
+    /// The schema version after this step.
+    pub to: i32,
+
+    /// A description of the upgrade step, which is undone if this is a downgrade.
+    pub summary: &'static str,
+
+    /// For a downgrade step, a description of the data discarded; otherwise empty.
+    pub discards: &'static str,
+
+    pub touches_sample_files: bool,
+
+    /// False iff this is a downgrade step which isn't implemented.
+    pub supported: bool,
+}
+
+/// A description of what an upgrade or downgrade would do, as returned by [`plan`].
+#[derive(Debug)]
+pub struct Plan {
+    pub from: i32,
+    pub to: i32,
+
+    pub steps: Vec<PlannedStep>,
+
+    /// The current size of the database.
+    pub db_bytes: i64,
+
+    /// The estimated duration on slow hardware such as a Raspberry Pi 4.
+    pub estimated_duration: std::time::Duration,
+
+    /// The estimated free space needed beyond the database itself.
+    pub extra_bytes: i64,
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = if self.to < self.from {
+            "downgrade"
+        } else {
+            "upgrade"
+        };
+        if self.steps.is_empty() {
+            return write!(f, "database is already at schema version {}", self.from);
+        }
+        writeln!(
+            f,
+            "would {verb} database from schema version {} to {}:",
+            self.from, self.to
+        )?;
+        for s in &self.steps {
+            let undo = if self.to < self.from { "undo: " } else { "" };
+            write!(f, "*   to version {}: {undo}{}", s.to, s.summary)?;
+            if !s.discards.is_empty() {
+                write!(f, "; discards {}", s.discards)?;
+            }
+            if s.touches_sample_files {
+                f.write_str(" (also changes sample file directories)")?;
+            }
+            if !s.supported {
+                f.write_str(" (UNSUPPORTED)")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "database size: {}",
+            base::strutil::encode_size(self.db_bytes)
+        )?;
+        writeln!(
+            f,
+            "estimated duration on a Raspberry Pi 4: {} min",
+            self.estimated_duration.as_secs().div_ceil(60)
+        )?;
+        write!(
+            f,
+            "free space needed beyond the database and your backup: {}",
+            base::strutil::encode_size(self.extra_bytes)
+        )
+    }
+}
+
+/// Describes what upgrading (or downgrading) to `target_schema_ver` would do, without changing
+/// anything.
+pub fn plan(
+    args: &Args,
+    target_schema_ver: i32,
+    conn: &rusqlite::Connection,
+) -> Result<Plan, Error> {
+    let from = current_version(conn)?;
+    if !(0..=EXPECTED_SCHEMA_VERSION).contains(&target_schema_ver) {
+        bail!(
+            InvalidArgument,
+            msg("no such schema version {target_schema_ver}")
+        );
+    }
+    let steps: Vec<_> = if target_schema_ver >= from {
+        (from..target_schema_ver)
+            .map(|v| PlannedStep {
+                to: v + 1,
+                summary: STEPS[v as usize].summary,
+                discards: "",
+                touches_sample_files: STEPS[v as usize].touches_sample_files,
+                supported: true,
+            })
+            .collect()
+    } else {
+        (target_schema_ver..from)
+            .rev()
+            .map(|v| PlannedStep {
+                to: v,
+                summary: STEPS[v as usize].summary,
+                discards: STEPS[v as usize].discards,
+                touches_sample_files: STEPS[v as usize].touches_sample_files,
+                supported: STEPS[v as usize].revert.is_some(),
+            })
+            .collect()
+    };
+    let db_bytes: i64 = conn.query_row(
+        "select page_count * page_size from pragma_page_count, pragma_page_size",
+        params![],
+        |row| row.get(0),
+    )?;
+    let upgrading = target_schema_ver > from;
+    let vacuum = upgrading && !args.no_vacuum;
+    let gib = (db_bytes as u64).div_ceil(1 << 30);
+    let estimated_duration = std::time::Duration::from_secs(
+        gib * SECS_PER_GIB_PER_STEP * (steps.len() as u64 + u64::from(vacuum)),
+    );
+
+    // Steps may duplicate tables, occupying space in both the database and the journal; the
+    // vacuum makes a complete copy.
+    let mut extra_bytes = 0;
+    if !steps.is_empty() && args.preset_journal != "off" {
+        extra_bytes += db_bytes;
+    }
+    if vacuum {
+        extra_bytes += db_bytes;
+    }
+    Ok(Plan {
+        from,
+        to: target_schema_ver,
+        steps,
+        db_bytes,
+        estimated_duration,
+        extra_bytes,
+    })
+}
+
+fn current_version(conn: &rusqlite::Connection) -> Result<i32, Error> {
+    let ver = conn.query_row("select max(id) from version", params![], |row| row.get(0))?;
+    if ver > EXPECTED_SCHEMA_VERSION {
+        bail!(
+            FailedPrecondition,
+            msg("database is at version {ver}, later than expected {EXPECTED_SCHEMA_VERSION}"),
+        );
+    } else if ver < 0 {
+        bail!(
+            FailedPrecondition,
+            msg("Database is at negative version {ver}!")
+        );
+    }
+    Ok(ver)
+}
+
 fn set_journal_mode(conn: &rusqlite::Connection, requested: &str) -> Result<(), Error> {
     assert!(!requested.contains(';')); // quick check for accidental sql injection.
     let actual = conn.query_row(
@@ -50,32 +309,8 @@ fn upgrade(
     sw_version: &str,
     conn: &mut rusqlite::Connection,
 ) -> Result<(), Error> {
-    let upgraders = [
-        v0_to_v1::run,
-        v1_to_v2::run,
-        v2_to_v3::run,
-        v3_to_v4::run,
-        v4_to_v5::run,
-        v5_to_v6::run,
-        v6_to_v7::run,
-    ];
-
     {
-        assert_eq!(upgraders.len(), db::EXPECTED_SCHEMA_VERSION as usize);
-        let old_schema_ver =
-            conn.query_row("select max(id) from version", params![], |row| row.get(0))?;
-        if old_schema_ver > EXPECTED_SCHEMA_VERSION {
-            bail!(
-                FailedPrecondition,
-                msg("database is at version {old_schema_ver}, \
-                     later than expected {EXPECTED_SCHEMA_VERSION}"),
-            );
-        } else if old_schema_ver < 0 {
-            bail!(
-                FailedPrecondition,
-                msg("Database is at negative version {old_schema_ver}!")
-            );
-        }
+        let old_schema_ver = current_version(conn)?;
         info!(
             "Upgrading database from schema version {} to schema version {}...",
             old_schema_ver, target_schema_ver
@@ -87,7 +322,7 @@ fn upgrade(
                 ver + 1
             );
             let tx = conn.transaction()?;
-            upgraders[ver as usize](args, &tx)?;
+            (STEPS[ver as usize].upgrade)(args, &tx)?;
             tx.execute(
                 r#"
                 insert into version (id, unix_time, notes)
@@ -131,6 +366,55 @@ pub fn run(args: &Args, sw_version: &str, conn: &mut rusqlite::Connection) -> Re
     Ok(())
 }
 
+/// Downgrades the database to `target_schema_ver`, which must be reachable by implemented
+/// reverse steps.
+///
+/// Each step is applied in its own transaction, so a failure leaves the database at some
+/// intermediate, consistent version.
+pub fn downgrade(target_schema_ver: i32, conn: &mut rusqlite::Connection) -> Result<(), Error> {
+    db::check_sqlite_version()?;
+    db::set_integrity_pragmas(conn)?;
+    let old_schema_ver = current_version(conn)?;
+    if !(0..old_schema_ver).contains(&target_schema_ver) {
+        bail!(
+            InvalidArgument,
+            msg("can't downgrade from schema version {old_schema_ver} to {target_schema_ver}")
+        );
+    }
+    if let Some(v) =
+        (target_schema_ver..old_schema_ver).find(|&v| STEPS[v as usize].revert.is_none())
+    {
+        bail!(
+            Unimplemented,
+            msg(
+                "downgrading from schema version {} to {v} is unsupported",
+                v + 1
+            )
+        );
+    }
+    info!(
+        "Downgrading database from schema version {} to schema version {}...",
+        old_schema_ver, target_schema_ver
+    );
+    for ver in (target_schema_ver..old_schema_ver).rev() {
+        info!(
+            "...from schema version {} to schema version {}",
+            ver + 1,
+            ver
+        );
+        let discards = STEPS[ver as usize].discards;
+        if !discards.is_empty() {
+            warn!("...discarding {discards}");
+        }
+        let tx = conn.transaction()?;
+        (STEPS[ver as usize].revert.expect("checked above"))(&tx)?;
+        tx.execute("delete from version where id > ?", params![ver])?;
+        tx.commit()?;
+    }
+    info!("...done.");
+    Ok(())
+}
+
 /// A uuid-based path, as used in version 0 and version 1 schemas.
 struct UuidPath([u8; 37]);
 
@@ -291,7 +575,8 @@ mod tests {
             (4, None), // transitional; don't compare schemas.
             (5, Some(include_str!("v5.sql"))),
             (6, Some(include_str!("v6.sql"))),
-            (7, Some(include_str!("../schema.sql"))),
+            (7, Some(include_str!("v7.sql"))),
+            (8, Some(include_str!("../schema.sql"))),
         ] {
             upgrade(
                 &Args {
@@ -369,4 +654,153 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn plan_and_downgrade() -> Result<(), Error> {
+        testutil::init();
+        let args = Args {
+            sample_file_dir: None,
+            preset_journal: "delete",
+            no_vacuum: false,
+        };
+        let mut conn = new_conn()?;
+        conn.execute_batch(include_str!("v7.sql"))?;
+        let p = plan(&args, EXPECTED_SCHEMA_VERSION, &conn)?;
+        assert_eq!((p.from, p.to), (7, 8));
+        assert_eq!(p.steps.len(), 1);
+        assert_eq!(p.extra_bytes, 2 * p.db_bytes);
+        upgrade(&args, 8, "test", &mut conn)?;
+
+        let p = plan(&args, 7, &conn)?;
+        assert!(p.steps[0].supported);
+        assert!(p.to_string().contains("discards packet loss"));
+        assert!(!plan(&args, 6, &conn)?.steps[1].supported);
+        downgrade(7, &mut conn)?;
+        compare(&conn, 7, include_str!("v7.sql"))?;
+
+        // Encrypted credentials block the downgrade; the database is left at version 8.
+        upgrade(&args, 8, "test", &mut conn)?;
+        conn.execute(
+            "insert into credential_key (id, wrapped_key) values (1, zeroblob(60))",
+            params![],
+        )?;
+        let e = downgrade(7, &mut conn).unwrap_err();
+        assert_eq!(e.kind(), base::ErrorKind::FailedPrecondition);
+        assert_eq!(current_version(&conn)?, 8);
+        Ok(())
+    }
+
+    /// Downgrades recordings whose video indexes have composition offsets.
+    #[test]
+    fn downgrade_composition_offsets() -> Result<(), Error> {
+        testutil::init();
+        let mut conn = new_conn()?;
+        db::init(&mut conn)?;
+
+        // Two samples of 3000 ticks each: a 10-byte key frame with offset 0 and a 20-byte
+        // non-key frame with offset 3000. Each has duration/key, bytes, and offset varints.
+        conn.execute_batch(
+            r#"
+            insert into open (id, uuid) values (1, X'00000000000000000000000000000001');
+            insert into camera (id, uuid, short_name, config)
+                        values (1, X'00000000000000000000000000000001', 'c', '{}');
+            insert into stream (id, camera_id, type, config, cum_recordings,
+                                cum_media_duration_90k, cum_runs)
+                        values (1, 1, 'main', '{}', 2, 6000, 1);
+            insert into recording (composite_id, open_id, stream_id, run_offset, flags,
+                                   sample_file_bytes, start_time_90k, prev_media_duration_90k,
+                                   prev_runs, wall_duration_90k, media_duration_delta_90k,
+                                   video_samples, video_sync_samples)
+                           values (4294967296, 1, 1, 0, 13, 30, 1, 0, 0, 6000, 0, 2, 1),
+                                  (4294967297, 1, 1, 1, 1, 30, 6001, 6000, 0, 6000, 0, 2, 1);
+            insert into recording_playback (composite_id, video_index)
+                                    values (4294967296, X'E15D14000028F02E'),
+                                           (4294967297, X'E15D140028');
+            "#,
+        )?;
+        downgrade(7, &mut conn)?;
+        compare(&conn, 7, include_str!("v7.sql"))?;
+        let mut stmt = conn.prepare(
+            r#"
+            select flags, video_index
+            from recording join recording_playback using (composite_id)
+            order by composite_id
+            "#,
+        )?;
+        let rows: Vec<(i32, Vec<u8>)> = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            rows,
+            [
+                (1, b"\xe1\x5d\x14\x00\x28".to_vec()),
+                (1, b"\xe1\x5d\x14\x00\x28".to_vec()),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Downgrades a database created from `schema.sql` rather than upgraded from version 7.
+    #[test]
+    fn downgrade_fresh() -> Result<(), Error> {
+        testutil::init();
+        let mut conn = new_conn()?;
+        db::init(&mut conn)?;
+        downgrade(7, &mut conn)?;
+        assert_eq!(current_version(&conn)?, 7);
+        compare(&conn, 7, include_str!("v7.sql"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn split_view_video() -> Result<(), Error> {
+        testutil::init();
+        let args = Args {
+            sample_file_dir: None,
+            preset_journal: "delete",
+            no_vacuum: false,
+        };
+        let mut conn = new_conn()?;
+        conn.execute_batch(include_str!("v7.sql"))?;
+        conn.execute_batch(
+            r#"
+            insert into user (id, username, permissions) values (1, 'viewer', X'0801'),
+                                                                (2, 'reader', X'1001');
+            insert into user_session (session_id_hash, user_id, seed, flags, creation_time_sec,
+                                      permissions)
+                              values (X'00', 1, X'00', 0, 0, X'08011001');
+            "#,
+        )?;
+        let perms = |conn: &rusqlite::Connection| -> Result<Vec<Vec<u8>>, Error> {
+            let mut stmt = conn.prepare(
+                r#"
+                select permissions from user
+                union all
+                select permissions from user_session
+                "#,
+            )?;
+            let rows = stmt.query_map(params![], |row| row.get(0))?;
+            Ok(rows.collect::<Result<_, _>>()?)
+        };
+
+        // view_video (1) becomes view_live (5) and download_recordings (6).
+        upgrade(&args, 8, "test", &mut conn)?;
+        assert_eq!(
+            perms(&conn)?,
+            [
+                &b"\x28\x01\x30\x01"[..],
+                b"\x10\x01",
+                b"\x10\x01\x28\x01\x30\x01",
+            ]
+        );
+
+        // Downgrading restores view_video only where both replacements are present.
+        conn.execute(
+            "update user set permissions = X'2801' where id = 2",
+            params![],
+        )?;
+        downgrade(7, &mut conn)?;
+        assert_eq!(perms(&conn)?, [&b"\x08\x01"[..], b"", b"\x08\x01\x10\x01"]);
+        Ok(())
+    }
 }
@@ -20,7 +20,11 @@ use std::sync::Arc;
 /// Makes a couple simplifying assumptions valid for version 2:
 /// *   there's only one dir.
 /// *   it has a last completed open.
-fn open_sample_file_dir(tx: &rusqlite::Transaction) -> Result<Arc<dir::SampleFileDir>, Error> {
+/// Opens the sample file directory, returning it (which holds the directory's lock) and a separate
+/// descriptor for renaming files within it.
+fn open_sample_file_dir(
+    tx: &rusqlite::Transaction,
+) -> Result<(Arc<dir::SampleFileDir>, dir::Fd), Error> {
     let (p, s_uuid, o_id, o_uuid, db_uuid): (String, SqlUuid, i32, SqlUuid, SqlUuid) = tx
         .query_row(
             r#"
@@ -51,11 +55,13 @@ fn open_sample_file_dir(tx: &rusqlite::Transaction) -> Result<Arc<dir::SampleFil
         open.uuid.extend_from_slice(&o_uuid.0.as_bytes()[..]);
     }
     let p = PathBuf::from(p);
-    dir::SampleFileDir::open(&p, &meta)
+    let d = dir::SampleFileDir::open(&p, &meta)?;
+    let fd = dir::Fd::open(&p, false)?;
+    Ok((d, fd))
 }
 
 pub fn run(_args: &super::Args, tx: &rusqlite::Transaction) -> Result<(), Error> {
-    let d = open_sample_file_dir(tx)?;
+    let (_d, fd) = open_sample_file_dir(tx)?;
     let mut stmt = tx.prepare(
         r#"
         select
@@ -72,9 +78,9 @@ pub fn run(_args: &super::Args, tx: &rusqlite::Transaction) -> Result<(), Error>
         let from_path = super::UuidPath::from(sample_file_uuid.0);
         let to_path = crate::dir::CompositeIdPath::from(id);
         if let Err(e) = nix::fcntl::renameat(
-            Some(d.fd.as_fd().as_raw_fd()),
+            Some(fd.as_fd().as_raw_fd()),
             &from_path,
-            Some(d.fd.as_fd().as_raw_fd()),
+            Some(fd.as_fd().as_raw_fd()),
             &to_path,
         ) {
             if e == nix::Error::ENOENT {
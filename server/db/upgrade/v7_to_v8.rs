@@ -0,0 +1,219 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+/// Upgrades a version 7 schema to a version 8 schema.
+use crate::coding::decode_varint32;
+use crate::db::CompositeId;
+use crate::schema::Permissions;
+use base::{bail, err, Error, ErrorKind, ResultExt as _};
+use protobuf::Message;
+use rusqlite::{named_params, params};
+
+/// Rewrites the `permissions` blob of each row in `table` with `f`.
+fn update_permissions(
+    tx: &rusqlite::Transaction,
+    table: &str,
+    key: &str,
+    f: fn(&mut Permissions),
+) -> Result<(), Error> {
+    let mut stmt = tx.prepare(&format!("select {key}, permissions from {table}"))?;
+    let mut update = tx.prepare(&format!(
+        "update {table} set permissions = :permissions where {key} = :key"
+    ))?;
+    let mut rows = stmt.query(params![])?;
+    while let Some(row) = rows.next()? {
+        let key: rusqlite::types::Value = row.get(0)?;
+        let mut permissions = Permissions::new();
+        permissions
+            .merge_from_bytes(row.get_ref(1)?.as_blob()?)
+            .err_kind(ErrorKind::DataLoss)?;
+        f(&mut permissions);
+        let permissions = permissions
+            .write_to_bytes()
+            .expect("proto3->vec is infallible");
+        update.execute(named_params! {
+            ":permissions": permissions,
+            ":key": key,
+        })?;
+    }
+    Ok(())
+}
+
+/// Splits the obsolete `view_video` permission into `view_live` and `download_recordings`.
+fn split_view_video(p: &mut Permissions) {
+    if std::mem::take(&mut p.view_video) {
+        p.view_live = true;
+        p.download_recordings = true;
+    }
+}
+
+/// Inverse of `split_view_video`. Only grants `view_video` if both of its replacements are
+/// present, so that reverting never widens a user's access.
+fn join_view_video(p: &mut Permissions) {
+    p.view_video = std::mem::take(&mut p.view_live) & std::mem::take(&mut p.download_recordings);
+}
+
+pub fn run(_args: &super::Args, tx: &rusqlite::Transaction) -> Result<(), Error> {
+    // This create statement matches the schema.sql when version 8 was the latest.
+    tx.execute_batch(
+        r#"
+        create table credential_key (
+          id integer primary key check (id = 1),
+          wrapped_key blob not null check (length(wrapped_key) = 60)
+        );
+        create table sample_file_key (
+          id integer primary key check (id = 1),
+          key_check blob not null check (length(key_check) = 32)
+        );
+        alter table recording add column packets_lost integer not null default 0
+            check (packets_lost >= 0);
+        alter table recording add column damaged_frames integer not null default 0
+            check (damaged_frames >= 0);
+        create table recording_rtsp_session (
+          composite_id integer primary key references recording (composite_id),
+          sdp blob not null,
+          transport text,
+          server text
+        );
+        alter table user add column permission_template_id integer;
+        alter table user add column download_day integer not null default 0;
+        alter table user add column download_bytes integer not null default 0;
+        create table permission_template (
+          id integer primary key,
+          name text unique not null,
+          permissions blob not null default X''
+        );
+        insert into permission_template (name,       permissions)
+                                 values ('viewer',   X'28013001'),
+                                        ('operator', X'1001180128013001'),
+                                        ('admin',    X'10011801200128013001');
+        create table user_webauthn_credential (
+          id integer primary key,
+          user_id integer references user (id) not null,
+          credential_id blob unique not null,
+          public_key blob not null,
+          sign_count integer not null default 0 check (sign_count >= 0),
+          name text not null,
+          creation_time_sec integer not null,
+          last_use_time_sec integer
+        );
+        create index user_webauthn_credential_uid on user_webauthn_credential (user_id);
+        create table signal_hour (
+          start_time_90k integer not null check (start_time_90k % 324000000 = 0),
+          signal_id integer not null references signal (id),
+          states blob not null,
+          primary key (start_time_90k, signal_id)
+        ) without rowid;
+        create table event (
+          id integer primary key,
+          time_90k integer not null,
+          kind text not null,
+          stream_id integer,
+          message text not null
+        );
+        create index event_time on event (time_90k);
+        "#,
+    )?;
+    update_permissions(tx, "user", "id", split_view_video)?;
+    update_permissions(tx, "user_session", "session_id_hash", split_view_video)?;
+    Ok(())
+}
+
+/// Rewrites the video indexes of recordings with composition offsets (flag 4) to version 7's
+/// format by dropping each sample's third varint, and clears that flag and "reordered" (flag 8).
+fn strip_composition_offsets(tx: &rusqlite::Transaction) -> Result<(), Error> {
+    let mut stmt = tx.prepare(
+        r#"
+        select
+          recording_playback.composite_id,
+          recording_playback.video_index
+        from
+          recording
+          join recording_playback on (recording.composite_id = recording_playback.composite_id)
+        where
+          recording.flags & 4 != 0
+        "#,
+    )?;
+    let mut update = tx.prepare(
+        "update recording_playback set video_index = :video_index where composite_id = :id",
+    )?;
+    let mut rows = stmt.query(params![])?;
+    while let Some(row) = rows.next()? {
+        let id = CompositeId(row.get(0)?);
+        let index = row.get_ref(1)?.as_blob()?;
+        let skip = |i| {
+            decode_varint32(index, i)
+                .map(|(_, next)| next)
+                .map_err(|()| err!(DataLoss, msg("bad video index for recording {id}")))
+        };
+        let mut stripped = Vec::with_capacity(index.len());
+        let mut i = 0;
+        while i < index.len() {
+            let offset = skip(skip(i)?)?;
+            stripped.extend_from_slice(&index[i..offset]);
+            i = skip(offset)?;
+        }
+        update.execute(named_params! {
+            ":video_index": stripped,
+            ":id": id.0,
+        })?;
+    }
+    tx.execute(
+        "update recording set flags = flags & ~12 where flags & 4 != 0",
+        params![],
+    )?;
+    Ok(())
+}
+
+/// Reverts a version 8 schema to a version 7 schema.
+///
+/// This fails if encryption is in use, as version 7 can't represent encrypted credentials or
+/// sample files. Recordings' packet loss statistics and composition offsets, runs' RTSP session
+/// details, permission templates, download quota usage, WebAuthn credentials, hourly signal
+/// summaries, and the event journal are discarded; users keep their current permissions, except
+/// that `view_video` is restored only to users and sessions which have both `view_live` and
+/// `download_recordings`.
+pub fn revert(tx: &rusqlite::Transaction) -> Result<(), Error> {
+    let (credentials, sample_files): (bool, bool) = tx.query_row(
+        r#"
+        select
+          exists (select 1 from credential_key),
+          exists (select 1 from sample_file_key)
+        "#,
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if credentials {
+        bail!(
+            FailedPrecondition,
+            msg("camera credentials are encrypted, which version 7 doesn't support")
+        );
+    }
+    if sample_files {
+        bail!(
+            FailedPrecondition,
+            msg("sample file encryption is enabled, which version 7 doesn't support")
+        );
+    }
+    strip_composition_offsets(tx)?;
+    tx.execute_batch(
+        r#"
+        drop table credential_key;
+        drop table sample_file_key;
+        drop table recording_rtsp_session;
+        alter table recording drop column packets_lost;
+        alter table recording drop column damaged_frames;
+        drop table permission_template;
+        drop table user_webauthn_credential;
+        drop table signal_hour;
+        drop table event;
+        alter table user drop column permission_template_id;
+        alter table user drop column download_day;
+        alter table user drop column download_bytes;
+        "#,
+    )?;
+    update_permissions(tx, "user", "id", join_view_video)?;
+    update_permissions(tx, "user_session", "session_id_hash", join_view_video)?;
+    Ok(())
+}
@@ -6,12 +6,14 @@
 
 use crate::db::{self, CompositeId};
 use crate::dir;
+use crate::event;
 use crate::recording::{self, MAX_RECORDING_WALL_DURATION};
 use base::clock::{self, Clocks};
 use base::shutdown::ShutdownError;
 use base::FastHashMap;
 use base::{bail, err, Error};
-use std::cmp::{self, Ordering};
+use std::cmp::{self, Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 use std::convert::TryFrom;
 use std::io;
 use std::mem;
@@ -32,6 +34,10 @@ pub trait DirWriter: 'static + Send {
     fn create_file(&self, id: CompositeId) -> Result<Self::File, nix::Error>;
     fn sync(&self) -> Result<(), nix::Error>;
     fn unlink_file(&self, id: CompositeId) -> Result<(), nix::Error>;
+
+    /// Returns the bytes available to unprivileged users on the directory's filesystem, as in
+    /// `statvfs`.
+    fn free_bytes(&self) -> Result<i64, nix::Error>;
 }
 
 /// Trait to allow mocking out [std::fs::File] in syncer tests.
@@ -57,6 +63,9 @@ impl DirWriter for Arc<dir::SampleFileDir> {
     fn unlink_file(&self, id: CompositeId) -> Result<(), nix::Error> {
         dir::SampleFileDir::unlink_file(self, id)
     }
+    fn free_bytes(&self) -> Result<i64, nix::Error> {
+        dir::SampleFileDir::free_bytes(self)
+    }
 }
 
 impl FileWriter for ::std::fs::File {
@@ -79,6 +88,9 @@ enum SyncerCommand<F> {
 
     /// Command sent by [SyncerChannel::flush].
     Flush(mpsc::SyncSender<()>),
+
+    /// Command sent by [SyncerChannel::ping].
+    Ping(tokio::sync::oneshot::Sender<()>),
 }
 
 /// A channel which can be used to send commands to the syncer.
@@ -164,13 +176,36 @@ pub fn start_syncer<C>(
 where
     C: Clocks + Clone,
 {
-    let db2 = db.clone();
-    let (mut syncer, path) = Syncer::new(&db.lock(), shutdown_rx, db2, dir_id)?;
+    start_syncer_with_dir(db, shutdown_rx, dir_id, |d| d)
+}
+
+/// Starts a syncer as in [`start_syncer`], but accessing the directory after initial rotation
+/// through the `DirWriter` returned by `wrap`. This is for injecting faults in tests; see
+/// [`crate::testutil::FaultyDir`].
+pub fn start_syncer_with_dir<C, D, W>(
+    db: Arc<db::Database<C>>,
+    shutdown_rx: base::shutdown::Receiver,
+    dir_id: i32,
+    wrap: W,
+) -> Result<(SyncerChannel<D::File>, thread::JoinHandle<()>), Error>
+where
+    C: Clocks + Clone,
+    D: DirWriter,
+    W: FnOnce(Arc<dir::SampleFileDir>) -> D,
+{
+    let (mut syncer, path) = Syncer::new(shutdown_rx, db.clone(), dir_id)?;
     let span = tracing::info_span!("syncer", path = %path.display());
     span.in_scope(|| {
         tracing::info!("initial rotation");
         syncer.initial_rotation()
     })?;
+    let mut syncer = Syncer {
+        dir_id: syncer.dir_id,
+        dir: wrap(syncer.dir),
+        db: syncer.db,
+        planned_flushes: syncer.planned_flushes,
+        shutdown_rx: syncer.shutdown_rx,
+    };
     let (snd, rcv) = mpsc::channel();
     db.lock().on_flush(Box::new({
         let snd = snd.clone();
@@ -211,9 +246,8 @@ pub fn lower_retention(
     dir_id: i32,
     limits: &[NewLimit],
 ) -> Result<(), Error> {
-    let db2 = db.clone();
     let (_tx, rx) = base::shutdown::channel();
-    let (mut syncer, _) = Syncer::new(&db.lock(), rx, db2, dir_id)?;
+    let (mut syncer, _) = Syncer::new(rx, db.clone(), dir_id)?;
     syncer.do_rotation(|db| {
         for l in limits {
             let (fs_bytes_before, extra);
@@ -247,6 +281,10 @@ fn delete_recordings(
             None => bail!(NotFound, msg("no stream {stream_id}")),
             Some(s) => s,
         };
+        if !stream.loaded {
+            debug!("{stream_id}: deferring retention until recordings are loaded");
+            return Ok(());
+        }
         stream.fs_bytes + stream.fs_bytes_to_add - stream.fs_bytes_to_delete + extra_bytes_needed
             - stream.config.retain_bytes
     };
@@ -271,6 +309,80 @@ fn delete_recordings(
     Ok(())
 }
 
+/// Enqueues deletion of a sample file directory's oldest recordings, across all its streams, to
+/// keep at least its `min_free_bytes` available on the filesystem. This protects against other
+/// users of the filesystem causing `ENOSPC` even when every stream is within its `retain_bytes`.
+///
+/// `free_bytes` is the filesystem's current free space, as from [`DirWriter::free_bytes`].
+fn delete_for_free_space(
+    db: &mut db::LockedDatabase,
+    dir_id: i32,
+    free_bytes: i64,
+) -> Result<(), Error> {
+    let min_free_bytes = {
+        let Some(d) = db.sample_file_dirs_by_id().get(&dir_id) else {
+            bail!(NotFound, msg("no dir {dir_id}"));
+        };
+        if d.min_free_bytes == 0 || !d.garbage_needs_unlink.is_empty() {
+            // Either there's no floor, or `free_bytes` doesn't yet reflect recordings which
+            // have been deleted but not unlinked. In the latter case, check again on the next
+            // save after garbage collection.
+            return Ok(());
+        }
+        d.min_free_bytes
+    };
+    let streams: Vec<i32> = db
+        .streams_by_id()
+        .iter()
+        .filter(|(_, s)| s.sample_file_dir_id == Some(dir_id))
+        .map(|(&id, _)| id)
+        .collect();
+    let pending: i64 = streams
+        .iter()
+        .map(|id| db.streams_by_id()[id].fs_bytes_to_delete)
+        .sum();
+    let mut fs_bytes_needed = min_free_bytes - free_bytes - pending;
+    if fs_bytes_needed <= 0 {
+        return Ok(());
+    }
+    debug!(
+        "dir {}: deleting {} to keep {} free",
+        dir_id,
+        base::strutil::encode_size(fs_bytes_needed),
+        base::strutil::encode_size(min_free_bytes)
+    );
+    while fs_bytes_needed > 0 {
+        let mut oldest: Option<(recording::Time, i32)> = None;
+        for &stream_id in &streams {
+            db.delete_oldest_recordings(stream_id, &mut |row| {
+                if oldest.map_or(true, |(start, _)| row.start < start) {
+                    oldest = Some((row.start, stream_id));
+                }
+                false
+            })?;
+        }
+        let Some((_, stream_id)) = oldest else {
+            warn!(
+                "dir {}: no more committed recordings to delete; {} short of keeping {} free",
+                dir_id,
+                base::strutil::encode_size(fs_bytes_needed),
+                base::strutil::encode_size(min_free_bytes)
+            );
+            break;
+        };
+        let mut deleted = false;
+        db.delete_oldest_recordings(stream_id, &mut |row| {
+            if deleted {
+                return false;
+            }
+            deleted = true;
+            fs_bytes_needed -= db::round_up(i64::from(row.sample_file_bytes));
+            true
+        })?;
+    }
+    Ok(())
+}
+
 impl<F: FileWriter> SyncerChannel<F> {
     /// Asynchronously syncs the given writer, closes it, records it into the database, and
     /// starts rotation.
@@ -288,71 +400,47 @@ impl<F: FileWriter> SyncerChannel<F> {
         self.0.send(SyncerCommand::Flush(snd)).unwrap();
         rcv.recv().unwrap_err(); // syncer should just drop the channel, closing it.
     }
-}
 
-/// Lists files which should be "abandoned" (deleted without ever recording in the database)
-/// on opening.
-fn list_files_to_abandon(
-    dir: &dir::SampleFileDir,
-    streams_to_next: FastHashMap<i32, i32>,
-) -> Result<Vec<CompositeId>, Error> {
-    let mut v = Vec::new();
-    let mut d = dir.opendir()?;
-    for e in d.iter() {
-        let e = e?;
-        let id = match dir::parse_id(e.file_name().to_bytes()) {
-            Ok(i) => i,
-            Err(_) => continue,
-        };
-        let next = match streams_to_next.get(&id.stream()) {
-            Some(n) => *n,
-            None => continue, // unknown stream.
-        };
-        if id.recording() >= next {
-            v.push(id);
-        }
+    /// Checks the syncer is responsive, returning a receiver which completes successfully once
+    /// it has handled all previously-queued commands. The receiver fails if the syncer has exited.
+    pub fn ping(&self) -> tokio::sync::oneshot::Receiver<()> {
+        let (snd, rcv) = tokio::sync::oneshot::channel();
+        let _ = self.0.send(SyncerCommand::Ping(snd));
+        rcv
     }
-    Ok(v)
 }
 
 impl<C: Clocks + Clone> Syncer<C, Arc<dir::SampleFileDir>> {
     fn new(
-        l: &db::LockedDatabase,
         shutdown_rx: base::shutdown::Receiver,
         db: Arc<db::Database<C>>,
         dir_id: i32,
     ) -> Result<(Self, PathBuf), Error> {
-        let d = l
-            .sample_file_dirs_by_id()
-            .get(&dir_id)
-            .ok_or_else(|| err!(NotFound, msg("no dir {dir_id}")))?;
-        let dir = d.get()?;
+        // Get the directory and the streams in question, then scan without holding the lock,
+        // as the scan may take a while on a large directory. Until the syncer is running, nothing
+        // else writes to these streams.
+        let (dir, path, streams_to_next) = {
+            let l = db.lock();
+            let d = l
+                .sample_file_dirs_by_id()
+                .get(&dir_id)
+                .ok_or_else(|| err!(NotFound, msg("no dir {dir_id}")))?;
+            let streams_to_next: FastHashMap<_, _> = l
+                .streams_by_id()
+                .iter()
+                .filter_map(|(&k, v)| {
+                    if v.sample_file_dir_id == Some(dir_id) {
+                        Some((k, v.cum_recordings))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            (d.get()?, d.path.clone(), streams_to_next)
+        };
 
         // Abandon files.
-        // First, get a list of the streams in question.
-        let streams_to_next: FastHashMap<_, _> = l
-            .streams_by_id()
-            .iter()
-            .filter_map(|(&k, v)| {
-                if v.sample_file_dir_id == Some(dir_id) {
-                    Some((k, v.cum_recordings))
-                } else {
-                    None
-                }
-            })
-            .collect();
-        let to_abandon = list_files_to_abandon(&dir, streams_to_next)?;
-        let mut undeletable = 0;
-        for &id in &to_abandon {
-            if let Err(err) = dir.unlink_file(id) {
-                if err == nix::Error::ENOENT {
-                    warn!(%id, "dir: abandoned recording already deleted");
-                } else {
-                    warn!(%err, %id, "dir: unable to unlink abandoned recording");
-                    undeletable += 1;
-                }
-            }
-        }
+        let undeletable = dir.abandon_files(&streams_to_next)?;
         if undeletable > 0 {
             bail!(
                 Unknown,
@@ -368,7 +456,7 @@ impl<C: Clocks + Clone> Syncer<C, Arc<dir::SampleFileDir>> {
                 db,
                 planned_flushes: std::collections::BinaryHeap::new(),
             },
-            d.path.clone(),
+            path,
         ))
     }
 
@@ -473,6 +561,9 @@ impl<C: Clocks + Clone, D: DirWriter> Syncer<C, D> {
                     f.senders.push(flush);
                 }
             }
+            SyncerCommand::Ping(ping) => {
+                let _ = ping.send(());
+            }
         };
 
         true
@@ -527,9 +618,31 @@ impl<C: Clocks + Clone, D: DirWriter> Syncer<C, D> {
         clock::retry(&self.db.clocks(), &self.shutdown_rx, &mut || {
             self.dir.sync()
         })?;
+        let has_floor = self
+            .db
+            .lock()
+            .sample_file_dirs_by_id()
+            .get(&self.dir_id)
+            .unwrap()
+            .min_free_bytes
+            > 0;
+        let free_bytes = if has_floor {
+            match self.dir.free_bytes() {
+                Ok(b) => Some(b),
+                Err(err) => {
+                    warn!(%err, "dir: unable to check free space");
+                    None
+                }
+            }
+        } else {
+            None
+        };
         let mut db = self.db.lock();
         db.mark_synced(id).unwrap();
         delete_recordings(&mut db, stream_id, 0).unwrap();
+        if let Some(free_bytes) = free_bytes {
+            delete_for_free_space(&mut db, self.dir_id, free_bytes).unwrap();
+        }
         let s = db.streams_by_id().get(&stream_id).unwrap();
         let c = db.cameras_by_id().get(&s.camera_id).unwrap();
 
@@ -605,6 +718,14 @@ impl<C: Clocks + Clone, D: DirWriter> Syncer<C, D> {
                 "flush failure on save for reason {}; will retry after {}: {:?}",
                 f.reason, d, e
             );
+
+            // This is written by the next successful flush.
+            l.add_event(event::Event {
+                time: recording::Time::new(self.db.clocks().realtime()),
+                kind: event::EventKind::FlushError,
+                stream_id: None,
+                message: format!("{} (flush reason: {})", e.chain(), f.reason),
+            });
             self.planned_flushes
                 .peek_mut()
                 .expect("planned_flushes is non-empty")
@@ -627,6 +748,9 @@ pub struct Writer<'a, C: Clocks + Clone, D: DirWriter> {
     channel: &'a SyncerChannel<D::File>,
     stream_id: i32,
     state: WriterState<D::File>,
+
+    /// Saved with the run's first recording; see [`Writer::set_rtsp_session`].
+    rtsp_session: Option<Arc<db::RtspSession>>,
 }
 
 // clippy points out that the `Open` variant is significantly larger and
@@ -651,6 +775,9 @@ struct InnerWriter<F: FileWriter> {
     id: CompositeId,
     video_sample_entry_id: i32,
 
+    /// If the sample file is encrypted, the sealer for its records. Each frame is one record.
+    sealer: Option<dir::crypt::Sealer>,
+
     hasher: blake3::Hasher,
 
     /// The start time of this recording, based solely on examining the local clock after frames in
@@ -660,33 +787,118 @@ struct InnerWriter<F: FileWriter> {
     /// are discovered. See design/time.md for details.
     local_start: recording::Time,
 
-    /// A sample which has been written to disk but not added to `index`. Index writes are one
-    /// sample behind disk writes because the duration of a sample is the difference between its
-    /// pts and the next sample's pts. A sample is flushed when the next sample is written, when
-    /// the writer is closed cleanly (the caller supplies the next pts), or when the writer is
-    /// closed uncleanly (with a zero duration, which the `.mp4` format allows only at the end).
+    /// Samples which have been written to disk but not added to `index`, in decode order. Index
+    /// writes are at least one sample behind disk writes because the duration of a sample is the
+    /// difference between its dts and the next sample's dts. A sample is flushed when the
+    /// following sample's dts is known, when the writer is closed cleanly (the caller supplies the
+    /// next pts), or when the writer is closed uncleanly (with a zero duration, which the `.mp4`
+    /// format allows only at the end).
     ///
-    /// `unindexed_sample` should always be `Some`, except when a `write` call has aborted on
+    /// `unindexed` should always be non-empty, except when a `write` call has aborted on
     /// shutdown. In that case, the close will be unable to write the full segment.
-    unindexed_sample: Option<UnindexedSample>,
+    unindexed: VecDeque<UnindexedSample>,
+
+    /// Assigns dts to `unindexed`'s samples.
+    reorderer: Reorderer,
+
+    /// True if the stream may reorder frames, so the recording has
+    /// [`db::RecordingFlags::CompositionOffsets`].
+    composition_offsets: bool,
+
+    /// True if packets have been lost since the last key frame, so subsequent frames count
+    /// toward `damaged_frames`.
+    damaged: bool,
+
+    /// Accounts for the growing `video_index` in [`base::mem::BUDGET`].
+    index_charge: base::mem::Charge<'static>,
 }
 
 /// A sample which has been written to disk but not included in the index yet.
 /// The index includes the sample's duration, which is calculated from the
-/// _following_ sample's pts, so the most recent sample is always unindexed.
+/// _following_ sample's dts, so the most recent sample is always unindexed.
 #[derive(Copy, Clone)]
 struct UnindexedSample {
     local_time: recording::Time,
     pts_90k: i64, // relative to the start of the run, not a single recording.
+
+    /// The decode timestamp, in the same timescale as `pts_90k`, once known.
+    dts_90k: Option<i64>,
     len: i32,
     is_key: bool,
 }
 
+/// The most frames [`Reorderer`] will hold back; H.264's maximum decoded picture buffer size.
+const MAX_REORDER_FRAMES: usize = 16;
+
+/// Assigns decode timestamps (dts) to frames which may arrive out of presentation order, as they
+/// do when a camera sends B-frames.
+///
+/// Frames arrive in decode order, each with a presentation timestamp (pts). The dts are the same
+/// values sorted in ascending order, so a frame's dts is known once `depth` more frames have
+/// arrived. `depth` starts from the H.264 SPS's `max_num_reorder_frames` (or zero if it's
+/// unspecified) and grows whenever a frame arrives later than it allows. Until then, dts are
+/// bumped as needed to keep them strictly increasing. The resulting composition offsets may be
+/// negative, but presentation times are still exact.
+#[derive(Debug, Default)]
+struct Reorderer {
+    depth: usize,
+
+    /// The pts of frames not yet assigned a dts.
+    pending: BinaryHeap<Reverse<i64>>,
+
+    /// The greatest pts which has been assigned as a dts.
+    max_assigned_pts: Option<i64>,
+    last_dts: Option<i64>,
+}
+
+impl Reorderer {
+    fn new(depth: usize) -> Self {
+        Reorderer {
+            depth,
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if a frame with the given pts arrives too late for the current depth.
+    fn is_late(&self, pts_90k: i64) -> bool {
+        self.max_assigned_pts.is_some_and(|m| pts_90k < m)
+    }
+
+    /// Adds a frame, returning the dts of the earliest frame (in decode order) not yet assigned
+    /// one, if it's now known.
+    fn push(&mut self, pts_90k: i64) -> Option<i64> {
+        if self.is_late(pts_90k) {
+            self.depth += 1;
+        }
+        self.pending.push(Reverse(pts_90k));
+        if self.pending.len() > self.depth {
+            self.pop()
+        } else {
+            None
+        }
+    }
+
+    /// Assigns the next dts regardless of depth, as when the recording ends.
+    fn pop(&mut self) -> Option<i64> {
+        let Reverse(pts_90k) = self.pending.pop()?;
+        self.max_assigned_pts = Some(self.max_assigned_pts.map_or(pts_90k, |m| m.max(pts_90k)));
+        let dts_90k = match self.last_dts {
+            Some(l) if pts_90k <= l => l + 1,
+            _ => pts_90k,
+        };
+        self.last_dts = Some(dts_90k);
+        Some(dts_90k)
+    }
+}
+
 /// State associated with a run's previous recording; used within [Writer].
 #[derive(Copy, Clone)]
 struct PreviousWriter {
     end: recording::Time,
     run_offset: i32,
+
+    /// The previous recording's [`Reorderer`] depth, so later recordings needn't learn it again.
+    reorder_depth: usize,
 }
 
 impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
@@ -703,13 +915,20 @@ impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
             channel,
             stream_id,
             state: WriterState::Unopened,
+            rtsp_session: None,
         }
     }
 
+    /// Sets the details of the RTSP session producing this run, to be saved with its first
+    /// recording.
+    pub fn set_rtsp_session(&mut self, s: Arc<db::RtspSession>) {
+        self.rtsp_session = Some(s);
+    }
+
     /// Opens a new recording if not already open.
     ///
     /// On successful return, `self.state` will be `WriterState::Open(w)` with `w` violating the
-    /// invariant that `unindexed_sample` is `Some`. The caller (`write`) is responsible for
+    /// invariant that `unindexed` is non-empty. The caller (`write`) is responsible for
     /// correcting this.
     fn open(
         &mut self,
@@ -726,30 +945,78 @@ impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
             }
             WriterState::Closed(prev) => Some(prev),
         };
-        let (id, r) = self.db.lock().add_recording(
-            self.stream_id,
-            db::RecordingToInsert {
-                run_offset: prev.map(|p| p.run_offset + 1).unwrap_or(0),
-                start: prev.map(|p| p.end).unwrap_or(recording::Time::MAX),
-                video_sample_entry_id,
-                flags: db::RecordingFlags::Growing as i32,
-                ..Default::default()
-            },
-        )?;
-        let f = clock::retry(&self.db.clocks(), shutdown_rx, &mut || {
+        let (id, r, key, reorder_depth) = {
+            let mut l = self.db.lock();
+            let key = l.sample_file_key().cloned();
+            let encrypted_flag = if key.is_some() {
+                db::RecordingFlags::Encrypted as i32
+            } else {
+                0
+            };
+            let max_reorder_frames = l
+                .video_sample_entries_by_id()
+                .get(&video_sample_entry_id)
+                .map_or(Some(0), |e| crate::h264::max_reorder_frames(&e.data));
+            let reorder_depth = match max_reorder_frames {
+                Some(0) => None,
+                n => Some(cmp::min(
+                    cmp::max(
+                        prev.map_or(0, |p| p.reorder_depth),
+                        n.map_or(0, |n| n as usize),
+                    ),
+                    MAX_REORDER_FRAMES,
+                )),
+            };
+            let composition_offsets_flag = if reorder_depth.is_some() {
+                db::RecordingFlags::CompositionOffsets as i32
+            } else {
+                0
+            };
+            let (id, r) = l.add_recording(
+                self.stream_id,
+                db::RecordingToInsert {
+                    run_offset: prev.map(|p| p.run_offset + 1).unwrap_or(0),
+                    start: prev.map(|p| p.end).unwrap_or(recording::Time::MAX),
+                    video_sample_entry_id,
+                    flags: db::RecordingFlags::Growing as i32
+                        | encrypted_flag
+                        | composition_offsets_flag,
+                    rtsp_session: match prev {
+                        None => self.rtsp_session.clone(),
+                        Some(_) => None,
+                    },
+                    ..Default::default()
+                },
+            )?;
+            (id, r, key, reorder_depth)
+        };
+        let mut f = clock::retry(&self.db.clocks(), shutdown_rx, &mut || {
             self.dir.create_file(id)
         })
         .map_err(|e| err!(Cancelled, source(e)))?;
+        let sealer = match key {
+            None => None,
+            Some(k) => {
+                let (header, sealer) = k.new_file(id)?;
+                write_all(&self.db.clocks(), shutdown_rx, &mut f, id, &header)?;
+                Some(sealer)
+            }
+        };
 
         self.state = WriterState::Open(InnerWriter {
             f,
             r,
             e: recording::SampleIndexEncoder::default(),
             id,
+            sealer,
             hasher: blake3::Hasher::new(),
             local_start: recording::Time::MAX,
-            unindexed_sample: None,
+            unindexed: VecDeque::new(),
+            reorderer: Reorderer::new(reorder_depth.unwrap_or(0)),
+            composition_offsets: reorder_depth.is_some(),
+            damaged: false,
             video_sample_entry_id,
+            index_charge: base::mem::BUDGET.charge(base::mem::Category::PendingWrites, 0),
         });
         Ok(())
     }
@@ -764,6 +1031,8 @@ impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
 
     /// Writes a new frame to this recording.
     /// `local_time` should be the local clock's time as of when this packet was received.
+    /// `packets_lost` is the number of RTP packets lost between the previous frame and this one.
+    #[allow(clippy::too_many_arguments)]
     pub fn write(
         &mut self,
         shutdown_rx: &mut base::shutdown::Receiver,
@@ -772,6 +1041,7 @@ impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
         pts_90k: i64,
         is_key: bool,
         video_sample_entry_id: i32,
+        packets_lost: u16,
     ) -> Result<(), Error> {
         self.open(shutdown_rx, video_sample_entry_id)?;
         let w = match self.state {
@@ -779,72 +1049,66 @@ impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
             _ => unreachable!(),
         };
 
-        // Note w's invariant that `unindexed_sample` is `None` may currently be violated.
-        // We must restore it on all success or error paths.
+        // Note w's invariant that `unindexed` is non-empty may currently be violated.
+        // We must restore it on all success paths.
 
-        if let Some(unindexed) = w.unindexed_sample.take() {
-            let duration = pts_90k - unindexed.pts_90k;
-            if duration <= 0 {
-                w.unindexed_sample = Some(unindexed); // restore invariant.
+        if let Some(prev) = w.unindexed.back() {
+            if !w.composition_offsets && pts_90k <= prev.pts_90k {
                 bail!(
                     InvalidArgument,
                     msg(
                         "pts not monotonically increasing; got {} then {}",
-                        unindexed.pts_90k,
+                        prev.pts_90k,
                         pts_90k,
                     ),
                 );
             }
-            let duration = match i32::try_from(duration) {
-                Ok(d) => d,
-                Err(_) => {
-                    w.unindexed_sample = Some(unindexed); // restore invariant.
-                    bail!(
-                        InvalidArgument,
-                        msg(
-                            "excessive pts jump from {} to {}",
-                            unindexed.pts_90k,
-                            pts_90k,
-                        ),
-                    )
-                }
-            };
-            if let Err(e) = w.add_sample(
-                duration,
-                unindexed.len,
-                unindexed.is_key,
-                unindexed.local_time,
-                self.db,
-                self.stream_id,
-            ) {
-                w.unindexed_sample = Some(unindexed); // restore invariant.
-                return Err(e);
+            if w.reorderer.is_late(pts_90k) && w.reorderer.depth >= MAX_REORDER_FRAMES {
+                bail!(
+                    InvalidArgument,
+                    msg("pts {pts_90k} is more than {MAX_REORDER_FRAMES} frames out of order"),
+                );
+            }
+            if i32::try_from(pts_90k - prev.pts_90k).is_err() {
+                bail!(
+                    InvalidArgument,
+                    msg("excessive pts jump from {} to {}", prev.pts_90k, pts_90k,),
+                );
             }
         }
-        let mut remaining = pkt;
-        while !remaining.is_empty() {
-            let written =
-                match clock::retry(&self.db.clocks(), shutdown_rx, &mut || w.f.write(remaining)) {
-                    Ok(w) => w,
-                    Err(e) => {
-                        // close() will do nothing because unindexed_sample will be None.
-                        tracing::warn!(
-                            "abandoning incompletely written recording {} on shutdown",
-                            w.id
-                        );
-                        bail!(Cancelled, source(e));
-                    }
-                };
-            remaining = &remaining[written..];
+
+        // `/view.mp4` may read a growing recording up to its current `sample_file_bytes`.
+        // That's safe because a sample is indexed only after its data has been fully written to
+        // the file.
+        let written = match w.sealer {
+            Some(ref mut s) => s.seal(pkt).and_then(|record| {
+                write_all(&self.db.clocks(), shutdown_rx, &mut w.f, w.id, &record)
+            }),
+            None => write_all(&self.db.clocks(), shutdown_rx, &mut w.f, w.id, pkt),
+        };
+        if let Err(e) = written {
+            // close() will do nothing because `unindexed` is empty.
+            w.unindexed.clear();
+            return Err(e);
+        }
+        w.hasher.update(pkt);
+        w.damaged = !is_key && (w.damaged || packets_lost > 0);
+        if packets_lost > 0 || w.damaged {
+            let mut l = w.r.lock().unwrap();
+            l.packets_lost += i32::from(packets_lost);
+            l.damaged_frames += i32::from(w.damaged);
         }
-        w.unindexed_sample = Some(UnindexedSample {
+        w.unindexed.push_back(UnindexedSample {
             local_time,
             pts_90k,
+            dts_90k: None,
             len: i32::try_from(pkt.len()).unwrap(),
             is_key,
         });
-        w.hasher.update(pkt);
-        Ok(())
+        if let Some(dts_90k) = w.reorderer.push(pts_90k) {
+            w.assign_dts(dts_90k);
+        }
+        w.index_ready(self.db, self.stream_id)
     }
 
     /// Cleanly closes a single recording within this writer, using a supplied
@@ -867,14 +1131,84 @@ impl<'a, C: Clocks + Clone, D: DirWriter> Writer<'a, C, D> {
     }
 }
 
+/// Writes all of `buf` to the sample file `f`, retrying on error until shutdown.
+fn write_all<C: Clocks, F: FileWriter>(
+    clocks: &C,
+    shutdown_rx: &base::shutdown::Receiver,
+    f: &mut F,
+    id: CompositeId,
+    mut buf: &[u8],
+) -> Result<(), Error> {
+    while !buf.is_empty() {
+        let written = match clock::retry(clocks, shutdown_rx, &mut || f.write(buf)) {
+            Ok(w) => w,
+            Err(e) => {
+                tracing::warn!("abandoning incompletely written recording {id} on shutdown");
+                bail!(Cancelled, source(e));
+            }
+        };
+        buf = &buf[written..];
+    }
+    Ok(())
+}
+
 fn clamp(v: i64, min: i64, max: i64) -> i64 {
     std::cmp::min(std::cmp::max(v, min), max)
 }
 
 impl<F: FileWriter> InnerWriter<F> {
+    /// Assigns `dts_90k` to the earliest unindexed sample which lacks one.
+    fn assign_dts(&mut self, dts_90k: i64) {
+        let s = self
+            .unindexed
+            .iter_mut()
+            .find(|s| s.dts_90k.is_none())
+            .expect("reorderer should assign at most one dts per sample");
+        s.dts_90k = Some(dts_90k);
+    }
+
+    /// Indexes each sample whose following sample's dts, and thus its duration, is known.
+    fn index_ready<C: Clocks + Clone>(
+        &mut self,
+        db: &db::Database<C>,
+        stream_id: i32,
+    ) -> Result<(), Error> {
+        while let Some(next_dts_90k) = self.unindexed.get(1).and_then(|s| s.dts_90k) {
+            let s = self.unindexed[0];
+            let dts_90k = s.dts_90k.expect("dts are assigned in decode order");
+            let (Ok(duration_90k), Ok(composition_offset_90k)) = (
+                i32::try_from(next_dts_90k - dts_90k),
+                i32::try_from(s.pts_90k - dts_90k),
+            ) else {
+                bail!(
+                    OutOfRange,
+                    msg(
+                        "excessive pts jump around {} (dts {}, next dts {})",
+                        s.pts_90k,
+                        dts_90k,
+                        next_dts_90k,
+                    ),
+                );
+            };
+            self.add_sample(
+                duration_90k,
+                composition_offset_90k,
+                s.len,
+                s.is_key,
+                s.local_time,
+                db,
+                stream_id,
+            )?;
+            self.unindexed.pop_front();
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn add_sample<C: Clocks + Clone>(
         &mut self,
         duration_90k: i32,
+        composition_offset_90k: i32,
         bytes: i32,
         is_key: bool,
         pkt_local_time: recording::Time,
@@ -908,13 +1242,22 @@ impl<F: FileWriter> InnerWriter<F> {
         l.wall_duration_90k = wall_duration_90k;
         l.start = start;
         self.local_start = local_start;
-        self.e.add_sample(duration_90k, bytes, is_key, &mut l);
+        self.e.add_sample_with_composition_offset(
+            duration_90k,
+            composition_offset_90k,
+            bytes,
+            is_key,
+            &mut l,
+        );
+        self.index_charge.set(l.video_index.capacity() as u64);
         drop(l);
         db.lock()
             .send_live_segment(
                 stream_id,
                 db::LiveFrame {
+                    seq: 0,
                     recording: self.id.recording(),
+                    bytes,
                     is_key,
                     media_off_90k: prev_media_duration_90k..media_duration_90k,
                 },
@@ -931,28 +1274,40 @@ impl<F: FileWriter> InnerWriter<F> {
         stream_id: i32,
         reason: Option<String>,
     ) -> Result<PreviousWriter, Error> {
-        let unindexed = self.unindexed_sample.take().ok_or_else(|| {
-            err!(
+        if self.unindexed.is_empty() {
+            bail!(
                 FailedPrecondition,
                 msg(
                     "unable to add recording {} to database due to aborted write",
                     self.id,
                 ),
-            )
-        })?;
+            );
+        }
+        while let Some(dts_90k) = self.reorderer.pop() {
+            self.assign_dts(dts_90k);
+        }
+        self.index_ready(db, stream_id)?;
+        let unindexed = self
+            .unindexed
+            .pop_front()
+            .expect("all but the last sample should be indexed");
+        let dts_90k = unindexed.dts_90k.expect("all samples should have dts");
         let (last_sample_duration, flags) = match next_pts {
             None => (0, db::RecordingFlags::TrailingZero as i32),
             Some(p) => (
-                i32::try_from(p - unindexed.pts_90k).map_err(|_| {
-                    err!(
-                        OutOfRange,
-                        msg(
-                            "pts {} following {} creates invalid duration",
-                            p,
-                            unindexed.pts_90k
+                i32::try_from(p - dts_90k)
+                    .ok()
+                    .filter(|&d| d >= 0)
+                    .ok_or_else(|| {
+                        err!(
+                            OutOfRange,
+                            msg(
+                                "pts {} following {} creates invalid duration",
+                                p,
+                                unindexed.pts_90k
+                            )
                         )
-                    )
-                })?,
+                    })?,
                 0,
             ),
         };
@@ -960,6 +1315,7 @@ impl<F: FileWriter> InnerWriter<F> {
         let (run_offset, end);
         self.add_sample(
             last_sample_duration,
+            i32::try_from(unindexed.pts_90k - dts_90k).unwrap_or(0),
             unindexed.len,
             unindexed.is_key,
             unindexed.local_time,
@@ -971,7 +1327,11 @@ impl<F: FileWriter> InnerWriter<F> {
         let wall_duration;
         {
             let mut l = self.r.lock().unwrap();
-            l.flags = flags;
+            l.flags = flags
+                | (l.flags
+                    & (db::RecordingFlags::Encrypted as i32
+                        | db::RecordingFlags::CompositionOffsets as i32
+                        | db::RecordingFlags::Reordered as i32));
             l.local_time_delta = self.local_start - l.start;
             l.sample_file_blake3 = Some(*blake3.as_bytes());
             l.end_reason = reason;
@@ -981,7 +1341,11 @@ impl<F: FileWriter> InnerWriter<F> {
         }
         drop(self.r);
         channel.async_save_recording(self.id, wall_duration, self.f);
-        Ok(PreviousWriter { end, run_offset })
+        Ok(PreviousWriter {
+            end,
+            run_offset,
+            reorder_depth: self.reorderer.depth,
+        })
     }
 }
 
@@ -1033,6 +1397,7 @@ mod tests {
             CompositeId,
             Box<dyn Fn(CompositeId) -> Result<(), nix::Error> + Send>,
         ),
+        FreeBytes(Box<dyn Fn() -> Result<i64, nix::Error> + Send>),
     }
 
     impl MockDir {
@@ -1092,6 +1457,18 @@ mod tests {
                 _ => panic!("got unlink({id}), expected something else"),
             }
         }
+        fn free_bytes(&self) -> Result<i64, nix::Error> {
+            match self
+                .0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("got free_bytes with no expectation")
+            {
+                MockDirAction::FreeBytes(f) => f(),
+                _ => panic!("got free_bytes, expected something else"),
+            }
+        }
     }
 
     impl Drop for MockDir {
@@ -1256,6 +1633,7 @@ mod tests {
             0,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
 
@@ -1267,6 +1645,7 @@ mod tests {
                 i32::max_value() as i64 + 1,
                 true,
                 video_sample_entry_id,
+                0,
             )
             .unwrap_err();
         assert!(e.to_string().contains("excessive pts jump"));
@@ -1281,6 +1660,205 @@ mod tests {
         h.dir.ensure_done();
     }
 
+    #[test]
+    fn reorderer_known_depth() {
+        // Decode order is I P B B; presentation order is I B B P.
+        let mut r = super::Reorderer::new(1);
+        assert_eq!(r.push(0), None);
+        assert_eq!(r.push(9000), Some(0));
+        assert_eq!(r.push(3000), Some(3000));
+        assert_eq!(r.push(6000), Some(6000));
+        assert_eq!(r.pop(), Some(9000));
+        assert_eq!(r.pop(), None);
+    }
+
+    #[test]
+    fn reorderer_learned_depth() {
+        let mut r = super::Reorderer::new(0);
+        assert_eq!(r.push(0), Some(0));
+        assert_eq!(r.push(9000), Some(9000));
+        assert!(r.is_late(3000));
+        assert_eq!(r.push(3000), None);
+        assert_eq!(r.depth, 1);
+
+        // dts are bumped to stay increasing, then catch up.
+        assert_eq!(r.push(6000), Some(9001));
+        assert_eq!(r.push(18000), Some(9002));
+        assert_eq!(r.push(12000), Some(12000));
+        assert_eq!(r.push(15000), Some(15000));
+        assert_eq!(r.depth, 1);
+    }
+
+    /// Tests writing a stream with B-frames: the index's presentation times should match the pts.
+    #[test]
+    fn reordered_frames() {
+        testutil::init();
+        let mut h = new_harness(0);
+        let video_sample_entry_id =
+            h.db.lock()
+                .insert_video_sample_entry(VideoSampleEntryToInsert {
+                    width: 320,
+                    height: 240,
+                    pasp_h_spacing: 1,
+                    pasp_v_spacing: 1,
+                    data: crate::h264::testutil::reordering_sample_entry(),
+                    rfc6381_codec: "avc1.4d001e".to_owned(),
+                })
+                .unwrap();
+        let mut w = Writer::new(&h.dir, &h.db, &h.channel, testutil::TEST_STREAM_ID);
+        let f = MockFile::new();
+        h.dir.expect(MockDirAction::Create(
+            CompositeId::new(1, 0),
+            Box::new({
+                let f = f.clone();
+                move |_id| Ok(f.clone())
+            }),
+        ));
+        let frames = [(0, true), (9000, false), (3000, false), (6000, false)];
+        for _ in &frames {
+            f.expect(MockFileAction::Write(Box::new(|buf| Ok(buf.len()))));
+        }
+        f.expect(MockFileAction::SyncAll(Box::new(|| Ok(()))));
+        for &(pts, is_key) in &frames {
+            w.write(
+                &mut h.shutdown_rx,
+                b"1",
+                recording::Time(1),
+                pts,
+                is_key,
+                video_sample_entry_id,
+                0,
+            )
+            .unwrap();
+        }
+        h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
+        w.close(Some(12000), None).unwrap();
+
+        let id = CompositeId::new(testutil::TEST_STREAM_ID, 0);
+        let l = h.db.lock();
+        let mut row = None;
+        l.list_recordings_by_id(testutil::TEST_STREAM_ID, 0..1, &mut |r| {
+            row = Some(r);
+            Ok(())
+        })
+        .unwrap();
+        let row = row.unwrap();
+        let reordered_flags =
+            db::RecordingFlags::CompositionOffsets as i32 | db::RecordingFlags::Reordered as i32;
+        assert_eq!(row.flags & reordered_flags, reordered_flags);
+        assert_eq!(row.media_duration_90k, 12000);
+        let presentation_times = l
+            .with_recording_playback(id, &mut |playback| {
+                let mut it = recording::SampleIndexIterator::new(row.flags);
+                let mut times = Vec::new();
+                while it.next(playback.video_index)? {
+                    times.push(i64::from(it.start_90k + it.composition_offset_90k));
+                }
+                Ok(times)
+            })
+            .unwrap();
+        assert_eq!(
+            presentation_times,
+            frames.iter().map(|&(pts, _)| pts).collect::<Vec<_>>()
+        );
+        drop(l);
+
+        assert!(h.syncer.iter(&h.syncer_rx)); // AsyncSave
+        assert!(h.syncer.iter(&h.syncer_rx)); // planned flush
+        assert!(h.syncer.iter(&h.syncer_rx)); // DatabaseFlushed
+        f.ensure_done();
+        h.dir.ensure_done();
+    }
+
+    /// Tests that lost packets and the frames they damage are counted.
+    #[test]
+    fn packet_loss() {
+        testutil::init();
+        let mut h = new_harness(0);
+        let video_sample_entry_id =
+            h.db.lock()
+                .insert_video_sample_entry(VideoSampleEntryToInsert {
+                    width: 1920,
+                    height: 1080,
+                    pasp_h_spacing: 1,
+                    pasp_v_spacing: 1,
+                    data: [0u8; 100].to_vec(),
+                    rfc6381_codec: "avc1.000000".to_owned(),
+                })
+                .unwrap();
+        let mut w = Writer::new(&h.dir, &h.db, &h.channel, testutil::TEST_STREAM_ID);
+        let f = MockFile::new();
+        h.dir.expect(MockDirAction::Create(
+            CompositeId::new(1, 0),
+            Box::new({
+                let f = f.clone();
+                move |_id| Ok(f.clone())
+            }),
+        ));
+
+        // (is_key, packets_lost). Loss before a non-key frame damages it and the non-key frames
+        // which follow; loss before a key frame damages nothing.
+        let frames = [
+            (true, 0),
+            (false, 0),
+            (false, 3),
+            (false, 0),
+            (true, 0),
+            (false, 0),
+            (true, 2),
+            (false, 0),
+        ];
+        for _ in &frames {
+            f.expect(MockFileAction::Write(Box::new(|buf| Ok(buf.len()))));
+        }
+        f.expect(MockFileAction::SyncAll(Box::new(|| Ok(()))));
+        for (i, &(is_key, packets_lost)) in frames.iter().enumerate() {
+            w.write(
+                &mut h.shutdown_rx,
+                b"1",
+                recording::Time(1),
+                i as i64 * 3000,
+                is_key,
+                video_sample_entry_id,
+                packets_lost,
+            )
+            .unwrap();
+        }
+        h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
+        w.close(Some(frames.len() as i64 * 3000), None).unwrap();
+
+        let mut row = None;
+        h.db.lock()
+            .list_recordings_by_id(testutil::TEST_STREAM_ID, 0..1, &mut |r| {
+                row = Some(r);
+                Ok(())
+            })
+            .unwrap();
+        let row = row.unwrap();
+        assert_eq!(row.packets_lost, 5);
+        assert_eq!(row.damaged_frames, 2);
+
+        assert!(h.syncer.iter(&h.syncer_rx)); // AsyncSave
+        assert!(h.syncer.iter(&h.syncer_rx)); // planned flush
+        assert!(h.syncer.iter(&h.syncer_rx)); // DatabaseFlushed
+        f.ensure_done();
+        h.dir.ensure_done();
+    }
+
+    #[test]
+    fn ping() {
+        testutil::init();
+        let mut h = new_harness(0);
+        let mut rcv = h.channel.ping();
+        assert!(rcv.try_recv().is_err()); // not yet handled.
+        assert!(h.syncer.iter(&h.syncer_rx)); // Ping
+        rcv.try_recv().unwrap();
+
+        // Once the syncer is gone, pings fail rather than hang.
+        drop(h.syncer_rx);
+        h.channel.ping().try_recv().unwrap_err();
+    }
+
     /// Tests the database flushing while a syncer is still processing a previous flush event.
     #[test]
     fn double_flush() {
@@ -1327,6 +1905,7 @@ mod tests {
             0,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
@@ -1360,6 +1939,7 @@ mod tests {
             1,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
@@ -1419,6 +1999,97 @@ mod tests {
         assert!(h.syncer.planned_flushes.is_empty());
     }
 
+    #[test]
+    fn free_space_floor() {
+        testutil::init();
+        let mut h = new_harness(0);
+        h.db.lock()
+            .set_sample_file_dir_min_free_bytes(h.dir_id, 1 << 20)
+            .unwrap();
+        let video_sample_entry_id =
+            h.db.lock()
+                .insert_video_sample_entry(VideoSampleEntryToInsert {
+                    width: 1920,
+                    height: 1080,
+                    pasp_h_spacing: 1,
+                    pasp_v_spacing: 1,
+                    data: [0u8; 100].to_vec(),
+                    rfc6381_codec: "avc1.000000".to_owned(),
+                })
+                .unwrap();
+        let mut w = Writer::new(&h.dir, &h.db, &h.channel, testutil::TEST_STREAM_ID);
+
+        // Setup: add a 3-byte recording with plenty of free space.
+        let f = MockFile::new();
+        h.dir.expect(MockDirAction::Create(
+            CompositeId::new(1, 0),
+            Box::new({
+                let f = f.clone();
+                move |_id| Ok(f.clone())
+            }),
+        ));
+        f.expect(MockFileAction::Write(Box::new(|_| Ok(3))));
+        f.expect(MockFileAction::SyncAll(Box::new(|| Ok(()))));
+        w.write(
+            &mut h.shutdown_rx,
+            b"123",
+            recording::Time(2),
+            0,
+            true,
+            video_sample_entry_id,
+            0,
+        )
+        .unwrap();
+        h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
+        h.dir
+            .expect(MockDirAction::FreeBytes(Box::new(|| Ok(2 << 20))));
+        w.close(Some(1), None).unwrap();
+        assert!(h.syncer.iter(&h.syncer_rx)); // AsyncSave
+        assert!(h.syncer.iter(&h.syncer_rx)); // planned flush
+        assert!(h.syncer.iter(&h.syncer_rx)); // DatabaseFlushed
+        f.ensure_done();
+        h.dir.ensure_done();
+
+        // Then a 1-byte recording, after which something else has used up the free space.
+        // The older recording should be deleted even though the stream's within its limit.
+        let f = MockFile::new();
+        h.dir.expect(MockDirAction::Create(
+            CompositeId::new(1, 1),
+            Box::new({
+                let f = f.clone();
+                move |_id| Ok(f.clone())
+            }),
+        ));
+        f.expect(MockFileAction::Write(Box::new(|_| Ok(1))));
+        f.expect(MockFileAction::SyncAll(Box::new(|| Ok(()))));
+        w.write(
+            &mut h.shutdown_rx,
+            b"4",
+            recording::Time(3),
+            1,
+            true,
+            video_sample_entry_id,
+            0,
+        )
+        .unwrap();
+        h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
+        h.dir.expect(MockDirAction::FreeBytes(Box::new(|| Ok(0))));
+        h.dir.expect(MockDirAction::Unlink(
+            CompositeId::new(1, 0),
+            Box::new(|_| Ok(())),
+        ));
+        h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
+        drop(w);
+        assert!(h.syncer.iter(&h.syncer_rx)); // AsyncSave
+        assert!(h.syncer.iter(&h.syncer_rx)); // planned flush
+        assert!(h.syncer.iter(&h.syncer_rx)); // DatabaseFlushed
+        f.ensure_done();
+        h.dir.ensure_done();
+        let l = h.db.lock();
+        let s = l.streams_by_id().get(&testutil::TEST_STREAM_ID).unwrap();
+        assert_eq!(s.sample_file_bytes, 1);
+    }
+
     #[test]
     fn write_path_retries() {
         testutil::init();
@@ -1472,6 +2143,7 @@ mod tests {
             0,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir
@@ -1548,6 +2220,7 @@ mod tests {
             0,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
@@ -1582,6 +2255,7 @@ mod tests {
             1,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
@@ -1688,6 +2362,7 @@ mod tests {
             0,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
@@ -1724,6 +2399,7 @@ mod tests {
             1,
             true,
             video_sample_entry_id,
+            0,
         )
         .unwrap();
         h.dir.expect(MockDirAction::Sync(Box::new(|| Ok(()))));
@@ -1759,4 +2435,97 @@ mod tests {
         );
         assert!(h.syncer.planned_flushes.is_empty());
     }
+
+    /// Tests recovery from faults on a real directory, through a real syncer thread.
+    #[test]
+    fn injected_faults() {
+        use testutil::{FaultOp, Faults, FaultyDir};
+        testutil::init();
+        let clocks = SimulatedClocks::new(time::Timespec::new(1429920000, 0));
+        let faults = Faults::new();
+        let tdb = testutil::TestDb::new_with_faults(clocks.clone(), faults.clone());
+        let dir = FaultyDir::new(
+            tdb.dirs_by_stream_id
+                .get(&testutil::TEST_STREAM_ID)
+                .unwrap()
+                .clone(),
+            faults.clone(),
+            clocks.clone(),
+        );
+        let video_sample_entry_id = tdb
+            .db
+            .lock()
+            .insert_video_sample_entry(VideoSampleEntryToInsert {
+                width: 1920,
+                height: 1080,
+                pasp_h_spacing: 1,
+                pasp_v_spacing: 1,
+                data: [0u8; 100].to_vec(),
+                rfc6381_codec: "avc1.000000".to_owned(),
+            })
+            .unwrap();
+
+        // A full disk, then a failing one, then a slow one.
+        faults.fail(FaultOp::CreateFile, 1, nix::Error::ENOSPC);
+        faults.fail(FaultOp::Write, 2, nix::Error::EIO);
+        faults.fail(FaultOp::SyncFile, 1, nix::Error::EIO);
+        faults.fail(FaultOp::SyncDir, 1, nix::Error::EIO);
+        faults.set_sync_delay(Some(time::Duration::seconds(5)));
+        let start = clocks.monotonic();
+
+        let mut shutdown_rx = tdb.shutdown_rx.clone();
+        let mut w = Writer::new(&dir, &tdb.db, &tdb.syncer_channel, testutil::TEST_STREAM_ID);
+        let t = recording::Time::new(clocks.realtime());
+        w.write(
+            &mut shutdown_rx,
+            b"1234",
+            t,
+            0,
+            true,
+            video_sample_entry_id,
+            0,
+        )
+        .unwrap();
+        w.write(
+            &mut shutdown_rx,
+            b"567",
+            t + recording::Duration(90_000),
+            90_000,
+            false,
+            video_sample_entry_id,
+            0,
+        )
+        .unwrap();
+        w.close(Some(180_000), None).unwrap();
+        drop(w);
+        tdb.syncer_channel.flush();
+
+        for op in [
+            FaultOp::CreateFile,
+            FaultOp::Write,
+            FaultOp::SyncFile,
+            FaultOp::SyncDir,
+        ] {
+            assert_eq!(faults.pending(op), 0, "{op:?}");
+        }
+        assert_eq!(faults.attempts(FaultOp::CreateFile), 2);
+        assert!(faults.attempts(FaultOp::Write) >= 4);
+        assert_eq!(faults.attempts(FaultOp::SyncFile), 2);
+        assert!(faults.attempts(FaultOp::SyncDir) >= 2);
+
+        // Each failure waits a second before retrying, and each sync takes 5 seconds.
+        assert!(clocks.monotonic() - start >= time::Duration::seconds(4 + 4 * 5));
+
+        let l = tdb.db.lock();
+        let s = l.streams_by_id().get(&testutil::TEST_STREAM_ID).unwrap();
+        assert_eq!(s.bytes_to_add, 0);
+        assert_eq!(s.sample_file_bytes, 7);
+        let mut rows = Vec::new();
+        l.list_recordings_by_id(testutil::TEST_STREAM_ID, 0..1, &mut |r| {
+            rows.push((r.sample_file_bytes, r.video_samples));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rows, [(7, 2)]);
+    }
 }
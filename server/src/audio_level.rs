@@ -0,0 +1,174 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Measurement of a stream's audio level, for raising a signal on loud sounds such as breaking
+//! glass or shouting. See `audioLevel` in [`db::json::StreamConfig`].
+//!
+//! Audio isn't recorded; it's decoded only to compute the RMS level of each second, in dB
+//! relative to full scale (dBFS). Only the uncompressed and G.711 encodings are supported, as
+//! decoding them is trivial. Many cameras offer G.711 as an audio codec option.
+
+/// A supported audio encoding, as named in the SDP.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Codec {
+    /// G.711 μ-law.
+    Pcmu,
+
+    /// G.711 A-law.
+    Pcma,
+
+    /// Signed 16-bit big-endian linear PCM.
+    L16,
+}
+
+impl Codec {
+    /// Returns the codec for a Retina encoding name (lowercase, as in `pcmu`), if supported.
+    pub fn from_encoding_name(name: &str) -> Option<Self> {
+        match name {
+            "pcmu" => Some(Codec::Pcmu),
+            "pcma" => Some(Codec::Pcma),
+            "l16" => Some(Codec::L16),
+            _ => None,
+        }
+    }
+
+    fn decode(self, data: &[u8], mut f: impl FnMut(i16)) {
+        match self {
+            Codec::Pcmu => data.iter().for_each(|&b| f(decode_ulaw(b))),
+            Codec::Pcma => data.iter().for_each(|&b| f(decode_alaw(b))),
+            Codec::L16 => data
+                .chunks_exact(2)
+                .for_each(|c| f(i16::from_be_bytes([c[0], c[1]]))),
+        }
+    }
+}
+
+fn decode_ulaw(b: u8) -> i16 {
+    let b = !b;
+    let t = ((i16::from(b & 0x0f) << 3) + 0x84) << ((b & 0x70) >> 4);
+    if b & 0x80 != 0 {
+        0x84 - t
+    } else {
+        t - 0x84
+    }
+}
+
+fn decode_alaw(b: u8) -> i16 {
+    let b = b ^ 0x55;
+    let mut t = i16::from(b & 0x0f) << 4;
+    let seg = (b & 0x70) >> 4;
+    t += if seg == 0 { 8 } else { 0x108 };
+    if seg > 1 {
+        t <<= seg - 1;
+    }
+    if b & 0x80 != 0 {
+        t
+    } else {
+        -t
+    }
+}
+
+/// Computes the level of each second of audio.
+///
+/// Seconds are counted in samples rather than by timestamps, so lost packets stretch a second
+/// slightly rather than producing a quiet reading.
+pub struct Meter {
+    codec: Codec,
+    samples_per_sec: u64,
+    sum_squares: f64,
+    samples: u64,
+
+    /// Levels of completed seconds not yet returned by [`Meter::take_levels`].
+    levels: Vec<f32>,
+}
+
+impl Meter {
+    pub fn new(codec: Codec, clock_rate: u32, channels: u16) -> Self {
+        Meter {
+            codec,
+            samples_per_sec: u64::from(clock_rate) * u64::from(channels.max(1)),
+            sum_squares: 0.,
+            samples: 0,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Adds an encoded audio frame.
+    pub fn push(&mut self, data: &[u8]) {
+        let codec = self.codec;
+        codec.decode(data, |s| {
+            let s = f64::from(s);
+            self.sum_squares += s * s;
+            self.samples += 1;
+            if self.samples == self.samples_per_sec {
+                self.levels.push(dbfs(self.sum_squares, self.samples));
+                self.sum_squares = 0.;
+                self.samples = 0;
+            }
+        });
+    }
+
+    /// Returns the levels of seconds completed since the last call, oldest first, in dBFS.
+    pub fn take_levels(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.levels)
+    }
+}
+
+/// Returns the RMS level of `samples` with the given sum of squares, in dBFS. Silence is
+/// `-inf`.
+fn dbfs(sum_squares: f64, samples: u64) -> f32 {
+    let rms = (sum_squares / samples as f64).sqrt();
+    (20. * (rms / 32768.).log10()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_ulaw(0xff), 0);
+        assert_eq!(decode_ulaw(0x7f), 0);
+        assert_eq!(decode_ulaw(0x80), 32124);
+        assert_eq!(decode_ulaw(0x00), -32124);
+        assert_eq!(decode_alaw(0xd5), 8);
+        assert_eq!(decode_alaw(0x55), -8);
+        assert_eq!(decode_alaw(0xaa), 32256);
+        assert_eq!(decode_alaw(0x2a), -32256);
+    }
+
+    #[test]
+    fn meter() {
+        // Half a second of a full-scale square wave, then a second and a half of a quarter-scale
+        // one.
+        let mut m = Meter::new(Codec::L16, 8000, 1);
+        let loud: Vec<u8> = [i16::MAX, -i16::MAX]
+            .iter()
+            .cycle()
+            .take(4000)
+            .flat_map(|s| s.to_be_bytes())
+            .collect();
+        let quiet: Vec<u8> = [8192i16, -8192]
+            .iter()
+            .cycle()
+            .take(4000)
+            .flat_map(|s| s.to_be_bytes())
+            .collect();
+        m.push(&loud);
+        assert!(m.take_levels().is_empty());
+        m.push(&quiet);
+        let levels = m.take_levels();
+        assert_eq!(levels.len(), 1);
+
+        // The mean square is (1 + 1/16) / 2 of full scale.
+        assert!((levels[0] - -2.75).abs() < 0.01, "{levels:?}");
+        m.push(&quiet);
+        assert!(m.take_levels().is_empty());
+        m.push(&quiet);
+        let levels = m.take_levels();
+        assert_eq!(levels.len(), 1);
+        assert!((levels[0] - -12.04).abs() < 0.01, "{levels:?}");
+        assert!(m.take_levels().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! A loopback TCP proxy which connects to a camera from a configured local address or interface.
+//!
+//! `ffmpeg` has no way to choose the local end of its RTSP connection, so when a camera sets
+//! `bindAddress` or `bindInterface`, [`crate::ffmpeg`] points it at a [`Proxy`] instead. The proxy
+//! makes each upstream connection with the requested binding and copies bytes both ways. Only
+//! TCP is proxied, so RTSP must use TCP transport. The camera sees the proxy's address in the
+//! request URL (and, for HTTP, the `Host` header); cameras generally ignore it.
+
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base::{err, Error};
+use tracing::warn;
+use url::Url;
+
+use crate::stream::Bind;
+
+/// How long to wait for an upstream connection to be established.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A running proxy, which stops accepting connections when dropped.
+///
+/// Connections already accepted last until either end closes them.
+pub struct Proxy {
+    listener_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl Proxy {
+    /// Starts proxying to the host and port of `url`, returning the proxy along with `url`
+    /// rewritten to point at it.
+    pub fn start(label: &str, url: &Url, bind: &Bind) -> Result<(Self, Url), Error> {
+        let upstream = url
+            .socket_addrs(|| match url.scheme() {
+                "rtsp" => Some(554),
+                _ => None,
+            })
+            .map_err(|e| err!(e, msg("unable to resolve {}", url.host_str().unwrap_or(""))))?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .map_err(|e| err!(e, msg("unable to listen on loopback for ffmpeg")))?;
+        let listener_addr = listener.local_addr()?;
+        let mut proxied = url.clone();
+        proxied
+            .set_ip_host(listener_addr.ip())
+            .expect("network URL has a host");
+        proxied
+            .set_port(Some(listener_addr.port()))
+            .expect("network URL can have a port");
+        let stop = Arc::new(AtomicBool::new(false));
+        let label = label.to_owned();
+        let bind = bind.clone();
+        let stop_clone = stop.clone();
+        std::thread::Builder::new()
+            .name(format!("proxy-{label}"))
+            .spawn(move || accept(&label, listener, &upstream, &bind, &stop_clone))
+            .expect("can't create thread");
+        Ok((
+            Proxy {
+                listener_addr,
+                stop,
+            },
+            proxied,
+        ))
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        // Wake the accept loop so it notices it should stop.
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = TcpStream::connect(self.listener_addr);
+    }
+}
+
+/// Accepts connections until `stop` is set, connecting each to `upstream`.
+fn accept(
+    label: &str,
+    listener: TcpListener,
+    upstream: &[SocketAddr],
+    bind: &Bind,
+    stop: &AtomicBool,
+) {
+    for conn in listener.incoming() {
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let conn = match conn {
+            Ok(c) => c,
+            Err(err) => {
+                warn!(%err, "{label}: proxy accept failed");
+                continue;
+            }
+        };
+
+        // On failure, dropping `conn` closes it, and ffmpeg reports the error.
+        match connect(upstream, bind) {
+            Ok(up) => splice(label, conn, up),
+            Err(err) => warn!(err = %err.chain(), "{label}: proxy connect failed"),
+        }
+    }
+}
+
+/// Connects to the first reachable address of `upstream` which matches `bind`.
+fn connect(upstream: &[SocketAddr], bind: &Bind) -> Result<TcpStream, Error> {
+    let mut last_err = None;
+    for &addr in upstream {
+        if bind.address.is_some_and(|a| a.is_ipv4() != addr.is_ipv4()) {
+            continue;
+        }
+        match connect_one(addr, bind) {
+            Ok(s) => return Ok(s),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        err!(
+            NotFound,
+            msg("no address of {upstream:?} matches bindAddress's family")
+        )
+    }))
+}
+
+fn connect_one(addr: SocketAddr, bind: &Bind) -> Result<TcpStream, Error> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if let Some(i) = bind.interface.as_ref() {
+        #[cfg(target_os = "linux")]
+        socket
+            .bind_device(Some(i.as_bytes()))
+            .map_err(|e| err!(e, msg("unable to bind to interface {i:?}")))?;
+        #[cfg(not(target_os = "linux"))]
+        base::bail!(Unimplemented, msg("bindInterface {i:?} requires Linux"));
+    }
+    if let Some(a) = bind.address {
+        socket
+            .bind(&SocketAddr::new(a, 0).into())
+            .map_err(|e| err!(e, msg("unable to bind to {a}")))?;
+    }
+    socket
+        .connect_timeout(&addr.into(), CONNECT_TIMEOUT)
+        .map_err(|e| err!(e, msg("unable to connect to {addr}")))?;
+    Ok(socket.into())
+}
+
+/// Copies bytes both ways between `a` and `b` until either closes.
+fn splice(label: &str, a: TcpStream, b: TcpStream) {
+    let (a2, b2) = match (a.try_clone(), b.try_clone()) {
+        (Ok(a2), Ok(b2)) => (a2, b2),
+        (Err(err), _) | (_, Err(err)) => {
+            warn!(%err, "{label}: proxy setup failed");
+            return;
+        }
+    };
+    for (from, to) in [(a, b), (b2, a2)] {
+        std::thread::Builder::new()
+            .name(format!("proxy-{label}"))
+            .spawn(move || copy(from, to))
+            .expect("can't create thread");
+    }
+}
+
+fn copy(mut from: TcpStream, mut to: TcpStream) {
+    let _ = std::io::copy(&mut from, &mut to);
+
+    // Shut down both sockets so the opposite direction's copy ends too.
+    let _ = from.shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read as _, Write as _};
+
+    #[test]
+    fn proxies() {
+        let upstream = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = Url::parse(&format!(
+            "rtsp://user:pass@{}/main",
+            upstream.local_addr().unwrap()
+        ))
+        .unwrap();
+        let bind = Bind {
+            address: Some(Ipv4Addr::LOCALHOST.into()),
+            interface: None,
+        };
+        let (proxy, proxied) = Proxy::start("test", &url, &bind).unwrap();
+        assert_ne!(proxied.port(), url.port());
+        assert_eq!(proxied.username(), "user");
+        assert_eq!(proxied.path(), "/main");
+
+        let mut client =
+            TcpStream::connect((proxied.host_str().unwrap(), proxied.port().unwrap())).unwrap();
+        let (mut server, peer) = upstream.accept().unwrap();
+        assert_eq!(peer.ip(), bind.address.unwrap());
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        server.write_all(b"pong").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Closing one end closes the other.
+        drop(server);
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        drop(proxy);
+    }
+}
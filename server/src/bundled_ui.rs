@@ -11,11 +11,16 @@ use std::{io::Read, pin::Pin};
 
 use crate::body::{BoxedError, Chunk};
 
-pub struct Ui(FastHashMap<&'static str, FileSet>);
+pub struct Ui {
+    files: FastHashMap<&'static str, FileSet>,
+
+    /// See [`Ui::version`].
+    version: String,
+}
 
 /// A file as passed in from `build.rs`.
 struct BuildFile {
-    /// Path without any prefix (even `/`) for the root or any encoding suffix (`.gz`).
+    /// Path without any prefix (even `/`) for the root or any encoding suffix (`.gz`, `.br`).
     bare_path: &'static str,
     data: &'static [u8],
     etag: &'static str,
@@ -27,6 +32,7 @@ struct BuildFile {
 enum FileEncoding {
     Uncompressed,
     Gzipped,
+    Brotli,
 }
 
 // `build.rs` fills in: `static FILES: [BuildFile; _] = [ ... ];`
@@ -41,6 +47,7 @@ struct File {
 struct FileSet {
     uncompressed: File,
     gzipped: Option<File>,
+    brotli: Option<File>,
 }
 
 impl Ui {
@@ -50,10 +57,21 @@ impl Ui {
 
     #[tracing::instrument]
     fn init() -> Self {
-        Ui(FILES
-            .iter()
-            .map(|f| {
-                let set = if matches!(f.encoding, FileEncoding::Gzipped) {
+        let mut brotli: FastHashMap<&'static str, File> = FastHashMap::default();
+        let mut files: FastHashMap<&'static str, FileSet> = FastHashMap::default();
+        for f in &FILES {
+            let set = match f.encoding {
+                FileEncoding::Brotli => {
+                    brotli.insert(
+                        f.bare_path,
+                        File {
+                            data: f.data,
+                            etag: f.etag,
+                        },
+                    );
+                    continue;
+                }
+                FileEncoding::Gzipped => {
                     let mut uncompressed = Vec::new();
                     let mut d = flate2::read::GzDecoder::new(f.data);
                     d.read_to_end(&mut uncompressed)
@@ -74,19 +92,39 @@ impl Ui {
                             data: f.data,
                             etag: f.etag,
                         }),
+                        brotli: None,
                     }
-                } else {
-                    FileSet {
-                        uncompressed: File {
-                            data: f.data,
-                            etag: f.etag,
-                        },
-                        gzipped: None,
-                    }
-                };
-                (f.bare_path, set)
-            })
-            .collect())
+                }
+                FileEncoding::Uncompressed => FileSet {
+                    uncompressed: File {
+                        data: f.data,
+                        etag: f.etag,
+                    },
+                    gzipped: None,
+                    brotli: None,
+                },
+            };
+            files.insert(f.bare_path, set);
+        }
+        for (bare_path, b) in brotli {
+            files
+                .get_mut(bare_path)
+                .expect("build.rs ensures brotli files have another representation")
+                .brotli = Some(b);
+        }
+        let version = crate::web::ui_version(
+            files
+                .get("index.html")
+                .expect("build.rs ensures index.html exists")
+                .uncompressed
+                .data,
+        );
+        Ui { files, version }
+    }
+
+    /// Returns an identifier of this UI build; see [`crate::web::ui_version`].
+    pub fn version(&self) -> &str {
+        &self.version
     }
 
     pub fn lookup(
@@ -96,28 +134,17 @@ impl Ui {
         cache_control: &'static str,
         content_type: &'static str,
     ) -> Option<Entity> {
-        let Some(set) = self.0.get(path) else {
-            return None;
-        };
-        let auto_gzip;
-        if let Some(ref gzipped) = set.gzipped {
-            auto_gzip = true;
-            if http_serve::should_gzip(hdrs) {
-                return Some(Entity {
-                    file: &gzipped,
-                    auto_gzip,
-                    is_gzipped: true,
-                    cache_control,
-                    content_type,
-                });
-            }
-        } else {
-            auto_gzip = false
+        let set = self.files.get(path)?;
+        let vary = set.gzipped.is_some() || set.brotli.is_some();
+        let (file, content_encoding) = match (&set.brotli, &set.gzipped) {
+            (Some(b), _) if accepts_brotli(hdrs) => (b, Some("br")),
+            (_, Some(g)) if http_serve::should_gzip(hdrs) => (g, Some("gzip")),
+            _ => (&set.uncompressed, None),
         };
         Some(Entity {
-            file: &set.uncompressed,
-            auto_gzip,
-            is_gzipped: false,
+            file,
+            vary,
+            content_encoding,
             cache_control,
             content_type,
         })
@@ -126,11 +153,31 @@ impl Ui {
 
 static UI: OnceLock<Ui> = OnceLock::new();
 
+/// Returns true if the `Accept-Encoding` header allows `br`.
+fn accepts_brotli(hdrs: &HeaderMap<HeaderValue>) -> bool {
+    hdrs.get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            if !parts.next().is_some_and(|c| c.eq_ignore_ascii_case("br")) {
+                return false;
+            }
+            parts.all(|p| match p.strip_prefix("q=") {
+                Some(q) => q.parse::<f32>().map_or(false, |q| q > 0.),
+                None => true,
+            })
+        })
+}
+
 #[derive(Copy, Clone)]
 pub struct Entity {
     file: &'static File,
-    auto_gzip: bool,
-    is_gzipped: bool,
+
+    /// True if other representations are available depending on `Accept-Encoding`.
+    vary: bool,
+    content_encoding: Option<&'static str>,
     cache_control: &'static str,
     content_type: &'static str,
 }
@@ -163,11 +210,11 @@ impl http_serve::Entity for Entity {
     }
 
     fn add_headers(&self, hdrs: &mut http::HeaderMap) {
-        if self.auto_gzip {
+        if self.vary {
             hdrs.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
         }
-        if self.is_gzipped {
-            hdrs.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        if let Some(e) = self.content_encoding {
+            hdrs.insert(header::CONTENT_ENCODING, HeaderValue::from_static(e));
         }
         hdrs.insert(
             header::CACHE_CONTROL,
@@ -200,4 +247,18 @@ mod tests {
             .unwrap();
         assert!(e.file.data.starts_with(b"<!doctype html"));
     }
+
+    #[test]
+    fn accepts_brotli() {
+        let hdrs = |v: &'static str| {
+            let mut h = HeaderMap::new();
+            h.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(v));
+            h
+        };
+        assert!(super::accepts_brotli(&hdrs("gzip, deflate, br")));
+        assert!(super::accepts_brotli(&hdrs("br;q=0.5, gzip")));
+        assert!(!super::accepts_brotli(&hdrs("br;q=0, gzip")));
+        assert!(!super::accepts_brotli(&hdrs("gzip")));
+        assert!(!super::accepts_brotli(&HeaderMap::new()));
+    }
 }
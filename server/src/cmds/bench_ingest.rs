@@ -0,0 +1,386 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Ingest benchmark: records synthetic cameras through the real streamer, writer, and syncer
+//! code, reporting throughput, flush latency, and memory use.
+
+use crate::stream;
+use crate::streamer;
+use base::clock;
+use base::{bail, err, Error};
+use bpaf::Bpaf;
+use bytes::Bytes;
+use db::writer;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+use url::Url;
+
+/// Measures recording performance with synthetic cameras.
+///
+/// Each camera produces fixed-size frames at a fixed rate, in-process, without RTSP or a real
+/// encoder. Everything downstream of the RTSP client is the same as in `moonfire-nvr run`. This
+/// creates its own database and sample file directory, so it doesn't touch an existing
+/// installation.
+#[derive(Bpaf, Debug)]
+#[bpaf(command("bench-ingest"))]
+pub struct Args {
+    /// Number of synthetic cameras, each with a single recorded stream.
+    #[bpaf(argument("N"), fallback(4), debug_fallback)]
+    cameras: u32,
+
+    /// How long to record.
+    #[bpaf(argument("SECS"), fallback(60), debug_fallback)]
+    duration_sec: u64,
+
+    /// Frames per second for each camera.
+    #[bpaf(argument("FPS"), fallback(30), debug_fallback)]
+    fps: u32,
+
+    /// Size of each frame.
+    #[bpaf(argument("BYTES"), fallback(16384), debug_fallback)]
+    frame_bytes: usize,
+
+    /// Frames from one key frame to the next.
+    #[bpaf(argument("FRAMES"), fallback(60), debug_fallback)]
+    key_frame_interval: u32,
+
+    /// Each stream's `flushIfSec`.
+    #[bpaf(argument("SECS"), fallback(10), debug_fallback)]
+    flush_if_sec: u32,
+
+    /// Produces frames as fast as they're consumed, rather than at `--fps` in real time.
+    unthrottled: bool,
+
+    /// Directory in which to create the database and sample files. It must not already exist.
+    /// Defaults to a new directory under `$TMPDIR`, removed on completion.
+    #[bpaf(argument("PATH"))]
+    dir: Option<PathBuf>,
+}
+
+/// Counts of frames handed to the streamers by all synthetic cameras.
+#[derive(Default)]
+struct Counters {
+    frames: AtomicU64,
+    bytes: AtomicU64,
+}
+
+struct SyntheticOpener {
+    args: Arc<Args>,
+    counters: Arc<Counters>,
+    data: Bytes,
+    video_sample_entry: db::VideoSampleEntryToInsert,
+}
+
+impl stream::Opener for SyntheticOpener {
+    fn open(
+        &self,
+        _label: String,
+        _url: Url,
+        _options: stream::Options,
+    ) -> Result<Box<dyn stream::Stream>, Error> {
+        Ok(Box::new(SyntheticStream {
+            args: self.args.clone(),
+            counters: self.counters.clone(),
+            data: self.data.clone(),
+            video_sample_entry: self.video_sample_entry.clone(),
+            start: Instant::now(),
+            next_frame: 0,
+        }))
+    }
+}
+
+struct SyntheticStream {
+    args: Arc<Args>,
+    counters: Arc<Counters>,
+    data: Bytes,
+    video_sample_entry: db::VideoSampleEntryToInsert,
+    start: Instant,
+    next_frame: u32,
+}
+
+impl stream::Stream for SyntheticStream {
+    fn tool(&self) -> Option<&retina::client::Tool> {
+        None
+    }
+
+    fn rtsp_session(&self) -> Option<db::RtspSession> {
+        None
+    }
+
+    fn video_sample_entry(&self) -> &db::VideoSampleEntryToInsert {
+        &self.video_sample_entry
+    }
+
+    fn next(&mut self) -> Result<stream::VideoFrame, Error> {
+        let i = self.next_frame;
+        self.next_frame += 1;
+        if !self.args.unthrottled {
+            let due = self.start + Duration::from_secs(u64::from(i)) / self.args.fps;
+            if let Some(d) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(d);
+            }
+        }
+        self.counters.frames.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes
+            .fetch_add(self.data.len() as u64, Ordering::Relaxed);
+        Ok(stream::VideoFrame {
+            pts: i64::from(i) * db::recording::TIME_UNITS_PER_SEC / i64::from(self.args.fps),
+            #[cfg(test)]
+            duration: 0,
+            is_key: i % self.args.key_frame_interval == 0,
+            data: self.data.clone(),
+            new_video_sample_entry: false,
+            loss: 0,
+        })
+    }
+}
+
+/// Returns the given field of `/proc/self/status` in bytes, if available.
+fn proc_status_bytes(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with(field))?;
+    let kib: u64 = line[field.len()..]
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Formats a byte count with a binary suffix, e.g. `1.5 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut v = bytes as f64;
+    let mut unit = 0;
+    while v >= 1024. && unit + 1 < UNITS.len() {
+        v /= 1024.;
+        unit += 1;
+    }
+    format!("{v:.1} {}", UNITS[unit])
+}
+
+/// Returns the `p`th percentile of the sorted `durations`.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+pub fn run(args: Args) -> Result<i32, Error> {
+    if args.cameras == 0 || args.fps == 0 || args.key_frame_interval == 0 {
+        bail!(
+            InvalidArgument,
+            msg("--cameras, --fps, and --key-frame-interval must be positive")
+        );
+    }
+    let (dir, remove_dir) = match &args.dir {
+        Some(d) => (d.clone(), false),
+        None => (
+            std::env::temp_dir().join(format!("moonfire-nvr-bench-{}", std::process::id())),
+            true,
+        ),
+    };
+    std::fs::create_dir(&dir).map_err(|e| err!(e, msg("unable to create {}", dir.display())))?;
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let guard = rt.enter();
+    let r = bench(Arc::new(args), &dir);
+    drop(guard);
+    rt.shutdown_background();
+    if remove_dir {
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| err!(e, msg("unable to remove {}", dir.display())))?;
+    }
+    r
+}
+
+fn bench(args: Arc<Args>, dir: &std::path::Path) -> Result<i32, Error> {
+    let mut conn = rusqlite::Connection::open(dir.join("db"))?;
+    db::init(&mut conn)?;
+    let db = Arc::new(db::Database::new(clock::RealClocks {}, conn, true)?);
+    let sample_file_dir_id = {
+        let mut l = db.lock();
+        let sample_file_dir_id = l.add_sample_file_dir(dir.join("sample"))?;
+        for i in 0..args.cameras {
+            let camera_id = l.add_camera(db::CameraChange {
+                short_name: format!("synthetic-{i}"),
+                config: db::json::CameraConfig::default(),
+                streams: [
+                    db::StreamChange {
+                        sample_file_dir_id: Some(sample_file_dir_id),
+                        config: db::json::StreamConfig {
+                            url: Some(Url::parse(&format!("rtsp://synthetic-{i}/main")).unwrap()),
+                            mode: db::json::STREAM_MODE_RECORD.to_owned(),
+                            flush_if_sec: args.flush_if_sec,
+                            ..Default::default()
+                        },
+                    },
+                    Default::default(),
+                    Default::default(),
+                ],
+            })?;
+            let stream_id = l.cameras_by_id().get(&camera_id).unwrap().streams[0].unwrap();
+            l.update_retention(&[db::RetentionChange {
+                stream_id,
+                new_record: true,
+                new_limit: 1 << 60,
+            }])?;
+        }
+        sample_file_dir_id
+    };
+
+    let (shutdown_tx, shutdown_rx) = base::shutdown::channel();
+    let (channel, syncer_join) =
+        writer::start_syncer(db.clone(), shutdown_rx.clone(), sample_file_dir_id)?;
+    let counters = Arc::new(Counters::default());
+    let opener = SyntheticOpener {
+        args: args.clone(),
+        counters: counters.clone(),
+        data: Bytes::from(vec![0u8; args.frame_bytes]),
+        video_sample_entry: db::VideoSampleEntryToInsert {
+            data: db::testutil::TEST_VIDEO_SAMPLE_ENTRY_DATA.to_vec(),
+            rfc6381_codec: "avc1.4d0029".to_owned(),
+            width: 1920,
+            height: 1080,
+            pasp_h_spacing: 1,
+            pasp_v_spacing: 1,
+        },
+    };
+    let notify = Default::default();
+    let env = streamer::Environment {
+        opener: &opener,
+        db: &db,
+        shutdown_rx: &shutdown_rx,
+        notify: &notify,
+    };
+    let mut streamers = Vec::new();
+    {
+        let l = db.lock();
+        let dir = l
+            .sample_file_dirs_by_id()
+            .get(&sample_file_dir_id)
+            .unwrap()
+            .get()?;
+        let streams = l.streams_by_id().len();
+        for (i, (&id, stream)) in l.streams_by_id().iter().enumerate() {
+            let camera = l.cameras_by_id().get(&stream.camera_id).unwrap();
+            streamers.push(streamer::Streamer::new(
+                &env,
+                dir.clone(),
+                channel.clone(),
+                id,
+                camera,
+                stream,
+                Arc::new(retina::client::SessionGroup::default()),
+                None,
+                streamer::ROTATE_INTERVAL_SEC * i as i64 / streams as i64,
+                streamer::ROTATE_INTERVAL_SEC,
+            )?);
+        }
+    }
+
+    info!(
+        "recording {} synthetic cameras for {} s",
+        args.cameras, args.duration_sec
+    );
+    let rss_before = proc_status_bytes("VmRSS:");
+    let handle = tokio::runtime::Handle::current();
+    let start = Instant::now();
+    let mut flushes = Vec::new();
+    thread::scope(|scope| {
+        for mut s in streamers {
+            let handle = handle.clone();
+            thread::Builder::new()
+                .name(format!("s-{}", s.short_name()))
+                .spawn_scoped(scope, move || {
+                    let _enter = handle.enter();
+                    s.run();
+                })
+                .expect("can't create thread");
+        }
+
+        // Measure flush latency: the time for the syncer to write out everything queued so far
+        // and commit it to the database.
+        let end = start + Duration::from_secs(args.duration_sec);
+        while let Some(left) = end.checked_duration_since(Instant::now()) {
+            thread::sleep(left.min(Duration::from_secs(1)));
+            let flush_start = Instant::now();
+            channel.flush();
+            flushes.push(flush_start.elapsed());
+        }
+        drop(shutdown_tx);
+    });
+    channel.flush();
+    let elapsed = start.elapsed();
+    drop(channel);
+    db.lock().clear_on_flush();
+    syncer_join
+        .join()
+        .map_err(|_| err!(Internal, msg("syncer panicked")))?;
+    db.lock().flush("bench-ingest")?;
+
+    let (mut recordings, mut committed_bytes) = (0, 0);
+    {
+        let l = db.lock();
+        for (&id, s) in l.streams_by_id() {
+            committed_bytes += s.sample_file_bytes;
+            l.list_recordings_by_id(id, 0..i32::MAX, &mut |_| {
+                recordings += 1;
+                Ok(())
+            })?;
+        }
+    }
+    let frames = counters.frames.load(Ordering::Relaxed);
+    let bytes = counters.bytes.load(Ordering::Relaxed);
+    let secs = elapsed.as_secs_f64();
+    flushes.sort_unstable();
+    println!(
+        "elapsed:         {secs:.1} s\n\
+         frames:          {frames} ({:.0}/s)\n\
+         ingested:        {} ({}/s)\n\
+         committed:       {} in {recordings} recordings\n\
+         flush latency:   p50 {:?}, p99 {:?}, max {:?} over {} flushes\n\
+         rss:             {} before, {} after, {} peak",
+        frames as f64 / secs,
+        format_bytes(bytes),
+        format_bytes((bytes as f64 / secs) as u64),
+        format_bytes(committed_bytes as u64),
+        percentile(&flushes, 50),
+        percentile(&flushes, 99),
+        flushes.last().copied().unwrap_or_default(),
+        flushes.len(),
+        rss_before.map_or_else(|| "?".to_owned(), format_bytes),
+        proc_status_bytes("VmRSS:").map_or_else(|| "?".to_owned(), format_bytes),
+        proc_status_bytes("VmHWM:").map_or_else(|| "?".to_owned(), format_bytes),
+    );
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn format_bytes() {
+        assert_eq!(super::format_bytes(512), "512.0 B");
+        assert_eq!(super::format_bytes(1536), "1.5 KiB");
+        assert_eq!(super::format_bytes(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn percentile() {
+        let d: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(super::percentile(&d, 50), Duration::from_millis(50));
+        assert_eq!(super::percentile(&d, 99), Duration::from_millis(99));
+        assert_eq!(super::percentile(&[], 50), Duration::ZERO);
+    }
+}
@@ -4,7 +4,7 @@
 
 //! Subcommand to check the database and sample file dir for errors.
 
-use base::Error;
+use base::{err, Error};
 use bpaf::Bpaf;
 use db::check;
 use std::path::PathBuf;
@@ -34,9 +34,39 @@ pub struct Args {
     /// `garbage` table to indicate their files need to be deleted. Garbage is
     /// collected on normal startup.
     trash_corrupt_rows: bool,
+
+    /// Adopts H.264 sample files written after the last database commit,
+    /// as after restoring the database from an older backup. Recording rows
+    /// are created with estimated times. Run this before normal startup,
+    /// which deletes such files.
+    adopt_orphan_sample_files: bool,
+
+    /// Validates every frame of each recording's `video_index`, not just its
+    /// totals. Corrupt indexes are reported as "bad video_index" errors, which
+    /// `--trash-corrupt-rows` addresses.
+    indexes: bool,
+
+    /// Rewrites recording rows' frame, byte, and duration columns from their
+    /// `video_index` when they disagree. This addresses `summary doesn't match
+    /// video_index` errors.
+    repair_summaries: bool,
+
+    /// Verifies that encrypted sample files decrypt with the key in the given file, as in
+    /// the `sampleFileKey` option of `/etc/moonfire-nvr.toml`: e.g.
+    /// `/run/secrets/sample_file_key`.
+    #[bpaf(argument("PATH"))]
+    sample_file_key_file: Option<PathBuf>,
 }
 
 pub fn run(args: Args) -> Result<i32, Error> {
+    let sample_file_key = args
+        .sample_file_key_file
+        .as_deref()
+        .map(|p| {
+            db::dir::crypt::Key::parse(&super::run::config::read_secret_file(p)?)
+                .map_err(|e| err!(e, msg("bad --sample-file-key-file")))
+        })
+        .transpose()?;
     let (_db_dir, mut conn) = super::open_conn(&args.db_dir, super::OpenMode::ReadWrite)?;
     check::run(
         &mut conn,
@@ -45,6 +75,10 @@ pub fn run(args: Args) -> Result<i32, Error> {
             trash_orphan_sample_files: args.trash_orphan_sample_files,
             delete_orphan_rows: args.delete_orphan_rows,
             trash_corrupt_rows: args.trash_corrupt_rows,
+            adopt_orphan_sample_files: args.adopt_orphan_sample_files,
+            indexes: args.indexes,
+            repair_summaries: args.repair_summaries,
+            sample_file_key,
         },
     )
 }
@@ -2,13 +2,14 @@
 // Copyright (C) 2020 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
+use crate::cmds::run::config::Secret;
 use crate::stream::{self, Opener};
 use base::strutil::{decode_size, encode_size};
 use base::{bail, err, Error};
 use cursive::traits::{Finder, Nameable, Resizable, Scrollable};
 use cursive::views::{self, Dialog, ViewRef};
 use cursive::Cursive;
-use db::writer;
+use db::{mover, writer};
 use itertools::Itertools;
 use std::collections::BTreeMap;
 use std::str::FromStr;
@@ -22,6 +23,8 @@ struct Camera {
     onvif_base_url: String,
     username: String,
     password: String,
+    password_env: String,
+    password_file: String,
     streams: [Stream; db::NUM_STREAM_TYPES],
 }
 
@@ -67,12 +70,26 @@ fn get_camera(siv: &mut Cursive) -> Camera {
         .get_content()
         .as_str()
         .to_owned();
+    let password_env = siv
+        .find_name::<views::EditView>("password_env")
+        .unwrap()
+        .get_content()
+        .as_str()
+        .to_owned();
+    let password_file = siv
+        .find_name::<views::EditView>("password_file")
+        .unwrap()
+        .get_content()
+        .as_str()
+        .to_owned();
     let mut camera = Camera {
         short_name,
         description,
         onvif_base_url,
         username,
         password,
+        password_env,
+        password_file,
         streams: Default::default(),
     };
     for &t in &db::ALL_STREAM_TYPES {
@@ -115,7 +132,7 @@ fn get_camera(siv: &mut Cursive) -> Camera {
 }
 
 /// Attempts to parse a URL field into a sort-of-validated URL.
-fn parse_url(
+pub(super) fn parse_url(
     field_name: &str,
     raw: &str,
     allowed_schemes: &'static [&'static str],
@@ -153,25 +170,45 @@ fn parse_url(
     Ok(Some(url))
 }
 
-fn parse_stream_url(type_: db::StreamType, raw: &str) -> Result<Option<Url>, Error> {
-    parse_url(&format!("{} stream url", type_.as_str()), raw, &["rtsp"])
+pub(super) fn parse_stream_url(type_: db::StreamType, raw: &str) -> Result<Option<Url>, Error> {
+    parse_url(
+        &format!("{} stream url", type_.as_str()),
+        raw,
+        &["rtsp", "http", "https", "v4l2"],
+    )
 }
 
 fn press_edit(siv: &mut Cursive, db: &Arc<db::Database>, id: Option<i32>) {
     let result = (|| {
+        let camera = get_camera(siv);
+        if let Some(id) = id {
+            move_streams(
+                db,
+                id,
+                camera.streams.each_ref().map(|s| s.sample_file_dir_id),
+            )?;
+        }
         let mut l = db.lock();
         let mut change = if let Some(id) = id {
             l.null_camera_change(id)?
         } else {
             db::CameraChange::default()
         };
-        let camera = get_camera(siv);
         change.short_name = camera.short_name;
         change.config.description = camera.description;
         change.config.onvif_base_url =
             parse_url("onvif_base_url", &camera.onvif_base_url, &["http", "https"])?;
+        let password_file: Option<std::path::PathBuf> =
+            (!camera.password_file.is_empty()).then(|| camera.password_file.into());
+        Secret::from_password_fields(
+            &camera.password,
+            &camera.password_env,
+            password_file.as_deref(),
+        )?;
         change.config.username = camera.username;
         change.config.password = camera.password;
+        change.config.password_env = camera.password_env;
+        change.config.password_file = password_file;
         for (i, stream) in camera.streams.iter().enumerate() {
             let type_ = db::StreamType::from_index(i).unwrap();
             if stream.record && (stream.url.is_empty() || stream.sample_file_dir_id.is_none()) {
@@ -228,11 +265,50 @@ fn press_edit(siv: &mut Cursive, db: &Arc<db::Database>, id: Option<i32>) {
     }
 }
 
+/// Moves existing recordings of each stream whose sample file directory has changed.
+///
+/// `new_dirs` is indexed by `db::StreamType::index`.
+pub(super) fn move_streams(
+    db: &Arc<db::Database>,
+    camera_id: i32,
+    new_dirs: [Option<i32>; db::NUM_STREAM_TYPES],
+) -> Result<(), Error> {
+    let mut moves = Vec::new();
+    {
+        let l = db.lock();
+        let c = l
+            .cameras_by_id()
+            .get(&camera_id)
+            .ok_or_else(|| err!(NotFound, msg("no such camera {camera_id}")))?;
+        for (sid, new_dir) in c.streams.iter().zip(new_dirs) {
+            let Some(sid) = *sid else {
+                continue;
+            };
+            let s = l.streams_by_id().get(&sid).unwrap();
+            if let (Some(old), Some(new)) = (s.sample_file_dir_id, new_dir) {
+                if old != new && s.range.is_some() {
+                    moves.push((sid, old, new));
+                }
+            }
+        }
+    }
+    let (_shutdown_tx, shutdown_rx) = base::shutdown::channel();
+    for (stream_id, old_dir_id, new_dir_id) in moves {
+        let mut m = mover::Move::new(db.clone(), stream_id, new_dir_id)?;
+        m.copy_committed()?;
+        m.finish(&shutdown_rx)?;
+
+        // Delete the old copies now; there's no syncer to do so.
+        writer::lower_retention(db, old_dir_id, &[])?;
+    }
+    Ok(())
+}
+
 fn press_test_inner(
     handle: tokio::runtime::Handle,
     url: Url,
     username: String,
-    password: String,
+    password: Result<Secret, Error>,
     transport: retina::client::Transport,
 ) -> Result<String, Error> {
     let _enter = handle.enter();
@@ -240,9 +316,16 @@ fn press_test_inner(
         session: retina::client::SessionOptions::default().creds(if username.is_empty() {
             None
         } else {
-            Some(retina::client::Credentials { username, password })
+            Some(retina::client::Credentials {
+                username,
+                password: password?.resolve()?,
+            })
         }),
         setup: retina::client::SetupOptions::default().transport(transport),
+        play: retina::client::PlayOptions::default(),
+        audio_setup: None,
+        capture: None,
+        bind: None,
     };
     let stream = stream::OPENER.open("test stream".to_owned(), url, options)?;
     let video_sample_entry = stream.video_sample_entry();
@@ -272,7 +355,11 @@ fn press_test(siv: &mut Cursive, t: db::StreamType) {
         ),
     };
     let username = c.username;
-    let password = c.password;
+    let password = Secret::from_password_fields(
+        &c.password,
+        &c.password_env,
+        (!c.password_file.is_empty()).then(|| std::path::Path::new(&c.password_file)),
+    );
 
     siv.add_layer(
         views::Dialog::text(format!(
@@ -447,7 +534,9 @@ fn actually_delete(siv: &mut Cursive, db: &Arc<db::Database>, id: i32) {
 }
 
 fn edit_stream_url(type_: db::StreamType, content: &str, mut test_button: ViewRef<views::Button>) {
-    let enable_test = matches!(parse_stream_url(type_, content), Ok(Some(_)));
+    // The test uses Retina, which doesn't support MJPEG-over-HTTP.
+    let enable_test =
+        matches!(parse_stream_url(type_, content), Ok(Some(u)) if u.scheme() == "rtsp");
     test_button.set_enabled(enable_test);
 }
 
@@ -557,6 +646,15 @@ fn load_camera_values(
         ),
         ("username", &camera.config.username),
         ("password", &camera.config.password),
+        ("password_env", &camera.config.password_env),
+        (
+            "password_file",
+            camera
+                .config
+                .password_file
+                .as_deref()
+                .map_or("", |p| p.to_str().unwrap_or("")),
+        ),
     ] {
         dialog
             .call_on_name(view_id, |v: &mut views::EditView| {
@@ -588,7 +686,15 @@ fn edit_camera_dialog(db: &Arc<db::Database>, siv: &mut Cursive, item: &Option<i
         )
         .child("username", views::EditView::new().with_name("username"))
         .child("password", views::EditView::new().with_name("password"))
-        .min_height(6);
+        .child(
+            "password env",
+            views::EditView::new().with_name("password_env"),
+        )
+        .child(
+            "password file",
+            views::EditView::new().with_name("password_file"),
+        )
+        .min_height(8);
     let mut layout = views::LinearLayout::vertical()
         .child(camera_list)
         .child(views::TextView::new("description"))
@@ -740,6 +846,10 @@ pub fn top_dialog(db: &Arc<db::Database>, siv: &mut Cursive) {
                 .full_width()
                 .scrollable(),
         )
+        .button("Import CSV...", {
+            let db = db.clone();
+            move |siv| super::import::dialog(siv, &db)
+        })
         .dismiss_button("Done")
         .title("Edit cameras"),
     );
@@ -0,0 +1,737 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Declarative configuration via `moonfire-nvr config export` and `moonfire-nvr config apply`.
+//!
+//! The document describes sample file directories, cameras, streams (including retention), and
+//! users. Applying it makes only the changes necessary to match, so applying the same document
+//! twice is a no-op. This allows keeping the configuration in version control or copying it to
+//! another host.
+
+use base::{bail, err, Error};
+use db::json::{CameraConfig, StreamConfig, UserConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A full configuration document.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub(super) struct Document {
+    /// Paths of all sample file directories.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sample_file_dirs: Vec<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    cameras: Vec<Camera>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    users: Vec<User>,
+}
+
+/// A camera, identified by its short name.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct Camera {
+    short_name: String,
+
+    #[serde(default)]
+    config: CameraConfig,
+
+    /// Streams by type: `main`, `sub`, or `ext`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    streams: BTreeMap<String, Stream>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub(super) struct Stream {
+    /// The path of the stream's sample file directory, which must be listed in
+    /// `Document::sample_file_dirs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sample_file_dir: Option<PathBuf>,
+
+    #[serde(default)]
+    config: StreamConfig,
+}
+
+/// A user, identified by username.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct User {
+    username: String,
+
+    #[serde(default)]
+    config: UserConfig,
+
+    #[serde(default)]
+    permissions: crate::json::Permissions,
+
+    /// The password hash, in PHC string format.
+    ///
+    /// This is only exported on request. When absent, `apply` leaves an existing user's password
+    /// unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+}
+
+/// Parses a document; the format is JSON if `path` ends in `.json` and TOML otherwise.
+pub(super) fn parse(path: &Path, data: &str) -> Result<Document, Error> {
+    if is_json(path) {
+        serde_json::from_str(data).map_err(|e| err!(InvalidArgument, source(e)))
+    } else {
+        toml::from_str(data).map_err(|e| err!(InvalidArgument, source(e)))
+    }
+}
+
+/// Serializes a document in the format implied by `path`, as in [`parse`].
+pub(super) fn serialize(path: Option<&Path>, doc: &Document) -> Result<String, Error> {
+    if path.is_some_and(is_json) {
+        let mut out = serde_json::to_string_pretty(doc).map_err(|e| err!(Internal, source(e)))?;
+        out.push('\n');
+        Ok(out)
+    } else {
+        toml::to_string_pretty(doc).map_err(|e| err!(Internal, source(e)))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+}
+
+/// Exports the current configuration, optionally including password hashes.
+pub(super) fn export(db: &db::LockedDatabase, password_hashes: bool) -> Document {
+    let dir_paths: BTreeMap<i32, &Path> = db
+        .sample_file_dirs_by_id()
+        .iter()
+        .map(|(&id, d)| (id, d.path.as_path()))
+        .collect();
+    let mut doc = Document {
+        sample_file_dirs: dir_paths.values().map(|&p| p.to_owned()).collect(),
+        ..Default::default()
+    };
+    doc.sample_file_dirs.sort();
+    for c in db.cameras_by_id().values() {
+        let mut streams = BTreeMap::new();
+        for (type_, sid) in db::ALL_STREAM_TYPES.iter().zip(c.streams) {
+            let Some(sid) = sid else {
+                continue;
+            };
+            let s = db
+                .streams_by_id()
+                .get(&sid)
+                .expect("cameras reference valid streams");
+            streams.insert(
+                type_.as_str().to_owned(),
+                Stream {
+                    sample_file_dir: s.sample_file_dir_id.map(|id| dir_paths[&id].to_owned()),
+                    config: s.config.clone(),
+                },
+            );
+        }
+        doc.cameras.push(Camera {
+            short_name: c.short_name.clone(),
+            config: c.config.clone(),
+            streams,
+        });
+    }
+    doc.cameras.sort_by(|a, b| a.short_name.cmp(&b.short_name));
+    for u in db.users_by_id().values() {
+        doc.users.push(User {
+            username: u.username.clone(),
+            config: u.config.clone(),
+            permissions: u.permissions.clone().into(),
+            password_hash: u
+                .password_hash()
+                .filter(|_| password_hashes)
+                .map(str::to_owned),
+        });
+    }
+    doc.users.sort_by(|a, b| a.username.cmp(&b.username));
+    doc
+}
+
+/// Changes necessary to make the database match a [`Document`].
+#[derive(Debug, Default)]
+pub(super) struct Plan<'d> {
+    dirs_to_add: Vec<&'d Path>,
+    dirs_to_delete: Vec<(i32, PathBuf)>,
+
+    /// Cameras to add (`None`) or update (`Some(id)`).
+    cameras: Vec<(Option<i32>, &'d Camera)>,
+    cameras_to_delete: Vec<(i32, String)>,
+
+    /// Users to add (`None`) or update (`Some(id)`).
+    users: Vec<(Option<i32>, &'d User)>,
+    users_to_delete: Vec<(i32, String)>,
+}
+
+impl Plan<'_> {
+    pub(super) fn is_empty(&self) -> bool {
+        self.dirs_to_add.is_empty()
+            && self.dirs_to_delete.is_empty()
+            && self.cameras.is_empty()
+            && self.cameras_to_delete.is_empty()
+            && self.users.is_empty()
+            && self.users_to_delete.is_empty()
+    }
+
+    /// Returns a human-readable description of each change, in the order it will be applied.
+    pub(super) fn describe(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        for p in &self.dirs_to_add {
+            out.push(format!("add sample file dir {}", p.display()));
+        }
+        for (id, c) in &self.cameras {
+            let verb = if id.is_some() { "update" } else { "add" };
+            out.push(format!("{verb} camera {}", c.short_name));
+        }
+        for (id, u) in &self.users {
+            let verb = if id.is_some() { "update" } else { "add" };
+            out.push(format!("{verb} user {}", u.username));
+        }
+        for (_, name) in &self.cameras_to_delete {
+            out.push(format!("delete camera {name}"));
+        }
+        for (_, name) in &self.users_to_delete {
+            out.push(format!("delete user {name}"));
+        }
+        for (_, p) in &self.dirs_to_delete {
+            out.push(format!("delete sample file dir {}", p.display()));
+        }
+        out
+    }
+}
+
+/// Returns the changes needed to match `doc`.
+///
+/// If `prune` is true, directories, cameras, and users absent from `doc` will be deleted.
+/// Otherwise they are left alone.
+pub(super) fn plan<'d>(
+    db: &mut db::LockedDatabase,
+    doc: &'d Document,
+    prune: bool,
+) -> Result<Plan<'d>, Error> {
+    let mut plan = Plan::default();
+    let mut dir_ids_by_path = BTreeMap::new();
+    for (&id, d) in db.sample_file_dirs_by_id() {
+        if doc.sample_file_dirs.contains(&d.path) {
+            dir_ids_by_path.insert(d.path.clone(), id);
+        } else if prune {
+            plan.dirs_to_delete.push((id, d.path.clone()));
+        }
+    }
+    for p in &doc.sample_file_dirs {
+        if !dir_ids_by_path.contains_key(p) {
+            if plan.dirs_to_add.contains(&p.as_path()) {
+                bail!(
+                    InvalidArgument,
+                    msg("duplicate sample file dir {}", p.display())
+                );
+            }
+            plan.dirs_to_add.push(p);
+        }
+    }
+
+    let mut camera_ids_by_name = BTreeMap::new();
+    for (&id, c) in db.cameras_by_id() {
+        camera_ids_by_name.insert(c.short_name.clone(), id);
+    }
+    let mut seen_cameras = Vec::new();
+    for c in &doc.cameras {
+        if seen_cameras.contains(&c.short_name.as_str()) {
+            bail!(InvalidArgument, msg("duplicate camera {:?}", c.short_name));
+        }
+        seen_cameras.push(c.short_name.as_str());
+        validate_streams(doc, c)?;
+        let Some(id) = camera_ids_by_name.remove(&c.short_name) else {
+            plan.cameras.push((None, c));
+            continue;
+        };
+
+        // If any stream refers to a directory which doesn't exist yet, the camera needs an
+        // update; otherwise compare the desired change to a no-op change.
+        let Some(want) = camera_change(c, &dir_ids_by_path)? else {
+            plan.cameras.push((Some(id), c));
+            continue;
+        };
+        let have = db.null_camera_change(id)?;
+        let differs =
+            have.short_name != want.short_name
+                || have.config != want.config
+                || have.streams.iter().zip(&want.streams).any(|(h, w)| {
+                    h.sample_file_dir_id != w.sample_file_dir_id || h.config != w.config
+                });
+        if differs {
+            plan.cameras.push((Some(id), c));
+        }
+    }
+    if prune {
+        plan.cameras_to_delete = camera_ids_by_name
+            .into_iter()
+            .map(|(name, id)| (id, name))
+            .collect();
+    }
+
+    let mut user_ids_by_name = BTreeMap::new();
+    for (&id, u) in db.users_by_id() {
+        user_ids_by_name.insert(u.username.as_str(), id);
+    }
+    let mut seen_users = Vec::new();
+    for u in &doc.users {
+        if seen_users.contains(&u.username.as_str()) {
+            bail!(InvalidArgument, msg("duplicate user {:?}", u.username));
+        }
+        seen_users.push(u.username.as_str());
+        let Some(id) = user_ids_by_name.remove(u.username.as_str()) else {
+            plan.users.push((None, u));
+            continue;
+        };
+        let have = &db.users_by_id()[&id];
+        let differs = have.config != u.config
+            || crate::json::Permissions::from(have.permissions.clone()) != u.permissions
+            || u.password_hash
+                .as_deref()
+                .is_some_and(|h| have.password_hash() != Some(h));
+        if differs {
+            plan.users.push((Some(id), u));
+        }
+    }
+    if prune {
+        plan.users_to_delete = user_ids_by_name
+            .into_iter()
+            .map(|(name, id)| (id, name.to_owned()))
+            .collect();
+    }
+    Ok(plan)
+}
+
+/// Checks that a camera's streams have valid types and refer to listed directories.
+fn validate_streams(doc: &Document, c: &Camera) -> Result<(), Error> {
+    for (type_, s) in &c.streams {
+        if db::StreamType::parse(type_).is_none() {
+            bail!(
+                InvalidArgument,
+                msg(
+                    "camera {:?} has unknown stream type {type_:?}",
+                    c.short_name
+                )
+            );
+        }
+        if let Some(p) = &s.sample_file_dir {
+            if !doc.sample_file_dirs.contains(p) {
+                bail!(
+                    InvalidArgument,
+                    msg(
+                        "camera {:?} stream {type_} refers to unlisted sample file dir {}",
+                        c.short_name,
+                        p.display()
+                    )
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `CameraChange` for `c`, or `None` if it refers to a directory not yet added.
+fn camera_change(
+    c: &Camera,
+    dir_ids_by_path: &BTreeMap<PathBuf, i32>,
+) -> Result<Option<db::CameraChange>, Error> {
+    let mut change = db::CameraChange {
+        short_name: c.short_name.clone(),
+        config: c.config.clone(),
+        streams: Default::default(),
+    };
+    for (type_, s) in &c.streams {
+        let type_ = db::StreamType::parse(type_)
+            .ok_or_else(|| err!(InvalidArgument, msg("unknown stream type {type_:?}")))?;
+        let sample_file_dir_id = match &s.sample_file_dir {
+            None => None,
+            Some(p) => match dir_ids_by_path.get(p) {
+                None => return Ok(None),
+                Some(&id) => Some(id),
+            },
+        };
+        change.streams[type_.index()] = db::StreamChange {
+            sample_file_dir_id,
+            config: s.config.clone(),
+        };
+    }
+    Ok(Some(change))
+}
+
+/// Applies `plan`, which must have been created from the current state of `db`.
+///
+/// Existing recordings of streams whose sample file directory changes are moved, as in the
+/// interactive camera editor. Recordings beyond a lowered retention limit are deleted on the
+/// next `moonfire-nvr run`.
+pub(super) fn apply(db: &Arc<db::Database>, plan: Plan) -> Result<(), Error> {
+    let mut dir_ids_by_path = BTreeMap::new();
+    {
+        let mut l = db.lock();
+        for p in &plan.dirs_to_add {
+            l.add_sample_file_dir(p.to_path_buf())?;
+        }
+        for (&id, d) in l.sample_file_dirs_by_id() {
+            dir_ids_by_path.insert(d.path.clone(), id);
+        }
+    }
+    for (id, c) in &plan.cameras {
+        let change = camera_change(c, &dir_ids_by_path)?
+            .ok_or_else(|| err!(Internal, msg("camera {:?} dir not added", c.short_name)))?;
+        match *id {
+            Some(id) => {
+                super::cameras::move_streams(
+                    db,
+                    id,
+                    change.streams.each_ref().map(|s| s.sample_file_dir_id),
+                )?;
+                db.lock().update_camera(id, change)?;
+            }
+            None => {
+                db.lock().add_camera(change)?;
+            }
+        }
+    }
+    {
+        let mut l = db.lock();
+        for (id, u) in &plan.users {
+            let mut change = match *id {
+                Some(id) => l.users_by_id()[&id].change(),
+                None => db::UserChange::add_user(u.username.clone()),
+            };
+            change.config = u.config.clone();
+            let permissions = u.permissions.clone().into();
+            if permissions != change.permissions {
+                // Explicit permissions take precedence over any assigned template.
+                change.permission_template_id = None;
+            }
+            change.permissions = permissions;
+            if let Some(h) = &u.password_hash {
+                change.set_password_hash(h.clone())?;
+            }
+            l.apply_user_change(change)?;
+        }
+        for (id, name) in &plan.cameras_to_delete {
+            l.delete_camera(*id)
+                .map_err(|e| err!(e, msg("unable to delete camera {name:?}")))?;
+        }
+        for (id, _) in &plan.users_to_delete {
+            l.delete_user(*id)?;
+        }
+        for (id, p) in &plan.dirs_to_delete {
+            l.delete_sample_file_dir(*id)
+                .map_err(|e| err!(e, msg("unable to delete dir {}", p.display())))?;
+        }
+    }
+    Ok(())
+}
+
+/// Changes to one camera, as given to `moonfire-nvr config set-camera`.
+///
+/// Fields which are `None` are left unchanged.
+#[derive(Debug, Default)]
+pub(super) struct CameraEdit {
+    /// A replacement for the camera's entire config, applied before the other fields.
+    pub(super) config: Option<CameraConfig>,
+    pub(super) description: Option<String>,
+    pub(super) onvif_base_url: Option<String>,
+    pub(super) snapshot_url: Option<String>,
+    pub(super) username: Option<String>,
+    pub(super) password: Option<String>,
+}
+
+/// Changes to one stream, as given to `moonfire-nvr config set-stream` or `set-retention`.
+///
+/// Fields which are `None` are left unchanged.
+#[derive(Debug, Default)]
+pub(super) struct StreamEdit {
+    /// A replacement for the entire stream, applied before the other fields.
+    pub(super) stream: Option<Stream>,
+    pub(super) url: Option<String>,
+
+    /// `record` or `off`.
+    pub(super) mode: Option<String>,
+    pub(super) sample_file_dir: Option<PathBuf>,
+    pub(super) retain_bytes: Option<i64>,
+    pub(super) rtsp_transport: Option<String>,
+
+    /// Clockwise degrees: 0, 90, 180, or 270.
+    pub(super) rotation: Option<u16>,
+}
+
+impl Document {
+    /// Applies `edit` to the camera called `name`, adding it if necessary.
+    pub(super) fn set_camera(&mut self, name: &str, edit: CameraEdit) -> Result<(), Error> {
+        let i = match self.cameras.iter().position(|c| c.short_name == name) {
+            Some(i) => i,
+            None => {
+                self.cameras.push(Camera {
+                    short_name: name.to_owned(),
+                    config: CameraConfig::default(),
+                    streams: BTreeMap::new(),
+                });
+                self.cameras.len() - 1
+            }
+        };
+        let config = &mut self.cameras[i].config;
+        if let Some(c) = edit.config {
+            *config = c;
+        }
+        if let Some(d) = edit.description {
+            config.description = d;
+        }
+        if let Some(u) = edit.onvif_base_url {
+            config.onvif_base_url =
+                super::cameras::parse_url("onvif_base_url", &u, &["http", "https"])?;
+        }
+        if let Some(u) = edit.snapshot_url {
+            config.snapshot_url =
+                super::cameras::parse_url("snapshot_url", &u, &["http", "https"])?;
+        }
+        if let Some(u) = edit.username {
+            config.username = u;
+        }
+        if let Some(p) = edit.password {
+            config.password = p;
+        }
+        Ok(())
+    }
+
+    /// Applies `edit` to the given stream of an existing camera, adding the stream if necessary.
+    pub(super) fn set_stream(
+        &mut self,
+        camera: &str,
+        type_: db::StreamType,
+        edit: StreamEdit,
+    ) -> Result<(), Error> {
+        let c = self
+            .cameras
+            .iter_mut()
+            .find(|c| c.short_name == camera)
+            .ok_or_else(|| err!(NotFound, msg("no camera {camera:?}")))?;
+        let s = c
+            .streams
+            .entry(type_.as_str().to_owned())
+            .or_insert_with(|| Stream {
+                sample_file_dir: None,
+                config: StreamConfig::default(),
+            });
+        if let Some(new) = edit.stream {
+            *s = new;
+        }
+        if let Some(u) = edit.url {
+            s.config.url = super::cameras::parse_stream_url(type_, &u)?;
+        }
+        match edit.mode.as_deref() {
+            None => {}
+            Some("record") => db::json::STREAM_MODE_RECORD.clone_into(&mut s.config.mode),
+            Some("off") => s.config.mode.clear(),
+            Some(m) => bail!(
+                InvalidArgument,
+                msg("unknown mode {m:?}; should be record or off")
+            ),
+        }
+        if let Some(p) = edit.sample_file_dir {
+            if !self.sample_file_dirs.contains(&p) {
+                bail!(NotFound, msg("no sample file dir {}", p.display()));
+            }
+            s.sample_file_dir = Some(p);
+        }
+        if let Some(b) = edit.retain_bytes {
+            if b < 0 {
+                bail!(InvalidArgument, msg("retention must be non-negative"));
+            }
+            s.config.retain_bytes = b;
+        }
+        if let Some(t) = edit.rtsp_transport {
+            if !["", "tcp", "udp"].contains(&t.as_str()) {
+                bail!(
+                    InvalidArgument,
+                    msg("unknown rtsp transport {t:?}; should be tcp or udp")
+                );
+            }
+            s.config.rtsp_transport = t;
+        }
+        if let Some(r) = edit.rotation {
+            if ![0, 90, 180, 270].contains(&r) {
+                bail!(
+                    InvalidArgument,
+                    msg("unsupported rotation {r}; should be 0, 90, 180, or 270")
+                );
+            }
+            s.config.rotation = r;
+        }
+        if s.config.mode == db::json::STREAM_MODE_RECORD && s.sample_file_dir.is_none() {
+            bail!(
+                InvalidArgument,
+                msg("can't record {type_} stream without sample file directory")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::testutil::{self, TestDb};
+
+    #[test]
+    fn round_trip() {
+        testutil::init();
+        let tdb = TestDb::new(base::clock::RealClocks {});
+        let doc = export(&tdb.db.lock(), true);
+        let toml = serialize(None, &doc).unwrap();
+        assert_eq!(parse(Path::new("a.toml"), &toml).unwrap(), doc);
+        let json = serialize(Some(Path::new("a.json")), &doc).unwrap();
+        assert_eq!(parse(Path::new("a.json"), &json).unwrap(), doc);
+        assert!(plan(&mut tdb.db.lock(), &doc, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn apply_is_idempotent() {
+        testutil::init();
+        let tdb = TestDb::new(base::clock::RealClocks {});
+        let mut doc = export(&tdb.db.lock(), false);
+        doc.cameras[0].config.description = "new description".to_owned();
+        doc.cameras.push(Camera {
+            short_name: "added".to_owned(),
+            config: CameraConfig::default(),
+            streams: BTreeMap::from([(
+                "main".to_owned(),
+                Stream {
+                    sample_file_dir: Some(doc.sample_file_dirs[0].clone()),
+                    config: StreamConfig {
+                        mode: db::json::STREAM_MODE_RECORD.to_owned(),
+                        retain_bytes: 1 << 20,
+                        ..Default::default()
+                    },
+                },
+            )]),
+        });
+        doc.users.push(User {
+            username: "slamb".to_owned(),
+            config: UserConfig::default(),
+            permissions: crate::json::Permissions {
+                view_live: true,
+                download_recordings: true,
+                ..Default::default()
+            },
+            password_hash: None,
+        });
+        let p = plan(&mut tdb.db.lock(), &doc, false).unwrap();
+        assert_eq!(
+            p.describe(),
+            [
+                format!("update camera {}", doc.cameras[0].short_name),
+                "add camera added".to_owned(),
+                "add user slamb".to_owned(),
+            ]
+        );
+        apply(&tdb.db, p).unwrap();
+        let mut exported = export(&tdb.db.lock(), false);
+        exported
+            .cameras
+            .sort_by(|a, b| a.short_name.cmp(&b.short_name));
+        doc.cameras.sort_by(|a, b| a.short_name.cmp(&b.short_name));
+        doc.users.sort_by(|a, b| a.username.cmp(&b.username));
+        assert_eq!(exported, doc);
+        assert!(plan(&mut tdb.db.lock(), &doc, false).unwrap().is_empty());
+
+        // Pruning removes the camera and user which aren't in the document.
+        doc.cameras.retain(|c| c.short_name == "added");
+        doc.users.clear();
+        let p = plan(&mut tdb.db.lock(), &doc, true).unwrap();
+        assert_eq!(p.cameras_to_delete.len(), 1);
+        assert!(p.dirs_to_delete.is_empty());
+    }
+
+    #[test]
+    fn set_camera_and_stream() {
+        testutil::init();
+        let tdb = TestDb::new(base::clock::RealClocks {});
+        let mut doc = export(&tdb.db.lock(), false);
+        let dir = doc.sample_file_dirs[0].clone();
+        doc.set_camera(
+            "new",
+            CameraEdit {
+                onvif_base_url: Some("http://192.168.1.10/".to_owned()),
+                username: Some("admin".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        doc.set_stream(
+            "new",
+            db::StreamType::Main,
+            StreamEdit {
+                url: Some("rtsp://192.168.1.10/main".to_owned()),
+                mode: Some("record".to_owned()),
+                sample_file_dir: Some(dir),
+                retain_bytes: Some(1 << 30),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let p = plan(&mut tdb.db.lock(), &doc, false).unwrap();
+        assert_eq!(p.describe(), ["add camera new"]);
+        apply(&tdb.db, p).unwrap();
+
+        // Lowering retention is a single update.
+        let mut doc = export(&tdb.db.lock(), false);
+        doc.set_stream(
+            "new",
+            db::StreamType::Main,
+            StreamEdit {
+                retain_bytes: Some(1 << 20),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let p = plan(&mut tdb.db.lock(), &doc, false).unwrap();
+        assert_eq!(p.describe(), ["update camera new"]);
+        apply(&tdb.db, p).unwrap();
+        {
+            let l = tdb.db.lock();
+            let c = l
+                .cameras_by_id()
+                .values()
+                .find(|c| c.short_name == "new")
+                .unwrap();
+            assert_eq!(c.config.username, "admin");
+            let s = &l.streams_by_id()[&c.streams[0].unwrap()];
+            assert_eq!(s.config.retain_bytes, 1 << 20);
+            assert_eq!(s.config.mode, db::json::STREAM_MODE_RECORD);
+        }
+
+        // Bad edits are rejected.
+        let edit = |mode: &str| StreamEdit {
+            mode: Some(mode.to_owned()),
+            ..Default::default()
+        };
+        assert!(doc
+            .set_stream("new", db::StreamType::Sub, edit("record"))
+            .is_err());
+        assert!(doc
+            .set_stream("new", db::StreamType::Main, edit("bogus"))
+            .is_err());
+        assert!(doc
+            .set_stream("missing", db::StreamType::Main, edit("off"))
+            .is_err());
+        assert!(doc
+            .set_stream(
+                "new",
+                db::StreamType::Main,
+                StreamEdit {
+                    rotation: Some(45),
+                    ..Default::default()
+                }
+            )
+            .is_err());
+    }
+}
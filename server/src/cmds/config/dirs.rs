@@ -21,6 +21,50 @@ struct Stream {
     used: i64,
     record: bool,
     retain: Option<i64>, // None if unparseable
+
+    /// The average bitrate over the stream's most recent recordings, if any.
+    bytes_per_sec: Option<f64>,
+}
+
+/// How far back from the end of a stream's recordings to average its bitrate.
+const BITRATE_WINDOW: db::recording::Duration =
+    db::recording::Duration(24 * 60 * 60 * db::recording::TIME_UNITS_PER_SEC);
+
+/// Returns the average bitrate of the stream's last day of recordings.
+fn recent_bytes_per_sec(l: &db::LockedDatabase, s: &db::Stream) -> Option<f64> {
+    let end = s.range.as_ref()?.end;
+    let mut bytes = 0;
+    let mut duration_90k = 0;
+    l.list_recordings_by_time(s.id, end - BITRATE_WINDOW..end, &mut |r| {
+        bytes += i64::from(r.sample_file_bytes);
+        duration_90k += i64::from(r.wall_duration_90k);
+        Ok(())
+    })
+    .ok()?;
+    if duration_90k == 0 {
+        return None;
+    }
+    Some(bytes as f64 * db::recording::TIME_UNITS_PER_SEC as f64 / duration_90k as f64)
+}
+
+fn format_bitrate(bytes_per_sec: Option<f64>) -> String {
+    match bytes_per_sec {
+        None => "-".to_owned(),
+        Some(b) => format!("{:.1} Mbps", b * 8. / 1_000_000.),
+    }
+}
+
+/// Returns how long `retain` bytes last at the given bitrate.
+fn format_retention(retain: Option<i64>, bytes_per_sec: Option<f64>) -> String {
+    let (Some(retain), Some(bytes_per_sec)) = (retain, bytes_per_sec) else {
+        return "-".to_owned();
+    };
+    let hours = retain as f64 / bytes_per_sec / 3600.;
+    if hours < 48. {
+        format!("~{hours:.1} hours")
+    } else {
+        format!("~{:.1} days", hours / 24.)
+    }
 }
 
 struct Model {
@@ -83,6 +127,9 @@ fn edit_limit(model: &mut Model, siv: &mut Cursive, id: i32, content: &str) {
             .set_content(if new_value.is_none() { "*" } else { " " });
     }
     stream.retain = new_value;
+    siv.find_name::<views::TextView>(&format!("{id}_retention"))
+        .unwrap()
+        .set_content(format_retention(new_value, stream.bytes_per_sec));
     debug!("model.errors = {}", model.errors);
     if (model.errors == 0) != (old_errors == 0) {
         trace!("toggling change state: errors={}", model.errors);
@@ -334,6 +381,7 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
         {
             let mut l = db.lock();
             for (&id, s) in l.streams_by_id() {
+                let bytes_per_sec = recent_bytes_per_sec(&l, s);
                 let c = l
                     .cameras_by_id()
                     .get(&s.camera_id)
@@ -348,6 +396,7 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
                         used: s.fs_bytes,
                         record: s.config.mode == db::json::STREAM_MODE_RECORD,
                         retain: Some(s.config.retain_bytes),
+                        bytes_per_sec,
                     },
                 );
                 total_used += s.fs_bytes;
@@ -358,8 +407,7 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
             }
             l.open_sample_file_dirs(&[dir_id]).unwrap(); // TODO: don't unwrap.
             let dir = l.sample_file_dirs_by_id().get(&dir_id).unwrap();
-            let stat = dir.get().unwrap().statfs().unwrap();
-            fs_capacity = stat.block_size() as i64 * stat.blocks_available() as i64 + total_used;
+            fs_capacity = dir.get().unwrap().free_bytes().unwrap() + total_used;
             path = dir.path.clone();
         }
         Arc::new(Mutex::new(Model {
@@ -375,6 +423,7 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
 
     const RECORD_WIDTH: usize = 8;
     const BYTES_WIDTH: usize = 22;
+    const BITRATE_WIDTH: usize = 12;
 
     let mut list = views::ListView::new();
     list.add_child(
@@ -382,7 +431,9 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
         views::LinearLayout::horizontal()
             .child(views::TextView::new("record").fixed_width(RECORD_WIDTH))
             .child(views::TextView::new("usage").fixed_width(BYTES_WIDTH))
-            .child(views::TextView::new("limit").fixed_width(BYTES_WIDTH)),
+            .child(views::TextView::new("bitrate").fixed_width(BITRATE_WIDTH))
+            .child(views::TextView::new("limit").fixed_width(BYTES_WIDTH))
+            .child(views::TextView::new("est. retention")),
     );
     let l = model.lock().unwrap();
     for (&id, stream) in &l.streams {
@@ -397,6 +448,10 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
             views::LinearLayout::horizontal()
                 .child(record_cb.fixed_width(RECORD_WIDTH))
                 .child(views::TextView::new(encode_size(stream.used)).fixed_width(BYTES_WIDTH))
+                .child(
+                    views::TextView::new(format_bitrate(stream.bytes_per_sec))
+                        .fixed_width(BITRATE_WIDTH),
+                )
                 .child(
                     views::EditView::new()
                         .content(encode_size(stream.retain.unwrap()))
@@ -416,6 +471,11 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
                     views::TextView::new("")
                         .with_name(format!("{id}_ok"))
                         .fixed_width(1),
+                )
+                .child(views::DummyView.fixed_width(1))
+                .child(
+                    views::TextView::new(format_retention(stream.retain, stream.bytes_per_sec))
+                        .with_name(format!("{id}_retention")),
                 ),
         );
     }
@@ -425,6 +485,7 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
         views::LinearLayout::horizontal()
             .child(views::DummyView {}.fixed_width(RECORD_WIDTH))
             .child(views::TextView::new(encode_size(l.total_used)).fixed_width(BYTES_WIDTH))
+            .child(views::DummyView {}.fixed_width(BITRATE_WIDTH))
             .child(
                 views::TextView::new(encode_size(l.total_retain))
                     .with_name("total_retain")
@@ -437,6 +498,7 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
         views::LinearLayout::horizontal()
             .child(views::DummyView {}.fixed_width(RECORD_WIDTH))
             .child(views::DummyView {}.fixed_width(BYTES_WIDTH))
+            .child(views::DummyView {}.fixed_width(BITRATE_WIDTH))
             .child(views::TextView::new(encode_size(l.fs_capacity)).fixed_width(BYTES_WIDTH)),
     );
     drop(l);
@@ -458,3 +520,22 @@ fn edit_dir_dialog(db: &Arc<db::Database>, siv: &mut Cursive, dir_id: i32) {
         .title(format!("Edit retention for {}", path.display())),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retention() {
+        assert_eq!(format_bitrate(Some(500_000.)), "4.0 Mbps");
+        assert_eq!(format_retention(Some(1 << 30), None), "-");
+        assert_eq!(
+            format_retention(Some(1_800_000_000), Some(500_000.)),
+            "~1.0 hours"
+        );
+        assert_eq!(
+            format_retention(Some(500_000 * 86_400 * 7), Some(500_000.)),
+            "~7.0 days"
+        );
+    }
+}
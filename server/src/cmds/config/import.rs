@@ -0,0 +1,371 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Bulk import of cameras from a CSV file, from the "Edit cameras" dialog.
+//!
+//! Each row has the columns `name,host,username,password`. Stream URLs and the ONVIF base URL are
+//! built from templates in which `{host}` is replaced with the row's host, as many installations
+//! use several cameras of the same model.
+
+use super::cameras::{parse_stream_url, parse_url};
+use base::{bail, err, Error};
+use cursive::traits::{Nameable, Resizable};
+use cursive::views;
+use cursive::Cursive;
+use std::sync::Arc;
+
+/// A single row of the CSV file.
+#[derive(Debug, PartialEq, Eq)]
+struct Row {
+    short_name: String,
+    host: String,
+    username: String,
+    password: String,
+}
+
+/// Settings shared by all imported cameras.
+#[derive(Debug, Default)]
+struct Template {
+    onvif_base_url: String,
+
+    /// URL templates, indexed by `db::StreamType::index`.
+    urls: [String; db::NUM_STREAM_TYPES],
+
+    /// Whether to record each stream type, indexed by `db::StreamType::index`.
+    record: [bool; db::NUM_STREAM_TYPES],
+
+    sample_file_dir_id: Option<i32>,
+}
+
+/// Splits a CSV line into fields, as in RFC 4180 except that fields can't span lines.
+fn split_line(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut fields = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cur.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if cur.is_empty() => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut cur)),
+            (_, c) => cur.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field");
+    }
+    fields.push(cur);
+    Ok(fields)
+}
+
+/// Parses CSV data, skipping blank lines, `#` comments, and a header row starting with `name`.
+fn parse(data: &str) -> Result<Vec<Row>, Error> {
+    let mut rows = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields =
+            split_line(line).map_err(|e| err!(InvalidArgument, msg("line {}: {e}", i + 1)))?;
+        if rows.is_empty() && fields[0].trim().eq_ignore_ascii_case("name") {
+            continue;
+        }
+        let Ok([short_name, host, username, password]) = <[String; 4]>::try_from(fields) else {
+            bail!(
+                InvalidArgument,
+                msg(
+                    "line {}: expected 4 fields: name,host,username,password",
+                    i + 1
+                )
+            );
+        };
+        if short_name.is_empty() || host.is_empty() {
+            bail!(
+                InvalidArgument,
+                msg("line {}: name and host must be non-empty", i + 1)
+            );
+        }
+        rows.push(Row {
+            short_name,
+            host,
+            username,
+            password,
+        });
+    }
+    Ok(rows)
+}
+
+/// Builds the change which adds the camera described by `row`.
+fn camera_change(row: &Row, template: &Template) -> Result<db::CameraChange, Error> {
+    let expand = |t: &str| t.replace("{host}", &row.host);
+    let mut change = db::CameraChange {
+        short_name: row.short_name.clone(),
+        ..Default::default()
+    };
+    change.config.onvif_base_url = parse_url(
+        "onvif_base_url",
+        &expand(&template.onvif_base_url),
+        &["http", "https"],
+    )?;
+    change.config.username.clone_from(&row.username);
+    change.config.password.clone_from(&row.password);
+    for (i, stream_change) in change.streams.iter_mut().enumerate() {
+        let type_ = db::StreamType::from_index(i).unwrap();
+        let url = parse_stream_url(type_, &expand(&template.urls[i]))?;
+        if url.is_none() {
+            continue;
+        }
+        if template.record[i] {
+            if template.sample_file_dir_id.is_none() {
+                bail!(
+                    InvalidArgument,
+                    msg("can't record {type_} stream without sample file directory")
+                );
+            }
+            db::json::STREAM_MODE_RECORD.clone_into(&mut stream_change.config.mode);
+        }
+        stream_change.config.url = url;
+        stream_change.sample_file_dir_id = template.sample_file_dir_id;
+    }
+    Ok(change)
+}
+
+/// Adds a camera for each row, returning the number added.
+///
+/// All rows are validated before any camera is added.
+fn import(db: &db::Database, rows: &[Row], template: &Template) -> Result<usize, Error> {
+    let changes = rows
+        .iter()
+        .map(|r| camera_change(r, template).map_err(|e| err!(e, msg("camera {:?}", r.short_name))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut l = db.lock();
+    for c in &changes {
+        if l.cameras_by_id()
+            .values()
+            .any(|e| e.short_name == c.short_name)
+            || changes
+                .iter()
+                .filter(|o| o.short_name == c.short_name)
+                .count()
+                > 1
+        {
+            bail!(
+                AlreadyExists,
+                msg("duplicate camera name {:?}", c.short_name)
+            );
+        }
+    }
+    let n = changes.len();
+    for c in changes {
+        l.add_camera(c)?;
+    }
+    Ok(n)
+}
+
+fn get_template(siv: &mut Cursive) -> Template {
+    let mut template = Template {
+        onvif_base_url: siv
+            .find_name::<views::EditView>("import_onvif_base_url")
+            .unwrap()
+            .get_content()
+            .as_str()
+            .to_owned(),
+        sample_file_dir_id: *siv
+            .find_name::<views::SelectView<Option<i32>>>("import_sample_file_dir")
+            .unwrap()
+            .selection()
+            .unwrap(),
+        ..Default::default()
+    };
+    for t in &db::ALL_STREAM_TYPES {
+        template.urls[t.index()] = siv
+            .find_name::<views::EditView>(&format!("import_{t}_url"))
+            .unwrap()
+            .get_content()
+            .as_str()
+            .to_owned();
+        template.record[t.index()] = siv
+            .find_name::<views::Checkbox>(&format!("import_{t}_record"))
+            .unwrap()
+            .is_checked();
+    }
+    template
+}
+
+fn press_import(siv: &mut Cursive, db: &Arc<db::Database>) {
+    let path = siv
+        .find_name::<views::EditView>("import_path")
+        .unwrap()
+        .get_content();
+    let template = get_template(siv);
+    let result = std::fs::read_to_string(path.as_str())
+        .map_err(|e| err!(e, msg("unable to read {path}")))
+        .and_then(|data| parse(&data))
+        .and_then(|rows| import(db, &rows, &template));
+    match result {
+        Err(e) => siv.add_layer(
+            views::Dialog::text(format!("Unable to import cameras: {}", e.chain()))
+                .title("Error")
+                .dismiss_button("Abort"),
+        ),
+        Ok(n) => {
+            siv.pop_layer(); // get rid of the import dialog.
+
+            // Recreate the "Edit cameras" dialog to show the new cameras.
+            siv.pop_layer();
+            super::cameras::top_dialog(db, siv);
+            siv.add_layer(
+                views::Dialog::text(format!("Added {n} cameras."))
+                    .title("Import complete")
+                    .dismiss_button("Ok"),
+            );
+        }
+    }
+}
+
+pub(super) fn dialog(siv: &mut Cursive, db: &Arc<db::Database>) {
+    let dirs: Vec<_> = ::std::iter::once(("<none>".to_owned(), None))
+        .chain(
+            db.lock()
+                .sample_file_dirs_by_id()
+                .iter()
+                .map(|(&id, d)| (d.path.display().to_string(), Some(id))),
+        )
+        .collect();
+    let mut list = views::ListView::new()
+        .child(
+            "csv file",
+            views::EditView::new()
+                .with_name("import_path")
+                .min_width(40),
+        )
+        .child(
+            "onvif_base_url",
+            views::EditView::new()
+                .content("http://{host}/")
+                .with_name("import_onvif_base_url"),
+        )
+        .child(
+            "sample file dir",
+            views::SelectView::<Option<i32>>::new()
+                .with_all(dirs)
+                .popup()
+                .with_name("import_sample_file_dir"),
+        );
+    for t in &db::ALL_STREAM_TYPES {
+        list.add_child(
+            &format!("{t} rtsp url"),
+            views::EditView::new().with_name(format!("import_{t}_url")),
+        );
+        list.add_child(
+            &format!("{t} record"),
+            views::Checkbox::new().with_name(format!("import_{t}_record")),
+        );
+    }
+    let layout = views::LinearLayout::vertical()
+        .child(views::TextView::new(
+            "Adds a camera for each row of a CSV file with columns \
+             name,host,username,password. In URLs, {host} is replaced with each row's host; \
+             streams with empty URLs are omitted.",
+        ))
+        .child(views::DummyView)
+        .child(list);
+    siv.add_layer(
+        views::Dialog::around(layout)
+            .title("Import cameras")
+            .button("Import", {
+                let db = db.clone();
+                move |s| press_import(s, &db)
+            })
+            .dismiss_button("Cancel"),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv() {
+        let rows = parse(
+            "name,host,username,password\r\n\
+             # comment\n\
+             \n\
+             front,192.168.1.10,admin,\"pa,ss\"\"word\"\n\
+             back,back.lan,admin,\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            [
+                Row {
+                    short_name: "front".to_owned(),
+                    host: "192.168.1.10".to_owned(),
+                    username: "admin".to_owned(),
+                    password: "pa,ss\"word".to_owned(),
+                },
+                Row {
+                    short_name: "back".to_owned(),
+                    host: "back.lan".to_owned(),
+                    username: "admin".to_owned(),
+                    password: "".to_owned(),
+                },
+            ]
+        );
+        assert!(parse("front,host,admin\n").is_err());
+        assert!(parse("front,host,admin,\"unterminated\n").is_err());
+        assert!(parse(",host,admin,pass\n").is_err());
+    }
+
+    #[test]
+    fn import_cameras() {
+        db::testutil::init();
+        let tdb = db::testutil::TestDb::new(base::clock::RealClocks {});
+        let dir_id = *tdb
+            .db
+            .lock()
+            .sample_file_dirs_by_id()
+            .keys()
+            .next()
+            .unwrap();
+        let mut template = Template {
+            onvif_base_url: "http://{host}/".to_owned(),
+            sample_file_dir_id: Some(dir_id),
+            ..Default::default()
+        };
+        template.urls[0] = "rtsp://{host}/main".to_owned();
+        template.record[0] = true;
+        let rows = parse("a,10.0.0.1,u,p\nb,10.0.0.2,u,p\n").unwrap();
+        assert_eq!(import(&tdb.db, &rows, &template).unwrap(), 2);
+        {
+            let l = tdb.db.lock();
+            let b = l
+                .cameras_by_id()
+                .values()
+                .find(|c| c.short_name == "b")
+                .unwrap();
+            assert_eq!(
+                b.config.onvif_base_url.as_ref().unwrap().as_str(),
+                "http://10.0.0.2/"
+            );
+            let main = l.streams_by_id().get(&b.streams[0].unwrap()).unwrap();
+            assert_eq!(
+                main.config.url.as_ref().unwrap().as_str(),
+                "rtsp://10.0.0.2/main"
+            );
+            assert_eq!(main.config.mode, db::json::STREAM_MODE_RECORD);
+            assert!(b.streams[1].is_none());
+        }
+
+        // Importing the same names again fails without adding anything.
+        let n = tdb.db.lock().cameras_by_id().len();
+        assert!(import(&tdb.db, &rows, &template).is_err());
+        assert_eq!(tdb.db.lock().cameras_by_id().len(), n);
+    }
+}
@@ -2,36 +2,333 @@
 // Copyright (C) 2017 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
-//! Text-based configuration interface.
+//! Text-based configuration interface, plus non-interactive export, apply, and edits.
 //!
 //! This code is a bit messy, but it's essentially a prototype. Eventually Moonfire NVR's
 //! configuration will likely be almost entirely done through a web-based UI.
 
 use base::clock;
-use base::Error;
+use base::strutil::decode_size;
+use base::{bail, err, Error};
 use bpaf::Bpaf;
 use cursive::views;
 use cursive::Cursive;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tracing::info;
 
 mod cameras;
+mod declarative;
 mod dirs;
+mod import;
 mod tab_complete;
 mod users;
 
-/// Interactively edits configuration.
+/// Interactively edits configuration, exports/applies it as a file, or
+/// makes a single change non-interactively.
 #[derive(Bpaf, Debug)]
 #[bpaf(command("config"))]
 pub struct Args {
     #[bpaf(external(crate::parse_db_dir))]
     db_dir: PathBuf,
+
+    /// Decrypts and encrypts camera credentials with the key in the given
+    /// file, as in the `credentialKey` option of `/etc/moonfire-nvr.toml`:
+    /// e.g. `/run/secrets/credential_key`. Required if camera credentials have
+    /// previously been encrypted.
+    #[bpaf(argument("PATH"))]
+    credential_key_file: Option<PathBuf>,
+
+    #[bpaf(external(action), optional)]
+    action: Option<Action>,
+}
+
+#[derive(Bpaf, Debug)]
+enum Action {
+    /// Writes the sample file directories, cameras, streams, and users as a
+    /// TOML document (or JSON if the output path ends in `.json`).
+    #[bpaf(command("export"))]
+    Export {
+        /// Includes users' password hashes.
+        include_password_hashes: bool,
+
+        /// Writes to the given path rather than stdout.
+        #[bpaf(short, long, argument("PATH"))]
+        output: Option<PathBuf>,
+    },
+
+    /// Changes the configuration to match a document written by `export`.
+    /// Applying the same document again makes no further changes.
+    #[bpaf(command("apply"))]
+    Apply {
+        /// Prints the changes without making them.
+        dry_run: bool,
+
+        /// Deletes sample file directories, cameras, and users which aren't
+        /// in the document. Cameras with recordings can't be deleted.
+        prune: bool,
+
+        #[bpaf(positional("PATH"))]
+        input: PathBuf,
+    },
+
+    /// Adds a camera or changes its settings. Settings which aren't given are
+    /// left unchanged.
+    #[bpaf(command("set-camera"))]
+    SetCamera {
+        /// Reads the camera's whole `config` as JSON from stdin, in the format
+        /// written by `export`, before applying the other options. This avoids
+        /// passing a password on the command line.
+        json: bool,
+
+        #[bpaf(argument("TEXT"))]
+        description: Option<String>,
+
+        #[bpaf(argument("URL"))]
+        onvif_base_url: Option<String>,
+
+        /// A URL which returns a JPEG of the camera's current view, for signal snapshots.
+        #[bpaf(argument("URL"))]
+        snapshot_url: Option<String>,
+
+        #[bpaf(argument("USERNAME"))]
+        username: Option<String>,
+
+        #[bpaf(argument("PASSWORD"))]
+        password: Option<String>,
+
+        #[bpaf(positional("NAME"))]
+        name: String,
+    },
+
+    /// Adds or changes a stream (`main`, `sub`, or `ext`) of an existing
+    /// camera. Settings which aren't given are left unchanged.
+    #[bpaf(command("set-stream"))]
+    SetStream {
+        /// Reads the whole stream as JSON from stdin, in the format written by
+        /// `export`, before applying the other options.
+        json: bool,
+
+        #[bpaf(argument("URL"))]
+        url: Option<String>,
+
+        /// `record` or `off`.
+        #[bpaf(argument("MODE"))]
+        mode: Option<String>,
+
+        /// The path of an existing sample file directory. Existing recordings
+        /// are moved if this changes.
+        #[bpaf(argument("PATH"))]
+        sample_file_dir: Option<PathBuf>,
+
+        /// The number of bytes to retain, e.g. `100 GiB`.
+        #[bpaf(argument("SIZE"))]
+        retain: Option<String>,
+
+        /// `tcp` or `udp`.
+        #[bpaf(argument("TRANSPORT"))]
+        rtsp_transport: Option<String>,
+
+        /// The clockwise rotation to apply on playback: 0, 90, 180, or 270.
+        #[bpaf(argument("DEGREES"))]
+        rotation: Option<u16>,
+
+        #[bpaf(positional("CAMERA"))]
+        camera: String,
+
+        #[bpaf(positional("TYPE"))]
+        stream_type: String,
+    },
+
+    /// Sets the number of bytes to retain for a stream, e.g. `100 GiB`.
+    /// Recordings beyond a lowered limit are deleted on the next
+    /// `moonfire-nvr run`.
+    #[bpaf(command("set-retention"))]
+    SetRetention {
+        #[bpaf(positional("CAMERA"))]
+        camera: String,
+
+        #[bpaf(positional("TYPE"))]
+        stream_type: String,
+
+        #[bpaf(positional("SIZE"))]
+        retain: String,
+    },
+}
+
+fn read_document(path: &Path) -> Result<declarative::Document, Error> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| err!(e, msg("unable to read {}", path.display())))?;
+    declarative::parse(path, &data)
+}
+
+/// A configuration document used to provision a new database, as in `moonfire-nvr init --seed`.
+pub(super) struct Seed(declarative::Document);
+
+impl Seed {
+    /// Reads a document in the format written by `config export`.
+    pub(super) fn read(path: &Path) -> Result<Self, Error> {
+        read_document(path).map(Seed)
+    }
+
+    /// Adds everything in the document to `db`, printing each change.
+    pub(super) fn apply(&self, db: &Arc<db::Database>) -> Result<(), Error> {
+        let plan = declarative::plan(&mut db.lock(), &self.0, false)?;
+        for c in plan.describe() {
+            info!("seed: {c}");
+        }
+        declarative::apply(db, plan)
+    }
+}
+
+/// Reads a JSON value from stdin.
+fn read_json<T: serde::de::DeserializeOwned>() -> Result<T, Error> {
+    serde_json::from_reader(std::io::stdin().lock())
+        .map_err(|e| err!(InvalidArgument, msg("unable to parse stdin"), source(e)))
+}
+
+fn parse_stream_type(raw: &str) -> Result<db::StreamType, Error> {
+    db::StreamType::parse(raw).ok_or_else(|| {
+        err!(
+            InvalidArgument,
+            msg("unknown stream type {raw:?}; should be main, sub, or ext")
+        )
+    })
+}
+
+fn parse_retain(raw: &str) -> Result<i64, Error> {
+    decode_size(raw).map_err(|()| err!(InvalidArgument, msg("can't parse size {raw:?}")))
+}
+
+/// Edits the exported configuration with `f`, then applies the result as `apply` would.
+fn edit(
+    db: &Arc<db::Database>,
+    f: impl FnOnce(&mut declarative::Document) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut doc = declarative::export(&db.lock(), false);
+    f(&mut doc)?;
+    let plan = declarative::plan(&mut db.lock(), &doc, false)?;
+    for c in plan.describe() {
+        println!("{c}");
+    }
+    if !plan.is_empty() {
+        declarative::apply(db, plan)?;
+    }
+    Ok(())
 }
 
 pub fn run(args: Args) -> Result<i32, Error> {
-    let (_db_dir, conn) = super::open_conn(&args.db_dir, super::OpenMode::ReadWrite)?;
+    let read_only = matches!(args.action, Some(Action::Export { .. }));
+    let (_db_dir, conn) = super::open_conn(
+        &args.db_dir,
+        if read_only {
+            super::OpenMode::ReadOnly
+        } else {
+            super::OpenMode::ReadWrite
+        },
+    )?;
     let clocks = clock::RealClocks {};
-    let db = Arc::new(db::Database::new(clocks, conn, true)?);
+    let db = Arc::new(db::Database::new(clocks, conn, !read_only)?);
+    {
+        let mut l = db.lock();
+        if let Some(p) = &args.credential_key_file {
+            let k = db::credential::Key::parse(&crate::cmds::run::config::read_secret_file(p)?)?;
+            l.set_credential_key(&k)?;
+        } else if l.has_locked_credentials() {
+            bail!(
+                FailedPrecondition,
+                msg("camera credentials are encrypted; --credential-key-file must be set")
+            );
+        }
+    }
+    match args.action {
+        Some(Action::Export {
+            include_password_hashes,
+            output,
+        }) => {
+            let doc = declarative::export(&db.lock(), include_password_hashes);
+            let out = declarative::serialize(output.as_deref(), &doc)?;
+            match output {
+                Some(p) => std::fs::write(&p, out)
+                    .map_err(|e| err!(e, msg("unable to write {}", p.display())))?,
+                None => print!("{out}"),
+            }
+            return Ok(0);
+        }
+        Some(Action::Apply {
+            dry_run,
+            prune,
+            input,
+        }) => {
+            let doc = read_document(&input)?;
+            let plan = declarative::plan(&mut db.lock(), &doc, prune)?;
+            for c in plan.describe() {
+                println!("{c}");
+            }
+            if !dry_run && !plan.is_empty() {
+                declarative::apply(&db, plan)?;
+            }
+            return Ok(0);
+        }
+        Some(Action::SetCamera {
+            json,
+            description,
+            onvif_base_url,
+            snapshot_url,
+            username,
+            password,
+            name,
+        }) => {
+            let e = declarative::CameraEdit {
+                config: if json { Some(read_json()?) } else { None },
+                description,
+                onvif_base_url,
+                snapshot_url,
+                username,
+                password,
+            };
+            edit(&db, |doc| doc.set_camera(&name, e))?;
+            return Ok(0);
+        }
+        Some(Action::SetStream {
+            json,
+            url,
+            mode,
+            sample_file_dir,
+            retain,
+            rtsp_transport,
+            rotation,
+            camera,
+            stream_type,
+        }) => {
+            let type_ = parse_stream_type(&stream_type)?;
+            let e = declarative::StreamEdit {
+                stream: if json { Some(read_json()?) } else { None },
+                url,
+                mode,
+                sample_file_dir,
+                retain_bytes: retain.as_deref().map(parse_retain).transpose()?,
+                rtsp_transport,
+                rotation,
+            };
+            edit(&db, |doc| doc.set_stream(&camera, type_, e))?;
+            return Ok(0);
+        }
+        Some(Action::SetRetention {
+            camera,
+            stream_type,
+            retain,
+        }) => {
+            let type_ = parse_stream_type(&stream_type)?;
+            let e = declarative::StreamEdit {
+                retain_bytes: Some(parse_retain(&retain)?),
+                ..Default::default()
+            };
+            edit(&db, |doc| doc.set_stream(&camera, type_, e))?;
+            return Ok(0);
+        }
+        None => {}
+    }
 
     // This runtime is needed by the "Test" button in the camera config.
     let rt = tokio::runtime::Builder::new_multi_thread()
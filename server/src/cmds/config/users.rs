@@ -35,8 +35,13 @@ fn get_change(
         }
         PasswordChange::Clear => change.clear_password(),
     };
+    let old_permissions = change.permissions.clone();
     for (id, ref mut b) in &mut [
-        ("perm_view_video", &mut change.permissions.view_video),
+        ("perm_view_live", &mut change.permissions.view_live),
+        (
+            "perm_download_recordings",
+            &mut change.permissions.download_recordings,
+        ),
         (
             "perm_read_camera_configs",
             &mut change.permissions.read_camera_configs,
@@ -48,6 +53,10 @@ fn get_change(
     ] {
         **b = siv.find_name::<views::Checkbox>(id).unwrap().is_checked();
     }
+    if change.permissions != old_permissions {
+        // Editing permissions directly unassigns any permission template.
+        change.permission_template_id = None;
+    }
     change
 }
 
@@ -183,7 +192,8 @@ fn edit_user_dialog(db: &Arc<db::Database>, siv: &mut Cursive, item: Option<i32>
     layout.add_child(views::TextView::new("permissions"));
     let mut perms = views::ListView::new();
     for (name, b) in &[
-        ("view_video", permissions.view_video),
+        ("view_live", permissions.view_live),
+        ("download_recordings", permissions.download_recordings),
         ("read_camera_configs", permissions.read_camera_configs),
         ("update_signals", permissions.update_signals),
     ] {
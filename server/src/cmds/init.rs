@@ -2,9 +2,10 @@
 // Copyright (C) 2020 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
-use base::Error;
+use base::{bail, clock, err, Error};
 use bpaf::Bpaf;
 use std::path::PathBuf;
+use std::sync::Arc;
 use tracing::info;
 
 /// Initializes a database.
@@ -13,15 +14,55 @@ use tracing::info;
 pub struct Args {
     #[bpaf(external(crate::parse_db_dir))]
     db_dir: PathBuf,
+
+    /// Encrypts the new database with SQLCipher, using the key in `MOONFIRE_DB_KEY`.
+    /// The same key must be supplied to every later command that opens the database.
+    /// Requires building with the `sqlcipher` feature.
+    encrypt: bool,
+
+    /// Provisions the new database from a configuration file in the format
+    /// written by `moonfire-nvr config export`, creating its sample file
+    /// directories, cameras, and users. Ignored if the database already exists.
+    #[bpaf(argument("PATH"))]
+    seed: Option<PathBuf>,
 }
 
 pub fn run(args: Args) -> Result<i32, Error> {
+    match (args.encrypt, super::db_key()?.is_some()) {
+        (true, false) => bail!(
+            InvalidArgument,
+            msg(
+                "--encrypt requires {} or {}",
+                super::DB_KEY_VAR,
+                super::DB_KEY_FILE_VAR
+            )
+        ),
+        (false, true) => bail!(
+            InvalidArgument,
+            msg(
+                "{} or {} is set; pass --encrypt to create an encrypted database",
+                super::DB_KEY_VAR,
+                super::DB_KEY_FILE_VAR
+            )
+        ),
+        _ => {}
+    }
+
+    // Read the seed first, so a mistake in it doesn't leave behind an unseeded database.
+    let seed = args
+        .seed
+        .as_deref()
+        .map(super::config::Seed::read)
+        .transpose()?;
     let (_db_dir, mut conn) = super::open_conn(&args.db_dir, super::OpenMode::Create)?;
 
     // Check if the database has already been initialized.
     let cur_ver = db::get_schema_version(&conn)?;
     if let Some(v) = cur_ver {
         info!("Database is already initialized with schema version {}.", v);
+        if seed.is_some() {
+            info!("Leaving existing database's configuration alone; ignoring --seed.");
+        }
         return Ok(0);
     }
 
@@ -29,15 +70,32 @@ pub fn run(args: Args) -> Result<i32, Error> {
     // page size (so reading large recording_playback rows doesn't require as many seeks). Changing
     // the page size requires doing a vacuum in non-WAL mode. This will be cheap on an empty
     // database. https://www.sqlite.org/pragma.html#pragma_page_size
-    conn.execute_batch(
-        r#"
-        pragma journal_mode = delete;
-        pragma page_size = 16384;
-        vacuum;
-        pragma journal_mode = wal;
-        "#,
-    )?;
+    //
+    // SQLCipher's page size is instead set via `cipher_page_size`, which would have to be repeated
+    // on every open, so encrypted databases keep the default.
+    //
+    // Incremental auto-vacuum lets the maintenance window return freed pages to the filesystem.
+    // Like the page size, it can only be set cheaply before any tables are created.
+    conn.execute_batch("pragma auto_vacuum = incremental;")?;
+    if args.encrypt {
+        conn.execute_batch("pragma journal_mode = wal;")?;
+    } else {
+        conn.execute_batch(
+            r#"
+            pragma journal_mode = delete;
+            pragma page_size = 16384;
+            vacuum;
+            pragma journal_mode = wal;
+            "#,
+        )?;
+    }
     db::init(&mut conn)?;
     info!("Database initialized.");
+    if let Some(seed) = seed {
+        let db = Arc::new(db::Database::new(clock::RealClocks {}, conn, true)?);
+        seed.apply(&db)
+            .map_err(|e| err!(e, msg("unable to apply seed; fix it with `config apply`")))?;
+        info!("Database seeded.");
+    }
     Ok(0)
 }
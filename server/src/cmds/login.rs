@@ -14,7 +14,7 @@ use std::os::unix::fs::OpenOptionsExt as _;
 use std::path::PathBuf;
 use std::str::FromStr;
 
-fn parse_perms(perms: String) -> Result<crate::json::Permissions, serde_json::Error> {
+pub(super) fn parse_perms(perms: String) -> Result<crate::json::Permissions, serde_json::Error> {
     serde_json::from_str(&perms)
 }
 
@@ -36,7 +36,8 @@ pub struct Args {
     db_dir: PathBuf,
 
     /// Creates a session with the given permissions, as a JSON object.
-    /// E.g. `{"viewVideo": true}`. See `ref/api.md` for a description of `Permissions`.
+    /// E.g. `{"viewLive": true, "downloadRecordings": true}`. See `ref/api.md` for a
+    /// description of `Permissions`.
     /// If unspecified, uses user's default permissions.
     #[bpaf(argument::<String>("PERMS"), parse(parse_perms), optional)]
     permissions: Option<crate::json::Permissions>,
@@ -159,7 +160,7 @@ mod tests {
             .run_inner(bpaf::Args::from(&[
                 "login",
                 "--permissions",
-                "{\"viewVideo\": true}",
+                "{\"viewLive\": true}",
                 "--session-flags",
                 "http-only, same-site",
                 "slamb",
@@ -172,7 +173,7 @@ mod tests {
                 domain: None,
                 curl_cookie_jar: None,
                 permissions: Some(crate::json::Permissions {
-                    view_video: true,
+                    view_live: true,
                     ..Default::default()
                 }),
                 session_flags: vec![SessionFlag::HttpOnly, SessionFlag::SameSite],
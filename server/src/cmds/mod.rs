@@ -8,14 +8,56 @@ use nix::fcntl::FlockArg;
 use std::path::Path;
 use tracing::info;
 
+pub mod bench_ingest;
 pub mod check;
 pub mod config;
 pub mod init;
 pub mod login;
+pub mod rekey;
+pub mod replay;
 pub mod run;
 pub mod sql;
 pub mod ts;
 pub mod upgrade;
+pub mod users;
+
+/// The environment variable holding the key of a SQLCipher-encrypted database.
+const DB_KEY_VAR: &str = "MOONFIRE_DB_KEY";
+
+/// The environment variable naming a file which holds the database key, such as
+/// `/run/secrets/db_key`, as an alternative to [`DB_KEY_VAR`].
+const DB_KEY_FILE_VAR: &str = "MOONFIRE_DB_KEY_FILE";
+
+/// Returns the database key from [`DB_KEY_VAR`] or [`DB_KEY_FILE_VAR`], if set.
+fn db_key() -> Result<Option<String>, Error> {
+    match (std::env::var(DB_KEY_VAR), std::env::var_os(DB_KEY_FILE_VAR)) {
+        (Err(std::env::VarError::NotPresent), None) => Ok(None),
+        (Err(std::env::VarError::NotPresent), Some(path)) => {
+            Ok(Some(run::config::read_secret_file(Path::new(&path))?))
+        }
+        (Ok(v), None) => Ok(Some(v)),
+        (Ok(_), Some(_)) => Err(err!(
+            InvalidArgument,
+            msg("{DB_KEY_VAR} and {DB_KEY_FILE_VAR} are mutually exclusive")
+        )),
+        (Err(e), _) => Err(err!(InvalidArgument, msg("bad {DB_KEY_VAR}"), source(e))),
+    }
+}
+
+/// Sets a SQLCipher key-related pragma (`key` or `rekey`) on the given connection.
+#[cfg(feature = "sqlcipher")]
+fn set_key_pragma(conn: &rusqlite::Connection, pragma: &str, key: &str) -> Result<(), Error> {
+    conn.pragma_update(None, pragma, key)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlcipher"))]
+fn set_key_pragma(_conn: &rusqlite::Connection, _pragma: &str, _key: &str) -> Result<(), Error> {
+    base::bail!(
+        Unimplemented,
+        msg("database encryption requires building with the sqlcipher feature; unset {DB_KEY_VAR}")
+    );
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum OpenMode {
@@ -84,18 +126,46 @@ fn open_conn(db_dir: &Path, mode: OpenMode) -> Result<(dir::Fd, rusqlite::Connec
         // `rusqlite::Connection` is not Sync, so there's no reason to tell SQLite3 to use the
         // serialized threading mode.
         rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        // In read/write mode, Moonfire holds a directory lock for its entire operation, as
-        // described above. There's then no point in SQLite releasing its lock after each
-        // transaction and reacquiring it, or in using shared memory for the wal-index.
+        // Moonfire holds a directory lock for its entire operation, as described above, so other
+        // processes never share the database. When creating it, there's then no point in SQLite
+        // releasing its lock after each transaction and reacquiring it, or in using shared memory
+        // for the wal-index. In read/write mode, though, `open_reader_conn` connections must see
+        // this connection's commits, which requires the shared-memory wal-index.
         // See the following page: <https://www.sqlite.org/vfs.html>
         match mode {
-            OpenMode::ReadOnly => "unix",
-            _ => "unix-excl",
+            OpenMode::Create => "unix-excl",
+            _ => "unix",
         },
     )?;
+    check_key(&conn)?;
     Ok((dir, conn))
 }
 
+/// Opens an additional read-only connection for use with `db::Database::add_reader`.
+///
+/// The caller must hold the directory lock returned by `open_conn` for the connection's lifetime.
+fn open_reader_conn(db_dir: &Path) -> Result<rusqlite::Connection, Error> {
+    let conn = rusqlite::Connection::open_with_flags_and_vfs(
+        db_dir.join("db"),
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        "unix",
+    )?;
+    check_key(&conn)?;
+    Ok(conn)
+}
+
+/// Sets the SQLCipher key on a freshly opened connection, if one is configured.
+fn check_key(conn: &rusqlite::Connection) -> Result<(), Error> {
+    if let Some(key) = db_key()? {
+        // The key must be set before any other statement. SQLCipher only checks it on first
+        // access, so do that now for a clear error message.
+        set_key_pragma(conn, "key", &key)?;
+        conn.query_row("select count(*) from sqlite_master", [], |_| Ok(()))
+            .map_err(|e| err!(e, msg("unable to read database; is {DB_KEY_VAR} correct?")))?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,84 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Subcommand to change the key of a SQLCipher-encrypted database.
+
+use base::{bail, Error};
+use bpaf::Bpaf;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Changes the key of an encrypted database.
+///
+/// The current key is taken from `MOONFIRE_DB_KEY` or `MOONFIRE_DB_KEY_FILE` as with other
+/// subcommands. Afterward, that variable must be updated to the new key. This can't encrypt or
+/// decrypt an existing database; use `moonfire-nvr init --encrypt` for a new database instead.
+#[derive(Bpaf, Debug, PartialEq, Eq)]
+#[bpaf(command("rekey"))]
+pub struct Args {
+    #[bpaf(external(crate::parse_db_dir))]
+    db_dir: PathBuf,
+
+    /// A file holding the new key, minus any trailing newline, such as
+    /// `/run/secrets/new_db_key`.
+    #[bpaf(argument("PATH"))]
+    new_key_file: PathBuf,
+}
+
+pub fn run(args: Args) -> Result<i32, Error> {
+    if super::db_key()?.is_none() {
+        bail!(
+            FailedPrecondition,
+            msg(
+                "{} and {} are unset; only an encrypted database can be rekeyed",
+                super::DB_KEY_VAR,
+                super::DB_KEY_FILE_VAR
+            )
+        );
+    }
+    let new_key = super::run::config::read_secret_file(&args.new_key_file)?;
+    if new_key.is_empty() {
+        bail!(InvalidArgument, msg("new key must be non-empty"));
+    }
+    let (_db_dir, conn) = super::open_conn(&args.db_dir, super::OpenMode::ReadWrite)?;
+
+    // SQLCipher rewrites every page on rekey; do so outside WAL mode so the result is a single
+    // consistent file.
+    conn.execute_batch("pragma journal_mode = delete;")?;
+    super::set_key_pragma(&conn, "rekey", &new_key)?;
+    conn.execute_batch("pragma journal_mode = wal;")?;
+    info!(
+        "Database rekeyed; update {} or {} to match.",
+        super::DB_KEY_VAR,
+        super::DB_KEY_FILE_VAR
+    );
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpaf::Parser;
+
+    #[test]
+    fn parse_args() {
+        let args = args()
+            .to_options()
+            .run_inner(bpaf::Args::from(&[
+                "rekey",
+                "--db-dir",
+                "/foo/bar",
+                "--new-key-file",
+                "/run/secrets/new_db_key",
+            ]))
+            .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                db_dir: "/foo/bar".into(),
+                new_key_file: "/run/secrets/new_db_key".into(),
+            }
+        );
+    }
+}
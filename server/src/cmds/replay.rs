@@ -0,0 +1,105 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Subcommand to replay an RTSP session captured with a stream's `rtspCapture` config.
+
+use crate::rtsp_capture;
+use crate::stream;
+use base::Error;
+use bpaf::Bpaf;
+use std::path::PathBuf;
+
+/// Replays a captured RTSP session through the normal stream parsing code.
+///
+/// This reproduces problems with a camera's output without access to the camera. It doesn't
+/// touch the database.
+#[derive(Bpaf, Debug)]
+#[bpaf(command("replay"))]
+pub struct Args {
+    /// Prints a line for each frame.
+    frames: bool,
+
+    /// The `.rtspdump` file to replay.
+    #[bpaf(positional("DUMP"))]
+    dump: PathBuf,
+}
+
+pub fn run(args: Args) -> Result<i32, Error> {
+    let records = rtsp_capture::read_dump(&args.dump)?;
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let url = rt.block_on(rtsp_capture::serve(records))?;
+    let guard = rt.enter();
+    let r = replay(&args, url);
+    drop(guard);
+    rt.shutdown_background();
+    r
+}
+
+fn replay(args: &Args, url: url::Url) -> Result<i32, Error> {
+    let mut stream = stream::OPENER.open(
+        "replay".to_owned(),
+        url,
+        stream::Options {
+            // The replay server doesn't check credentials, but the client must have some to
+            // answer any authentication challenge in the dump.
+            session: retina::client::SessionOptions::default().creds(Some(
+                retina::client::Credentials {
+                    username: "replay".to_owned(),
+                    password: "replay".to_owned(),
+                },
+            )),
+            setup: retina::client::SetupOptions::default()
+                .transport("tcp".parse().expect("tcp should be a valid transport")),
+            play: retina::client::PlayOptions::default(),
+            audio_setup: None,
+            capture: None,
+            bind: None,
+        },
+    )?;
+    let entry = stream.video_sample_entry();
+    println!(
+        "video: {}x{} {} ({} byte sample entry)",
+        entry.width,
+        entry.height,
+        entry.rfc6381_codec,
+        entry.data.len()
+    );
+    let (mut frames, mut key_frames, mut bytes, mut loss) = (0u64, 0u64, 0u64, 0u64);
+    let err = loop {
+        let f = match stream.next() {
+            Ok(f) => f,
+            Err(e) => break e,
+        };
+        frames += 1;
+        key_frames += u64::from(f.is_key);
+        bytes += f.data.len() as u64;
+        loss += u64::from(f.loss);
+        if args.frames {
+            println!(
+                "pts={} bytes={}{}{}{}",
+                f.pts,
+                f.data.len(),
+                if f.is_key { " key" } else { "" },
+                if f.new_video_sample_entry {
+                    " new-parameters"
+                } else {
+                    ""
+                },
+                if f.loss > 0 {
+                    format!(" lost={}", f.loss)
+                } else {
+                    String::new()
+                },
+            );
+        }
+    };
+    println!(
+        "{frames} frames ({key_frames} key), {bytes} bytes, {loss} RTP packets lost\n\
+         stopped: {}",
+        err.chain()
+    );
+    Ok(0)
+}
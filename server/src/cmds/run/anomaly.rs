@@ -0,0 +1,222 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Detection of degraded camera output: a frame rate or key frame interval outside the stream's
+//! configured limits (`minFps` and `maxKeyFrameIntervalSec` in [`db::json::StreamConfig`]).
+//!
+//! Cameras sometimes silently drop to 1 fps or stretch their key frame interval, e.g. after a
+//! firmware update or a "smart codec" mode kicks in. Recording continues normally, so without
+//! this nobody notices until the footage is needed.
+
+use std::collections::VecDeque;
+
+use db::recording::TIME_UNITS_PER_SEC;
+
+/// The span of frames over which the frame rate is measured, in 90 kHz units. This is also how
+/// long a degraded stream must be within limits before it's considered recovered.
+const WINDOW_90K: i64 = 30 * TIME_UNITS_PER_SEC;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Change {
+    /// The stream went out of limits, for the given human-readable reason.
+    Degraded(String),
+
+    /// The stream has been within limits for a full window since being degraded.
+    Recovered,
+}
+
+/// Tracks a single stream's frame timing. See the module documentation.
+pub struct Detector {
+    min_fps: Option<u32>,
+    max_key_frame_interval_90k: Option<i64>,
+
+    /// Timestamps of frames within the last [`WINDOW_90K`] of the current session.
+    frames: VecDeque<i64>,
+
+    /// The first timestamp of the current session.
+    start: Option<i64>,
+    last_key: Option<i64>,
+
+    /// The interval between the last two key frames, if known.
+    last_key_frame_interval_90k: Option<i64>,
+
+    degraded: bool,
+
+    /// While degraded, the start of the current run of frames within limits.
+    healthy_since: Option<i64>,
+}
+
+impl Detector {
+    pub fn new(config: &db::json::StreamConfig) -> Self {
+        Detector {
+            min_fps: config.min_fps.filter(|&f| f > 0),
+            max_key_frame_interval_90k: config
+                .max_key_frame_interval_sec
+                .filter(|&s| s > 0)
+                .map(|s| i64::from(s) * TIME_UNITS_PER_SEC),
+            frames: VecDeque::new(),
+            start: None,
+            last_key: None,
+            last_key_frame_interval_90k: None,
+            degraded: false,
+            healthy_since: None,
+        }
+    }
+
+    /// Returns true if any limit is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.min_fps.is_some() || self.max_key_frame_interval_90k.is_some()
+    }
+
+    /// Forgets the current session's frames, as timestamps restart on reconnection. Whether the
+    /// stream is degraded is retained.
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.start = None;
+        self.last_key = None;
+        self.last_key_frame_interval_90k = None;
+        self.healthy_since = None;
+    }
+
+    /// Notes a frame with the given timestamp (in 90 kHz units), returning any change in status.
+    pub fn frame(&mut self, pts: i64, is_key: bool) -> Option<Change> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let start = *self.start.get_or_insert(pts);
+        self.frames.push_back(pts);
+        while self.frames.front().is_some_and(|&f| pts - f > WINDOW_90K) {
+            self.frames.pop_front();
+        }
+        if is_key {
+            if let Some(last) = self.last_key {
+                self.last_key_frame_interval_90k = Some(pts - last);
+            }
+            self.last_key = Some(pts);
+        }
+
+        match self.problem(pts, start) {
+            Some(reason) => {
+                self.healthy_since = None;
+                if self.degraded {
+                    return None;
+                }
+                self.degraded = true;
+                Some(Change::Degraded(reason))
+            }
+            None if self.degraded => {
+                let since = *self.healthy_since.get_or_insert(pts);
+                if pts - since < WINDOW_90K {
+                    return None;
+                }
+                self.degraded = false;
+                self.healthy_since = None;
+                Some(Change::Recovered)
+            }
+            None => None,
+        }
+    }
+
+    fn problem(&self, pts: i64, start: i64) -> Option<String> {
+        if let Some(max) = self.max_key_frame_interval_90k {
+            // Also consider the current, unfinished interval, so a camera which stops sending
+            // key frames entirely is noticed.
+            let current = self.last_key.map(|k| pts - k);
+            let interval = std::cmp::max(self.last_key_frame_interval_90k, current);
+            if let Some(i) = interval.filter(|&i| i > max) {
+                return Some(format!(
+                    "key frames {:.1} s apart; expected at most {} s",
+                    i as f64 / TIME_UNITS_PER_SEC as f64,
+                    max / TIME_UNITS_PER_SEC,
+                ));
+            }
+        }
+        if let Some(min) = self.min_fps {
+            // Only judge the frame rate once a full window has been seen.
+            let (first, last) = (*self.frames.front()?, *self.frames.back()?);
+            if pts - start >= WINDOW_90K && last > first {
+                let fps = (self.frames.len() - 1) as f64 * TIME_UNITS_PER_SEC as f64
+                    / (last - first) as f64;
+                if fps < f64::from(min) {
+                    return Some(format!(
+                        "{fps:.1} fps over the last {} s; expected at least {min}",
+                        WINDOW_90K / TIME_UNITS_PER_SEC,
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: i64 = TIME_UNITS_PER_SEC;
+
+    fn detector(min_fps: Option<u32>, max_key_frame_interval_sec: Option<u32>) -> Detector {
+        Detector::new(&db::json::StreamConfig {
+            min_fps,
+            max_key_frame_interval_sec,
+            ..Default::default()
+        })
+    }
+
+    /// Feeds frames at `fps` with a key frame every `gop` frames from `*pts` for `secs`, returning
+    /// all changes.
+    fn feed(d: &mut Detector, pts: &mut i64, fps: i64, gop: i64, secs: i64) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for i in 0..fps * secs {
+            changes.extend(d.frame(*pts, i % gop == 0));
+            *pts += SEC / fps;
+        }
+        changes
+    }
+
+    #[test]
+    fn disabled() {
+        let mut d = detector(None, Some(0));
+        assert!(!d.is_enabled());
+        let mut pts = 0;
+        assert!(feed(&mut d, &mut pts, 1, 100, 120).is_empty());
+    }
+
+    #[test]
+    fn frame_rate() {
+        let mut d = detector(Some(5), None);
+        let mut pts = 0;
+        assert!(feed(&mut d, &mut pts, 10, 10, 60).is_empty());
+        let changes = feed(&mut d, &mut pts, 1, 1, 60);
+        assert_eq!(changes.len(), 1);
+        assert!(
+            matches!(&changes[0], Change::Degraded(r) if r.contains("expected at least 5")),
+            "{changes:?}"
+        );
+
+        // Recovery needs a full window within limits.
+        assert!(feed(&mut d, &mut pts, 10, 10, 29).is_empty());
+        assert_eq!(feed(&mut d, &mut pts, 10, 10, 60), [Change::Recovered]);
+    }
+
+    #[test]
+    fn key_frame_interval() {
+        let mut d = detector(None, Some(4));
+        let mut pts = 0;
+        assert!(feed(&mut d, &mut pts, 10, 20, 60).is_empty());
+
+        // Noticed before the next key frame arrives.
+        let changes = feed(&mut d, &mut pts, 10, 100, 5);
+        assert_eq!(changes.len(), 1);
+        assert!(
+            matches!(&changes[0], Change::Degraded(r) if r.contains("expected at most 4 s")),
+            "{changes:?}"
+        );
+
+        // A reconnection doesn't itself count as recovery.
+        d.reset();
+        assert!(feed(&mut d, &mut pts, 10, 20, 29).is_empty());
+        assert_eq!(feed(&mut d, &mut pts, 10, 20, 10), [Change::Recovered]);
+    }
+}
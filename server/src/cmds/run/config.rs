@@ -5,8 +5,9 @@
 //! Runtime configuration file (`/etc/moonfire-nvr.toml`).
 //! See `ref/config.md` for more description.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use base::{bail, err, Error};
 use serde::Deserialize;
 
 use crate::json::Permissions;
@@ -15,6 +16,10 @@ fn default_db_dir() -> PathBuf {
     crate::DEFAULT_DB_DIR.into()
 }
 
+fn default_reader_threads() -> usize {
+    1
+}
+
 /// Top-level configuration file object.
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -33,11 +38,352 @@ pub struct ConfigFile {
     #[cfg_attr(feature = "bundled-ui", serde(default))]
     pub ui_dir: UiDir,
 
+    /// Serves the UI's `index.html` for unknown paths outside `/api/` which look like UI routes
+    /// (no file extension), so deep links work when the UI uses path-based routing.
+    #[serde(default)]
+    pub ui_spa_fallback: bool,
+
     /// The number of worker threads used by the asynchronous runtime.
     ///
     /// Defaults to the number of cores on the system.
     #[serde(default)]
     pub worker_threads: Option<usize>,
+
+    /// Per-directory settings for sample file directories.
+    ///
+    /// Directories not listed here use the defaults.
+    #[serde(default)]
+    pub sample_file_dirs: Vec<SampleFileDirConfig>,
+
+    /// The key which encrypts camera credentials in the database, as a base64-encoded 32-byte
+    /// value.
+    #[serde(default)]
+    pub credential_key: Option<Secret>,
+
+    /// The master key which encrypts new sample files, as a base64-encoded 32-byte value.
+    #[serde(default)]
+    pub sample_file_key: Option<Secret>,
+
+    /// The Ed25519 key which signs export manifests, as a base64-encoded 32-byte private key
+    /// seed.
+    ///
+    /// default: manifests are unsigned.
+    #[serde(default)]
+    pub export_signing_key: Option<Secret>,
+
+    /// A daily window in which to perform database maintenance.
+    ///
+    /// default: no maintenance.
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceConfig>,
+
+    /// Upgrades an older database schema on startup, after backing it up.
+    #[serde(default)]
+    pub auto_upgrade: bool,
+
+    /// Loads streams' recording totals in the background after startup rather than before
+    /// serving and recording.
+    #[serde(default)]
+    pub lazy_load: bool,
+
+    /// Session cookie settings.
+    #[serde(default)]
+    pub session: SessionConfig,
+
+    /// Path of a Unix-domain socket on which to serve the JSON-RPC control protocol.
+    ///
+    /// default: none.
+    #[serde(default)]
+    pub control_socket: Option<PathBuf>,
+
+    /// Channels to notify of camera and motion events.
+    ///
+    /// default: none.
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+
+    /// Where to archive camera snapshots taken when signals report motion.
+    ///
+    /// default: no snapshots.
+    #[serde(default)]
+    pub snapshots: Option<SnapshotsConfig>,
+
+    /// The delay between starting successive streams, in milliseconds, so many cameras don't all
+    /// connect at once after a power outage.
+    ///
+    /// default: 0.
+    #[serde(default)]
+    pub stream_start_interval_ms: u64,
+
+    /// A limit on memory used for buffering, above which live viewers and new sample file
+    /// reads are shed. See [`base::mem`].
+    ///
+    /// default: unlimited.
+    #[serde(default)]
+    pub memory_budget_bytes: Option<u64>,
+}
+
+fn default_maintenance_duration_minutes() -> u32 {
+    60
+}
+
+fn default_vacuum_step_pages() -> u32 {
+    1024
+}
+
+fn default_media_max_age_sec() -> u32 {
+    3600
+}
+
+fn default_max_body_bytes() -> u32 {
+    1 << 20
+}
+
+fn default_request_timeout_sec() -> u32 {
+    60
+}
+
+fn default_notifier_events() -> Vec<super::notify::EventType> {
+    super::notify::EventType::ALL.to_vec()
+}
+
+fn default_max_per_hour() -> u32 {
+    20
+}
+
+fn default_snapshot_max_bytes() -> u64 {
+    1 << 30
+}
+
+fn default_smtp_server() -> String {
+    "localhost:25".to_owned()
+}
+
+/// A daily window for database maintenance: `PRAGMA incremental_vacuum`, `ANALYZE`, and a WAL
+/// checkpoint.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceConfig {
+    /// The local time at which the window starts, as `HH:MM`.
+    pub start: TimeOfDay,
+
+    /// The length of the window.
+    ///
+    /// default: 60.
+    #[serde(default = "default_maintenance_duration_minutes")]
+    pub duration_minutes: u32,
+
+    /// The number of pages to free in each incremental vacuum step. The database lock is
+    /// released between steps.
+    ///
+    /// default: 1024.
+    #[serde(default = "default_vacuum_step_pages")]
+    pub vacuum_step_pages: u32,
+}
+
+/// Attributes of the session cookie set on login.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionConfig {
+    /// The cookie's `SameSite` attribute.
+    ///
+    /// default: `lax`.
+    #[serde(default)]
+    pub same_site: SameSite,
+
+    /// When to set the cookie's `Secure` attribute.
+    ///
+    /// default: `auto`.
+    #[serde(default)]
+    pub secure: CookieSecure,
+
+    /// How long a session lasts, in seconds. This sets the cookie's `Max-Age` and is also
+    /// enforced by the server.
+    ///
+    /// default: sessions don't expire.
+    #[serde(default)]
+    pub expiry_sec: Option<u32>,
+
+    /// Measures `expiry_sec` from a session's last use rather than its creation, renewing the
+    /// cookie on each authenticated response.
+    #[serde(default)]
+    pub sliding_renewal: bool,
+
+    /// How long to keep revoked and expired sessions in the database after their last
+    /// activity, for auditing. Older ones are purged hourly.
+    ///
+    /// default: 30 days.
+    #[serde(default)]
+    pub retention_sec: Option<u32>,
+}
+
+impl SessionConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.same_site == SameSite::None && self.secure == CookieSecure::Never {
+            bail!(
+                InvalidArgument,
+                msg("session sameSite = \"none\" requires secure = \"always\" or \"auto\"")
+            );
+        }
+        if self.sliding_renewal && self.expiry_sec.is_none() {
+            bail!(
+                InvalidArgument,
+                msg("session slidingRenewal requires expirySec")
+            );
+        }
+        Ok(())
+    }
+
+    pub fn retention_sec(&self) -> i64 {
+        i64::from(self.retention_sec.unwrap_or(DEFAULT_SESSION_RETENTION_SEC))
+    }
+}
+
+/// The default for [`SessionConfig::retention_sec`].
+const DEFAULT_SESSION_RETENTION_SEC: u32 = 30 * 24 * 60 * 60;
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SameSite {
+    /// Sent on top-level navigations from other sites but not on their subrequests.
+    #[default]
+    Lax,
+
+    /// Sent only on requests from the same site. Note some browsers don't send `Strict` cookies
+    /// on WebSocket upgrade requests, which breaks live view.
+    Strict,
+
+    /// Sent on all requests, including from other sites. Requires `Secure`.
+    None,
+}
+
+/// When to set a cookie's `Secure` attribute.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieSecure {
+    /// When the login request came over `https`, as indicated by a trusted `X-Forwarded-Proto`
+    /// header.
+    #[default]
+    Auto,
+
+    Always,
+    Never,
+}
+
+/// A local time of day, deserialized from `HH:MM`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    /// Minutes since midnight.
+    pub minutes: u32,
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parsed = value
+            .split_once(':')
+            .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+            .filter(|&(h, m)| h < 24 && m < 60 && value.len() == 5);
+        match parsed {
+            Some((h, m)) => Ok(TimeOfDay {
+                minutes: h * 60 + m,
+            }),
+            None => Err(format!("invalid time of day {value:?}; expected HH:MM")),
+        }
+    }
+}
+
+/// Per-sample file directory configuration.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleFileDirConfig {
+    /// The directory's path, exactly as it was specified to `moonfire-nvr config`.
+    pub path: PathBuf,
+
+    /// The number of threads which read sample files from this directory.
+    ///
+    /// default: 1.
+    #[serde(default = "default_reader_threads")]
+    pub reader_threads: usize,
+
+    /// The `nice` value of this directory's reader threads (Linux-only).
+    #[serde(default)]
+    pub nice: Option<i32>,
+
+    /// The I/O scheduling class and priority of this directory's reader threads (Linux-only).
+    #[serde(default)]
+    pub io_priority: Option<IoPriorityConfig>,
+
+    /// The maximum number of queued reader commands before new playback requests are rejected.
+    ///
+    /// default: unlimited.
+    #[serde(default)]
+    pub max_reader_queue_depth: Option<usize>,
+
+    /// The length of the first read from each opened sample file.
+    ///
+    /// default: 64 KiB.
+    #[serde(default)]
+    pub reader_chunk_bytes: Option<usize>,
+
+    /// The length sequential reads of a sample file grow to.
+    ///
+    /// default: 1 MiB.
+    #[serde(default)]
+    pub reader_max_chunk_bytes: Option<usize>,
+
+    /// Whether to ask the kernel to read ahead the next chunk of a sample file.
+    ///
+    /// default: true.
+    #[serde(default)]
+    pub reader_read_ahead: Option<bool>,
+
+    /// The free space to keep on the directory's filesystem, deleting its oldest recordings
+    /// beyond what streams' retention limits require if necessary.
+    ///
+    /// default: 0 (no floor).
+    #[serde(default)]
+    pub min_free_bytes: i64,
+}
+
+impl SampleFileDirConfig {
+    pub fn reader_config(&self) -> db::dir::ReaderConfig {
+        let default = db::dir::ReaderConfig::default();
+        db::dir::ReaderConfig {
+            threads: self.reader_threads,
+            nice: self.nice,
+            io_priority: self.io_priority.map(|p| match p {
+                IoPriorityConfig::Realtime(l) => db::dir::IoPriority::Realtime(l),
+                IoPriorityConfig::BestEffort(l) => db::dir::IoPriority::BestEffort(l),
+                IoPriorityConfig::Idle => db::dir::IoPriority::Idle,
+            }),
+            max_queue_depth: self.max_reader_queue_depth,
+            chunk_len: self.reader_chunk_bytes.unwrap_or(default.chunk_len),
+            max_chunk_len: self.reader_max_chunk_bytes.unwrap_or(default.max_chunk_len),
+            read_ahead: self.reader_read_ahead.unwrap_or(default.read_ahead),
+        }
+    }
+}
+
+/// An I/O scheduling class and priority, as in `ionice(1)`.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub enum IoPriorityConfig {
+    /// Realtime class with a level from 0 (highest) to 7 (lowest). Requires privileges.
+    Realtime(u8),
+
+    /// Best-effort class with a level from 0 (highest) to 7 (lowest).
+    BestEffort(u8),
+
+    /// Idle class: only does I/O when no other process has asked for disk time.
+    Idle,
 }
 
 #[derive(Debug, Deserialize)]
@@ -97,6 +443,239 @@ pub struct BindConfig {
     /// effective UID as privileged.
     #[serde(default)]
     pub own_uid_is_privileged: bool,
+
+    /// Cross-Origin Resource Sharing settings, allowing web apps served from other origins to
+    /// use the API.
+    ///
+    /// default: no cross-origin access.
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+
+    /// Also accepts cleartext HTTP/2 ("h2c" with prior knowledge), as from a proxy server which
+    /// terminates TLS. HTTP/1.1 clients are still accepted.
+    #[serde(default)]
+    pub http2: bool,
+
+    /// `Cache-Control` settings for responses on this bind.
+    #[serde(default)]
+    pub cache: CacheConfig,
+
+    /// Socket options for a TCP bind.
+    #[serde(default)]
+    pub socket: SocketConfig,
+
+    /// Limits on requests to this bind.
+    #[serde(default)]
+    pub requests: RequestsConfig,
+
+    /// The parts of the API to serve on this bind; requests for others get `404 Not Found`.
+    ///
+    /// default: all.
+    #[serde(default)]
+    pub areas: Option<Vec<ApiArea>>,
+}
+
+/// A part of the API which a bind may serve; see [`BindConfig::areas`].
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ApiArea {
+    /// The web UI's static files.
+    Ui,
+
+    /// Logging in and out, and `/api/request`.
+    Session,
+
+    /// Listing cameras, streams, recordings, runs, and server events.
+    List,
+
+    /// Recorded video: `view.mp4`, `view.m4s`, `replay.mp4`, and init segments.
+    View,
+
+    /// Live video and stream snapshots.
+    Live,
+
+    /// Signals and their snapshots.
+    Signals,
+
+    /// User management, stream moves, and RTSP session details.
+    Admin,
+
+    /// Health checks, metrics, and the OpenAPI document.
+    Monitoring,
+}
+
+/// Limits on requests, protecting the server from misbehaving clients.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestsConfig {
+    /// The largest request body to accept, such as the JSON of a `POST /api/users`.
+    ///
+    /// default: 1 MiB.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: u32,
+
+    /// The longest a request may take from receiving its headers to sending the response's
+    /// headers, including reading the request body. Streaming the response body (such as
+    /// video) isn't limited, nor are stream moves, which may wait on the streamer. 0 means no
+    /// limit.
+    ///
+    /// default: 60.
+    #[serde(default = "default_request_timeout_sec")]
+    pub timeout_sec: u32,
+}
+
+impl Default for RequestsConfig {
+    fn default() -> Self {
+        RequestsConfig {
+            max_body_bytes: default_max_body_bytes(),
+            timeout_sec: default_request_timeout_sec(),
+        }
+    }
+}
+
+/// Socket options for a TCP bind. Unset options keep the operating system's defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketConfig {
+    /// For an IPv6 bind, whether to accept only IPv6 connections (`true`) or also IPv4 ones
+    /// (`false`, "dual-stack").
+    #[serde(default)]
+    pub v6only: Option<bool>,
+
+    /// Enables TCP keepalive on accepted connections, probing after this many idle seconds.
+    #[serde(default)]
+    pub tcp_keepalive_sec: Option<u32>,
+
+    /// The maximum length of the queue of pending connections.
+    ///
+    /// default: 128.
+    #[serde(default)]
+    pub backlog: Option<u32>,
+
+    /// `SO_RCVBUF`, in bytes.
+    #[serde(default)]
+    pub recv_buffer_bytes: Option<u32>,
+
+    /// `SO_SNDBUF`, in bytes.
+    #[serde(default)]
+    pub send_buffer_bytes: Option<u32>,
+}
+
+impl SocketConfig {
+    fn is_empty(&self) -> bool {
+        self.v6only.is_none()
+            && self.tcp_keepalive_sec.is_none()
+            && self.backlog.is_none()
+            && self.recv_buffer_bytes.is_none()
+            && self.send_buffer_bytes.is_none()
+    }
+}
+
+/// `Cache-Control` settings for a bind.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheConfig {
+    /// The `max-age` of responses which rarely change for a given URL, such as segments of
+    /// completed recordings via `/view.mp4`, in seconds.
+    ///
+    /// default: 3600.
+    #[serde(default = "default_media_max_age_sec")]
+    pub media_max_age_sec: u32,
+
+    /// The `stale-while-revalidate` window of the same responses, in seconds.
+    ///
+    /// default: none.
+    #[serde(default)]
+    pub media_stale_while_revalidate_sec: Option<u32>,
+
+    /// The `stale-while-revalidate` window of responses which may change from request to
+    /// request, such as the API's JSON, in seconds. Without this, they're marked `no-cache`.
+    ///
+    /// default: none.
+    #[serde(default)]
+    pub dynamic_stale_while_revalidate_sec: Option<u32>,
+
+    /// Marks the responses covered by `media_max_age_sec` as `public` rather than `private`,
+    /// so that shared caches such as a CDN or proxy server may store them. This requires
+    /// `allow_unauthenticated_permissions` to include `download_recordings`; otherwise a shared
+    /// cache could serve video to clients which aren't allowed to see it.
+    #[serde(default)]
+    pub public_media: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            media_max_age_sec: default_media_max_age_sec(),
+            media_stale_while_revalidate_sec: None,
+            dynamic_stale_while_revalidate_sec: None,
+            public_media: false,
+        }
+    }
+}
+
+impl BindConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(c) = &self.cors {
+            c.validate()?;
+        }
+        if self.cache.public_media
+            && !self
+                .allow_unauthenticated_permissions
+                .as_ref()
+                .is_some_and(|p| p.download_recordings)
+        {
+            bail!(
+                InvalidArgument,
+                msg(
+                    "cache publicMedia requires allowUnauthenticatedPermissions with \
+                     downloadRecordings"
+                )
+            );
+        }
+        match self.address {
+            AddressConfig::Ipv6(_) => {}
+            AddressConfig::Ipv4(_) if self.socket.v6only.is_none() => {}
+            _ if self.socket.is_empty() => {}
+            AddressConfig::Ipv4(_) => {
+                bail!(InvalidArgument, msg("socket v6only requires an ipv6 bind"))
+            }
+            _ => bail!(
+                InvalidArgument,
+                msg("socket options are only supported on ipv4 and ipv6 binds")
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Cross-Origin Resource Sharing settings for a bind.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct CorsConfig {
+    /// Origins such as `https://dashboard.example.com` which may make requests, or `*` for any.
+    pub allowed_origins: Vec<String>,
+
+    /// Allows requests with credentials (the session cookie). The cookie must also be sent
+    /// cross-site; see [`SessionConfig::same_site`].
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.allow_credentials && self.allowed_origins.iter().any(|o| o == "*") {
+            bail!(
+                InvalidArgument,
+                msg("cors allowCredentials requires listing specific allowedOrigins, not \"*\"")
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -129,3 +708,295 @@ impl std::fmt::Display for AddressConfig {
         }
     }
 }
+
+/// Archive of snapshots taken on signal rising edges.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotsConfig {
+    /// The directory in which to store snapshots, which will be created if necessary. This
+    /// should not be a sample file directory.
+    pub dir: PathBuf,
+
+    /// The total size of snapshots to keep; the oldest are deleted beyond this.
+    ///
+    /// default: 1 GiB.
+    #[serde(default = "default_snapshot_max_bytes")]
+    pub max_bytes: u64,
+}
+
+/// A channel to notify of events, such as an email address.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct NotifierConfig {
+    /// Where to send notifications.
+    #[serde(flatten)]
+    pub channel: ChannelConfig,
+
+    /// The types of events to send.
+    ///
+    /// default: all of them.
+    #[serde(default = "default_notifier_events")]
+    pub events: Vec<super::notify::EventType>,
+
+    /// The maximum number of notifications to send in any hour; further events are logged and
+    /// dropped.
+    ///
+    /// default: 20.
+    #[serde(default = "default_max_per_hour")]
+    pub max_per_hour: u32,
+
+    /// After sending an event, the time during which further events of the same type for the
+    /// same camera are held back, in seconds. At its end, a single digest of the held-back
+    /// events is sent, which starts another cooldown.
+    ///
+    /// default: 0, meaning no cooldown.
+    #[serde(default)]
+    pub cooldown_sec: u32,
+
+    /// The message text, with placeholders `{summary}`, `{camera}`, `{event}`, `{time}`,
+    /// `{detail}`, and `{link}`. Lines whose placeholders all expand to nothing are omitted.
+    ///
+    /// default: [`super::notify::DEFAULT_TEMPLATE`].
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// The externally reachable base URL of this server, such as `https://nvr.example.com`,
+    /// used to fill in the `{link}` placeholder.
+    ///
+    /// default: none; `{link}` expands to nothing.
+    #[serde(default)]
+    pub link_base_url: Option<url::Url>,
+}
+
+impl NotifierConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        match &self.channel {
+            ChannelConfig::Email(e) => e.validate(),
+            ChannelConfig::Telegram(_) | ChannelConfig::Discord(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub enum ChannelConfig {
+    Email(EmailConfig),
+    Telegram(TelegramConfig),
+    Discord(DiscordConfig),
+}
+
+/// Email via an SMTP relay.
+///
+/// The connection is neither encrypted nor authenticated, so this should be a relay on the
+/// local machine or a trusted network, such as Postfix or `msmtpd`, which in turn forwards to
+/// the real mail server.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailConfig {
+    /// The relay's `host:port`.
+    ///
+    /// default: `localhost:25`.
+    #[serde(default = "default_smtp_server")]
+    pub smtp_server: String,
+
+    /// The envelope and header sender, such as `nvr@example.com`.
+    pub from: String,
+
+    /// The recipients.
+    pub to: Vec<String>,
+}
+
+impl EmailConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.to.is_empty() {
+            bail!(
+                InvalidArgument,
+                msg("email notifier must have at least one `to` address")
+            );
+        }
+        for a in std::iter::once(&self.from).chain(self.to.iter()) {
+            if a.is_empty() || a.contains(['\r', '\n', '<', '>']) {
+                bail!(InvalidArgument, msg("invalid email address {a:?}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A chat via the Telegram Bot API.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct TelegramConfig {
+    /// The bot's token as given by `@BotFather`.
+    pub bot_token: Secret,
+
+    /// The chat to send to: a numeric id or `@channelusername`.
+    pub chat_id: String,
+}
+
+/// A Discord channel via an incoming webhook.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscordConfig {
+    /// The webhook URL, which includes its token.
+    pub webhook_url: Secret,
+}
+
+/// A secret such as a key or token.
+///
+/// In the config file, this is either a string holding the secret itself, or a table naming
+/// where to read it: `{ env = "NAME" }` or `{ file = "/run/secrets/NAME" }`. A string is always
+/// taken literally.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Secret {
+    Literal(String),
+    Ref(SecretRef),
+}
+
+/// Where to read a [`Secret`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub enum SecretRef {
+    /// The environment variable of the given name.
+    Env(String),
+
+    /// The file at the given path, such as a Docker secret or systemd credential.
+    File(PathBuf),
+}
+
+impl Secret {
+    /// Returns the password of a camera with the given config, from exactly one of `password`,
+    /// `passwordEnv`, and `passwordFile`.
+    pub fn camera_password(c: &db::json::CameraConfig) -> Result<Self, Error> {
+        Self::from_password_fields(&c.password, &c.password_env, c.password_file.as_deref())
+    }
+
+    /// Returns a password from fields as in [`db::json::CameraConfig`], of which at most one
+    /// may be set.
+    pub fn from_password_fields(
+        password: &str,
+        password_env: &str,
+        password_file: Option<&Path>,
+    ) -> Result<Self, Error> {
+        match (password.is_empty(), password_env.is_empty(), password_file) {
+            (_, true, None) => Ok(Secret::Literal(password.to_owned())),
+            (true, false, None) => Ok(Secret::Ref(SecretRef::Env(password_env.to_owned()))),
+            (true, true, Some(p)) => Ok(Secret::Ref(SecretRef::File(p.to_owned()))),
+            _ => bail!(
+                InvalidArgument,
+                msg("at most one of password, passwordEnv, and passwordFile may be set")
+            ),
+        }
+    }
+
+    /// Returns the secret's value, reading it from its environment variable or file if
+    /// necessary.
+    pub fn resolve(&self) -> Result<String, Error> {
+        match self {
+            Secret::Literal(s) => Ok(s.clone()),
+            Secret::Ref(SecretRef::Env(name)) => std::env::var(name).map_err(|e| {
+                err!(
+                    NotFound,
+                    msg("unable to read environment variable {name:?}"),
+                    source(e)
+                )
+            }),
+            Secret::Ref(SecretRef::File(path)) => read_secret_file(path),
+        }
+    }
+}
+
+/// Reads a secret from the file at `path`, minus any trailing newline, as with Docker secrets
+/// (`/run/secrets/NAME`) or systemd credentials.
+pub fn read_secret_file(path: &Path) -> Result<String, Error> {
+    let mut s = std::fs::read_to_string(path)
+        .map_err(|e| err!(e, msg("unable to read secret file {}", path.display())))?;
+    let len = s.trim_end_matches(['\r', '\n']).len();
+    s.truncate(len);
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret() {
+        #[derive(Deserialize)]
+        struct T {
+            s: Secret,
+        }
+        let resolve = |toml: &str| toml::from_str::<T>(toml).unwrap().s.resolve();
+
+        // Strings which look like references are still taken literally.
+        assert_eq!(resolve("s = \"hunter2\"").unwrap(), "hunter2");
+        assert_eq!(resolve("s = \"${PATH}\"").unwrap(), "${PATH}");
+        assert_eq!(
+            resolve("s = \"file:/etc/passwd\"").unwrap(),
+            "file:/etc/passwd"
+        );
+
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let path = tmpdir.path().join("secret");
+        std::fs::write(&path, "hunter2\n").unwrap();
+        assert_eq!(
+            resolve(&format!(
+                "s = {{ file = {:?} }}",
+                path.display().to_string()
+            ))
+            .unwrap(),
+            "hunter2"
+        );
+        assert!(resolve("s = { file = \"/nonexistent/secret\" }").is_err());
+
+        // PATH is always set; a made-up name shouldn't be.
+        assert_eq!(
+            resolve("s = { env = \"PATH\" }").unwrap(),
+            std::env::var("PATH").unwrap()
+        );
+        assert!(resolve("s = { env = \"MOONFIRE_NVR_TEST_NONEXISTENT\" }").is_err());
+        assert!(toml::from_str::<T>("s = { env = \"A\", file = \"/b\" }").is_err());
+    }
+
+    #[test]
+    fn camera_password() {
+        let p = |password: &str, env: &str, file: Option<&str>| {
+            Secret::from_password_fields(password, env, file.map(Path::new))
+        };
+        assert_eq!(
+            p("${PATH}", "", None).unwrap().resolve().unwrap(),
+            "${PATH}"
+        );
+        assert_eq!(
+            p("", "PATH", None).unwrap().resolve().unwrap(),
+            std::env::var("PATH").unwrap()
+        );
+        assert!(p("", "", Some("/nonexistent/secret"))
+            .unwrap()
+            .resolve()
+            .is_err());
+        p("hunter2", "PATH", None).unwrap_err();
+        p("", "PATH", Some("/run/secrets/password")).unwrap_err();
+    }
+
+    #[test]
+    fn socket_options() {
+        let bind = |s: &str| toml::from_str::<BindConfig>(s).unwrap().validate();
+        bind("ipv6 = \"[::]:8080\"\nsocket = { v6only = false, backlog = 4096 }").unwrap();
+        bind("ipv4 = \"0.0.0.0:8080\"\nsocket = { tcpKeepaliveSec = 60 }").unwrap();
+        bind("ipv4 = \"0.0.0.0:8080\"\nsocket = { v6only = true }").unwrap_err();
+        bind("unix = \"/tmp/sock\"\nsocket = { recvBufferBytes = 65536 }").unwrap_err();
+        bind("unix = \"/tmp/sock\"").unwrap();
+    }
+}
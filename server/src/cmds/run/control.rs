@@ -0,0 +1,512 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! JSON-RPC 2.0 control protocol on a Unix-domain socket, for local tooling.
+//!
+//! Unlike the web API, this has no sessions or permissions: only the server's own user and root
+//! may connect, and they may do anything. Each request and response is a single line of JSON.
+//! See `ref/config.md` for the available methods.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base::{bail, err, Error, ErrorKind};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::json;
+use crate::streamer::PanicLog;
+
+/// The longest accepted request line, in bytes.
+const MAX_REQUEST_LEN: u64 = 1 << 20;
+
+// Error codes defined by JSON-RPC 2.0.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+/// An error reported by a method, in the implementation-defined range.
+const SERVER_ERROR: i32 = -32000;
+
+pub(super) struct Control {
+    pub(super) db: Arc<db::Database>,
+    pub(super) panics: Arc<PanicLog>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+
+    #[serde(default)]
+    params: Option<Value>,
+
+    /// The request id. Notifications, which have none, get no response.
+    #[serde(default)]
+    id: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct ListCamerasParams {
+    /// Includes each camera's and stream's config, as with `cameraConfigs` in the web API.
+    #[serde(default)]
+    configs: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct UpdateCameraParams {
+    id: i32,
+    short_name: Option<String>,
+    description: Option<String>,
+    onvif_base_url: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct UpdateStreamParams {
+    id: i32,
+    record: Option<bool>,
+    retain_bytes: Option<i64>,
+}
+
+/// A method's failure, reported as a JSON-RPC error object.
+struct RpcError {
+    code: i32,
+    message: String,
+    kind: Option<ErrorKind>,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            kind: None,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        RpcError {
+            code: if e.kind() == ErrorKind::InvalidArgument {
+                INVALID_PARAMS
+            } else {
+                SERVER_ERROR
+            },
+            message: e.chain().to_string(),
+            kind: Some(e.kind()),
+        }
+    }
+}
+
+/// Parses `params`, treating absent params as an empty object.
+fn params<T: serde::de::DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    serde_json::from_value(params.unwrap_or_else(|| json!({})))
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid params: {e}")))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => {
+            let mut error = json!({ "code": e.code, "message": e.message });
+            if let Some(k) = e.kind {
+                error["data"] = json!({ "kind": format!("{k:?}") });
+            }
+            json!({ "jsonrpc": "2.0", "id": id, "error": error })
+        }
+    }
+}
+
+impl Control {
+    /// Handles a single request line, returning the response line, if any.
+    async fn handle_line(&self, line: &str) -> Option<Value> {
+        let req = match serde_json::from_str::<Value>(line) {
+            Err(e) => {
+                return Some(response(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, format!("parse error: {e}"))),
+                ))
+            }
+            Ok(v) => v,
+        };
+        let req = match serde_json::from_value::<Request>(req) {
+            Ok(r) if r.jsonrpc == "2.0" => r,
+            Ok(r) => {
+                return Some(response(
+                    r.id.unwrap_or(Value::Null),
+                    Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+                ))
+            }
+            Err(e) => {
+                return Some(response(
+                    Value::Null,
+                    Err(RpcError::new(
+                        INVALID_REQUEST,
+                        format!("invalid request: {e}"),
+                    )),
+                ))
+            }
+        };
+        debug!(method = req.method, "control request");
+        let result = self.call(&req.method, req.params).await;
+        req.id.map(|id| response(id, result))
+    }
+
+    async fn call(&self, method: &str, p: Option<Value>) -> Result<Value, RpcError> {
+        match method {
+            "health" => {
+                params::<serde::de::IgnoredAny>(p)?;
+                Ok(self.health())
+            }
+            "listCameras" => Ok(self.list_cameras(params(p)?)?),
+            "updateCamera" => Ok(self.update_camera(params(p)?)?),
+            "updateStream" => Ok(self.update_stream(params(p)?)?),
+            "flush" => {
+                params::<serde::de::IgnoredAny>(p)?;
+                let db = self.db.clone();
+                tokio::task::spawn_blocking(move || db.lock().flush("control request"))
+                    .await
+                    .map_err(|e| err!(Internal, source(e)))??;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no such method {method:?}"),
+            )),
+        }
+    }
+
+    /// Reports recent streamer panics and directory scans, as `/api/health` does for a caller
+    /// with `read_camera_configs`.
+    fn health(&self) -> Value {
+        let panics = self.panics.recent(SystemTime::now());
+        let health = json::Health {
+            status: if panics.is_empty() { "ok" } else { "degraded" },
+            recent_panic_count: panics.len(),
+            recent_panics: Some(
+                panics
+                    .into_iter()
+                    .map(|p| json::StreamerPanic {
+                        stream: p.stream,
+                        time_sec: p
+                            .when
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_secs() as i64)
+                            .unwrap_or(0),
+                        message: p.message,
+                    })
+                    .collect(),
+            ),
+            dir_scans: Some(json::DirScan::list(&self.db.lock())),
+        };
+        serde_json::to_value(health).expect("health is serializable")
+    }
+
+    fn list_cameras(&self, p: ListCamerasParams) -> Result<Value, Error> {
+        let l = self.db.lock();
+        let cameras = l
+            .cameras_by_id()
+            .values()
+            .map(|c| json::Camera::wrap(c, &l, false, p.configs))
+            .collect::<Result<Vec<_>, Error>>()?;
+        serde_json::to_value(cameras).map_err(|e| err!(Internal, source(e)))
+    }
+
+    /// Updates a camera's name or config. Running streamers pick up the change on restart.
+    fn update_camera(&self, p: UpdateCameraParams) -> Result<Value, Error> {
+        let mut l = self.db.lock();
+        if !l.cameras_by_id().contains_key(&p.id) {
+            bail!(NotFound, msg("no such camera {}", p.id));
+        }
+        let mut change = l.null_camera_change(p.id)?;
+        if let Some(n) = p.short_name {
+            if n.is_empty() {
+                bail!(InvalidArgument, msg("shortName must be non-empty"));
+            }
+            change.short_name = n;
+        }
+        if let Some(d) = p.description {
+            change.config.description = d;
+        }
+        if let Some(u) = p.onvif_base_url {
+            change.config.onvif_base_url =
+                if u.is_empty() {
+                    None
+                } else {
+                    Some(Url::parse(&u).map_err(|e| {
+                        err!(InvalidArgument, msg("bad onvifBaseUrl {u:?}"), source(e))
+                    })?)
+                };
+        }
+        if let Some(u) = p.username {
+            change.config.username = u;
+        }
+        if let Some(pwd) = p.password {
+            // A literal password replaces any other source, as at most one may be set.
+            change.config.password = pwd;
+            change.config.password_env.clear();
+            change.config.password_file = None;
+        }
+        l.update_camera(p.id, change)?;
+        info!(camera_id = p.id, "camera updated via control socket");
+        Ok(Value::Null)
+    }
+
+    /// Updates a stream's recording mode or retention limit.
+    ///
+    /// A lowered limit takes effect on the next flush; a changed mode on restart.
+    fn update_stream(&self, p: UpdateStreamParams) -> Result<Value, Error> {
+        let mut l = self.db.lock();
+        let Some(s) = l.streams_by_id().get(&p.id) else {
+            bail!(NotFound, msg("no such stream {}", p.id));
+        };
+        let new_limit = p.retain_bytes.unwrap_or(s.config.retain_bytes);
+        if new_limit < 0 {
+            bail!(InvalidArgument, msg("retainBytes must be non-negative"));
+        }
+        let new_record = p
+            .record
+            .unwrap_or(s.config.mode == db::json::STREAM_MODE_RECORD);
+        if new_record && s.sample_file_dir_id.is_none() {
+            bail!(
+                FailedPrecondition,
+                msg("stream {} has no sample file directory", p.id)
+            );
+        }
+        l.update_retention(&[db::RetentionChange {
+            stream_id: p.id,
+            new_record,
+            new_limit,
+        }])?;
+        info!(stream_id = p.id, "stream updated via control socket");
+        Ok(Value::Null)
+    }
+
+    /// Serves requests on one connection until the client closes it or sends an overlong line.
+    async fn serve_conn(&self, conn: tokio::net::UnixStream) -> Result<(), std::io::Error> {
+        let (r, mut w) = conn.into_split();
+        let mut r = BufReader::new(r);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = (&mut r).take(MAX_REQUEST_LEN).read_line(&mut line).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') && n as u64 == MAX_REQUEST_LEN {
+                let resp = response(
+                    Value::Null,
+                    Err(RpcError::new(INVALID_REQUEST, "request too long")),
+                );
+                w.write_all(format!("{resp}\n").as_bytes()).await?;
+                return Ok(());
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(resp) = self.handle_line(&line).await {
+                w.write_all(format!("{resp}\n").as_bytes()).await?;
+            }
+        }
+    }
+}
+
+/// Binds `path` and serves the control protocol until shutdown.
+pub(super) fn start(
+    control: Control,
+    path: &Path,
+    shutdown_rx: base::shutdown::Receiver,
+) -> Result<(), Error> {
+    super::prepare_unix_socket(path);
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| err!(e, msg("unable to bind control socket {}", path.display())))?;
+    let control = Arc::new(control);
+    let own_uid = nix::unistd::Uid::effective();
+    tokio::spawn(async move {
+        loop {
+            let conn = tokio::select! {
+                c = listener.accept() => c,
+                _ = shutdown_rx.as_future() => return,
+            };
+            let conn = match conn {
+                Ok((c, _)) => c,
+                Err(err) => {
+                    warn!(%err, "control socket accept failed; will retry in 1 sec");
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let uid = match conn.peer_cred() {
+                Ok(c) => nix::unistd::Uid::from_raw(c.uid()),
+                Err(err) => {
+                    warn!(%err, "unable to get control socket peer credentials");
+                    continue;
+                }
+            };
+            if uid != own_uid && !uid.is_root() {
+                warn!(%uid, "rejecting control socket connection from unprivileged user");
+                continue;
+            }
+            let control = control.clone();
+            tokio::spawn(async move {
+                if let Err(err) = control.serve_conn(conn).await {
+                    debug!(%err, "control connection failed");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control() -> (db::testutil::TestDb<base::clock::RealClocks>, Control) {
+        db::testutil::init();
+        let tdb = db::testutil::TestDb::new(base::clock::RealClocks {});
+        let control = Control {
+            db: tdb.db.clone(),
+            panics: Arc::new(PanicLog::default()),
+        };
+        (tdb, control)
+    }
+
+    async fn call(control: &Control, line: &str) -> Value {
+        control.handle_line(line).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn protocol_errors() {
+        let (_tdb, c) = control();
+        assert_eq!(call(&c, "{").await["error"]["code"], PARSE_ERROR);
+        assert_eq!(
+            call(&c, r#"{"jsonrpc": "1.0", "method": "health", "id": 1}"#).await["error"]["code"],
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            call(&c, r#"{"jsonrpc": "2.0", "method": "junk", "id": 1}"#).await["error"]["code"],
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            call(
+                &c,
+                r#"{"jsonrpc": "2.0", "method": "updateStream", "params": {"junk": 1}, "id": 1}"#
+            )
+            .await["error"]["code"],
+            INVALID_PARAMS
+        );
+
+        // Notifications get no response.
+        assert!(c
+            .handle_line(r#"{"jsonrpc": "2.0", "method": "health"}"#)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn methods() {
+        let (tdb, c) = control();
+        let resp = call(&c, r#"{"jsonrpc": "2.0", "method": "health", "id": "a"}"#).await;
+        assert_eq!(resp["id"], "a");
+        assert_eq!(resp["result"]["status"], "ok");
+
+        let resp = call(
+            &c,
+            r#"{"jsonrpc": "2.0", "method": "listCameras", "id": 1}"#,
+        )
+        .await;
+        let cameras = resp["result"].as_array().unwrap();
+        assert_eq!(cameras.len(), 1);
+        assert_eq!(cameras[0]["id"], db::testutil::TEST_CAMERA_ID);
+
+        let resp = call(
+            &c,
+            &format!(
+                r#"{{"jsonrpc": "2.0", "method": "updateStream", "id": 2,
+                     "params": {{"id": {}, "retainBytes": 42}}}}"#,
+                db::testutil::TEST_STREAM_ID
+            ),
+        )
+        .await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+        assert_eq!(
+            tdb.db.lock().streams_by_id()[&db::testutil::TEST_STREAM_ID]
+                .config
+                .retain_bytes,
+            42
+        );
+
+        let resp = call(
+            &c,
+            &format!(
+                r#"{{"jsonrpc": "2.0", "method": "updateCamera", "id": 3,
+                     "params": {{"id": {}, "description": "new"}}}}"#,
+                db::testutil::TEST_CAMERA_ID
+            ),
+        )
+        .await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+        assert_eq!(
+            tdb.db.lock().cameras_by_id()[&db::testutil::TEST_CAMERA_ID]
+                .config
+                .description,
+            "new"
+        );
+
+        // A password replaces the camera's password file.
+        {
+            let mut l = tdb.db.lock();
+            let mut change = l.null_camera_change(db::testutil::TEST_CAMERA_ID).unwrap();
+            change.config.password_file = Some("/etc/moonfire-nvr/camera-password".into());
+            l.update_camera(db::testutil::TEST_CAMERA_ID, change)
+                .unwrap();
+        }
+        let resp = call(
+            &c,
+            &format!(
+                r#"{{"jsonrpc": "2.0", "method": "updateCamera", "id": 3,
+                     "params": {{"id": {}, "password": "hunter2"}}}}"#,
+                db::testutil::TEST_CAMERA_ID
+            ),
+        )
+        .await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+        {
+            let l = tdb.db.lock();
+            let config = &l.cameras_by_id()[&db::testutil::TEST_CAMERA_ID].config;
+            assert_eq!(config.password, "hunter2");
+            assert_eq!(config.password_file, None);
+            super::super::config::Secret::from_password_fields(
+                &config.password,
+                &config.password_env,
+                config.password_file.as_deref(),
+            )
+            .unwrap();
+        }
+
+        let resp = call(
+            &c,
+            r#"{"jsonrpc": "2.0", "method": "updateCamera", "id": 4, "params": {"id": 999}}"#,
+        )
+        .await;
+        assert_eq!(resp["error"]["code"], SERVER_ERROR);
+        assert_eq!(resp["error"]["data"]["kind"], "NotFound");
+
+        let resp = call(&c, r#"{"jsonrpc": "2.0", "method": "flush", "id": 5}"#).await;
+        assert_eq!(resp["result"], Value::Null, "{resp}");
+    }
+}
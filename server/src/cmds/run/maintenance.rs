@@ -0,0 +1,158 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Daily database maintenance within a configured window.
+//! See [`super::config::MaintenanceConfig`].
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base::Error;
+use tracing::{info, warn};
+
+use super::config::MaintenanceConfig;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// How often to check whether the window has opened.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Returns true iff `now` (minutes since local midnight) is within the window, which may wrap
+/// past midnight.
+fn in_window(config: &MaintenanceConfig, now: u32) -> bool {
+    let offset = (now + MINUTES_PER_DAY - config.start.minutes) % MINUTES_PER_DAY;
+    offset < config.duration_minutes
+}
+
+/// Returns the local date, as days since the epoch, and minutes since local midnight.
+fn local_now() -> (i64, u32) {
+    let tm = time::now();
+    let date = (tm.to_timespec().sec + i64::from(tm.tm_utcoff)).div_euclid(86_400);
+    (date, (tm.tm_hour * 60 + tm.tm_min) as u32)
+}
+
+/// Runs maintenance once per day while the window is open, until shutdown.
+pub(super) async fn run(
+    db: Arc<db::Database>,
+    config: MaintenanceConfig,
+    shutdown_rx: base::shutdown::Receiver,
+) {
+    let mut last_date = None;
+    loop {
+        let (date, now) = local_now();
+
+        // Attribute the whole window to the day it started, so one which wraps past midnight
+        // runs only once.
+        let offset = (now + MINUTES_PER_DAY - config.start.minutes) % MINUTES_PER_DAY;
+        let window_date = date - i64::from(now < offset);
+        if in_window(&config, now) && last_date != Some(window_date) {
+            last_date = Some(window_date);
+            let remaining = Duration::from_secs(u64::from(config.duration_minutes - offset) * 60);
+            let db = db.clone();
+            let vacuum_step_pages = config.vacuum_step_pages;
+            let shutdown_rx = shutdown_rx.clone();
+            let r = tokio::task::spawn_blocking(move || {
+                maintain(
+                    &db,
+                    vacuum_step_pages,
+                    Instant::now() + remaining,
+                    &shutdown_rx,
+                )
+            })
+            .await
+            .expect("maintenance shouldn't panic");
+            if let Err(err) = r {
+                warn!(err = %err.chain(), "database maintenance failed");
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {},
+            _ = shutdown_rx.as_future() => return,
+        }
+    }
+}
+
+/// Performs one round of maintenance, stopping early at `deadline` or shutdown.
+///
+/// The database lock is taken for each step separately, so recording continues in between.
+fn maintain(
+    db: &db::Database,
+    vacuum_step_pages: u32,
+    deadline: Instant,
+    shutdown_rx: &base::shutdown::Receiver,
+) -> Result<(), Error> {
+    let out_of_time = || Instant::now() >= deadline || shutdown_rx.check().is_err();
+    info!("starting database maintenance");
+
+    // This goes first so that vacuuming can reclaim any pages it frees.
+    let (merged, deleted) = db.lock().gc_video_sample_entries()?;
+    info!("video sample entries: merged {merged}, deleted {deleted}");
+
+    if db.lock().incremental_vacuum_enabled()? {
+        let mut freed = 0;
+        let mut remaining = db.lock().freelist_count()?;
+        info!("vacuum: {remaining} unused pages");
+        while remaining > 0 && !out_of_time() {
+            let mut l = db.lock();
+            let n = l.incremental_vacuum(vacuum_step_pages)?;
+            remaining = l.freelist_count()?;
+            drop(l);
+            if n == 0 {
+                break;
+            }
+            freed += n;
+            info!("vacuum: freed {freed} pages; {remaining} remain");
+        }
+    } else {
+        info!("vacuum: skipping; database doesn't have auto_vacuum = incremental");
+    }
+
+    if out_of_time() {
+        info!("database maintenance stopped at end of window");
+        return Ok(());
+    }
+    let start = Instant::now();
+    db.lock().analyze()?;
+    info!("analyze: done in {:?}", start.elapsed());
+
+    if out_of_time() {
+        info!("database maintenance stopped at end of window");
+        return Ok(());
+    }
+    let frames = db.lock().checkpoint()?;
+    info!("checkpoint: copied {frames} WAL frames");
+    info!("database maintenance complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmds::run::config::TimeOfDay;
+
+    #[test]
+    fn window() {
+        let config = |start: &str, duration_minutes| MaintenanceConfig {
+            start: TimeOfDay::try_from(start.to_owned()).unwrap(),
+            duration_minutes,
+            vacuum_step_pages: 1,
+        };
+        let c = config("03:30", 60);
+        assert!(!in_window(&c, 3 * 60 + 29));
+        assert!(in_window(&c, 3 * 60 + 30));
+        assert!(in_window(&c, 4 * 60 + 29));
+        assert!(!in_window(&c, 4 * 60 + 30));
+
+        // A window which wraps past midnight.
+        let c = config("23:00", 120);
+        assert!(in_window(&c, 23 * 60 + 30));
+        assert!(in_window(&c, 30));
+        assert!(!in_window(&c, 60));
+        assert!(!in_window(&c, 22 * 60));
+
+        assert!(TimeOfDay::try_from("24:00".to_owned()).is_err());
+        assert!(TimeOfDay::try_from("3:00".to_owned()).is_err());
+        assert!(TimeOfDay::try_from("03:60".to_owned()).is_err());
+    }
+}
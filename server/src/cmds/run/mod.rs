@@ -5,19 +5,19 @@
 use crate::streamer;
 use crate::web;
 use crate::web::accept::Listener;
-use base::clock;
+use base::clock::{self, Clocks};
 use base::err;
 use base::FastHashMap;
 use base::{bail, Error};
 use bpaf::Bpaf;
-use db::{dir, writer};
+use db::{dir, event, recording, writer};
 use hyper::service::service_fn;
 use itertools::Itertools;
 use retina::client::SessionGroup;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use tokio::signal::unix::{signal, SignalKind};
 use tracing::error;
@@ -28,7 +28,16 @@ use libsystemd::daemon::{notify, NotifyState};
 
 use self::config::ConfigFile;
 
+pub mod anomaly;
 pub mod config;
+mod control;
+mod maintenance;
+pub mod mover;
+pub mod notify;
+mod session_cleanup;
+pub mod snapshots;
+mod user_expiry;
+pub mod watchdog;
 
 /// Runs the server, saving recordings and allowing web access.
 #[derive(Bpaf, Debug)]
@@ -42,6 +51,10 @@ pub struct Args {
     /// Note this is incompatible with session authentication; consider adding
     /// a bind with `allowUnauthenticatedPermissions` to your config.
     read_only: bool,
+
+    /// Upgrades an older database schema on startup, after backing it up.
+    /// Equivalent to `autoUpgrade = true` in the config file.
+    auto_upgrade: bool,
 }
 
 // These are used in a hack to get the name of the current time zone (e.g. America/Los_Angeles).
@@ -49,6 +62,13 @@ pub struct Args {
 const LOCALTIME_PATH: &str = "/etc/localtime";
 const TIMEZONE_PATH: &str = "/etc/timezone";
 
+/// The number of extra database connections for read paths, so that web requests listing
+/// recordings don't wait behind writer flushes. See `db::Database::add_reader`.
+const DB_READERS: usize = 4;
+
+/// The default `listen(2)` backlog of TCP binds, matching [`std::net::TcpListener::bind`].
+const DEFAULT_BACKLOG: u32 = 128;
+
 // Some well-known zone paths looks like the following:
 //   /usr/share/zoneinfo/*          for Linux and macOS < High Sierra
 //   /var/db/timezone/zoneinfo/*    for macOS High Sierra
@@ -127,6 +147,34 @@ fn resolve_zone() -> Result<String, Error> {
     }
 }
 
+/// Re-resolves the time zone and rebuilds the days maps on each `SIGHUP`, so that fixing
+/// `/etc/localtime` or updating the zone's rules doesn't require a restart.
+async fn reload_time_zone(db: Arc<db::Database>, mut hangup: tokio::signal::unix::Signal) {
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP; reloading time zone.");
+        let db = db.clone();
+        let r = tokio::task::spawn_blocking(move || {
+            let time_zone_name = resolve_zone()?;
+            let start = std::time::Instant::now();
+            let mut l = db.lock();
+            l.rebuild_days()?;
+            info!(
+                "Resolved timezone: {}; rebuilt days in {:?}",
+                &time_zone_name,
+                start.elapsed()
+            );
+            l.set_time_zone_name(time_zone_name);
+            Ok::<_, Error>(())
+        })
+        .await
+        .map_err(|e| err!(Unknown, source(e)))
+        .and_then(|r| r);
+        if let Err(err) = r {
+            warn!(err = %err.chain(), "unable to reload time zone");
+        }
+    }
+}
+
 struct Syncer {
     dir: Arc<dir::SampleFileDir>,
     channel: writer::SyncerChannel<::std::fs::File>,
@@ -188,7 +236,7 @@ fn read_config(path: &Path) -> Result<ConfigFile, Error> {
 }
 
 pub fn run(args: Args) -> Result<i32, Error> {
-    let config = read_config(&args.config).map_err(|e| {
+    let mut config = read_config(&args.config).map_err(|e| {
         err!(
             e,
             msg(
@@ -197,6 +245,14 @@ pub fn run(args: Args) -> Result<i32, Error> {
             ),
         )
     })?;
+    config.auto_upgrade |= args.auto_upgrade;
+    config.session.validate()?;
+    for b in &config.binds {
+        b.validate()?;
+    }
+    for n in &config.notifiers {
+        n.validate()?;
+    }
 
     let mut builder = tokio::runtime::Builder::new_multi_thread();
     builder.enable_all();
@@ -267,13 +323,13 @@ fn prepare_unix_socket(p: &Path) {
 }
 
 fn make_listener(
-    addr: &config::AddressConfig,
+    bind: &config::BindConfig,
     #[cfg_attr(not(target_os = "linux"), allow(unused))] preopened: &mut FastHashMap<
         String,
         Listener,
     >,
 ) -> Result<Listener, Error> {
-    let sa: SocketAddr = match addr {
+    let sa: SocketAddr = match &bind.address {
         config::AddressConfig::Ipv4(a) => (*a).into(),
         config::AddressConfig::Ipv6(a) => (*a).into(),
         config::AddressConfig::Unix(p) => {
@@ -301,12 +357,43 @@ fn make_listener(
         }
     };
 
-    // Go through std::net::TcpListener to avoid needing async. That's there for DNS resolution,
-    // but it's unnecessary when starting from a SocketAddr.
-    let listener = std::net::TcpListener::bind(sa)
+    // Go through socket2 to set options before binding and listening. This avoids needing async,
+    // which is there for DNS resolution but unnecessary when starting from a SocketAddr.
+    let opts = &bind.socket;
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(sa),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+
+    // Like std::net::TcpListener::bind, allow rebinding while old connections are in TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if let Some(v6only) = opts.v6only {
+        socket.set_only_v6(v6only)?;
+    }
+    if let Some(sec) = opts.tcp_keepalive_sec {
+        // Accepted connections inherit this from the listening socket.
+        socket.set_tcp_keepalive(
+            &socket2::TcpKeepalive::new().with_time(std::time::Duration::from_secs(sec.into())),
+        )?;
+    }
+    if let Some(b) = opts.recv_buffer_bytes {
+        socket.set_recv_buffer_size(b as usize)?;
+    }
+    if let Some(b) = opts.send_buffer_bytes {
+        socket.set_send_buffer_size(b as usize)?;
+    }
+    socket
+        .bind(&sa.into())
         .map_err(|e| err!(e, msg("unable to bind TCP socket {sa}")))?;
-    listener.set_nonblocking(true)?;
-    Ok(Listener::Tcp(tokio::net::TcpListener::from_std(listener)?))
+    let backlog = i32::try_from(opts.backlog.unwrap_or(DEFAULT_BACKLOG))
+        .map_err(|_| err!(InvalidArgument, msg("socket backlog is too large")))?;
+    socket.listen(backlog)?;
+    socket.set_nonblocking(true)?;
+    Ok(Listener::Tcp(tokio::net::TcpListener::from_std(
+        socket.into(),
+    )?))
 }
 
 async fn inner(
@@ -315,7 +402,8 @@ async fn inner(
     shutdown_rx: base::shutdown::Receiver,
 ) -> Result<i32, Error> {
     let clocks = clock::RealClocks {};
-    let (_db_dir, conn) = super::open_conn(
+    base::mem::BUDGET.set_limit(config.memory_budget_bytes);
+    let (_db_dir, mut conn) = super::open_conn(
         &config.db_dir,
         if read_only {
             super::OpenMode::ReadOnly
@@ -323,27 +411,129 @@ async fn inner(
             super::OpenMode::ReadWrite
         },
     )?;
-    let db = Arc::new(db::Database::new(clocks, conn, !read_only)?);
+    if config.auto_upgrade && !read_only {
+        super::upgrade::auto_upgrade(&config.db_dir, &mut conn)?;
+    }
+    let db = Arc::new(if config.lazy_load {
+        db::Database::new_lazy(clocks, conn, !read_only)?
+    } else {
+        db::Database::new(clocks, conn, !read_only)?
+    });
+    for _ in 0..DB_READERS {
+        db.add_reader(super::open_reader_conn(&config.db_dir)?)?;
+    }
+    if config.lazy_load {
+        let loader_conn = super::open_reader_conn(&config.db_dir)?;
+        let db = db.clone();
+        thread::Builder::new()
+            .name("load-recordings".to_owned())
+            .spawn(move || {
+                if let Err(e) = db.load_recordings(loader_conn) {
+                    error!(err = %e.chain(), "unable to load recordings");
+                }
+            })
+            .expect("can't create thread");
+    }
     info!("Database is loaded.");
 
     {
         let mut l = db.lock();
+        l.set_session_expiry(config.session.expiry_sec.map(|e| db::auth::SessionExpiry {
+            max_age_sec: i64::from(e),
+            sliding: config.session.sliding_renewal,
+        }));
+        if let Some(k) = &config.credential_key {
+            let k = db::credential::Key::parse(&k.resolve()?)
+                .map_err(|e| err!(e, msg("bad credentialKey")))?;
+            l.set_credential_key(&k)?;
+        } else if l.has_locked_credentials() {
+            bail!(
+                FailedPrecondition,
+                msg("camera credentials are encrypted; credentialKey must be set")
+            );
+        }
+        if let Some(k) = &config.sample_file_key {
+            let k = db::dir::crypt::Key::parse(&k.resolve()?)
+                .map_err(|e| err!(e, msg("bad sampleFileKey")))?;
+            l.set_sample_file_key(k)?;
+        } else if l.has_locked_sample_files() {
+            bail!(
+                FailedPrecondition,
+                msg("sample file encryption is enabled; sampleFileKey must be set")
+            );
+        }
+        for c in &config.sample_file_dirs {
+            let id = l
+                .sample_file_dirs_by_id()
+                .values()
+                .find(|d| d.path == c.path)
+                .map(|d| d.id)
+                .ok_or_else(|| {
+                    err!(
+                        NotFound,
+                        msg(
+                            "config references unknown sample file dir {}",
+                            c.path.display()
+                        )
+                    )
+                })?;
+            l.set_sample_file_dir_reader_config(id, c.reader_config())?;
+            l.set_sample_file_dir_min_free_bytes(id, c.min_free_bytes)?;
+        }
         let dirs_to_open: Vec<_> = l
             .streams_by_id()
             .values()
             .filter_map(|s| s.sample_file_dir_id)
             .collect();
         l.open_sample_file_dirs(&dirs_to_open)?;
+        let now = recording::Time::new(clocks.realtime());
+        l.add_event(event::Event {
+            time: now,
+            kind: event::EventKind::ServerStart,
+            stream_id: None,
+            message: format!("version {}", env!("CARGO_PKG_VERSION")),
+        });
+        for id in dirs_to_open.into_iter().unique() {
+            let message = l.sample_file_dirs_by_id()[&id].path.display().to_string();
+            l.add_event(event::Event {
+                time: now,
+                kind: event::EventKind::DirOpen,
+                stream_id: None,
+                message,
+            });
+        }
     }
     info!("Directories are opened.");
 
     let time_zone_name = resolve_zone()?;
     info!("Resolved timezone: {}", &time_zone_name);
+    db.lock().set_time_zone_name(time_zone_name);
+
+    let notify = notify::start(&config.notifiers, shutdown_rx.clone());
+    let snapshots = config
+        .snapshots
+        .as_ref()
+        .map(snapshots::Archive::new)
+        .transpose()?;
+    let export_signing_key = match &config.export_signing_key {
+        Some(k) => Some(Arc::new(
+            web::parse_export_signing_key(&k.resolve()?)
+                .map_err(|e| err!(e, msg("bad exportSigningKey")))?,
+        )),
+        None => None,
+    };
 
     // Start a streamer for each stream.
     let mut streamers = Vec::new();
+    let mut heartbeats = Vec::new();
+    let panics = Arc::new(streamer::PanicLog::default());
+    let mut dir_changes = FastHashMap::default();
     let mut session_groups_by_camera: FastHashMap<i32, Arc<retina::client::SessionGroup>> =
         FastHashMap::default();
+    let mut connect_limits_by_camera: FastHashMap<i32, Option<Arc<tokio::sync::Semaphore>>> =
+        FastHashMap::default();
+    #[cfg_attr(not(feature = "ffmpeg"), allow(unused_mut))]
+    let mut previews = crate::preview::Previews::default();
     let syncers = if !read_only {
         let l = db.lock();
         let mut dirs = FastHashMap::with_capacity_and_hasher(
@@ -355,6 +545,7 @@ async fn inner(
             db: &db,
             opener: &crate::stream::OPENER,
             shutdown_rx: &shutdown_rx,
+            notify: &notify,
         };
 
         // Get the directories that need syncers.
@@ -376,15 +567,34 @@ async fn inner(
             }
         }
 
-        // Then, with the lock dropped, create syncers.
+        // Then, with the lock dropped, create syncers. Each scans its directory for files to
+        // abandon, which may be slow, so do the directories (typically separate disks) in
+        // parallel.
         drop(l);
         let mut syncers = FastHashMap::with_capacity_and_hasher(dirs.len(), Default::default());
-        for (id, dir) in dirs.drain() {
-            let (channel, join) = writer::start_syncer(db.clone(), shutdown_rx.clone(), id)?;
+        let started: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = dirs
+                .drain()
+                .map(|(id, dir)| {
+                    let db = db.clone();
+                    let shutdown_rx = shutdown_rx.clone();
+                    let h = s.spawn(move || writer::start_syncer(db, shutdown_rx, id));
+                    (id, dir, h)
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|(id, dir, h)| (id, dir, h.join().expect("syncer startup panicked")))
+                .collect()
+        });
+        for (id, dir, r) in started {
+            let (channel, join) = r?;
             syncers.insert(id, Syncer { dir, channel, join });
         }
 
-        // Then start up streams.
+        // Then start up streams, paced by `stream_start_interval_ms`.
+        let start_interval = std::time::Duration::from_millis(config.stream_start_interval_ms);
+        let mut started = 0;
         let handle = tokio::runtime::Handle::current();
         let l = db.lock();
         for (i, (id, stream)) in l.streams_by_id().iter().enumerate() {
@@ -412,6 +622,16 @@ async fn inner(
                     Arc::new(SessionGroup::default().named(camera.short_name.clone()))
                 })
                 .clone();
+            let connect_limit = connect_limits_by_camera
+                .entry(camera.id)
+                .or_insert_with(|| {
+                    camera
+                        .config
+                        .max_concurrent_connects
+                        .filter(|&n| n > 0)
+                        .map(|n| Arc::new(tokio::sync::Semaphore::new(n as usize)))
+                })
+                .clone();
             let mut streamer = streamer::Streamer::new(
                 &env,
                 syncer.dir.clone(),
@@ -420,12 +640,45 @@ async fn inner(
                 camera,
                 stream,
                 session_group,
+                connect_limit,
                 rotate_offset_sec,
                 streamer::ROTATE_INTERVAL_SEC,
             )?;
+            if stream.config.preview {
+                #[cfg(feature = "ffmpeg")]
+                {
+                    let previewer = crate::preview::Previewer::new(
+                        db.clone(),
+                        shutdown_rx.clone(),
+                        camera,
+                        stream,
+                    )?;
+                    previews.insert(*id, previewer.preview().clone());
+                    let span = tracing::info_span!("preview", stream = previewer.label());
+                    streamers.push(
+                        thread::Builder::new()
+                            .name(format!("p-{}", streamer.short_name()))
+                            .spawn(move || span.in_scope(|| previewer.run()))
+                            .expect("can't create thread"),
+                    );
+                }
+                #[cfg(not(feature = "ffmpeg"))]
+                bail!(
+                    FailedPrecondition,
+                    msg(
+                        "{}: preview requires building with --features=ffmpeg",
+                        streamer.short_name()
+                    )
+                );
+            }
+            streamer.delay_start(start_interval * started);
+            started += 1;
+            dir_changes.insert(*id, streamer.dir_change_sender());
+            heartbeats.push((streamer.short_name().to_owned(), streamer.heartbeat()));
             let span = tracing::info_span!("streamer", stream = streamer.short_name());
             let thread_name = format!("s-{}", streamer.short_name());
             let handle = handle.clone();
+            let panics = panics.clone();
             streamers.push(
                 thread::Builder::new()
                     .name(thread_name)
@@ -433,17 +686,42 @@ async fn inner(
                         span.in_scope(|| {
                             let _enter_tokio = handle.enter();
                             info!("starting");
-                            streamer.run();
+                            streamer.run_restarting(&panics);
                         })
                     })
                     .expect("can't create thread"),
             );
         }
         drop(l);
-        Some(syncers)
+        Some(Arc::new(Mutex::new(syncers)))
     } else {
         None
     };
+    if let Some(m) = &config.maintenance {
+        if read_only {
+            warn!("ignoring maintenance config in read-only mode");
+        } else {
+            tokio::spawn(maintenance::run(db.clone(), m.clone(), shutdown_rx.clone()));
+        }
+    }
+    if !read_only {
+        tokio::spawn(user_expiry::run(db.clone(), shutdown_rx.clone()));
+        tokio::spawn(session_cleanup::run(
+            db.clone(),
+            config.session.retention_sec(),
+            shutdown_rx.clone(),
+        ));
+    }
+    tokio::spawn(reload_time_zone(db.clone(), signal(SignalKind::hangup())?));
+    let previews = Arc::new(previews);
+    let stream_mover = syncers.as_ref().map(|syncers| {
+        Arc::new(mover::StreamMover::new(
+            db.clone(),
+            shutdown_rx.clone(),
+            syncers.clone(),
+            dir_changes,
+        ))
+    });
 
     // Start the web interface(s).
     let own_euid = nix::unistd::Uid::effective();
@@ -452,16 +730,28 @@ async fn inner(
         let svc = Arc::new(web::Service::new(web::Config {
             db: db.clone(),
             ui_dir: Some(&config.ui_dir),
+            ui_spa_fallback: config.ui_spa_fallback,
             allow_unauthenticated_permissions: bind
                 .allow_unauthenticated_permissions
                 .clone()
                 .map(db::Permissions::from),
             trust_forward_hdrs: bind.trust_forward_headers,
-            time_zone_name: time_zone_name.clone(),
             privileged_unix_uid: bind.own_uid_is_privileged.then_some(own_euid),
+            stream_mover: stream_mover.clone(),
+            panics: panics.clone(),
+            session: config.session.clone(),
+            cors: bind.cors.clone(),
+            cache: bind.cache.clone(),
+            areas: bind.areas.clone(),
+            requests: bind.requests.clone(),
+            notify: notify.clone(),
+            snapshots: snapshots.clone(),
+            export_signing_key: export_signing_key.clone(),
+            previews: previews.clone(),
         })?);
-        let mut listener = make_listener(&bind.address, &mut preopened)?;
+        let mut listener = make_listener(bind, &mut preopened)?;
         let addr = bind.address.clone();
+        let http2 = bind.http2;
         tokio::spawn(async move {
             loop {
                 let conn = match listener.accept().await {
@@ -477,14 +767,35 @@ async fn inner(
                 let io = hyper_util::rt::TokioIo::new(conn);
                 let svc = Arc::clone(&svc);
                 let svc_fn = service_fn(move |req| Arc::clone(&svc).serve(req, conn_data));
-                tokio::spawn(
-                    hyper::server::conn::http1::Builder::new()
-                        .serve_connection(io, svc_fn)
-                        .with_upgrades(),
-                );
+                if http2 {
+                    // The builder sniffs the HTTP/2 connection preface to choose a protocol.
+                    tokio::spawn(async move {
+                        hyper_util::server::conn::auto::Builder::new(
+                            hyper_util::rt::TokioExecutor::new(),
+                        )
+                        .serve_connection_with_upgrades(io, svc_fn)
+                        .await
+                    });
+                } else {
+                    tokio::spawn(
+                        hyper::server::conn::http1::Builder::new()
+                            .serve_connection(io, svc_fn)
+                            .with_upgrades(),
+                    );
+                }
             }
         });
     }
+    if let Some(p) = &config.control_socket {
+        control::start(
+            control::Control {
+                db: db.clone(),
+                panics: panics.clone(),
+            },
+            p,
+            shutdown_rx.clone(),
+        )?;
+    }
     if !preopened.is_empty() {
         warn!(
             "ignoring systemd sockets not referenced in config: {}",
@@ -497,6 +808,11 @@ async fn inner(
         if let Err(err) = notify(false, &[NotifyState::Ready]) {
             tracing::warn!(%err, "unable to notify systemd on ready");
         }
+        tokio::spawn(watchdog::supervise(
+            heartbeats,
+            syncers.clone(),
+            shutdown_rx.clone(),
+        ));
     }
 
     info!("Ready to serve HTTP requests");
@@ -518,10 +834,11 @@ async fn inner(
                     tracing::error!("streamer panicked; look for previous panic message");
                 }
             }
-            if let Some(mut ss) = syncers {
+            if let Some(ss) = syncers {
                 // The syncers shut down when all channels to them have been dropped.
                 // The database maintains one; and `ss` holds one. Drop both.
                 db.lock().clear_on_flush();
+                let mut ss = ss.lock().unwrap();
                 for (_, s) in ss.drain() {
                     drop(s.channel);
                     s.join.join().unwrap();
@@ -532,6 +849,14 @@ async fn inner(
     .await
     .map_err(|e| err!(Unknown, source(e)))?;
 
+    // This is written by the final flush when the database is dropped.
+    db.lock().add_event(event::Event {
+        time: recording::Time::new(clocks.realtime()),
+        kind: event::EventKind::ServerStop,
+        stream_id: None,
+        message: String::new(),
+    });
+
     info!("Waiting for TEARDOWN requests to complete.");
     for g in session_groups_by_camera.values() {
         if let Err(err) = g.await_teardown().await {
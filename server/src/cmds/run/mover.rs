@@ -0,0 +1,152 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Moves of streams between sample file directories while running; see [`db::mover`].
+
+use std::sync::{mpsc, Arc, Mutex};
+
+use base::clock::RealClocks;
+use base::{bail, err, Error, FastHashMap, FastHashSet};
+use db::{dir, mover, writer};
+use tracing::info;
+
+use super::Syncer;
+use crate::streamer::DirChange;
+
+/// Handles requests to move streams' recordings, as from `POST /api/cameras/<uuid>/<type>/move`.
+pub struct StreamMover {
+    db: Arc<db::Database>,
+    shutdown_rx: base::shutdown::Receiver,
+
+    /// All running syncers, by sample file dir id. Shared with `run` for shutdown.
+    syncers: Arc<Mutex<FastHashMap<i32, Syncer>>>,
+
+    /// Senders to running streamers, by stream id.
+    dir_changes: FastHashMap<i32, mpsc::Sender<DirChange<RealClocks>>>,
+
+    /// Ids of streams with a move in progress.
+    in_progress: Mutex<FastHashSet<i32>>,
+}
+
+/// Marks a stream's move as in progress until dropped.
+struct InProgress<'a> {
+    set: &'a Mutex<FastHashSet<i32>>,
+    stream_id: i32,
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        self.set.lock().unwrap().remove(&self.stream_id);
+    }
+}
+
+impl StreamMover {
+    pub(super) fn new(
+        db: Arc<db::Database>,
+        shutdown_rx: base::shutdown::Receiver,
+        syncers: Arc<Mutex<FastHashMap<i32, Syncer>>>,
+        dir_changes: FastHashMap<i32, mpsc::Sender<DirChange<RealClocks>>>,
+    ) -> Self {
+        StreamMover {
+            db,
+            shutdown_rx,
+            syncers,
+            dir_changes,
+            in_progress: Mutex::new(FastHashSet::default()),
+        }
+    }
+
+    /// Moves the given stream's recordings to the given directory, returning when done.
+    ///
+    /// If the stream is recording, its streamer keeps recording into the old directory while
+    /// existing recordings are copied, then switches at a key frame.
+    pub async fn move_stream(
+        self: Arc<Self>,
+        stream_id: i32,
+        dst_dir_id: i32,
+    ) -> Result<(), Error> {
+        if !self.in_progress.lock().unwrap().insert(stream_id) {
+            bail!(
+                Aborted,
+                msg("stream {stream_id} already has a move in progress")
+            );
+        }
+        let _in_progress = InProgress {
+            set: &self.in_progress,
+            stream_id,
+        };
+        let this = self.clone();
+        let (m, syncer_channel) = tokio::task::spawn_blocking(move || {
+            let mut m = mover::Move::new(this.db.clone(), stream_id, dst_dir_id)?;
+            m.copy_committed()?;
+            let syncer_channel = if this.dir_changes.contains_key(&stream_id) {
+                Some(this.syncer_channel(m.dst(), dst_dir_id)?)
+            } else {
+                None
+            };
+            Ok::<_, Error>((m, syncer_channel))
+        })
+        .await
+        .map_err(|e| err!(Internal, source(e)))??;
+        if let (Some(tx), Some(syncer_channel)) = (self.dir_changes.get(&stream_id), syncer_channel)
+        {
+            let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+            tx.send(DirChange {
+                dir: m.dst().clone(),
+                mover: m,
+                syncer_channel,
+                done: done_tx,
+            })
+            .map_err(|_| err!(Unavailable, msg("streamer {stream_id} has shut down")))?;
+            return done_rx.await.map_err(|_| {
+                err!(
+                    Unavailable,
+                    msg("streamer {stream_id} stopped before finishing move")
+                )
+            })?;
+        }
+
+        // The stream isn't recording, so it can be switched immediately. If there's no syncer
+        // for the old directory, collect its garbage here.
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let src_dir_id = m.src_dir_id();
+            m.finish(&this.shutdown_rx)?;
+            let syncers = this.syncers.lock().unwrap();
+            if !syncers.contains_key(&src_dir_id) {
+                writer::lower_retention(&this.db, src_dir_id, &[])?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| err!(Internal, source(e)))?
+    }
+
+    /// Returns a channel to the syncer for the given directory, starting one if necessary.
+    fn syncer_channel(
+        &self,
+        dir: &Arc<dir::SampleFileDir>,
+        dir_id: i32,
+    ) -> Result<writer::SyncerChannel<std::fs::File>, Error> {
+        let mut syncers = self.syncers.lock().unwrap();
+        if let Some(s) = syncers.get(&dir_id) {
+            return Ok(s.channel.clone());
+        }
+        self.shutdown_rx
+            .check()
+            .map_err(|e| err!(Cancelled, source(e)))?;
+        info!("Starting syncer for dir {dir_id}");
+        let (channel, join) =
+            writer::start_syncer(self.db.clone(), self.shutdown_rx.clone(), dir_id)?;
+        syncers.insert(
+            dir_id,
+            Syncer {
+                dir: dir.clone(),
+                channel: channel.clone(),
+                join,
+            },
+        );
+        Ok(channel)
+    }
+}
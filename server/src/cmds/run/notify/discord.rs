@@ -0,0 +1,41 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Discord notifications via an
+//! [incoming webhook](https://discord.com/developers/docs/resources/webhook#execute-webhook).
+
+use base::{err, Error};
+
+use super::Message;
+use crate::cmds::run::config::DiscordConfig;
+
+/// Discord's limit on the length of a message's content, in characters.
+const MAX_LEN: usize = 2000;
+
+pub(super) async fn send(
+    config: &DiscordConfig,
+    client: &reqwest::Client,
+    msg: &Message<'_>,
+) -> Result<(), Error> {
+    let url = config.webhook_url.resolve()?;
+    let resp = client
+        .post(url)
+        .json(&serde_json::json!({
+            "content": super::truncate(&msg.text, MAX_LEN),
+
+            // Don't let camera names or signal states ping anyone.
+            "allowed_mentions": { "parse": [] },
+        }))
+        .send()
+        .await
+        // The URL is itself a credential, so don't include it in the error.
+        .map_err(|e| {
+            err!(
+                Unavailable,
+                msg("unable to send to Discord"),
+                source(e.without_url())
+            )
+        })?;
+    super::check_response(resp).await
+}
@@ -0,0 +1,243 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Email notifications via a minimal SMTP client (RFC 5321).
+//!
+//! This deliberately supports only what's needed to hand a message to a local relay: no TLS,
+//! no authentication, no pipelining.
+
+use base::{bail, err, Error};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::Message;
+use crate::cmds::run::config::EmailConfig;
+
+pub(super) async fn send(config: &EmailConfig, msg: &Message<'_>) -> Result<(), Error> {
+    let stream = TcpStream::connect(&config.smtp_server).await.map_err(|e| {
+        err!(
+            e,
+            msg("unable to connect to SMTP server {}", config.smtp_server)
+        )
+    })?;
+    let helo = match stream.local_addr() {
+        Ok(std::net::SocketAddr::V4(a)) => format!("[{}]", a.ip()),
+        Ok(std::net::SocketAddr::V6(a)) => format!("[IPv6:{}]", a.ip()),
+        Err(_) => "localhost".to_owned(),
+    };
+    let message = format_message(config, msg);
+    transact(BufReader::new(stream), &helo, config, &message).await
+}
+
+/// Formats the message, including headers and the terminating `.` line, with dot-stuffing.
+fn format_message(config: &EmailConfig, msg: &Message<'_>) -> String {
+    let mut m = String::new();
+    m.push_str(&format!("From: {}\r\n", config.from));
+    m.push_str(&format!("To: {}\r\n", config.to.join(", ")));
+    m.push_str(&format!(
+        "Subject: {}\r\n",
+        encode_header(&msg.event.subject())
+    ));
+    m.push_str(&format!("Date: {}\r\n", time::now().rfc822z()));
+    let domain = config.from.rsplit_once('@').map_or("localhost", |(_, d)| d);
+    m.push_str(&format!(
+        "Message-ID: <{}@{}>\r\n",
+        ulid::Ulid::new(),
+        domain
+    ));
+    m.push_str("MIME-Version: 1.0\r\n");
+    m.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    m.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
+    for line in msg.text.lines() {
+        if line.starts_with('.') {
+            m.push('.');
+        }
+        m.push_str(line);
+        m.push_str("\r\n");
+    }
+    m.push_str(".\r\n");
+    m
+}
+
+/// Encodes a header value as an RFC 2047 encoded-word if it's not plain ASCII.
+fn encode_header(v: &str) -> String {
+    if v.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+        return v.to_owned();
+    }
+    format!("=?utf-8?B?{}?=", STANDARD.encode(v))
+}
+
+async fn transact<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: BufReader<S>,
+    helo: &str,
+    config: &EmailConfig,
+    message: &str,
+) -> Result<(), Error> {
+    expect(&mut stream, &[220]).await?;
+    command(&mut stream, &format!("EHLO {helo}"), &[250]).await?;
+    command(&mut stream, &format!("MAIL FROM:<{}>", config.from), &[250]).await?;
+    for to in &config.to {
+        command(&mut stream, &format!("RCPT TO:<{to}>"), &[250, 251]).await?;
+    }
+    command(&mut stream, "DATA", &[354]).await?;
+    stream
+        .write_all(message.as_bytes())
+        .await
+        .map_err(|e| err!(e, msg("unable to write SMTP message")))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| err!(e, msg("unable to write SMTP message")))?;
+    expect(&mut stream, &[250]).await?;
+
+    // The message has been accepted; a failed QUIT doesn't matter.
+    let _ = command(&mut stream, "QUIT", &[221]).await;
+    Ok(())
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    cmd: &str,
+    ok_codes: &[u16],
+) -> Result<(), Error> {
+    stream
+        .write_all(format!("{cmd}\r\n").as_bytes())
+        .await
+        .map_err(|e| err!(e, msg("unable to write SMTP command")))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| err!(e, msg("unable to write SMTP command")))?;
+    expect(stream, ok_codes)
+        .await
+        .map_err(|e| err!(e, msg("SMTP command {cmd:?} failed")))
+}
+
+/// Reads a reply, which may span several lines, and checks its code against `ok_codes`.
+async fn expect<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+    ok_codes: &[u16],
+) -> Result<(), Error> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let n = stream
+            .read_line(&mut line)
+            .await
+            .map_err(|e| err!(e, msg("unable to read SMTP reply")))?;
+        if n == 0 {
+            bail!(Unavailable, msg("SMTP server closed connection"));
+        }
+        let reply = line.trim_end();
+        let code = reply
+            .get(..3)
+            .and_then(|c| c.parse::<u16>().ok())
+            .ok_or_else(|| err!(Unavailable, msg("bad SMTP reply {reply:?}")))?;
+        if reply.as_bytes().get(3) == Some(&b'-') {
+            continue; // more lines follow.
+        }
+        if !ok_codes.contains(&code) {
+            bail!(Unavailable, msg("SMTP server replied {reply:?}"));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmds::run::notify::{Camera, Event, EventType};
+    use db::{recording, testutil};
+    use tokio::io::AsyncReadExt;
+
+    fn config() -> EmailConfig {
+        EmailConfig {
+            smtp_server: String::new(),
+            from: "nvr@example.com".to_owned(),
+            to: vec!["a@example.com".to_owned(), "b@example.com".to_owned()],
+        }
+    }
+
+    fn event() -> Event {
+        Event {
+            type_: EventType::CameraDown,
+            time: recording::Time(0),
+            camera: Some(Camera {
+                uuid: uuid::Uuid::nil(),
+                short_name: "driveway".to_owned(),
+            }),
+            detail: ".hidden line".to_owned(),
+        }
+    }
+
+    #[test]
+    fn encoded_subject() {
+        assert_eq!(encode_header("driveway: motion"), "driveway: motion");
+        assert_eq!(encode_header("café"), "=?utf-8?B?Y2Fmw6k=?=");
+    }
+
+    #[tokio::test]
+    async fn dialogue() {
+        testutil::init();
+        let (client, mut server) = tokio::io::duplex(4096);
+        let config = config();
+        let message = format_message(
+            &config,
+            &Message {
+                event: &event(),
+                text: "driveway: camera down\n.hidden line\n".to_owned(),
+            },
+        );
+        assert!(message.contains("\r\n..hidden line\r\n"));
+        assert!(message.ends_with("\r\n.\r\n"));
+        let server = tokio::spawn(async move {
+            server
+                .write_all(
+                    b"220 relay ESMTP\r\n\
+                      250-relay\r\n250 8BITMIME\r\n\
+                      250 ok\r\n\
+                      250 ok\r\n251 forwarding\r\n\
+                      354 go ahead\r\n\
+                      250 queued\r\n\
+                      221 bye\r\n",
+                )
+                .await
+                .unwrap();
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).await.unwrap();
+            String::from_utf8(received).unwrap()
+        });
+        transact(BufReader::new(client), "[127.0.0.1]", &config, &message)
+            .await
+            .unwrap();
+        let received = server.await.unwrap();
+        let expected_prefix = "EHLO [127.0.0.1]\r\n\
+                               MAIL FROM:<nvr@example.com>\r\n\
+                               RCPT TO:<a@example.com>\r\n\
+                               RCPT TO:<b@example.com>\r\n\
+                               DATA\r\n";
+        assert!(received.starts_with(expected_prefix), "{received}");
+        assert!(received.ends_with("\r\n.\r\nQUIT\r\n"), "{received}");
+    }
+
+    #[tokio::test]
+    async fn rejected_recipient() {
+        testutil::init();
+        let (client, mut server) = tokio::io::duplex(4096);
+        let config = config();
+        tokio::spawn(async move {
+            server
+                .write_all(b"220 relay\r\n250 relay\r\n250 ok\r\n550 no such user\r\n")
+                .await
+                .unwrap();
+            let mut received = Vec::new();
+            let _ = server.read_to_end(&mut received).await;
+        });
+        let e = transact(BufReader::new(client), "[127.0.0.1]", &config, "")
+            .await
+            .unwrap_err();
+        assert!(e.chain().to_string().contains("550"), "{}", e.chain());
+    }
+}
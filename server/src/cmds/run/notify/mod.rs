@@ -0,0 +1,538 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Notifications of camera and signal events via configured channels such as email.
+//! See [`super::config::NotifierConfig`].
+
+mod discord;
+mod email;
+mod telegram;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use base::Error;
+use db::recording;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::config::{ChannelConfig, NotifierConfig};
+
+/// The number of events which may be queued for sending before further events are dropped.
+const QUEUE_LEN: usize = 256;
+
+/// The period over which [`NotifierConfig::max_per_hour`] applies.
+const RATE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// How long a single notification may take to send.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The message text used when [`NotifierConfig::template`] is unset.
+pub const DEFAULT_TEMPLATE: &str = "{summary}\nTime: {time}\n{detail}\n{link}";
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum EventType {
+    /// A stream failed, either after being connected or on its first attempt since startup.
+    CameraDown,
+
+    /// A stream connected after a `CameraDown`.
+    CameraUp,
+
+    /// A signal entered a state configured as motion.
+    Motion,
+
+    /// A stream's frame rate or key frame interval went outside its configured limits.
+    StreamDegraded,
+
+    /// A stream returned to its configured limits after a `StreamDegraded`.
+    StreamRecovered,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 5] = [
+        EventType::CameraDown,
+        EventType::CameraUp,
+        EventType::Motion,
+        EventType::StreamDegraded,
+        EventType::StreamRecovered,
+    ];
+
+    fn description(self) -> &'static str {
+        match self {
+            EventType::CameraDown => "camera down",
+            EventType::CameraUp => "camera up",
+            EventType::Motion => "motion",
+            EventType::StreamDegraded => "stream degraded",
+            EventType::StreamRecovered => "stream recovered",
+        }
+    }
+}
+
+/// The camera an [`Event`] concerns.
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub uuid: Uuid,
+    pub short_name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub type_: EventType,
+    pub time: recording::Time,
+
+    /// The camera concerned, if any. Motion signals without a camera association have none.
+    pub camera: Option<Camera>,
+
+    /// Human-readable details, such as the stream and error or the signal and state.
+    pub detail: String,
+}
+
+impl Event {
+    fn subject(&self) -> String {
+        match &self.camera {
+            Some(c) => format!("{}: {}", c.short_name, self.type_.description()),
+            None => self.type_.description().to_owned(),
+        }
+    }
+
+    /// Returns a link for viewing the event: the stream's instant replay for motion, or the
+    /// UI otherwise.
+    fn link(&self, base: &url::Url) -> String {
+        match (self.type_, &self.camera) {
+            (EventType::Motion, Some(c)) => base
+                .join(&format!("api/cameras/{}/main/replay.mp4", c.uuid))
+                .map(String::from)
+                .unwrap_or_default(),
+            _ => base.to_string(),
+        }
+    }
+
+    /// Expands `template` as described at [`NotifierConfig::template`].
+    fn render(&self, template: &str, link_base_url: Option<&url::Url>) -> String {
+        let summary = self.subject();
+        let camera = self.camera.as_ref().map(|c| c.short_name.as_str());
+        let time = self.time.to_string();
+        let link = link_base_url.map(|b| self.link(b));
+        let mut out = String::new();
+        for line in template.lines() {
+            let mut rendered = String::new();
+            let mut any_placeholder = false;
+            let mut any_value = false;
+            let mut rest = line;
+            while let Some(start) = rest.find('{') {
+                let Some(len) = rest[start..].find('}') else {
+                    break;
+                };
+                let value = match &rest[start + 1..start + len] {
+                    "summary" => Some(summary.as_str()),
+                    "camera" => Some(camera.unwrap_or("")),
+                    "event" => Some(self.type_.description()),
+                    "time" => Some(time.as_str()),
+                    "detail" => Some(self.detail.as_str()),
+                    "link" => Some(link.as_deref().unwrap_or("")),
+                    _ => None,
+                };
+                let Some(value) = value else {
+                    // Not a placeholder; keep it verbatim.
+                    rendered.push_str(&rest[..start + len + 1]);
+                    rest = &rest[start + len + 1..];
+                    continue;
+                };
+                any_placeholder = true;
+                any_value |= !value.is_empty();
+                rendered.push_str(&rest[..start]);
+                rendered.push_str(value);
+                rest = &rest[start + len + 1..];
+            }
+            rendered.push_str(rest);
+            if any_placeholder && !any_value && rendered.trim().is_empty() {
+                continue;
+            }
+            out.push_str(&rendered);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// A handle for queueing events to be sent to all notifiers. The default handle discards them.
+#[derive(Clone, Default)]
+pub struct Sender(Option<mpsc::Sender<Event>>);
+
+impl Sender {
+    /// Queues `event` without blocking, so this may be called from streamer threads as well as
+    /// async tasks. Drops the event if the queue is full.
+    pub fn send(&self, event: Event) {
+        let Some(tx) = &self.0 else {
+            return;
+        };
+        if let Err(mpsc::error::TrySendError::Full(e)) = tx.try_send(event) {
+            warn!(event = ?e.type_, "notification queue full; dropping event");
+        }
+    }
+}
+
+/// Limits sends to a given number per [`RATE_PERIOD`].
+struct RateLimiter {
+    max: usize,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    fn new(max: u32) -> Self {
+        RateLimiter {
+            max: max as usize,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Returns true and records a send if one is allowed at `now`.
+    fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .sent
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= RATE_PERIOD)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+/// Per-camera, per-event-type cooldowns, as configured by [`NotifierConfig::cooldown_sec`].
+struct Cooldowns {
+    period: Duration,
+    active: HashMap<(Option<Uuid>, EventType), Cooldown>,
+}
+
+struct Cooldown {
+    until: Instant,
+
+    /// The first and last events suppressed during this cooldown, and the total count.
+    suppressed: Option<(Event, Event, usize)>,
+}
+
+impl Cooldowns {
+    fn new(period: Duration) -> Self {
+        Cooldowns {
+            period,
+            active: HashMap::new(),
+        }
+    }
+
+    /// Returns true if `event` should be sent now, starting a cooldown for its camera and type.
+    /// Otherwise records it for the digest sent when the cooldown ends.
+    fn offer(&mut self, now: Instant, event: &Event) -> bool {
+        if self.period.is_zero() {
+            return true;
+        }
+        let key = (event.camera.as_ref().map(|c| c.uuid), event.type_);
+        match self.active.get_mut(&key) {
+            Some(c) if c.until > now => {
+                match &mut c.suppressed {
+                    None => c.suppressed = Some((event.clone(), event.clone(), 1)),
+                    Some((_, last, n)) => {
+                        *last = event.clone();
+                        *n += 1;
+                    }
+                }
+                false
+            }
+            _ => {
+                self.active.insert(
+                    key,
+                    Cooldown {
+                        until: now + self.period,
+                        suppressed: None,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Ends cooldowns which have expired by `now`, returning a digest event for each which
+    /// suppressed anything. The digest itself starts a new cooldown.
+    fn expire(&mut self, now: Instant) -> Vec<Event> {
+        let mut digests = Vec::new();
+        let period = self.period;
+        self.active.retain(|_, c| {
+            if c.until > now {
+                return true;
+            }
+            let Some((first, last, n)) = c.suppressed.take() else {
+                return false;
+            };
+            digests.push(Event {
+                detail: format!(
+                    "{n} more {} event{} from {} to {}; last: {}",
+                    last.type_.description(),
+                    if n == 1 { "" } else { "s" },
+                    first.time,
+                    last.time,
+                    last.detail,
+                ),
+                ..last
+            });
+            c.until = now + period;
+            true
+        });
+        digests
+    }
+
+    fn next_expiry(&self) -> Option<Instant> {
+        self.active.values().map(|c| c.until).min()
+    }
+}
+
+struct Notifier {
+    i: usize,
+    config: NotifierConfig,
+    limiter: RateLimiter,
+    cooldowns: Cooldowns,
+}
+
+impl Notifier {
+    /// Sends `event` in the background, subject to the rate limit.
+    fn send(&mut self, now: Instant, client: &reqwest::Client, event: &Event) {
+        let i = self.i;
+        if !self.limiter.allow(now) {
+            warn!(
+                notifier = i,
+                event = ?event.type_,
+                "over maxPerHour; dropping notification"
+            );
+            return;
+        }
+        let channel = self.config.channel.clone();
+        let text = event.render(
+            self.config.template.as_deref().unwrap_or(DEFAULT_TEMPLATE),
+            self.config.link_base_url.as_ref(),
+        );
+        let event = event.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let msg = Message {
+                event: &event,
+                text,
+            };
+            match tokio::time::timeout(SEND_TIMEOUT, send(&channel, &client, &msg)).await {
+                Ok(Ok(())) => {
+                    debug!(notifier = i, event = ?event.type_, "sent notification")
+                }
+                Ok(Err(err)) => warn!(
+                    notifier = i,
+                    err = %err.chain(),
+                    "unable to send notification"
+                ),
+                Err(_) => warn!(
+                    notifier = i,
+                    "notification took over {SEND_TIMEOUT:?}; abandoning"
+                ),
+            }
+        });
+    }
+}
+
+/// A notification ready to send on a particular channel.
+struct Message<'a> {
+    event: &'a Event,
+    text: String,
+}
+
+/// Starts sending events to the given notifiers until shutdown, returning a handle for queueing
+/// events. Must be called from within a tokio runtime.
+pub fn start(configs: &[NotifierConfig], shutdown_rx: base::shutdown::Receiver) -> Sender {
+    if configs.is_empty() {
+        return Sender::default();
+    }
+    let mut notifiers: Vec<Notifier> = configs
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Notifier {
+            i,
+            config: c.clone(),
+            limiter: RateLimiter::new(c.max_per_hour),
+            cooldowns: Cooldowns::new(Duration::from_secs(c.cooldown_sec.into())),
+        })
+        .collect();
+    let client = reqwest::Client::builder()
+        .timeout(SEND_TIMEOUT)
+        .build()
+        .expect("HTTP client should build");
+    let (tx, mut rx) = mpsc::channel(QUEUE_LEN);
+    tokio::spawn(async move {
+        loop {
+            let next_expiry = notifiers
+                .iter()
+                .filter_map(|n| n.cooldowns.next_expiry())
+                .min();
+            let expired = async {
+                match next_expiry {
+                    Some(t) => tokio::time::sleep_until(t.into()).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let event = tokio::select! {
+                e = rx.recv() => match e {
+                    Some(e) => Some(e),
+                    None => return,
+                },
+                _ = expired => None,
+                _ = shutdown_rx.as_future() => return,
+            };
+            let now = Instant::now();
+            for n in &mut notifiers {
+                for digest in n.cooldowns.expire(now) {
+                    n.send(now, &client, &digest);
+                }
+                let Some(event) = &event else {
+                    continue;
+                };
+                if n.config.events.contains(&event.type_) && n.cooldowns.offer(now, event) {
+                    n.send(now, &client, event);
+                }
+            }
+        }
+    });
+    Sender(Some(tx))
+}
+
+async fn send(
+    channel: &ChannelConfig,
+    client: &reqwest::Client,
+    msg: &Message<'_>,
+) -> Result<(), Error> {
+    match channel {
+        ChannelConfig::Email(c) => email::send(c, msg).await,
+        ChannelConfig::Telegram(c) => telegram::send(c, client, msg).await,
+        ChannelConfig::Discord(c) => discord::send(c, client, msg).await,
+    }
+}
+
+/// Truncates `text` to at most `max_chars` characters, marking any truncation with an ellipsis.
+fn truncate(text: &str, max_chars: usize) -> std::borrow::Cow<'_, str> {
+    match text.char_indices().nth(max_chars) {
+        None => text.into(),
+        Some(_) => {
+            let (end, _) = text
+                .char_indices()
+                .nth(max_chars - 1)
+                .expect("max_chars > 0");
+            format!("{}…", &text[..end]).into()
+        }
+    }
+}
+
+/// Checks the response of an HTTP-based channel, including the body in any error.
+async fn check_response(resp: reqwest::Response) -> Result<(), Error> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = resp.text().await.unwrap_or_default();
+    base::bail!(Unavailable, msg("server replied {status}: {body}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn test_event() -> Event {
+        Event {
+            type_: EventType::Motion,
+            time: recording::Time(0),
+            camera: Some(Camera {
+                uuid: Uuid::nil(),
+                short_name: "driveway".to_owned(),
+            }),
+            detail: String::new(),
+        }
+    }
+
+    #[test]
+    fn render() {
+        let e = test_event();
+        let base = url::Url::parse("https://nvr.example.com/").unwrap();
+        assert_eq!(
+            e.render("{camera}: {event} {unknown}\n{detail}\n{link}", Some(&base)),
+            "driveway: motion {unknown}\n\
+             https://nvr.example.com/api/cameras/00000000-0000-0000-0000-000000000000/main/replay.mp4\n"
+        );
+        assert_eq!(
+            e.render("{summary}\n\n{link}", None),
+            "driveway: motion\n\n"
+        );
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(super::truncate("café", 4), "café");
+        assert_eq!(super::truncate("café!", 4), "caf…");
+    }
+
+    #[test]
+    fn cooldowns() {
+        let mut c = Cooldowns::new(Duration::from_secs(600));
+        let t0 = Instant::now();
+        let e = test_event();
+        let other_camera = Event {
+            camera: None,
+            ..test_event()
+        };
+        assert!(c.offer(t0, &e));
+        assert!(c.offer(t0, &other_camera));
+        assert!(!c.offer(t0 + Duration::from_secs(1), &e));
+        assert!(!c.offer(
+            t0 + Duration::from_secs(2),
+            &Event {
+                time: recording::Time(90_000),
+                detail: "signal a: motion".to_owned(),
+                ..test_event()
+            }
+        ));
+        assert_eq!(c.next_expiry(), Some(t0 + Duration::from_secs(600)));
+        assert!(c.expire(t0 + Duration::from_secs(599)).is_empty());
+
+        // The camera's cooldown produces a digest and restarts; the other simply ends.
+        let t1 = t0 + Duration::from_secs(600);
+        let digests = c.expire(t1);
+        assert_eq!(digests.len(), 1);
+        assert_eq!(
+            digests[0].detail,
+            format!(
+                "2 more motion events from {} to {}; last: signal a: motion",
+                recording::Time(0),
+                recording::Time(90_000),
+            )
+        );
+        assert!(c.offer(t1, &other_camera));
+        assert!(!c.offer(t1, &e));
+
+        // A cooldown with nothing suppressed just ends.
+        let t2 = t1 + Duration::from_secs(600);
+        assert_eq!(c.expire(t2).len(), 1);
+        assert!(c.expire(t2 + Duration::from_secs(600)).is_empty());
+        assert!(c.offer(t2 + Duration::from_secs(600), &e));
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut l = RateLimiter::new(2);
+        let t0 = Instant::now();
+        assert!(l.allow(t0));
+        assert!(l.allow(t0 + Duration::from_secs(1)));
+        assert!(!l.allow(t0 + Duration::from_secs(2)));
+        assert!(l.allow(t0 + RATE_PERIOD));
+        assert!(!l.allow(t0 + RATE_PERIOD));
+        assert!(l.allow(t0 + RATE_PERIOD + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,67 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Telegram notifications via the Bot API's
+//! [`sendMessage`](https://core.telegram.org/bots/api#sendmessage).
+
+use base::{err, Error};
+
+use super::Message;
+use crate::cmds::run::config::TelegramConfig;
+
+/// Telegram's limit on the length of a message's text, in characters.
+const MAX_LEN: usize = 4096;
+
+pub(super) async fn send(
+    config: &TelegramConfig,
+    client: &reqwest::Client,
+    msg: &Message<'_>,
+) -> Result<(), Error> {
+    let token = config.bot_token.resolve()?;
+    let resp = client
+        .post(format!("https://api.telegram.org/bot{token}/sendMessage"))
+        .json(&body(config, msg))
+        .send()
+        .await
+        // The URL includes the token, so don't include it in the error.
+        .map_err(|e| {
+            err!(
+                Unavailable,
+                msg("unable to send to Telegram"),
+                source(e.without_url())
+            )
+        })?;
+    super::check_response(resp).await
+}
+
+fn body(config: &TelegramConfig, msg: &Message<'_>) -> serde_json::Value {
+    serde_json::json!({
+        "chat_id": config.chat_id,
+        "text": super::truncate(&msg.text, MAX_LEN),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmds::run::config::Secret;
+    use crate::cmds::run::notify::tests::test_event;
+
+    #[test]
+    fn request_body() {
+        let config = TelegramConfig {
+            bot_token: Secret::Literal(String::new()),
+            chat_id: "@mynvr".to_owned(),
+        };
+        let event = test_event();
+        let msg = Message {
+            event: &event,
+            text: "driveway: motion\n".to_owned(),
+        };
+        assert_eq!(
+            body(&config, &msg),
+            serde_json::json!({"chat_id": "@mynvr", "text": "driveway: motion\n"}),
+        );
+    }
+}
@@ -0,0 +1,37 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Purges revoked and expired sessions from the database once they're older than
+//! `retentionSec` in [`super::config::SessionConfig`].
+//!
+//! Without this, the `user_session` table grows with every login forever, which adds up on
+//! installs exposed to the internet.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use base::clock::Clocks;
+use tracing::{info, warn};
+
+/// How often to purge sessions.
+const INTERVAL: Duration = Duration::from_secs(3600);
+
+pub(super) async fn run(
+    db: Arc<db::Database>,
+    retention_sec: i64,
+    shutdown_rx: base::shutdown::Receiver,
+) {
+    loop {
+        let now = db.clocks().realtime().sec;
+        match db.lock().purge_sessions(now, retention_sec) {
+            Ok(0) => {}
+            Ok(n) => info!(sessions = n, "purged old sessions"),
+            Err(err) => warn!(err = %err.chain(), "unable to purge old sessions"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(INTERVAL) => {},
+            _ = shutdown_rx.as_future() => return,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Archive of camera snapshots taken on signal rising edges.
+//! See [`super::config::SnapshotsConfig`].
+//!
+//! Snapshots are JPEGs fetched from each camera's own `snapshotUrl`, stored as
+//! `<signal id>-<time 90k>.jpg` in a dedicated directory. They're kept independently of video
+//! retention, limited only by the archive's total size.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base::{bail, err, Error};
+use db::recording;
+use tracing::{debug, warn};
+use url::Url;
+
+use super::config::{Secret, SnapshotsConfig};
+
+/// How long fetching a snapshot may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest snapshot to accept.
+const MAX_SNAPSHOT_BYTES: usize = 16 << 20;
+
+/// A snapshot in the archive.
+#[derive(Debug)]
+struct Entry {
+    signal: u32,
+    time: recording::Time,
+    len: u64,
+}
+
+impl Entry {
+    fn filename(&self) -> String {
+        format!("{}-{}.jpg", self.signal, self.time.0)
+    }
+
+    fn parse(filename: &str, len: u64) -> Option<Self> {
+        let (signal, time) = filename.strip_suffix(".jpg")?.split_once('-')?;
+        Some(Entry {
+            signal: signal.parse().ok()?,
+            time: recording::Time(time.parse().ok()?),
+            len,
+        })
+    }
+}
+
+pub struct Archive {
+    dir: PathBuf,
+    max_bytes: u64,
+    client: reqwest::Client,
+
+    /// Serializes writes and pruning.
+    write_lock: Mutex<()>,
+}
+
+impl Archive {
+    pub fn new(config: &SnapshotsConfig) -> Result<Arc<Self>, Error> {
+        std::fs::create_dir_all(&config.dir).map_err(|e| {
+            err!(
+                e,
+                msg("unable to create snapshot dir {}", config.dir.display())
+            )
+        })?;
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .map_err(|e| err!(Internal, source(e)))?;
+        Ok(Arc::new(Archive {
+            dir: config.dir.clone(),
+            max_bytes: config.max_bytes,
+            client,
+            write_lock: Mutex::new(()),
+        }))
+    }
+
+    /// Fetches and stores a snapshot for `signal` at `time` in the background.
+    ///
+    /// `username` and `password` are the camera's.
+    pub fn capture(
+        self: &Arc<Self>,
+        signal: u32,
+        time: recording::Time,
+        url: Url,
+        username: String,
+        password: Secret,
+    ) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let data = match this.fetch(url, &username, &password).await {
+                Ok(d) => d,
+                Err(err) => {
+                    warn!(signal, err = %err.chain(), "unable to fetch snapshot");
+                    return;
+                }
+            };
+            let entry = Entry {
+                signal,
+                time,
+                len: data.len() as u64,
+            };
+            let r = tokio::task::spawn_blocking(move || this.store(&entry, &data))
+                .await
+                .expect("store shouldn't panic");
+            match r {
+                Ok(()) => debug!(signal, %time, "stored snapshot"),
+                Err(err) => warn!(signal, err = %err.chain(), "unable to store snapshot"),
+            }
+        });
+    }
+
+    async fn fetch(&self, url: Url, username: &str, password: &Secret) -> Result<Vec<u8>, Error> {
+        let mut req = self.client.get(url);
+        if !username.is_empty() {
+            req = req.basic_auth(username, Some(password.resolve()?));
+        }
+        let resp = req
+            .send()
+            .await
+            .map_err(|e| err!(Unavailable, source(e.without_url())))?;
+        let status = resp.status();
+        if !status.is_success() {
+            bail!(Unavailable, msg("camera replied {status}"));
+        }
+        if resp
+            .content_length()
+            .is_some_and(|l| l > MAX_SNAPSHOT_BYTES as u64)
+        {
+            bail!(ResourceExhausted, msg("snapshot is too large"));
+        }
+        let data = resp
+            .bytes()
+            .await
+            .map_err(|e| err!(Unavailable, source(e.without_url())))?;
+        if data.len() > MAX_SNAPSHOT_BYTES {
+            bail!(ResourceExhausted, msg("snapshot is too large"));
+        }
+        if !data.starts_with(b"\xff\xd8") {
+            bail!(
+                InvalidArgument,
+                msg("camera returned something other than a JPEG")
+            );
+        }
+        Ok(data.to_vec())
+    }
+
+    /// Writes a snapshot, then deletes the oldest snapshots beyond `max_bytes`.
+    fn store(&self, entry: &Entry, data: &[u8]) -> Result<(), Error> {
+        let _guard = self.write_lock.lock().unwrap();
+        let filename = entry.filename();
+        let tmp = self.dir.join(format!(".{filename}.tmp"));
+        std::fs::write(&tmp, data)
+            .map_err(|e| err!(e, msg("unable to write {}", tmp.display())))?;
+        std::fs::rename(&tmp, self.dir.join(&filename))
+            .map_err(|e| err!(e, msg("unable to rename {}", tmp.display())))?;
+        let mut entries = list(&self.dir)?;
+        entries.sort_by_key(|e| e.time);
+        let mut total: u64 = entries.iter().map(|e| e.len).sum();
+        for e in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            let path = self.dir.join(e.filename());
+            match std::fs::remove_file(&path) {
+                Ok(()) => total -= e.len,
+                Err(err) => warn!(%err, "unable to delete snapshot {}", path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the latest snapshot for `signal` taken at or before `at`, returning its time and
+    /// contents.
+    pub fn find(
+        &self,
+        signal: u32,
+        at: recording::Time,
+    ) -> Result<Option<(recording::Time, Vec<u8>)>, Error> {
+        let Some(e) = list(&self.dir)?
+            .into_iter()
+            .filter(|e| e.signal == signal && e.time <= at)
+            .max_by_key(|e| e.time)
+        else {
+            return Ok(None);
+        };
+        let path = self.dir.join(e.filename());
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some((e.time, data))),
+
+            // It may have just been pruned.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err!(err, msg("unable to read {}", path.display()))),
+        }
+    }
+}
+
+fn list(dir: &Path) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+    let rd =
+        std::fs::read_dir(dir).map_err(|e| err!(e, msg("unable to read {}", dir.display())))?;
+    for de in rd {
+        let de = de.map_err(|e| err!(e, msg("unable to read {}", dir.display())))?;
+        let Some(name) = de.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        let Ok(m) = de.metadata() else {
+            continue;
+        };
+        if let Some(e) = Entry::parse(&name, m.len()) {
+            entries.push(e);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_find_prune() {
+        db::testutil::init();
+        let tmpdir = tempfile::Builder::new()
+            .prefix("moonfire-nvr-test")
+            .tempdir()
+            .unwrap();
+        let archive = Archive::new(&SnapshotsConfig {
+            dir: tmpdir.path().to_owned(),
+            max_bytes: 10,
+        })
+        .unwrap();
+        let entry = |signal, time| Entry {
+            signal,
+            time: recording::Time(time),
+            len: 4,
+        };
+        archive.store(&entry(1, 100), b"\xff\xd8aa").unwrap();
+        archive.store(&entry(2, 200), b"\xff\xd8bb").unwrap();
+        assert!(archive.find(1, recording::Time(99)).unwrap().is_none());
+        assert_eq!(
+            archive.find(1, recording::Time::MAX).unwrap(),
+            Some((recording::Time(100), b"\xff\xd8aa".to_vec()))
+        );
+
+        // The third snapshot pushes the total over 10 bytes, so the oldest is deleted.
+        archive.store(&entry(1, 300), b"\xff\xd8cc").unwrap();
+        assert_eq!(archive.find(1, recording::Time(299)).unwrap(), None,);
+        assert_eq!(
+            archive.find(1, recording::Time(300)).unwrap(),
+            Some((recording::Time(300), b"\xff\xd8cc".to_vec()))
+        );
+        assert!(archive.find(2, recording::Time::MAX).unwrap().is_some());
+    }
+}
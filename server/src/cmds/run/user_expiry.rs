@@ -0,0 +1,35 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Disables expired user accounts (see `expirationTimeSec` in [`db::json::UserConfig`]).
+//!
+//! Authentication already rejects expired users; this just makes their state visible to admins
+//! as `disabled`, so extending an expiration doesn't silently restore access.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use base::clock::Clocks;
+use tracing::{info, warn};
+
+/// How often to check for expired users.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+pub(super) async fn run(db: Arc<db::Database>, shutdown_rx: base::shutdown::Receiver) {
+    loop {
+        let now = db.clocks().realtime().sec;
+        match db.lock().disable_expired_users(now) {
+            Ok(names) => {
+                for name in names {
+                    info!(user = name, "disabled expired user");
+                }
+            }
+            Err(err) => warn!(err = %err.chain(), "unable to disable expired users"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(INTERVAL) => {},
+            _ = shutdown_rx.as_future() => return,
+        }
+    }
+}
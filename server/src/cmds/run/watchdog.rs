@@ -0,0 +1,170 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Integration with systemd's watchdog (`WatchdogSec=`).
+//!
+//! `WATCHDOG=1` is sent only while every streamer and syncer is making progress, so that systemd
+//! restarts a wedged process. Streamers report progress via a [`Heartbeat`]; syncers are pinged.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use base::FastHashMap;
+#[cfg(target_os = "linux")]
+use itertools::Itertools;
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+#[cfg(target_os = "linux")]
+use tracing::{info, warn};
+
+/// How long a streamer may go without progress before it's considered stalled.
+///
+/// This comfortably exceeds the RTSP library's own timeouts, so a streamer stuck on an
+/// unresponsive camera will have returned an error and retried before reaching it.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+fn millis_since_epoch(t: Instant) -> u64 {
+    let epoch = *EPOCH.get_or_init(Instant::now);
+    u64::try_from(t.saturating_duration_since(epoch).as_millis()).unwrap_or(u64::MAX)
+}
+
+/// A thread's promise to make progress by a deadline.
+///
+/// Cloning shares the deadline, so the watchdog can hold one clone and the thread another.
+#[derive(Clone, Debug)]
+pub struct Heartbeat(Arc<AtomicU64>);
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        let h = Heartbeat(Arc::new(AtomicU64::new(0)));
+        h.beat();
+        h
+    }
+
+    /// Notes progress; the thread should beat again within [`STALL_TIMEOUT`].
+    pub fn beat(&self) {
+        self.expect_within(STALL_TIMEOUT);
+    }
+
+    /// Notes that the thread may legitimately block for up to `timeout` before its next beat,
+    /// e.g. while waiting for stale RTSP sessions to expire.
+    pub fn expect_within(&self, timeout: Duration) {
+        let deadline = Instant::now()
+            .checked_add(timeout)
+            .map(millis_since_epoch)
+            .unwrap_or(u64::MAX);
+        self.0.store(deadline, Ordering::Relaxed);
+    }
+
+    fn is_stalled(&self, now: Instant) -> bool {
+        self.0.load(Ordering::Relaxed) < millis_since_epoch(now)
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the names of all stalled streamers and syncers.
+#[cfg(target_os = "linux")]
+async fn check(
+    heartbeats: &[(String, Heartbeat)],
+    syncers: Option<&Mutex<FastHashMap<i32, super::Syncer>>>,
+    timeout: Duration,
+) -> Vec<String> {
+    let now = Instant::now();
+    let mut stalled: Vec<String> = heartbeats
+        .iter()
+        .filter(|(_, h)| h.is_stalled(now))
+        .map(|(name, _)| format!("streamer {name}"))
+        .collect();
+
+    // Ping all syncers at once, then wait for the replies. The set of syncers can change as
+    // streams are moved between directories, so look at it anew each time.
+    let pings: Vec<_> = syncers
+        .map(|s| {
+            s.lock()
+                .unwrap()
+                .iter()
+                .map(|(&id, s)| (id, s.channel.ping()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let deadline = tokio::time::Instant::now() + timeout;
+    for (id, ping) in pings {
+        match tokio::time::timeout_at(deadline, ping).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => stalled.push(format!("syncer for dir {id} (exited)")),
+            Err(_) => stalled.push(format!("syncer for dir {id}")),
+        }
+    }
+    stalled
+}
+
+/// Sends `WATCHDOG=1` to systemd as long as all streamers and syncers are making progress.
+///
+/// Returns immediately if systemd's watchdog isn't enabled for this process.
+#[cfg(target_os = "linux")]
+pub(super) async fn supervise(
+    heartbeats: Vec<(String, Heartbeat)>,
+    syncers: Option<Arc<Mutex<FastHashMap<i32, super::Syncer>>>>,
+    shutdown_rx: base::shutdown::Receiver,
+) {
+    use libsystemd::daemon::{notify, watchdog_enabled, NotifyState};
+    let Some(watchdog) = watchdog_enabled(false) else {
+        return;
+    };
+
+    // systemd recommends pinging at half the configured interval.
+    let period = watchdog / 2;
+    info!("pinging systemd watchdog every {period:?} while streamers and syncers progress");
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut was_healthy = true;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = shutdown_rx.as_future() => return,
+        }
+        let stalled = check(&heartbeats, syncers.as_deref(), period).await;
+        if stalled.is_empty() {
+            if !was_healthy {
+                info!("all streamers and syncers are making progress again");
+                was_healthy = true;
+            }
+            if let Err(err) = notify(false, &[NotifyState::Watchdog]) {
+                warn!(%err, "unable to notify systemd watchdog");
+            }
+        } else if was_healthy {
+            warn!(
+                "withholding systemd watchdog ping; no progress from: {}",
+                stalled.iter().join(", ")
+            );
+            was_healthy = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat() {
+        let h = Heartbeat::new();
+        let now = Instant::now();
+        assert!(!h.is_stalled(now));
+        assert!(h.is_stalled(now + STALL_TIMEOUT + Duration::from_secs(1)));
+        h.expect_within(Duration::from_secs(3600));
+        assert!(!h.is_stalled(now + STALL_TIMEOUT + Duration::from_secs(1)));
+        h.expect_within(Duration::MAX);
+        assert!(!h.is_stalled(now + Duration::from_secs(86400 * 365)));
+    }
+}
@@ -5,9 +5,11 @@
 //! Subcommand to run a SQLite shell.
 
 use super::OpenMode;
-use base::Error;
+use base::{err, Error};
 use bpaf::Bpaf;
 use std::ffi::OsString;
+use std::io::Write as _;
+use std::os::fd::FromRawFd as _;
 use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
 use std::process::Command;
@@ -17,6 +19,9 @@ use std::process::Command;
 ///
 /// Note this locks the database to prevent simultaneous access with a running server. The
 /// server maintains cached state which could be invalidated otherwise.
+///
+/// If `MOONFIRE_DB_KEY` or `MOONFIRE_DB_KEY_FILE` is set, this runs SQLCipher's `sqlcipher`
+/// shell instead, passing the key via a pipe named by its `-init` option.
 #[derive(Bpaf, Debug, PartialEq, Eq)]
 #[bpaf(command("sql"))]
 pub struct Args {
@@ -48,7 +53,12 @@ pub fn run(args: Args) -> Result<i32, Error> {
     if args.read_only {
         db.push("?mode=ro");
     }
-    Err(Command::new("sqlite3")
+    let (program, init) = match super::db_key()? {
+        Some(k) => ("sqlcipher", Some(key_pragma_pipe(&k)?)),
+        None => ("sqlite3", None),
+    };
+    Err(Command::new(program)
+        .args(init.iter().flat_map(|p| ["-init", p]))
         .args(db::db::INTEGRITY_PRAGMAS.iter().flat_map(|p| ["-cmd", p]))
         .arg(&db)
         .args(&args.arg)
@@ -56,6 +66,22 @@ pub fn run(args: Args) -> Result<i32, Error> {
         .into())
 }
 
+/// Returns a path from which the shell can read a `pragma key` statement for `key`.
+///
+/// This is the read end of a pipe which the shell inherits across `exec`, so the key appears
+/// neither on the command line, where other local users could see it, nor on disk.
+fn key_pragma_pipe(key: &str) -> Result<String, Error> {
+    let (r, w) = nix::unistd::pipe().map_err(|e| err!(e, msg("unable to create pipe")))?;
+
+    // SAFETY: `w` was just returned by `pipe`, and nothing else owns it.
+    let mut w = unsafe { std::fs::File::from_raw_fd(w) };
+
+    // The statement is far smaller than the pipe's buffer, so this doesn't block.
+    writeln!(w, "pragma key = '{}';", key.replace('\'', "''"))
+        .map_err(|e| err!(e, msg("unable to write key to pipe")))?;
+    Ok(format!("/dev/fd/{r}"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,4 +109,13 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn key_pragma() {
+        let path = key_pragma_pipe("it's").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "pragma key = 'it''s';\n"
+        );
+    }
 }
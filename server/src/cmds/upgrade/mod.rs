@@ -5,8 +5,10 @@
 /// Upgrades the database schema.
 ///
 /// See `guide/schema.md` for more information.
-use base::Error;
+use base::{bail, err, Error};
 use bpaf::Bpaf;
+use std::path::Path;
+use tracing::info;
 
 /// Upgrades to the latest database schema.
 #[derive(Bpaf, Debug)]
@@ -28,19 +30,86 @@ pub struct Args {
 
     /// Skips the normal post-upgrade vacuum operation.
     no_vacuum: bool,
+
+    /// Reports what the upgrade (or downgrade) would change, its estimated duration, and the
+    /// disk space it needs, without changing anything.
+    dry_run: bool,
+
+    /// Downgrades to the given earlier schema version rather than upgrading, if supported.
+    /// Take a backup first.
+    #[bpaf(argument("VERSION"))]
+    downgrade_to: Option<i32>,
 }
 
 pub fn run(args: Args) -> Result<i32, Error> {
-    let (_db_dir, mut conn) = super::open_conn(&args.db_dir, super::OpenMode::ReadWrite)?;
-
-    db::upgrade::run(
-        &db::upgrade::Args {
-            sample_file_dir: args.sample_file_dir.as_deref(),
-            preset_journal: &args.preset_journal,
-            no_vacuum: args.no_vacuum,
+    let (_db_dir, mut conn) = super::open_conn(
+        &args.db_dir,
+        if args.dry_run {
+            super::OpenMode::ReadOnly
+        } else {
+            super::OpenMode::ReadWrite
         },
-        crate::VERSION,
-        &mut conn,
     )?;
+    let upgrade_args = db::upgrade::Args {
+        sample_file_dir: args.sample_file_dir.as_deref(),
+        preset_journal: &args.preset_journal,
+        no_vacuum: args.no_vacuum,
+    };
+    let target = args.downgrade_to.unwrap_or(db::EXPECTED_SCHEMA_VERSION);
+
+    if args.dry_run {
+        println!("{}", db::upgrade::plan(&upgrade_args, target, &conn)?);
+        return Ok(0);
+    }
+    match args.downgrade_to {
+        Some(v) => db::upgrade::downgrade(v, &mut conn)?,
+        None => db::upgrade::run(&upgrade_args, crate::VERSION, &mut conn)?,
+    }
     Ok(0)
 }
+
+/// Upgrades the database if it's at an older schema version, for `moonfire-nvr run
+/// --auto-upgrade`.
+///
+/// First backs up the database to `db.pre-upgrade-vN` within `db_dir`. Upgrades which also change
+/// the sample file directories aren't restorable from that backup, so they must be run manually.
+pub(super) fn auto_upgrade(db_dir: &Path, conn: &mut rusqlite::Connection) -> Result<(), Error> {
+    let Some(ver) = db::get_schema_version(conn)? else {
+        return Ok(()); // uninitialized; db::Database::new will explain.
+    };
+    if ver >= db::EXPECTED_SCHEMA_VERSION {
+        return Ok(());
+    }
+    let upgrade_args = db::upgrade::Args {
+        sample_file_dir: None,
+        preset_journal: "delete",
+        no_vacuum: false,
+    };
+    let plan = db::upgrade::plan(&upgrade_args, db::EXPECTED_SCHEMA_VERSION, conn)?;
+    if plan.steps.iter().any(|s| s.touches_sample_files) {
+        bail!(
+            FailedPrecondition,
+            msg(
+                "database schema version {ver} can't be upgraded automatically; \
+                 run `moonfire-nvr upgrade` as described in guide/schema.md"
+            )
+        );
+    }
+    let backup = db_dir.join(format!("db.pre-upgrade-v{ver}"));
+    let Some(backup_str) = backup.to_str() else {
+        bail!(
+            InvalidArgument,
+            msg("backup path {} isn't valid UTF-8", backup.display())
+        );
+    };
+    info!("{plan}");
+    info!("Backing up database to {}.", backup.display());
+    conn.execute("vacuum into ?", [backup_str])
+        .map_err(|e| err!(e, msg("unable to back up database to {}", backup.display())))?;
+    db::upgrade::run(&upgrade_args, crate::VERSION, conn)?;
+    info!(
+        "Upgraded database; the backup at {} may be removed once you're satisfied.",
+        backup.display()
+    );
+    Ok(())
+}
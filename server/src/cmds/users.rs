@@ -0,0 +1,327 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Subcommand to manage users non-interactively, e.g. to recover from a lost password.
+
+use base::clock::{self, Clocks};
+use base::{bail, err, Error};
+use bpaf::Bpaf;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// Adds users, sets passwords and permissions, and revokes sessions.
+/// Like `login`, this is a privileged command that directly accesses the database.
+#[derive(Bpaf, Debug, PartialEq, Eq)]
+#[bpaf(command("users"))]
+pub struct Args {
+    #[bpaf(external(crate::parse_db_dir))]
+    db_dir: PathBuf,
+
+    #[bpaf(external(action))]
+    action: Action,
+}
+
+#[derive(Bpaf, Debug, PartialEq, Eq)]
+enum Action {
+    /// Lists users with their permissions.
+    #[bpaf(command("list"))]
+    List,
+
+    /// Adds a user.
+    #[bpaf(command("add"))]
+    Add {
+        /// Reads the new user's password from the first line of stdin.
+        /// Without this, the user has no password.
+        password_stdin: bool,
+
+        /// Grants the given permissions, as a JSON object.
+        /// E.g. `{"viewLive": true, "downloadRecordings": true}`. See `ref/api.md` for a
+        /// description of `Permissions`.
+        #[bpaf(argument::<String>("PERMS"), parse(super::login::parse_perms), optional)]
+        permissions: Option<crate::json::Permissions>,
+
+        /// Makes this a temporary account which expires after the given number of days,
+        /// e.g. for a guest. Expired accounts can't authenticate and are soon disabled.
+        #[bpaf(argument("DAYS"), optional)]
+        expires_days: Option<u32>,
+
+        #[bpaf(positional("USERNAME"))]
+        username: String,
+    },
+
+    /// Sets a user's password, read from the first line of stdin, and revokes
+    /// the user's existing sessions.
+    #[bpaf(command("set-password"))]
+    SetPassword {
+        /// Leaves existing sessions valid.
+        keep_sessions: bool,
+
+        #[bpaf(positional("USERNAME"))]
+        username: String,
+    },
+
+    /// Replaces a user's permissions. Existing sessions keep the permissions
+    /// they were created with; use `revoke-sessions` to end them.
+    #[bpaf(command("set-permissions"))]
+    SetPermissions {
+        #[bpaf(positional("USERNAME"))]
+        username: String,
+
+        /// The permissions as a JSON object, as in `add --permissions`.
+        #[bpaf(positional::<String>("PERMS"), parse(super::login::parse_perms))]
+        permissions: crate::json::Permissions,
+    },
+
+    /// Revokes all of a user's sessions, logging them out everywhere.
+    #[bpaf(command("revoke-sessions"))]
+    RevokeSessions {
+        #[bpaf(positional("USERNAME"))]
+        username: String,
+    },
+
+    /// Lists users whose password hashes use an older algorithm or parameters.
+    /// Each is upgraded at the user's next password login.
+    ///
+    /// Given any of the parameter options, first changes the Argon2id parameters
+    /// for new hashes, keeping unspecified ones as configured.
+    #[bpaf(command("rehash"))]
+    Rehash {
+        /// Sets the memory size, in KiB. The default is 19456 (19 MiB).
+        #[bpaf(argument("KIB"), optional)]
+        memory_kib: Option<u32>,
+
+        /// Sets the number of iterations. The default is 2.
+        #[bpaf(argument("N"), optional)]
+        iterations: Option<u32>,
+
+        /// Sets the degree of parallelism. The default is 1.
+        #[bpaf(argument("N"), optional)]
+        parallelism: Option<u32>,
+
+        /// Revokes the listed users' sessions, so that they must log in with
+        /// their passwords (upgrading the hashes) soon.
+        revoke_sessions: bool,
+    },
+}
+
+/// Reads a password from the first line of `r`, excluding the line ending.
+fn read_password(r: &mut dyn BufRead) -> Result<String, Error> {
+    let mut line = String::new();
+    r.read_line(&mut line)
+        .map_err(|e| err!(e, msg("unable to read password from stdin")))?;
+    let pwd = line.trim_end_matches(['\r', '\n']);
+    if pwd.is_empty() {
+        bail!(InvalidArgument, msg("password on stdin must be non-empty"));
+    }
+    Ok(pwd.to_owned())
+}
+
+fn user_id(l: &db::LockedDatabase, username: &str) -> Result<i32, Error> {
+    l.get_user(username)
+        .map(|u| u.id)
+        .ok_or_else(|| err!(NotFound, msg("no such user {username:?}")))
+}
+
+pub fn run(args: Args) -> Result<i32, Error> {
+    let clocks = clock::RealClocks {};
+    let read_only = args.action == Action::List;
+    let (_db_dir, conn) = super::open_conn(
+        &args.db_dir,
+        if read_only {
+            super::OpenMode::ReadOnly
+        } else {
+            super::OpenMode::ReadWrite
+        },
+    )?;
+    let db = db::Database::new(clocks, conn, !read_only)?;
+    let mut l = db.lock();
+    let req = db::auth::Request {
+        when_sec: Some(db.clocks().realtime().sec),
+        user_agent: None,
+        addr: None,
+    };
+    match args.action {
+        Action::List => {
+            for u in l.users_by_id().values() {
+                let perms = crate::json::Permissions::from(u.permissions.clone());
+                let expiration = match u.config.expiration_time_sec {
+                    Some(e) => format!(
+                        "\texpire{} {}",
+                        if u.is_expired(req.when_sec) { "d" } else { "s" },
+                        db::recording::Time(e * db::recording::TIME_UNITS_PER_SEC)
+                    ),
+                    None => String::new(),
+                };
+                println!(
+                    "{}\t{}{}{}{}",
+                    u.username,
+                    serde_json::to_string(&perms).expect("permissions are serializable"),
+                    if u.has_password() {
+                        ""
+                    } else {
+                        "\tno password"
+                    },
+                    if u.config.disabled { "\tdisabled" } else { "" },
+                    expiration,
+                );
+            }
+        }
+        Action::Add {
+            password_stdin,
+            permissions,
+            expires_days,
+            username,
+        } => {
+            let mut change = db::UserChange::add_user(username);
+            if let Some(d) = expires_days {
+                change.config.expiration_time_sec =
+                    req.when_sec.map(|now| now + i64::from(d) * 86_400);
+            }
+            if password_stdin {
+                change.set_password(read_password(&mut std::io::stdin().lock())?);
+            }
+            if let Some(p) = permissions {
+                change.permissions = p.into();
+            }
+            l.apply_user_change(change)?;
+        }
+        Action::SetPassword {
+            keep_sessions,
+            username,
+        } => {
+            let id = user_id(&l, &username)?;
+            let mut change = l.users_by_id()[&id].change();
+            change.set_password(read_password(&mut std::io::stdin().lock())?);
+            l.apply_user_change(change)?;
+            if !keep_sessions {
+                let n = l.revoke_user_sessions(
+                    id,
+                    db::auth::RevocationReason::AdminRevoked,
+                    Some("password changed".to_owned()),
+                    req,
+                )?;
+                println!("Revoked {n} sessions.");
+            }
+        }
+        Action::SetPermissions {
+            username,
+            permissions,
+        } => {
+            let id = user_id(&l, &username)?;
+            let mut change = l.users_by_id()[&id].change();
+            change.permissions = permissions.into();
+            change.permission_template_id = None;
+            l.apply_user_change(change)?;
+        }
+        Action::RevokeSessions { username } => {
+            let id = user_id(&l, &username)?;
+            let n =
+                l.revoke_user_sessions(id, db::auth::RevocationReason::AdminRevoked, None, req)?;
+            println!("Revoked {n} sessions.");
+        }
+        Action::Rehash {
+            memory_kib,
+            iterations,
+            parallelism,
+            revoke_sessions,
+        } => {
+            if memory_kib.is_some() || iterations.is_some() || parallelism.is_some() {
+                let mut c = l.password_hash_config()?.unwrap_or_default();
+                c.memory_kib = memory_kib.or(c.memory_kib);
+                c.iterations = iterations.or(c.iterations);
+                c.parallelism = parallelism.or(c.parallelism);
+                l.set_password_hash_config(c)?;
+            }
+            let stale: Vec<(i32, String)> = l
+                .users_by_id()
+                .values()
+                .filter(|u| l.password_needs_rehash(u))
+                .map(|u| (u.id, u.username.clone()))
+                .collect();
+            for (id, username) in &stale {
+                if revoke_sessions {
+                    let n = l.revoke_user_sessions(
+                        *id,
+                        db::auth::RevocationReason::AdminRevoked,
+                        Some("password hash upgrade".to_owned()),
+                        req.clone(),
+                    )?;
+                    println!("{username}	revoked {n} sessions");
+                } else {
+                    println!("{username}");
+                }
+            }
+            println!(
+                "{} users have password hashes to upgrade at next login.",
+                stale.len()
+            );
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpaf::Parser;
+
+    #[test]
+    fn parse_args() {
+        let args = args()
+            .to_options()
+            .run_inner(bpaf::Args::from(&[
+                "users",
+                "set-permissions",
+                "slamb",
+                "{\"viewVideo\": true}",
+            ]))
+            .unwrap();
+        assert_eq!(
+            args.action,
+            Action::SetPermissions {
+                username: "slamb".to_owned(),
+                permissions: crate::json::Permissions {
+                    // The obsolete `viewVideo` is shorthand for both.
+                    view_live: true,
+                    download_recordings: true,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn parse_rehash_args() {
+        let args = args()
+            .to_options()
+            .run_inner(bpaf::Args::from(&[
+                "users",
+                "rehash",
+                "--memory-kib",
+                "65536",
+                "--revoke-sessions",
+            ]))
+            .unwrap();
+        assert_eq!(
+            args.action,
+            Action::Rehash {
+                memory_kib: Some(65536),
+                iterations: None,
+                parallelism: None,
+                revoke_sessions: true,
+            }
+        );
+    }
+
+    #[test]
+    fn password() {
+        assert_eq!(
+            read_password(&mut &b"hunter2\r\nignored\n"[..]).unwrap(),
+            "hunter2"
+        );
+        assert_eq!(read_password(&mut &b"hunter2"[..]).unwrap(), "hunter2");
+        assert!(read_password(&mut &b"\n"[..]).is_err());
+        assert!(read_password(&mut &b""[..]).is_err());
+    }
+}
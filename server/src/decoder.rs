@@ -0,0 +1,311 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Shared decoding of streams' latest key frames to JPEG.
+//!
+//! Consumers such as the `snapshot.jpg` endpoint ask a [`Decoder`] for a stream's latest key
+//! frame rather than decoding it themselves. Each key frame is decoded at most once: the result
+//! is cached per stream until the next key frame arrives, and concurrent requests for the same
+//! stream wait on one decode. At most a fixed number of decodes run at once, so many clients
+//! polling many cameras cost one decode per camera per key frame interval.
+//!
+//! Decoding runs the `ffmpeg` binary on a one-frame `.mp4` with `-hwaccel auto`, so it uses a
+//! hardware decoder when one works. It requires building with `--features=ffmpeg`.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use base::{bail, err, Error, FastHashMap};
+use bytes::Bytes;
+use db::dir::SampleFileDir;
+use db::recording::{self, rescale};
+
+use crate::mp4;
+
+/// The default number of decodes which may run at once.
+pub const DEFAULT_WORKERS: usize = 2;
+
+/// A decoded key frame.
+#[derive(Clone, Debug)]
+pub struct Decoded {
+    /// The key frame's start time.
+    pub time: recording::Time,
+    pub jpeg: Bytes,
+}
+
+/// Identifies a key frame within its stream. Recording ids of uncommitted recordings may be
+/// reused after a crash, so this includes the open id.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Key {
+    open_id: u32,
+    recording: i32,
+    media_off_90k: std::ops::Range<i32>,
+}
+
+type DecodeFn = fn(Vec<u8>) -> Result<Bytes, Error>;
+
+/// The cached key frame of a stream, locked for the duration of a decode.
+type Slot = tokio::sync::Mutex<Option<(Key, Decoded)>>;
+
+pub struct Decoder {
+    workers: tokio::sync::Semaphore,
+    streams: std::sync::Mutex<FastHashMap<i32, Arc<Slot>>>,
+    decode: DecodeFn,
+}
+
+impl Decoder {
+    /// Creates a decoder which runs at most `workers` decodes at once.
+    pub fn new(workers: usize) -> Self {
+        Self::with_decode_fn(workers, ffmpeg_decode)
+    }
+
+    fn with_decode_fn(workers: usize, decode: DecodeFn) -> Self {
+        Decoder {
+            workers: tokio::sync::Semaphore::new(workers),
+            streams: Default::default(),
+            decode,
+        }
+    }
+
+    /// Returns the latest buffered key frame of `stream_id`, decoding it if necessary.
+    pub async fn latest_key_frame(
+        &self,
+        db: &Arc<db::Database>,
+        dirs_by_stream_id: Arc<FastHashMap<i32, Arc<SampleFileDir>>>,
+        stream_id: i32,
+    ) -> Result<Decoded, Error> {
+        let key = {
+            let l = db.lock();
+            let Some(open) = l.open else {
+                bail!(
+                    FailedPrecondition,
+                    msg("database is read-only; there are no live streams")
+                );
+            };
+            let frames = l.recent_live_frames(stream_id, i64::MAX)?;
+            let Some(f) = frames.into_iter().rev().find(|f| f.is_key) else {
+                bail!(
+                    NotFound,
+                    msg("no recent key frame of stream {stream_id}; is it streaming?")
+                );
+            };
+            Key {
+                open_id: open.id,
+                recording: f.recording,
+                media_off_90k: f.media_off_90k,
+            }
+        };
+        let (recording_id, media_off_90k) = (key.recording, key.media_off_90k.clone());
+        let input = async move {
+            let mut builder = mp4::FileBuilder::new(mp4::Type::Normal);
+            let mut time = None;
+            {
+                let l = db.lock();
+                let ids = recording_id..recording_id + 1;
+                l.list_recordings_by_id(stream_id, ids, &mut |r| {
+                    time = Some(
+                        r.start
+                            + recording::Duration(i64::from(rescale(
+                                media_off_90k.start,
+                                r.media_duration_90k,
+                                r.wall_duration_90k,
+                            ))),
+                    );
+                    builder.append(&l, &r, media_off_90k.clone(), true)
+                })?;
+            }
+            let time = time.ok_or_else(|| {
+                err!(
+                    NotFound,
+                    msg("recording {stream_id}/{recording_id} is gone")
+                )
+            })?;
+            let mut v = Vec::new();
+            builder
+                .build(db.clone(), dirs_by_stream_id)?
+                .append_into_vec(&mut v)
+                .await?;
+            Ok((time, v))
+        };
+        self.get_or_decode(stream_id, key, input).await
+    }
+
+    /// Returns the cached decode of `key` if there is one, or otherwise decodes `input`.
+    async fn get_or_decode(
+        &self,
+        stream_id: i32,
+        key: Key,
+        input: impl Future<Output = Result<(recording::Time, Vec<u8>), Error>>,
+    ) -> Result<Decoded, Error> {
+        let slot = self
+            .streams
+            .lock()
+            .unwrap()
+            .entry(stream_id)
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some((k, d)) = slot.as_ref() {
+            if *k == key {
+                return Ok(d.clone());
+            }
+        }
+        let (time, input) = input.await?;
+        let _permit = self
+            .workers
+            .acquire()
+            .await
+            .expect("semaphore is never closed");
+        let decode = self.decode;
+        let jpeg = tokio::task::spawn_blocking(move || decode(input))
+            .await
+            .map_err(|e| err!(Internal, source(e)))??;
+        let decoded = Decoded { time, jpeg };
+        *slot = Some((key, decoded.clone()));
+        Ok(decoded)
+    }
+}
+
+/// Decodes the first video frame of the `.mp4` file `input` to JPEG with `ffmpeg`.
+#[cfg(feature = "ffmpeg")]
+fn ffmpeg_decode(input: Vec<u8>) -> Result<Bytes, Error> {
+    use std::io::Write as _;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-hide_banner",
+            "-loglevel",
+            "error",
+            "-hwaccel",
+            "auto",
+            "-f",
+            "mp4",
+            "-i",
+            "pipe:0",
+            "-frames:v",
+            "1",
+            "-f",
+            "image2pipe",
+            "-c:v",
+            "mjpeg",
+            "pipe:1",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| err!(e, msg("unable to run ffmpeg")))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let output = std::thread::scope(|s| {
+        s.spawn(move || {
+            // ffmpeg may exit without reading all of its input; its stderr says why.
+            let _ = stdin.write_all(&input);
+        });
+        child.wait_with_output()
+    })?;
+    if !output.status.success() || output.stdout.is_empty() {
+        bail!(
+            Unknown,
+            msg(
+                "ffmpeg couldn't decode key frame ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        );
+    }
+    Ok(Bytes::from(output.stdout))
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn ffmpeg_decode(_input: Vec<u8>) -> Result<Bytes, Error> {
+    bail!(
+        FailedPrecondition,
+        msg("decoding key frames requires building with --features=ffmpeg")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn key(recording: i32) -> Key {
+        Key {
+            open_id: 1,
+            recording,
+            media_off_90k: 0..3000,
+        }
+    }
+
+    async fn input(len: usize) -> Result<(recording::Time, Vec<u8>), Error> {
+        Ok((recording::Time(42), vec![0; len]))
+    }
+
+    #[tokio::test]
+    async fn caches_per_key() {
+        static DECODES: AtomicUsize = AtomicUsize::new(0);
+        fn decode(input: Vec<u8>) -> Result<Bytes, Error> {
+            DECODES.fetch_add(1, Ordering::SeqCst);
+            Ok(Bytes::from(input))
+        }
+        let d = Decoder::with_decode_fn(1, decode);
+        let a = d.get_or_decode(1, key(1), input(1)).await.unwrap();
+        assert_eq!(a.time, recording::Time(42));
+        assert_eq!(&a.jpeg[..], &[0]);
+        let a = d.get_or_decode(1, key(1), input(2)).await.unwrap();
+        assert_eq!(&a.jpeg[..], &[0]);
+        assert_eq!(DECODES.load(Ordering::SeqCst), 1);
+
+        // Concurrent requests for a new key frame share one decode.
+        let (b1, b2) = tokio::join!(
+            d.get_or_decode(1, key(2), input(3)),
+            d.get_or_decode(1, key(2), input(4)),
+        );
+        assert_eq!(b1.unwrap().jpeg.len(), 3);
+        assert_eq!(b2.unwrap().jpeg.len(), 3);
+        assert_eq!(DECODES.load(Ordering::SeqCst), 2);
+
+        // Other streams have their own cache.
+        d.get_or_decode(2, key(2), input(5)).await.unwrap();
+        assert_eq!(DECODES.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_cached() {
+        fn decode(input: Vec<u8>) -> Result<Bytes, Error> {
+            if input.len() == 1 {
+                bail!(Unknown, msg("corrupt"));
+            }
+            Ok(Bytes::from(input))
+        }
+        let d = Decoder::with_decode_fn(1, decode);
+        d.get_or_decode(1, key(1), input(1)).await.unwrap_err();
+        let a = d.get_or_decode(1, key(1), input(2)).await.unwrap();
+        assert_eq!(a.jpeg.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn bounds_workers() {
+        static RUNNING: AtomicUsize = AtomicUsize::new(0);
+        static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+        fn decode(input: Vec<u8>) -> Result<Bytes, Error> {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            Ok(Bytes::from(input))
+        }
+        let d = Decoder::with_decode_fn(2, decode);
+        let (a, b, c) = tokio::join!(
+            d.get_or_decode(1, key(1), input(1)),
+            d.get_or_decode(2, key(1), input(1)),
+            d.get_or_decode(3, key(1), input(1)),
+        );
+        a.unwrap();
+        b.unwrap();
+        c.unwrap();
+        assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 2);
+    }
+}
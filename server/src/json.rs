@@ -9,6 +9,7 @@ use base::{err, Error};
 use db::auth::SessionHash;
 use serde::ser::{Error as _, SerializeMap, SerializeSeq, Serializer};
 use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::ops::Not;
 use uuid::Uuid;
 
@@ -63,6 +64,16 @@ pub struct Camera<'a> {
     pub id: i32,
     pub short_name: &'a str,
 
+    // Descriptive metadata from the config, included even without `config`.
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub location: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coordinates: Option<&'a db::json::Coordinates>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction_deg: Option<u16>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    pub tags: &'a [String],
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub config: Option<&'a db::json::CameraConfig>,
 
@@ -88,6 +99,25 @@ pub struct Stream<'a> {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub config: Option<&'a db::json::StreamConfig>,
+
+    /// The streamer's most recent failure, present only with `config` and while the stream
+    /// isn't receiving frames.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<StreamError<'a>>,
+
+    /// True while the stream's totals and `days` are still being loaded in the background.
+    #[serde(skip_serializing_if = "Not::not")]
+    pub loading: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamError<'a> {
+    pub time_90k: Time,
+
+    /// `auth`, `network`, or `other`.
+    pub kind: &'static str,
+    pub message: &'a str,
 }
 
 #[derive(Serialize)]
@@ -126,6 +156,197 @@ pub struct LogoutRequest<'a> {
     pub csrf: &'a str,
 }
 
+/// Request to `POST /api/webauthn/register/options`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnRegisterOptionsRequest<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+}
+
+/// Response to `POST /api/webauthn/register/options`, to be passed (after decoding binary fields
+/// from base64url) to `navigator.credentials.create` as `PublicKeyCredentialCreationOptions`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnCreationOptions<'a> {
+    pub challenge: String,
+    pub rp: WebAuthnRelyingParty<'a>,
+    pub user: WebAuthnUserEntity<'a>,
+    pub pub_key_cred_params: Vec<WebAuthnCredentialParameters>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<WebAuthnCredentialDescriptor>,
+    pub authenticator_selection: WebAuthnAuthenticatorSelection,
+}
+
+#[derive(Serialize)]
+pub struct WebAuthnRelyingParty<'a> {
+    pub id: &'a str,
+    pub name: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnUserEntity<'a> {
+    /// The user handle, base64url-encoded.
+    pub id: String,
+    pub name: &'a str,
+    pub display_name: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct WebAuthnCredentialParameters {
+    #[serde(rename = "type")]
+    pub type_: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Serialize)]
+pub struct WebAuthnCredentialDescriptor {
+    #[serde(rename = "type")]
+    pub type_: &'static str,
+
+    /// The credential id, base64url-encoded.
+    pub id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnAuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub user_verification: &'static str,
+}
+
+/// Request to `POST /api/webauthn/register`, with fields from the
+/// `AuthenticatorAttestationResponse` base64url-encoded.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnRegisterRequest<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub name: String,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: &'a str,
+    pub attestation_object: &'a str,
+}
+
+/// Request to `POST /api/webauthn/login/options`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnLoginOptionsRequest<'a> {
+    /// If specified, limits the login to this user's credentials. Otherwise, the authenticator
+    /// must offer a discoverable credential (passkey).
+    #[serde(borrow)]
+    pub username: Option<&'a str>,
+}
+
+/// Response to `POST /api/webauthn/login/options`, to be passed (after decoding binary fields
+/// from base64url) to `navigator.credentials.get` as `PublicKeyCredentialRequestOptions`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnRequestOptions<'a> {
+    pub challenge: String,
+    pub rp_id: &'a str,
+    pub allow_credentials: Vec<WebAuthnCredentialDescriptor>,
+    pub timeout: u64,
+    pub user_verification: &'static str,
+}
+
+/// Request to `POST /api/webauthn/login`, with the credential id and the fields from the
+/// `AuthenticatorAssertionResponse` base64url-encoded.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnLoginRequest<'a> {
+    pub id: &'a str,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: &'a str,
+    pub authenticator_data: &'a str,
+    pub signature: &'a str,
+    pub user_handle: Option<&'a str>,
+}
+
+/// Response to `GET /api/users/<id>/webauthn-credentials`.
+#[derive(Serialize)]
+pub struct GetWebAuthnCredentialsResponse {
+    pub credentials: Vec<WebAuthnCredential>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebAuthnCredential {
+    pub id: i32,
+    pub name: String,
+    pub creation_time_sec: i64,
+    pub last_use_time_sec: Option<i64>,
+}
+
+impl From<&db::WebAuthnCredential> for WebAuthnCredential {
+    fn from(c: &db::WebAuthnCredential) -> Self {
+        WebAuthnCredential {
+            id: c.id,
+            name: c.name.clone(),
+            creation_time_sec: c.creation_time_sec,
+            last_use_time_sec: c.last_use_time_sec,
+        }
+    }
+}
+
+/// Request body to `PUT /api/signal-types/<uuid>`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PutSignalType<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub name: String,
+    pub states: Vec<PutSignalTypeState>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PutSignalTypeState {
+    pub value: u8,
+    pub name: String,
+
+    #[serde(default)]
+    pub motion: bool,
+
+    #[serde(default)]
+    pub color: String,
+}
+
+/// Request body to `PUT /api/signals/<uuid>`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PutSignal<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub short_name: String,
+
+    #[serde(rename = "type")]
+    pub type_: Uuid,
+
+    /// Associated cameras' uuids to `direct` or `indirect`.
+    #[serde(default)]
+    pub cameras: BTreeMap<Uuid, String>,
+}
+
+/// Response to `PUT /api/signals/<uuid>`.
+#[derive(Serialize)]
+pub struct PutSignalResponse {
+    pub id: u32,
+}
+
+/// Request body to `DELETE /api/signal-types/<uuid>` or `DELETE /api/signals/<uuid>`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct DeleteSignal<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PostSignalsRequest<'a> {
@@ -151,11 +372,25 @@ pub struct Signals {
     pub states: Vec<u16>,
 }
 
+/// Hourly signal summaries, as returned by `GET /api/signals?summary=hour`.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignalHours {
+    pub start_times_90k: Vec<Time>,
+    pub signal_ids: Vec<u32>,
+
+    /// `states[i][j]` is the time in 90 kHz units spent in state `j+1`.
+    pub states: Vec<Vec<u64>>,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SignalType<'a> {
     pub uuid: Uuid,
 
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub name: &'a str,
+
     #[serde(serialize_with = "SignalType::serialize_states")]
     pub states: &'a db::signal::Type,
 }
@@ -182,6 +417,10 @@ impl<'a> Camera<'a> {
             uuid: c.uuid,
             id: c.id,
             short_name: &c.short_name,
+            location: &c.config.location,
+            coordinates: c.config.coordinates.as_ref(),
+            direction_deg: c.config.direction_deg,
+            tags: &c.config.tags,
             config: match include_config {
                 false => None,
                 true => Some(&c.config),
@@ -245,6 +484,16 @@ impl<'a> Stream<'a> {
                 false => None,
                 true => Some(&s.config),
             },
+            last_error: s
+                .last_error
+                .as_ref()
+                .filter(|_| include_config)
+                .map(|e| StreamError {
+                    time_90k: e.time,
+                    kind: e.kind.as_str(),
+                    message: &e.message,
+                }),
+            loading: !s.loaded,
         }))
     }
 
@@ -333,6 +582,7 @@ impl<'a> SignalType<'a> {
     pub fn wrap(uuid: Uuid, type_: &'a db::signal::Type) -> Self {
         SignalType {
             uuid,
+            name: &type_.config.name,
             states: type_,
         }
     }
@@ -486,6 +736,99 @@ pub struct Recording {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end_reason: Option<String>,
+
+    #[serde(skip_serializing_if = "is_zero")]
+    pub packets_lost: i64,
+
+    #[serde(skip_serializing_if = "is_zero")]
+    pub damaged_frames: i64,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub video_sample_entry_changes: Vec<VideoSampleEntryChange>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRuns {
+    pub runs: Vec<Run>,
+}
+
+/// A run: the recordings made from a single RTSP session.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub run_start_id: i32,
+
+    /// The first surviving recording, which differs from `run_start_id` if retention has deleted
+    /// the start of the run.
+    pub start_id: i32,
+    pub end_id: i32,
+    pub open_id: u32,
+    pub start_time_90k: i64,
+    pub end_time_90k: i64,
+    pub sample_file_bytes: i64,
+    pub video_samples: i64,
+
+    #[serde(skip_serializing_if = "Not::not")]
+    pub growing: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListEvents {
+    pub events: Vec<Event>,
+}
+
+/// An entry of the server's event journal.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub time_90k: i64,
+    pub kind: &'static str,
+
+    /// The camera and stream concerned, if any and if they still exist.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_uuid: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_type: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub message: String,
+}
+
+/// Details of the RTSP session which produced a run.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtspSession {
+    /// The SDP, with any invalid UTF-8 replaced.
+    pub sdp: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+}
+
+impl RtspSession {
+    pub fn from(s: &db::RtspSession) -> Self {
+        RtspSession {
+            sdp: String::from_utf8_lossy(&s.sdp).into_owned(),
+            transport: s.transport.clone(),
+            server: s.server.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoSampleEntryChange {
+    pub id: i32,
+    pub start_time_90k: i64,
+    pub video_sample_entry_id: i32,
 }
 
 #[derive(Debug, Serialize)]
@@ -558,6 +901,16 @@ pub struct UserSubset<'a> {
 
     pub disabled: Option<bool>,
 
+    /// An optional expiration time, in seconds since epoch; `Some(None)` indicates none.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub expiration_time_sec: Option<Option<i64>>,
+
+    /// An optional daily download quota in bytes; `Some(None)` indicates none.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub download_quota_bytes_per_day: Option<Option<u64>>,
+
+    pub tag_downloads: Option<bool>,
+
     pub preferences: Option<db::json::UserPreferences>,
 
     /// An optional password value.
@@ -568,6 +921,13 @@ pub struct UserSubset<'a> {
     pub password: Option<Option<&'a str>>,
 
     pub permissions: Option<Permissions>,
+
+    /// An optional permission template id.
+    ///
+    /// When set, `permissions` is copied from the template and follows its later changes.
+    /// `Some(None)` unassigns the template, keeping the current permissions.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub permission_template_id: Option<Option<i32>>,
 }
 
 impl<'a> From<&'a db::User> for UserSubset<'a> {
@@ -575,13 +935,21 @@ impl<'a> From<&'a db::User> for UserSubset<'a> {
         Self {
             username: Some(&u.username),
             disabled: Some(u.config.disabled),
+            expiration_time_sec: Some(u.config.expiration_time_sec),
+            download_quota_bytes_per_day: Some(u.config.download_quota_bytes_per_day),
+            tag_downloads: Some(u.config.tag_downloads),
             preferences: Some(u.config.preferences.clone()),
             password: Some(u.has_password().then_some("(censored)")),
             permissions: Some(u.permissions.clone().into()),
+            permission_template_id: Some(u.permission_template_id),
         }
     }
 }
 
+fn is_zero(v: &i64) -> bool {
+    *v == 0
+}
+
 // Any value that is present is considered Some value, including null.
 // https://github.com/serde-rs/serde/issues/984#issuecomment-314143738
 fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
@@ -594,30 +962,63 @@ where
 
 /// API/config analog of `Permissions` defined in `db/proto/schema.proto`.
 #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
-#[serde(deny_unknown_fields)]
+#[serde(from = "PermissionsIn")]
 #[serde(rename_all = "camelCase")]
 pub struct Permissions {
+    pub view_live: bool,
+    pub download_recordings: bool,
+    pub read_camera_configs: bool,
+    pub update_signals: bool,
+    pub admin_users: bool,
+}
+
+/// Deserialized form of [`Permissions`], which also accepts the obsolete `viewVideo` as
+/// shorthand for both `viewLive` and `downloadRecordings`, so existing configs and scripts
+/// keep working.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+struct PermissionsIn {
     #[serde(default)]
-    pub view_video: bool,
+    view_video: bool,
 
     #[serde(default)]
-    pub read_camera_configs: bool,
+    view_live: bool,
 
     #[serde(default)]
-    pub update_signals: bool,
+    download_recordings: bool,
 
     #[serde(default)]
-    pub admin_users: bool,
+    read_camera_configs: bool,
+
+    #[serde(default)]
+    update_signals: bool,
+
+    #[serde(default)]
+    admin_users: bool,
+}
+
+impl From<PermissionsIn> for Permissions {
+    fn from(p: PermissionsIn) -> Self {
+        Self {
+            view_live: p.view_live || p.view_video,
+            download_recordings: p.download_recordings || p.view_video,
+            read_camera_configs: p.read_camera_configs,
+            update_signals: p.update_signals,
+            admin_users: p.admin_users,
+        }
+    }
 }
 
 impl From<Permissions> for db::schema::Permissions {
     fn from(p: Permissions) -> Self {
         Self {
-            view_video: p.view_video,
+            view_live: p.view_live,
+            download_recordings: p.download_recordings,
             read_camera_configs: p.read_camera_configs,
             update_signals: p.update_signals,
             admin_users: p.admin_users,
-            special_fields: Default::default(),
+            ..Default::default()
         }
     }
 }
@@ -625,7 +1026,8 @@ impl From<Permissions> for db::schema::Permissions {
 impl From<db::schema::Permissions> for Permissions {
     fn from(p: db::schema::Permissions) -> Self {
         Self {
-            view_video: p.view_video,
+            view_live: p.view_live,
+            download_recordings: p.download_recordings,
             read_camera_configs: p.read_camera_configs,
             update_signals: p.update_signals,
             admin_users: p.admin_users,
@@ -645,8 +1047,195 @@ pub struct UserWithId<'a> {
     pub user: UserSubset<'a>,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PostPermissionTemplates<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub template: PermissionTemplateSubset<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PatchPermissionTemplate<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub update: PermissionTemplateSubset<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetActivityResponse {
+    pub activities: Vec<Activity>,
+}
+
+/// A live view or download in progress.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub id: u64,
+    pub kind: &'static str,
+    pub user: Option<String>,
+    pub camera_uuid: Uuid,
+    pub stream: &'static str,
+    pub start_time_90k: Time,
+    pub bytes: u64,
+
+    /// The average since `start_time_90k`.
+    pub bytes_per_sec: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct DeleteActivity<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+}
+
+/// A request to update a camera's descriptive metadata.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PatchCamera<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub update: CameraMetadataSubset,
+}
+
+/// Camera metadata fields to set; absent fields are left unchanged.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct CameraMetadataSubset {
+    pub location: Option<String>,
+
+    /// `Some(None)` clears the coordinates.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub coordinates: Option<Option<db::json::Coordinates>>,
+
+    /// `Some(None)` clears the direction.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub direction_deg: Option<Option<u16>>,
+
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct DeletePermissionTemplate<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PermissionTemplateSubset<'a> {
+    #[serde(borrow)]
+    pub name: Option<&'a str>,
+
+    pub permissions: Option<Permissions>,
+}
+
+impl<'a> From<&'a db::PermissionTemplate> for PermissionTemplateSubset<'a> {
+    fn from(t: &'a db::PermissionTemplate) -> Self {
+        Self {
+            name: Some(&t.name),
+            permissions: Some(t.permissions.clone().into()),
+        }
+    }
+}
+
+/// Response to `GET /api/permission-templates`.
+#[derive(Serialize)]
+pub struct GetPermissionTemplatesResponse<'a> {
+    pub templates: Vec<PermissionTemplateWithId<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct PermissionTemplateWithId<'a> {
+    pub id: i32,
+    pub template: PermissionTemplateSubset<'a>,
+}
+
+/// Response to `POST /api/permission-templates`.
+#[derive(Serialize)]
+pub struct PostPermissionTemplatesResponse {
+    pub id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct PostStreamMove<'a> {
+    #[serde(borrow)]
+    pub csrf: Option<&'a str>,
+    pub sample_file_dir_path: std::path::PathBuf,
+}
+
 /// Response to `PUT /api/users/`.
 #[derive(Serialize)]
 pub struct PutUsersResponse {
     pub id: i32,
 }
+
+/// Response to `GET /api/ui-version`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UiVersion {
+    pub version: String,
+}
+
+/// Response to `GET /api/health`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    pub status: &'static str,
+    pub recent_panic_count: usize,
+
+    /// Details of recent panics, present only for callers with `read_camera_configs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recent_panics: Option<Vec<StreamerPanic>>,
+
+    /// Sample file directories currently being scanned for files to abandon, present only for
+    /// callers with `read_camera_configs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_scans: Option<Vec<DirScan>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirScan {
+    pub path: String,
+    pub files_scanned: u64,
+    pub files_abandoned: u64,
+}
+
+impl DirScan {
+    /// Lists the scans in progress.
+    pub fn list(db: &db::LockedDatabase) -> Vec<Self> {
+        db.sample_file_dirs_by_id()
+            .values()
+            .filter_map(|d| {
+                let p = d.get().ok()?.scan_progress()?;
+                Some(DirScan {
+                    path: d.path.display().to_string(),
+                    files_scanned: p.files_scanned,
+                    files_abandoned: p.files_abandoned,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamerPanic {
+    pub stream: String,
+    pub time_sec: i64,
+    pub message: String,
+}
@@ -10,14 +10,25 @@ use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use tracing::{debug, error};
 
+mod audio_level;
+#[cfg(feature = "ffmpeg")]
+mod bind_proxy;
 mod body;
 mod cmds;
+mod decoder;
+#[cfg(feature = "ffmpeg")]
+mod ffmpeg;
 mod json;
+mod mkv;
 mod mp4;
+mod preview;
+mod rtsp_capture;
 mod slices;
 mod stream;
 mod streamer;
 mod web;
+mod webauthn;
+mod zip;
 
 #[cfg(feature = "bundled-ui")]
 mod bundled_ui;
@@ -32,27 +43,35 @@ const VERSION: &str = env!("VERSION");
 #[bpaf(options, version(VERSION))]
 enum Args {
     // See docstrings of `cmds::*::Args` structs for a description of the respective subcommands.
+    BenchIngest(#[bpaf(external(cmds::bench_ingest::args))] cmds::bench_ingest::Args),
     Check(#[bpaf(external(cmds::check::args))] cmds::check::Args),
     Config(#[bpaf(external(cmds::config::args))] cmds::config::Args),
     Init(#[bpaf(external(cmds::init::args))] cmds::init::Args),
     Login(#[bpaf(external(cmds::login::args))] cmds::login::Args),
+    Rekey(#[bpaf(external(cmds::rekey::args))] cmds::rekey::Args),
+    Replay(#[bpaf(external(cmds::replay::args))] cmds::replay::Args),
     Run(#[bpaf(external(cmds::run::args))] cmds::run::Args),
     Sql(#[bpaf(external(cmds::sql::args))] cmds::sql::Args),
     Ts(#[bpaf(external(cmds::ts::args))] cmds::ts::Args),
     Upgrade(#[bpaf(external(cmds::upgrade::args))] cmds::upgrade::Args),
+    Users(#[bpaf(external(cmds::users::args))] cmds::users::Args),
 }
 
 impl Args {
     fn run(self) -> Result<i32, Error> {
         match self {
+            Args::BenchIngest(a) => cmds::bench_ingest::run(a),
             Args::Check(a) => cmds::check::run(a),
             Args::Config(a) => cmds::config::run(a),
             Args::Init(a) => cmds::init::run(a),
             Args::Login(a) => cmds::login::run(a),
+            Args::Rekey(a) => cmds::rekey::run(a),
+            Args::Replay(a) => cmds::replay::run(a),
             Args::Run(a) => cmds::run::run(a),
             Args::Sql(a) => cmds::sql::run(a),
             Args::Ts(a) => cmds::ts::run(a),
             Args::Upgrade(a) => cmds::upgrade::run(a),
+            Args::Users(a) => cmds::users::run(a),
         }
     }
 }
@@ -0,0 +1,1145 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `.mkv` virtual file serving.
+//!
+//! The `mkv` module builds virtual Matroska files from the same recording segments as
+//! [`crate::mp4`]. Like `.mp4`s, these support HTTP range serving and are suitable for download.
+//! Elements are arranged as follows:
+//!
+//! ```text
+//! * EBML (header)
+//! * Segment
+//! ** SeekHead (positions of Info, Tracks, and Cues)
+//! ** Info
+//! ** Tracks
+//! *** TrackEntry (video)
+//! ** Cluster (one per key frame)
+//! *** Timestamp
+//! *** SimpleBlock (one per frame)
+//! ** Cues (one CuePoint per cluster)
+//! ```
+//!
+//! There's a single video track. When a segment's video sample entry differs from the track's
+//! `CodecPrivate`, its parameter sets are prepended to each of its key frames, so players can
+//! follow mid-file parameter changes such as a resolution switch. Only H.264 is supported.
+//!
+//! Unlike in a `.mp4`, each frame's data is preceded by a small block header, so a segment's
+//! clusters are generated on demand by interleaving headers with sample file reads. Every size
+//! field has a fixed width, so the file's length is known without reading any video indexes.
+//! One consequence is that a frame's timestamp must be within about 32 seconds of its cluster's
+//! key frame; longer GOPs fail when served.
+
+use crate::body::{wrap_error, BoxedError, Chunk};
+use crate::slices::{self, Slices};
+use base::{bail, err, Error, ErrorKind, ResultExt};
+use db::dir;
+use db::recording::{self, rescale};
+use futures::stream::{self, Stream};
+use http::header::HeaderValue;
+use reffers::ARefss;
+use smallvec::SmallVec;
+use std::cmp;
+use std::fmt;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tracing::{error, trace};
+
+/// This value should be incremented any time a change is made to this file that causes different
+/// bytes or headers to be output for a particular set of `FileBuilder` options. Incrementing this
+/// value will cause the etag to change as well.
+const FORMAT_VERSION: [u8; 1] = [0x00];
+
+// Element ids, from RFC 8794 (EBML) and RFC 9559 (Matroska).
+const EBML: &[u8] = &[0x1A, 0x45, 0xDF, 0xA3];
+const EBML_VERSION: &[u8] = &[0x42, 0x86];
+const EBML_READ_VERSION: &[u8] = &[0x42, 0xF7];
+const EBML_MAX_ID_LENGTH: &[u8] = &[0x42, 0xF2];
+const EBML_MAX_SIZE_LENGTH: &[u8] = &[0x42, 0xF3];
+const DOC_TYPE: &[u8] = &[0x42, 0x82];
+const DOC_TYPE_VERSION: &[u8] = &[0x42, 0x87];
+const DOC_TYPE_READ_VERSION: &[u8] = &[0x42, 0x85];
+const SEGMENT: &[u8] = &[0x18, 0x53, 0x80, 0x67];
+const SEEK_HEAD: &[u8] = &[0x11, 0x4D, 0x9B, 0x74];
+const SEEK: &[u8] = &[0x4D, 0xBB];
+const SEEK_ID: &[u8] = &[0x53, 0xAB];
+const SEEK_POSITION: &[u8] = &[0x53, 0xAC];
+const INFO: &[u8] = &[0x15, 0x49, 0xA9, 0x66];
+const TIMESTAMP_SCALE: &[u8] = &[0x2A, 0xD7, 0xB1];
+const DURATION: &[u8] = &[0x44, 0x89];
+const DATE_UTC: &[u8] = &[0x44, 0x61];
+const MUXING_APP: &[u8] = &[0x4D, 0x80];
+const WRITING_APP: &[u8] = &[0x57, 0x41];
+const TRACKS: &[u8] = &[0x16, 0x54, 0xAE, 0x6B];
+const TRACK_ENTRY: &[u8] = &[0xAE];
+const TRACK_NUMBER: &[u8] = &[0xD7];
+const TRACK_UID: &[u8] = &[0x73, 0xC5];
+const TRACK_TYPE: &[u8] = &[0x83];
+const FLAG_LACING: &[u8] = &[0x9C];
+const CODEC_ID: &[u8] = &[0x86];
+const CODEC_PRIVATE: &[u8] = &[0x63, 0xA2];
+const VIDEO: &[u8] = &[0xE0];
+const PIXEL_WIDTH: &[u8] = &[0xB0];
+const PIXEL_HEIGHT: &[u8] = &[0xBA];
+const DISPLAY_WIDTH: &[u8] = &[0x54, 0xB0];
+const DISPLAY_HEIGHT: &[u8] = &[0x54, 0xBA];
+const CLUSTER: &[u8] = &[0x1F, 0x43, 0xB6, 0x75];
+const TIMESTAMP: &[u8] = &[0xE7];
+const SIMPLE_BLOCK: u8 = 0xA3;
+const CUES: &[u8] = &[0x1C, 0x53, 0xBB, 0x6B];
+const CUE_POINT: &[u8] = &[0xBB];
+const CUE_TIME: &[u8] = &[0xB3];
+const CUE_TRACK_POSITIONS: &[u8] = &[0xB7];
+const CUE_TRACK: &[u8] = &[0xF7];
+const CUE_CLUSTER_POSITION: &[u8] = &[0xF1];
+
+/// The video track's number, as referenced by each block.
+const VIDEO_TRACK: u64 = 1;
+
+/// Timestamps are in milliseconds.
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+
+/// The number of 90 kHz units per timestamp tick.
+const TICKS_90K: i64 = 90;
+
+/// The length of a cluster's id, size, and `Timestamp` element.
+const CLUSTER_HEADER_LEN: u64 = 4 + 8 + 10;
+
+/// The length of a `SimpleBlock`'s id, 4-byte size, track number, timestamp, and flags.
+const BLOCK_HEADER_LEN: u64 = 1 + 4 + 1 + 2 + 1;
+
+/// The length of the `Cues` element's id and size.
+const CUES_HEADER_LEN: u64 = 4 + 8;
+
+/// The length of a `CuePoint`: id and size, `CueTime`, and `CueTrackPositions` holding
+/// `CueTrack` and `CueClusterPosition`.
+const CUE_POINT_LEN: u64 = 1 + 8 + 10 + (1 + 8 + 10 + 10);
+
+/// The target length of each generated chunk of cluster data.
+const CLUSTER_CHUNK_LEN: usize = 1 << 16;
+
+/// Appends an 8-byte element data size.
+fn put_size(buf: &mut Vec<u8>, size: u64) {
+    debug_assert!(size < (1 << 56) - 1);
+    buf.push(0x01);
+    buf.extend_from_slice(&size.to_be_bytes()[1..]);
+}
+
+/// Appends an unsigned integer element, returning the position of its 8-byte value.
+fn put_uint(buf: &mut Vec<u8>, id: &[u8], v: u64) -> usize {
+    buf.extend_from_slice(id);
+    buf.push(0x88);
+    let pos = buf.len();
+    buf.extend_from_slice(&v.to_be_bytes());
+    pos
+}
+
+fn put_bytes(buf: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+    buf.extend_from_slice(id);
+    put_size(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Appends a master element whose children are written by `f`.
+fn put_master<R>(buf: &mut Vec<u8>, id: &[u8], f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+    buf.extend_from_slice(id);
+    let size_pos = buf.len();
+    put_size(buf, 0);
+    let r = f(buf);
+    let size = (buf.len() - size_pos - 8) as u64;
+    buf[size_pos + 1..size_pos + 8].copy_from_slice(&size.to_be_bytes()[1..]);
+    r
+}
+
+/// Returns the parameter sets within an `avcC` box's contents, each with a 4-byte length prefix
+/// as in the sample data.
+fn parameter_sets(avcc: &[u8]) -> Result<Vec<u8>, Error> {
+    let bad = || err!(InvalidArgument, msg("bad avcC"));
+    if avcc.len() < 6 || avcc[0] != 1 {
+        return Err(bad());
+    }
+    if avcc[4] & 0x03 != 3 {
+        bail!(
+            InvalidArgument,
+            msg(
+                "avcC has unsupported NAL length size {}",
+                (avcc[4] & 0x03) + 1
+            )
+        );
+    }
+    let mut out = Vec::new();
+    let mut i = 5;
+    for mask in [0x1F, 0xFF] {
+        let n = avcc.get(i).ok_or_else(bad)? & mask;
+        i += 1;
+        for _ in 0..n {
+            let len = usize::from(u16::from_be_bytes(
+                avcc.get(i..i + 2).ok_or_else(bad)?.try_into().unwrap(),
+            ));
+            let nal = avcc.get(i + 2..i + 2 + len).ok_or_else(bad)?;
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+            out.extend_from_slice(nal);
+            i += 2 + len;
+        }
+    }
+    Ok(out)
+}
+
+/// Returns the `avcC` contents of an H.264 video sample entry.
+fn avcc(e: &db::VideoSampleEntry) -> Result<&[u8], Error> {
+    db::h264::avcc(&e.data).ok_or_else(|| {
+        err!(
+            InvalidArgument,
+            msg(".mkv supports only H.264, not {}", e.rfc6381_codec)
+        )
+    })
+}
+
+/// A wrapper around `recording::Segment` that keeps some additional `.mkv`-specific state.
+struct Segment {
+    s: recording::Segment,
+    recording_start: recording::Time,
+    recording_wall_duration_90k: i32,
+    recording_media_duration_90k: i32,
+
+    /// The _desired_, _relative_, _media_ time range covered by this recording, as in
+    /// `mp4::Segment`.
+    rel_media_range_90k: Range<i32>,
+
+    /// The file-relative time of the segment's actual start, in 90 kHz units.
+    offset_90k: i64,
+
+    /// Parameter sets to prepend to each key frame, or empty if the segment uses the track's.
+    parameter_sets: Vec<u8>,
+
+    /// The position of the segment's first cluster, relative to the `Segment` element's data.
+    rel_pos: u64,
+
+    /// The total length of the segment's clusters.
+    len: u64,
+
+    /// The lazily-built layout; errors are logged when building.
+    layout: OnceLock<Result<Arc<Layout>, ()>>,
+}
+
+impl fmt::Debug for Segment {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("mkv::Segment")
+            .field("s", &self.s)
+            .field("recording_start", &self.recording_start)
+            .field("rel_media_range_90k", &self.rel_media_range_90k)
+            .field("offset_90k", &self.offset_90k)
+            .field("parameter_sets_len", &self.parameter_sets.len())
+            .field("rel_pos", &self.rel_pos)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// The layout of a segment's clusters: headers interleaved with ranges of the sample file.
+struct Layout {
+    /// Cluster and block headers and parameter sets, as referenced by `pieces`.
+    headers: Vec<u8>,
+
+    /// Contiguous pieces of the segment's clusters, in order.
+    pieces: Vec<Piece>,
+
+    /// Each key frame cluster's position within the segment's clusters and timestamp.
+    cues: Vec<(u64, u64)>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Piece {
+    /// The starting position within the segment's clusters. The piece ends where the next
+    /// begins.
+    start: u64,
+
+    /// The starting position within `Layout::headers` or the sample file.
+    src: u64,
+    from_file: bool,
+}
+
+impl Segment {
+    fn wall(&self, rel_media_90k: i32) -> i32 {
+        rescale(
+            rel_media_90k,
+            self.recording_media_duration_90k,
+            self.recording_wall_duration_90k,
+        )
+    }
+
+    /// Returns the segment's length, as laid out by `build_layout`.
+    fn compute_len(&self) -> u64 {
+        let clusters = u64::from(self.s.key_frames) + u64::from(self.s.starts_with_nonkey());
+        let r = self.s.sample_file_range();
+        clusters * CLUSTER_HEADER_LEN
+            + u64::from(self.s.frames) * BLOCK_HEADER_LEN
+            + u64::from(self.s.key_frames) * self.parameter_sets.len() as u64
+            + (r.end - r.start)
+    }
+
+    fn layout(&self, db: &db::Database) -> Result<Arc<Layout>, Error> {
+        let layout = self.layout.get_or_init(|| {
+            // Copy the video index out so that the database lock isn't held while building.
+            let video_index = db
+                .lock()
+                .with_recording_playback(self.s.id, &mut |playback| {
+                    Ok(playback.video_index.to_vec())
+                });
+            video_index
+                .and_then(|video_index| {
+                    self.build_layout(&db::RecordingPlayback {
+                        video_index: &video_index,
+                    })
+                })
+                .map(Arc::new)
+                .map_err(|err| {
+                    error!(%err, recording_id = %self.s.id, "unable to build .mkv layout");
+                })
+        });
+        match layout {
+            Ok(l) => Ok(l.clone()),
+            Err(()) => bail!(Unknown, msg("unable to build .mkv layout; see logs")),
+        }
+    }
+
+    fn build_layout(&self, playback: &db::RecordingPlayback) -> Result<Layout, Error> {
+        struct Frame {
+            dts_ms: i64,
+            pts_ms: i64,
+            pos: u64,
+            bytes: u32,
+            is_key: bool,
+        }
+        let mut frames = Vec::with_capacity(usize::from(self.s.frames));
+        let actual_start_90k = self.s.actual_start_90k();
+        self.s.foreach(playback, |it| {
+            let dts_90k = self.offset_90k + i64::from(it.start_90k - actual_start_90k);
+            frames.push(Frame {
+                dts_ms: dts_90k.div_euclid(TICKS_90K),
+                pts_ms: (dts_90k + i64::from(it.composition_offset_90k)).div_euclid(TICKS_90K),
+                pos: it.pos as u64,
+                bytes: it.bytes as u32,
+                is_key: it.is_key(),
+            });
+            Ok(())
+        })?;
+        let mut layout = Layout {
+            headers: Vec::with_capacity(
+                frames.len() * BLOCK_HEADER_LEN as usize
+                    + usize::from(self.s.key_frames)
+                        * (CLUSTER_HEADER_LEN as usize + self.parameter_sets.len()),
+            ),
+            pieces: Vec::with_capacity(2 * frames.len()),
+            cues: Vec::with_capacity(usize::from(self.s.key_frames)),
+        };
+        let mut pos = 0;
+        let mut cluster_start = 0;
+        while cluster_start < frames.len() {
+            let cluster_end = frames[cluster_start + 1..]
+                .iter()
+                .position(|f| f.is_key)
+                .map_or(frames.len(), |i| cluster_start + 1 + i);
+            let cluster = &frames[cluster_start..cluster_end];
+            let cluster_ts = cluster[0].dts_ms;
+            let size = 10
+                + cluster
+                    .iter()
+                    .map(|f| {
+                        BLOCK_HEADER_LEN
+                            + u64::from(f.bytes)
+                            + if f.is_key {
+                                self.parameter_sets.len() as u64
+                            } else {
+                                0
+                            }
+                    })
+                    .sum::<u64>();
+            if cluster[0].is_key {
+                layout.cues.push((pos, cluster_ts as u64));
+            }
+            layout.pieces.push(Piece {
+                start: pos,
+                src: layout.headers.len() as u64,
+                from_file: false,
+            });
+            layout.headers.extend_from_slice(CLUSTER);
+            put_size(&mut layout.headers, size);
+            put_uint(&mut layout.headers, TIMESTAMP, cluster_ts as u64);
+            for (i, f) in cluster.iter().enumerate() {
+                let rel_ts = i16::try_from(f.pts_ms - cluster_ts).map_err(|_| {
+                    err!(
+                        OutOfRange,
+                        msg(
+                            "recording {} frame {} is too far from its key frame for .mkv",
+                            self.s.id,
+                            cluster_start + i,
+                        ),
+                    )
+                })?;
+                let param_sets: &[u8] = if f.is_key { &self.parameter_sets } else { &[] };
+                let block_size = 4 + param_sets.len() as u32 + f.bytes;
+                if block_size >= (1 << 28) - 1 {
+                    bail!(
+                        OutOfRange,
+                        msg("recording {} frame is too large", self.s.id)
+                    );
+                }
+                if i > 0 {
+                    layout.pieces.push(Piece {
+                        start: pos,
+                        src: layout.headers.len() as u64,
+                        from_file: false,
+                    });
+                }
+                layout.headers.push(SIMPLE_BLOCK);
+                layout
+                    .headers
+                    .extend_from_slice(&(block_size | (1 << 28)).to_be_bytes());
+                layout.headers.push(0x80 | VIDEO_TRACK as u8);
+                layout.headers.extend_from_slice(&rel_ts.to_be_bytes());
+                layout.headers.push(if f.is_key { 0x80 } else { 0x00 });
+                layout.headers.extend_from_slice(param_sets);
+                pos += BLOCK_HEADER_LEN
+                    + param_sets.len() as u64
+                    + if i == 0 { CLUSTER_HEADER_LEN } else { 0 };
+                layout.pieces.push(Piece {
+                    start: pos,
+                    src: f.pos,
+                    from_file: true,
+                });
+                pos += u64::from(f.bytes);
+            }
+            cluster_start = cluster_end;
+        }
+        if pos != self.len {
+            bail!(
+                Internal,
+                msg(
+                    "recording {} .mkv layout has len {}, expected {}",
+                    self.s.id,
+                    pos,
+                    self.len
+                ),
+            );
+        }
+        Ok(layout)
+    }
+}
+
+#[derive(Default)]
+pub struct FileBuilder {
+    segments: Vec<Segment>,
+    video_sample_entries: SmallVec<[Arc<db::VideoSampleEntry>; 1]>,
+    content_disposition: Option<HeaderValue>,
+
+    /// True if any segment is of a recording which is still being written.
+    growing: bool,
+}
+
+impl FileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves space for the given number of additional segments.
+    pub fn reserve(&mut self, additional: usize) {
+        self.segments.reserve(additional);
+    }
+
+    /// Appends a segment for (a subset of) the given recording, as in
+    /// [`crate::mp4::FileBuilder::append`].
+    pub fn append(
+        &mut self,
+        db: &db::LockedDatabase,
+        row: &db::ListRecordingsRow,
+        rel_media_range_90k: Range<i32>,
+        start_at_key: bool,
+    ) -> Result<(), Error> {
+        // As in `mp4::FileBuilder::append`, refer to the surviving entry if this one was merged.
+        let vse = db
+            .video_sample_entry(row.video_sample_entry_id)
+            .ok_or_else(|| {
+                err!(
+                    Internal,
+                    msg(
+                        "recording {} has unknown video sample entry {}",
+                        row.id,
+                        row.video_sample_entry_id
+                    ),
+                )
+            })?;
+        let remapped;
+        let row = if vse.id != row.video_sample_entry_id {
+            remapped = db::ListRecordingsRow {
+                video_sample_entry_id: vse.id,
+                ..row.clone()
+            };
+            &remapped
+        } else {
+            row
+        };
+        if let Some(prev) = self.segments.last() {
+            if prev.s.have_trailing_zero() {
+                bail!(
+                    InvalidArgument,
+                    msg(
+                        "unable to append recording {} after recording {} with trailing zero",
+                        row.id,
+                        prev.s.id,
+                    ),
+                );
+            }
+        }
+        let s = recording::Segment::new(db, row, rel_media_range_90k.clone(), start_at_key)
+            .err_kind(ErrorKind::Unknown)?;
+        self.segments.push(Segment {
+            s,
+            recording_start: row.start,
+            recording_wall_duration_90k: row.wall_duration_90k,
+            recording_media_duration_90k: row.media_duration_90k,
+            rel_media_range_90k,
+            offset_90k: 0,
+            parameter_sets: Vec::new(),
+            rel_pos: 0,
+            len: 0,
+            layout: OnceLock::new(),
+        });
+        self.growing |= (row.flags & db::RecordingFlags::Growing as i32) != 0;
+        if !self.video_sample_entries.iter().any(|e| e.id == vse.id) {
+            self.video_sample_entries.push(vse.clone());
+        }
+        Ok(())
+    }
+
+    pub fn set_filename(&mut self, filename: &str) -> Result<(), Error> {
+        self.content_disposition = Some(
+            HeaderValue::try_from(format!("attachment; filename=\"{filename}\""))
+                .err_kind(ErrorKind::InvalidArgument)?,
+        );
+        Ok(())
+    }
+
+    /// Builds the `File`, consuming the builder.
+    pub fn build(
+        mut self,
+        db: Arc<db::Database>,
+        dirs_by_stream_id: Arc<::base::FastHashMap<i32, Arc<dir::SampleFileDir>>>,
+    ) -> Result<File, Error> {
+        let Some(track_entry) = self.video_sample_entries.first().cloned() else {
+            bail!(InvalidArgument, msg("no video_sample_entries"));
+        };
+        let codec_private = avcc(&track_entry)?;
+        parameter_sets(codec_private)?; // validates the NAL length size.
+        let mut other_parameter_sets: SmallVec<[(i32, Vec<u8>); 1]> = SmallVec::new();
+        for e in &self.video_sample_entries[1..] {
+            other_parameter_sets.push((e.id, parameter_sets(avcc(e)?)?));
+        }
+
+        let mut etag = blake3::Hasher::new();
+        etag.update(&FORMAT_VERSION[..]);
+        etag.update(b":mkv:");
+        if let Some(cd) = self.content_disposition.as_ref() {
+            etag.update(b":cd:");
+            etag.update(cd.as_bytes());
+        }
+        let mut max_end = None;
+        let mut offset_90k = 0;
+        let mut num_cues = 0;
+        for s in &mut self.segments {
+            let md = s.rel_media_range_90k.clone();
+            s.offset_90k = offset_90k;
+            offset_90k += i64::from(md.end - s.s.actual_start_90k());
+            if let Some((_, p)) = other_parameter_sets
+                .iter()
+                .find(|(id, _)| *id == s.s.video_sample_entry_id())
+            {
+                s.parameter_sets.clone_from(p);
+            }
+            s.len = s.compute_len();
+            num_cues += u64::from(s.s.key_frames);
+            let wall_end = s.recording_start + recording::Duration(i64::from(s.wall(md.end)));
+            max_end = Some(max_end.map_or(wall_end, |e| cmp::max(e, wall_end)));
+
+            etag.update(&s.s.id.0.to_be_bytes());
+            etag.update(&s.recording_start.0.to_be_bytes());
+            etag.update(&s.s.open_id.to_be_bytes());
+            etag.update(&md.start.to_be_bytes());
+            etag.update(&md.end.to_be_bytes());
+        }
+        let start = self.segments.first().map(|s| {
+            s.recording_start + recording::Duration(i64::from(s.wall(s.s.actual_start_90k())))
+        });
+
+        let mut buf = Vec::with_capacity(512 + codec_private.len());
+        put_master(&mut buf, EBML, |b| {
+            put_uint(b, EBML_VERSION, 1);
+            put_uint(b, EBML_READ_VERSION, 1);
+            put_uint(b, EBML_MAX_ID_LENGTH, 4);
+            put_uint(b, EBML_MAX_SIZE_LENGTH, 8);
+            put_bytes(b, DOC_TYPE, b"matroska");
+            put_uint(b, DOC_TYPE_VERSION, 4);
+            put_uint(b, DOC_TYPE_READ_VERSION, 2);
+        });
+        buf.extend_from_slice(SEGMENT);
+        let segment_size_pos = buf.len();
+        put_size(&mut buf, 0);
+        let segment_data_start = buf.len() as u64;
+        let mut seek_targets = vec![INFO, TRACKS];
+        if num_cues > 0 {
+            seek_targets.push(CUES);
+        }
+        let seek_position_pos: Vec<usize> = put_master(&mut buf, SEEK_HEAD, |b| {
+            seek_targets
+                .iter()
+                .map(|id| {
+                    put_master(b, SEEK, |b| {
+                        put_bytes(b, SEEK_ID, id);
+                        put_uint(b, SEEK_POSITION, 0)
+                    })
+                })
+                .collect()
+        });
+        let info_pos = buf.len() as u64;
+        put_master(&mut buf, INFO, |b| {
+            put_uint(b, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+            put_bytes(
+                b,
+                DURATION,
+                &(offset_90k as f64 / TICKS_90K as f64).to_be_bytes(),
+            );
+            if let Some(start) = start {
+                // Nanoseconds since 2001-01-01 00:00:00 UTC.
+                const EPOCH_2001_90K: i128 = 978_307_200 * recording::TIME_UNITS_PER_SEC as i128;
+                let ns = (i128::from(start.0) - EPOCH_2001_90K) * 1_000_000_000
+                    / i128::from(recording::TIME_UNITS_PER_SEC);
+                put_bytes(b, DATE_UTC, &(ns as i64).to_be_bytes());
+            }
+            put_bytes(b, MUXING_APP, b"moonfire-nvr");
+            put_bytes(b, WRITING_APP, b"moonfire-nvr");
+        });
+        let tracks_pos = buf.len() as u64;
+        put_master(&mut buf, TRACKS, |b| {
+            put_master(b, TRACK_ENTRY, |b| {
+                put_uint(b, TRACK_NUMBER, VIDEO_TRACK);
+                put_uint(b, TRACK_UID, VIDEO_TRACK);
+                put_uint(b, TRACK_TYPE, 1); // video
+                put_uint(b, FLAG_LACING, 0);
+                put_bytes(b, CODEC_ID, b"V_MPEG4/ISO/AVC");
+                put_bytes(b, CODEC_PRIVATE, codec_private);
+                put_master(b, VIDEO, |b| {
+                    put_uint(b, PIXEL_WIDTH, u64::from(track_entry.width));
+                    put_uint(b, PIXEL_HEIGHT, u64::from(track_entry.height));
+                    if track_entry.pasp_h_spacing != track_entry.pasp_v_spacing {
+                        let aspect = track_entry.aspect();
+                        put_uint(b, DISPLAY_WIDTH, u64::from(*aspect.numer()));
+                        put_uint(b, DISPLAY_HEIGHT, u64::from(*aspect.denom()));
+                    }
+                });
+            });
+        });
+
+        let mut slices = Slices::new();
+        slices.reserve(2 + self.segments.len());
+        let mut pos = buf.len() as u64;
+        slices.append(Slice {
+            end: pos,
+            t: SliceType::Header,
+        })?;
+        for (i, s) in self.segments.iter_mut().enumerate() {
+            s.rel_pos = pos - segment_data_start;
+            if s.len > 0 {
+                pos += s.len;
+                slices.append(Slice {
+                    end: pos,
+                    t: SliceType::Clusters(i),
+                })?;
+            }
+        }
+        let cues_pos = pos;
+        if num_cues > 0 {
+            pos += CUES_HEADER_LEN + num_cues * CUE_POINT_LEN;
+            slices.append(Slice {
+                end: pos,
+                t: SliceType::Cues,
+            })?;
+        }
+        buf[segment_size_pos + 1..segment_size_pos + 8]
+            .copy_from_slice(&(pos - segment_data_start).to_be_bytes()[1..]);
+        for (&p, target) in seek_position_pos
+            .iter()
+            .zip([info_pos, tracks_pos, cues_pos])
+        {
+            buf[p..p + 8].copy_from_slice(&(target - segment_data_start).to_be_bytes());
+        }
+        trace!("slices: {:?}", slices);
+
+        // As in `mp4::FileBuilder::build`, rely on the etag alone for growing recordings.
+        let last_modified = max_end.filter(|_| !self.growing).map(|e| {
+            ::std::time::UNIX_EPOCH + ::std::time::Duration::from_secs(e.unix_seconds() as u64)
+        });
+        let etag = etag.finalize();
+        Ok(File(Arc::new(FileInner {
+            db,
+            dirs_by_stream_id,
+            segments: self.segments,
+            slices,
+            buf,
+            num_cues,
+            last_modified,
+            etag: HeaderValue::try_from(format!("\"{}\"", etag.to_hex().as_str()))
+                .expect("hex string should be valid UTF-8"),
+            content_disposition: self.content_disposition,
+        })))
+    }
+}
+
+struct FileInner {
+    db: Arc<db::Database>,
+    dirs_by_stream_id: Arc<::base::FastHashMap<i32, Arc<dir::SampleFileDir>>>,
+    segments: Vec<Segment>,
+    slices: Slices<Slice>,
+
+    /// The EBML header and `Segment` element up to the first cluster.
+    buf: Vec<u8>,
+    num_cues: u64,
+    last_modified: Option<SystemTime>,
+    etag: HeaderValue,
+    content_disposition: Option<HeaderValue>,
+}
+
+impl FileInner {
+    fn get_clusters(
+        &self,
+        i: usize,
+        r: Range<u64>,
+    ) -> Box<dyn Stream<Item = Result<Chunk, BoxedError>> + Send + Sync> {
+        let s = &self.segments[i];
+        let layout = match s.layout(&self.db) {
+            Ok(l) => l,
+            Err(e) => {
+                return Box::new(stream::once(futures::future::err::<Chunk, _>(wrap_error(
+                    e,
+                ))))
+            }
+        };
+        let first_piece = layout.pieces.partition_point(|p| p.start <= r.start) - 1;
+
+        // Frames are contiguous within the sample file, so a single read covers all the data
+        // pieces within `r`.
+        let mut file_range: Option<Range<u64>> = None;
+        for (j, p) in layout.pieces.iter().enumerate().skip(first_piece) {
+            if p.start >= r.end {
+                break;
+            }
+            if !p.from_file {
+                continue;
+            }
+            let piece_end = layout.pieces.get(j + 1).map_or(s.len, |n| n.start);
+            let start = p.src + r.start.saturating_sub(p.start);
+            let end = p.src + cmp::min(piece_end, r.end) - p.start;
+            file_range = Some(file_range.map_or(start..end, |f| f.start..end));
+        }
+        let file = match file_range {
+            None => None,
+            Some(f) => match self.dirs_by_stream_id.get(&s.s.id.stream()) {
+                None => {
+                    return Box::new(stream::once(futures::future::err::<Chunk, _>(wrap_error(
+                        err!(NotFound, msg("{}: stream not found", s.s.id)),
+                    ))))
+                }
+                Some(d) => Some(Box::pin(d.open_file(s.s.id, f)) as FileData),
+            },
+        };
+        Box::new(ClusterStream {
+            layout,
+            len: s.len,
+            pos: r.start,
+            end: r.end,
+            piece: first_piece,
+            file,
+            file_buf: Vec::new(),
+            file_buf_pos: 0,
+            out: Vec::new(),
+        })
+    }
+
+    fn get_cues(&self, r: Range<u64>, len: u64) -> Result<Chunk, Error> {
+        let mut v = Vec::with_capacity(usize::try_from(len).unwrap());
+        v.extend_from_slice(CUES);
+        put_size(&mut v, self.num_cues * CUE_POINT_LEN);
+        for s in &self.segments {
+            let layout = s.layout(&self.db)?;
+            for &(pos, ts) in &layout.cues {
+                put_master(&mut v, CUE_POINT, |b| {
+                    put_uint(b, CUE_TIME, ts);
+                    put_master(b, CUE_TRACK_POSITIONS, |b| {
+                        put_uint(b, CUE_TRACK, VIDEO_TRACK);
+                        put_uint(b, CUE_CLUSTER_POSITION, s.rel_pos + pos);
+                    });
+                });
+            }
+        }
+        if v.len() as u64 != len {
+            bail!(Internal, msg("expected cues len {} got {}", len, v.len()));
+        }
+        Ok(ARefss::new(v)
+            .map(|v| &v[r.start as usize..r.end as usize])
+            .into())
+    }
+}
+
+type FileData = Pin<Box<dyn Stream<Item = Result<Vec<u8>, Error>> + Send + Sync>>;
+
+/// A stream of a range of a segment's clusters, interleaving headers from the [`Layout`] with
+/// sample file data.
+struct ClusterStream {
+    layout: Arc<Layout>,
+
+    /// The total length of the segment's clusters.
+    len: u64,
+
+    /// The current position within the segment's clusters.
+    pos: u64,
+    end: u64,
+
+    /// The index within `layout.pieces` of the piece containing `pos`.
+    piece: usize,
+
+    /// The sample file data, positioned at the next data piece byte not in `file_buf`.
+    file: Option<FileData>,
+
+    /// Sample file data read but not yet output, starting from `file_buf_pos`.
+    file_buf: Vec<u8>,
+    file_buf_pos: usize,
+
+    /// Output accumulated for the next chunk.
+    out: Vec<u8>,
+}
+
+impl ClusterStream {
+    fn fail(&mut self, e: Error) -> Poll<Option<Result<Chunk, BoxedError>>> {
+        self.pos = self.end;
+        self.file = None;
+        self.out.clear();
+        Poll::Ready(Some(Err(wrap_error(e))))
+    }
+
+    fn take_out(&mut self) -> Poll<Option<Result<Chunk, BoxedError>>> {
+        Poll::Ready(Some(Ok(Chunk::from(std::mem::take(&mut self.out)))))
+    }
+}
+
+impl Stream for ClusterStream {
+    type Item = Result<Chunk, BoxedError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.pos == this.end {
+                if this.out.is_empty() {
+                    return Poll::Ready(None);
+                }
+                return this.take_out();
+            }
+            if this.out.len() >= CLUSTER_CHUNK_LEN {
+                return this.take_out();
+            }
+            let p = this.layout.pieces[this.piece];
+            let piece_end = this
+                .layout
+                .pieces
+                .get(this.piece + 1)
+                .map_or(this.len, |n| n.start);
+            if this.pos >= piece_end {
+                this.piece += 1;
+                continue;
+            }
+            let want = cmp::min(piece_end, this.end) - this.pos;
+            if !p.from_file {
+                let start = (p.src + this.pos - p.start) as usize;
+                let layout = &this.layout;
+                this.out
+                    .extend_from_slice(&layout.headers[start..start + want as usize]);
+                this.pos += want;
+                continue;
+            }
+            if this.file_buf_pos == this.file_buf.len() {
+                let Some(file) = this.file.as_mut() else {
+                    return this.fail(err!(Internal, msg("no sample file data for .mkv range")));
+                };
+                match file.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok(v))) => {
+                        this.file_buf = v;
+                        this.file_buf_pos = 0;
+                        continue;
+                    }
+                    Poll::Ready(Some(Err(e))) => return this.fail(e),
+                    Poll::Ready(None) => {
+                        return this.fail(err!(
+                            OutOfRange,
+                            msg("sample file ended early at .mkv position {}", this.pos)
+                        ))
+                    }
+                    Poll::Pending if this.out.is_empty() => return Poll::Pending,
+                    Poll::Pending => return this.take_out(),
+                }
+            }
+            let n = cmp::min(want as usize, this.file_buf.len() - this.file_buf_pos);
+            this.out
+                .extend_from_slice(&this.file_buf[this.file_buf_pos..this.file_buf_pos + n]);
+            this.file_buf_pos += n;
+            this.pos += n as u64;
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum SliceType {
+    /// `FileInner::buf`: the EBML header and `Segment` element up to the first cluster.
+    Header,
+
+    /// The clusters of the given segment.
+    Clusters(usize),
+
+    /// The `Cues` element.
+    Cues,
+}
+
+#[derive(Debug)]
+struct Slice {
+    end: u64,
+    t: SliceType,
+}
+
+impl slices::Slice for Slice {
+    type Ctx = File;
+    type Chunk = Chunk;
+
+    fn end(&self) -> u64 {
+        self.end
+    }
+    fn get_range(
+        &self,
+        f: &File,
+        range: Range<u64>,
+        len: u64,
+    ) -> Box<dyn Stream<Item = Result<Self::Chunk, BoxedError>> + Send + Sync> {
+        trace!("getting mkv slice {:?}'s range {:?} / {}", self, range, len);
+        let res = match self.t {
+            SliceType::Header => {
+                let r = ARefss::new(f.0.clone());
+                Ok(r.map(|f| &f.buf[range.start as usize..range.end as usize])
+                    .into())
+            }
+            SliceType::Clusters(i) => return f.0.get_clusters(i, range),
+            SliceType::Cues => f.0.get_cues(range, len),
+        };
+        Box::new(stream::once(futures::future::ready(
+            res.map_err(wrap_error),
+        )))
+    }
+    fn get_slices(ctx: &File) -> &Slices<Self> {
+        &ctx.0.slices
+    }
+}
+
+#[derive(Clone)]
+pub struct File(Arc<FileInner>);
+
+impl http_serve::Entity for File {
+    type Data = Chunk;
+    type Error = BoxedError;
+
+    fn add_headers(&self, hdrs: &mut http::header::HeaderMap) {
+        hdrs.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("video/x-matroska"),
+        );
+        if let Some(cd) = self.0.content_disposition.as_ref() {
+            hdrs.insert(http::header::CONTENT_DISPOSITION, cd.clone());
+        }
+    }
+    fn last_modified(&self) -> Option<SystemTime> {
+        self.0.last_modified
+    }
+    fn etag(&self) -> Option<HeaderValue> {
+        Some(self.0.etag.clone())
+    }
+    fn len(&self) -> u64 {
+        self.0.slices.len()
+    }
+    fn get_range(
+        &self,
+        range: Range<u64>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Self::Data, Self::Error>> + Send + Sync>> {
+        self.0.slices.get_range(self, range)
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("mkv::File")
+            .field("last_modified", &self.0.last_modified)
+            .field("etag", &self.0.etag)
+            .field("slices", &self.0.slices)
+            .field("segments", &self.0.segments)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4::tests::copy_mp4_to_db;
+    use base::clock::RealClocks;
+    use db::testutil::{self, TestDb, TEST_STREAM_ID};
+    use futures::stream::TryStreamExt;
+    use http_serve::Entity;
+    use hyper::body::Buf;
+
+    fn create_mkv_from_db(tdb: &TestDb<RealClocks>) -> File {
+        let mut builder = FileBuilder::new();
+        let all_time = recording::Time(i64::MIN)..recording::Time(i64::MAX);
+        {
+            let db = tdb.db.lock();
+            db.list_recordings_by_time(TEST_STREAM_ID, all_time, &mut |r| {
+                builder
+                    .append(&db, &r, 0..r.media_duration_90k, true)
+                    .unwrap();
+                Ok(())
+            })
+            .unwrap();
+        }
+        builder
+            .build(tdb.db.clone(), tdb.dirs_by_stream_id.clone())
+            .unwrap()
+    }
+
+    async fn get(mkv: &File, r: Range<u64>) -> Vec<u8> {
+        let mut v = Vec::new();
+        Pin::from(mkv.get_range(r))
+            .try_for_each(|chunk| {
+                v.extend_from_slice(chunk.chunk());
+                futures::future::ok::<_, BoxedError>(())
+            })
+            .await
+            .unwrap();
+        v
+    }
+
+    /// Reads a variable-length integer, returning it and its length. Element ids keep their
+    /// length marker; sizes don't.
+    fn read_vint(b: &[u8], keep_marker: bool) -> (u64, usize) {
+        let len = b[0].leading_zeros() as usize + 1;
+        let mut v = if keep_marker {
+            u64::from(b[0])
+        } else {
+            u64::from(b[0] & (0xFF >> len))
+        };
+        for &x in &b[1..len] {
+            v = (v << 8) | u64::from(x);
+        }
+        (v, len)
+    }
+
+    /// Returns the id and data range of each element within `b[r]`.
+    fn elements(b: &[u8], r: Range<usize>) -> Vec<(u64, Range<usize>)> {
+        let mut out = Vec::new();
+        let mut p = r.start;
+        while p < r.end {
+            let (id, id_len) = read_vint(&b[p..], true);
+            let (size, size_len) = read_vint(&b[p + id_len..], false);
+            let data = p + id_len + size_len..p + id_len + size_len + size as usize;
+            assert!(
+                data.end <= r.end,
+                "element {id:x} at {p} overflows its parent"
+            );
+            p = data.end;
+            out.push((id, data));
+        }
+        out
+    }
+
+    #[test]
+    fn parameter_sets_from_avcc() {
+        let avcc = [
+            0x01, 0x64, 0x00, 0x1f,
+            0xff, // version, profile, compat, level, NAL length size 4
+            0xe1, 0x00, 0x02, 0x67, 0x64, // one 2-byte SPS
+            0x01, 0x00, 0x01, 0x68, // one 1-byte PPS
+        ];
+        assert_eq!(
+            parameter_sets(&avcc).unwrap(),
+            [0, 0, 0, 2, 0x67, 0x64, 0, 0, 0, 1, 0x68]
+        );
+
+        let mut two_byte_lengths = avcc;
+        two_byte_lengths[4] = 0xfd;
+        parameter_sets(&two_byte_lengths).unwrap_err();
+        parameter_sets(&avcc[..12]).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        testutil::init();
+        let mut db = TestDb::new(RealClocks {});
+        copy_mp4_to_db(&mut db);
+        let mkv = create_mkv_from_db(&db);
+        let len = mkv.len();
+        let all = get(&mkv, 0..len).await;
+        assert_eq!(all.len() as u64, len);
+
+        // Ranges starting and ending anywhere match the whole.
+        for start in (0..len).step_by(4099) {
+            let end = cmp::min(start + 20_011, len);
+            assert_eq!(
+                get(&mkv, start..end).await,
+                &all[start as usize..end as usize],
+                "range {start}..{end}"
+            );
+        }
+
+        let top = elements(&all, 0..all.len());
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0, 0x1A45DFA3);
+        assert_eq!(top[1].0, 0x18538067);
+        let segment_start = top[1].1.start;
+        let mut clusters = Vec::new();
+        let mut blocks = 0;
+        let mut cue_positions = Vec::new();
+        for (id, data) in elements(&all, top[1].1.clone()) {
+            match id {
+                0x1F43B675 => {
+                    clusters.push((data.start - 12 - segment_start) as u64);
+                    let children = elements(&all, data);
+                    assert_eq!(children[0].0, 0xE7);
+                    blocks += children[1..].iter().filter(|(id, _)| *id == 0xA3).count();
+                }
+                0x1C53BB6B => {
+                    for (_, cue_point) in elements(&all, data) {
+                        let (_, positions) = elements(&all, cue_point)
+                            .into_iter()
+                            .find(|(id, _)| *id == 0xB7)
+                            .unwrap();
+                        let (_, p) = elements(&all, positions)
+                            .into_iter()
+                            .find(|(id, _)| *id == 0xF1)
+                            .unwrap();
+                        cue_positions
+                            .push(u64::from_be_bytes(<[u8; 8]>::try_from(&all[p]).unwrap()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let frames: usize = mkv.0.segments.iter().map(|s| usize::from(s.s.frames)).sum();
+        assert!(frames > 0);
+        assert_eq!(blocks, frames);
+        assert_eq!(cue_positions, clusters);
+    }
+}
@@ -56,6 +56,7 @@
 
 use crate::body::{wrap_error, BoxedError, Chunk};
 use crate::slices::{self, Slices};
+use base::mem::{BufPool, PooledBuf};
 use base::{bail, err, Error, ErrorKind, ResultExt};
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use bytes::BytesMut;
@@ -63,6 +64,7 @@ use db::dir;
 use db::recording::{self, rescale, TIME_UNITS_PER_SEC};
 use futures::stream::{self, TryStreamExt};
 use futures::Stream;
+use hashlink::LinkedHashMap;
 use http::header::HeaderValue;
 use hyper::body::Buf;
 use reffers::ARefss;
@@ -75,7 +77,9 @@ use std::io;
 use std::mem;
 use std::ops::Range;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::Once;
 use std::time::SystemTime;
 use tracing::{debug, error, trace, warn};
@@ -180,6 +184,21 @@ const TKHD_JUNK: &[u8] = &[
     0x40, 0x00, 0x00, 0x00, // matrix[8]
 ];
 
+/// Returns the transformation matrix (ISO/IEC 14496-12 section 6.2.2) for a clockwise rotation of
+/// a track with the given 16.16 fixed point size. `a`, `b`, `c`, `d`, `x`, and `y` are in 16.16
+/// fixed point; `u`, `v`, and `w` are in 2.30 fixed point. Unknown rotations yield the identity.
+fn rotation_matrix(degrees: u16, width: u32, height: u32) -> [u32; 9] {
+    const ONE: u32 = 0x0001_0000;
+    const NEG_ONE: u32 = 0xffff_0000;
+    const W: u32 = 0x4000_0000;
+    match degrees {
+        90 => [0, ONE, 0, NEG_ONE, 0, 0, height, 0, W],
+        180 => [NEG_ONE, 0, 0, 0, NEG_ONE, 0, width, height, W],
+        270 => [0, NEG_ONE, 0, ONE, 0, 0, 0, width, W],
+        _ => [ONE, 0, 0, 0, ONE, 0, 0, 0, W],
+    }
+}
+
 /// Part of a `minf` (`MediaInformationBox`, ISO/IEC 14496-12 section 8.4.4), used from
 /// `append_video_minf`.
 const VIDEO_MINF_JUNK: &[u8] = &[
@@ -301,14 +320,238 @@ enum StaticBytestring {
 /// a given time range.
 const SUBTITLE_TEMPLATE: &str = "%Y-%m-%d %H:%M:%S %z";
 
+/// Buffers for copies of video indexes while building segment indexes. See
+/// [`base::mem::BufPool`].
+static VIDEO_INDEX_BUFS: BufPool = BufPool::new(64, 1 << 20);
+
+/// Buffers for `trun` boxes of `.m4s` segments, which are built for each request rather than
+/// cached.
+static TRUNS_BUFS: BufPool = BufPool::new(64, 1 << 20);
+
 /// The length of the output of `SUBTITLE_TEMPLATE`.
 const SUBTITLE_LENGTH: usize = 25; // "2015-07-02 17:10:00 -0700".len();
 
+/// Labels to follow the timestamp in each subtitle; see [`FileBuilder::set_subtitle_labels`].
+///
+/// Like the timestamp itself, the labels have the same length every second: state names are
+/// padded to the longest one the signal takes on within the file.
+#[derive(Debug, Default)]
+pub struct SubtitleLabels {
+    /// The camera's short name, if it should be included.
+    pub camera_name: Option<String>,
+
+    /// The downloading user's name, if it should be included as a tag.
+    pub user: Option<String>,
+
+    /// Signals whose states should be included, in the order they should appear.
+    pub signals: Vec<SubtitleSignal>,
+}
+
+/// A signal whose state should be included in each subtitle.
+#[derive(Debug)]
+pub struct SubtitleSignal {
+    pub short_name: String,
+
+    /// Names of states by value. Other states are shown as numbers, except 0 is `unknown`.
+    pub state_names: std::collections::BTreeMap<u16, String>,
+
+    /// Changes to the signal's state, sorted by time. The first should be at or before the start
+    /// of the file; before it, the state is considered unknown.
+    pub changes: Vec<(recording::Time, u16)>,
+}
+
+impl SubtitleLabels {
+    fn is_empty(&self) -> bool {
+        self.camera_name.is_none() && self.user.is_none() && self.signals.is_empty()
+    }
+
+    /// Returns the width in bytes of each signal's state.
+    fn widths(&self) -> Vec<usize> {
+        self.signals.iter().map(SubtitleSignal::width).collect()
+    }
+
+    /// Returns the length in bytes of the labels, which is the same every second.
+    fn len(&self) -> usize {
+        let mut len = self.camera_name.as_ref().map_or(0, |n| 1 + n.len());
+        len += self.user.as_ref().map_or(0, |u| " user=".len() + u.len());
+        for (s, w) in self.signals.iter().zip(self.widths()) {
+            len += " =".len() + s.short_name.len() + w;
+        }
+        len
+    }
+
+    fn hash(&self, etag: &mut blake3::Hasher) {
+        if let Some(n) = self.camera_name.as_ref() {
+            etag.update(b":camera:");
+            etag.update(n.as_bytes());
+        }
+        if let Some(u) = self.user.as_ref() {
+            etag.update(b":user:");
+            etag.update(u.as_bytes());
+        }
+        for s in &self.signals {
+            etag.update(b":signal:");
+            etag.update(s.short_name.as_bytes());
+            for (state, name) in &s.state_names {
+                etag.update(&state.to_be_bytes());
+                etag.update(name.as_bytes());
+            }
+            for (when, state) in &s.changes {
+                etag.update(&when.0.to_be_bytes());
+                etag.update(&state.to_be_bytes());
+            }
+        }
+    }
+
+    /// Appends the labels for the second starting at `start`. `widths` is as returned by
+    /// [`SubtitleLabels::widths`].
+    fn write(&self, start: recording::Time, widths: &[usize], v: &mut Vec<u8>) {
+        if let Some(n) = self.camera_name.as_ref() {
+            v.push(b' ');
+            v.extend_from_slice(n.as_bytes());
+        }
+        if let Some(u) = self.user.as_ref() {
+            v.extend_from_slice(b" user=");
+            v.extend_from_slice(u.as_bytes());
+        }
+        let sec = start..start + recording::Duration(TIME_UNITS_PER_SEC);
+        for (s, &w) in self.signals.iter().zip(widths) {
+            v.push(b' ');
+            v.extend_from_slice(s.short_name.as_bytes());
+            v.push(b'=');
+            let name = s.state_name(s.state_during(sec.clone()));
+            v.extend_from_slice(name.as_bytes());
+            v.resize(v.len() + w - name.len(), b' ');
+        }
+    }
+}
+
+impl SubtitleSignal {
+    fn state_name(&self, state: u16) -> std::borrow::Cow<'_, str> {
+        match self.state_names.get(&state) {
+            Some(n) => n.as_str().into(),
+            None if state == 0 => "unknown".into(),
+            None => state.to_string().into(),
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.changes
+            .iter()
+            .map(|&(_, state)| state)
+            .chain(std::iter::once(0))
+            .map(|state| self.state_name(state).len())
+            .max()
+            .expect("iterator is non-empty")
+    }
+
+    /// Returns the most notable state during `time`. That's the highest-numbered one, as rarer
+    /// states are conventionally given higher numbers (see [`db::json::SignalTypeConfig`]), so
+    /// a brief motion event isn't hidden by the second's start or end being still.
+    fn state_during(&self, time: Range<recording::Time>) -> u16 {
+        let i = self
+            .changes
+            .partition_point(|&(when, _)| when <= time.start);
+        let mut state = match i {
+            0 => 0,
+            _ => self.changes[i - 1].1,
+        };
+        for &(when, s) in &self.changes[i..] {
+            if when >= time.end {
+                break;
+            }
+            state = cmp::max(state, s);
+        }
+        state
+    }
+}
+
+/// Returns the size of each subtitle sample, including its length prefix.
+fn subtitle_sample_len(labels: &SubtitleLabels) -> usize {
+    mem::size_of::<u16>() + SUBTITLE_LENGTH + labels.len()
+}
+
 /// The lengths of the indexes associated with a `Segment`; for use within `Segment` only.
 struct SegmentLengths {
     stts: usize,
     stsz: usize,
     stss: usize,
+    ctts: usize,
+}
+
+/// The default byte limit of an [`IndexCache`].
+pub const DEFAULT_INDEX_CACHE_BYTES: usize = 16 << 20;
+
+/// The key of an [`IndexCache`] entry.
+///
+/// The index bytes depend on more than the recording: `stss` holds frame numbers relative to the
+/// start of the `.mp4`, and the final `stts` entry is trimmed to the desired end. A given
+/// `(id, open_id, rel_media_range_90k)` may also cover a varying number of frames while the
+/// recording is still in progress, so `frames` is part of the key too.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct IndexCacheKey {
+    id: db::CompositeId,
+    open_id: u32,
+    rel_media_range_90k: Range<i32>,
+    first_frame_num: u32,
+    frames: u16,
+}
+
+/// A cache of built `Segment` indexes, shared between `File`s.
+///
+/// Browsers typically send many range requests for the same URL while seeking within a
+/// recording. Each request creates a fresh `File`, so without this cache each would rebuild its
+/// indexes from the database's video index. Entries are evicted in least-recently-used order
+/// once their total size exceeds the limit supplied to [`IndexCache::new`].
+pub struct IndexCache(Mutex<IndexCacheInner>);
+
+struct IndexCacheInner {
+    entries: LinkedHashMap<IndexCacheKey, Arc<[u8]>, base::RandomState>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl IndexCache {
+    pub fn new(max_bytes: usize) -> Self {
+        IndexCache(Mutex::new(IndexCacheInner {
+            entries: LinkedHashMap::with_hasher(Default::default()),
+            bytes: 0,
+            max_bytes,
+        }))
+    }
+
+    fn get(&self, key: &IndexCacheKey) -> Option<Arc<[u8]>> {
+        use hashlink::linked_hash_map::RawEntryMut;
+        let mut l = self.0.lock().unwrap();
+        match l.entries.raw_entry_mut().from_key(key) {
+            RawEntryMut::Occupied(mut occupied) => {
+                occupied.to_back();
+                Some(occupied.get().clone())
+            }
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    fn insert(&self, key: IndexCacheKey, index: Arc<[u8]>) {
+        let mut l = self.0.lock().unwrap();
+        if index.len() > l.max_bytes {
+            return;
+        }
+        l.bytes += index.len();
+        if let Some(old) = l.entries.insert(key, index) {
+            l.bytes -= old.len();
+        }
+        while l.bytes > l.max_bytes {
+            let (_, evicted) = l.entries.pop_front().expect("bytes > 0 implies entries");
+            l.bytes -= evicted.len();
+        }
+    }
+
+    /// Returns the number of cached indexes and their total size in bytes.
+    pub fn stats(&self) -> (usize, usize) {
+        let l = self.0.lock().unwrap();
+        (l.entries.len(), l.bytes)
+    }
 }
 
 /// A wrapper around `recording::Segment` that keeps some additional `.mp4`-specific state.
@@ -332,8 +575,9 @@ struct Segment {
     /// If generated, the `.mp4`-format sample indexes, accessed only through `get_index`:
     ///    1. stts: `slice[.. stsz_start]`
     ///    2. stsz: `slice[stsz_start .. stss_start]`
-    ///    3. stss: `slice[stss_start ..]`
-    index: UnsafeCell<Result<Box<[u8]>, ()>>,
+    ///    3. stss: `slice[stss_start .. ctts_start]`
+    ///    4. ctts: `slice[ctts_start ..]`, empty unless the recording has composition offsets.
+    index: UnsafeCell<Result<Arc<[u8]>, ()>>,
     index_once: Once,
 
     /// The 1-indexed frame number in the `File` of the first frame in this segment.
@@ -402,18 +646,55 @@ impl Segment {
         )
     }
 
-    fn get_index<'a, F>(&'a self, db: &db::Database, f: F) -> Result<&'a [u8], Error>
+    fn index_cache_key(&self) -> IndexCacheKey {
+        IndexCacheKey {
+            id: self.s.id,
+            open_id: self.s.open_id,
+            rel_media_range_90k: self.rel_media_range_90k.clone(),
+            first_frame_num: self.first_frame_num,
+            frames: self.s.frames,
+        }
+    }
+
+    fn get_index<'a, F>(
+        &'a self,
+        db: &db::Database,
+        cache: Option<&IndexCache>,
+        f: F,
+    ) -> Result<&'a [u8], Error>
     where
         F: FnOnce(&[u8], SegmentLengths) -> &[u8],
     {
         self.index_once.call_once(|| {
             let index = unsafe { &mut *self.index.get() };
+            let key = cache.map(|_| self.index_cache_key());
+            if let (Some(cache), Some(key)) = (cache, key.as_ref()) {
+                if let Some(cached) = cache.get(key) {
+                    trace!(recording_id = %self.s.id, "index cache hit");
+                    *index = Ok(cached);
+                    return;
+                }
+            }
+            // Copy the video index out so that the database lock isn't held while building.
+            // This allows `File::prebuild_indexes` to build several segments in parallel.
             *index = db
                 .lock()
-                .with_recording_playback(self.s.id, &mut |playback| self.build_index(playback))
+                .with_recording_playback(self.s.id, &mut |playback| {
+                    let mut buf = VIDEO_INDEX_BUFS.get(playback.video_index.len());
+                    buf.extend_from_slice(playback.video_index);
+                    Ok(buf)
+                })
+                .and_then(|video_index| {
+                    self.build_index(&db::RecordingPlayback {
+                        video_index: &video_index,
+                    })
+                })
                 .map_err(|err| {
                     error!(%err, recording_id = %self.s.id, "unable to build index for segment");
                 });
+            if let (Some(cache), Some(key), Ok(built)) = (cache, key, index.as_ref()) {
+                cache.insert(key, built.clone());
+            }
         });
         let index: &'a _ = unsafe { &*self.index.get() };
         match *index {
@@ -427,6 +708,11 @@ impl Segment {
             stts: mem::size_of::<u32>() * 2 * (self.s.frames as usize),
             stsz: mem::size_of::<u32>() * self.s.frames as usize,
             stss: mem::size_of::<u32>() * self.s.key_frames as usize,
+            ctts: if self.s.has_composition_offsets() {
+                mem::size_of::<u32>() * 2 * (self.s.frames as usize)
+            } else {
+                0
+            },
         }
     }
 
@@ -437,14 +723,20 @@ impl Segment {
         &buf[lens.stts..lens.stts + lens.stsz]
     }
     fn stss(buf: &[u8], lens: SegmentLengths) -> &[u8] {
-        &buf[lens.stts + lens.stsz..]
+        &buf[lens.stts + lens.stsz..lens.stts + lens.stsz + lens.stss]
+    }
+    fn ctts(buf: &[u8], lens: SegmentLengths) -> &[u8] {
+        &buf[lens.stts + lens.stsz + lens.stss..]
     }
 
-    fn build_index(&self, playback: &db::RecordingPlayback) -> Result<Box<[u8]>, Error> {
+    fn build_index(&self, playback: &db::RecordingPlayback) -> Result<Arc<[u8]>, Error> {
         let s = &self.s;
         let lens = self.lens();
-        let len = lens.stts + lens.stsz + lens.stss;
+        let len = lens.stts + lens.stsz + lens.stss + lens.ctts;
 
+        // Build directly into the `Arc` which is cached and shared, rather than into a `Box`
+        // which must then be copied. Collecting from a `TrustedLen` iterator allocates once.
+        //
         // This was a few percent faster when we didn't pre-initialize the
         // slice (as in the commented-out code below), but it was unsound. See
         // <https://github.com/scottlamb/moonfire-nvr/issues/185>. It might be
@@ -452,7 +744,7 @@ impl Segment {
         // more ergonomic than dealing with raw pointers. In the meantime, a few
         // percent difference in speed here probably isn't the biggest unsolved
         // problem with Moonfire...
-        let mut buf = vec![0; len].into_boxed_slice();
+        let mut buf: Arc<[u8]> = std::iter::repeat(0).take(len).collect();
         // let mut buf = {
         //     let mut v = Vec::with_capacity(len);
         //     unsafe { v.set_len(len) };
@@ -460,8 +752,10 @@ impl Segment {
         // };
 
         {
+            let buf = Arc::get_mut(&mut buf).expect("new Arc is unique");
             let (stts, rest) = buf.split_at_mut(lens.stts);
-            let (stsz, stss) = rest.split_at_mut(lens.stsz);
+            let (stsz, rest) = rest.split_at_mut(lens.stsz);
+            let (stss, ctts) = rest.split_at_mut(lens.stss);
             let mut frame = 0;
             let mut key_frame = 0;
             let mut last_start_and_dur = None;
@@ -480,6 +774,13 @@ impl Segment {
                     );
                     key_frame += 1;
                 }
+                if !ctts.is_empty() {
+                    BigEndian::write_u32(&mut ctts[8 * frame..8 * frame + 4], 1);
+                    BigEndian::write_u32(
+                        &mut ctts[8 * frame + 4..8 * frame + 8],
+                        it.composition_offset_90k as u32,
+                    );
+                }
                 frame += 1;
                 Ok(())
             })?;
@@ -496,9 +797,15 @@ impl Segment {
         Ok(buf)
     }
 
+    /// The number of `u32`s per sample in each `trun`: duration, size, and perhaps composition
+    /// offset.
+    fn trun_sample_words(&self) -> usize {
+        2 + self.s.has_composition_offsets() as usize
+    }
+
     fn truns_len(&self) -> usize {
         self.s.key_frames as usize * (mem::size_of::<u32>() * 6)
-            + self.s.frames as usize * (mem::size_of::<u32>() * 2)
+            + self.s.frames as usize * (mem::size_of::<u32>() * self.trun_sample_words())
             + if self.s.starts_with_nonkey() {
                 mem::size_of::<u32>() * 5
             } else {
@@ -512,8 +819,8 @@ impl Segment {
         playback: &db::RecordingPlayback,
         initial_pos: u64,
         len: usize,
-    ) -> Result<Vec<u8>, Error> {
-        let mut v = Vec::with_capacity(len);
+    ) -> Result<PooledBuf<'static>, Error> {
+        let mut v = TRUNS_BUFS.get(len);
 
         struct RunInfo {
             box_len_pos: usize,
@@ -524,6 +831,7 @@ impl Segment {
         }
         let mut run_info: Option<RunInfo> = None;
         let mut data_pos = initial_pos;
+        let composition_offsets = self.s.has_composition_offsets();
         self.s
             .foreach(playback, |it| {
                 let is_key = it.is_key();
@@ -553,14 +861,15 @@ impl Segment {
                             b'r',
                             b'u',
                             b'n',
-                            // version 0, tr_flags:
+                            // version 0 (or 1 for signed composition offsets), tr_flags:
                             // 0x000001 data-offset-present
                             // 0x000004 first-sample-flags-present
                             // 0x000100 sample-duration-present
                             // 0x000200 sample-size-present
+                            // 0x000800 sample-composition-time-offsets-present
+                            composition_offsets as u8,
                             0x00,
-                            0x00,
-                            0x03,
+                            0x03 | if composition_offsets { 0x08 } else { 0 },
                             0x01 | if is_key { 0x04 } else { 0 },
                         ]);
                         let sample_count_pos = v.len();
@@ -597,6 +906,9 @@ impl Segment {
                 r.last_dur = it.duration_90k;
                 v.write_u32::<BigEndian>(it.duration_90k as u32)?;
                 v.write_u32::<BigEndian>(it.bytes as u32)?;
+                if composition_offsets {
+                    v.write_i32::<BigEndian>(it.composition_offset_90k)?;
+                }
                 data_pos += it.bytes as u64;
                 run_info = Some(r);
                 Ok(())
@@ -614,8 +926,9 @@ impl Segment {
             // One more thing to do in the terminal case: fix up the final frame's duration.
             // Doing this after the fact is more efficient than having a condition on every
             // iteration.
+            let dur_pos = p - mem::size_of::<u32>() * self.trun_sample_words();
             BigEndian::write_u32(
-                &mut v[p - 8..p - 4],
+                &mut v[dur_pos..dur_pos + 4],
                 u32::try_from(cmp::min(
                     self.rel_media_range_90k.end - r.last_start,
                     r.last_dur,
@@ -653,7 +966,15 @@ pub struct FileBuilder {
     type_: Type,
     prev_media_duration_and_cur_runs: Option<(recording::Duration, i32)>,
     include_timestamp_subtitle_track: bool,
+    subtitle_labels: SubtitleLabels,
     content_disposition: Option<HeaderValue>,
+    index_cache: Option<Arc<IndexCache>>,
+
+    /// True if any segment is of a recording which is still being written.
+    growing: bool,
+
+    /// The clockwise rotation in degrees, taken from the first segment's stream config.
+    rotation: u16,
 }
 
 /// The portion of `FileBuilder` which is mutated while building the body of the file.
@@ -694,6 +1015,7 @@ enum SliceType {
     VideoSampleData = 7,    // param is index into m.segments
     SubtitleSampleData = 8, // param is index into m.segments
     Truns = 9,              // param is index into m.segments
+    Ctts = 10,              // param is index into m.segments
 
                             // There must be no value > 15, as this is packed into 4 bits in Slice.
 }
@@ -728,7 +1050,7 @@ impl Slice {
         let p = self.p();
         Ok(mp4
             .try_map(|mp4| {
-                let i = mp4.segments[p].get_index(&mp4.db, f)?;
+                let i = mp4.segments[p].get_index(&mp4.db, mp4.index_cache.as_deref(), f)?;
                 if u64::try_from(i.len()).unwrap() != len {
                     bail!(Internal, msg("expected len {} got {}", len, i.len()));
                 }
@@ -750,7 +1072,7 @@ impl Slice {
             .lock()
             .with_recording_playback(s.s.id, &mut |playback| s.truns(playback, pos, len))
             .err_kind(ErrorKind::Unknown)?;
-        let truns = ARefss::new(truns);
+        let truns = ARefss::new(Arc::new(truns));
         Ok(truns.map(|t| &t[r.start as usize..r.end as usize]).into())
     }
 
@@ -807,6 +1129,7 @@ impl slices::Slice for Slice {
             SliceType::Stts => self.wrap_index(f, range.clone(), len, &Segment::stts),
             SliceType::Stsz => self.wrap_index(f, range.clone(), len, &Segment::stsz),
             SliceType::Stss => self.wrap_index(f, range.clone(), len, &Segment::stss),
+            SliceType::Ctts => self.wrap_index(f, range.clone(), len, &Segment::ctts),
             SliceType::Co64 => f.0.get_co64(range.clone(), len),
             SliceType::VideoSampleData => return f.0.get_video_sample_data(p, range),
             SliceType::SubtitleSampleData => f.0.get_subtitle_sample_data(p, range.clone(), len),
@@ -892,8 +1215,12 @@ impl FileBuilder {
             },
             type_,
             include_timestamp_subtitle_track: false,
+            subtitle_labels: SubtitleLabels::default(),
             content_disposition: None,
             prev_media_duration_and_cur_runs: None,
+            index_cache: None,
+            growing: false,
+            rotation: 0,
         }
     }
 
@@ -913,6 +1240,16 @@ impl FileBuilder {
         Ok(())
     }
 
+    /// Sets labels to follow the timestamp in each subtitle, making clips self-describing.
+    /// These have no effect unless the timestamp subtitle track is included.
+    pub fn set_subtitle_labels(&mut self, labels: SubtitleLabels) -> Result<(), Error> {
+        if SUBTITLE_LENGTH + labels.len() > usize::from(u16::MAX) {
+            bail!(InvalidArgument, msg("subtitle labels are too long"));
+        }
+        self.subtitle_labels = labels;
+        Ok(())
+    }
+
     /// Reserves space for the given number of additional segments.
     pub fn reserve(&mut self, additional: usize) {
         self.segments.reserve(additional);
@@ -932,6 +1269,49 @@ impl FileBuilder {
         rel_media_range_90k: Range<i32>,
         start_at_key: bool,
     ) -> Result<(), Error> {
+        // The row may have been read before its video sample entry was merged into an identical
+        // one; refer to the surviving entry.
+        let vse = db
+            .video_sample_entry(row.video_sample_entry_id)
+            .ok_or_else(|| {
+                err!(
+                    Internal,
+                    msg(
+                        "recording {} has unknown video sample entry {}",
+                        row.id,
+                        row.video_sample_entry_id
+                    ),
+                )
+            })?;
+        let remapped;
+        let row = if vse.id != row.video_sample_entry_id {
+            remapped = db::ListRecordingsRow {
+                video_sample_entry_id: vse.id,
+                ..row.clone()
+            };
+            &remapped
+        } else {
+            row
+        };
+
+        // Within a media segment's single `traf`, all samples use the sample entry of the
+        // corresponding init segment.
+        if self.type_ == Type::MediaSegment {
+            if let Some(first) = self.video_sample_entries.first() {
+                if first.id != vse.id {
+                    bail!(
+                        InvalidArgument,
+                        msg(
+                            "media segment can't switch from video sample entry {} to {} at \
+                             recording {}",
+                            first.id,
+                            vse.id,
+                            row.id,
+                        ),
+                    );
+                }
+            }
+        }
         if let Some(prev) = self.segments.last() {
             if prev.s.have_trailing_zero() {
                 bail!(
@@ -944,6 +1324,11 @@ impl FileBuilder {
                 );
             }
         } else {
+            self.rotation = db
+                .streams_by_id()
+                .get(&row.id.stream())
+                .map_or(0, |s| s.config.rotation);
+
             // Include the current run in this count here, as we're not propagating the
             // run_offset_id further.
             self.prev_media_duration_and_cur_runs = row
@@ -960,20 +1345,23 @@ impl FileBuilder {
 
         self.next_frame_num += s.s.frames as u32;
         self.segments.push(s);
+        self.growing |= (row.flags & db::RecordingFlags::Growing as i32) != 0;
         if !self
             .video_sample_entries
             .iter()
             .any(|e| e.id == row.video_sample_entry_id)
         {
-            let vse = db
-                .video_sample_entries_by_id()
-                .get(&row.video_sample_entry_id)
-                .unwrap();
             self.video_sample_entries.push(vse.clone());
         }
         Ok(())
     }
 
+    /// Sets a cache to consult for (and populate with) built sample indexes.
+    /// Default is none, meaning each `File` builds its own indexes.
+    pub fn set_index_cache(&mut self, cache: Arc<IndexCache>) {
+        self.index_cache = Some(cache);
+    }
+
     pub fn set_filename(&mut self, filename: &str) -> Result<(), Error> {
         self.content_disposition = Some(
             HeaderValue::try_from(format!("attachment; filename=\"{filename}\""))
@@ -993,11 +1381,28 @@ impl FileBuilder {
         etag.update(&FORMAT_VERSION[..]);
         if self.include_timestamp_subtitle_track {
             etag.update(b":ts:");
+            if !self.subtitle_labels.is_empty() {
+                etag.update(b":labels:");
+                self.subtitle_labels.hash(&mut etag);
+            }
         }
         if let Some(cd) = self.content_disposition.as_ref() {
             etag.update(b":cd:");
             etag.update(cd.as_bytes());
         }
+        if self.rotation != 0 {
+            etag.update(b":rot:");
+            etag.update(&self.rotation.to_be_bytes());
+        }
+        if self
+            .video_sample_entries
+            .iter()
+            .any(|e| e.pasp_h_spacing != e.pasp_v_spacing)
+        {
+            // The `tkhd` width of anamorphic streams changed without a `FORMAT_VERSION` bump, so
+            // as not to change the etag of all other files.
+            etag.update(b":pasp:");
+        }
         match self.type_ {
             Type::Normal => {}
             Type::InitSegment => {
@@ -1126,8 +1531,10 @@ impl FileBuilder {
         }
         trace!("segments: {:#?}", self.segments);
         trace!("slices: {:?}", self.body.slices);
-        let last_modified =
-            ::std::time::UNIX_EPOCH + ::std::time::Duration::from_secs(max_end as u64);
+        // A growing recording's segment may gain frames within the same second, so a
+        // `Last-Modified` would let `If-Modified-Since` requests wrongly match. Rely on the etag.
+        let last_modified = (!self.growing)
+            .then(|| ::std::time::UNIX_EPOCH + ::std::time::Duration::from_secs(max_end as u64));
         let etag = etag.finalize();
         Ok(File(Arc::new(FileInner {
             db,
@@ -1143,6 +1550,8 @@ impl FileBuilder {
             content_disposition: self.content_disposition,
             prev_media_duration_and_cur_runs: self.prev_media_duration_and_cur_runs,
             type_: self.type_,
+            index_cache: self.index_cache,
+            subtitle_labels: self.subtitle_labels,
         })))
     }
 
@@ -1154,10 +1563,10 @@ impl FileBuilder {
         }
         if let Some(p) = self.subtitle_co64_pos {
             BigEndian::write_u64(&mut self.body.buf[p..p + 8], self.body.slices.len());
+            let sample_len = subtitle_sample_len(&self.subtitle_labels) as u64;
             for (i, s) in self.segments.iter().enumerate() {
                 self.body.append_slice(
-                    s.num_subtitle_samples as u64
-                        * (mem::size_of::<u16>() + SUBTITLE_LENGTH) as u64,
+                    s.num_subtitle_samples as u64 * sample_len,
                     SliceType::SubtitleSampleData,
                     i,
                 )?;
@@ -1350,18 +1759,35 @@ impl FileBuilder {
             self.body.append_u32(1); // track_id
             self.body.append_u32(0); // reserved
             self.body.append_u32(self.media_duration_90k as u32);
-            self.body.append_static(StaticBytestring::TkhdJunk)?;
 
+            // The track's presentation size, in 16.16 fixed point. This accounts for
+            // non-square pixels, so anamorphic streams are displayed at the intended aspect
+            // ratio.
             let (width, height) = self
                 .video_sample_entries
                 .iter()
-                .fold(None, |m, e| match m {
-                    None => Some((e.width, e.height)),
-                    Some((w, h)) => Some((cmp::max(w, e.width), cmp::max(h, e.height))),
+                .map(|e| {
+                    let h_spacing = u64::from(cmp::max(e.pasp_h_spacing, 1));
+                    let v_spacing = u64::from(cmp::max(e.pasp_v_spacing, 1));
+                    let width = (u64::from(e.width) << 16) * h_spacing / v_spacing;
+                    (
+                        u32::try_from(width).unwrap_or(u32::MAX),
+                        u32::from(e.height) << 16,
+                    )
                 })
+                .reduce(|(w1, h1), (w2, h2)| (cmp::max(w1, w2), cmp::max(h1, h2)))
                 .ok_or_else(|| err!(InvalidArgument, msg("no video_sample_entries")))?;
-            self.body.append_u32((width as u32) << 16);
-            self.body.append_u32((height as u32) << 16);
+            if self.rotation == 0 {
+                self.body.append_static(StaticBytestring::TkhdJunk)?;
+            } else {
+                // As in TKHD_JUNK, but with a rotation matrix.
+                self.body.buf.extend_from_slice(&[0; 16]);
+                for v in rotation_matrix(self.rotation, width, height) {
+                    self.body.append_u32(v);
+                }
+            }
+            self.body.append_u32(width);
+            self.body.append_u32(height);
         })
     }
 
@@ -1500,6 +1926,9 @@ impl FileBuilder {
             self.body.buf.extend_from_slice(b"stbl");
             self.append_video_stsd()?;
             self.append_video_stts()?;
+            if self.segments.iter().any(|s| s.s.has_composition_offsets()) {
+                self.append_video_ctts()?;
+            }
             self.append_video_stsc()?;
             self.append_video_stsz()?;
             self.append_video_co64()?;
@@ -1557,6 +1986,37 @@ impl FileBuilder {
         })
     }
 
+    /// Appends a `ctts` / `CompositionOffsetBox` (ISO/IEC 14496-12 section 8.6.1.3) for video.
+    /// This uses version 1, as offsets can be negative.
+    fn append_video_ctts(&mut self) -> Result<(), Error> {
+        write_length!(self, {
+            self.body.buf.extend_from_slice(b"ctts\x01\x00\x00\x00");
+            let mut entry_count = 0;
+            for s in &self.segments {
+                entry_count += if s.s.has_composition_offsets() {
+                    s.s.frames as u32
+                } else {
+                    1
+                };
+            }
+            self.body.append_u32(entry_count);
+            for (i, s) in self.segments.iter().enumerate() {
+                if s.s.has_composition_offsets() {
+                    self.body.flush_buf()?;
+                    self.body.append_slice(
+                        2 * (mem::size_of::<u32>() as u64) * (s.s.frames as u64),
+                        SliceType::Ctts,
+                        i,
+                    )?;
+                } else {
+                    // One entry covering all of the segment's frames.
+                    self.body.append_u32(s.s.frames as u32);
+                    self.body.append_u32(0);
+                }
+            }
+        })
+    }
+
     /// Appends an `stts` / `TimeToSampleBox` (ISO/IEC 14496-12 section 8.6.1) for subtitles.
     fn append_subtitle_stts(&mut self) -> Result<(), Error> {
         write_length!(self, {
@@ -1679,8 +2139,8 @@ impl FileBuilder {
     fn append_subtitle_stsz(&mut self) -> Result<(), Error> {
         write_length!(self, {
             self.body.buf.extend_from_slice(b"stsz\x00\x00\x00\x00");
-            self.body
-                .append_u32((mem::size_of::<u16>() + SUBTITLE_LENGTH) as u32);
+            let sample_len = subtitle_sample_len(&self.subtitle_labels) as u32;
+            self.body.append_u32(sample_len);
             self.body.append_u32(self.num_subtitle_samples);
         })
     }
@@ -1784,11 +2244,13 @@ struct FileInner {
     buf: Vec<u8>,
     video_sample_entries: SmallVec<[Arc<db::VideoSampleEntry>; 1]>,
     initial_sample_byte_pos: u64,
-    last_modified: SystemTime,
+    last_modified: Option<SystemTime>,
     etag: HeaderValue,
     content_disposition: Option<HeaderValue>,
     prev_media_duration_and_cur_runs: Option<(recording::Duration, i32)>,
     type_: Type,
+    index_cache: Option<Arc<IndexCache>>,
+    subtitle_labels: SubtitleLabels,
 }
 
 impl FileInner {
@@ -1845,9 +2307,11 @@ impl FileInner {
         .unix_seconds();
         let len = usize::try_from(len).unwrap();
         let mut v = Vec::with_capacity(len);
+        let text_len = subtitle_sample_len(&self.subtitle_labels) - mem::size_of::<u16>();
+        let widths = self.subtitle_labels.widths();
         // TODO(slamb): is this right?!? might have an off-by-one here.
         for ts in start_sec..end_sec {
-            v.write_u16::<BigEndian>(SUBTITLE_LENGTH as u16)
+            v.write_u16::<BigEndian>(text_len as u16)
                 .expect("Vec write shouldn't fail");
             let tm = time::at(time::Timespec { sec: ts, nsec: 0 });
             use std::io::Write;
@@ -1858,6 +2322,8 @@ impl FileInner {
                     .err_kind(ErrorKind::Internal)?
             )
             .expect("Vec write shouldn't fail");
+            self.subtitle_labels
+                .write(recording::Time(ts * TIME_UNITS_PER_SEC), &widths, &mut v);
         }
         assert_eq!(len, v.len());
         Ok(ARefss::new(v)
@@ -1898,6 +2364,57 @@ impl File {
             }
         }
     }
+
+    /// Returns the number of segments (recordings or portions thereof) in this file.
+    pub fn num_segments(&self) -> usize {
+        self.0.segments.len()
+    }
+
+    /// Starts building all segments' sample indexes in the background, with up to `max_queued`
+    /// builds queued at once.
+    ///
+    /// Otherwise each index is built lazily and serially as the `moov` box is served, which for a
+    /// file spanning hundreds of recordings adds noticeably to the time to first byte. Serving
+    /// doesn't wait for this to finish; a segment which is still being built when needed simply
+    /// blocks until it's done, as with two concurrent requests on the same `File`.
+    ///
+    /// The builds run on each segment's sample file dir's reader threads, so they're subject to
+    /// that dir's thread count and queue rather than adding to them.
+    pub fn prebuild_indexes(&self, max_queued: usize) {
+        let next = Arc::new(AtomicUsize::new(0));
+        for _ in 0..cmp::min(max_queued, self.0.segments.len()) {
+            self.prebuild_next(next.clone(), tracing::Span::current());
+        }
+    }
+
+    /// Queues a build of the next segment's index, which when done queues the one after.
+    fn prebuild_next(&self, next: Arc<AtomicUsize>, span: tracing::Span) {
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(s) = self.0.segments.get(i) else {
+                return;
+            };
+            let Some(dir) = self.0.dirs_by_stream_id.get(&s.s.id.stream()) else {
+                continue; // serving will report the missing stream.
+            };
+            let f = self.clone();
+            dir.run_on_reader(move || {
+                {
+                    let _enter = span.enter();
+
+                    // Errors are logged within `get_index` and will be returned again when
+                    // serving.
+                    let _ = f.0.segments[i].get_index(
+                        &f.0.db,
+                        f.0.index_cache.as_deref(),
+                        Segment::stts,
+                    );
+                }
+                f.prebuild_next(next, span);
+            });
+            return;
+        }
+    }
 }
 
 impl http_serve::Entity for File {
@@ -1908,7 +2425,14 @@ impl http_serve::Entity for File {
         let mut mime = BytesMut::with_capacity(64);
         mime.extend_from_slice(b"video/mp4; codecs=\"");
         let mut first = true;
-        for e in &self.0.video_sample_entries {
+        let entries = &self.0.video_sample_entries;
+        for (i, e) in entries.iter().enumerate() {
+            if entries[..i]
+                .iter()
+                .any(|p| p.rfc6381_codec == e.rfc6381_codec)
+            {
+                continue; // a resolution change within the same codec.
+            }
             if first {
                 first = false
             } else {
@@ -1961,7 +2485,7 @@ impl http_serve::Entity for File {
         }
     }
     fn last_modified(&self) -> Option<SystemTime> {
-        Some(self.0.last_modified)
+        self.0.last_modified
     }
     fn etag(&self) -> Option<HeaderValue> {
         Some(self.0.etag.clone())
@@ -1999,7 +2523,7 @@ impl fmt::Debug for File {
 ///      detect misunderstandings of the specification or incompatibilities, but they can be used
 ///      to verify the output is byte-for-byte as expected.
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::stream;
     use base::clock::RealClocks;
@@ -2294,7 +2818,7 @@ mod tests {
         }
     }
 
-    fn copy_mp4_to_db(db: &mut TestDb<RealClocks>) {
+    pub(crate) fn copy_mp4_to_db(db: &mut TestDb<RealClocks>) {
         let input = stream::testutil::Mp4Stream::open("src/testdata/clip.mp4").unwrap();
         let mut input: Box<dyn stream::Stream> = Box::new(input);
 
@@ -2336,6 +2860,7 @@ mod tests {
                     pkt.pts,
                     pkt.is_key,
                     video_sample_entry_id,
+                    0,
                 )
                 .unwrap();
             end_pts = Some(pkt.pts + i64::from(pkt.duration));
@@ -2685,6 +3210,59 @@ mod tests {
         assert_eq!(cursor.get_u32(12).await, 2);
     }
 
+    /// Tests a resolution change between recordings.
+    #[tokio::test]
+    async fn test_video_sample_entry_change() {
+        testutil::init();
+        let db = TestDb::new(RealClocks {});
+        let mut rows = Vec::new();
+        for _ in 0..2 {
+            let mut r = db::RecordingToInsert::default();
+            let mut encoder = recording::SampleIndexEncoder::default();
+            encoder.add_sample(1, 1, true, &mut r);
+            rows.push(db.insert_recording_from_encoder(r));
+        }
+        rows[1].video_sample_entry_id = db
+            .db
+            .lock()
+            .insert_video_sample_entry(db::VideoSampleEntryToInsert {
+                width: 1280,
+                height: 720,
+                pasp_h_spacing: 1,
+                pasp_v_spacing: 1,
+                data: [1u8; 100].to_vec(),
+                rfc6381_codec: "avc1.000000".to_owned(),
+            })
+            .unwrap();
+        let build = |type_: Type| -> Result<File, Error> {
+            let mut builder = FileBuilder::new(type_);
+            for row in &rows {
+                builder.append(&db.db.lock(), row, 0..row.media_duration_90k, true)?;
+            }
+            builder.build(db.db.clone(), db.dirs_by_stream_id.clone())
+        };
+        let e = build(Type::MediaSegment).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidArgument);
+
+        let mp4 = build(Type::Normal).unwrap();
+        let mut hdrs = http::header::HeaderMap::new();
+        mp4.add_headers(&mut hdrs);
+        assert_eq!(
+            hdrs.get(http::header::CONTENT_TYPE).unwrap(),
+            "video/mp4; codecs=\"avc1.000000\""
+        );
+        traverse(mp4.clone()).await;
+        let track = find_track(mp4, 1).await;
+        let mut cursor = track.stbl_cursor;
+        cursor.down().await;
+        assert!(cursor.find(b"stsd").await);
+        assert_eq!(cursor.get_u32(4).await, 2); // entry_count
+        assert!(cursor.find(b"stsc").await);
+        assert_eq!(cursor.get_u32(4).await, 2); // entry_count
+        assert_eq!(cursor.get_u32(16).await, 1); // sample_description_index of chunk 1
+        assert_eq!(cursor.get_u32(28).await, 2); // sample_description_index of chunk 2
+    }
+
     #[tokio::test]
     async fn test_zero_duration_recording() {
         testutil::init();
@@ -2712,6 +3290,52 @@ mod tests {
         assert_eq!(cursor.get_u64(16).await, 1); // media_time
     }
 
+    #[tokio::test]
+    async fn test_rotation() {
+        testutil::init();
+        let db = TestDb::new(RealClocks {});
+        testutil::add_dummy_recordings_to_db(&db.db, 1);
+        let unrotated_etag = create_mp4_from_db(&db, 0, 0, false).etag();
+        {
+            let mut l = db.db.lock();
+            let mut change = l.null_camera_change(testutil::TEST_CAMERA_ID).unwrap();
+            change.streams[db::StreamType::Main.index()].config.rotation = 90;
+            l.update_camera(testutil::TEST_CAMERA_ID, change).unwrap();
+        }
+        let mp4 = create_mp4_from_db(&db, 0, 0, false);
+        assert_ne!(mp4.etag(), unrotated_etag);
+        let mut cursor = BoxCursor::new(mp4);
+        cursor.down().await;
+        assert!(cursor.find(b"moov").await);
+        cursor.down().await;
+        assert!(cursor.find(b"trak").await);
+        cursor.down().await;
+        assert!(cursor.find(b"tkhd").await);
+
+        // The matrix follows the version 0 fields through duration, then reserved, layer,
+        // alternate_group, and volume.
+        let mut matrix = [0u32; 9];
+        for (i, m) in matrix.iter_mut().enumerate() {
+            *m = cursor.get_u32(40 + 4 * i as u64).await;
+        }
+        assert_eq!(
+            matrix,
+            [
+                0,
+                0x1_0000,
+                0,
+                0xffff_0000,
+                0,
+                0,
+                1080 << 16,
+                0,
+                0x4000_0000
+            ]
+        );
+        assert_eq!(cursor.get_u32(76).await, 1920 << 16); // width
+        assert_eq!(cursor.get_u32(80).await, 1080 << 16); // height
+    }
+
     #[tokio::test]
     async fn test_init_segment() {
         testutil::init();
@@ -2794,6 +3418,57 @@ mod tests {
         assert_eq!(cursor.get_u32(20).await, 15); // sample size
     }
 
+    /// Tests composition offsets, as for a stream with B-frames, in normal `.mp4` files' `ctts`
+    /// boxes and media segments' `trun` boxes.
+    #[tokio::test]
+    async fn test_composition_offsets() {
+        testutil::init();
+        let db = TestDb::new(RealClocks {});
+        let mut r = db::RecordingToInsert {
+            flags: db::RecordingFlags::CompositionOffsets as i32,
+            ..Default::default()
+        };
+        let mut encoder = recording::SampleIndexEncoder::default();
+        // Decode order I P B B; presentation order I B B P.
+        for (offset_90k, bytes, is_key) in [(0, 10, true), (6000, 5, false), (-3000, 5, false)] {
+            encoder.add_sample_with_composition_offset(3000, offset_90k, bytes, is_key, &mut r);
+        }
+        encoder.add_sample_with_composition_offset(3000, -3000, 5, false, &mut r);
+
+        let mp4 =
+            make_mp4_from_encoders(Type::Normal, &db, vec![r.clone()], 0..12000, true).unwrap();
+        traverse(mp4.clone()).await;
+        let track = find_track(mp4, 1).await;
+        let mut cursor = track.stbl_cursor;
+        cursor.down().await;
+        assert!(cursor.find(b"ctts").await);
+        assert_eq!(
+            cursor.get_all().await,
+            &[
+                0x01, 0x00, 0x00, 0x00, // version + flags
+                0x00, 0x00, 0x00, 0x04, // entry_count
+                // entries
+                0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // sample_count, sample_offset
+                0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x17, 0x70, 0x00, 0x00, 0x00, 0x01, 0xff, 0xff,
+                0xf4, 0x48, 0x00, 0x00, 0x00, 0x01, 0xff, 0xff, 0xf4, 0x48,
+            ]
+        );
+
+        let mp4 = make_mp4_from_encoders(Type::MediaSegment, &db, vec![r], 0..12000, true).unwrap();
+        traverse(mp4.clone()).await;
+        let mut cursor = BoxCursor::new(mp4);
+        cursor.down().await;
+        assert!(cursor.find(b"moof").await);
+        cursor.down().await;
+        assert!(cursor.find(b"traf").await);
+        cursor.down().await;
+        assert!(cursor.find(b"trun").await);
+        assert_eq!(cursor.get_u32(0).await, 0x01000b05); // version 1 with composition offsets.
+        assert_eq!(cursor.get_u32(4).await, 4); // sample_count
+        assert_eq!(cursor.get_u32(16 + 12 + 8).await, 6000); // second sample's offset.
+        assert_eq!(cursor.get_u32(16 + 2 * 12 + 8).await, -3000i32 as u32);
+    }
+
     /// Tests `.mp4` files which represent a single frame, as in the live view WebSocket stream.
     #[tokio::test]
     async fn test_single_frame_media_segment() {
@@ -2859,6 +3534,114 @@ mod tests {
         db.syncer_join.join().unwrap();
     }
 
+    #[tokio::test]
+    async fn test_round_trip_encrypted() {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        testutil::init();
+        let mut db = TestDb::new(RealClocks {});
+        let key = db::dir::crypt::Key::parse(&STANDARD.encode([1u8; 32])).unwrap();
+        db.db.lock().set_sample_file_key(key).unwrap();
+        copy_mp4_to_db(&mut db);
+        let id = db::CompositeId::new(TEST_STREAM_ID, 0);
+        let on_disk = std::fs::read(db.tmpdir.path().join(format!("{:016x}", id.0))).unwrap();
+        assert!(db::dir::crypt::is_encrypted(&on_disk));
+
+        // The decrypted .mp4 matches the unencrypted case.
+        let mp4 = create_mp4_from_db(&db, 0, 0, false);
+        traverse(mp4.clone()).await;
+        let new_filename = write_mp4(&mp4, db.tmpdir.path()).await;
+        compare_mp4s(&new_filename, 0, 0);
+        let hash = digest(&mp4).await;
+        assert_eq!(
+            "123e2cf075125c81e80820bffa412d38729aff05c252c7ea2ab3384905903bb7",
+            hash.to_hex().as_str()
+        );
+        drop(db.syncer_channel);
+        db.db.lock().clear_on_flush();
+        db.syncer_join.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shared_index_cache() {
+        testutil::init();
+        let mut db = TestDb::new(RealClocks {});
+        copy_mp4_to_db(&mut db);
+        let cache = Arc::new(IndexCache::new(DEFAULT_INDEX_CACHE_BYTES));
+        let build = || {
+            let mut builder = FileBuilder::new(Type::Normal);
+            builder.set_index_cache(cache.clone());
+            let all_time = recording::Time(i64::MIN)..recording::Time(i64::MAX);
+            {
+                let l = db.db.lock();
+                l.list_recordings_by_time(TEST_STREAM_ID, all_time, &mut |r| {
+                    builder
+                        .append(&l, &r, 0..r.media_duration_90k, true)
+                        .unwrap();
+                    Ok(())
+                })
+                .unwrap();
+            }
+            builder
+                .build(db.db.clone(), db.dirs_by_stream_id.clone())
+                .unwrap()
+        };
+        let uncached = create_mp4_from_db(&db, 0, 0, false);
+        let first = build();
+        let expected = digest(&uncached).await;
+        assert_eq!(digest(&first).await, expected);
+        let (entries, bytes) = cache.stats();
+        assert_eq!(entries, 1);
+        assert!(bytes > 0);
+
+        // A second file should produce identical bytes from the cached index without adding
+        // another entry.
+        let second = build();
+        assert_eq!(digest(&second).await, expected);
+        assert_eq!(cache.stats(), (entries, bytes));
+        drop(db.syncer_channel);
+        db.db.lock().clear_on_flush();
+        db.syncer_join.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prebuild_indexes() {
+        testutil::init();
+        let mut db = TestDb::new(RealClocks {});
+        copy_mp4_to_db(&mut db);
+        let expected = digest(&create_mp4_from_db(&db, 0, 0, false)).await;
+        let mp4 = create_mp4_from_db(&db, 0, 0, false);
+        mp4.prebuild_indexes(2);
+        assert_eq!(digest(&mp4).await, expected);
+        drop(db.syncer_channel);
+        db.db.lock().clear_on_flush();
+        db.syncer_join.join().unwrap();
+    }
+
+    #[test]
+    fn index_cache_eviction() {
+        let key = |id| IndexCacheKey {
+            id: db::CompositeId(id),
+            open_id: 1,
+            rel_media_range_90k: 0..90_000,
+            first_frame_num: 1,
+            frames: 1,
+        };
+        let cache = IndexCache::new(10);
+        cache.insert(key(1), Arc::from(&[0u8; 4][..]));
+        cache.insert(key(2), Arc::from(&[0u8; 4][..]));
+        assert!(cache.get(&key(1)).is_some()); // now most recently used.
+        cache.insert(key(3), Arc::from(&[0u8; 4][..]));
+        assert!(cache.get(&key(1)).is_some());
+        assert!(cache.get(&key(2)).is_none());
+        assert!(cache.get(&key(3)).is_some());
+        assert_eq!(cache.stats(), (2, 8));
+
+        // An index larger than the entire cache is never stored.
+        cache.insert(key(4), Arc::from(&[0u8; 11][..]));
+        assert!(cache.get(&key(4)).is_none());
+        assert_eq!(cache.stats(), (2, 8));
+    }
+
     #[tokio::test]
     async fn test_round_trip_with_subtitles() {
         testutil::init();
@@ -2888,6 +3671,58 @@ mod tests {
         db.syncer_join.join().unwrap();
     }
 
+    #[test]
+    fn test_subtitle_labels() {
+        let t0 = recording::Time(1_000 * TIME_UNITS_PER_SEC);
+        let labels = SubtitleLabels {
+            camera_name: Some("driveway".to_owned()),
+            user: None,
+            signals: vec![SubtitleSignal {
+                short_name: "motion".to_owned(),
+                state_names: [(1, "still".to_owned()), (2, "moving".to_owned())]
+                    .into_iter()
+                    .collect(),
+                changes: vec![
+                    (t0, 1),
+                    (t0 + recording::Duration(135_000), 2),
+                    (t0 + recording::Duration(153_000), 1),
+                ],
+            }],
+        };
+        assert_eq!(labels.widths(), [7]); // "unknown".len()
+        assert_eq!(labels.len(), " driveway motion=unknown".len());
+        let write = |start| {
+            let mut v = Vec::new();
+            labels.write(start, &labels.widths(), &mut v);
+            String::from_utf8(v).unwrap()
+        };
+        assert_eq!(
+            write(t0 - recording::Duration(TIME_UNITS_PER_SEC)),
+            " driveway motion=unknown"
+        );
+        assert_eq!(write(t0), " driveway motion=still  ");
+
+        // The brief motion within the second is shown.
+        assert_eq!(
+            write(t0 + recording::Duration(TIME_UNITS_PER_SEC)),
+            " driveway motion=moving "
+        );
+        assert_eq!(
+            write(t0 + recording::Duration(2 * TIME_UNITS_PER_SEC)),
+            " driveway motion=still  "
+        );
+
+        let labels = SubtitleLabels {
+            camera_name: Some("driveway".to_owned()),
+            user: Some("guest".to_owned()),
+            signals: Vec::new(),
+        };
+        let mut v = Vec::new();
+        labels.write(t0, &labels.widths(), &mut v);
+        assert_eq!(v, b" driveway user=guest");
+        assert_eq!(labels.len(), v.len());
+    }
+
     #[tokio::test]
     async fn test_round_trip_with_edit_list() {
         testutil::init();
@@ -0,0 +1,391 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Low-resolution preview renditions of live streams, for watching many cameras at once over a
+//! slow link.
+//!
+//! A recorded stream with `preview` set in its config gets a second connection to its camera
+//! through `ffmpeg`, which transcodes it to at most [`HEIGHT`] pixels high at [`FPS`] frames per
+//! second and about [`BITRATE`] bits per second. The frames are kept only in RAM, from the latest
+//! key frame on, and sent to `live.m4s?preview=true` viewers as they arrive. Their video sample
+//! entries go in the database as usual, so viewers fetch init segments from `/api/init`.
+//!
+//! Previews require building with `--features=ffmpeg`. Without it, nothing produces them.
+
+#![cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+
+use std::sync::{Arc, Mutex};
+
+use base::FastHashMap;
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+/// The maximum height of a preview, in pixels. Smaller streams keep their size.
+pub const HEIGHT: u32 = 320;
+
+/// The frame rate of a preview.
+pub const FPS: u32 = 5;
+
+/// The target bitrate of a preview, as in ffmpeg's `-b:v`.
+pub const BITRATE: &str = "150k";
+
+/// The duration of each preview frame, in 90 kHz units. ffmpeg's `fps` filter makes the frame
+/// rate constant.
+const FRAME_DURATION_90K: u32 = 90_000 / FPS;
+
+/// How many frames a viewer may fall behind before it's disconnected.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// The preview renditions of streams which have them, by stream id.
+pub type Previews = FastHashMap<i32, Arc<Preview>>;
+
+/// A frame of a preview rendition.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub is_key: bool,
+
+    /// The sample data, as in a `.mp4` file.
+    pub data: Bytes,
+
+    pub video_sample_entry_id: i32,
+}
+
+/// A stream's preview rendition.
+pub struct Preview {
+    state: Mutex<State>,
+    tx: broadcast::Sender<Frame>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The frames since the latest key frame, inclusive.
+    gop: Vec<Frame>,
+
+    /// Accounts for `gop` in [`base::mem::BUDGET`].
+    charge: Option<base::mem::Charge<'static>>,
+}
+
+impl State {
+    fn update_charge(&mut self) {
+        let bytes = self.gop.iter().map(|f| f.data.len() as u64).sum();
+        self.charge
+            .get_or_insert_with(|| base::mem::BUDGET.charge(base::mem::Category::Previews, 0))
+            .set(bytes);
+    }
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Preview {
+            state: Mutex::new(State::default()),
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl Preview {
+    /// Adds a frame and sends it to viewers.
+    pub fn push(&self, f: Frame) {
+        let mut s = self.state.lock().unwrap();
+        if f.is_key {
+            s.gop.clear();
+        }
+        if f.is_key || !s.gop.is_empty() {
+            s.gop.push(f.clone());
+        }
+        s.update_charge();
+
+        // This only fails when there are no viewers. Sending under the lock keeps `subscribe`
+        // from missing or repeating a frame.
+        let _ = self.tx.send(f);
+    }
+
+    /// Forgets the buffered frames, as when the connection to the camera is lost.
+    pub fn reset(&self) {
+        let mut s = self.state.lock().unwrap();
+        s.gop.clear();
+        s.update_charge();
+    }
+
+    /// Returns the frames since the latest key frame and a receiver of later frames.
+    pub fn subscribe(&self) -> (Vec<Frame>, broadcast::Receiver<Frame>) {
+        let s = self.state.lock().unwrap();
+        (s.gop.clone(), self.tx.subscribe())
+    }
+}
+
+/// Returns a `.m4s` media segment of `frames`, to follow the `/api/init/<id>.mp4` of their video
+/// sample entry. As with `live.m4s`, each segment starts at media time 0.
+pub fn media_segment(frames: &[Frame]) -> Vec<u8> {
+    let first_is_key = frames.first().is_some_and(|f| f.is_key);
+    let trun_len = 20 + if first_is_key { 4 } else { 0 } + 8 * frames.len();
+    let traf_len = 8 + 16 + 16 + trun_len;
+    let moof_len = 8 + 16 + traf_len;
+    let data_len: usize = frames.iter().map(|f| f.data.len()).sum();
+    let mut v = Vec::with_capacity(moof_len + 8 + data_len);
+
+    // MovieFragmentBox (ISO/IEC 14496-12 section 8.8.4).
+    put_u32(&mut v, moof_len);
+    v.extend_from_slice(b"moof");
+
+    // MovieFragmentHeaderBox (section 8.8.5).
+    put_u32(&mut v, 16);
+    v.extend_from_slice(b"mfhd\x00\x00\x00\x00");
+    put_u32(&mut v, 1); // sequence_number
+
+    // TrackFragmentBox (section 8.8.6).
+    put_u32(&mut v, traf_len);
+    v.extend_from_slice(b"traf");
+
+    // TrackFragmentHeaderBox (section 8.8.7), with flags default-base-is-moof and track_id 1.
+    put_u32(&mut v, 16);
+    v.extend_from_slice(b"tfhd\x00\x02\x00\x00");
+    put_u32(&mut v, 1);
+
+    // TrackFragmentBaseMediaDecodeTimeBox (section 8.8.12).
+    put_u32(&mut v, 16);
+    v.extend_from_slice(b"tfdt\x00\x00\x00\x00");
+    put_u32(&mut v, 0);
+
+    // TrackRunBox (section 8.8.8), with flags data-offset-present, sample-duration-present,
+    // sample-size-present, and, if starting with a key frame, first-sample-flags-present.
+    // Other samples use the non-sync default of the init segment's `trex`.
+    put_u32(&mut v, trun_len);
+    v.extend_from_slice(b"trun\x00\x00\x03");
+    v.push(if first_is_key { 0x05 } else { 0x01 });
+    put_u32(&mut v, frames.len());
+    put_u32(&mut v, moof_len + 8); // data_offset, to just past the mdat header.
+    if first_is_key {
+        // As in `mp4.rs`: not leading, depends on no others, others may depend on it, no
+        // redundant coding, a sync sample.
+        put_u32(&mut v, (2 << 26) | (2 << 24) | (1 << 22) | (2 << 20));
+    }
+    for f in frames {
+        put_u32(&mut v, FRAME_DURATION_90K as usize);
+        put_u32(&mut v, f.data.len());
+    }
+    debug_assert_eq!(v.len(), moof_len);
+
+    // MediaDataBox (section 8.1.1).
+    put_u32(&mut v, 8 + data_len);
+    v.extend_from_slice(b"mdat");
+    for f in frames {
+        v.extend_from_slice(&f.data);
+    }
+    v
+}
+
+fn put_u32(v: &mut Vec<u8>, n: usize) {
+    v.extend_from_slice(&(n as u32).to_be_bytes());
+}
+
+#[cfg(feature = "ffmpeg")]
+pub use runner::Previewer;
+
+#[cfg(feature = "ffmpeg")]
+mod runner {
+    use std::sync::Arc;
+
+    use base::{bail, err, Error};
+    use tracing::{info, warn};
+    use url::Url;
+
+    use super::{Frame, Preview};
+    use crate::cmds::run::config::Secret;
+    use crate::stream::{self, Opener as _};
+
+    /// Keeps a stream's [`Preview`] up to date via its own connection to the camera.
+    pub struct Previewer {
+        label: String,
+        db: Arc<db::Database>,
+        shutdown_rx: base::shutdown::Receiver,
+        preview: Arc<Preview>,
+        opener: &'static crate::ffmpeg::Opener,
+
+        /// The stream's URL, without credentials.
+        url: Url,
+
+        /// The username and password to add to `url`, if the camera needs them.
+        creds: Option<(String, Secret)>,
+        bind: Option<stream::Bind>,
+    }
+
+    impl Previewer {
+        pub fn new(
+            db: Arc<db::Database>,
+            shutdown_rx: base::shutdown::Receiver,
+            c: &db::Camera,
+            s: &db::Stream,
+        ) -> Result<Self, Error> {
+            let label = format!("{}-{}-preview", &c.short_name, s.type_.as_str());
+            let url = s
+                .config
+                .url
+                .clone()
+                .ok_or_else(|| err!(InvalidArgument, msg("{label}: stream has no URL")))?;
+            let opener = match url.scheme() {
+                "rtsp" | "rtsps" if s.config.rtsp_transport == "udp" => {
+                    &crate::ffmpeg::PREVIEW_UDP_OPENER
+                }
+                "rtsp" | "rtsps" => &crate::ffmpeg::PREVIEW_TCP_OPENER,
+                "http" | "https" => &crate::ffmpeg::PREVIEW_MJPEG_OPENER,
+                _ => bail!(
+                    InvalidArgument,
+                    msg("{label}: previews require an rtsp or http stream URL")
+                ),
+            };
+            let creds = if c.config.username.is_empty() || c.config.rtsp_auth == "none" {
+                None
+            } else {
+                Some((
+                    c.config.username.clone(),
+                    Secret::camera_password(&c.config)?,
+                ))
+            };
+            Ok(Previewer {
+                label,
+                db,
+                shutdown_rx,
+                preview: Arc::new(Preview::default()),
+                opener,
+                url,
+                creds,
+                bind: stream::Bind::from_config(&c.config),
+            })
+        }
+
+        pub fn label(&self) -> &str {
+            &self.label
+        }
+
+        pub fn preview(&self) -> &Arc<Preview> {
+            &self.preview
+        }
+
+        /// Runs until shutdown, reconnecting after errors; blocks.
+        pub fn run(&self) {
+            while self.shutdown_rx.check().is_ok() {
+                if let Err(err) = self.run_once() {
+                    self.preview.reset();
+                    warn!(err = %err.chain(), "sleeping for 1 s after error");
+                    if self
+                        .shutdown_rx
+                        .wait_for(std::time::Duration::from_secs(1))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+            info!("shutting down");
+        }
+
+        fn run_once(&self) -> Result<(), Error> {
+            // As in the streamer, resolve the password on each attempt.
+            let url = match &self.creds {
+                None => self.url.clone(),
+                Some((username, password)) => crate::streamer::url_with_credentials(
+                    &self.url,
+                    &retina::client::Credentials {
+                        username: username.clone(),
+                        password: password.resolve()?,
+                    },
+                )?,
+            };
+            let options = stream::Options {
+                session: retina::client::SessionOptions::default(),
+                setup: retina::client::SetupOptions::default(),
+                play: retina::client::PlayOptions::default(),
+                audio_setup: None,
+                capture: None,
+                bind: self.bind.clone(),
+            };
+            let mut stream = self.opener.open(self.label.clone(), url, options)?;
+            let mut video_sample_entry_id = self
+                .db
+                .lock()
+                .insert_video_sample_entry(stream.video_sample_entry().clone())?;
+            while self.shutdown_rx.check().is_ok() {
+                let f = stream.next()?;
+                if f.new_video_sample_entry {
+                    video_sample_entry_id = self
+                        .db
+                        .lock()
+                        .insert_video_sample_entry(stream.video_sample_entry().clone())?;
+                }
+                self.preview.push(Frame {
+                    is_key: f.is_key,
+                    data: f.data,
+                    video_sample_entry_id,
+                });
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(is_key: bool, data: &'static [u8]) -> Frame {
+        Frame {
+            is_key,
+            data: Bytes::from_static(data),
+            video_sample_entry_id: 1,
+        }
+    }
+
+    #[test]
+    fn subscribe_starts_at_key_frame() {
+        let p = Preview::default();
+        p.push(frame(false, b"a"));
+        let (gop, _rx) = p.subscribe();
+        assert!(gop.is_empty());
+        p.push(frame(true, b"b"));
+        p.push(frame(false, b"c"));
+        let (gop, mut rx) = p.subscribe();
+        assert_eq!(
+            gop.iter().map(|f| &f.data[..]).collect::<Vec<_>>(),
+            [&b"b"[..], &b"c"[..]]
+        );
+        p.push(frame(true, b"d"));
+        assert_eq!(&rx.try_recv().unwrap().data[..], b"d");
+        let (gop, _rx) = p.subscribe();
+        assert_eq!(gop.len(), 1);
+        p.reset();
+        let (gop, _rx) = p.subscribe();
+        assert!(gop.is_empty());
+    }
+
+    /// Returns the length and type of the box at `pos`.
+    fn box_at(seg: &[u8], pos: usize) -> (usize, &[u8]) {
+        let len = u32::from_be_bytes(seg[pos..pos + 4].try_into().unwrap());
+        (len as usize, &seg[pos + 4..pos + 8])
+    }
+
+    #[test]
+    fn media_segment_layout() {
+        let seg = media_segment(&[frame(true, b"key"), frame(false, b"no")]);
+        let (moof_len, moof) = box_at(&seg, 0);
+        assert_eq!(moof, b"moof");
+        let (mdat_len, mdat) = box_at(&seg, moof_len);
+        assert_eq!(mdat, b"mdat");
+        assert_eq!(moof_len + mdat_len, seg.len());
+        assert_eq!(&seg[moof_len + 8..], b"keyno");
+
+        // The trun ends the moof, and its data offset points at the sample data.
+        let trun = seg.windows(4).position(|w| w == b"trun").unwrap() - 4;
+        assert_eq!(trun + box_at(&seg, trun).0, moof_len);
+        assert_eq!(seg[trun + 11], 0x05);
+        let offset = u32::from_be_bytes(seg[trun + 16..trun + 20].try_into().unwrap());
+        assert_eq!(offset as usize, moof_len + 8);
+
+        // Without a leading key frame, there are no first sample flags.
+        let seg = media_segment(&[frame(false, b"no")]);
+        let trun = seg.windows(4).position(|w| w == b"trun").unwrap() - 4;
+        assert_eq!(seg[trun + 11], 0x01);
+        assert_eq!(box_at(&seg, trun).0, 28);
+    }
+}
@@ -0,0 +1,554 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Capture and replay of raw RTSP sessions, for reproducing camera-specific parsing problems
+//! offline.
+//!
+//! Retina owns the RTSP connection, so capture works by pointing Retina at a local TCP proxy
+//! which forwards to the camera and records the bytes in each direction. Replay serves the
+//! recorded camera side of the session from a local listener, so the real [`crate::stream`] code
+//! parses exactly the bytes the camera sent.
+//!
+//! A dump file is [`MAGIC`] followed by records, each of:
+//!
+//! *   a direction byte: `C` from client (Moonfire NVR) to server (camera) or `S` the reverse.
+//! *   milliseconds since the connection was accepted, as a big-endian `u32`.
+//! *   the length of the data, as a big-endian `u32`.
+//! *   the data.
+//!
+//! `Authorization` headers are redacted from client requests.
+
+use std::borrow::Cow;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use base::{bail, err, Error};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tracing::{debug, info, warn};
+use url::Url;
+
+const MAGIC: &[u8] = b"MOONFIRE-RTSP-DUMP\n";
+
+/// The length of a record's direction, time, and length fields.
+const RECORD_HEADER_LEN: usize = 9;
+
+/// The longest RTSP header section accepted when splitting a session into messages.
+const MAX_HEADER_BYTES: usize = 64 << 10;
+
+/// How long to wait for Retina to connect to the proxy or replay server.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_MAX_SESSIONS: u32 = 3;
+const DEFAULT_MAX_SESSION_BYTES: u64 = 64 << 20;
+
+const SUFFIX: &str = ".rtspdump";
+
+/// The length of the milliseconds since epoch in dump names.
+const TIMESTAMP_LEN: usize = 15;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub from_server: bool,
+    pub elapsed_ms: u32,
+    pub data: Vec<u8>,
+}
+
+/// Returns the length of the complete RTSP message or interleaved data frame at the start of
+/// `buf`, `Ok(None)` if more bytes are needed, or `Err(())` if `buf` starts with neither.
+fn message_len(buf: &[u8]) -> Result<Option<usize>, ()> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'$') => {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+            let len = 4 + usize::from(u16::from_be_bytes([buf[2], buf[3]]));
+            Ok((buf.len() >= len).then_some(len))
+        }
+        Some(_) => {
+            let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                return if buf.len() > MAX_HEADER_BYTES {
+                    Err(())
+                } else {
+                    Ok(None)
+                };
+            };
+            let headers = std::str::from_utf8(&buf[..end]).map_err(|_| ())?;
+            let mut lines = headers.split("\r\n");
+            if !lines.next().is_some_and(|l| l.contains("RTSP/")) {
+                return Err(());
+            }
+            let mut body_len = 0;
+            for line in lines {
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.trim().eq_ignore_ascii_case("content-length") {
+                        body_len = value.trim().parse().map_err(|_| ())?;
+                    }
+                }
+            }
+            let len = end + 4 + body_len;
+            Ok((buf.len() >= len).then_some(len))
+        }
+    }
+}
+
+/// Replaces the value of any `Authorization` header in the given complete message.
+fn redact(msg: &[u8]) -> Cow<'_, [u8]> {
+    if msg.first() == Some(&b'$') {
+        return Cow::Borrowed(msg);
+    }
+    let Some(end) = msg.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Cow::Borrowed(msg);
+    };
+    let Ok(headers) = std::str::from_utf8(&msg[..end]) else {
+        return Cow::Borrowed(msg);
+    };
+    let mut out = Vec::with_capacity(msg.len());
+    for line in headers.split("\r\n") {
+        match line.split_once(':') {
+            Some((name, _)) if name.trim().eq_ignore_ascii_case("authorization") => {
+                out.extend_from_slice(name.as_bytes());
+                out.extend_from_slice(b": (redacted)");
+            }
+            _ => out.extend_from_slice(line.as_bytes()),
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(&msg[end + 2..]);
+    Cow::Owned(out)
+}
+
+/// Removes complete messages from the start of `buf`, returning them redacted.
+///
+/// If `buf` can't be split into messages, returns all of it as-is.
+fn take_client_messages(buf: &mut Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        match message_len(buf) {
+            Ok(Some(n)) => {
+                out.extend_from_slice(&redact(&buf[..n]));
+                buf.drain(..n);
+            }
+            Ok(None) => break,
+            Err(()) => {
+                out.append(buf);
+                break;
+            }
+        }
+    }
+    out
+}
+
+fn write_record(w: &mut impl std::io::Write, r: &Record) -> Result<(), std::io::Error> {
+    let len = u32::try_from(r.data.len()).expect("records are from bounded reads");
+    w.write_all(&[if r.from_server { b'S' } else { b'C' }])?;
+    w.write_all(&r.elapsed_ms.to_be_bytes())?;
+    w.write_all(&len.to_be_bytes())?;
+    w.write_all(&r.data)
+}
+
+/// Writes records to the dump at `path` until `rx` is closed.
+fn write_dump(path: PathBuf, max_bytes: u64, rx: mpsc::Receiver<Record>) {
+    let f = match std::fs::File::create(&path) {
+        Ok(f) => f,
+        Err(err) => {
+            warn!(%err, "unable to create RTSP dump {}", path.display());
+            return;
+        }
+    };
+    let mut w = std::io::BufWriter::new(f);
+    if let Err(err) = w.write_all(MAGIC) {
+        warn!(%err, "unable to write RTSP dump {}", path.display());
+        return;
+    }
+    let mut written = MAGIC.len() as u64;
+    let mut client = Vec::new();
+    let mut full = false;
+    for mut r in rx {
+        if !r.from_server {
+            client.extend_from_slice(&r.data);
+            r.data = take_client_messages(&mut client);
+            if r.data.is_empty() {
+                continue;
+            }
+        }
+        if full {
+            continue;
+        }
+        let len = (RECORD_HEADER_LEN + r.data.len()) as u64;
+        if written + len > max_bytes {
+            info!("RTSP dump {} is full; capturing no more", path.display());
+            full = true;
+            continue;
+        }
+        if let Err(err) = write_record(&mut w, &r) {
+            warn!(%err, "unable to write RTSP dump {}", path.display());
+            return;
+        }
+        written += len;
+    }
+    if let Err(err) = w.flush() {
+        warn!(%err, "unable to write RTSP dump {}", path.display());
+    }
+}
+
+/// Removes all but the newest `keep` dumps with the given prefix in `dir`.
+fn prune(dir: &Path, prefix: &str, keep: usize) -> Result<(), Error> {
+    let mut dumps = Vec::new();
+    for e in std::fs::read_dir(dir)? {
+        let name = e?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        // Match only the timestamp after the prefix, so that e.g. `cam-main-` doesn't match
+        // dumps of a camera called `cam-main`.
+        let is_dump = name
+            .strip_prefix(prefix)
+            .and_then(|n| n.strip_suffix(SUFFIX))
+            .is_some_and(|t| t.len() == TIMESTAMP_LEN && t.bytes().all(|b| b.is_ascii_digit()));
+        if is_dump {
+            dumps.push(name.to_owned());
+        }
+    }
+
+    // Names end with a fixed-width timestamp, so they sort by age.
+    dumps.sort_unstable();
+    for name in &dumps[..dumps.len().saturating_sub(keep)] {
+        debug!("removing old RTSP dump {name}");
+        std::fs::remove_file(dir.join(name))?;
+    }
+    Ok(())
+}
+
+/// Copies from `r` to `w`, also sending everything read to `tx`.
+async fn pump(
+    mut r: tokio::net::tcp::OwnedReadHalf,
+    mut w: tokio::net::tcp::OwnedWriteHalf,
+    from_server: bool,
+    start: Instant,
+    tx: mpsc::Sender<Record>,
+) {
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        let n = match r.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let _ = tx.send(Record {
+            from_server,
+            elapsed_ms: u32::try_from(start.elapsed().as_millis()).unwrap_or(u32::MAX),
+            data: buf[..n].to_vec(),
+        });
+        if w.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = w.shutdown().await;
+}
+
+/// Starts capturing a session to `url` as configured, returning the URL Retina should use
+/// instead. This must be called within a tokio runtime.
+pub fn start(config: &db::json::RtspCaptureConfig, label: &str, url: &Url) -> Result<Url, Error> {
+    let upstream = format!(
+        "{}:{}",
+        url.host_str()
+            .ok_or_else(|| err!(InvalidArgument, msg("RTSP URL has no host")))?,
+        url.port().unwrap_or(554)
+    );
+    let prefix = format!("{}-", label.replace('/', "_"));
+    let keep = config.max_sessions.unwrap_or(DEFAULT_MAX_SESSIONS);
+    prune(&config.dir, &prefix, keep.saturating_sub(1) as usize).map_err(|e| {
+        err!(
+            e,
+            msg("unable to prune RTSP dumps in {}", config.dir.display())
+        )
+    })?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let path = config
+        .dir
+        .join(format!("{prefix}{:015}{SUFFIX}", now.as_millis()));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    let port = listener.local_addr()?.port();
+    let mut proxied = url.clone();
+    proxied
+        .set_host(Some("127.0.0.1"))
+        .map_err(|e| err!(Internal, msg("unable to rewrite RTSP URL"), source(e)))?;
+    proxied
+        .set_port(Some(port))
+        .map_err(|()| err!(Internal, msg("unable to rewrite RTSP URL")))?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    info!("capturing RTSP session to {}", path.display());
+    let max_bytes = config
+        .max_session_bytes
+        .unwrap_or(DEFAULT_MAX_SESSION_BYTES);
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name(format!("dump-{label}"))
+        .spawn(move || write_dump(path, max_bytes, rx))
+        .expect("can't create thread");
+    tokio::spawn(async move {
+        let client = match tokio::time::timeout(ACCEPT_TIMEOUT, listener.accept()).await {
+            Ok(Ok((c, _))) => c,
+            Ok(Err(err)) => {
+                warn!(%err, "RTSP capture proxy accept failed");
+                return;
+            }
+            Err(_) => {
+                warn!("RTSP capture proxy wasn't connected to");
+                return;
+            }
+        };
+        let server = match tokio::net::TcpStream::connect(&upstream).await {
+            Ok(s) => s,
+            Err(err) => {
+                warn!(%err, "RTSP capture proxy unable to connect to {upstream}");
+                return;
+            }
+        };
+        let start = Instant::now();
+        let (client_r, client_w) = client.into_split();
+        let (server_r, server_w) = server.into_split();
+        tokio::join!(
+            pump(client_r, server_w, false, start, tx.clone()),
+            pump(server_r, client_w, true, start, tx),
+        );
+    });
+    Ok(proxied)
+}
+
+/// Reads a dump written by [`start`].
+pub fn read_dump(path: &Path) -> Result<Vec<Record>, Error> {
+    let data =
+        std::fs::read(path).map_err(|e| err!(e, msg("unable to read {}", path.display())))?;
+    let Some(mut rest) = data.strip_prefix(MAGIC) else {
+        bail!(
+            InvalidArgument,
+            msg("{} isn't an RTSP dump", path.display())
+        );
+    };
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        let len = rest
+            .get(5..RECORD_HEADER_LEN)
+            .map(|l| u32::from_be_bytes(l.try_into().unwrap()) as usize);
+        let Some(data) = len.and_then(|l| rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + l))
+        else {
+            warn!("{} ends with a truncated record", path.display());
+            break;
+        };
+        records.push(Record {
+            from_server: match rest[0] {
+                b'S' => true,
+                b'C' => false,
+                o => bail!(DataLoss, msg("bad record direction {o:#x}")),
+            },
+            elapsed_ms: u32::from_be_bytes(rest[1..5].try_into().unwrap()),
+            data: data.to_vec(),
+        });
+        rest = &rest[RECORD_HEADER_LEN + data.len()..];
+    }
+    Ok(records)
+}
+
+/// Reads one complete client message, discarding it.
+async fn read_client_message(
+    r: &mut tokio::net::TcpStream,
+    buf: &mut Vec<u8>,
+) -> Result<(), Error> {
+    loop {
+        match message_len(buf) {
+            Ok(Some(n)) => {
+                let is_data = buf[0] == b'$';
+                buf.drain(..n);
+                if !is_data {
+                    return Ok(());
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(()) => bail!(InvalidArgument, msg("client sent unparseable request")),
+        }
+        let mut chunk = [0u8; 4096];
+        let n = r.read(&mut chunk).await?;
+        if n == 0 {
+            bail!(Unavailable, msg("client disconnected"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Serves the camera side of one session from `records`.
+///
+/// Until playback starts, each RTSP response is sent after a request is received. Once
+/// interleaved data starts, it's sent as fast as the client reads it. RTSP responses during
+/// playback (such as to keepalives) are skipped, as the client's requests at that point depend
+/// on timing. Bytes which can't be split into messages are sent as-is, as they may be what the
+/// client fails to parse.
+async fn replay(mut conn: tokio::net::TcpStream, server: Vec<u8>) -> Result<(), Error> {
+    let mut client_buf = Vec::new();
+    let mut playing = false;
+    let mut pos = 0;
+    while pos < server.len() {
+        match message_len(&server[pos..]) {
+            Ok(Some(n)) => {
+                let msg = &server[pos..pos + n];
+                pos += n;
+                if msg[0] == b'$' {
+                    playing = true;
+                } else if playing {
+                    debug!("skipping RTSP message during playback");
+                    continue;
+                } else {
+                    read_client_message(&mut conn, &mut client_buf).await?;
+                }
+                conn.write_all(msg).await?;
+            }
+            Ok(None) => {
+                debug!("dump ends with a partial message");
+                break;
+            }
+            Err(()) => {
+                debug!(
+                    "sending unparseable remainder of {} bytes",
+                    server.len() - pos
+                );
+                conn.write_all(&server[pos..]).await?;
+                break;
+            }
+        }
+    }
+    conn.shutdown().await?;
+    Ok(())
+}
+
+/// Starts serving the camera side of the given dump, returning the URL to connect to. This must
+/// be called within a tokio runtime.
+pub async fn serve(records: Vec<Record>) -> Result<Url, Error> {
+    let server: Vec<u8> = records
+        .into_iter()
+        .filter(|r| r.from_server)
+        .flat_map(|r| r.data)
+        .collect();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = Url::parse(&format!("rtsp://{}/replay", listener.local_addr()?))
+        .expect("replay URL should be valid");
+    tokio::spawn(async move {
+        let conn = match tokio::time::timeout(ACCEPT_TIMEOUT, listener.accept()).await {
+            Ok(Ok((c, _))) => c,
+            Ok(Err(err)) => {
+                warn!(%err, "replay accept failed");
+                return;
+            }
+            Err(_) => {
+                warn!("replay server wasn't connected to");
+                return;
+            }
+        };
+        if let Err(err) = replay(conn, server).await {
+            warn!(err = %err.chain(), "replay failed");
+        }
+    });
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIBE: &[u8] = b"DESCRIBE rtsp://192.168.5.2/main RTSP/1.0\r\n\
+        CSeq: 2\r\n\
+        Authorization: Basic YWRtaW46c2VjcmV0\r\n\
+        \r\n";
+
+    #[test]
+    fn message_len() {
+        let resp = b"RTSP/1.0 200 OK\r\nCSeq: 2\r\nContent-Length: 3\r\n\r\nv=0";
+        assert_eq!(super::message_len(resp), Ok(Some(resp.len())));
+        assert_eq!(super::message_len(&resp[..resp.len() - 1]), Ok(None));
+        assert_eq!(super::message_len(DESCRIBE), Ok(Some(DESCRIBE.len())));
+        assert_eq!(super::message_len(b"$\x00\x00\x02ab$"), Ok(Some(6)));
+        assert_eq!(super::message_len(b"$\x00\x00\x02a"), Ok(None));
+        assert_eq!(super::message_len(b"garbage\r\n\r\n"), Err(()));
+        assert_eq!(super::message_len(b""), Ok(None));
+    }
+
+    #[test]
+    fn redaction() {
+        let mut buf = DESCRIBE.to_vec();
+        buf.extend_from_slice(b"$\x01\x00\x01x");
+        buf.extend_from_slice(&DESCRIBE[..10]);
+        let out = take_client_messages(&mut buf);
+        assert_eq!(
+            std::str::from_utf8(&out).unwrap(),
+            "DESCRIBE rtsp://192.168.5.2/main RTSP/1.0\r\n\
+             CSeq: 2\r\n\
+             Authorization: (redacted)\r\n\
+             \r\n\
+             $\x01\x00\x01x"
+        );
+        assert_eq!(buf, &DESCRIBE[..10]);
+    }
+
+    #[test]
+    fn dump_round_trip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join(format!("cam-main-{:015}{SUFFIX}", 1));
+        let (tx, rx) = mpsc::channel();
+        let records = [
+            Record {
+                from_server: false,
+                elapsed_ms: 0,
+                data: DESCRIBE.to_vec(),
+            },
+            Record {
+                from_server: true,
+                elapsed_ms: 5,
+                data: b"RTSP/1.0 200 OK\r\nCSeq: 2\r\n\r\n".to_vec(),
+            },
+            Record {
+                from_server: true,
+                elapsed_ms: 6,
+                data: vec![b'$'; 100],
+            },
+        ];
+        for r in &records {
+            tx.send(r.clone()).unwrap();
+        }
+        drop(tx);
+        write_dump(path.clone(), 200, rx);
+        let read = read_dump(&path).unwrap();
+        assert_eq!(read.len(), 2, "last record should exceed max bytes");
+        assert!(!read[0].data.windows(5).any(|w| w == b"YWRta"));
+        assert_eq!(read[1], records[1]);
+
+        // Pruning keeps the newest.
+        for i in 2..5 {
+            std::fs::write(tmpdir.path().join(format!("cam-main-{i:015}{SUFFIX}")), b"").unwrap();
+        }
+        std::fs::write(tmpdir.path().join("other-file"), b"").unwrap();
+        let other = format!("cam-main-sub-{:015}{SUFFIX}", 1);
+        std::fs::write(tmpdir.path().join(&other), b"").unwrap();
+        prune(tmpdir.path(), "cam-main-", 2).unwrap();
+        let mut left: Vec<_> = std::fs::read_dir(tmpdir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                format!("cam-main-{:015}{SUFFIX}", 3),
+                format!("cam-main-{:015}{SUFFIX}", 4),
+                other,
+                "other-file".to_owned(),
+            ]
+        );
+    }
+}
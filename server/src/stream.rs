@@ -2,13 +2,16 @@
 // Copyright (C) 2016 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
+use crate::audio_level;
 use base::{bail, err, Error};
 use bytes::Bytes;
 use futures::StreamExt;
 use retina::client::Demuxed;
 use retina::codec::CodecItem;
+use std::collections::BTreeMap;
 use std::pin::Pin;
 use std::result::Result;
+use std::str::FromStr;
 use tracing::Instrument;
 use url::Url;
 
@@ -35,7 +38,7 @@ const PIXEL_ASPECT_RATIOS: [((u16, u16), (u16, u16)); 6] = [
 ///
 /// Note that at least in the case of .mp4 muxing, we don't need to fix up the underlying SPS.
 /// PixelAspectRatioBox's definition says that it overrides the H.264-level declaration.
-fn default_pixel_aspect_ratio(width: u16, height: u16) -> (u16, u16) {
+pub fn default_pixel_aspect_ratio(width: u16, height: u16) -> (u16, u16) {
     if width >= height {
         PIXEL_ASPECT_RATIOS
             .iter()
@@ -54,6 +57,103 @@ fn default_pixel_aspect_ratio(width: u16, height: u16) -> (u16, u16) {
 pub struct Options {
     pub session: retina::client::SessionOptions,
     pub setup: retina::client::SetupOptions,
+    pub play: retina::client::PlayOptions,
+
+    /// If present, the camera's audio is also set up with these options to measure its level;
+    /// see [`Stream::take_audio_levels`].
+    pub audio_setup: Option<retina::client::SetupOptions>,
+
+    /// If present, the session's raw bytes are dumped as described in [`crate::rtsp_capture`].
+    pub capture: Option<db::json::RtspCaptureConfig>,
+
+    /// If present, where to connect to the camera from. Only the ffmpeg backend supports this.
+    pub bind: Option<Bind>,
+}
+
+/// The local end of connections to a camera, as configured via `bindAddress` and
+/// `bindInterface` in [`db::json::CameraConfig`].
+#[derive(Clone, Debug, Default)]
+pub struct Bind {
+    pub address: Option<std::net::IpAddr>,
+    pub interface: Option<String>,
+}
+
+impl Bind {
+    /// Returns the camera's binding, or `None` if it has none.
+    pub fn from_config(c: &db::json::CameraConfig) -> Option<Self> {
+        if c.bind_address.is_none() && c.bind_interface.is_empty() {
+            return None;
+        }
+        Some(Bind {
+            address: c.bind_address,
+            interface: (!c.bind_interface.is_empty()).then(|| c.bind_interface.clone()),
+        })
+    }
+}
+
+/// Overrides of Retina's defaults, as configured via `rtspOptions` in [`db::json::StreamConfig`].
+///
+/// These are an escape hatch for cameras which don't follow the RTSP spec closely enough for
+/// Retina's defaults. Each key corresponds to a Retina option; values are as in Retina's
+/// `FromStr` implementations.
+#[derive(Default)]
+pub struct RtspOptions {
+    teardown: Option<retina::client::TeardownPolicy>,
+    unassigned_channel_data: Option<retina::client::UnassignedChannelDataPolicy>,
+    initial_timestamp: Option<retina::client::InitialTimestampPolicy>,
+    initial_sequence_number: Option<retina::client::InitialSequenceNumberPolicy>,
+    max_timestamp_jump_secs: Option<std::num::NonZeroU32>,
+}
+
+impl RtspOptions {
+    pub fn parse(raw: &BTreeMap<String, String>) -> Result<Self, Error> {
+        fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, Error> {
+            value.parse().map_err(|_| {
+                err!(
+                    InvalidArgument,
+                    msg("invalid value {value:?} for rtspOptions key {key:?}")
+                )
+            })
+        }
+        let mut o = RtspOptions::default();
+        for (key, value) in raw {
+            match key.as_str() {
+                "teardown" => o.teardown = Some(parse_value(key, value)?),
+                "unassignedChannelData" => {
+                    o.unassigned_channel_data = Some(parse_value(key, value)?)
+                }
+                "initialTimestamp" => o.initial_timestamp = Some(parse_value(key, value)?),
+                "initialSequenceNumber" => {
+                    o.initial_sequence_number = Some(parse_value(key, value)?)
+                }
+                "maxTimestampJumpSecs" => {
+                    o.max_timestamp_jump_secs = Some(parse_value(key, value)?)
+                }
+                _ => bail!(InvalidArgument, msg("unknown rtspOptions key {key:?}")),
+            }
+        }
+        Ok(o)
+    }
+
+    /// Applies these overrides to `options`.
+    pub fn apply(&self, mut options: Options) -> Options {
+        if let Some(p) = self.teardown {
+            options.session = options.session.teardown(p);
+        }
+        if let Some(p) = self.unassigned_channel_data {
+            options.session = options.session.unassigned_channel_data(p);
+        }
+        if let Some(p) = self.initial_timestamp {
+            options.play = options.play.initial_timestamp(p);
+        }
+        if let Some(p) = self.initial_sequence_number {
+            options.play = options.play.initial_seq(p);
+        }
+        if let Some(s) = self.max_timestamp_jump_secs {
+            options.play = options.play.enforce_timestamps_with_max_jump_secs(s);
+        }
+        options
+    }
 }
 
 /// Opens a RTSP stream. This is a trait for test injection.
@@ -77,12 +177,25 @@ pub struct VideoFrame {
     pub data: Bytes,
 
     pub new_video_sample_entry: bool,
+
+    /// The number of RTP packets lost since the previous frame.
+    pub loss: u16,
 }
 
 pub trait Stream: Send {
     fn tool(&self) -> Option<&retina::client::Tool>;
+
+    /// Returns details of the RTSP session, if this is a RTSP stream.
+    fn rtsp_session(&self) -> Option<db::RtspSession>;
     fn video_sample_entry(&self) -> &db::VideoSampleEntryToInsert;
     fn next(&mut self) -> Result<VideoFrame, Error>;
+
+    /// Returns the levels of each second of audio received since the last call, oldest first,
+    /// in dBFS. This is empty unless [`Options::audio_setup`] was set and the camera has audio in
+    /// a supported encoding; see [`crate::audio_level`].
+    fn take_audio_levels(&mut self) -> Vec<f32> {
+        Vec::new()
+    }
 }
 
 pub struct RealOpener;
@@ -100,6 +213,20 @@ impl Opener for RealOpener {
             .session
             .user_agent(format!("Moonfire NVR {}", env!("CARGO_PKG_VERSION")));
         let rt_handle = tokio::runtime::Handle::current();
+        let url = match &options.capture {
+            Some(c) => {
+                // Everything must go through the capturing proxy's TCP connection.
+                options.setup = options
+                    .setup
+                    .transport("tcp".parse().expect("tcp should be a valid transport"));
+                options.audio_setup = options
+                    .audio_setup
+                    .map(|s| s.transport("tcp".parse().expect("tcp should be a valid transport")));
+                let _enter = rt_handle.enter();
+                crate::rtsp_capture::start(c, &label, &url)?
+            }
+            None => url,
+        };
         let (inner, first_frame) = rt_handle
             .block_on(
                 rt_handle.spawn(
@@ -155,6 +282,8 @@ struct RetinaStreamInner {
     label: String,
     session: Demuxed,
     video_sample_entry: db::VideoSampleEntryToInsert,
+    rtsp_session: db::RtspSession,
+    audio: Option<audio_level::Meter>,
 }
 
 fn params_to_sample_entry(
@@ -178,6 +307,24 @@ fn params_to_sample_entry(
     })
 }
 
+/// Maps an error from a RTSP request, distinguishing the camera rejecting authentication
+/// (`Unauthenticated`) from other failures, which are treated as network problems
+/// (`Unavailable`).
+///
+/// Retina doesn't expose the response status, so this looks for it in the message.
+fn rtsp_err(e: retina::Error) -> Error {
+    let description = e.to_string();
+    if description.contains("401") || description.contains("Unauthorized") {
+        err!(
+            Unauthenticated,
+            msg("camera rejected authentication"),
+            source(e)
+        )
+    } else {
+        err!(Unavailable, source(e))
+    }
+}
+
 impl RetinaStreamInner {
     /// Plays to first frame. No timeout; that's the caller's responsibility.
     async fn play(
@@ -187,7 +334,7 @@ impl RetinaStreamInner {
     ) -> Result<(Box<Self>, retina::codec::VideoFrame), Error> {
         let mut session = retina::client::Session::describe(url, options.session)
             .await
-            .map_err(|e| err!(Unknown, source(e)))?;
+            .map_err(rtsp_err)?;
         tracing::debug!("connected to {:?}, tool {:?}", &label, session.tool());
         let video_i = session
             .streams()
@@ -202,11 +349,34 @@ impl RetinaStreamInner {
         session
             .setup(video_i, options.setup)
             .await
-            .map_err(|e| err!(Unknown, source(e)))?;
-        let session = session
-            .play(retina::client::PlayOptions::default())
-            .await
-            .map_err(|e| err!(Unknown, source(e)))?;
+            .map_err(rtsp_err)?;
+        let mut audio = None;
+        if let Some(audio_setup) = options.audio_setup {
+            let found = session.streams().iter().enumerate().find_map(|(i, s)| {
+                let codec = audio_level::Codec::from_encoding_name(s.encoding_name())?;
+                let channels = s.channels().map(|c| c.get()).unwrap_or(1);
+                (s.media() == "audio").then(|| (i, codec, s.clock_rate_hz(), channels))
+            });
+            match found {
+                Some((i, codec, clock_rate, channels)) => {
+                    session.setup(i, audio_setup).await.map_err(rtsp_err)?;
+                    audio = Some(audio_level::Meter::new(codec, clock_rate, channels));
+                }
+                None => tracing::warn!(
+                    "{}: no audio stream in a supported encoding (PCMU, PCMA, or L16); \
+                     audio level won't be measured",
+                    &label
+                ),
+            }
+        }
+        let rtsp_session = db::RtspSession {
+            sdp: session.sdp().to_vec(),
+            transport: session.streams()[video_i]
+                .ctx()
+                .map(|ctx| format!("{ctx:?}")),
+            server: session.tool().map(|t| format!("{t:?}")),
+        };
+        let session = session.play(options.play).await.map_err(rtsp_err)?;
         let mut session = session.demuxed().map_err(|e| err!(Unknown, source(e)))?;
 
         // First frame.
@@ -219,6 +389,11 @@ impl RetinaStreamInner {
                         break v;
                     }
                 }
+                Some(Ok(CodecItem::AudioFrame(a))) => {
+                    if let Some(m) = audio.as_mut() {
+                        m.push(a.data());
+                    }
+                }
                 Some(Ok(_)) => {}
             }
         };
@@ -232,6 +407,8 @@ impl RetinaStreamInner {
             label,
             session,
             video_sample_entry,
+            rtsp_session,
+            audio,
         });
         Ok((self_, first_frame))
     }
@@ -252,7 +429,7 @@ impl RetinaStreamInner {
                 .next()
                 .await
                 .transpose()
-                .map_err(|e| err!(Unknown, source(e)))?
+                .map_err(|e| err!(Unavailable, source(e)))?
             {
                 None => bail!(Unavailable, msg("end of stream")),
                 Some(CodecItem::VideoFrame(v)) => {
@@ -274,6 +451,11 @@ impl RetinaStreamInner {
                     };
                     return Ok((self, v, p));
                 }
+                Some(CodecItem::AudioFrame(a)) => {
+                    if let Some(m) = self.audio.as_mut() {
+                        m.push(a.data());
+                    }
+                }
                 Some(_) => {}
             }
         }
@@ -285,6 +467,10 @@ impl Stream for RetinaStream {
         self.inner.as_ref().unwrap().session.tool()
     }
 
+    fn rtsp_session(&self) -> Option<db::RtspSession> {
+        Some(self.inner.as_ref().unwrap().rtsp_session.clone())
+    }
+
     fn video_sample_entry(&self) -> &db::VideoSampleEntryToInsert {
         &self.inner.as_ref().unwrap().video_sample_entry
     }
@@ -334,10 +520,18 @@ impl Stream for RetinaStream {
             #[cfg(test)]
             duration: 0,
             is_key: frame.is_random_access_point(),
+            loss: frame.loss(),
             data: frame.into_data().into(),
             new_video_sample_entry,
         })
     }
+
+    fn take_audio_levels(&mut self) -> Vec<f32> {
+        match self.inner.as_mut().and_then(|i| i.audio.as_mut()) {
+            Some(m) => m.take_levels(),
+            None => Vec::new(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -429,6 +623,10 @@ pub mod testutil {
             None
         }
 
+        fn rtsp_session(&self) -> Option<db::RtspSession> {
+            None
+        }
+
         fn next(&mut self) -> Result<VideoFrame, Error> {
             let sample = self
                 .reader
@@ -443,6 +641,7 @@ pub mod testutil {
                 is_key: sample.is_sync,
                 data: sample.bytes,
                 new_video_sample_entry: false,
+                loss: 0,
             })
         }
 
@@ -470,4 +669,32 @@ mod tests {
             assert_eq!(Ratio::new(h * h_spacing, w * v_spacing), Ratio::new(9, 16));
         }
     }
+
+    #[test]
+    fn rtsp_options() {
+        testutil::init();
+        use super::RtspOptions;
+        let parse = |pairs: &[(&str, &str)]| {
+            RtspOptions::parse(
+                &pairs
+                    .iter()
+                    .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect(),
+            )
+        };
+        let o = parse(&[
+            ("teardown", "always"),
+            ("initialTimestamp", "ignore"),
+            ("maxTimestampJumpSecs", "20"),
+        ])
+        .unwrap();
+        assert!(o.teardown.is_some());
+        assert!(o.unassigned_channel_data.is_none());
+        assert_eq!(o.max_timestamp_jump_secs.map(|s| s.get()), Some(20));
+        let e = parse(&[("teardwon", "always")]).err().unwrap();
+        assert_eq!(e.kind(), base::ErrorKind::InvalidArgument);
+        assert!(e.to_string().contains("teardwon"), "{e}");
+        let e = parse(&[("maxTimestampJumpSecs", "0")]).err().unwrap();
+        assert_eq!(e.kind(), base::ErrorKind::InvalidArgument);
+    }
 }
@@ -0,0 +1,378 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/api/activity` handling: the live views and downloads in progress, for administrators.
+//!
+//! Each live view or download holds a [`Handle`] for its duration, which counts the bytes sent
+//! and lets an administrator terminate it. Live views stop promptly; downloads stop before
+//! sending their next chunk.
+
+use base::{bail, clock::Clocks as _, err};
+use db::recording;
+use futures::Stream;
+use http::{Method, Request, StatusCode};
+use hyper::body::Buf as _;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::body::{wrap_error, BoxedError, Chunk};
+use crate::json;
+
+use super::{
+    into_json_body, parse_json_body, plain_response, require_csrf_if_session, serve_json, Caller,
+    ResponseResult, Service,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Kind {
+    /// A `live.m4s` WebSocket.
+    Live,
+
+    /// A `view.mp4`, `view.m4s`, `view.mkv`, or `export.zip` response.
+    Download,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Live => "live",
+            Kind::Download => "download",
+        }
+    }
+}
+
+struct Shared {
+    id: u64,
+    kind: Kind,
+    user: Option<String>,
+    camera_uuid: Uuid,
+    stream_type: db::StreamType,
+    start: recording::Time,
+    started: Instant,
+    bytes: AtomicU64,
+    cancelled: AtomicBool,
+    cancel: tokio::sync::Notify,
+}
+
+/// The live views and downloads in progress.
+#[derive(Default)]
+pub(super) struct Registry(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    next_id: AtomicU64,
+    by_id: Mutex<BTreeMap<u64, Arc<Shared>>>,
+}
+
+/// A registered live view or download, which is unregistered when dropped.
+pub(super) struct Handle {
+    shared: Arc<Shared>,
+    registry: Arc<Inner>,
+}
+
+impl Registry {
+    pub(super) fn register(
+        &self,
+        kind: Kind,
+        user: Option<String>,
+        camera_uuid: Uuid,
+        stream_type: db::StreamType,
+        start: recording::Time,
+    ) -> Handle {
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let shared = Arc::new(Shared {
+            id,
+            kind,
+            user,
+            camera_uuid,
+            stream_type,
+            start,
+            started: Instant::now(),
+            bytes: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            cancel: tokio::sync::Notify::new(),
+        });
+        self.0.by_id.lock().unwrap().insert(id, shared.clone());
+        Handle {
+            shared,
+            registry: self.0.clone(),
+        }
+    }
+
+    fn list(&self) -> Vec<json::Activity> {
+        let now = Instant::now();
+        self.0
+            .by_id
+            .lock()
+            .unwrap()
+            .values()
+            .map(|s| {
+                let bytes = s.bytes.load(Ordering::Relaxed);
+                let secs = now.duration_since(s.started).as_secs_f64();
+                json::Activity {
+                    id: s.id,
+                    kind: s.kind.as_str(),
+                    user: s.user.clone(),
+                    camera_uuid: s.camera_uuid,
+                    stream: s.stream_type.as_str(),
+                    start_time_90k: s.start,
+                    bytes,
+                    bytes_per_sec: if secs > 0.0 {
+                        (bytes as f64 / secs) as u64
+                    } else {
+                        0
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Asks the given activity to stop, returning false if there's no such activity.
+    fn cancel(&self, id: u64) -> bool {
+        let Some(s) = self.0.by_id.lock().unwrap().get(&id).cloned() else {
+            return false;
+        };
+        s.cancelled.store(true, Ordering::Relaxed);
+        s.cancel.notify_waiters();
+        true
+    }
+}
+
+impl Handle {
+    pub(super) fn add_bytes(&self, n: u64) {
+        self.shared.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns when an administrator has terminated this activity.
+    pub(super) async fn cancelled(&self) {
+        loop {
+            // `notify_waiters` wakes `Notified`s created before it's called, even if not yet
+            // polled, so creating this before checking the flag avoids missing a cancellation.
+            let notified = self.shared.cancel.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Returns an error if an administrator has terminated this activity.
+    pub(super) fn check(&self) -> Result<(), base::Error> {
+        if self.is_cancelled() {
+            bail!(Cancelled, msg("terminated by an administrator"));
+        }
+        Ok(())
+    }
+
+    /// Wraps a response stream to count its bytes and end it on termination.
+    pub(super) fn track<S>(self: Arc<Self>, inner: S) -> Tracked<S> {
+        Tracked {
+            inner,
+            handle: self,
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.registry.by_id.lock().unwrap().remove(&self.shared.id);
+    }
+}
+
+/// A response stream or entity tracked by a [`Handle`].
+pub(super) struct Tracked<S> {
+    inner: S,
+    handle: Arc<Handle>,
+}
+
+impl<S> Stream for Tracked<S>
+where
+    S: Stream<Item = Result<Chunk, BoxedError>> + Unpin,
+{
+    type Item = Result<Chunk, BoxedError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Err(e) = self.handle.check() {
+            return Poll::Ready(Some(Err(wrap_error(e))));
+        }
+        let r = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(c))) = &r {
+            self.handle.add_bytes(c.remaining() as u64);
+        }
+        r
+    }
+}
+
+impl<E> http_serve::Entity for Tracked<E>
+where
+    E: http_serve::Entity<Data = Chunk, Error = BoxedError>,
+{
+    type Data = Chunk;
+    type Error = BoxedError;
+
+    fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    fn get_range(
+        &self,
+        range: Range<u64>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Chunk, BoxedError>> + Send + Sync>> {
+        Box::pin(self.handle.clone().track(self.inner.get_range(range)))
+    }
+
+    fn add_headers(&self, hdrs: &mut http::header::HeaderMap) {
+        self.inner.add_headers(hdrs)
+    }
+
+    fn etag(&self) -> Option<http::HeaderValue> {
+        self.inner.etag()
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        self.inner.last_modified()
+    }
+}
+
+impl Service {
+    /// Registers a live view or download of the given stream by `caller`.
+    pub(super) fn register_activity(
+        &self,
+        kind: Kind,
+        caller: &Caller,
+        camera_uuid: Uuid,
+        stream_type: db::StreamType,
+    ) -> Handle {
+        self.activity_registry.register(
+            kind,
+            caller.user.as_ref().map(|u| u.name.clone()),
+            camera_uuid,
+            stream_type,
+            recording::Time::new(self.db.clocks().realtime()),
+        )
+    }
+
+    pub(super) fn activities(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        caller: Caller,
+    ) -> ResponseResult {
+        if !caller.permissions.admin_users {
+            bail!(Unauthenticated, msg("must have admin_users permission"));
+        }
+        match *req.method() {
+            Method::GET | Method::HEAD => serve_json(
+                req,
+                &json::GetActivityResponse {
+                    activities: self.activity_registry.list(),
+                },
+            ),
+            _ => Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "GET or HEAD expected",
+            )),
+        }
+    }
+
+    pub(super) async fn activity(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        id: u64,
+    ) -> ResponseResult {
+        if *req.method() != Method::DELETE {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "DELETE expected",
+            ));
+        }
+        if !caller.permissions.admin_users {
+            bail!(Unauthenticated, msg("must have admin_users permission"));
+        }
+        let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::DeleteActivity = parse_json_body(&b)?;
+        require_csrf_if_session(&caller, r.csrf)?;
+        if !self.activity_registry.cancel(id) {
+            return Err(err!(NotFound, msg("no such activity {id}")));
+        }
+        info!(id, "activity terminated via API");
+        Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::tests::Server;
+    use db::testutil;
+    use futures::{stream, StreamExt as _};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn register_track_cancel() {
+        let r = Registry::default();
+        let h = Arc::new(r.register(
+            Kind::Download,
+            Some("slamb".to_owned()),
+            Uuid::nil(),
+            db::StreamType::Main,
+            recording::Time(0),
+        ));
+        let chunks: Vec<Result<Chunk, BoxedError>> =
+            vec![Ok(Chunk::from(&b"abc"[..])), Ok(Chunk::from(&b"de"[..]))];
+        let mut s = h.clone().track(stream::iter(chunks));
+        s.next().await.unwrap().unwrap();
+        let l = r.list();
+        assert_eq!(l.len(), 1);
+        assert_eq!(l[0].bytes, 3);
+        assert_eq!(l[0].user.as_deref(), Some("slamb"));
+
+        let id = l[0].id;
+        assert!(r.cancel(id));
+        h.cancelled().await;
+        assert!(s.next().await.unwrap().is_err());
+        assert!(!r.cancel(id + 1));
+
+        drop(s);
+        drop(h);
+        assert!(r.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn requires_admin() {
+        testutil::init();
+        let s = Server::new(Some(db::Permissions::new()));
+        let cli = reqwest::Client::new();
+        let url = format!("{}/api/activity", &s.base_url);
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let mut permissions = db::Permissions::new();
+        permissions.admin_users = true;
+        let s = Server::new(Some(permissions));
+        let url = format!("{}/api/activity", &s.base_url);
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body, json!({ "activities": [] }));
+        let resp = cli
+            .delete(format!("{url}/1"))
+            .json(&json!({}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,177 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/api/cameras/<uuid>/` handling.
+
+use base::{bail, err, ErrorKind, ResultExt as _};
+use http::{Method, Request, StatusCode};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::json;
+
+use super::{
+    into_json_body, parse_json_body, plain_response, require_csrf_if_session, serve_json, Caller,
+    ResponseResult, Service,
+};
+
+impl Service {
+    pub(super) async fn camera(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+    ) -> ResponseResult {
+        match *req.method() {
+            Method::GET | Method::HEAD => self.get_camera(&req, uuid),
+            Method::PATCH => self.patch_camera(req, caller, uuid).await,
+            _ => Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "GET, HEAD, or PATCH expected",
+            )),
+        }
+    }
+
+    fn get_camera(&self, req: &Request<hyper::body::Incoming>, uuid: Uuid) -> ResponseResult {
+        let db = self.db.lock();
+        let camera = db
+            .get_camera(uuid)
+            .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+        serve_json(
+            req,
+            &json::Camera::wrap(camera, &db, true, false).err_kind(ErrorKind::Internal)?,
+        )
+    }
+
+    /// Updates the camera's descriptive metadata. Other config fields aren't editable here.
+    async fn patch_camera(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+    ) -> ResponseResult {
+        if !caller.permissions.admin_users {
+            bail!(Unauthenticated, msg("must have admin_users permission"));
+        }
+        let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::PatchCamera = parse_json_body(&b)?;
+        require_csrf_if_session(&caller, r.csrf)?;
+        let u = r.update;
+        if let Some(Some(c)) = &u.coordinates {
+            if !c.is_valid() {
+                bail!(
+                    InvalidArgument,
+                    msg("coordinates must be within [-90, 90] latitude and [-180, 180] longitude")
+                );
+            }
+        }
+        if let Some(Some(d)) = u.direction_deg {
+            if d >= 360 {
+                bail!(InvalidArgument, msg("directionDeg must be less than 360"));
+            }
+        }
+        if let Some(tags) = &u.tags {
+            for (i, t) in tags.iter().enumerate() {
+                if t.is_empty() || t.trim() != t {
+                    bail!(
+                        InvalidArgument,
+                        msg("tag {t:?} must be non-empty without surrounding whitespace")
+                    );
+                }
+                if tags[..i].contains(t) {
+                    bail!(InvalidArgument, msg("duplicate tag {t:?}"));
+                }
+            }
+        }
+        let mut db = self.db.lock();
+        let id = db
+            .get_camera(uuid)
+            .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?
+            .id;
+        let mut change = db.null_camera_change(id)?;
+        if let Some(l) = u.location {
+            change.config.location = l;
+        }
+        if let Some(c) = u.coordinates {
+            change.config.coordinates = c;
+        }
+        if let Some(d) = u.direction_deg {
+            change.config.direction_deg = d;
+        }
+        if let Some(t) = u.tags {
+            change.config.tags = t;
+        }
+        db.update_camera(id, change)?;
+        info!(%uuid, "camera metadata updated via API");
+        Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::web::tests::Server;
+    use db::testutil;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn patch_metadata() {
+        testutil::init();
+        let mut permissions = db::Permissions::new();
+        permissions.admin_users = true;
+        let s = Server::new(Some(permissions));
+        let cli = reqwest::Client::new();
+        let url = format!("{}/api/cameras/{}/", &s.base_url, s.db.test_camera_uuid);
+
+        let resp = cli
+            .patch(&url)
+            .json(&json!({ "update": { "coordinates": { "latitude": 91, "longitude": 0 } } }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = cli
+            .patch(&url)
+            .json(&json!({ "update": { "tags": ["outdoor", "outdoor"] } }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let resp = cli
+            .patch(&url)
+            .json(&json!({
+                "update": {
+                    "location": "garage, north wall",
+                    "coordinates": { "latitude": 37.5, "longitude": -122.25 },
+                    "directionDeg": 270,
+                    "tags": ["outdoor", "driveway"],
+                },
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        let camera: serde_json::Value = cli.get(&url).send().await.unwrap().json().await.unwrap();
+        assert_eq!(camera["location"], "garage, north wall");
+        assert_eq!(camera["coordinates"]["longitude"], -122.25);
+        assert_eq!(camera["directionDeg"], 270);
+        assert_eq!(camera["tags"], json!(["outdoor", "driveway"]));
+
+        // Absent fields are unchanged; null clears.
+        let resp = cli
+            .patch(&url)
+            .json(&json!({ "update": { "directionDeg": null } }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        let camera: serde_json::Value = cli.get(&url).send().await.unwrap().json().await.unwrap();
+        assert!(camera.get("directionDeg").is_none());
+        assert_eq!(camera["location"], "garage, north wall");
+        let l = s.db.db.lock();
+        let config = &l.cameras_by_id()[&testutil::TEST_CAMERA_ID].config;
+        assert_eq!(config.tags, ["outdoor", "driveway"]);
+        assert_eq!(config.direction_deg, None);
+    }
+}
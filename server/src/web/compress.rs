@@ -0,0 +1,172 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Negotiated compression of JSON responses.
+//!
+//! Media responses are served by `http_serve::serve` and never pass through here; they're
+//! already compressed, and compressing them would break byte range requests.
+
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header::{self, HeaderMap};
+
+/// Bodies smaller than this aren't worth the CPU or the framing overhead.
+const MIN_LEN: usize = 1024;
+
+/// The compression level; JSON compresses well at fast levels.
+const LEVEL: u32 = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Parses a `qvalue` as in RFC 9110 section 12.4.2, returning thousandths.
+fn parse_q(params: &str) -> Option<u16> {
+    for p in params.split(';') {
+        let Some((k, v)) = p.split_once('=') else {
+            continue;
+        };
+        if !k.trim().eq_ignore_ascii_case("q") {
+            continue;
+        }
+        let v = v.trim();
+        let (int, frac) = v.split_once('.').unwrap_or((v, ""));
+        if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let thousandths = format!("{frac:0<3}").parse::<u16>().ok()?;
+        return match int {
+            "0" => Some(thousandths),
+            "1" if thousandths == 0 => Some(1000),
+            _ => None,
+        };
+    }
+    Some(1000)
+}
+
+/// Chooses an encoding for a body of `len` bytes given the request's `Accept-Encoding`
+/// headers, or `None` for the identity encoding.
+///
+/// The supported encoding with the highest weight wins, preferring gzip on ties.
+pub(super) fn negotiate(req_hdrs: &HeaderMap, len: usize) -> Option<Encoding> {
+    if len < MIN_LEN {
+        return None;
+    }
+    let mut best: Option<(u16, Encoding)> = None;
+    let mut wildcard = None;
+    let mut explicit = [None; 2];
+    for h in req_hdrs.get_all(header::ACCEPT_ENCODING) {
+        let Ok(h) = h.to_str() else {
+            continue;
+        };
+        for item in h.split(',') {
+            let (coding, params) = item.split_once(';').unwrap_or((item, ""));
+            let Some(q) = parse_q(params) else {
+                continue;
+            };
+            match coding.trim().to_ascii_lowercase().as_str() {
+                "gzip" | "x-gzip" => explicit[0] = Some(q),
+                "deflate" => explicit[1] = Some(q),
+                "*" => wildcard = Some(q),
+                _ => {}
+            }
+        }
+    }
+    for (i, e) in [Encoding::Gzip, Encoding::Deflate].into_iter().enumerate() {
+        let q = explicit[i].or(wildcard).unwrap_or(0);
+        if q > 0 && !best.is_some_and(|(b, _)| b >= q) {
+            best = Some((q, e));
+        }
+    }
+    best.map(|(_, e)| e)
+}
+
+/// Compresses `body` with the given encoding.
+pub(super) fn encode(encoding: Encoding, body: &[u8]) -> Vec<u8> {
+    let out = Vec::with_capacity(body.len() / 4);
+    let level = Compression::new(LEVEL);
+    match encoding {
+        Encoding::Gzip => {
+            let mut e = GzEncoder::new(out, level);
+            e.write_all(body).expect("writes to Vec can't fail");
+            e.finish()
+        }
+        Encoding::Deflate => {
+            // HTTP's `deflate` is the zlib format of RFC 1950, not raw deflate.
+            let mut e = ZlibEncoder::new(out, level);
+            e.write_all(body).expect("writes to Vec can't fail");
+            e.finish()
+        }
+    }
+    .expect("writes to Vec can't fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use std::io::Read;
+
+    fn accept(v: &'static str) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(v));
+        h
+    }
+
+    #[test]
+    fn negotiation() {
+        let big = MIN_LEN;
+        assert_eq!(negotiate(&HeaderMap::new(), big), None);
+        assert_eq!(negotiate(&accept("gzip"), MIN_LEN - 1), None);
+        assert_eq!(
+            negotiate(&accept("gzip, deflate, br"), big),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate(&accept("deflate"), big), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate(&accept("gzip;q=0.5, deflate;q=0.8"), big),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate(&accept("gzip;q=0, identity"), big), None);
+        assert_eq!(negotiate(&accept("*"), big), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate(&accept("*;q=0.1, gzip;q=0"), big),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate(&accept("br, zstd"), big), None);
+        assert_eq!(negotiate(&accept("gzip;q=2"), big), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let body = b"[{\"startTime90k\": 0}]".repeat(100);
+        let compressed = encode(Encoding::Gzip, &body);
+        assert!(compressed.len() < body.len());
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, body);
+
+        let compressed = encode(Encoding::Deflate, &body);
+        let mut out = Vec::new();
+        flate2::read::ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, body);
+    }
+}
@@ -0,0 +1,155 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Cross-Origin Resource Sharing, allowing web apps served from other origins to use the API.
+//! See [`CorsConfig`].
+
+use http::header::{self, HeaderValue};
+use http::{HeaderMap, Method, Response, StatusCode};
+
+use crate::body::Body;
+use crate::cmds::run::config::CorsConfig;
+
+/// Methods used by the API.
+const ALLOW_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE";
+
+/// Response headers, beyond the CORS-safelisted ones, which other origins' scripts may read.
+const EXPOSE_HEADERS: &str = "Content-Range, ETag, X-Request-Id";
+
+/// How long browsers may cache a preflight response, in seconds.
+const PREFLIGHT_MAX_AGE: &str = "86400";
+
+/// Returns the `Access-Control-Allow-Origin` value for a request with the given headers, or
+/// `None` if it has no allowed `Origin`.
+pub(super) fn allowed_origin(config: &CorsConfig, req_hdrs: &HeaderMap) -> Option<HeaderValue> {
+    let origin = req_hdrs.get(header::ORIGIN)?;
+    if config.allowed_origins.iter().any(|o| o == "*") {
+        return Some(HeaderValue::from_static("*"));
+    }
+    config
+        .allowed_origins
+        .iter()
+        .any(|o| o.as_bytes() == origin.as_bytes())
+        .then(|| origin.clone())
+}
+
+/// Returns a response to a preflight request, or `None` if this isn't one.
+///
+/// The caller should add the headers common to all responses via [`add_headers`].
+pub(super) fn preflight(method: &Method, req_hdrs: &HeaderMap) -> Option<Response<Body>> {
+    if *method != Method::OPTIONS || !req_hdrs.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD) {
+        return None;
+    }
+    let mut builder = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static(ALLOW_METHODS),
+        )
+        .header(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static(PREFLIGHT_MAX_AGE),
+        );
+    if let Some(h) = req_hdrs.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+        builder = builder.header(header::ACCESS_CONTROL_ALLOW_HEADERS, h.clone());
+    }
+    Some(
+        builder
+            .body(b""[..].into())
+            .expect("hardcoded head should be valid"),
+    )
+}
+
+/// Adds CORS headers to a response, given the result of [`allowed_origin`].
+pub(super) fn add_headers(
+    config: &CorsConfig,
+    origin: Option<HeaderValue>,
+    resp_hdrs: &mut HeaderMap,
+) {
+    // The response varies by origin unless every origin gets the same wildcard.
+    if !origin.as_ref().is_some_and(|o| o == "*") {
+        resp_hdrs.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    let Some(origin) = origin else {
+        return;
+    };
+    resp_hdrs.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    resp_hdrs.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static(EXPOSE_HEADERS),
+    );
+    if config.allow_credentials {
+        resp_hdrs.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|&o| o.to_owned()).collect(),
+            allow_credentials,
+        }
+    }
+
+    fn origin(o: &'static str) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert(header::ORIGIN, HeaderValue::from_static(o));
+        h
+    }
+
+    #[test]
+    fn origins() {
+        let c = config(&["https://a.example"], true);
+        assert_eq!(
+            allowed_origin(&c, &origin("https://a.example")).unwrap(),
+            "https://a.example"
+        );
+        assert_eq!(allowed_origin(&c, &origin("https://b.example")), None);
+        assert_eq!(allowed_origin(&c, &HeaderMap::new()), None);
+
+        let c = config(&["*"], false);
+        assert_eq!(
+            allowed_origin(&c, &origin("https://b.example")).unwrap(),
+            "*"
+        );
+        assert!(c.validate().is_ok());
+        assert!(config(&["*"], true).validate().is_err());
+    }
+
+    #[test]
+    fn headers() {
+        let c = config(&["https://a.example"], true);
+        let mut req = origin("https://a.example");
+        assert!(preflight(&Method::OPTIONS, &req).is_none());
+        req.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("POST"),
+        );
+        req.insert(
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            HeaderValue::from_static("content-type"),
+        );
+        assert!(preflight(&Method::GET, &req).is_none());
+        let mut resp = preflight(&Method::OPTIONS, &req).unwrap();
+        add_headers(&c, allowed_origin(&c, &req), resp.headers_mut());
+        let h = resp.headers();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(h[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://a.example");
+        assert_eq!(h[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(h[header::ACCESS_CONTROL_ALLOW_HEADERS], "content-type");
+        assert_eq!(h[header::VARY], "Origin");
+
+        // Disallowed origins get no CORS headers.
+        let mut h = HeaderMap::new();
+        add_headers(&c, allowed_origin(&c, &origin("https://b.example")), &mut h);
+        assert!(!h.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(h[header::VARY], "Origin");
+    }
+}
@@ -0,0 +1,460 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/export.zip` handling: long exports as a ZIP archive of one `.mp4` per hour or run.
+//!
+//! On request, the archive ends with a `manifest.json` of the `.mp4`s' BLAKE3 digests and
+//! other details for chain-of-custody records. If an export signing key is configured, a
+//! detached Ed25519 signature of the manifest follows as `manifest.json.sig`.
+
+use base::{bail, clock::Clocks as _, err, ErrorKind, ResultExt as _};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use db::recording::{self, rescale, TIME_UNITS_PER_SEC};
+use futures::Stream;
+use http::{header, HeaderValue, Method, Request, Response};
+use ring::signature::{Ed25519KeyPair, KeyPair as _};
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::cmp;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+use url::form_urlencoded;
+use uuid::Uuid;
+
+use crate::body::{Body, BoxedError, Chunk};
+use crate::mp4;
+use crate::zip;
+
+use super::{activity, view, Caller, ResponseResult, Service};
+
+const HOUR_90K: i64 = 3600 * TIME_UNITS_PER_SEC;
+
+/// The length of an Ed25519 signature.
+const SIGNATURE_LEN: u64 = 64;
+
+/// Parses a base64-encoded 32-byte Ed25519 private key seed, as generated by
+/// `openssl rand -base64 32`.
+pub fn parse_export_signing_key(encoded: &str) -> Result<Ed25519KeyPair, base::Error> {
+    let raw = STANDARD.decode(encoded.trim()).map_err(|e| {
+        err!(
+            InvalidArgument,
+            msg("export signing key isn't valid base64"),
+            source(e)
+        )
+    })?;
+    Ed25519KeyPair::from_seed_unchecked(&raw).map_err(|_| {
+        err!(
+            InvalidArgument,
+            msg("export signing key must be 32 bytes; got {}", raw.len())
+        )
+    })
+}
+
+/// How an export is divided into `.mp4` files.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Split {
+    /// One file per local clock hour.
+    Hour,
+
+    /// One file per run of recordings.
+    Run,
+}
+
+/// One `.mp4` within the archive.
+struct Part {
+    /// The start of the hour or the id of the run's first recording, depending on the split.
+    key: i64,
+    start: recording::Time,
+    end: recording::Time,
+
+    /// The recordings to include, each with the wall time range within it.
+    recordings: Vec<(db::ListRecordingsRow, Range<i32>)>,
+}
+
+/// Returns the start of the local clock hour containing `t`.
+fn hour_start(t: recording::Time) -> recording::Time {
+    let sec = t.0.div_euclid(TIME_UNITS_PER_SEC);
+    let tm = time::at(time::Timespec { sec, nsec: 0 });
+    recording::Time((sec - i64::from(tm.tm_min) * 60 - i64::from(tm.tm_sec)) * TIME_UNITS_PER_SEC)
+}
+
+/// Divides the recordings within `desired_time` into parts.
+fn list_parts(
+    db: &db::Database,
+    stream_id: i32,
+    desired_time: Range<recording::Time>,
+    split: Split,
+) -> Result<Vec<Part>, base::Error> {
+    let mut rows = Vec::new();
+    db.list_recordings_by_time(stream_id, desired_time.clone(), &mut |r| {
+        rows.push(r);
+        Ok(())
+    })?;
+    rows.sort_by_key(|r| r.id.0);
+    let mut parts: Vec<Part> = Vec::new();
+    for r in rows {
+        let end = cmp::min(
+            r.start + recording::Duration(i64::from(r.wall_duration_90k)),
+            desired_time.end,
+        );
+        let mut t = cmp::max(r.start, desired_time.start);
+        while t < end {
+            let (key, piece_end) = match split {
+                Split::Hour => {
+                    let h = hour_start(t);
+                    (h.0, cmp::min(end, h + recording::Duration(HOUR_90K)))
+                }
+                Split::Run => (i64::from(r.id.recording() - r.run_offset), end),
+            };
+            if parts.last().map(|p| p.key) != Some(key) {
+                parts.push(Part {
+                    key,
+                    start: t,
+                    end: piece_end,
+                    recordings: Vec::new(),
+                });
+            }
+            let wr = i32::try_from((t - r.start).0).unwrap()
+                ..i32::try_from((piece_end - r.start).0).unwrap();
+            let part = parts.last_mut().expect("part was just pushed");
+            part.end = piece_end;
+            part.recordings.push((r.clone(), wr));
+            t = piece_end;
+        }
+    }
+    Ok(parts)
+}
+
+/// The contents of `manifest.json`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    server_version: &'static str,
+    camera_uuid: Uuid,
+    camera_short_name: String,
+    stream: &'static str,
+    start_time_90k: i64,
+    end_time_90k: i64,
+    export_time_90k: i64,
+
+    /// The exporting user, if any.
+    user: Option<String>,
+
+    /// The base64-encoded Ed25519 public key which verifies `manifest.json.sig`, if signed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestFile {
+    name: String,
+    start_time_90k: i64,
+    end_time_90k: i64,
+    bytes: u64,
+
+    /// The hex-encoded BLAKE3 digest.
+    blake3: String,
+}
+
+impl Manifest {
+    /// Serializes the manifest with the given digests of its files.
+    ///
+    /// The length doesn't depend on the digests, so it can be computed in advance by passing
+    /// none.
+    fn to_json(&self, digests: &[blake3::Hash]) -> Vec<u8> {
+        let mut m = self.clone();
+        for (f, d) in m.files.iter_mut().zip(digests) {
+            f.blake3 = d.to_hex().to_string();
+        }
+        let mut v = serde_json::to_vec_pretty(&m).expect("manifest is serializable");
+        v.push(b'\n');
+        v
+    }
+}
+
+impl Service {
+    /// Serves a `.zip` of `.mp4`s covering the requested time range.
+    pub(super) fn stream_export_zip(
+        &self,
+        req: &Request<::hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+        stream_type: db::StreamType,
+    ) -> ResponseResult {
+        if !caller.permissions.download_recordings {
+            bail!(PermissionDenied, msg("download_recordings required"));
+        }
+        let (mut start, mut end, mut split, mut manifest) = (None, None, Split::Hour, false);
+        if let Some(q) = req.uri().query() {
+            for (key, value) in form_urlencoded::parse(q.as_bytes()) {
+                let (key, value) = (key.borrow(), value.borrow());
+                match key {
+                    "startTime90k" => {
+                        start =
+                            Some(i64::from_str(value).map_err(|_| {
+                                err!(InvalidArgument, msg("unparseable startTime90k"))
+                            })?)
+                    }
+                    "endTime90k" => {
+                        end =
+                            Some(i64::from_str(value).map_err(|_| {
+                                err!(InvalidArgument, msg("unparseable endTime90k"))
+                            })?)
+                    }
+                    "split" => {
+                        split = match value {
+                            "hour" => Split::Hour,
+                            "run" => Split::Run,
+                            _ => bail!(InvalidArgument, msg("unknown split {value:?}")),
+                        }
+                    }
+                    "manifest" => manifest = value == "true",
+                    _ => bail!(InvalidArgument, msg("parameter {key} not understood")),
+                }
+            }
+        }
+        let (Some(start), Some(end)) = (start, end) else {
+            bail!(
+                InvalidArgument,
+                msg("startTime90k and endTime90k are required")
+            );
+        };
+        if start >= end {
+            bail!(
+                InvalidArgument,
+                msg("startTime90k must be before endTime90k")
+            );
+        }
+        let desired_time = recording::Time(start)..recording::Time(end);
+
+        let dirs_by_stream_id = self.dirs_by_stream_id();
+        let (stream_id, camera_name) = {
+            let index = self.db.read_index();
+            let camera = index
+                .get_camera(uuid)
+                .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+            let stream_id = camera.streams[stream_type.index()]
+                .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{stream_type}")))?;
+            (stream_id, camera.short_name.clone())
+        };
+        if dirs_by_stream_id
+            .get(&stream_id)
+            .is_some_and(|d| !d.admit_playback())
+        {
+            bail!(
+                ResourceExhausted,
+                msg("sample file dir for {uuid}/{stream_type} is overloaded; try again later")
+            );
+        }
+        let parts = list_parts(&self.db, stream_id, desired_time.clone(), split)?;
+        let Some(first) = parts.first() else {
+            bail!(
+                NotFound,
+                msg("no recordings for {uuid}/{stream_type} in the requested time range")
+            );
+        };
+        let filename = |start: recording::Time, suffix: &str| {
+            let tm = time::at(time::Timespec {
+                sec: start.unix_seconds(),
+                nsec: 0,
+            });
+            let name = format!(
+                "{}-{}-{}.{}",
+                tm.strftime("%Y%m%d%H%M%S").unwrap(),
+                camera_name,
+                stream_type.as_str(),
+                suffix
+            );
+            (tm, name)
+        };
+        let (_, archive_name) = filename(first.start, "zip");
+
+        // Build each part as `view.mp4` would, with the same tag if required.
+        let user_id = caller.user.as_ref().map(|u| u.id);
+        let username = caller.user.as_ref().map(|u| u.name.clone());
+        let mut builders = Vec::with_capacity(parts.len());
+        {
+            let db = self.db.lock();
+            let tag = user_id.and_then(|id| {
+                let u = db.users_by_id().get(&id)?;
+                u.config.tag_downloads.then(|| u.username.clone())
+            });
+            for part in &parts {
+                let mut builder = mp4::FileBuilder::new(mp4::Type::Normal);
+                builder.set_index_cache(self.index_cache.clone());
+                builder.reserve(part.recordings.len());
+                if let Some(user) = tag.as_ref() {
+                    builder.include_timestamp_subtitle_track(true)?;
+                    builder.set_subtitle_labels(mp4::SubtitleLabels {
+                        camera_name: None,
+                        user: Some(user.clone()),
+                        signals: Vec::new(),
+                    })?;
+                }
+                for (i, (row, wr)) in part.recordings.iter().enumerate() {
+                    let mr = rescale(wr.start, row.wall_duration_90k, row.media_duration_90k)
+                        ..rescale(wr.end, row.wall_duration_90k, row.media_duration_90k);
+                    let followed = i + 1 < part.recordings.len();
+                    builder.append(&db, row, view::trim_trailing_zero(row, mr, followed), true)?;
+                }
+                builders.push(builder);
+            }
+        }
+        let mut entries = Vec::with_capacity(parts.len() + 2);
+        let mut files = Vec::with_capacity(parts.len());
+        for (part, builder) in parts.iter().zip(builders) {
+            let (modified, name) = filename(part.start, "mp4");
+            let mp4 = builder.build(self.db.clone(), dirs_by_stream_id.clone())?;
+            files.push(ManifestFile {
+                name: name.clone(),
+                start_time_90k: part.start.0,
+                end_time_90k: part.end.0,
+                bytes: http_serve::Entity::len(&mp4),
+                blake3: "0".repeat(blake3::OUT_LEN * 2),
+            });
+            entries.push(zip::Entry {
+                name,
+                modified,
+                contents: zip::Contents::Entity(mp4),
+            });
+        }
+        if manifest {
+            let now = self.db.clocks().realtime();
+            let n = files.len();
+            let m = Arc::new(Manifest {
+                server_version: crate::VERSION,
+                camera_uuid: uuid,
+                camera_short_name: camera_name.clone(),
+                stream: stream_type.as_str(),
+                start_time_90k: desired_time.start.0,
+                end_time_90k: desired_time.end.0,
+                export_time_90k: recording::Time::new(now).0,
+                user: username,
+                public_key: self
+                    .export_signing_key
+                    .as_ref()
+                    .map(|k| STANDARD.encode(k.public_key().as_ref())),
+                files,
+            });
+            let modified = time::at(now);
+            let len = m.to_json(&[]).len() as u64;
+            entries.push(zip::Entry {
+                name: "manifest.json".to_owned(),
+                modified,
+                contents: zip::Contents::Derived(zip::Derived::new(len, {
+                    let m = m.clone();
+                    move |digests| m.to_json(&digests[..n])
+                })),
+            });
+            if let Some(k) = self.export_signing_key.clone() {
+                entries.push(zip::Entry {
+                    name: "manifest.json.sig".to_owned(),
+                    modified,
+                    contents: zip::Contents::Derived(zip::Derived::new(
+                        SIGNATURE_LEN,
+                        move |digests| k.sign(&m.to_json(&digests[..n])).as_ref().to_vec(),
+                    )),
+                });
+            }
+        }
+        let archive = zip::Archive::new(entries)?;
+        self.charge_download(req, user_id, archive.len())?;
+
+        let resp = Response::builder()
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/zip"),
+            )
+            .header(header::CONTENT_LENGTH, archive.len())
+            .header(
+                header::CONTENT_DISPOSITION,
+                HeaderValue::try_from(format!("attachment; filename=\"{archive_name}\""))
+                    .err_kind(ErrorKind::InvalidArgument)?,
+            );
+        let body = if req.method() == Method::HEAD {
+            Body::empty()
+        } else {
+            let activity =
+                self.register_activity(activity::Kind::Download, &caller, uuid, stream_type);
+            let stream: Box<dyn Stream<Item = Result<Chunk, BoxedError>> + Send> =
+                Box::new(Arc::new(activity).track(archive.into_stream()));
+            Body::from(stream)
+        };
+        Ok(resp.body(body).expect("export headers should be valid"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::tests::Server;
+    use db::testutil;
+
+    #[test]
+    fn manifest_len_is_independent_of_digests() {
+        let m = Manifest {
+            server_version: "test",
+            camera_uuid: Uuid::nil(),
+            camera_short_name: "front \"door\"".to_owned(),
+            stream: "main",
+            start_time_90k: 0,
+            end_time_90k: 90_000,
+            export_time_90k: 180_000,
+            user: Some("slamb".to_owned()),
+            public_key: None,
+            files: vec![ManifestFile {
+                name: "19700101000000-front-main.mp4".to_owned(),
+                start_time_90k: 0,
+                end_time_90k: 90_000,
+                bytes: 4,
+                blake3: "0".repeat(blake3::OUT_LEN * 2),
+            }],
+        };
+        let digest = blake3::hash(b"data");
+        let json = m.to_json(&[digest]);
+        assert_eq!(json.len(), m.to_json(&[]).len());
+        let v: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(v["files"][0]["blake3"], digest.to_hex().as_str());
+        assert_eq!(v["cameraShortName"], "front \"door\"");
+    }
+
+    #[test]
+    fn parse_signing_key() {
+        parse_export_signing_key(&STANDARD.encode([1u8; 32])).unwrap();
+        let e = parse_export_signing_key(&STANDARD.encode([1u8; 16])).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArgument);
+        let e = parse_export_signing_key("not base64!").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn export_zip_errors() {
+        testutil::init();
+        let mut permissions = db::Permissions::new();
+        permissions.download_recordings = true;
+        let s = Server::new(Some(permissions));
+        let cli = reqwest::Client::new();
+        let url = format!(
+            "{}/api/cameras/{}/main/export.zip",
+            &s.base_url, s.db.test_camera_uuid
+        );
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = cli
+            .get(format!("{url}?startTime90k=0&endTime90k=90000&split=day"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = cli
+            .get(format!("{url}?startTime90k=0&endTime90k=90000"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,62 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/api/health` handling.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::{Request, StatusCode};
+
+use crate::json;
+
+use super::{serve_json, Caller, ResponseResult, Service};
+
+impl Service {
+    /// Reports whether any streamer has panicked recently.
+    ///
+    /// This is available without authentication, so that it can be used by load balancers and
+    /// monitoring systems; only callers with `read_camera_configs` see which streams panicked
+    /// and the progress of any directory scans.
+    pub(super) fn health(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        caller: Caller,
+    ) -> ResponseResult {
+        let now = SystemTime::now();
+        let panics = self.panics.recent(now);
+        let healthy = panics.is_empty();
+        let recent_panic_count = panics.len();
+        let recent_panics = caller.permissions.read_camera_configs.then(|| {
+            panics
+                .into_iter()
+                .map(|p| json::StreamerPanic {
+                    stream: p.stream,
+                    time_sec: p
+                        .when
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs() as i64)
+                        .unwrap_or(0),
+                    message: p.message,
+                })
+                .collect()
+        });
+        let dir_scans = caller
+            .permissions
+            .read_camera_configs
+            .then(|| json::DirScan::list(&self.db.lock()));
+        let mut resp = serve_json(
+            req,
+            &json::Health {
+                status: if healthy { "ok" } else { "degraded" },
+                recent_panic_count,
+                recent_panics,
+                dir_scans,
+            },
+        )?;
+        if !healthy {
+            *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        Ok(resp)
+    }
+}
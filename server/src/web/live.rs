@@ -4,25 +4,84 @@
 
 //! Live video websocket handling.
 
+use std::borrow::Borrow;
 use std::sync::Arc;
 
 use base::{bail, err, Error};
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
 use http::header;
 use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Duration, Instant};
 use tokio_tungstenite::tungstenite;
+use url::form_urlencoded;
 use uuid::Uuid;
 
 use crate::mp4;
 
-use super::{websocket::WebSocketStream, Caller, Service};
+use super::{activity, websocket::WebSocketStream, Caller, Service};
 
-/// Interval at which to send keepalives if there are no frames.
+/// Interval at which to send pings.
 ///
 /// Chrome appears to time out WebSockets after 60 seconds of inactivity.
 /// If the camera is disconnected or not sending frames, we'd like to keep
 /// the connection open so everything will recover when the camera comes back.
-const KEEPALIVE_AFTER_IDLE: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+/// The pings also let us detect dead clients, as below.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for any message, typically a pong, from the client before closing the
+/// connection. Browsers answer pings automatically, so silence means the client is gone, as is
+/// common when a mobile device sleeps or changes networks without closing the connection.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(75);
+
+/// How long a single send may take before the client is considered too slow to keep up.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The query parameters of a `live.m4s` request.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) struct LiveParams {
+    /// A previously received `X-Frame-Id` of the form `<openId>.<seq>`.
+    pub(super) resume: Option<(u32, u64)>,
+
+    /// If true, sends the stream's preview rendition; see [`crate::preview`].
+    pub(super) preview: bool,
+}
+
+/// Parses the query parameters of a `live.m4s` request.
+pub(super) fn parse_params<B>(req: &http::Request<B>) -> Result<LiveParams, Error> {
+    let mut params = LiveParams::default();
+    let Some(q) = req.uri().query() else {
+        return Ok(params);
+    };
+    for (key, value) in form_urlencoded::parse(q.as_bytes()) {
+        let (key, value): (&str, &str) = (key.borrow(), value.borrow());
+        match key {
+            "resume" => {
+                let parsed = value
+                    .split_once('.')
+                    .and_then(|(o, s)| Some((o.parse().ok()?, s.parse().ok()?)));
+                params.resume =
+                    Some(parsed.ok_or_else(|| err!(InvalidArgument, msg("unparseable resume")))?);
+            }
+            "preview" => params.preview = value == "true",
+            _ => {}
+        }
+    }
+    if params.preview && params.resume.is_some() {
+        bail!(InvalidArgument, msg("previews can't be resumed"));
+    }
+    Ok(params)
+}
+
+/// Sheds live viewers while over the memory budget, so that recording can continue.
+fn check_memory_budget() -> Result<(), Error> {
+    if base::mem::BUDGET.is_exceeded() {
+        bail!(
+            ResourceExhausted,
+            msg("server is over its memory budget; shedding live viewers")
+        );
+    }
+    Ok(())
+}
 
 impl Service {
     pub(super) async fn stream_live_m4s(
@@ -31,14 +90,17 @@ impl Service {
         caller: Result<Caller, Error>,
         uuid: Uuid,
         stream_type: db::StreamType,
+        resume: Option<(u32, u64)>,
     ) -> Result<(), Error> {
         let caller = caller?;
-        if !caller.permissions.view_video {
-            bail!(PermissionDenied, msg("view_video required"));
+        if !caller.permissions.view_live {
+            bail!(PermissionDenied, msg("view_live required"));
         }
+        check_memory_budget()?;
 
         let stream_id;
         let open_id;
+        let backlog;
         let mut sub_rx = {
             let mut db = self.db.lock();
             open_id = match db.open {
@@ -55,33 +117,87 @@ impl Service {
                 .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
             stream_id = camera.streams[stream_type.index()]
                 .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{stream_type}")))?;
+
+            // Frames buffered before a restart can't be told apart from new ones, so the
+            // open id must match. Fetching the backlog and subscribing under the same lock
+            // ensures no frame is skipped or repeated.
+            backlog = match resume {
+                Some((o, seq)) if o == open_id => db
+                    .recent_live_frames_after(stream_id, seq)
+                    .expect("stream_id refed by camera"),
+                _ => None,
+            };
             db.watch_live(stream_id).expect("stream_id refed by camera")
         };
+        let activity = self.register_activity(activity::Kind::Live, &caller, uuid, stream_type);
 
-        let mut keepalive = tokio::time::interval(KEEPALIVE_AFTER_IDLE);
-        keepalive.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut ping = tokio::time::interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut last_heard = Instant::now();
 
-        // On the first LiveFrame, send all the data from the previous key frame
-        // onward. Afterward, send a single (often non-key) frame at a time.
-        let mut start_at_key = true;
+        // When resuming, send the frames the client missed and continue where it left off.
+        // Otherwise, on the first LiveFrame, send all the data from the previous key frame
+        // onward, marking it as a reset if the client asked to resume. Afterward, send a
+        // single (often non-key) frame at a time.
+        let mut start_at_key = backlog.is_none();
+        let mut reset = resume.is_some() && backlog.is_none();
+        for l in backlog.unwrap_or_default() {
+            let send =
+                self.stream_live_m4s_chunk(open_id, stream_id, ws, &activity, l, false, false);
+            match tokio::time::timeout(SEND_TIMEOUT, send).await {
+                Ok(Ok(true)) => {}
+                Ok(Ok(false)) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => bail!(
+                    ResourceExhausted,
+                    msg("client took over {SEND_TIMEOUT:?} to accept a frame"),
+                ),
+            }
+        }
         loop {
             tokio::select! {
                 biased;
 
+                _ = activity.cancelled() => {
+                    bail!(Cancelled, msg("terminated by an administrator"));
+                }
+
+                msg = ws.next() => {
+                    match msg {
+                        // tungstenite answers pings itself on the next write.
+                        Some(Ok(tungstenite::Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => last_heard = Instant::now(),
+                        Some(Err(err)) => {
+                            tracing::debug!(%err, "websocket read failed");
+                            return Ok(());
+                        }
+                    }
+                }
+
                 next = sub_rx.recv() => {
                     match next {
                         Ok(l) => {
-                            keepalive.reset_after(KEEPALIVE_AFTER_IDLE);
-                            if !self.stream_live_m4s_chunk(
+                            check_memory_budget()?;
+                            let send = self.stream_live_m4s_chunk(
                                 open_id,
                                 stream_id,
                                 ws,
+                                &activity,
                                 l,
                                 start_at_key,
-                            ).await? {
-                                return Ok(());
+                                reset,
+                            );
+                            match tokio::time::timeout(SEND_TIMEOUT, send).await {
+                                Ok(Ok(true)) => {}
+                                Ok(Ok(false)) => return Ok(()),
+                                Ok(Err(e)) => return Err(e),
+                                Err(_) => bail!(
+                                    ResourceExhausted,
+                                    msg("client took over {SEND_TIMEOUT:?} to accept a frame"),
+                                ),
                             }
                             start_at_key = false;
+                            reset = false;
                         }
                         Err(RecvError::Closed) => {
                             bail!(Internal, msg("live stream closed unexpectedly"));
@@ -96,8 +212,123 @@ impl Service {
                     }
                 }
 
-                _ = keepalive.tick() => {
-                    if ws.send(tungstenite::Message::Ping(Vec::new())).await.is_err() {
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > CLIENT_TIMEOUT {
+                        tracing::info!("closing after {CLIENT_TIMEOUT:?} without hearing from client");
+                        return Ok(());
+                    }
+                    let send = ws.send(tungstenite::Message::Ping(Vec::new()));
+                    if !matches!(tokio::time::timeout(SEND_TIMEOUT, send).await, Ok(Ok(()))) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the preview rendition of a stream over `live.m4s?preview=true`: first the frames
+    /// since the latest key frame, then each frame as it arrives. Unlike the full stream, this
+    /// has no `X-Frame-Id` or recording headers and can't be resumed.
+    pub(super) async fn stream_live_preview(
+        self: Arc<Self>,
+        ws: &mut WebSocketStream,
+        caller: Result<Caller, Error>,
+        uuid: Uuid,
+        stream_type: db::StreamType,
+    ) -> Result<(), Error> {
+        let caller = caller?;
+        if !caller.permissions.view_live {
+            bail!(PermissionDenied, msg("view_live required"));
+        }
+        check_memory_budget()?;
+        let stream_id = {
+            let db = self.db.lock();
+            let camera = db
+                .get_camera(uuid)
+                .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+            camera.streams[stream_type.index()]
+                .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{stream_type}")))?
+        };
+        let preview = self.previews.get(&stream_id).ok_or_else(|| {
+            err!(
+                NotFound,
+                msg("stream {uuid}/{stream_type} has no preview; set preview in its config")
+            )
+        })?;
+        let (backlog, mut sub_rx) = preview.subscribe();
+        let activity = self.register_activity(activity::Kind::Live, &caller, uuid, stream_type);
+
+        let mut ping = tokio::time::interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut last_heard = Instant::now();
+        let mut mime_type = None;
+
+        // Send the backlog with one chunk per video sample entry. If it's empty, wait for a key
+        // frame.
+        let mut started = !backlog.is_empty();
+        for frames in backlog.chunk_by(|a, b| a.video_sample_entry_id == b.video_sample_entry_id) {
+            if !self
+                .send_preview_chunk(ws, &activity, &mut mime_type, frames)
+                .await?
+            {
+                return Ok(());
+            }
+        }
+        loop {
+            tokio::select! {
+                biased;
+
+                _ = activity.cancelled() => {
+                    bail!(Cancelled, msg("terminated by an administrator"));
+                }
+
+                msg = ws.next() => {
+                    match msg {
+                        Some(Ok(tungstenite::Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => last_heard = Instant::now(),
+                        Some(Err(err)) => {
+                            tracing::debug!(%err, "websocket read failed");
+                            return Ok(());
+                        }
+                    }
+                }
+
+                next = sub_rx.recv() => {
+                    match next {
+                        Ok(f) => {
+                            started |= f.is_key;
+                            if !started {
+                                continue;
+                            }
+                            check_memory_budget()?;
+                            let frames = std::slice::from_ref(&f);
+                            if !self
+                                .send_preview_chunk(ws, &activity, &mut mime_type, frames)
+                                .await?
+                            {
+                                return Ok(());
+                            }
+                        }
+                        Err(RecvError::Closed) => {
+                            bail!(Internal, msg("preview closed unexpectedly"));
+                        }
+                        Err(RecvError::Lagged(frames)) => {
+                            bail!(
+                                ResourceExhausted,
+                                msg("subscriber {frames} frames further behind than allowed; \
+                                     this typically indicates insufficient bandwidth"),
+                            )
+                        }
+                    }
+                }
+
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > CLIENT_TIMEOUT {
+                        tracing::info!("closing after {CLIENT_TIMEOUT:?} without hearing from client");
+                        return Ok(());
+                    }
+                    let send = ws.send(tungstenite::Message::Ping(Vec::new()));
+                    if !matches!(tokio::time::timeout(SEND_TIMEOUT, send).await, Ok(Ok(()))) {
                         return Ok(());
                     }
                 }
@@ -105,15 +336,59 @@ impl Service {
         }
     }
 
+    /// Sends `frames`, which share a video sample entry, as one chunk of a preview stream,
+    /// returning `Ok(false)` when the connection is lost. `mime_type` caches the `Content-Type`
+    /// of the latest video sample entry.
+    async fn send_preview_chunk(
+        &self,
+        ws: &mut WebSocketStream,
+        activity: &activity::Handle,
+        mime_type: &mut Option<(i32, String)>,
+        frames: &[crate::preview::Frame],
+    ) -> Result<bool, Error> {
+        let id = frames[0].video_sample_entry_id;
+        let mime_type = match mime_type {
+            Some((i, m)) if *i == id => m,
+            _ => {
+                let entry = self.db.lock().video_sample_entry(id).ok_or_else(|| {
+                    err!(Internal, msg("preview has unknown video sample entry {id}"))
+                })?;
+                let m = format!("video/mp4; codecs=\"{}\"", entry.rfc6381_codec);
+                &mut mime_type.insert((id, m)).1
+            }
+        };
+        let hdr = format!(
+            "Content-Type: {mime_type}\r\n\
+            X-Video-Sample-Entry-Id: {id}\r\n\
+            \r\n"
+        );
+        let mut v = hdr.into_bytes();
+        v.extend_from_slice(&crate::preview::media_segment(frames));
+        let _charge =
+            base::mem::BUDGET.charge(base::mem::Category::LiveSegments, v.capacity() as u64);
+        activity.add_bytes(v.len() as u64);
+        let send = ws.send(tungstenite::Message::Binary(v));
+        match tokio::time::timeout(SEND_TIMEOUT, send).await {
+            Ok(r) => Ok(r.is_ok()),
+            Err(_) => bail!(
+                ResourceExhausted,
+                msg("client took over {SEND_TIMEOUT:?} to accept a frame"),
+            ),
+        }
+    }
+
     /// Sends a single live segment chunk of a `live.m4s` stream, returning `Ok(false)` when
-    /// the connection is lost.
+    /// the connection is lost. `reset` marks the first chunk after a failed resume.
+    #[allow(clippy::too_many_arguments)]
     async fn stream_live_m4s_chunk(
         &self,
         open_id: u32,
         stream_id: i32,
         ws: &mut WebSocketStream,
+        activity: &activity::Handle,
         live: db::LiveFrame,
         start_at_key: bool,
+        reset: bool,
     ) -> Result<bool, Error> {
         let mut builder = mp4::FileBuilder::new(mp4::Type::MediaSegment);
         let mut row = None;
@@ -129,20 +404,23 @@ impl Service {
         }
         let row = row.ok_or_else(|| err!(Internal, msg("unable to find {live:?}")))?;
         use http_serve::Entity;
-        let mp4 = builder.build(self.db.clone(), self.dirs_by_stream_id.clone())?;
+        let mp4 = builder.build(self.db.clone(), self.dirs_by_stream_id())?;
         let mut hdrs = header::HeaderMap::new();
         mp4.add_headers(&mut hdrs);
         let mime_type = hdrs.get(header::CONTENT_TYPE).unwrap();
         let (prev_media_duration, prev_runs) = row.prev_media_duration_and_runs.unwrap();
-        let hdr = format!(
+        let mut hdr = format!(
             "Content-Type: {}\r\n\
+            X-Frame-Id: {}.{}\r\n\
             X-Recording-Start: {}\r\n\
             X-Recording-Id: {}.{}\r\n\
             X-Media-Time-Range: {}-{}\r\n\
             X-Prev-Media-Duration: {}\r\n\
             X-Runs: {}\r\n\
-            X-Video-Sample-Entry-Id: {}\r\n\r\n",
+            X-Video-Sample-Entry-Id: {}\r\n",
             mime_type.to_str().unwrap(),
+            open_id,
+            live.seq,
             row.start.0,
             open_id,
             live.recording,
@@ -152,8 +430,48 @@ impl Service {
             prev_runs + if row.run_offset == 0 { 1 } else { 0 },
             &row.video_sample_entry_id
         );
+        if reset {
+            hdr.push_str("X-Live-Reset: true\r\n");
+        }
+        hdr.push_str("\r\n");
         let mut v = hdr.into_bytes();
         mp4.append_into_vec(&mut v).await?;
+        let _charge =
+            base::mem::BUDGET.charge(base::mem::Category::LiveSegments, v.capacity() as u64);
+        activity.add_bytes(v.len() as u64);
         Ok(ws.send(tungstenite::Message::Binary(v)).await.is_ok())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(uri: &str) -> Result<LiveParams, Error> {
+        parse_params(&http::Request::get(uri).body(()).unwrap())
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(parse("/live.m4s").unwrap(), LiveParams::default());
+        assert_eq!(
+            parse("/live.m4s?resume=3.42").unwrap(),
+            LiveParams {
+                resume: Some((3, 42)),
+                preview: false,
+            }
+        );
+        assert!(parse("/live.m4s?preview=true").unwrap().preview);
+        assert!(!parse("/live.m4s?preview=false").unwrap().preview);
+        assert_eq!(
+            parse("/live.m4s?resume=3").unwrap_err().kind(),
+            base::ErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            parse("/live.m4s?resume=3.42&preview=true")
+                .unwrap_err()
+                .kind(),
+            base::ErrorKind::InvalidArgument
+        );
+    }
+}
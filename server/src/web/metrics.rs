@@ -0,0 +1,288 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/api/metrics` handling.
+//!
+//! Metrics are served in the [Prometheus text exposition
+//! format](https://prometheus.io/docs/instrumenting/exposition_formats/).
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use base::bail;
+use db::auth::TableStats;
+use db::dir::{LatencyHistogram, ReaderStats, LATENCY_BUCKETS_SEC};
+use db::DatabaseStats;
+use http::header::{self, HeaderValue};
+use http::{Request, Response};
+
+use super::{Caller, ResponseResult, Service};
+
+impl Service {
+    pub(super) fn metrics(
+        &self,
+        _req: &Request<hyper::body::Incoming>,
+        caller: Caller,
+    ) -> ResponseResult {
+        if !caller.permissions.read_camera_configs {
+            bail!(PermissionDenied, msg("read_camera_configs required"));
+        }
+        let mut dirs = Vec::new();
+        let auth_stats;
+        {
+            let l = self.db.lock();
+            auth_stats = l.auth_table_stats();
+            for d in l.sample_file_dirs_by_id().values() {
+                if let Ok(dir) = d.get() {
+                    dirs.push((d.path.clone(), dir.reader_stats()));
+                }
+            }
+        }
+        let mut out = String::new();
+        write_db_metrics(&mut out, &self.db.stats());
+        write_reader_metrics(&mut out, &dirs);
+        write_memory_metrics(&mut out, &base::mem::BUDGET);
+        write_auth_metrics(&mut out, &auth_stats);
+        Ok(Response::builder()
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; version=0.0.4"),
+            )
+            .body(out.into())
+            .expect("hardcoded head should be valid"))
+    }
+}
+
+/// Escapes a label value as described in the exposition format.
+fn escape_label(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes a histogram; `labels` may be empty.
+fn write_histogram(out: &mut String, name: &str, labels: &str, h: &LatencyHistogram) {
+    let (sep, braced) = if labels.is_empty() {
+        ("", String::new())
+    } else {
+        (",", format!("{{{labels}}}"))
+    };
+    for (bound, count) in LATENCY_BUCKETS_SEC.iter().zip(&h.buckets) {
+        let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {count}");
+    }
+    let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}", h.count);
+    let _ = writeln!(out, "{name}_sum{braced} {}", h.sum.as_secs_f64());
+    let _ = writeln!(out, "{name}_count{braced} {}", h.count);
+}
+
+fn write_db_metrics(out: &mut String, stats: &DatabaseStats) {
+    for (name, help, h) in [
+        (
+            "moonfire_db_lock_wait_seconds",
+            "Time spent waiting to acquire the database lock.",
+            &stats.lock_wait,
+        ),
+        (
+            "moonfire_db_lock_hold_seconds",
+            "Time the database lock was held.",
+            &stats.lock_hold,
+        ),
+        (
+            "moonfire_db_statement_duration_seconds",
+            "Time spent executing SQLite statements.",
+            &stats.statements,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        write_histogram(out, name, "", h);
+    }
+}
+
+fn write_reader_metrics(out: &mut String, dirs: &[(PathBuf, ReaderStats)]) {
+    out.push_str(
+        "# HELP moonfire_reader_queue_depth Sample file reads queued or in progress.\n\
+         # TYPE moonfire_reader_queue_depth gauge\n",
+    );
+    for (path, stats) in dirs {
+        let dir = escape_label(&path.to_string_lossy());
+        let _ = writeln!(
+            out,
+            "moonfire_reader_queue_depth{{dir=\"{dir}\"}} {}",
+            stats.queue_depth
+        );
+    }
+    out.push_str(
+        "# HELP moonfire_reader_shed_opens_total New playbacks rejected due to overload.\n\
+         # TYPE moonfire_reader_shed_opens_total counter\n",
+    );
+    for (path, stats) in dirs {
+        let dir = escape_label(&path.to_string_lossy());
+        let _ = writeln!(
+            out,
+            "moonfire_reader_shed_opens_total{{dir=\"{dir}\"}} {}",
+            stats.shed_opens
+        );
+    }
+    out.push_str(
+        "# HELP moonfire_reader_command_duration_seconds Latency of sample file reader \
+         commands, including time queued.\n\
+         # TYPE moonfire_reader_command_duration_seconds histogram\n",
+    );
+    for (path, stats) in dirs {
+        let dir = escape_label(&path.to_string_lossy());
+        for (command, h) in [
+            ("open", &stats.open),
+            ("read_chunk", &stats.read_chunk),
+            ("close", &stats.close),
+        ] {
+            write_histogram(
+                out,
+                "moonfire_reader_command_duration_seconds",
+                &format!("dir=\"{dir}\",command=\"{command}\""),
+                h,
+            );
+        }
+    }
+}
+
+fn write_memory_metrics(out: &mut String, budget: &base::mem::Budget) {
+    out.push_str(
+        "# HELP moonfire_memory_bytes Memory charged to the memory budget.\n\
+         # TYPE moonfire_memory_bytes gauge\n",
+    );
+    for c in base::mem::Category::ALL {
+        let _ = writeln!(
+            out,
+            "moonfire_memory_bytes{{category=\"{}\"}} {}",
+            c.as_str(),
+            budget.used(c)
+        );
+    }
+    if let Some(l) = budget.limit() {
+        let _ = writeln!(
+            out,
+            "# HELP moonfire_memory_budget_bytes The configured memory budget.\n\
+             # TYPE moonfire_memory_budget_bytes gauge\n\
+             moonfire_memory_budget_bytes {l}"
+        );
+    }
+}
+
+fn write_auth_metrics(out: &mut String, stats: &TableStats) {
+    let _ = writeln!(
+        out,
+        "# HELP moonfire_auth_users Rows in the user table.\n\
+         # TYPE moonfire_auth_users gauge\n\
+         moonfire_auth_users {}\n\
+         # HELP moonfire_auth_sessions Rows in the user_session table, as of the last purge.\n\
+         # TYPE moonfire_auth_sessions gauge\n\
+         moonfire_auth_sessions{{revoked=\"false\"}} {}\n\
+         moonfire_auth_sessions{{revoked=\"true\"}} {}\n\
+         # HELP moonfire_auth_sessions_purged_total Old sessions purged since startup.\n\
+         # TYPE moonfire_auth_sessions_purged_total counter\n\
+         moonfire_auth_sessions_purged_total {}",
+        stats.users,
+        stats.sessions - stats.revoked_sessions,
+        stats.revoked_sessions,
+        stats.purged_sessions,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn auth_metrics() {
+        let mut out = String::new();
+        write_auth_metrics(
+            &mut out,
+            &TableStats {
+                users: 2,
+                sessions: 10,
+                revoked_sessions: 3,
+                purged_sessions: 4,
+            },
+        );
+        assert!(out.contains("\nmoonfire_auth_users 2\n"), "{out}");
+        assert!(
+            out.contains("\nmoonfire_auth_sessions{revoked=\"false\"} 7\n"),
+            "{out}"
+        );
+        assert!(
+            out.contains("\nmoonfire_auth_sessions{revoked=\"true\"} 3\n"),
+            "{out}"
+        );
+        assert!(
+            out.ends_with("\nmoonfire_auth_sessions_purged_total 4\n"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn reader_metrics() {
+        let mut stats = ReaderStats {
+            queue_depth: 3,
+            shed_opens: 1,
+            ..Default::default()
+        };
+        stats.open.buckets = [0, 1, 2, 2, 2, 2, 2, 2];
+        stats.open.count = 3;
+        stats.open.sum = std::time::Duration::from_millis(20_500);
+        let mut out = String::new();
+        write_reader_metrics(&mut out, &[("/media/a".into(), stats)]);
+        assert!(out.contains("moonfire_reader_queue_depth{dir=\"/media/a\"} 3\n"));
+        assert!(out.contains("moonfire_reader_shed_opens_total{dir=\"/media/a\"} 1\n"));
+        assert!(out.contains(
+            "moonfire_reader_command_duration_seconds_bucket{dir=\"/media/a\",command=\"open\",\
+             le=\"0.004\"} 1\n"
+        ));
+        assert!(out.contains(
+            "moonfire_reader_command_duration_seconds_bucket{dir=\"/media/a\",command=\"open\",\
+             le=\"+Inf\"} 3\n"
+        ));
+        assert!(out.contains(
+            "moonfire_reader_command_duration_seconds_sum{dir=\"/media/a\",command=\"open\"} 20.5\n"
+        ));
+    }
+
+    #[test]
+    fn memory_metrics() {
+        let budget = base::mem::Budget::new();
+        budget.set_limit(Some(1 << 20));
+        let _c = budget.charge(base::mem::Category::LiveSegments, 42);
+        let mut out = String::new();
+        write_memory_metrics(&mut out, &budget);
+        assert!(out.contains("moonfire_memory_bytes{category=\"live_segments\"} 42\n"));
+        assert!(out.contains("moonfire_memory_bytes{category=\"reader_chunks\"} 0\n"));
+        assert!(out.contains("moonfire_memory_budget_bytes 1048576\n"));
+    }
+
+    #[test]
+    fn db_metrics() {
+        let mut stats = DatabaseStats::default();
+        stats.lock_hold.buckets = [0, 0, 1, 1, 1, 1, 1, 1];
+        stats.lock_hold.count = 1;
+        stats.lock_hold.sum = std::time::Duration::from_millis(10);
+        let mut out = String::new();
+        write_db_metrics(&mut out, &stats);
+        assert!(out.contains("# TYPE moonfire_db_lock_wait_seconds histogram\n"));
+        assert!(out.contains("moonfire_db_lock_hold_seconds_bucket{le=\"0.016\"} 1\n"));
+        assert!(out.contains("moonfire_db_lock_hold_seconds_sum 0.01\n"));
+        assert!(out.contains("moonfire_db_statement_duration_seconds_count 0\n"));
+    }
+}
@@ -3,20 +3,36 @@
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
 pub mod accept;
+mod activity;
+mod camera;
+mod compress;
+mod cors;
+mod export;
+mod health;
 mod live;
+mod metrics;
+mod move_stream;
+mod openapi;
 mod path;
+mod permission_templates;
+mod replay;
 mod session;
 mod signals;
+mod snapshot;
 mod static_file;
 mod users;
 mod view;
+mod webauthn;
 mod websocket;
 
 use self::accept::ConnData;
+pub use self::export::parse_export_signing_key;
 use self::path::Path;
 use crate::body::Body;
 use crate::json;
 use crate::mp4;
+#[cfg(feature = "bundled-ui")]
+pub(crate) use crate::web::static_file::ui_version;
 use crate::web::static_file::Ui;
 use base::err;
 use base::Error;
@@ -37,6 +53,20 @@ use tracing::Instrument;
 use url::form_urlencoded;
 use uuid::Uuid;
 
+/// The header carrying a request's correlation id, as described in [`Service::serve`].
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Returns the correlation id supplied by a trusted proxy, if it's reasonable to log and echo.
+fn forwarded_request_id(hdrs: &http::HeaderMap) -> Option<String> {
+    let id = hdrs.get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let ok = !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+    ok.then(|| id.to_owned())
+}
+
 fn plain_response<B: Into<Body>>(status: http::StatusCode, body: B) -> Response<Body> {
     Response::builder()
         .status(status)
@@ -53,6 +83,8 @@ fn from_base_error(err: &base::Error) -> Response<Body> {
         InvalidArgument => StatusCode::BAD_REQUEST,
         FailedPrecondition => StatusCode::PRECONDITION_FAILED,
         NotFound => StatusCode::NOT_FOUND,
+        ResourceExhausted => StatusCode::SERVICE_UNAVAILABLE,
+        DeadlineExceeded => StatusCode::REQUEST_TIMEOUT,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
     };
     plain_response(status_code, err.to_string())
@@ -62,6 +94,9 @@ fn from_base_error(err: &base::Error) -> Response<Body> {
 struct Caller {
     permissions: db::Permissions,
     user: Option<json::ToplevelUser>,
+
+    /// A `Set-Cookie` value which renews the caller's session cookie, with sliding renewal.
+    renew_cookie: Option<HeaderValue>,
 }
 
 type ResponseResult = Result<Response<Body>, base::Error>;
@@ -70,15 +105,89 @@ fn serve_json<R: http_serve::AsRequest, T: serde::ser::Serialize>(
     req: &R,
     out: &T,
 ) -> ResponseResult {
-    let (mut resp, writer) = http_serve::streaming_body(req).build();
-    resp.headers_mut().insert(
+    serve_json_body(req, out, false)
+}
+
+/// Like [`serve_json`], but with a strong `ETag` computed from the body, returning
+/// `304 Not Modified` when it matches the request's `If-None-Match`.
+///
+/// This is for large listings which polling clients fetch repeatedly; it saves transferring the
+/// body but not producing it.
+fn serve_json_with_etag<R: http_serve::AsRequest, T: serde::ser::Serialize>(
+    req: &R,
+    out: &T,
+) -> ResponseResult {
+    serve_json_body(req, out, true)
+}
+
+fn serve_json_body<R: http_serve::AsRequest, T: serde::ser::Serialize>(
+    req: &R,
+    out: &T,
+    with_etag: bool,
+) -> ResponseResult {
+    let body = serde_json::to_vec(out).err_kind(ErrorKind::Internal)?;
+    let encoding = compress::negotiate(req.headers(), body.len());
+    let mut builder =
+        Response::builder().header(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    if with_etag {
+        let etag = etag(&body, encoding);
+        if if_none_match(req.headers(), &etag) {
+            return Ok(builder
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(b""[..].into())
+                .expect("hardcoded head should be valid"));
+        }
+        builder = builder.header(header::ETAG, etag);
+    }
+    builder = builder.header(
         header::CONTENT_TYPE,
         HeaderValue::from_static("application/json"),
     );
-    if let Some(mut w) = writer {
-        serde_json::to_writer(&mut w, out).err_kind(ErrorKind::Internal)?;
+    let body = match encoding {
+        None => body,
+        Some(e) => {
+            builder = builder.header(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(e.as_str()),
+            );
+            compress::encode(e, &body)
+        }
+    };
+    builder = builder.header(header::CONTENT_LENGTH, body.len());
+    Ok(if req.method() == http::Method::HEAD {
+        builder.body(b""[..].into())
+    } else {
+        builder.body(body.into())
     }
-    Ok(resp)
+    .expect("hardcoded head should be valid"))
+}
+
+/// Returns a strong entity tag for `body` with the given content coding: a quoted, truncated
+/// SHA-256 hash of the uncompressed body, suffixed with the coding as each is a distinct
+/// representation.
+fn etag(body: &[u8], encoding: Option<compress::Encoding>) -> HeaderValue {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+    let digest = ring::digest::digest(&ring::digest::SHA256, body);
+    let hash = URL_SAFE_NO_PAD.encode(&digest.as_ref()[..18]);
+    let tag = match encoding {
+        None => format!("\"{hash}\""),
+        Some(e) => format!("\"{hash}-{}\"", e.as_str()),
+    };
+    HeaderValue::try_from(tag).expect("base64 is a valid header value")
+}
+
+/// Returns true iff the request's `If-None-Match` header(s) match `etag`.
+///
+/// As specified in RFC 9110 section 13.1.2, this uses weak comparison, so a `W/` prefix is
+/// ignored.
+fn if_none_match(req_hdrs: &http::HeaderMap, etag: &HeaderValue) -> bool {
+    req_hdrs.get_all(header::IF_NONE_MATCH).iter().any(|h| {
+        h.as_bytes().split(|&b| b == b',').any(|t| {
+            let t = t.trim_ascii();
+            t == b"*" || t.strip_prefix(b"W/").unwrap_or(t) == etag.as_bytes()
+        })
+    })
 }
 
 fn csrf_matches(csrf: &str, session: auth::SessionHash) -> bool {
@@ -111,6 +220,7 @@ fn extract_sid(req_hdrs: &http::HeaderMap) -> Option<auth::RawSessionId> {
 /// that borrows from the bytes.
 async fn into_json_body(
     req: Request<hyper::body::Incoming>,
+    max_bytes: usize,
 ) -> Result<(http::request::Parts, Bytes), base::Error> {
     let correct_mime_type = match req.headers().get(header::CONTENT_TYPE) {
         Some(t) if t == "application/json" => true,
@@ -123,10 +233,30 @@ async fn into_json_body(
             msg("expected application/json request body")
         );
     }
+    let too_large = || {
+        err!(
+            InvalidArgument,
+            msg("request body exceeds {max_bytes} bytes")
+        )
+    };
+    if req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<u64>().ok())
+        .is_some_and(|l| l > max_bytes as u64)
+    {
+        return Err(too_large());
+    }
     let (parts, b) = req.into_parts();
-    let b = http_body_util::BodyExt::collect(b)
+    let b = http_body_util::BodyExt::collect(http_body_util::Limited::new(b, max_bytes))
         .await
-        .map_err(|e| err!(Unavailable, msg("unable to read request body"), source(e)))?
+        .map_err(|e| {
+            if e.is::<http_body_util::LengthLimitError>() {
+                return too_large();
+            }
+            err!(Unavailable, msg("unable to read request body"), source(e))
+        })?
         .to_bytes();
     Ok((parts, b))
 }
@@ -149,20 +279,67 @@ fn require_csrf_if_session(caller: &Caller, csrf: Option<&str>) -> Result<(), ba
 pub struct Config<'a> {
     pub db: Arc<db::Database>,
     pub ui_dir: Option<&'a crate::cmds::run::config::UiDir>,
+    pub ui_spa_fallback: bool,
     pub trust_forward_hdrs: bool,
-    pub time_zone_name: String,
     pub allow_unauthenticated_permissions: Option<db::Permissions>,
     pub privileged_unix_uid: Option<nix::unistd::Uid>,
+    pub stream_mover: Option<Arc<crate::cmds::run::mover::StreamMover>>,
+    pub panics: Arc<crate::streamer::PanicLog>,
+    pub session: crate::cmds::run::config::SessionConfig,
+    pub cors: Option<crate::cmds::run::config::CorsConfig>,
+    pub cache: crate::cmds::run::config::CacheConfig,
+
+    /// The parts of the API to serve, or `None` for all of them.
+    pub areas: Option<Vec<crate::cmds::run::config::ApiArea>>,
+
+    pub requests: crate::cmds::run::config::RequestsConfig,
+
+    /// Where to send notifications of events reported via `POST /api/signals`.
+    pub notify: crate::cmds::run::notify::Sender,
+
+    /// Where to archive snapshots on signal rising edges, if anywhere.
+    pub snapshots: Option<Arc<crate::cmds::run::snapshots::Archive>>,
+
+    /// The key which signs export manifests, if any.
+    pub export_signing_key: Option<Arc<ring::signature::Ed25519KeyPair>>,
+
+    /// The streams' preview renditions, for `live.m4s?preview=true`.
+    pub previews: Arc<crate::preview::Previews>,
 }
 
 pub struct Service {
     db: Arc<db::Database>,
     ui: Ui,
-    dirs_by_stream_id: Arc<FastHashMap<i32, Arc<SampleFileDir>>>,
-    time_zone_name: String,
+    ui_spa_fallback: bool,
+    index_cache: Arc<mp4::IndexCache>,
     allow_unauthenticated_permissions: Option<db::Permissions>,
     trust_forward_hdrs: bool,
     privileged_unix_uid: Option<nix::unistd::Uid>,
+    stream_mover: Option<Arc<crate::cmds::run::mover::StreamMover>>,
+    panics: Arc<crate::streamer::PanicLog>,
+    session: crate::cmds::run::config::SessionConfig,
+    cors: Option<crate::cmds::run::config::CorsConfig>,
+    areas: Option<Vec<crate::cmds::run::config::ApiArea>>,
+    max_body_bytes: usize,
+
+    /// How long a request may take to produce response headers, if limited.
+    request_timeout: Option<std::time::Duration>,
+
+    /// The `Cache-Control` values for [`CacheControl::PrivateStatic`] and
+    /// [`CacheControl::PrivateDynamic`] respectively.
+    static_cache_control: HeaderValue,
+    dynamic_cache_control: HeaderValue,
+    notify: crate::cmds::run::notify::Sender,
+    snapshots: Option<Arc<crate::cmds::run::snapshots::Archive>>,
+    export_signing_key: Option<Arc<ring::signature::Ed25519KeyPair>>,
+    decoder: crate::decoder::Decoder,
+    previews: Arc<crate::preview::Previews>,
+    activity_registry: activity::Registry,
+    webauthn_challenges: webauthn::Challenges,
+
+    /// The result of the last [`Service::dirs_by_stream_id`] call, along with the
+    /// [`db::Database::stream_dirs_generation`] it reflects.
+    dirs_by_stream_id: std::sync::Mutex<Option<(u64, Arc<FastHashMap<i32, Arc<SampleFileDir>>>)>>,
 }
 
 /// Useful HTTP `Cache-Control` values to set on successful (HTTP 200) API responses.
@@ -174,39 +351,103 @@ enum CacheControl {
     /// E.g., a fixed segment of video. The underlying video logically never changes; there may
     /// rarely be some software change to the actual bytes (which would result in a new etag) so
     /// (unlike the content-hashed static content) it's not entirely immutable.
+    ///
+    /// The bind's `CacheConfig` may make these `public`.
     PrivateStatic,
 
     None,
 }
 
+/// Returns the `Cache-Control` values for `CacheControl::PrivateStatic` and
+/// `CacheControl::PrivateDynamic` under the given configuration.
+fn cache_control_values(
+    config: &crate::cmds::run::config::CacheConfig,
+) -> (HeaderValue, HeaderValue) {
+    let mut static_ = format!(
+        "{}, max-age={}",
+        if config.public_media {
+            "public"
+        } else {
+            "private"
+        },
+        config.media_max_age_sec
+    );
+    if let Some(s) = config.media_stale_while_revalidate_sec {
+        static_.push_str(&format!(", stale-while-revalidate={s}"));
+    }
+    let dynamic = match config.dynamic_stale_while_revalidate_sec {
+        None => "private, no-cache".to_owned(),
+        Some(s) => format!("private, max-age=0, stale-while-revalidate={s}"),
+    };
+    (
+        HeaderValue::try_from(static_).expect("Cache-Control value is valid"),
+        HeaderValue::try_from(dynamic).expect("Cache-Control value is valid"),
+    )
+}
+
 impl Service {
     pub fn new(config: Config) -> Result<Self, Error> {
         let ui_dir = config.ui_dir.map(Ui::from).unwrap_or(Ui::None);
-        let dirs_by_stream_id = {
-            let l = config.db.lock();
-            let mut d =
-                FastHashMap::with_capacity_and_hasher(l.streams_by_id().len(), Default::default());
-            for (&id, s) in l.streams_by_id().iter() {
-                let dir_id = match s.sample_file_dir_id {
-                    Some(d) => d,
-                    None => continue,
-                };
-                d.insert(id, l.sample_file_dirs_by_id().get(&dir_id).unwrap().get()?);
-            }
-            Arc::new(d)
-        };
-
+        let (static_cache_control, dynamic_cache_control) = cache_control_values(&config.cache);
         Ok(Service {
             db: config.db,
-            dirs_by_stream_id,
+            index_cache: Arc::new(mp4::IndexCache::new(mp4::DEFAULT_INDEX_CACHE_BYTES)),
             ui: ui_dir,
+            ui_spa_fallback: config.ui_spa_fallback,
             allow_unauthenticated_permissions: config.allow_unauthenticated_permissions,
             trust_forward_hdrs: config.trust_forward_hdrs,
-            time_zone_name: config.time_zone_name,
             privileged_unix_uid: config.privileged_unix_uid,
+            stream_mover: config.stream_mover,
+            panics: config.panics,
+            session: config.session,
+            cors: config.cors,
+            areas: config.areas,
+            max_body_bytes: config.requests.max_body_bytes as usize,
+            request_timeout: (config.requests.timeout_sec > 0)
+                .then(|| std::time::Duration::from_secs(u64::from(config.requests.timeout_sec))),
+            static_cache_control,
+            dynamic_cache_control,
+            notify: config.notify,
+            snapshots: config.snapshots,
+            export_signing_key: config.export_signing_key,
+            decoder: crate::decoder::Decoder::new(crate::decoder::DEFAULT_WORKERS),
+            previews: config.previews,
+            activity_registry: activity::Registry::default(),
+            webauthn_challenges: webauthn::Challenges::default(),
+            dirs_by_stream_id: std::sync::Mutex::new(None),
         })
     }
 
+    /// Returns the current sample file directory of each stream.
+    ///
+    /// Streams may be moved between directories while running, so this is rebuilt whenever
+    /// [`db::Database::stream_dirs_generation`] changes.
+    fn dirs_by_stream_id(&self) -> Arc<FastHashMap<i32, Arc<SampleFileDir>>> {
+        let generation = self.db.stream_dirs_generation();
+        let mut cached = self.dirs_by_stream_id.lock().unwrap();
+        if let Some((g, d)) = cached.as_ref() {
+            if *g == generation {
+                return d.clone();
+            }
+        }
+        let l = self.db.lock();
+        let mut d =
+            FastHashMap::with_capacity_and_hasher(l.streams_by_id().len(), Default::default());
+        for (&id, s) in l.streams_by_id().iter() {
+            let Some(dir) = s
+                .sample_file_dir_id
+                .and_then(|d| l.sample_file_dirs_by_id().get(&d))
+                .and_then(|d| d.get().ok())
+            else {
+                continue;
+            };
+            d.insert(id, dir);
+        }
+        let d = Arc::new(d);
+        *cached = Some((generation, d.clone()));
+        d
+    }
+
     /// Serves an HTTP request.
     ///
     /// The `Err` return path will cause the `serve` wrapper to log the error,
@@ -217,11 +458,30 @@ impl Service {
         authreq: auth::Request,
         conn_data: ConnData,
     ) -> ResponseResult {
+        if self.cors.is_some() {
+            if let Some(r) = cors::preflight(req.method(), req.headers()) {
+                return Ok(r);
+            }
+        }
         let path = Path::decode(req.uri().path());
         tracing::trace!(?path, "path");
+        if let (Some(areas), Some(area)) = (self.areas.as_ref(), path.area()) {
+            if !areas.contains(&area) {
+                return Err(err!(NotFound, msg("path not served on this bind")));
+            }
+        }
         let always_allow_unauthenticated = matches!(
             path,
-            Path::NotFound | Path::Request | Path::Login | Path::Logout | Path::Static
+            Path::NotFound
+                | Path::Request
+                | Path::Login
+                | Path::Logout
+                | Path::WebAuthnLoginOptions
+                | Path::WebAuthnLogin
+                | Path::Static
+                | Path::Health
+                | Path::OpenApi
+                | Path::UiVersion
         );
         let caller = self.authenticate(&req, &authreq, &conn_data, always_allow_unauthenticated);
         if let Some(username) = caller
@@ -237,12 +497,19 @@ impl Service {
         // errors are returned as text messages over the protocol, rather than
         // HTTP-level errors.
         if let Path::StreamLiveMp4Segments(uuid, type_) = path {
+            let params = live::parse_params(&req)?;
+            if params.preview {
+                return websocket::upgrade(req, move |ws| {
+                    Box::pin(self.stream_live_preview(ws, caller, uuid, type_))
+                });
+            }
             return websocket::upgrade(req, move |ws| {
-                Box::pin(self.stream_live_m4s(ws, caller, uuid, type_))
+                Box::pin(self.stream_live_m4s(ws, caller, uuid, type_, params.resume))
             });
         }
 
-        let caller = caller?;
+        let mut caller = caller?;
+        let renew_cookie = caller.renew_cookie.take();
         let (cache, mut response) = match path {
             Path::InitSegment(sha1, debug) => (
                 CacheControl::PrivateStatic,
@@ -253,18 +520,57 @@ impl Service {
                 CacheControl::PrivateDynamic,
                 self.request(&req, &authreq, caller)?,
             ),
-            Path::Camera(uuid) => (CacheControl::PrivateDynamic, self.camera(&req, uuid)?),
+            Path::Camera(uuid) => (
+                CacheControl::PrivateDynamic,
+                self.camera(req, caller, uuid).await?,
+            ),
             Path::StreamRecordings(uuid, type_) => (
                 CacheControl::PrivateDynamic,
                 self.stream_recordings(&req, uuid, type_)?,
             ),
-            Path::StreamViewMp4(uuid, type_, debug) => (
-                CacheControl::PrivateStatic,
-                self.stream_view_mp4(&req, caller, uuid, type_, mp4::Type::Normal, debug)?,
+            Path::StreamRuns(uuid, type_) => (
+                CacheControl::PrivateDynamic,
+                self.stream_runs(&req, uuid, type_)?,
             ),
-            Path::StreamViewMp4Segment(uuid, type_, debug) => (
-                CacheControl::PrivateStatic,
-                self.stream_view_mp4(&req, caller, uuid, type_, mp4::Type::MediaSegment, debug)?,
+            Path::Events => (CacheControl::PrivateDynamic, self.events(&req)?),
+            Path::StreamRunRtspSession(uuid, type_, id) => (
+                CacheControl::PrivateDynamic,
+                self.stream_run_rtsp_session(&req, caller, uuid, type_, id)?,
+            ),
+            Path::StreamViewMp4(uuid, type_, debug) => self.stream_view(
+                &req,
+                caller,
+                uuid,
+                type_,
+                view::Format::Mp4(mp4::Type::Normal),
+                debug,
+            )?,
+            Path::StreamViewMp4Segment(uuid, type_, debug) => self.stream_view(
+                &req,
+                caller,
+                uuid,
+                type_,
+                view::Format::Mp4(mp4::Type::MediaSegment),
+                debug,
+            )?,
+            Path::StreamViewMkv(uuid, type_, debug) => {
+                self.stream_view(&req, caller, uuid, type_, view::Format::Mkv, debug)?
+            }
+            Path::StreamExportZip(uuid, type_) => (
+                CacheControl::PrivateDynamic,
+                self.stream_export_zip(&req, caller, uuid, type_)?,
+            ),
+            Path::StreamReplayMp4(uuid, type_) => (
+                CacheControl::PrivateDynamic,
+                self.stream_replay_mp4(&req, caller, uuid, type_)?,
+            ),
+            Path::StreamSnapshotJpeg(uuid, type_) => (
+                CacheControl::PrivateDynamic,
+                self.stream_snapshot_jpeg(caller, uuid, type_).await?,
+            ),
+            Path::StreamMove(uuid, type_) => (
+                CacheControl::PrivateDynamic,
+                self.move_stream(req, caller, uuid, type_).await?,
             ),
             Path::StreamLiveMp4Segments(..) => {
                 unreachable!("StreamLiveMp4Segments should have already been handled")
@@ -278,32 +584,89 @@ impl Service {
                 CacheControl::PrivateDynamic,
                 self.logout(req, authreq).await?,
             ),
+            Path::WebAuthnRegisterOptions => (
+                CacheControl::PrivateDynamic,
+                self.webauthn_register_options(req, caller).await?,
+            ),
+            Path::WebAuthnRegister => (
+                CacheControl::PrivateDynamic,
+                self.webauthn_register(req, caller).await?,
+            ),
+            Path::WebAuthnLoginOptions => (
+                CacheControl::PrivateDynamic,
+                self.webauthn_login_options(req).await?,
+            ),
+            Path::WebAuthnLogin => (
+                CacheControl::PrivateDynamic,
+                self.webauthn_login(req, authreq).await?,
+            ),
             Path::Signals => (
                 CacheControl::PrivateDynamic,
                 self.signals(req, caller).await?,
             ),
+            Path::Signal(uuid) => (
+                CacheControl::PrivateDynamic,
+                self.signal(req, caller, uuid).await?,
+            ),
+            Path::SignalSnapshot(id) => (
+                CacheControl::PrivateDynamic,
+                self.signal_snapshot(&req, caller, id)?,
+            ),
+            Path::SignalType(uuid) => (
+                CacheControl::PrivateDynamic,
+                self.signal_type(req, caller, uuid).await?,
+            ),
+            Path::Metrics => (CacheControl::PrivateDynamic, self.metrics(&req, caller)?),
+            Path::Health => (CacheControl::PrivateDynamic, self.health(&req, caller)?),
+            Path::OpenApi => (CacheControl::PrivateDynamic, self.openapi(&req)?),
             Path::Static => (CacheControl::None, self.static_file(req).await?),
+            Path::UiVersion => (CacheControl::PrivateDynamic, self.ui_version(&req)?),
             Path::Users => (CacheControl::PrivateDynamic, self.users(req, caller).await?),
             Path::User(id) => (
                 CacheControl::PrivateDynamic,
                 self.user(req, caller, id).await?,
             ),
+            Path::UserWebAuthnCredentials(id) => (
+                CacheControl::PrivateDynamic,
+                self.user_webauthn_credentials(req, caller, id).await?,
+            ),
+            Path::UserWebAuthnCredential(user_id, id) => (
+                CacheControl::PrivateDynamic,
+                self.user_webauthn_credential(req, caller, user_id, id)
+                    .await?,
+            ),
+            Path::PermissionTemplates => (
+                CacheControl::PrivateDynamic,
+                self.permission_templates(req, caller).await?,
+            ),
+            Path::PermissionTemplate(id) => (
+                CacheControl::PrivateDynamic,
+                self.permission_template(req, caller, id).await?,
+            ),
+            Path::Activities => (CacheControl::PrivateDynamic, self.activities(&req, caller)?),
+            Path::Activity(id) => (
+                CacheControl::PrivateDynamic,
+                self.activity(req, caller, id).await?,
+            ),
         };
         match cache {
             CacheControl::PrivateStatic => {
-                response.headers_mut().insert(
-                    header::CACHE_CONTROL,
-                    HeaderValue::from_static("private, max-age=3600"),
-                );
+                response
+                    .headers_mut()
+                    .insert(header::CACHE_CONTROL, self.static_cache_control.clone());
             }
             CacheControl::PrivateDynamic => {
-                response.headers_mut().insert(
-                    header::CACHE_CONTROL,
-                    HeaderValue::from_static("private, no-cache"),
-                );
+                response
+                    .headers_mut()
+                    .insert(header::CACHE_CONTROL, self.dynamic_cache_control.clone());
             }
             CacheControl::None => {}
         }
+        if let Some(c) = renew_cookie {
+            if !response.headers().contains_key(header::SET_COOKIE) {
+                response.headers_mut().insert(header::SET_COOKIE, c);
+            }
+        }
         Ok(response)
     }
 
@@ -314,12 +677,32 @@ impl Service {
     /// method always returns `Ok`. It delegates to a `serve_inner` which is
     /// allowed to generate `Err` results with the `?` operator, but returns
     /// them to hyper as `Ok` results.
+    ///
+    /// Each request is assigned a correlation id, which is logged with everything done on its
+    /// behalf (including database lock waits and sample file reads) and returned in the
+    /// `X-Request-Id` response header. With `trust_forward_hdrs`, a proxy-supplied `X-Request-Id`
+    /// is used instead, so the id can be followed across both.
     pub async fn serve(
         self: Arc<Self>,
-        req: Request<::hyper::body::Incoming>,
+        mut req: Request<::hyper::body::Incoming>,
         conn_data: ConnData,
     ) -> Result<Response<Body>, std::convert::Infallible> {
-        let request_id = ulid::Ulid::new();
+        // HTTP/2 requests carry the host in the `:authority` pseudo-header, which hyper exposes
+        // via the URI rather than as a `Host` header. Handlers expect the latter.
+        if !req.headers().contains_key(header::HOST) {
+            if let Some(a) = req
+                .uri()
+                .authority()
+                .and_then(|a| HeaderValue::from_str(a.as_str()).ok())
+            {
+                req.headers_mut().insert(header::HOST, a);
+            }
+        }
+        let request_id = self
+            .trust_forward_hdrs
+            .then(|| forwarded_request_id(req.headers()))
+            .flatten()
+            .unwrap_or_else(|| ulid::Ulid::new().to_string());
         let authreq = auth::Request {
             when_sec: Some(self.db.clocks().realtime().sec),
             addr: if self.trust_forward_hdrs {
@@ -349,14 +732,39 @@ impl Service {
             enduser.id = tracing::field::Empty,
         );
         tracing::debug!(parent: &span, "received request headers");
-        let response = self
+        let cors_origin = self
+            .cors
+            .as_ref()
+            .and_then(|c| cors::allowed_origin(c, req.headers()));
+        // Stream moves are exempt from the timeout: they may legitimately wait on the streamer,
+        // and run to completion regardless.
+        let timeout = self
+            .request_timeout
+            .filter(|_| !matches!(Path::decode(req.uri().path()), Path::StreamMove(..)));
+        let inner = self
+            .clone()
             .serve_inner(req, authreq, conn_data)
-            .instrument(span.clone())
-            .await;
-        let (response, error) = match response {
+            .instrument(span.clone());
+        let response = match timeout {
+            None => inner.await,
+            Some(t) => tokio::time::timeout(t, inner).await.unwrap_or_else(|_| {
+                Err(err!(
+                    DeadlineExceeded,
+                    msg("request took longer than {t:?}")
+                ))
+            }),
+        };
+        let (mut response, error) = match response {
             Ok(r) => (r, None),
             Err(e) => (from_base_error(&e), Some(e)),
         };
+        response.headers_mut().insert(
+            REQUEST_ID_HEADER,
+            HeaderValue::try_from(&request_id).expect("request id should be a valid header"),
+        );
+        if let Some(c) = &self.cors {
+            cors::add_headers(c, cors_origin, response.headers_mut());
+        }
         span.record("http.status_code", response.status().as_u16());
         let latency = std::time::Instant::now().duration_since(start);
         if response.status().is_server_error() {
@@ -403,10 +811,10 @@ impl Service {
         }
 
         let db = self.db.lock();
-        serve_json(
+        serve_json_with_etag(
             req,
             &json::TopLevel {
-                time_zone_name: &self.time_zone_name,
+                time_zone_name: db.time_zone_name(),
                 server_version: env!("CARGO_PKG_VERSION"),
                 cameras: (&db, days, camera_configs),
                 user: caller.user,
@@ -417,17 +825,6 @@ impl Service {
         )
     }
 
-    fn camera(&self, req: &Request<::hyper::body::Incoming>, uuid: Uuid) -> ResponseResult {
-        let db = self.db.lock();
-        let camera = db
-            .get_camera(uuid)
-            .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
-        serve_json(
-            req,
-            &json::Camera::wrap(camera, &db, true, false).err_kind(ErrorKind::Internal)?,
-        )
-    }
-
     fn stream_recordings(
         &self,
         req: &Request<::hyper::body::Incoming>,
@@ -462,49 +859,201 @@ impl Service {
             }
             (time, split)
         };
+        // List without the database lock, so a busy system's flushes don't delay this request.
+        let stream_id = {
+            let index = self.db.read_index();
+            let Some(camera) = index.get_camera(uuid) else {
+                bail!(NotFound, msg("no such camera {uuid}"));
+            };
+            let Some(stream_id) = camera.streams[type_.index()] else {
+                bail!(NotFound, msg("no such stream {uuid}/{type_}"));
+            };
+            stream_id
+        };
+        let mut recordings = Vec::new();
+        let mut video_sample_entries = Vec::new();
+        self.db
+            .list_aggregated_recordings(stream_id, r, split, &mut |row| {
+                let end = row.ids.end - 1; // in api, ids are inclusive.
+                recordings.push(json::Recording {
+                    start_id: row.ids.start,
+                    end_id: if end == row.ids.start {
+                        None
+                    } else {
+                        Some(end)
+                    },
+                    run_start_id: row.run_start_id,
+                    start_time_90k: row.time.start.0,
+                    end_time_90k: row.time.end.0,
+                    sample_file_bytes: row.sample_file_bytes,
+                    open_id: row.open_id,
+                    first_uncommitted: row.first_uncommitted,
+                    video_samples: row.video_samples,
+                    video_sample_entry_id: row.video_sample_entry_id,
+                    growing: row.growing,
+                    has_trailing_zero: row.has_trailing_zero,
+                    end_reason: row.end_reason.clone(),
+                    packets_lost: row.packets_lost,
+                    damaged_frames: row.damaged_frames,
+                    video_sample_entry_changes: row
+                        .video_sample_entry_changes
+                        .iter()
+                        .map(|c| json::VideoSampleEntryChange {
+                            id: c.recording_id,
+                            start_time_90k: c.start.0,
+                            video_sample_entry_id: c.video_sample_entry_id,
+                        })
+                        .collect(),
+                });
+                let ids = std::iter::once(row.video_sample_entry_id).chain(
+                    row.video_sample_entry_changes
+                        .iter()
+                        .map(|c| c.video_sample_entry_id),
+                );
+                for id in ids {
+                    if !video_sample_entries.contains(&id) {
+                        video_sample_entries.push(id);
+                    }
+                }
+                Ok(())
+            })
+            .err_kind(ErrorKind::Internal)?;
         let db = self.db.lock();
-        let mut out = json::ListRecordings {
-            recordings: Vec::new(),
-            video_sample_entries: (&db, Vec::new()),
+        let out = json::ListRecordings {
+            recordings,
+            video_sample_entries: (&db, video_sample_entries),
         };
+        serve_json_with_etag(req, &out)
+    }
+
+    /// Lists the complete runs which overlap the requested time range.
+    fn stream_runs(
+        &self,
+        req: &Request<::hyper::body::Incoming>,
+        uuid: Uuid,
+        type_: db::StreamType,
+    ) -> ResponseResult {
+        let mut time = recording::Time::MIN..recording::Time::MAX;
+        if let Some(q) = req.uri().query() {
+            for (key, value) in form_urlencoded::parse(q.as_bytes()) {
+                let (key, value) = (key.borrow(), value.borrow());
+                match key {
+                    "startTime90k" => {
+                        time.start = recording::Time::parse(value)
+                            .map_err(|_| err!(InvalidArgument, msg("unparseable startTime90k")))?
+                    }
+                    "endTime90k" => {
+                        time.end = recording::Time::parse(value)
+                            .map_err(|_| err!(InvalidArgument, msg("unparseable endTime90k")))?
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let db = self.db.lock();
         let Some(camera) = db.get_camera(uuid) else {
             bail!(NotFound, msg("no such camera {uuid}"));
         };
         let Some(stream_id) = camera.streams[type_.index()] else {
             bail!(NotFound, msg("no such stream {uuid}/{type_}"));
         };
-        db.list_aggregated_recordings(stream_id, r, split, &mut |row| {
-            let end = row.ids.end - 1; // in api, ids are inclusive.
-            out.recordings.push(json::Recording {
-                start_id: row.ids.start,
-                end_id: if end == row.ids.start {
-                    None
-                } else {
-                    Some(end)
-                },
-                run_start_id: row.run_start_id,
-                start_time_90k: row.time.start.0,
-                end_time_90k: row.time.end.0,
-                sample_file_bytes: row.sample_file_bytes,
-                open_id: row.open_id,
-                first_uncommitted: row.first_uncommitted,
-                video_samples: row.video_samples,
-                video_sample_entry_id: row.video_sample_entry_id,
-                growing: row.growing,
-                has_trailing_zero: row.has_trailing_zero,
-                end_reason: row.end_reason.clone(),
-            });
-            if !out
-                .video_sample_entries
-                .1
-                .contains(&row.video_sample_entry_id)
-            {
-                out.video_sample_entries.1.push(row.video_sample_entry_id);
-            }
+        let mut run_start_ids = std::collections::BTreeSet::new();
+        db.list_recordings_by_time(stream_id, time, &mut |r| {
+            run_start_ids.insert(r.id.recording() - r.run_offset);
             Ok(())
         })
         .err_kind(ErrorKind::Internal)?;
-        serve_json(req, &out)
+        let mut runs = Vec::with_capacity(run_start_ids.len());
+        for run_start_id in run_start_ids {
+            let mut run: Option<json::Run> = None;
+            db.list_recordings_by_id(stream_id, db.run_ids(stream_id, run_start_id)?, &mut |r| {
+                let end = r.start + recording::Duration(i64::from(r.wall_duration_90k));
+                let growing = (r.flags & db::RecordingFlags::Growing as i32) != 0;
+                let run = run.get_or_insert_with(|| json::Run {
+                    run_start_id,
+                    start_id: r.id.recording(),
+                    end_id: r.id.recording(),
+                    open_id: r.open_id,
+                    start_time_90k: r.start.0,
+                    end_time_90k: end.0,
+                    sample_file_bytes: 0,
+                    video_samples: 0,
+                    growing,
+                    end_reason: None,
+                });
+                run.end_id = r.id.recording();
+                run.end_time_90k = end.0;
+                run.sample_file_bytes += i64::from(r.sample_file_bytes);
+                run.video_samples += i64::from(r.video_samples);
+                run.growing = growing;
+                run.end_reason = r.end_reason;
+                Ok(())
+            })
+            .err_kind(ErrorKind::Internal)?;
+            runs.extend(run);
+        }
+        serve_json_with_etag(req, &json::ListRuns { runs })
+    }
+
+    /// Lists the server's journal of events within the requested time range.
+    fn events(&self, req: &Request<::hyper::body::Incoming>) -> ResponseResult {
+        let mut time = recording::Time::MIN..recording::Time::MAX;
+        if let Some(q) = req.uri().query() {
+            for (key, value) in form_urlencoded::parse(q.as_bytes()) {
+                let (key, value) = (key.borrow(), value.borrow());
+                match key {
+                    "startTime90k" => {
+                        time.start = recording::Time::parse(value)
+                            .map_err(|_| err!(InvalidArgument, msg("unparseable startTime90k")))?
+                    }
+                    "endTime90k" => {
+                        time.end = recording::Time::parse(value)
+                            .map_err(|_| err!(InvalidArgument, msg("unparseable endTime90k")))?
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let db = self.db.lock();
+        let mut events = Vec::new();
+        db.list_events(time, &mut |e| {
+            let stream = e.stream_id.and_then(|id| db.streams_by_id().get(&id));
+            let camera = stream.and_then(|s| db.cameras_by_id().get(&s.camera_id));
+            events.push(json::Event {
+                time_90k: e.time.0,
+                kind: e.kind.as_str(),
+                camera_uuid: camera.map(|c| c.uuid),
+                stream_type: camera.and(stream).map(|s| s.type_.as_str()),
+                message: e.message.clone(),
+            });
+        })
+        .err_kind(ErrorKind::Internal)?;
+        serve_json(req, &json::ListEvents { events })
+    }
+
+    fn stream_run_rtsp_session(
+        &self,
+        req: &Request<::hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+        type_: db::StreamType,
+        run_start_id: i32,
+    ) -> ResponseResult {
+        if !caller.permissions.read_camera_configs {
+            bail!(PermissionDenied, msg("read_camera_configs required"));
+        }
+        let db = self.db.lock();
+        let Some(camera) = db.get_camera(uuid) else {
+            bail!(NotFound, msg("no such camera {uuid}"));
+        };
+        let Some(stream_id) = camera.streams[type_.index()] else {
+            bail!(NotFound, msg("no such stream {uuid}/{type_}"));
+        };
+        let id = db::CompositeId::new(stream_id, run_start_id);
+        let Some(s) = db.get_rtsp_session(id)? else {
+            bail!(NotFound, msg("no RTSP session recorded for run {id}"));
+        };
+        serve_json(req, &json::RtspSession::from(&s))
     }
 
     fn init_segment(
@@ -515,12 +1064,12 @@ impl Service {
     ) -> ResponseResult {
         let mut builder = mp4::FileBuilder::new(mp4::Type::InitSegment);
         let db = self.db.lock();
-        let Some(ent) = db.video_sample_entries_by_id().get(&id) else {
+        let Some(ent) = db.video_sample_entry(id) else {
             bail!(NotFound, msg("no such init segment"));
         };
         builder.append_video_sample_entry(ent.clone());
         let mp4 = builder
-            .build(self.db.clone(), self.dirs_by_stream_id.clone())
+            .build(self.db.clone(), self.dirs_by_stream_id())
             .err_kind(ErrorKind::Internal)?;
         if debug {
             Ok(plain_response(StatusCode::OK, format!("{mp4:#?}")))
@@ -616,6 +1165,10 @@ impl Service {
                             preferences: u.config.preferences.clone(),
                             session: Some(json::Session { csrf: s.csrf() }),
                         }),
+                        renew_cookie: self
+                            .session
+                            .sliding_renewal
+                            .then(|| self.session_cookie(sid, req.headers())),
                     })
                 }
                 Err(err) if err.kind() == base::ErrorKind::Unauthenticated => {
@@ -631,13 +1184,15 @@ impl Service {
         if matches!(conn_data.client_unix_uid, Some(uid) if Some(uid) == self.privileged_unix_uid) {
             return Ok(Caller {
                 permissions: db::Permissions {
-                    view_video: true,
+                    view_live: true,
+                    download_recordings: true,
                     read_camera_configs: true,
                     update_signals: true,
                     admin_users: true,
                     ..Default::default()
                 },
                 user: None,
+                renew_cookie: None,
             });
         }
 
@@ -645,6 +1200,7 @@ impl Service {
             return Ok(Caller {
                 permissions: s.clone(),
                 user: None,
+                renew_cookie: None,
             });
         }
 
@@ -652,6 +1208,7 @@ impl Service {
             return Ok(Caller {
                 permissions: db::Permissions::default(),
                 user: None,
+                renew_cookie: None,
             });
         }
 
@@ -683,10 +1240,21 @@ mod tests {
                 super::Service::new(super::Config {
                     db: db.db.clone(),
                     ui_dir: None,
+                    ui_spa_fallback: false,
                     allow_unauthenticated_permissions,
                     trust_forward_hdrs: true,
-                    time_zone_name: "".to_owned(),
                     privileged_unix_uid: None,
+                    stream_mover: None,
+                    panics: Default::default(),
+                    session: Default::default(),
+                    cors: None,
+                    cache: Default::default(),
+                    areas: None,
+                    requests: Default::default(),
+                    notify: Default::default(),
+                    snapshots: None,
+                    export_signing_key: None,
+                    previews: Default::default(),
                 })
                 .unwrap(),
             );
@@ -760,6 +1328,145 @@ mod tests {
             .await
             .unwrap();
         assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(resp.headers().contains_key(super::REQUEST_ID_HEADER));
+    }
+
+    #[tokio::test]
+    async fn forwarded_request_id() {
+        testutil::init();
+        let s = Server::new(None);
+        let cli = reqwest::Client::new();
+        let resp = cli
+            .get(&format!("{}/api/", &s.base_url))
+            .header(super::REQUEST_ID_HEADER, "proxy-123")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.headers().get(super::REQUEST_ID_HEADER).unwrap(),
+            "proxy-123"
+        );
+
+        // An unreasonable id is replaced.
+        let resp = cli
+            .get(&format!("{}/api/", &s.base_url))
+            .header(super::REQUEST_ID_HEADER, "a b")
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(resp.headers().get(super::REQUEST_ID_HEADER).unwrap(), "a b");
+    }
+
+    #[tokio::test]
+    async fn list_events() {
+        testutil::init();
+        let s = Server::new(Some(db::Permissions::new()));
+        {
+            let mut l = s.db.db.lock();
+            l.add_event(db::event::Event {
+                time: db::recording::Time(100),
+                kind: db::event::EventKind::StreamDown,
+                stream_id: Some(testutil::TEST_STREAM_ID),
+                message: "connection refused".to_owned(),
+            });
+            l.add_event(db::event::Event {
+                time: db::recording::Time(200),
+                kind: db::event::EventKind::ServerStop,
+                stream_id: None,
+                message: String::new(),
+            });
+        }
+        let cli = reqwest::Client::new();
+        let resp = cli
+            .get(format!("{}/api/events?endTime90k=200", &s.base_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            resp.json::<serde_json::Value>().await.unwrap(),
+            serde_json::json!({
+                "events": [{
+                    "time90k": 100,
+                    "kind": "streamDown",
+                    "cameraUuid": s.db.test_camera_uuid,
+                    "streamType": "main",
+                    "message": "connection refused",
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn conditional_top_level() {
+        testutil::init();
+        let s = Server::new(Some(db::Permissions::default()));
+        let cli = reqwest::Client::new();
+        let url = format!("{}/api/", &s.base_url);
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        let resp = cli
+            .get(&url)
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &etag);
+
+        // A change to the response yields a new tag.
+        let mut change =
+            s.db.db
+                .lock()
+                .null_camera_change(testutil::TEST_CAMERA_ID)
+                .unwrap();
+        change.short_name = "renamed".to_owned();
+        s.db.db
+            .lock()
+            .update_camera(testutil::TEST_CAMERA_ID, change)
+            .unwrap();
+        let resp = cli
+            .get(&url)
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_ne!(resp.headers().get(header::ETAG).unwrap(), &etag);
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = super::etag(b"body", None);
+        let mut hdrs = http::HeaderMap::new();
+        assert!(!super::if_none_match(&hdrs, &etag));
+        hdrs.insert(header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        assert!(!super::if_none_match(&hdrs, &etag));
+        hdrs.append(
+            header::IF_NONE_MATCH,
+            format!("\"x\", W/{}", etag.to_str().unwrap())
+                .parse()
+                .unwrap(),
+        );
+        assert!(super::if_none_match(&hdrs, &etag));
+        hdrs.insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+        assert!(super::if_none_match(&hdrs, &etag));
+    }
+
+    #[test]
+    fn cache_control_values() {
+        let mut config = crate::cmds::run::config::CacheConfig::default();
+        let (s, d) = super::cache_control_values(&config);
+        assert_eq!(s, "private, max-age=3600");
+        assert_eq!(d, "private, no-cache");
+        config.media_max_age_sec = 86400;
+        config.media_stale_while_revalidate_sec = Some(60);
+        config.dynamic_stale_while_revalidate_sec = Some(5);
+        config.public_media = true;
+        let (s, d) = super::cache_control_values(&config);
+        assert_eq!(s, "public, max-age=86400, stale-while-revalidate=60");
+        assert_eq!(d, "private, max-age=0, stale-while-revalidate=5");
     }
 
     #[test]
@@ -800,10 +1507,21 @@ mod bench {
                 super::Service::new(super::Config {
                     db: db.db.clone(),
                     ui_dir: None,
+                    ui_spa_fallback: false,
                     allow_unauthenticated_permissions: Some(db::Permissions::default()),
                     trust_forward_hdrs: false,
-                    time_zone_name: "".to_owned(),
                     privileged_unix_uid: None,
+                    stream_mover: None,
+                    panics: Default::default(),
+                    session: Default::default(),
+                    cors: None,
+                    cache: Default::default(),
+                    areas: None,
+                    requests: Default::default(),
+                    notify: Default::default(),
+                    snapshots: None,
+                    export_signing_key: None,
+                    previews: Default::default(),
                 })
                 .unwrap(),
             );
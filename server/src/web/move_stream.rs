@@ -0,0 +1,79 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/api/cameras/<uuid>/<type>/move` handling.
+
+use base::{bail, err};
+use http::{Method, Request, StatusCode};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::json;
+
+use super::{
+    into_json_body, parse_json_body, plain_response, require_csrf_if_session, Caller,
+    ResponseResult, Service,
+};
+
+impl Service {
+    pub(super) async fn move_stream(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+        type_: db::StreamType,
+    ) -> ResponseResult {
+        if *req.method() != Method::POST {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "POST expected",
+            ));
+        }
+        if !caller.permissions.admin_users {
+            bail!(Unauthenticated, msg("must have admin_users permission"));
+        }
+        let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::PostStreamMove = parse_json_body(&b)?;
+        require_csrf_if_session(&caller, r.csrf)?;
+        let Some(stream_mover) = self.stream_mover.clone() else {
+            bail!(
+                FailedPrecondition,
+                msg("can't move streams in read-only mode")
+            );
+        };
+        let (stream_id, dir_id) = {
+            let l = self.db.lock();
+            let camera = l
+                .get_camera(uuid)
+                .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+            let stream_id = camera.streams[type_.index()]
+                .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{type_}")))?;
+            let dir_id = l
+                .sample_file_dirs_by_id()
+                .values()
+                .find(|d| d.path == r.sample_file_dir_path)
+                .map(|d| d.id)
+                .ok_or_else(|| {
+                    err!(
+                        NotFound,
+                        msg(
+                            "no such sample file dir {}",
+                            r.sample_file_dir_path.display()
+                        )
+                    )
+                })?;
+            (stream_id, dir_id)
+        };
+
+        // Run the move in its own task, so that it completes even if the client disconnects.
+        tokio::spawn(
+            stream_mover
+                .move_stream(stream_id, dir_id)
+                .in_current_span(),
+        )
+        .await
+        .map_err(|e| err!(Internal, source(e)))??;
+        Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+    }
+}
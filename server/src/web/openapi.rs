@@ -0,0 +1,1434 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/api/openapi.json` handling: an OpenAPI 3 description of the API.
+//!
+//! This is written by hand to match the types in [`crate::json`] and the endpoints in
+//! [`super::path::Path`]; `ref/api.md` remains the authoritative description.
+
+use std::sync::OnceLock;
+
+use http::Request;
+use serde_json::{json, Value};
+
+use super::{serve_json, ResponseResult, Service};
+
+/// Returns a reference to the named schema in `#/components/schemas`.
+fn r(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// Returns a response with the given description and JSON body schema.
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// Returns a JSON request body with the given schema.
+fn json_body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn query(name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "schema": schema,
+        "description": description,
+    })
+}
+
+fn time_range_params() -> Value {
+    json!([
+        query(
+            "startTime90k",
+            r("Time90k"),
+            "Inclusive start of the time range."
+        ),
+        query(
+            "endTime90k",
+            r("Time90k"),
+            "Exclusive end of the time range."
+        ),
+    ])
+}
+
+fn no_content(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+fn video(content_type: &str, description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { content_type: { "schema": { "type": "string", "format": "binary" } } },
+    })
+}
+
+/// Merges JSON objects, to keep each `json!` invocation within the macro recursion limit.
+fn merge<const N: usize>(parts: [Value; N]) -> Value {
+    let mut out = serde_json::Map::new();
+    for p in parts {
+        let Value::Object(o) = p else {
+            unreachable!("parts should be objects");
+        };
+        out.extend(o);
+    }
+    Value::Object(out)
+}
+
+fn paths() -> Value {
+    let stream = |op: Value| {
+        let mut op = op;
+        let params = op
+            .as_object_mut()
+            .unwrap()
+            .entry("parameters")
+            .or_insert_with(|| json!([]));
+        let params = params.as_array_mut().unwrap();
+        params.insert(0, json!({ "$ref": "#/components/parameters/stream" }));
+        params.insert(0, json!({ "$ref": "#/components/parameters/camera" }));
+        op
+    };
+    let view_params = json!([
+        query(
+            "s",
+            json!({ "type": "string" }),
+            "Comma-separated segments to include, e.g. `1-5@0.26-42,9`. Required unless `run` is given."
+        ),
+        query(
+            "run",
+            json!({ "type": "string" }),
+            "A run to include, by the id of its first recording, e.g. `1@3.0-90000`."
+        ),
+        query(
+            "ts",
+            json!({ "type": "boolean" }),
+            "Whether to include a timestamp subtitle track."
+        ),
+        query(
+            "tsCamera",
+            json!({ "type": "boolean" }),
+            "Whether to include the camera's short name in each subtitle. Requires `ts`."
+        ),
+        query(
+            "tsSignals",
+            json!({ "type": "boolean" }),
+            "Whether to include the states of the camera's signals in each subtitle. Requires `ts`."
+        ),
+    ]);
+    merge([
+        json!({
+            "/api/": {
+                "get": {
+                    "summary": "Lists cameras, streams, and signals.",
+                    "parameters": [
+                        query("days", json!({ "type": "boolean" }), "Includes per-day summaries."),
+                        query(
+                            "cameraConfigs",
+                            json!({ "type": "boolean" }),
+                            "Includes camera and stream configuration; requires `readCameraConfigs`."
+                        ),
+                    ],
+                    "responses": { "200": json_response("Top-level information.", r("TopLevel")) },
+                },
+            },
+            "/api/login": {
+                "post": {
+                    "summary": "Logs in, setting a session cookie.",
+                    "security": [],
+                    "requestBody": json_body(r("LoginRequest")),
+                    "responses": { "204": no_content("Logged in.") },
+                },
+            },
+            "/api/logout": {
+                "post": {
+                    "summary": "Logs out, revoking the current session.",
+                    "requestBody": json_body(r("LogoutRequest")),
+                    "responses": { "204": no_content("Logged out.") },
+                },
+            },
+            "/api/cameras/{camera}/": {
+                "get": {
+                    "summary": "Describes a single camera.",
+                    "parameters": [{ "$ref": "#/components/parameters/camera" }],
+                    "responses": { "200": json_response("The camera.", r("Camera")) },
+                },
+                "patch": {
+                    "summary": "Updates a camera's descriptive metadata.",
+                    "parameters": [{ "$ref": "#/components/parameters/camera" }],
+                    "requestBody": json_body(r("PatchCamera")),
+                    "responses": { "204": no_content("Updated.") },
+                },
+            },
+            "/api/cameras/{camera}/{stream}/recordings": {
+                "get": stream(json!({
+                    "summary": "Lists recordings within a time range.",
+                    "parameters": [
+                        query("startTime90k", r("Time90k"), "Inclusive start of the time range."),
+                        query("endTime90k", r("Time90k"), "Exclusive end of the time range."),
+                        query(
+                            "split90k",
+                            json!({ "type": "integer", "format": "int64" }),
+                            "Splits runs of recordings longer than this duration."
+                        ),
+                    ],
+                    "responses": {
+                        "200": json_response("The recordings.", r("ListRecordings")),
+                    },
+                })),
+            },
+        }),
+        json!({
+            "/api/cameras/{camera}/{stream}/runs": {
+                "get": stream(json!({
+                    "summary": "Lists complete runs overlapping a time range.",
+                    "parameters": time_range_params(),
+                    "responses": { "200": json_response("The runs.", r("ListRuns")) },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/runs/{id}/rtspSession": {
+                "get": stream(json!({
+                    "summary": "Describes the RTSP session which produced a run.",
+                    "parameters": [{
+                        "name": "id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int32" },
+                        "description": "The run's start id.",
+                    }],
+                    "responses": {
+                        "200": json_response("The session.", r("RtspSession")),
+                    },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/view.mp4": {
+                "get": stream(json!({
+                    "summary": "Returns a `.mp4` file of the given segments.",
+                    "parameters": view_params.clone(),
+                    "responses": {
+                        "200": video("video/mp4", "The video."),
+                        "206": video("video/mp4", "A byte range of the video."),
+                    },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/view.m4s": {
+                "get": stream(json!({
+                    "summary": "Returns a `.m4s` media segment of a single recording.",
+                    "parameters": view_params.clone(),
+                    "responses": { "200": video("video/mp4", "The media segment.") },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/view.mkv": {
+                "get": stream(json!({
+                    "summary": "Returns a Matroska `.mkv` file of the given segments.",
+                    "parameters": view_params,
+                    "responses": {
+                        "200": video("video/x-matroska", "The video."),
+                        "206": video("video/x-matroska", "A byte range of the video."),
+                    },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/live.m4s": {
+                "get": stream(json!({
+                    "summary": "Streams live media segments over a WebSocket.",
+                    "responses": { "101": no_content("Switching to the WebSocket protocol.") },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/move": {
+                "post": stream(json!({
+                    "summary": "Moves a stream to another sample file directory.",
+                    "requestBody": json_body(r("PostStreamMove")),
+                    "responses": { "204": no_content("Move complete.") },
+                })),
+            },
+            "/api/init/{id}.mp4": {
+                "get": {
+                    "summary": "Returns a `.mp4` initialization segment.",
+                    "parameters": [{
+                        "name": "id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int32" },
+                        "description": "A video sample entry id.",
+                    }],
+                    "responses": { "200": video("video/mp4", "The initialization segment.") },
+                },
+            },
+        }),
+        json!({
+            "/api/cameras/{camera}/{stream}/replay.mp4": {
+                "get": stream(json!({
+                    "summary": "Returns a `.mp4` file of the stream's most recent frames.",
+                    "parameters": [query(
+                        "seconds",
+                        json!({ "type": "integer", "format": "int32", "minimum": 1 }),
+                        "The duration to replay; defaults to 30."
+                    )],
+                    "responses": {
+                        "200": video("video/mp4", "The video."),
+                        "206": video("video/mp4", "A byte range of the video."),
+                    },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/snapshot.jpg": {
+                "get": stream(json!({
+                    "summary": "Returns the stream's latest key frame as a JPEG.",
+                    "responses": { "200": video("image/jpeg", "The key frame.") },
+                })),
+            },
+            "/api/cameras/{camera}/{stream}/export.zip": {
+                "get": stream(json!({
+                    "summary": "Returns a `.zip` of `.mp4` files covering a time range.",
+                    "parameters": [
+                        query(
+                            "startTime90k",
+                            r("Time90k"),
+                            "Inclusive start of the time range. Required."
+                        ),
+                        query(
+                            "endTime90k",
+                            r("Time90k"),
+                            "Exclusive end of the time range. Required."
+                        ),
+                        query(
+                            "split",
+                            json!({ "type": "string", "enum": ["hour", "run"] }),
+                            "Whether to write one file per local hour (the default) or per run."
+                        ),
+                        query(
+                            "manifest",
+                            json!({ "type": "boolean" }),
+                            "Whether to add a `manifest.json` of the files' BLAKE3 digests."
+                        ),
+                    ],
+                    "responses": { "200": video("application/zip", "The archive.") },
+                })),
+            },
+            "/api/signals/{id}/snapshot": {
+                "get": {
+                    "summary": "Returns the latest archived snapshot of a signal's rising edge.",
+                    "parameters": [
+                        {
+                            "name": "id",
+                            "in": "path",
+                            "required": true,
+                            "schema": { "type": "integer", "format": "int32" },
+                            "description": "A signal id.",
+                        },
+                        query(
+                            "time90k",
+                            r("Time90k"),
+                            "Returns the latest snapshot at or before this time."
+                        ),
+                    ],
+                    "responses": { "200": video("image/jpeg", "The snapshot.") },
+                },
+            },
+        }),
+        json!({
+            "/api/signals": {
+                "get": {
+                    "summary": "Returns signal state changes or hourly summaries within a time range.",
+                    "parameters": [
+                        query("startTime90k", r("Time90k"), "Inclusive start of the time range."),
+                        query("endTime90k", r("Time90k"), "Exclusive end of the time range."),
+                        query(
+                            "summary",
+                            json!({ "type": "string", "enum": ["hour"] }),
+                            "Returns time spent in each state per hour rather than changes."
+                        ),
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "The changes or summaries.",
+                            json!({ "oneOf": [r("Signals"), r("SignalHours")] })
+                        ),
+                    },
+                },
+                "post": {
+                    "summary": "Changes signal states.",
+                    "requestBody": json_body(r("PostSignalsRequest")),
+                    "responses": {
+                        "200": json_response("The time of the change.", r("PostSignalsResponse")),
+                    },
+                },
+            },
+        }),
+        json!({
+            "/api/signals/{uuid}": {
+                "parameters": [{
+                    "name": "uuid",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string", "format": "uuid" },
+                    "description": "The signal's uuid, chosen by the client.",
+                }],
+                "put": {
+                    "summary": "Creates or replaces a signal.",
+                    "requestBody": json_body(r("PutSignal")),
+                    "responses": {
+                        "200": json_response("The signal's id.", r("PutSignalResponse")),
+                    },
+                },
+                "delete": {
+                    "summary": "Deletes a signal which has no recorded changes.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": { "204": no_content("Deleted.") },
+                },
+            },
+            "/api/signal-types/{uuid}": {
+                "parameters": [{
+                    "name": "uuid",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string", "format": "uuid" },
+                    "description": "The signal type's uuid, chosen by the client.",
+                }],
+                "put": {
+                    "summary": "Creates or replaces a signal type.",
+                    "requestBody": json_body(r("PutSignalType")),
+                    "responses": { "204": no_content("Stored.") },
+                },
+                "delete": {
+                    "summary": "Deletes a signal type which no signal uses.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": { "204": no_content("Deleted.") },
+                },
+            },
+            "/api/metrics": {
+                "get": {
+                    "summary": "Returns Prometheus metrics.",
+                    "responses": {
+                        "200": {
+                            "description": "Metrics in the Prometheus text exposition format.",
+                            "content": { "text/plain": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+            },
+            "/api/health": {
+                "get": {
+                    "summary": "Reports server health.",
+                    "security": [],
+                    "responses": {
+                        "200": json_response("Healthy.", r("Health")),
+                        "503": json_response("Degraded.", r("Health")),
+                    },
+                },
+            },
+            "/api/request": {
+                "get": {
+                    "summary": "Describes the request, for debugging.",
+                    "security": [],
+                    "responses": {
+                        "200": {
+                            "description": "A human-readable description.",
+                            "content": { "text/plain": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+            },
+            "/api/ui-version": {
+                "get": {
+                    "summary": "Identifies the web UI build being served.",
+                    "security": [],
+                    "responses": {
+                        "200": json_response("Success.", r("UiVersion")),
+                        "404": { "description": "No UI is configured." },
+                    },
+                },
+            },
+            "/api/openapi.json": {
+                "get": {
+                    "summary": "Returns this document.",
+                    "security": [],
+                    "responses": {
+                        "200": json_response("An OpenAPI 3 document.", json!({ "type": "object" })),
+                    },
+                },
+            },
+        }),
+        json!({
+            "/api/users/": {
+                "get": {
+                    "summary": "Lists users.",
+                    "responses": { "200": json_response("The users.", r("GetUsersResponse")) },
+                },
+                "post": {
+                    "summary": "Adds a user.",
+                    "requestBody": json_body(r("PutUsers")),
+                    "responses": { "200": json_response("The new user.", r("PutUsersResponse")) },
+                },
+            },
+            "/api/users/{id}": {
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int32" },
+                }],
+                "get": {
+                    "summary": "Describes a user.",
+                    "responses": { "200": json_response("The user.", r("UserSubset")) },
+                },
+                "patch": {
+                    "summary": "Updates a user.",
+                    "requestBody": json_body(r("PostUser")),
+                    "responses": { "204": no_content("Updated.") },
+                },
+                "delete": {
+                    "summary": "Deletes a user.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": { "204": no_content("Deleted.") },
+                },
+            },
+            "/api/permission-templates": {
+                "get": {
+                    "summary": "Lists permission templates.",
+                    "responses": {
+                        "200": json_response(
+                            "The templates.",
+                            r("GetPermissionTemplatesResponse"),
+                        ),
+                    },
+                },
+                "post": {
+                    "summary": "Adds a permission template.",
+                    "requestBody": json_body(r("PostPermissionTemplates")),
+                    "responses": {
+                        "200": json_response("The new template.", r("PutUsersResponse")),
+                    },
+                },
+            },
+            "/api/permission-templates/{id}": {
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int32" },
+                }],
+                "get": {
+                    "summary": "Describes a permission template.",
+                    "responses": {
+                        "200": json_response("The template.", r("PermissionTemplateSubset")),
+                    },
+                },
+                "patch": {
+                    "summary": "Updates a permission template and the users assigned to it.",
+                    "requestBody": json_body(r("PatchPermissionTemplate")),
+                    "responses": { "204": no_content("Updated.") },
+                },
+                "delete": {
+                    "summary": "Deletes a permission template which no user is assigned to.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": { "204": no_content("Deleted.") },
+                },
+            },
+        }),
+        json!({
+            "/api/activity": {
+                "get": {
+                    "summary": "Lists live views and downloads in progress.",
+                    "responses": {
+                        "200": json_response("The activities.", r("GetActivityResponse")),
+                    },
+                },
+            },
+            "/api/activity/{id}": {
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int64" },
+                }],
+                "delete": {
+                    "summary": "Terminates a live view or download.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": { "204": no_content("Terminated.") },
+                },
+            },
+        }),
+        json!({
+            "/api/webauthn/register/options": {
+                "post": {
+                    "summary": "Starts registering a WebAuthn credential for the current user.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": {
+                        "200": json_response(
+                            "Options for `navigator.credentials.create`.",
+                            r("WebAuthnCreationOptions"),
+                        ),
+                    },
+                },
+            },
+            "/api/webauthn/register": {
+                "post": {
+                    "summary": "Finishes registering a WebAuthn credential.",
+                    "requestBody": json_body(r("WebAuthnRegisterRequest")),
+                    "responses": {
+                        "200": json_response("The new credential.", r("PutUsersResponse")),
+                    },
+                },
+            },
+            "/api/webauthn/login/options": {
+                "post": {
+                    "summary": "Starts logging in with a WebAuthn credential.",
+                    "security": [],
+                    "requestBody": json_body(r("WebAuthnLoginOptionsRequest")),
+                    "responses": {
+                        "200": json_response(
+                            "Options for `navigator.credentials.get`.",
+                            r("WebAuthnRequestOptions"),
+                        ),
+                    },
+                },
+            },
+            "/api/webauthn/login": {
+                "post": {
+                    "summary": "Logs in with a WebAuthn assertion, setting a session cookie.",
+                    "security": [],
+                    "requestBody": json_body(r("WebAuthnLoginRequest")),
+                    "responses": { "204": no_content("Logged in.") },
+                },
+            },
+            "/api/users/{id}/webauthn-credentials": {
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int32" },
+                }],
+                "get": {
+                    "summary": "Lists a user's WebAuthn credentials.",
+                    "responses": {
+                        "200": json_response(
+                            "The credentials.",
+                            r("GetWebAuthnCredentialsResponse"),
+                        ),
+                    },
+                },
+            },
+            "/api/users/{id}/webauthn-credentials/{credentialId}": {
+                "parameters": [
+                    {
+                        "name": "id",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int32" },
+                    },
+                    {
+                        "name": "credentialId",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int32" },
+                    },
+                ],
+                "delete": {
+                    "summary": "Deletes a WebAuthn credential.",
+                    "requestBody": json_body(r("DeleteUser")),
+                    "responses": { "204": no_content("Deleted.") },
+                },
+            },
+        }),
+        json!({
+            "/api/events": {
+                "get": {
+                    "summary": "Lists server events, such as restarts and stream disconnections.",
+                    "parameters": time_range_params(),
+                    "responses": { "200": json_response("The events.", r("ListEvents")) },
+                },
+            },
+        }),
+    ])
+}
+
+fn schemas() -> Value {
+    let int = |format: &str| json!({ "type": "integer", "format": format });
+    let string = json!({ "type": "string" });
+    let boolean = json!({ "type": "boolean" });
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let csrf = json!({
+        "type": "string",
+        "description": "A CSRF token, required when using session authentication.",
+    });
+    let time_base = json!({
+        "type": "object",
+        "required": ["base", "rel90k"],
+        "properties": {
+            "base": { "type": "string", "enum": ["epoch", "now"] },
+            "rel90k": int("int64"),
+        },
+    });
+    merge([
+        json!({
+            "Time90k": {
+                "type": "integer",
+                "format": "int64",
+                "description": "Time in 90 kHz units since 1970-01-01 00:00:00 UTC.",
+            },
+            "TopLevel": {
+                "type": "object",
+                "required": [
+                    "timeZoneName", "serverVersion", "cameras", "permissions", "signals",
+                    "signalTypes",
+                ],
+                "properties": {
+                    "timeZoneName": string,
+                    "serverVersion": string,
+                    "cameras": { "type": "array", "items": r("Camera") },
+                    "permissions": r("Permissions"),
+                    "user": r("ToplevelUser"),
+                    "signals": { "type": "array", "items": r("Signal") },
+                    "signalTypes": { "type": "array", "items": r("SignalType") },
+                },
+            },
+            "ToplevelUser": {
+                "type": "object",
+                "required": ["name", "id", "preferences"],
+                "properties": {
+                    "name": string,
+                    "id": int("int32"),
+                    "preferences": { "type": "object" },
+                    "session": {
+                        "type": "object",
+                        "nullable": true,
+                        "required": ["csrf"],
+                        "properties": { "csrf": string },
+                    },
+                },
+            },
+            "Camera": {
+                "type": "object",
+                "required": ["uuid", "id", "shortName", "streams"],
+                "properties": {
+                    "uuid": uuid,
+                    "id": int("int32"),
+                    "shortName": string,
+                    "location": string,
+                    "coordinates": r("Coordinates"),
+                    "directionDeg": {
+                        "type": "integer",
+                        "minimum": 0,
+                        "maximum": 359,
+                        "description": "Degrees clockwise from true north.",
+                    },
+                    "tags": { "type": "array", "items": string },
+                    "config": {
+                        "type": "object",
+                        "description": "Present only with `cameraConfigs=true`.",
+                    },
+                    "streams": {
+                        "type": "object",
+                        "description": "Streams keyed by type: `main`, `sub`, or `ext`.",
+                        "additionalProperties": r("Stream"),
+                    },
+                },
+            },
+            "Stream": {
+                "type": "object",
+                "required": [
+                    "id", "retainBytes", "minStartTime90k", "maxEndTime90k", "totalDuration90k",
+                    "totalSampleFileBytes", "fsBytes", "record",
+                ],
+                "properties": {
+                    "id": int("int32"),
+                    "retainBytes": int("int64"),
+                    "minStartTime90k": { "allOf": [r("Time90k")], "nullable": true },
+                    "maxEndTime90k": { "allOf": [r("Time90k")], "nullable": true },
+                    "totalDuration90k": int("int64"),
+                    "totalSampleFileBytes": int("int64"),
+                    "fsBytes": int("int64"),
+                    "record": boolean,
+                    "days": {
+                        "type": "object",
+                        "description": "Present only with `days=true`; keyed by `YYYY-mm-dd`.",
+                        "additionalProperties": {
+                            "type": "object",
+                            "properties": {
+                                "startTime90k": r("Time90k"),
+                                "endTime90k": r("Time90k"),
+                                "totalDuration90k": int("int64"),
+                            },
+                        },
+                    },
+                    "config": {
+                        "type": "object",
+                        "description": "Present only with `cameraConfigs=true`.",
+                    },
+                    "lastError": {
+                        "type": "object",
+                        "description": "Present only with `cameraConfigs=true`, while failing.",
+                        "required": ["time90k", "kind", "message"],
+                        "properties": {
+                            "time90k": r("Time90k"),
+                            "kind": { "type": "string", "enum": ["auth", "network", "other"] },
+                            "message": { "type": "string" },
+                        },
+                    },
+                    "loading": {
+                        "type": "boolean",
+                        "description": "Present and true while recordings are still loading.",
+                    },
+                },
+            },
+        }),
+        json!({
+            "Signal": {
+                "type": "object",
+                "required": ["id", "cameras", "uuid", "type", "shortName"],
+                "properties": {
+                    "id": int("int32"),
+                    "cameras": {
+                        "type": "object",
+                        "description": "Associated cameras' uuids to `direct` or `indirect`.",
+                        "additionalProperties": { "type": "string", "enum": ["direct", "indirect"] },
+                    },
+                    "uuid": uuid,
+                    "type": uuid,
+                    "shortName": string,
+                    "days": {
+                        "type": "object",
+                        "description": "Present only with `days=true`; keyed by `YYYY-mm-dd`.",
+                        "additionalProperties": {
+                            "type": "object",
+                            "properties": {
+                                "startTime90k": r("Time90k"),
+                                "endTime90k": r("Time90k"),
+                                "states": { "type": "array", "items": int("int64") },
+                            },
+                        },
+                    },
+                },
+            },
+            "SignalType": {
+                "type": "object",
+                "required": ["uuid", "states"],
+                "properties": {
+                    "uuid": uuid,
+                    "name": string,
+                    "states": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["value", "name", "color"],
+                            "properties": {
+                                "value": int("int32"),
+                                "name": string,
+                                "motion": boolean,
+                                "color": string,
+                            },
+                        },
+                    },
+                },
+            },
+        }),
+        json!({
+            "PutSignalType": {
+                "type": "object",
+                "required": ["name", "states"],
+                "properties": {
+                    "csrf": csrf,
+                    "name": string,
+                    "states": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["value", "name"],
+                            "properties": {
+                                "value": { "type": "integer", "minimum": 1, "maximum": 15 },
+                                "name": string,
+                                "motion": boolean,
+                                "color": string,
+                            },
+                        },
+                    },
+                },
+            },
+            "PutSignal": {
+                "type": "object",
+                "required": ["shortName", "type"],
+                "properties": {
+                    "csrf": csrf,
+                    "shortName": string,
+                    "type": uuid,
+                    "cameras": {
+                        "type": "object",
+                        "description": "Associated cameras' uuids to `direct` or `indirect`.",
+                        "additionalProperties": { "type": "string", "enum": ["direct", "indirect"] },
+                    },
+                },
+            },
+            "PutSignalResponse": {
+                "type": "object",
+                "required": ["id"],
+                "properties": { "id": int("int32") },
+            },
+            "LoginRequest": {
+                "type": "object",
+                "required": ["username", "password"],
+                "properties": { "username": string, "password": string },
+            },
+            "LogoutRequest": {
+                "type": "object",
+                "required": ["csrf"],
+                "properties": { "csrf": csrf },
+            },
+        }),
+        json!({
+            "ListRecordings": {
+                "type": "object",
+                "required": ["recordings", "videoSampleEntries"],
+                "properties": {
+                    "recordings": { "type": "array", "items": r("Recording") },
+                    "videoSampleEntries": {
+                        "type": "object",
+                        "description": "Video sample entries keyed by id.",
+                        "additionalProperties": r("VideoSampleEntry"),
+                    },
+                },
+            },
+            "Recording": {
+                "type": "object",
+                "required": [
+                    "startTime90k", "endTime90k", "sampleFileBytes", "videoSamples",
+                    "videoSampleEntryId", "startId", "openId", "runStartId",
+                ],
+                "properties": {
+                    "startTime90k": r("Time90k"),
+                    "endTime90k": r("Time90k"),
+                    "sampleFileBytes": int("int64"),
+                    "videoSamples": int("int64"),
+                    "videoSampleEntryId": int("int32"),
+                    "startId": int("int32"),
+                    "openId": int("int32"),
+                    "runStartId": int("int32"),
+                    "firstUncommitted": int("int32"),
+                    "endId": int("int32"),
+                    "growing": boolean,
+                    "hasTrailingZero": boolean,
+                    "endReason": string,
+                    "packetsLost": int("int64"),
+                    "damagedFrames": int("int64"),
+                    "videoSampleEntryChanges": {
+                        "type": "array",
+                        "items": r("VideoSampleEntryChange"),
+                    },
+                },
+            },
+            "Signals": {
+                "type": "object",
+                "required": ["times90k", "signalIds", "states"],
+                "properties": {
+                    "times90k": { "type": "array", "items": r("Time90k") },
+                    "signalIds": { "type": "array", "items": int("int32") },
+                    "states": { "type": "array", "items": int("int32") },
+                },
+            },
+            "SignalHours": {
+                "type": "object",
+                "required": ["startTimes90k", "signalIds", "states"],
+                "properties": {
+                    "startTimes90k": { "type": "array", "items": r("Time90k") },
+                    "signalIds": { "type": "array", "items": int("int32") },
+                    "states": {
+                        "type": "array",
+                        "items": { "type": "array", "items": int("int64") },
+                    },
+                },
+            },
+            "PostSignalsRequest": {
+                "type": "object",
+                "required": ["signalIds", "states", "start", "end"],
+                "properties": {
+                    "csrf": csrf,
+                    "signalIds": { "type": "array", "items": int("int32") },
+                    "states": { "type": "array", "items": int("int32") },
+                    "start": time_base,
+                    "end": time_base,
+                },
+            },
+            "PostSignalsResponse": {
+                "type": "object",
+                "required": ["time90k"],
+                "properties": { "time90k": r("Time90k") },
+            },
+            "PostStreamMove": {
+                "type": "object",
+                "required": ["sampleFileDirPath"],
+                "properties": { "csrf": csrf, "sampleFileDirPath": string },
+            },
+        }),
+        json!({
+            "Permissions": {
+                "type": "object",
+                "properties": {
+                    "viewLive": boolean,
+                    "downloadRecordings": boolean,
+                    "readCameraConfigs": boolean,
+                    "updateSignals": boolean,
+                    "adminUsers": boolean,
+                },
+            },
+            "UserSubset": {
+                "type": "object",
+                "properties": {
+                    "username": string,
+                    "disabled": boolean,
+                    "expirationTimeSec": { "type": "integer", "format": "int64", "nullable": true },
+                    "downloadQuotaBytesPerDay": {
+                        "type": "integer",
+                        "format": "int64",
+                        "nullable": true,
+                    },
+                    "tagDownloads": boolean,
+                    "preferences": { "type": "object" },
+                    "password": { "type": "string", "nullable": true },
+                    "permissions": r("Permissions"),
+                    "permissionTemplateId": { "type": "integer", "format": "int32", "nullable": true },
+                },
+            },
+            "GetUsersResponse": {
+                "type": "object",
+                "required": ["users"],
+                "properties": {
+                    "users": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["id", "user"],
+                            "properties": { "id": int("int32"), "user": r("UserSubset") },
+                        },
+                    },
+                },
+            },
+            "PutUsers": {
+                "type": "object",
+                "required": ["user"],
+                "properties": { "csrf": csrf, "user": r("UserSubset") },
+            },
+            "PutUsersResponse": {
+                "type": "object",
+                "required": ["id"],
+                "properties": { "id": int("int32") },
+            },
+            "PostUser": {
+                "type": "object",
+                "properties": {
+                    "csrf": csrf,
+                    "update": r("UserSubset"),
+                    "precondition": r("UserSubset"),
+                },
+            },
+            "DeleteUser": {
+                "type": "object",
+                "properties": { "csrf": csrf },
+            },
+            "PermissionTemplateSubset": {
+                "type": "object",
+                "properties": { "name": string, "permissions": r("Permissions") },
+            },
+            "GetPermissionTemplatesResponse": {
+                "type": "object",
+                "required": ["templates"],
+                "properties": {
+                    "templates": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["id", "template"],
+                            "properties": {
+                                "id": int("int32"),
+                                "template": r("PermissionTemplateSubset"),
+                            },
+                        },
+                    },
+                },
+            },
+            "PostPermissionTemplates": {
+                "type": "object",
+                "required": ["template"],
+                "properties": { "csrf": csrf, "template": r("PermissionTemplateSubset") },
+            },
+            "PatchPermissionTemplate": {
+                "type": "object",
+                "required": ["update"],
+                "properties": { "csrf": csrf, "update": r("PermissionTemplateSubset") },
+            },
+            "UiVersion": {
+                "type": "object",
+                "required": ["version"],
+                "properties": { "version": { "type": "string" } },
+            },
+            "Health": {
+                "type": "object",
+                "required": ["status", "recentPanicCount"],
+                "properties": {
+                    "status": { "type": "string", "enum": ["ok", "degraded"] },
+                    "recentPanicCount": int("int64"),
+                    "recentPanics": {
+                        "type": "array",
+                        "description": "Present only with the `readCameraConfigs` permission.",
+                        "items": {
+                            "type": "object",
+                            "required": ["stream", "timeSec", "message"],
+                            "properties": {
+                                "stream": string,
+                                "timeSec": int("int64"),
+                                "message": string,
+                            },
+                        },
+                    },
+                    "dirScans": {
+                        "type": "array",
+                        "description": "Present only with the `readCameraConfigs` permission.",
+                        "items": {
+                            "type": "object",
+                            "required": ["path", "filesScanned", "filesAbandoned"],
+                            "properties": {
+                                "path": string,
+                                "filesScanned": int("int64"),
+                                "filesAbandoned": int("int64"),
+                            },
+                        },
+                    },
+                },
+            },
+        }),
+        json!({
+            "VideoSampleEntry": {
+                "type": "object",
+                "required": [
+                    "width", "height", "paspHSpacing", "paspVSpacing", "aspectWidth", "aspectHeight",
+                ],
+                "properties": {
+                    "width": int("int32"),
+                    "height": int("int32"),
+                    "paspHSpacing": int("int32"),
+                    "paspVSpacing": int("int32"),
+                    "aspectWidth": int("int32"),
+                    "aspectHeight": int("int32"),
+                },
+            },
+            "VideoSampleEntryChange": {
+                "type": "object",
+                "required": ["id", "startTime90k", "videoSampleEntryId"],
+                "properties": {
+                    "id": int("int32"),
+                    "startTime90k": r("Time90k"),
+                    "videoSampleEntryId": int("int32"),
+                },
+            },
+        }),
+        json!({
+            "ListRuns": {
+                "type": "object",
+                "required": ["runs"],
+                "properties": { "runs": { "type": "array", "items": r("Run") } },
+            },
+            "RtspSession": {
+                "type": "object",
+                "required": ["sdp"],
+                "properties": {
+                    "sdp": string,
+                    "transport": string,
+                    "server": string,
+                },
+            },
+            "Run": {
+                "type": "object",
+                "required": [
+                    "runStartId", "startId", "endId", "openId", "startTime90k", "endTime90k",
+                    "sampleFileBytes", "videoSamples",
+                ],
+                "properties": {
+                    "runStartId": int("int32"),
+                    "startId": int("int32"),
+                    "endId": int("int32"),
+                    "openId": int("int32"),
+                    "startTime90k": r("Time90k"),
+                    "endTime90k": r("Time90k"),
+                    "sampleFileBytes": int("int64"),
+                    "videoSamples": int("int64"),
+                    "growing": boolean,
+                    "endReason": string,
+                },
+            },
+        }),
+        json!({
+            "ListEvents": {
+                "type": "object",
+                "required": ["events"],
+                "properties": { "events": { "type": "array", "items": r("Event") } },
+            },
+            "Event": {
+                "type": "object",
+                "required": ["time90k", "kind"],
+                "properties": {
+                    "time90k": r("Time90k"),
+                    "kind": {
+                        "type": "string",
+                        "enum": [
+                            "serverStart", "serverStop", "dirOpen", "streamUp", "streamDown",
+                            "flushError",
+                        ],
+                    },
+                    "cameraUuid": uuid,
+                    "streamType": { "type": "string", "enum": ["main", "sub", "ext"] },
+                    "message": string,
+                },
+            },
+        }),
+        json!({
+            "Coordinates": {
+                "type": "object",
+                "description": "A position in WGS 84 decimal degrees.",
+                "required": ["latitude", "longitude"],
+                "properties": {
+                    "latitude": { "type": "number", "minimum": -90, "maximum": 90 },
+                    "longitude": { "type": "number", "minimum": -180, "maximum": 180 },
+                },
+            },
+            "GetActivityResponse": {
+                "type": "object",
+                "required": ["activities"],
+                "properties": {
+                    "activities": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": [
+                                "id", "kind", "user", "cameraUuid", "stream", "startTime90k",
+                                "bytes", "bytesPerSec",
+                            ],
+                            "properties": {
+                                "id": int("int64"),
+                                "kind": { "type": "string", "enum": ["live", "download"] },
+                                "user": { "type": "string", "nullable": true },
+                                "cameraUuid": uuid,
+                                "stream": { "type": "string", "enum": ["main", "sub", "ext"] },
+                                "startTime90k": r("Time90k"),
+                                "bytes": int("int64"),
+                                "bytesPerSec": int("int64"),
+                            },
+                        },
+                    },
+                },
+            },
+            "PatchCamera": {
+                "type": "object",
+                "required": ["update"],
+                "properties": {
+                    "csrf": csrf,
+                    "update": {
+                        "type": "object",
+                        "description": "Fields to set; absent fields are unchanged.",
+                        "properties": {
+                            "location": string,
+                            "coordinates": { "allOf": [r("Coordinates")], "nullable": true },
+                            "directionDeg": { "type": "integer", "nullable": true },
+                            "tags": { "type": "array", "items": string },
+                        },
+                    },
+                },
+            },
+        }),
+        webauthn_schemas(),
+    ])
+}
+
+/// Returns the schemas for `/api/webauthn/*`. Binary fields are base64url-encoded strings.
+fn webauthn_schemas() -> Value {
+    let int = |format: &str| json!({ "type": "integer", "format": format });
+    let string = json!({ "type": "string" });
+    let csrf = json!({
+        "type": "string",
+        "description": "A CSRF token, required when using session authentication.",
+    });
+    let descriptors = json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "required": ["type", "id"],
+            "properties": { "type": string, "id": string },
+        },
+    });
+    json!({
+        "WebAuthnCreationOptions": {
+            "type": "object",
+            "required": [
+                "challenge", "rp", "user", "pubKeyCredParams", "timeout", "excludeCredentials",
+                "authenticatorSelection",
+            ],
+            "properties": {
+                "challenge": string,
+                "rp": {
+                    "type": "object",
+                    "required": ["id", "name"],
+                    "properties": { "id": string, "name": string },
+                },
+                "user": {
+                    "type": "object",
+                    "required": ["id", "name", "displayName"],
+                    "properties": { "id": string, "name": string, "displayName": string },
+                },
+                "pubKeyCredParams": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["type", "alg"],
+                        "properties": { "type": string, "alg": int("int64") },
+                    },
+                },
+                "timeout": int("int64"),
+                "excludeCredentials": descriptors,
+                "authenticatorSelection": {
+                    "type": "object",
+                    "properties": { "residentKey": string, "userVerification": string },
+                },
+            },
+        },
+        "WebAuthnRegisterRequest": {
+            "type": "object",
+            "required": ["name", "clientDataJSON", "attestationObject"],
+            "properties": {
+                "csrf": csrf,
+                "name": string,
+                "clientDataJSON": string,
+                "attestationObject": string,
+            },
+        },
+        "WebAuthnLoginOptionsRequest": {
+            "type": "object",
+            "properties": { "username": string },
+        },
+        "WebAuthnRequestOptions": {
+            "type": "object",
+            "required": ["challenge", "rpId", "allowCredentials", "timeout", "userVerification"],
+            "properties": {
+                "challenge": string,
+                "rpId": string,
+                "allowCredentials": descriptors,
+                "timeout": int("int64"),
+                "userVerification": string,
+            },
+        },
+        "WebAuthnLoginRequest": {
+            "type": "object",
+            "required": ["id", "clientDataJSON", "authenticatorData", "signature"],
+            "properties": {
+                "id": string,
+                "clientDataJSON": string,
+                "authenticatorData": string,
+                "signature": string,
+                "userHandle": string,
+            },
+        },
+        "GetWebAuthnCredentialsResponse": {
+            "type": "object",
+            "required": ["credentials"],
+            "properties": {
+                "credentials": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["id", "name", "creationTimeSec"],
+                        "properties": {
+                            "id": int("int32"),
+                            "name": string,
+                            "creationTimeSec": int("int64"),
+                            "lastUseTimeSec": int("int64"),
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// Builds the full document.
+fn document() -> Value {
+    let path_param = |name: &str, description: &str| {
+        json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+            "description": description,
+        })
+    };
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Moonfire NVR API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "See `ref/api.md` for details.",
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "parameters": {
+                "camera": path_param("camera", "The camera's uuid."),
+                "stream": path_param("stream", "The stream type: `main`, `sub`, or `ext`."),
+            },
+            "securitySchemes": {
+                "session": { "type": "apiKey", "in": "cookie", "name": "s" },
+            },
+        },
+        "security": [{ "session": [] }],
+    })
+}
+
+impl Service {
+    /// Serves the OpenAPI document, which is available without authentication.
+    pub(super) fn openapi(&self, req: &Request<hyper::body::Incoming>) -> ResponseResult {
+        static DOCUMENT: OnceLock<Value> = OnceLock::new();
+        serve_json(req, DOCUMENT.get_or_init(document))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that every `$ref` in `v` refers to something defined in `doc`.
+    fn check_refs(doc: &Value, v: &Value) {
+        match v {
+            Value::Object(o) => {
+                if let Some(Value::String(target)) = o.get("$ref") {
+                    let pointer = target.strip_prefix('#').unwrap();
+                    assert!(doc.pointer(pointer).is_some(), "dangling {target}");
+                }
+                o.values().for_each(|v| check_refs(doc, v));
+            }
+            Value::Array(a) => a.iter().for_each(|v| check_refs(doc, v)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn document_is_consistent() {
+        let doc = document();
+        check_refs(&doc, &doc);
+
+        // Every path in the document should decode to a real endpoint.
+        let uuid = "35144640-ff1e-4619-b0d5-4c74c185741c";
+        for p in doc["paths"].as_object().unwrap().keys() {
+            let p = p
+                .replace("{camera}", uuid)
+                .replace("{stream}", "main")
+                .replace("{id}", "1")
+                .replace("{uuid}", uuid)
+                .replace("{credentialId}", "1");
+            let decoded = super::super::path::Path::decode(&p);
+            assert_ne!(decoded, super::super::path::Path::NotFound, "{p}");
+            assert_ne!(decoded, super::super::path::Path::Static, "{p}");
+        }
+    }
+}
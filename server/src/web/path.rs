@@ -4,6 +4,7 @@
 
 //! Decodes request paths.
 
+use crate::cmds::run::config::ApiArea;
 use std::str::FromStr;
 use uuid::Uuid;
 
@@ -15,19 +16,85 @@ pub(super) enum Path {
     InitSegment(i32, bool),                           // "/api/init/<id>.mp4{.txt}"
     Camera(Uuid),                                     // "/api/cameras/<uuid>/"
     Signals,                                          // "/api/signals"
+    Events,                                           // "/api/events"
+    Signal(Uuid),                                     // "/api/signals/<uuid>"
+    SignalSnapshot(u32),                              // "/api/signals/<id>/snapshot"
+    SignalType(Uuid),                                 // "/api/signal-types/<uuid>"
     StreamRecordings(Uuid, db::StreamType),           // "/api/cameras/<uuid>/<type>/recordings"
+    StreamRuns(Uuid, db::StreamType),                 // "/api/cameras/<uuid>/<type>/runs"
+    StreamRunRtspSession(Uuid, db::StreamType, i32),  // ".../<type>/runs/<id>/rtspSession"
     StreamViewMp4(Uuid, db::StreamType, bool),        // "/api/cameras/<uuid>/<type>/view.mp4{.txt}"
     StreamViewMp4Segment(Uuid, db::StreamType, bool), // "/api/cameras/<uuid>/<type>/view.m4s{.txt}"
+    StreamViewMkv(Uuid, db::StreamType, bool),        // "/api/cameras/<uuid>/<type>/view.mkv{.txt}"
     StreamLiveMp4Segments(Uuid, db::StreamType),      // "/api/cameras/<uuid>/<type>/live.m4s"
+    StreamReplayMp4(Uuid, db::StreamType),            // "/api/cameras/<uuid>/<type>/replay.mp4"
+    StreamExportZip(Uuid, db::StreamType),            // "/api/cameras/<uuid>/<type>/export.zip"
+    StreamSnapshotJpeg(Uuid, db::StreamType),         // "/api/cameras/<uuid>/<type>/snapshot.jpg"
+    StreamMove(Uuid, db::StreamType),                 // "/api/cameras/<uuid>/<type>/move"
     Login,                                            // "/api/login"
     Logout,                                           // "/api/logout"
+    WebAuthnRegisterOptions,                          // "/api/webauthn/register/options"
+    WebAuthnRegister,                                 // "/api/webauthn/register"
+    WebAuthnLoginOptions,                             // "/api/webauthn/login/options"
+    WebAuthnLogin,                                    // "/api/webauthn/login"
+    Metrics,                                          // "/api/metrics"
+    Health,                                           // "/api/health"
+    OpenApi,                                          // "/api/openapi.json"
+    UiVersion,                                        // "/api/ui-version"
     Static,                                           // (anything that doesn't start with "/api/")
     Users,                                            // "/api/users"
     User(i32),                                        // "/api/users/<id>"
+    UserWebAuthnCredentials(i32),                     // "/api/users/<id>/webauthn-credentials"
+    UserWebAuthnCredential(i32, i32),                 // ".../webauthn-credentials/<id>"
+    PermissionTemplates,                              // "/api/permission-templates"
+    PermissionTemplate(i32),                          // "/api/permission-templates/<id>"
+    Activities,                                       // "/api/activity"
+    Activity(u64),                                    // "/api/activity/<id>"
     NotFound,
 }
 
 impl Path {
+    /// Returns the part of the API this path belongs to, or `None` for `NotFound`.
+    pub(super) fn area(&self) -> Option<ApiArea> {
+        Some(match self {
+            Path::Static | Path::UiVersion => ApiArea::Ui,
+            Path::Request
+            | Path::Login
+            | Path::Logout
+            | Path::WebAuthnRegisterOptions
+            | Path::WebAuthnRegister
+            | Path::WebAuthnLoginOptions
+            | Path::WebAuthnLogin => ApiArea::Session,
+            Path::TopLevel
+            | Path::Camera(_)
+            | Path::StreamRecordings(..)
+            | Path::StreamRuns(..)
+            | Path::Events => ApiArea::List,
+            Path::InitSegment(..)
+            | Path::StreamViewMp4(..)
+            | Path::StreamViewMp4Segment(..)
+            | Path::StreamViewMkv(..)
+            | Path::StreamReplayMp4(..)
+            | Path::StreamExportZip(..) => ApiArea::View,
+            Path::StreamLiveMp4Segments(..) | Path::StreamSnapshotJpeg(..) => ApiArea::Live,
+            Path::Signals | Path::Signal(_) | Path::SignalSnapshot(_) | Path::SignalType(_) => {
+                ApiArea::Signals
+            }
+            Path::StreamRunRtspSession(..)
+            | Path::StreamMove(..)
+            | Path::Users
+            | Path::User(_)
+            | Path::UserWebAuthnCredentials(_)
+            | Path::UserWebAuthnCredential(..)
+            | Path::PermissionTemplates
+            | Path::PermissionTemplate(_)
+            | Path::Activities
+            | Path::Activity(_) => ApiArea::Admin,
+            Path::Metrics | Path::Health | Path::OpenApi => ApiArea::Monitoring,
+            Path::NotFound => return None,
+        })
+    }
+
     /// Decodes a request path, notably not including any request parameters.
     pub(super) fn decode(path: &str) -> Self {
         let path = match path.strip_prefix("/api/") {
@@ -38,8 +105,19 @@ impl Path {
             "" => return Path::TopLevel,
             "login" => return Path::Login,
             "logout" => return Path::Logout,
+            "webauthn/register/options" => return Path::WebAuthnRegisterOptions,
+            "webauthn/register" => return Path::WebAuthnRegister,
+            "webauthn/login/options" => return Path::WebAuthnLoginOptions,
+            "webauthn/login" => return Path::WebAuthnLogin,
+            "metrics" => return Path::Metrics,
+            "health" => return Path::Health,
+            "openapi.json" => return Path::OpenApi,
+            "ui-version" => return Path::UiVersion,
             "request" => return Path::Request,
             "signals" => return Path::Signals,
+            "events" => return Path::Events,
+            "permission-templates" | "permission-templates/" => return Path::PermissionTemplates,
+            "activity" => return Path::Activities,
             _ => {}
         };
         if let Some(path) = path.strip_prefix("init/") {
@@ -83,12 +161,42 @@ impl Path {
             };
             match path {
                 "recordings" => Path::StreamRecordings(uuid, type_),
+                "runs" => Path::StreamRuns(uuid, type_),
                 "view.mp4" => Path::StreamViewMp4(uuid, type_, false),
                 "view.mp4.txt" => Path::StreamViewMp4(uuid, type_, true),
                 "view.m4s" => Path::StreamViewMp4Segment(uuid, type_, false),
                 "view.m4s.txt" => Path::StreamViewMp4Segment(uuid, type_, true),
+                "view.mkv" => Path::StreamViewMkv(uuid, type_, false),
+                "view.mkv.txt" => Path::StreamViewMkv(uuid, type_, true),
                 "live.m4s" => Path::StreamLiveMp4Segments(uuid, type_),
-                _ => Path::NotFound,
+                "replay.mp4" => Path::StreamReplayMp4(uuid, type_),
+                "export.zip" => Path::StreamExportZip(uuid, type_),
+                "snapshot.jpg" => Path::StreamSnapshotJpeg(uuid, type_),
+                "move" => Path::StreamMove(uuid, type_),
+                _ => match path.strip_prefix("runs/").and_then(|p| p.split_once('/')) {
+                    Some((id, "rtspSession")) => match i32::from_str(id) {
+                        Ok(id) => Path::StreamRunRtspSession(uuid, type_, id),
+                        Err(_) => Path::NotFound,
+                    },
+                    _ => Path::NotFound,
+                },
+            }
+        } else if let Some(path) = path.strip_prefix("signals/") {
+            match path.split_once('/') {
+                Some((id, "snapshot")) => match u32::from_str(id) {
+                    Ok(id) => Path::SignalSnapshot(id),
+                    Err(_) => Path::NotFound,
+                },
+                Some(_) => Path::NotFound,
+                None => match Uuid::parse_str(path) {
+                    Ok(uuid) => Path::Signal(uuid),
+                    Err(_) => Path::NotFound,
+                },
+            }
+        } else if let Some(path) = path.strip_prefix("signal-types/") {
+            match Uuid::parse_str(path) {
+                Ok(uuid) => Path::SignalType(uuid),
+                Err(_) => Path::NotFound,
             }
         } else if let Some(path) = path.strip_prefix("users/") {
             if let Ok(id) = i32::from_str(path) {
@@ -97,7 +205,32 @@ impl Path {
             if path.is_empty() {
                 return Path::Users;
             }
-            Path::NotFound
+            let Some((id, path)) = path.split_once('/') else {
+                return Path::NotFound;
+            };
+            let Ok(id) = i32::from_str(id) else {
+                return Path::NotFound;
+            };
+            match path {
+                "webauthn-credentials" => Path::UserWebAuthnCredentials(id),
+                _ => match path
+                    .strip_prefix("webauthn-credentials/")
+                    .map(i32::from_str)
+                {
+                    Some(Ok(cred)) => Path::UserWebAuthnCredential(id, cred),
+                    _ => Path::NotFound,
+                },
+            }
+        } else if let Some(path) = path.strip_prefix("permission-templates/") {
+            match i32::from_str(path) {
+                Ok(id) => Path::PermissionTemplate(id),
+                Err(_) => Path::NotFound,
+            }
+        } else if let Some(path) = path.strip_prefix("activity/") {
+            match u64::from_str(path) {
+                Ok(id) => Path::Activity(id),
+                Err(_) => Path::NotFound,
+            }
         } else {
             Path::NotFound
         }
@@ -112,7 +245,37 @@ mod tests {
         use uuid::Uuid;
         let cam_uuid = Uuid::parse_str("35144640-ff1e-4619-b0d5-4c74c185741c").unwrap();
         assert_eq!(Path::decode("/foo"), Path::Static);
+        assert_eq!(Path::decode("/foo").area(), Some(super::ApiArea::Ui));
+        assert_eq!(
+            Path::decode("/api/users/").area(),
+            Some(super::ApiArea::Admin)
+        );
+        assert_eq!(Path::decode("/api/nonexistent").area(), None);
         assert_eq!(Path::decode("/api/"), Path::TopLevel);
+        assert_eq!(Path::decode("/api/metrics"), Path::Metrics);
+        assert_eq!(Path::decode("/api/health"), Path::Health);
+        assert_eq!(
+            Path::decode("/api/webauthn/login/options"),
+            Path::WebAuthnLoginOptions
+        );
+        assert_eq!(
+            Path::decode("/api/webauthn/register"),
+            Path::WebAuthnRegister
+        );
+        assert_eq!(
+            Path::decode("/api/users/1/webauthn-credentials"),
+            Path::UserWebAuthnCredentials(1)
+        );
+        assert_eq!(
+            Path::decode("/api/users/1/webauthn-credentials/2"),
+            Path::UserWebAuthnCredential(1, 2)
+        );
+        assert_eq!(
+            Path::decode("/api/users/1/webauthn-credentials/x"),
+            Path::NotFound
+        );
+        assert_eq!(Path::decode("/api/openapi.json"), Path::OpenApi);
+        assert_eq!(Path::decode("/api/ui-version"), Path::UiVersion);
         assert_eq!(
             Path::decode("/api/init/42.mp4"),
             Path::InitSegment(42, false)
@@ -135,6 +298,22 @@ mod tests {
             Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/sub/recordings"),
             Path::StreamRecordings(cam_uuid, db::StreamType::Sub)
         );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/runs"),
+            Path::StreamRuns(cam_uuid, db::StreamType::Main)
+        );
+        assert_eq!(
+            Path::decode(
+                "/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/runs/42/rtspSession"
+            ),
+            Path::StreamRunRtspSession(cam_uuid, db::StreamType::Main, 42)
+        );
+        assert_eq!(
+            Path::decode(
+                "/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/runs/x/rtspSession"
+            ),
+            Path::NotFound
+        );
         assert_eq!(
             Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/junk/recordings"),
             Path::NotFound
@@ -155,10 +334,34 @@ mod tests {
             Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/view.m4s.txt"),
             Path::StreamViewMp4Segment(cam_uuid, db::StreamType::Main, true)
         );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/view.mkv"),
+            Path::StreamViewMkv(cam_uuid, db::StreamType::Main, false)
+        );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/view.mkv.txt"),
+            Path::StreamViewMkv(cam_uuid, db::StreamType::Main, true)
+        );
         assert_eq!(
             Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/live.m4s"),
             Path::StreamLiveMp4Segments(cam_uuid, db::StreamType::Main)
         );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/replay.mp4"),
+            Path::StreamReplayMp4(cam_uuid, db::StreamType::Main)
+        );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/export.zip"),
+            Path::StreamExportZip(cam_uuid, db::StreamType::Main)
+        );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/snapshot.jpg"),
+            Path::StreamSnapshotJpeg(cam_uuid, db::StreamType::Main)
+        );
+        assert_eq!(
+            Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/move"),
+            Path::StreamMove(cam_uuid, db::StreamType::Main)
+        );
         assert_eq!(
             Path::decode("/api/cameras/35144640-ff1e-4619-b0d5-4c74c185741c/main/junk"),
             Path::NotFound
@@ -166,9 +369,37 @@ mod tests {
         assert_eq!(Path::decode("/api/login"), Path::Login);
         assert_eq!(Path::decode("/api/logout"), Path::Logout);
         assert_eq!(Path::decode("/api/signals"), Path::Signals);
+        assert_eq!(Path::decode("/api/events"), Path::Events);
+        assert_eq!(
+            Path::decode("/api/signals/3/snapshot"),
+            Path::SignalSnapshot(3)
+        );
+        assert_eq!(Path::decode("/api/signals/x/snapshot"), Path::NotFound);
+        assert_eq!(
+            Path::decode("/api/signals/35144640-ff1e-4619-b0d5-4c74c185741c"),
+            Path::Signal(cam_uuid)
+        );
+        assert_eq!(Path::decode("/api/signals/3"), Path::NotFound);
+        assert_eq!(
+            Path::decode("/api/signal-types/35144640-ff1e-4619-b0d5-4c74c185741c"),
+            Path::SignalType(cam_uuid)
+        );
+        assert_eq!(Path::decode("/api/signal-types/x"), Path::NotFound);
         assert_eq!(Path::decode("/api/junk"), Path::NotFound);
         assert_eq!(Path::decode("/api/users/42"), Path::User(42));
         assert_eq!(Path::decode("/api/users/asdf"), Path::NotFound);
         assert_eq!(Path::decode("/api/users/"), Path::Users);
+        assert_eq!(
+            Path::decode("/api/permission-templates"),
+            Path::PermissionTemplates
+        );
+        assert_eq!(
+            Path::decode("/api/permission-templates/3"),
+            Path::PermissionTemplate(3)
+        );
+        assert_eq!(Path::decode("/api/permission-templates/x"), Path::NotFound);
+        assert_eq!(Path::decode("/api/activity"), Path::Activities);
+        assert_eq!(Path::decode("/api/activity/7"), Path::Activity(7));
+        assert_eq!(Path::decode("/api/activity/x"), Path::NotFound);
     }
 }
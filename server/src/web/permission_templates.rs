@@ -0,0 +1,122 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Permission template management: `/api/permission-templates/*`.
+
+use base::{bail, err};
+use http::{Method, Request, StatusCode};
+
+use crate::json::{self, PermissionTemplateSubset, PermissionTemplateWithId};
+
+use super::{
+    into_json_body, parse_json_body, plain_response, require_csrf_if_session, serve_json, Caller,
+    ResponseResult, Service,
+};
+
+impl Service {
+    pub(super) async fn permission_templates(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+    ) -> ResponseResult {
+        require_admin_users(&caller)?;
+        match *req.method() {
+            Method::GET | Method::HEAD => {
+                let l = self.db.lock();
+                let templates = l
+                    .permission_templates_by_id()
+                    .iter()
+                    .map(|(&id, t)| PermissionTemplateWithId {
+                        id,
+                        template: PermissionTemplateSubset::from(t),
+                    })
+                    .collect();
+                serve_json(&req, &json::GetPermissionTemplatesResponse { templates })
+            }
+            Method::POST => {
+                let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let mut r: json::PostPermissionTemplates = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                let name = r
+                    .template
+                    .name
+                    .take()
+                    .ok_or_else(|| err!(InvalidArgument, msg("name must be specified")))?;
+                let permissions = r.template.permissions.take().unwrap_or_default();
+                if r.template != Default::default() {
+                    bail!(Unimplemented, msg("unsupported template fields: {r:#?}"));
+                }
+                let mut l = self.db.lock();
+                let t = l.add_permission_template(name.to_owned(), permissions.into())?;
+                serve_json(&parts, &json::PostPermissionTemplatesResponse { id: t.id })
+            }
+            _ => Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "GET, HEAD, or POST expected",
+            )),
+        }
+    }
+
+    pub(super) async fn permission_template(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        id: i32,
+    ) -> ResponseResult {
+        require_admin_users(&caller)?;
+        match *req.method() {
+            Method::GET | Method::HEAD => {
+                let l = self.db.lock();
+                let t = l
+                    .permission_templates_by_id()
+                    .get(&id)
+                    .ok_or_else(|| err!(NotFound, msg("can't find requested template")))?;
+                serve_json(&req, &PermissionTemplateSubset::from(t))
+            }
+            Method::PATCH => {
+                let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let mut r: json::PatchPermissionTemplate = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                let mut l = self.db.lock();
+                let t = l
+                    .permission_templates_by_id()
+                    .get(&id)
+                    .ok_or_else(|| err!(NotFound, msg("can't find requested template")))?;
+                let name = r.update.name.take().unwrap_or(&t.name).to_owned();
+                let permissions = r
+                    .update
+                    .permissions
+                    .take()
+                    .map(db::Permissions::from)
+                    .unwrap_or_else(|| t.permissions.clone());
+
+                // Safety valve in case something is added to PermissionTemplateSubset and
+                // forgotten here.
+                if r.update != Default::default() {
+                    bail!(Unimplemented, msg("updates not supported: {:#?}", r.update));
+                }
+                l.update_permission_template(id, name, permissions)?;
+                Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+            }
+            Method::DELETE => {
+                let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let r: json::DeletePermissionTemplate = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                self.db.lock().delete_permission_template(id)?;
+                Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+            }
+            _ => Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "GET, HEAD, PATCH, or DELETE expected",
+            )),
+        }
+    }
+}
+
+fn require_admin_users(caller: &Caller) -> Result<(), base::Error> {
+    if !caller.permissions.admin_users {
+        bail!(Unauthenticated, msg("must have admin_users permission"));
+    }
+    Ok(())
+}
@@ -0,0 +1,159 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/replay.mp4` handling: instant replay of a stream's recent live frames.
+
+use base::{bail, err};
+use db::recording::{self, rescale};
+use http::Request;
+use std::borrow::Borrow;
+use std::ops::Range;
+use url::form_urlencoded;
+use uuid::Uuid;
+
+use crate::mp4;
+
+use super::{Caller, ResponseResult, Service};
+
+/// The duration to replay when the request doesn't specify `seconds`.
+const DEFAULT_SECONDS: u32 = 30;
+
+impl Service {
+    pub(super) fn stream_replay_mp4(
+        &self,
+        req: &Request<::hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+        stream_type: db::StreamType,
+    ) -> ResponseResult {
+        if !caller.permissions.view_live {
+            bail!(PermissionDenied, msg("view_live required"));
+        }
+        let mut seconds = DEFAULT_SECONDS;
+        if let Some(q) = req.uri().query() {
+            for (key, value) in form_urlencoded::parse(q.as_bytes()) {
+                let (key, value) = (key.borrow(), value.borrow());
+                match key {
+                    "seconds" => {
+                        seconds = value
+                            .parse()
+                            .ok()
+                            .filter(|&s| s > 0)
+                            .ok_or_else(|| err!(InvalidArgument, msg("unparseable seconds")))?
+                    }
+                    _ => bail!(InvalidArgument, msg("parameter {key} not understood")),
+                }
+            }
+        }
+
+        let dirs_by_stream_id = self.dirs_by_stream_id();
+        let mut builder = mp4::FileBuilder::new(mp4::Type::Normal);
+        builder.set_index_cache(self.index_cache.clone());
+        let mut start_time_for_filename = None;
+        let camera_name;
+        {
+            let db = self.db.lock();
+            let camera = db
+                .get_camera(uuid)
+                .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+            camera_name = camera.short_name.clone();
+            let stream_id = camera.streams[stream_type.index()]
+                .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{stream_type}")))?;
+            if dirs_by_stream_id
+                .get(&stream_id)
+                .is_some_and(|d| !d.admit_playback())
+            {
+                bail!(
+                    ResourceExhausted,
+                    msg("sample file dir for {uuid}/{stream_type} is overloaded; try again later")
+                );
+            }
+
+            // The frames are in order, so each recording's frames are contiguous.
+            let frames = db.recent_live_frames(
+                stream_id,
+                i64::from(seconds) * recording::TIME_UNITS_PER_SEC,
+            )?;
+            let mut ranges: Vec<(i32, Range<i32>)> = Vec::new();
+            for f in frames {
+                match ranges.last_mut() {
+                    Some((id, r)) if *id == f.recording => r.end = f.media_off_90k.end,
+                    _ => ranges.push((f.recording, f.media_off_90k)),
+                }
+            }
+            let (Some(first), Some(last)) = (ranges.first(), ranges.last()) else {
+                bail!(
+                    NotFound,
+                    msg("no recent frames for {uuid}/{stream_type}; is it streaming?")
+                );
+            };
+            let ids = first.0..last.0 + 1;
+            let mut i = 0;
+            db.list_recordings_by_id(stream_id, ids, &mut |r| {
+                let Some((_, mr)) = ranges.get(i).filter(|(id, _)| *id == r.id.recording()) else {
+                    return Ok(());
+                };
+                i += 1;
+                if start_time_for_filename.is_none() {
+                    start_time_for_filename = Some(
+                        r.start
+                            + recording::Duration(i64::from(rescale(
+                                mr.start,
+                                r.media_duration_90k,
+                                r.wall_duration_90k,
+                            ))),
+                    );
+                }
+                builder.append(&db, &r, mr.clone(), true)
+            })?;
+            if i != ranges.len() {
+                bail!(
+                    Internal,
+                    msg(
+                        "recording {}/{} of recent frames is missing",
+                        stream_id,
+                        ranges[i].0
+                    ),
+                );
+            }
+        }
+        if let Some(start) = start_time_for_filename {
+            let tm = time::at(time::Timespec {
+                sec: start.unix_seconds(),
+                nsec: 0,
+            });
+            builder.set_filename(&format!(
+                "{}-{}-{}-replay.mp4",
+                tm.strftime("%Y%m%d%H%M%S").unwrap(),
+                camera_name,
+                stream_type.as_str(),
+            ))?;
+        }
+        let mp4 = builder.build(self.db.clone(), dirs_by_stream_id)?;
+        Ok(http_serve::serve(mp4, req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::web::tests::Server;
+    use db::testutil;
+
+    #[tokio::test]
+    async fn replay_without_frames() {
+        testutil::init();
+        let mut permissions = db::Permissions::new();
+        permissions.view_live = true;
+        let s = Server::new(Some(permissions));
+        let cli = reqwest::Client::new();
+        let url = format!(
+            "{}/api/cameras/{}/main/replay.mp4",
+            &s.base_url, s.db.test_camera_uuid
+        );
+        let resp = cli.get(format!("{url}?seconds=0")).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
@@ -14,7 +14,9 @@ use tracing::{info, warn};
 use crate::{json, web::parse_json_body};
 
 use super::{csrf_matches, extract_sid, into_json_body, plain_response, ResponseResult, Service};
+use crate::cmds::run::config::{CookieSecure, SameSite};
 use std::convert::TryFrom;
+use std::fmt::Write as _;
 
 impl Service {
     pub(super) async fn login(
@@ -28,7 +30,7 @@ impl Service {
                 "POST expected",
             ));
         }
-        let (parts, b) = into_json_body(req).await?;
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
         let r: json::LoginRequest = parse_json_body(&b)?;
         let Some(host) = parts.headers.get(header::HOST) else {
             bail!(InvalidArgument, msg("missing Host header"));
@@ -41,27 +43,11 @@ impl Service {
         .to_owned();
         let mut l = self.db.lock();
 
-        // If the request came in over https, tell the browser to only send the cookie on https
-        // requests also.
-        let is_secure = self.is_secure(&parts.headers);
-
-        // Use SameSite=Lax rather than SameSite=Strict. Safari apparently doesn't send
-        // SameSite=Strict cookies on WebSocket upgrade requests. There's no real security
-        // difference for Moonfire NVR anyway. SameSite=Strict exists as CSRF protection for
-        // sites that (unlike Moonfire NVR) don't follow best practices by (a)
-        // mutating based on GET requests and (b) not using CSRF tokens.
-        use auth::SessionFlag;
-        let flags = (SessionFlag::HttpOnly as i32)
-            | (SessionFlag::SameSite as i32)
-            | if is_secure {
-                SessionFlag::Secure as i32
-            } else {
-                0
-            };
+        let flags = self.session_flags(&parts.headers);
         let (sid, _) = l
             .login_by_password(authreq, r.username, r.password, Some(domain), flags)
             .err_kind(ErrorKind::Unauthenticated)?;
-        let cookie = encode_sid(sid, flags);
+        let cookie = encode_sid(sid, flags, self.cookie_max_age());
         Ok(Response::builder()
             .header(
                 header::SET_COOKIE,
@@ -83,7 +69,7 @@ impl Service {
                 "POST expected",
             ));
         }
-        let (parts, b) = into_json_body(req).await?;
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
         let r: json::LogoutRequest = parse_json_body(&b)?;
 
         let mut res = Response::new(b""[..].into());
@@ -119,8 +105,58 @@ impl Service {
     }
 }
 
+impl Service {
+    /// Returns the `SessionFlag`s for a session cookie sent in response to a request with the
+    /// given headers.
+    pub(super) fn session_flags(&self, hdrs: &http::HeaderMap) -> i32 {
+        // By default, if the request came in over https, tell the browser to only send the cookie
+        // on https requests also.
+        let secure = match self.session.secure {
+            CookieSecure::Auto => self.is_secure(hdrs),
+            CookieSecure::Always => true,
+            CookieSecure::Never => false,
+        };
+
+        // The default of SameSite=Lax rather than SameSite=Strict is deliberate. Safari apparently
+        // doesn't send SameSite=Strict cookies on WebSocket upgrade requests. There's no real
+        // security difference for Moonfire NVR anyway. SameSite=Strict exists as CSRF protection
+        // for sites that (unlike Moonfire NVR) don't follow best practices by (a) mutating based
+        // on GET requests and (b) not using CSRF tokens.
+        use auth::SessionFlag;
+        let same_site = match self.session.same_site {
+            SameSite::Lax => SessionFlag::SameSite as i32,
+            SameSite::Strict => SessionFlag::SameSite as i32 | SessionFlag::SameSiteStrict as i32,
+            SameSite::None => SessionFlag::SameSiteNone as i32,
+        };
+        (SessionFlag::HttpOnly as i32)
+            | same_site
+            | if secure {
+                SessionFlag::Secure as i32
+            } else {
+                0
+            }
+    }
+
+    fn cookie_max_age(&self) -> i64 {
+        self.session.expiry_sec.map_or(DEFAULT_MAX_AGE, i64::from)
+    }
+
+    /// Returns a `Set-Cookie` value which renews the given session's cookie.
+    pub(super) fn session_cookie(
+        &self,
+        sid: db::RawSessionId,
+        hdrs: &http::HeaderMap,
+    ) -> HeaderValue {
+        let cookie = encode_sid(sid, self.session_flags(hdrs), self.cookie_max_age());
+        HeaderValue::try_from(cookie).expect("cookie can't have invalid bytes")
+    }
+}
+
+/// The `Max-Age` of session cookies when sessions don't expire.
+const DEFAULT_MAX_AGE: i64 = 2147483648;
+
 /// Encodes a session into `Set-Cookie` header value form.
-fn encode_sid(sid: db::RawSessionId, flags: i32) -> String {
+fn encode_sid(sid: db::RawSessionId, flags: i32, max_age: i64) -> String {
     let mut cookie = String::with_capacity(128);
     cookie.push_str("s=");
     STANDARD_NO_PAD.encode_string(sid, &mut cookie);
@@ -135,8 +171,10 @@ fn encode_sid(sid: db::RawSessionId, flags: i32) -> String {
         cookie.push_str("; SameSite=Strict");
     } else if (flags & SessionFlag::SameSite as i32) != 0 {
         cookie.push_str("; SameSite=Lax");
+    } else if (flags & SessionFlag::SameSiteNone as i32) != 0 {
+        cookie.push_str("; SameSite=None");
     }
-    cookie.push_str("; Max-Age=2147483648; Path=/");
+    write!(cookie, "; Max-Age={max_age}; Path=/").expect("write to String should succeed");
     cookie
 }
 
@@ -275,14 +313,23 @@ mod tests {
                 (SessionFlag::Secure as i32)
                     | (SessionFlag::HttpOnly as i32)
                     | (SessionFlag::SameSite as i32)
-                    | (SessionFlag::SameSiteStrict as i32)
+                    | (SessionFlag::SameSiteStrict as i32),
+                2147483648
             ),
             format!("s={s64}; HttpOnly; Secure; SameSite=Strict; Max-Age=2147483648; Path=/")
         );
         assert_eq!(
-            encode_sid(s, SessionFlag::SameSite as i32),
+            encode_sid(s, SessionFlag::SameSite as i32, 2147483648),
             format!("s={s64}; SameSite=Lax; Max-Age=2147483648; Path=/")
         );
+        assert_eq!(
+            encode_sid(
+                s,
+                (SessionFlag::Secure as i32) | (SessionFlag::SameSiteNone as i32),
+                86400
+            ),
+            format!("s={s64}; Secure; SameSite=None; Max-Age=86400; Path=/")
+        );
     }
 
     #[derive(Clone, Debug, Default)]
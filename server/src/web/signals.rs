@@ -2,13 +2,14 @@
 // Copyright (C) 2021 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
-//! `/api/signals` handling.
+//! `/api/signals` and `/api/signal-types` handling.
 
-use base::{bail, clock::Clocks, err};
+use base::{bail, clock::Clocks, err, FastHashMap};
 use db::recording;
-use http::{Method, Request, StatusCode};
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
 use url::form_urlencoded;
 
+use crate::cmds::run::notify;
 use crate::json;
 
 use super::{
@@ -17,6 +18,12 @@ use super::{
 };
 
 use std::borrow::Borrow;
+use uuid::Uuid;
+
+/// Only signal changes this close to the current time get snapshots; the camera's current view
+/// says little about a change reported well after (or before) the fact.
+const SNAPSHOT_WINDOW: recording::Duration =
+    recording::Duration(60 * recording::TIME_UNITS_PER_SEC);
 
 impl Service {
     pub(super) async fn signals(
@@ -42,7 +49,7 @@ impl Service {
         if !caller.permissions.update_signals {
             bail!(PermissionDenied, msg("update_signals required"));
         }
-        let (parts, b) = into_json_body(req).await?;
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
         let r: json::PostSignalsRequest = parse_json_body(&b)?;
         require_csrf_if_session(&caller, r.csrf)?;
         let now = recording::Time::new(self.db.clocks().realtime());
@@ -55,12 +62,236 @@ impl Service {
             json::PostSignalsTimeBase::Epoch(t) => t,
             json::PostSignalsTimeBase::Now(d) => now + d,
         };
+        let mut prior_states = FastHashMap::default();
+        l.list_changes_by_time(start..start, &mut |c: &db::signal::ListStateChangesRow| {
+            prior_states.insert(c.signal, c.state);
+        });
         l.update_signals(start..end, &r.signal_ids, &r.states)?;
+        for (&signal_id, &state) in r.signal_ids.iter().zip(r.states.iter()) {
+            if prior_states.get(&signal_id).copied().unwrap_or(0) != state {
+                self.motion_edge(&l, now, start, signal_id, state);
+            }
+        }
+        drop(l);
         serve_json(&parts, &json::PostSignalsResponse { time_90k: now })
     }
 
+    /// Declares (`PUT`) or removes (`DELETE`) a signal, so external systems can set up their
+    /// own signals at runtime.
+    pub(super) async fn signal(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+    ) -> ResponseResult {
+        if !caller.permissions.update_signals {
+            bail!(PermissionDenied, msg("update_signals required"));
+        }
+        match *req.method() {
+            Method::PUT => {
+                let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let r: json::PutSignal = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                let mut l = self.db.lock();
+                let mut config = db::json::SignalConfig {
+                    short_name: r.short_name,
+                    ..Default::default()
+                };
+                for (camera_uuid, association) in r.cameras {
+                    let c = l.get_camera(camera_uuid).ok_or_else(|| {
+                        err!(InvalidArgument, msg("no such camera {camera_uuid}"))
+                    })?;
+                    config.camera_associations.insert(c.id, association);
+                }
+                let id = l.put_signal(uuid, r.type_, config)?;
+                drop(l);
+                serve_json(&parts, &json::PutSignalResponse { id })
+            }
+            Method::DELETE => {
+                let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let r: json::DeleteSignal = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                self.db.lock().delete_signal(uuid)?;
+                Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+            }
+            _ => Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "PUT or DELETE expected",
+            )),
+        }
+    }
+
+    /// Declares (`PUT`) or removes (`DELETE`) a signal type.
+    pub(super) async fn signal_type(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        uuid: Uuid,
+    ) -> ResponseResult {
+        if !caller.permissions.update_signals {
+            bail!(PermissionDenied, msg("update_signals required"));
+        }
+        match *req.method() {
+            Method::PUT => {
+                let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let r: json::PutSignalType = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                let mut config = db::json::SignalTypeConfig {
+                    name: r.name,
+                    ..Default::default()
+                };
+                for s in r.states {
+                    let value = s.value;
+                    let prev = config.values.insert(
+                        value,
+                        db::json::SignalTypeValueConfig {
+                            name: s.name,
+                            motion: s.motion,
+                            color: s.color,
+                            ..Default::default()
+                        },
+                    );
+                    if prev.is_some() {
+                        bail!(InvalidArgument, msg("duplicate state value {value}"));
+                    }
+                }
+                self.db.lock().put_signal_type(uuid, config)?;
+                Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+            }
+            Method::DELETE => {
+                let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+                let r: json::DeleteSignal = parse_json_body(&b)?;
+                require_csrf_if_session(&caller, r.csrf)?;
+                self.db.lock().delete_signal_type(uuid)?;
+                Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+            }
+            _ => Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "PUT or DELETE expected",
+            )),
+        }
+    }
+
+    /// Handles a signal's change to `state` at `time`, if `state` is configured as motion for the
+    /// signal's type: sends a `Motion` event and, if the change is current, archives a snapshot.
+    fn motion_edge(
+        &self,
+        l: &db::LockedDatabase,
+        now: recording::Time,
+        time: recording::Time,
+        signal_id: u32,
+        state: u16,
+    ) {
+        let Some(signal) = l.signals_by_id().get(&signal_id) else {
+            return;
+        };
+        let Some(value) = l
+            .signal_types_by_uuid()
+            .get(&signal.type_)
+            .zip(u8::try_from(state).ok())
+            .and_then(|(t, state)| t.config.values.get(&state))
+            .filter(|v| v.motion)
+        else {
+            return;
+        };
+        let direct_cameras = || {
+            signal
+                .config
+                .camera_associations
+                .iter()
+                .filter(|(_, type_)| *type_ == "direct")
+                .filter_map(|(id, _)| l.cameras_by_id().get(id))
+        };
+        let archive = self
+            .snapshots
+            .as_ref()
+            .filter(|_| (time - now).0.abs() <= SNAPSHOT_WINDOW.0);
+        if let Some(archive) = archive {
+            if let Some((c, url)) =
+                direct_cameras().find_map(|c| Some((c, c.config.snapshot_url.clone()?)))
+            {
+                match crate::cmds::run::config::Secret::camera_password(&c.config) {
+                    Ok(password) => {
+                        archive.capture(signal_id, time, url, c.config.username.clone(), password)
+                    }
+                    Err(err) => tracing::warn!(
+                        camera = %c.short_name,
+                        err = %err.chain(),
+                        "unable to take snapshot"
+                    ),
+                }
+            }
+        }
+        let detail = format!("signal {}: {}", signal.config.short_name, value.name);
+        let mut cameras = direct_cameras()
+            .map(|c| notify::Camera {
+                uuid: c.uuid,
+                short_name: c.short_name.clone(),
+            })
+            .peekable();
+        if cameras.peek().is_none() {
+            self.notify.send(notify::Event {
+                type_: notify::EventType::Motion,
+                time,
+                camera: None,
+                detail,
+            });
+            return;
+        }
+        for camera in cameras {
+            self.notify.send(notify::Event {
+                type_: notify::EventType::Motion,
+                time,
+                camera: Some(camera),
+                detail: detail.clone(),
+            });
+        }
+    }
+
+    fn signal_snapshot(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        caller: Caller,
+        signal_id: u32,
+    ) -> ResponseResult {
+        if !caller.permissions.download_recordings {
+            bail!(PermissionDenied, msg("download_recordings required"));
+        }
+        let mut at = recording::Time::MAX;
+        if let Some(q) = req.uri().query() {
+            for (key, value) in form_urlencoded::parse(q.as_bytes()) {
+                let (key, value) = (key.borrow(), value.borrow());
+                match key {
+                    "time90k" => {
+                        at = recording::Time::parse(value)
+                            .map_err(|_| err!(InvalidArgument, msg("unparseable time90k")))?
+                    }
+                    _ => bail!(InvalidArgument, msg("parameter {key} not understood")),
+                }
+            }
+        }
+        let Some(archive) = &self.snapshots else {
+            bail!(NotFound, msg("snapshots aren't configured"));
+        };
+        if !self.db.lock().signals_by_id().contains_key(&signal_id) {
+            bail!(NotFound, msg("no such signal {signal_id}"));
+        }
+        let Some((time, data)) = archive.find(signal_id, at)? else {
+            bail!(
+                NotFound,
+                msg("no snapshot of signal {signal_id} at or before {at}")
+            );
+        };
+        Ok(Response::builder()
+            .header(header::CONTENT_TYPE, HeaderValue::from_static("image/jpeg"))
+            .header("X-Time-90k", time.0.to_string())
+            .body(data.into())
+            .expect("hardcoded head should be valid"))
+    }
+
     fn get_signals(&self, req: &Request<hyper::body::Incoming>) -> ResponseResult {
         let mut time = recording::Time::MIN..recording::Time::MAX;
+        let mut hourly = false;
         if let Some(q) = req.uri().query() {
             for (key, value) in form_urlencoded::parse(q.as_bytes()) {
                 let (key, value) = (key.borrow(), value.borrow());
@@ -73,11 +304,25 @@ impl Service {
                         time.end = recording::Time::parse(value)
                             .map_err(|_| err!(InvalidArgument, msg("unparseable endTime90k")))?
                     }
+                    "summary" => match value {
+                        "hour" => hourly = true,
+                        _ => bail!(InvalidArgument, msg("unsupported summary {value:?}")),
+                    },
                     _ => {}
                 }
             }
         }
 
+        if hourly {
+            let mut hours = json::SignalHours::default();
+            for ((start, signal), v) in self.db.lock().list_signal_hours(time) {
+                hours.start_times_90k.push(start);
+                hours.signal_ids.push(signal);
+                hours.states.push(v.states.into_vec());
+            }
+            return serve_json(req, &hours);
+        }
+
         let mut signals = json::Signals::default();
         self.db
             .lock()
@@ -89,3 +334,158 @@ impl Service {
         serve_json(req, &signals)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::web::tests::Server;
+    use db::testutil;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn declare_signal() {
+        testutil::init();
+        let mut permissions = db::Permissions::new();
+        permissions.update_signals = true;
+        let s = Server::new(Some(permissions));
+        let cli = reqwest::Client::new();
+        let type_url = format!(
+            "{}/api/signal-types/ee66270f-d9c6-4819-8b33-9720d4cbca6b",
+            &s.base_url
+        );
+        let signal_url = format!(
+            "{}/api/signals/1b3889c0-a59f-400d-a24c-94ebeb19cc3a",
+            &s.base_url
+        );
+        let states = json!([
+            { "value": 1, "name": "closed", "color": "#888888" },
+            { "value": 2, "name": "open", "color": "red", "motion": true },
+        ]);
+        let resp = cli
+            .put(&type_url)
+            .json(&json!({ "name": "door", "states": states }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+
+        // An unknown camera is rejected.
+        let resp = cli
+            .put(&signal_url)
+            .json(&json!({
+                "shortName": "front door",
+                "type": "ee66270f-d9c6-4819-8b33-9720d4cbca6b",
+                "cameras": { "00000000-0000-0000-0000-000000000000": "direct" },
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let resp = cli
+            .put(&signal_url)
+            .json(&json!({
+                "shortName": "front door",
+                "type": "ee66270f-d9c6-4819-8b33-9720d4cbca6b",
+                "cameras": { s.db.test_camera_uuid.to_string(): "direct" },
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let id = resp.json::<serde_json::Value>().await.unwrap()["id"].clone();
+        {
+            let l = s.db.db.lock();
+            let signal = &l.signals_by_id()[&(id.as_u64().unwrap() as u32)];
+            assert_eq!(signal.config.short_name, "front door");
+            assert_eq!(
+                l.signal_types_by_uuid()[&signal.type_].config.values[&2].name,
+                "open"
+            );
+        }
+
+        // The type can't be deleted while in use.
+        let resp = cli.delete(&type_url).json(&json!({})).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::PRECONDITION_FAILED);
+        let resp = cli
+            .delete(&signal_url)
+            .json(&json!({}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        let resp = cli.delete(&type_url).json(&json!({})).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        assert!(s.db.db.lock().signal_types_by_uuid().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hourly_summary() {
+        testutil::init();
+        let mut permissions = db::Permissions::new();
+        permissions.update_signals = true;
+        let s = Server::new(Some(permissions));
+        let cli = reqwest::Client::new();
+        let resp = cli
+            .put(format!(
+                "{}/api/signal-types/ee66270f-d9c6-4819-8b33-9720d4cbca6b",
+                &s.base_url
+            ))
+            .json(&json!({ "name": "motion", "states": [{ "value": 1, "name": "on" }] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        let resp = cli
+            .put(format!(
+                "{}/api/signals/1b3889c0-a59f-400d-a24c-94ebeb19cc3a",
+                &s.base_url
+            ))
+            .json(&json!({
+                "shortName": "driveway",
+                "type": "ee66270f-d9c6-4819-8b33-9720d4cbca6b",
+                "cameras": {},
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let id = resp.json::<serde_json::Value>().await.unwrap()["id"].clone();
+
+        // On from 2019-04-26T11:59:00Z to 12:01:00Z, straddling an hour boundary.
+        let signals_url = format!("{}/api/signals", &s.base_url);
+        let resp = cli
+            .post(&signals_url)
+            .json(&json!({
+                "signalIds": [id],
+                "states": [1],
+                "start": { "base": "epoch", "rel90k": 140067462600000i64 },
+                "end": { "base": "epoch", "rel90k": 140067473400000i64 },
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        let resp = cli
+            .get(format!("{signals_url}?summary=hour"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            resp.json::<serde_json::Value>().await.unwrap(),
+            json!({
+                "startTimes90k": [140067144000000i64, 140067468000000i64],
+                "signalIds": [id, id],
+                "states": [[5400000], [5400000]],
+            })
+        );
+
+        let resp = cli
+            .get(format!("{signals_url}?summary=minute"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
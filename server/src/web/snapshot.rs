@@ -0,0 +1,71 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! `/snapshot.jpg` handling: a stream's latest key frame, decoded by [`crate::decoder`].
+
+use base::{bail, err};
+use http::header::{self, HeaderValue};
+use http::Response;
+use uuid::Uuid;
+
+use super::{Caller, ResponseResult, Service};
+
+impl Service {
+    pub(super) async fn stream_snapshot_jpeg(
+        &self,
+        caller: Caller,
+        uuid: Uuid,
+        stream_type: db::StreamType,
+    ) -> ResponseResult {
+        if !caller.permissions.view_live {
+            bail!(PermissionDenied, msg("view_live required"));
+        }
+        let stream_id = {
+            let db = self.db.lock();
+            let camera = db
+                .get_camera(uuid)
+                .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+            camera.streams[stream_type.index()]
+                .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{stream_type}")))?
+        };
+        let decoded = self
+            .decoder
+            .latest_key_frame(&self.db, self.dirs_by_stream_id(), stream_id)
+            .await?;
+        Ok(Response::builder()
+            .header(header::CONTENT_TYPE, HeaderValue::from_static("image/jpeg"))
+            .header("X-Time-90k", decoded.time.0.to_string())
+            .body(decoded.jpeg.into())
+            .expect("hardcoded head should be valid"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::web::tests::Server;
+    use db::testutil;
+
+    #[tokio::test]
+    async fn snapshot_without_frames() {
+        testutil::init();
+        let s = Server::new(Some(db::Permissions::new()));
+        let cli = reqwest::Client::new();
+        let url = format!(
+            "{}/api/cameras/{}/main/snapshot.jpg",
+            &s.base_url, s.db.test_camera_uuid
+        );
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+
+        let mut permissions = db::Permissions::new();
+        permissions.view_live = true;
+        let s = Server::new(Some(permissions));
+        let url = format!(
+            "{}/api/cameras/{}/main/snapshot.jpg",
+            &s.base_url, s.db.test_camera_uuid
+        );
+        let resp = cli.get(&url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
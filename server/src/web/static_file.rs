@@ -4,20 +4,31 @@
 
 //! Static file serving.
 
+use std::io::Read;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use base::{bail, err, ErrorKind, ResultExt};
+use base::{bail, err, Error, ErrorKind, ResultExt};
 use http::{header, HeaderValue, Request};
 use http_serve::dir::FsDir;
 use tracing::warn;
 
 use crate::cmds::run::config::UiDir;
 
-use super::{ResponseResult, Service};
+use super::{serve_json, ResponseResult, Service};
+use crate::json;
+
+/// Returns an identifier of a UI build, given its uncompressed `index.html`.
+///
+/// `index.html` refers to the other files by content-hashed names, so this changes whenever the
+/// UI does. This lets the UI notice that the server has been upgraded and prompt a reload.
+pub(crate) fn ui_version(index_html: &[u8]) -> String {
+    blake3::hash(index_html).to_hex()[..16].to_owned()
+}
 
 pub enum Ui {
     None,
-    FromFilesystem(Arc<FsDir>),
+    FromFilesystem(Arc<FsDir>, PathBuf),
     #[cfg(feature = "bundled-ui")]
     Bundled(&'static crate::bundled_ui::Ui),
 }
@@ -34,7 +45,7 @@ impl Ui {
                     );
                     Self::None
                 }
-                Ok(d) => Self::FromFilesystem(d),
+                Ok(fs_dir) => Self::FromFilesystem(fs_dir, d.clone()),
             },
             #[cfg(feature = "bundled-ui")]
             UiDir::Bundled(_) => Self::Bundled(crate::bundled_ui::Ui::get()),
@@ -58,7 +69,7 @@ impl Ui {
                 NotFound,
                 msg("ui not configured or missing; no static files available")
             ),
-            Ui::FromFilesystem(d) => {
+            Ui::FromFilesystem(d, _) => {
                 let node = d.clone().get(path, req.headers()).await.map_err(|e| {
                     if e.kind() == std::io::ErrorKind::NotFound {
                         err!(NotFound, msg("static file not found"))
@@ -87,11 +98,57 @@ impl Ui {
     }
 }
 
+impl Ui {
+    /// Returns the [`ui_version`] of the UI being served, if any.
+    fn version(&self) -> Result<Option<String>, Error> {
+        match self {
+            Ui::None => Ok(None),
+            Ui::FromFilesystem(_, d) => {
+                // The directory may be updated in place, so check the current `index.html`.
+                let path = d.join("index.html");
+                let data = match std::fs::read(&path) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        let gz_path = d.join("index.html.gz");
+                        let gz = std::fs::read(&gz_path)
+                            .map_err(|e| err!(e, msg("unable to read {}", gz_path.display())))?;
+                        let mut data = Vec::new();
+                        flate2::read::GzDecoder::new(&gz[..])
+                            .read_to_end(&mut data)
+                            .map_err(|e| {
+                                err!(e, msg("unable to decompress {}", gz_path.display()))
+                            })?;
+                        data
+                    }
+                    r => r.map_err(|e| err!(e, msg("unable to read {}", path.display())))?,
+                };
+                Ok(Some(ui_version(&data)))
+            }
+            #[cfg(feature = "bundled-ui")]
+            Ui::Bundled(ui) => Ok(Some(ui.version().to_owned())),
+        }
+    }
+}
+
 impl Service {
+    /// Serves `GET /api/ui-version`.
+    pub(super) fn ui_version(&self, req: &Request<hyper::body::Incoming>) -> ResponseResult {
+        let Some(version) = self.ui.version()? else {
+            bail!(NotFound, msg("no ui configured"));
+        };
+        serve_json(req, &json::UiVersion { version })
+    }
+
     /// Serves a static file if possible.
     pub(super) async fn static_file(&self, req: Request<hyper::body::Incoming>) -> ResponseResult {
-        let Some(static_req) = StaticFileRequest::parse(req.uri().path()) else {
-            bail!(NotFound, msg("static file not found"));
+        let path = req.uri().path();
+        let static_req = match StaticFileRequest::parse(path) {
+            Some(r) => r,
+            None if self.ui_spa_fallback && is_ui_route(path) => StaticFileRequest {
+                path: "index.html",
+                immutable: false,
+                mime: "text/html",
+            },
+            None => bail!(NotFound, msg("static file not found")),
         };
         let cache_control = if static_req.immutable {
             // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control#Caching_static_assets
@@ -105,6 +162,12 @@ impl Service {
     }
 }
 
+/// Returns true if `path` looks like a route within the UI rather than a file: its last
+/// segment has no extension.
+fn is_ui_route(path: &str) -> bool {
+    path.starts_with('/') && !path.rsplit('/').next().unwrap_or("").contains('.')
+}
+
 #[derive(Debug, Eq, PartialEq)]
 struct StaticFileRequest<'a> {
     path: &'a str,
@@ -162,6 +225,21 @@ mod tests {
 
     use super::StaticFileRequest;
 
+    #[test]
+    fn ui_version() {
+        let v = super::ui_version(b"<!doctype html>");
+        assert_eq!(v.len(), 16);
+        assert_ne!(v, super::ui_version(b"<!doctype html>\n"));
+    }
+
+    #[test]
+    fn is_ui_route() {
+        assert!(super::is_ui_route("/live"));
+        assert!(super::is_ui_route("/cameras/driveway/"));
+        assert!(!super::is_ui_route("/favicon.ico"));
+        assert!(!super::is_ui_route("/assets/index-abc123.js"));
+    }
+
     #[test]
     fn static_file() {
         testutil::init();
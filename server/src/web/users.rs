@@ -58,7 +58,7 @@ impl Service {
         if !caller.permissions.admin_users {
             bail!(Unauthenticated, msg("must have admin_users permission"));
         }
-        let (parts, b) = into_json_body(req).await?;
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
         let mut r: json::PutUsers = parse_json_body(&b)?;
         require_csrf_if_session(&caller, r.csrf)?;
         let username = r
@@ -73,9 +73,20 @@ impl Service {
         if let Some(preferences) = r.user.preferences.take() {
             change.config.preferences = preferences;
         }
-        if let Some(permissions) = r.user.permissions.take() {
-            change.permissions = permissions.into();
+        if let Some(e) = r.user.expiration_time_sec.take() {
+            change.config.expiration_time_sec = e;
         }
+        if let Some(q) = r.user.download_quota_bytes_per_day.take() {
+            change.config.download_quota_bytes_per_day = q;
+        }
+        if let Some(t) = r.user.tag_downloads.take() {
+            change.config.tag_downloads = t;
+        }
+        set_permissions(
+            &mut change,
+            r.user.permissions.take(),
+            r.user.permission_template_id.take(),
+        )?;
         if r.user != Default::default() {
             bail!(Unimplemented, msg("unsupported user fields: {r:#?}"));
         }
@@ -125,7 +136,7 @@ impl Service {
         if !caller.permissions.admin_users {
             bail!(Unauthenticated, msg("must have admin_users permission"));
         }
-        let (_parts, b) = into_json_body(req).await?;
+        let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
         let r: json::DeleteUser = parse_json_body(&b)?;
         require_csrf_if_session(&caller, r.csrf)?;
         let mut l = self.db.lock();
@@ -140,7 +151,7 @@ impl Service {
         id: i32,
     ) -> ResponseResult {
         require_same_or_admin(&caller, id)?;
-        let (_parts, b) = into_json_body(req).await?;
+        let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
         let r: json::PostUser = parse_json_body(&b)?;
         let mut db = self.db.lock();
         let user = db
@@ -160,6 +171,18 @@ impl Service {
             if matches!(precondition.disabled.take(), Some(d) if d != user.config.disabled) {
                 bail!(FailedPrecondition, msg("disabled mismatch"));
             }
+            if matches!(precondition.expiration_time_sec.take(), Some(e) if e != user.config.expiration_time_sec)
+            {
+                bail!(FailedPrecondition, msg("expiration time mismatch"));
+            }
+            if matches!(precondition.download_quota_bytes_per_day.take(), Some(q) if q != user.config.download_quota_bytes_per_day)
+            {
+                bail!(FailedPrecondition, msg("download quota mismatch"));
+            }
+            if matches!(precondition.tag_downloads.take(), Some(t) if t != user.config.tag_downloads)
+            {
+                bail!(FailedPrecondition, msg("tag downloads mismatch"));
+            }
             if matches!(precondition.username.take(), Some(n) if n != user.username) {
                 bail!(FailedPrecondition, msg("username mismatch"));
             }
@@ -177,6 +200,10 @@ impl Service {
                     bail!(FailedPrecondition, msg("permissions mismatch"));
                 }
             }
+            if matches!(precondition.permission_template_id.take(), Some(t) if t != user.permission_template_id)
+            {
+                bail!(FailedPrecondition, msg("permission template mismatch"));
+            }
 
             // Safety valve in case something is added to UserSubset and forgotten here.
             if precondition != Default::default() {
@@ -206,12 +233,23 @@ impl Service {
             if let Some(d) = update.disabled.take() {
                 change.config.disabled = d;
             }
+            if let Some(e) = update.expiration_time_sec.take() {
+                change.config.expiration_time_sec = e;
+            }
+            if let Some(q) = update.download_quota_bytes_per_day.take() {
+                change.config.download_quota_bytes_per_day = q;
+            }
+            if let Some(t) = update.tag_downloads.take() {
+                change.config.tag_downloads = t;
+            }
             if let Some(n) = update.username.take() {
                 change.username = n.to_string();
             }
-            if let Some(permissions) = update.permissions.take() {
-                change.permissions = permissions.into();
-            }
+            set_permissions(
+                &mut change,
+                update.permissions.take(),
+                update.permission_template_id.take(),
+            )?;
 
             // Safety valve in case something is added to UserSubset and forgotten here.
             if update != Default::default() {
@@ -225,7 +263,31 @@ impl Service {
     }
 }
 
-fn require_same_or_admin(caller: &Caller, id: i32) -> Result<(), base::Error> {
+/// Applies the `permissions` and `permissionTemplateId` fields of a user update.
+///
+/// Setting permissions directly unassigns any template.
+fn set_permissions(
+    change: &mut db::UserChange,
+    permissions: Option<json::Permissions>,
+    template_id: Option<Option<i32>>,
+) -> Result<(), base::Error> {
+    if let Some(permissions) = permissions {
+        if matches!(template_id, Some(Some(_))) {
+            bail!(
+                InvalidArgument,
+                msg("permissions and permissionTemplateId are mutually exclusive")
+            );
+        }
+        change.permissions = permissions.into();
+        change.permission_template_id = None;
+    }
+    if let Some(t) = template_id {
+        change.permission_template_id = t;
+    }
+    Ok(())
+}
+
+pub(super) fn require_same_or_admin(caller: &Caller, id: i32) -> Result<(), base::Error> {
     if caller.user.as_ref().map(|u| u.id) != Some(id) && !caller.permissions.admin_users {
         bail!(
             Unauthenticated,
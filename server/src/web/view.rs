@@ -2,183 +2,347 @@
 // Copyright (C) 2021 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
 // SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
 
-//! `/view.mp4` and `/view.m4s` handling.
+//! `/view.mp4`, `/view.m4s`, and `/view.mkv` handling.
 
-use base::{bail, err};
+use base::{bail, clock::Clocks, err};
 use db::recording::{self, rescale};
-use http::{Request, StatusCode};
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use http_serve::Entity as _;
 use nom::bytes::complete::{tag, take_while1};
 use nom::combinator::{all_consuming, map, map_res, opt};
 use nom::sequence::{preceded, tuple};
 use nom::IResult;
 use std::borrow::Borrow;
 use std::cmp;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::ops::Range;
 use std::str::FromStr;
+use std::sync::Arc;
 use tracing::trace;
 use url::form_urlencoded;
 use uuid::Uuid;
 
+use crate::body::Body;
+use crate::mkv;
 use crate::mp4;
 use crate::web::plain_response;
 
-use super::{Caller, ResponseResult, Service};
+use super::{activity, CacheControl, Caller, Service};
+
+/// The minimum number of segments in a `.mp4` for which indexes are built ahead of time, in
+/// parallel, rather than lazily as the response is served.
+const PREBUILD_MIN_SEGMENTS: usize = 16;
+
+/// The maximum number of index builds queued at once for a single `.mp4`, so they don't crowd
+/// out other reads from the sample file dir. The dir's reader threads further bound how many run
+/// at once.
+const PREBUILD_MAX_QUEUED: usize = 4;
+
+/// The container format to serve.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Format {
+    Mp4(mp4::Type),
+    Mkv,
+}
+
+/// A builder for either container format.
+enum Builder {
+    Mp4(mp4::FileBuilder),
+    Mkv(mkv::FileBuilder),
+}
+
+impl Builder {
+    fn reserve(&mut self, additional: usize) {
+        match self {
+            Builder::Mp4(b) => b.reserve(additional),
+            Builder::Mkv(b) => b.reserve(additional),
+        }
+    }
+
+    fn append(
+        &mut self,
+        db: &db::LockedDatabase,
+        row: &db::ListRecordingsRow,
+        rel_media_range_90k: Range<i32>,
+        start_at_key: bool,
+    ) -> Result<(), base::Error> {
+        match self {
+            Builder::Mp4(b) => b.append(db, row, rel_media_range_90k, start_at_key),
+            Builder::Mkv(b) => b.append(db, row, rel_media_range_90k, start_at_key),
+        }
+    }
+
+    fn set_filename(&mut self, filename: &str) -> Result<(), base::Error> {
+        match self {
+            Builder::Mp4(b) => b.set_filename(filename),
+            Builder::Mkv(b) => b.set_filename(filename),
+        }
+    }
+}
 
 impl Service {
-    pub(super) fn stream_view_mp4(
+    /// Serves a `.mp4`, `.m4s`, or `.mkv` of the requested segments.
+    ///
+    /// Returns the cache policy along with the response: a segment of a growing recording may
+    /// cover more frames or move as the recording progresses, so it can't be cached like
+    /// completed recordings.
+    pub(super) fn stream_view(
         &self,
         req: &Request<::hyper::body::Incoming>,
         caller: Caller,
         uuid: Uuid,
         stream_type: db::StreamType,
-        mp4_type: mp4::Type,
+        format: Format,
         debug: bool,
-    ) -> ResponseResult {
-        if !caller.permissions.view_video {
-            bail!(PermissionDenied, msg("view_video required"));
+    ) -> Result<(CacheControl, Response<Body>), base::Error> {
+        if !caller.permissions.download_recordings {
+            bail!(PermissionDenied, msg("download_recordings required"));
         }
-        let (stream_id, camera_name);
+        let (stream_id, camera_id, camera_name);
 
         // False positive: on Rust 1.78.0, clippy erroneously suggests calling `clone_from` on the
         // uninitialized `camera_name`.
         // Apparently fixed in rustc 1.80.0-nightly (ada5e2c7b 2024-05-31).
         #[allow(clippy::assigning_clones)]
         {
-            let db = self.db.lock();
-            let camera = db
+            let index = self.db.read_index();
+            let camera = index
                 .get_camera(uuid)
                 .ok_or_else(|| err!(NotFound, msg("no such camera {uuid}")))?;
+            camera_id = camera.id;
             camera_name = camera.short_name.clone();
             stream_id = camera.streams[stream_type.index()]
                 .ok_or_else(|| err!(NotFound, msg("no such stream {uuid}/{stream_type}")))?;
         };
+        let dirs_by_stream_id = self.dirs_by_stream_id();
+        if dirs_by_stream_id
+            .get(&stream_id)
+            .is_some_and(|d| !d.admit_playback())
+        {
+            bail!(
+                ResourceExhausted,
+                msg("sample file dir for {uuid}/{stream_type} is overloaded; try again later")
+            );
+        }
         let mut start_time_for_filename = None;
-        let mut builder = mp4::FileBuilder::new(mp4_type);
+        let mut growing = false;
+        let (mut ts, mut ts_camera, mut ts_signals) = (false, false, false);
+
+        // The wall time span of the included segments, for subtitle signal labels.
+        let mut wall_range: Option<Range<recording::Time>> = None;
+
+        // The most recent recording to include, which is appended once it's known whether
+        // another follows.
+        let mut pending: Option<(db::ListRecordingsRow, Range<i32>)> = None;
+        let mut builder = match format {
+            Format::Mp4(t) => {
+                let mut b = mp4::FileBuilder::new(t);
+                b.set_index_cache(self.index_cache.clone());
+                Builder::Mp4(b)
+            }
+            Format::Mkv => Builder::Mkv(mkv::FileBuilder::new()),
+        };
         if let Some(q) = req.uri().query() {
             for (key, value) in form_urlencoded::parse(q.as_bytes()) {
                 let (key, value) = (key.borrow(), value.borrow());
-                match key {
-                    "s" => {
-                        let s = Segments::from_str(value).map_err(|()| {
-                            err!(InvalidArgument, msg("invalid s parameter: {value}"))
-                        })?;
-                        trace!("stream_view_mp4: appending s={:?}", s);
-                        let mut est_segments = usize::try_from(s.ids.end - s.ids.start).unwrap();
-                        if let Some(end) = s.end_time {
-                            // There should be roughly ceil((end - start) /
-                            // desired_recording_duration) recordings in the desired timespan if
-                            // there are no gaps or overlap, possibly another for misalignment of
-                            // the requested timespan with the rotate offset and another because
-                            // rotation only happens at key frames.
-                            let ceil_durations = (end - s.start_time
-                                + recording::DESIRED_RECORDING_WALL_DURATION
-                                - 1)
-                                / recording::DESIRED_RECORDING_WALL_DURATION;
-                            est_segments = cmp::min(est_segments, (ceil_durations + 2) as usize);
-                        }
-                        builder.reserve(est_segments);
-                        let db = self.db.lock();
-                        let mut prev = None; // previous recording id
-                        let mut cur_off = 0;
-                        db.list_recordings_by_id(stream_id, s.ids.clone(), &mut |r| {
-                            let recording_id = r.id.recording();
-
-                            if let Some(o) = s.open_id {
-                                if r.open_id != o {
-                                    bail!(
-                                        NotFound,
-                                        msg(
-                                            "recording {} has open id {}, requested {}",
-                                            r.id,
-                                            r.open_id,
-                                            o,
-                                        ),
-                                    );
-                                }
+                let segments = match key {
+                    // Disjoint ranges may be given as several `s` parameters or as one with
+                    // comma-separated ranges.
+                    "s" => value
+                        .split(',')
+                        .map(Segments::from_str)
+                        .collect::<Result<Vec<_>, ()>>()
+                        .map_err(|()| err!(InvalidArgument, msg("invalid s parameter: {value}")))?,
+                    "run" => {
+                        // The same syntax as `s`, but with a run's start id in place of a range
+                        // of recording ids.
+                        let mut s = Segments::from_str(value)
+                            .ok()
+                            .filter(|s| s.ids.len() == 1)
+                            .ok_or_else(|| {
+                                err!(InvalidArgument, msg("invalid run parameter: {value}"))
+                            })?;
+                        s.ids = self.db.lock().run_ids(stream_id, s.ids.start)?;
+                        vec![s]
+                    }
+                    "ts" => {
+                        ts = value == "true";
+                        match &mut builder {
+                            Builder::Mp4(b) => b.include_timestamp_subtitle_track(ts)?,
+                            Builder::Mkv(_) if ts => {
+                                bail!(InvalidArgument, msg("ts isn't supported for .mkv"))
                             }
+                            Builder::Mkv(_) => {}
+                        }
+                        continue;
+                    }
+                    "tsCamera" => {
+                        ts_camera = value == "true";
+                        continue;
+                    }
+                    "tsSignals" => {
+                        ts_signals = value == "true";
+                        continue;
+                    }
+                    _ => bail!(InvalidArgument, msg("parameter {key} not understood")),
+                };
+                for s in segments {
+                    trace!("stream_view: appending s={:?}", s);
+                    let mut est_segments = usize::try_from(s.ids.end - s.ids.start).unwrap();
+                    if let Some(end) = s.end_time {
+                        // There should be roughly ceil((end - start) /
+                        // desired_recording_duration) recordings in the desired timespan if
+                        // there are no gaps or overlap, possibly another for misalignment of
+                        // the requested timespan with the rotate offset and another because
+                        // rotation only happens at key frames.
+                        let ceil_durations =
+                            (end - s.start_time + recording::DESIRED_RECORDING_WALL_DURATION - 1)
+                                / recording::DESIRED_RECORDING_WALL_DURATION;
+                        est_segments = cmp::min(est_segments, (ceil_durations + 2) as usize);
+                    }
+                    builder.reserve(est_segments);
+                    let db = self.db.lock();
+                    let mut prev = None; // previous recording id
+                    let mut cur_off = 0;
+                    db.list_recordings_by_id(stream_id, s.ids.clone(), &mut |r| {
+                        let recording_id = r.id.recording();
 
-                            // Check for missing recordings.
-                            match prev {
-                                None if recording_id == s.ids.start => {}
-                                None => bail!(
+                        if let Some(o) = s.open_id {
+                            if r.open_id != o {
+                                bail!(
                                     NotFound,
-                                    msg("no such recording {}/{}", stream_id, s.ids.start),
-                                ),
-                                Some(id) if r.id.recording() != id + 1 => {
-                                    bail!(
-                                        NotFound,
-                                        msg("no such recording {}/{}", stream_id, id + 1)
-                                    );
-                                }
-                                _ => {}
-                            };
-                            prev = Some(recording_id);
-
-                            // Add a segment for the relevant part of the recording, if any.
-                            // Note all calculations here are in wall times / wall durations.
-                            let end_time = s.end_time.unwrap_or(i64::MAX);
-                            let wd = i64::from(r.wall_duration_90k);
-                            if s.start_time <= cur_off + wd && cur_off < end_time {
-                                let start = cmp::max(0, s.start_time - cur_off);
-                                let end = cmp::min(wd, end_time - cur_off);
-                                let wr = i32::try_from(start).unwrap()..i32::try_from(end).unwrap();
-                                trace!(
-                                    "...appending recording {} with wall duration {:?} \
-                                       (out of total {})",
-                                    r.id,
-                                    wr,
-                                    wd
+                                    msg(
+                                        "recording {} has open id {}, requested {}",
+                                        r.id,
+                                        r.open_id,
+                                        o,
+                                    ),
                                 );
-                                if start_time_for_filename.is_none() {
-                                    start_time_for_filename =
-                                        Some(r.start + recording::Duration(start));
-                                }
-                                let mr =
-                                    rescale(wr.start, r.wall_duration_90k, r.media_duration_90k)
-                                        ..rescale(
-                                            wr.end,
-                                            r.wall_duration_90k,
-                                            r.media_duration_90k,
-                                        );
-                                builder.append(&db, &r, mr, true)?;
-                            } else {
-                                trace!("...skipping recording {} wall dur {}", r.id, wd);
                             }
-                            cur_off += wd;
-                            Ok(())
-                        })?;
+                        }
 
                         // Check for missing recordings.
                         match prev {
-                            Some(id) if s.ids.end != id + 1 => {
-                                bail!(
-                                    NotFound,
-                                    msg("no such recording {}/{}", stream_id, s.ids.end - 1),
-                                );
-                            }
-                            None => {
-                                bail!(
-                                    NotFound,
-                                    msg("no such recording {}/{}", stream_id, s.ids.start),
-                                );
+                            None if recording_id == s.ids.start => {}
+                            None => bail!(
+                                NotFound,
+                                msg("no such recording {}/{}", stream_id, s.ids.start),
+                            ),
+                            Some(id) if r.id.recording() != id + 1 => {
+                                bail!(NotFound, msg("no such recording {}/{}", stream_id, id + 1));
                             }
                             _ => {}
                         };
-                        if let Some(end) = s.end_time {
-                            if end > cur_off {
-                                bail!(
-                                    InvalidArgument,
-                                    msg("end time {end} is beyond specified recordings"),
-                                );
+                        prev = Some(recording_id);
+
+                        // Add a segment for the relevant part of the recording, if any.
+                        // Note all calculations here are in wall times / wall durations.
+                        let end_time = s.end_time.unwrap_or(i64::MAX);
+                        let wd = i64::from(r.wall_duration_90k);
+                        if s.start_time <= cur_off + wd && cur_off < end_time {
+                            let start = cmp::max(0, s.start_time - cur_off);
+                            let end = cmp::min(wd, end_time - cur_off);
+                            let wr = i32::try_from(start).unwrap()..i32::try_from(end).unwrap();
+                            trace!(
+                                "...appending recording {} with wall duration {:?} \
+                                       (out of total {})",
+                                r.id,
+                                wr,
+                                wd
+                            );
+                            if start_time_for_filename.is_none() {
+                                start_time_for_filename =
+                                    Some(r.start + recording::Duration(start));
+                            }
+                            let wall = r.start + recording::Duration(start)
+                                ..r.start + recording::Duration(end);
+                            wall_range = Some(match wall_range.take() {
+                                None => wall,
+                                Some(w) => cmp::min(w.start, wall.start)..cmp::max(w.end, wall.end),
+                            });
+                            let mr = rescale(wr.start, r.wall_duration_90k, r.media_duration_90k)
+                                ..rescale(wr.end, r.wall_duration_90k, r.media_duration_90k);
+                            growing |= (r.flags & db::RecordingFlags::Growing as i32) != 0;
+                            if let Some((row, mr)) = pending.take() {
+                                append(&mut builder, &db, &row, mr, true)?;
                             }
+                            pending = Some((r, mr));
+                        } else {
+                            trace!("...skipping recording {} wall dur {}", r.id, wd);
+                        }
+                        cur_off += wd;
+                        Ok(())
+                    })?;
+
+                    // Check for missing recordings.
+                    match prev {
+                        Some(id) if s.ids.end != id + 1 => {
+                            bail!(
+                                NotFound,
+                                msg("no such recording {}/{}", stream_id, s.ids.end - 1),
+                            );
+                        }
+                        None => {
+                            bail!(
+                                NotFound,
+                                msg("no such recording {}/{}", stream_id, s.ids.start),
+                            );
+                        }
+                        _ => {}
+                    };
+                    if let Some(end) = s.end_time {
+                        if end > cur_off {
+                            bail!(
+                                InvalidArgument,
+                                msg("end time {end} is beyond specified recordings"),
+                            );
                         }
                     }
-                    "ts" => builder.include_timestamp_subtitle_track(value == "true")?,
-                    _ => bail!(InvalidArgument, msg("parameter {key} not understood")),
                 }
             }
         }
+        if let Some((row, mr)) = pending {
+            append(&mut builder, &self.db.lock(), &row, mr, false)?;
+        }
+        if (ts_camera || ts_signals) && !ts {
+            bail!(
+                InvalidArgument,
+                msg("tsCamera and tsSignals require ts=true")
+            );
+        }
+
+        // Tag `.mp4` downloads with the username for users who require it. Media segments can't have
+        // subtitles, but they're meant for playback within the UI rather than export. `.mkv`s
+        // have no subtitle track, so they're refused instead.
+        let user_id = caller.user.as_ref().map(|u| u.id);
+        let tag = user_id
+            .and_then(|id| {
+                let db = self.db.lock();
+                let u = db.users_by_id().get(&id)?;
+                u.config.tag_downloads.then(|| u.username.clone())
+            })
+            .filter(|_| format != Format::Mp4(mp4::Type::MediaSegment));
+        if let Builder::Mp4(b) = &mut builder {
+            if tag.is_some() {
+                b.include_timestamp_subtitle_track(true)?;
+            }
+            if ts_camera || ts_signals || tag.is_some() {
+                b.set_subtitle_labels(mp4::SubtitleLabels {
+                    camera_name: ts_camera.then(|| camera_name.clone()),
+                    user: tag,
+                    signals: match (ts_signals, wall_range) {
+                        (true, Some(w)) => subtitle_signals(&self.db.lock(), camera_id, w),
+                        _ => Vec::new(),
+                    },
+                })?;
+            }
+        } else if tag.is_some() {
+            bail!(
+                PermissionDenied,
+                msg("tagged downloads are only available as .mp4")
+            );
+        }
         if let Some(start) = start_time_for_filename {
             let tm = time::at(time::Timespec {
                 sec: start.unix_seconds(),
@@ -189,10 +353,10 @@ impl Service {
             } else {
                 "sub"
             };
-            let suffix = if mp4_type == mp4::Type::Normal {
-                "mp4"
-            } else {
-                "m4s"
+            let suffix = match format {
+                Format::Mp4(mp4::Type::MediaSegment) => "m4s",
+                Format::Mp4(_) => "mp4",
+                Format::Mkv => "mkv",
             };
             builder.set_filename(&format!(
                 "{}-{}-{}.{}",
@@ -202,12 +366,158 @@ impl Service {
                 suffix
             ))?;
         }
-        let mp4 = builder.build(self.db.clone(), self.dirs_by_stream_id.clone())?;
-        if debug {
-            return Ok(plain_response(StatusCode::OK, format!("{mp4:#?}")));
+        let cache = if growing {
+            CacheControl::PrivateDynamic
+        } else {
+            CacheControl::PrivateStatic
+        };
+        let file = match builder {
+            Builder::Mp4(b) => {
+                let mp4 = b.build(self.db.clone(), dirs_by_stream_id)?;
+                if debug {
+                    return Ok((cache, plain_response(StatusCode::OK, format!("{mp4:#?}"))));
+                }
+                self.charge_download(req, user_id, mp4.len())?;
+                if format == Format::Mp4(mp4::Type::Normal)
+                    && mp4.num_segments() >= PREBUILD_MIN_SEGMENTS
+                {
+                    mp4.prebuild_indexes(PREBUILD_MAX_QUEUED);
+                }
+                let activity =
+                    self.register_activity(activity::Kind::Download, &caller, uuid, stream_type);
+                http_serve::serve(Arc::new(activity).track(mp4), req)
+            }
+            Builder::Mkv(b) => {
+                let mkv = b.build(self.db.clone(), dirs_by_stream_id)?;
+                if debug {
+                    return Ok((cache, plain_response(StatusCode::OK, format!("{mkv:#?}"))));
+                }
+                self.charge_download(req, user_id, mkv.len())?;
+                let activity =
+                    self.register_activity(activity::Kind::Download, &caller, uuid, stream_type);
+                http_serve::serve(Arc::new(activity).track(mkv), req)
+            }
+        };
+        Ok((cache, file))
+    }
+
+    /// Charges `user_id` (if any) for the bytes of a `len`-byte file that `req` will download.
+    pub(super) fn charge_download(
+        &self,
+        req: &Request<::hyper::body::Incoming>,
+        user_id: Option<i32>,
+        len: u64,
+    ) -> Result<(), base::Error> {
+        if let (Some(id), false) = (user_id, req.method() == Method::HEAD) {
+            let bytes = requested_bytes(req.headers().get(header::RANGE), len);
+            let now = self.db.clocks().realtime().sec;
+            let mut db = self.db.lock();
+            if let Some(u) = db.get_user_by_id_mut(id) {
+                u.charge_download(now, bytes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes a request for an entity of `len` bytes will return, given its
+/// `Range` header. This is an upper bound: it doesn't consider conditional requests, and
+/// overlapping ranges are counted twice.
+fn requested_bytes(range: Option<&HeaderValue>, len: u64) -> u64 {
+    let Some(ranges) = range
+        .and_then(|r| r.to_str().ok())
+        .and_then(|r| r.strip_prefix("bytes="))
+    else {
+        return len;
+    };
+    let mut total = 0u64;
+    for r in ranges.split(',') {
+        let Some((start, end)) = r.trim().split_once('-') else {
+            return len;
+        };
+        let n = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(s), Ok(e)) if s <= e => cmp::min(e.saturating_add(1), len).saturating_sub(s),
+            (Ok(s), Err(_)) if end.is_empty() => len.saturating_sub(s),
+            (Err(_), Ok(n)) if start.is_empty() => cmp::min(n, len),
+            _ => return len, // unparseable; http_serve will serve the whole entity.
+        };
+        total = total.saturating_add(n);
+    }
+    cmp::min(total, len)
+}
+
+/// Returns the signals associated with `camera_id`, with their changes within `time`.
+fn subtitle_signals(
+    db: &db::LockedDatabase,
+    camera_id: i32,
+    time: Range<recording::Time>,
+) -> Vec<mp4::SubtitleSignal> {
+    let types = db.signal_types_by_uuid();
+    let mut signals: BTreeMap<u32, mp4::SubtitleSignal> = db
+        .signals_by_id()
+        .values()
+        .filter(|s| s.config.camera_associations.contains_key(&camera_id))
+        .map(|s| {
+            let state_names = types
+                .get(&s.type_)
+                .map(|t| {
+                    t.config
+                        .values
+                        .iter()
+                        .map(|(&v, c)| (u16::from(v), c.name.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let signal = mp4::SubtitleSignal {
+                short_name: s.config.short_name.clone(),
+                state_names,
+                changes: Vec::new(),
+            };
+            (s.id, signal)
+        })
+        .collect();
+    db.list_changes_by_time(time, &mut |c| {
+        if let Some(s) = signals.get_mut(&c.signal) {
+            s.changes.push((c.when, c.state));
         }
-        Ok(http_serve::serve(mp4, req))
+    });
+    signals.into_values().collect()
+}
+
+/// Appends part of a recording to `builder`, as trimmed by [`trim_trailing_zero`].
+fn append(
+    builder: &mut Builder,
+    db: &db::LockedDatabase,
+    row: &db::ListRecordingsRow,
+    media_range_90k: Range<i32>,
+    followed: bool,
+) -> Result<(), base::Error> {
+    builder.append(
+        db,
+        row,
+        trim_trailing_zero(row, media_range_90k, followed),
+        true,
+    )
+}
+
+/// Returns the media range to append for part of a recording.
+///
+/// If `followed` (another recording will be appended after this one) and the part ends with the
+/// zero-duration frame which ends a run, trims that frame. `.mp4` files can only have such a
+/// frame at the end, and this allows stitching together disjoint ranges.
+pub(super) fn trim_trailing_zero(
+    row: &db::ListRecordingsRow,
+    mut media_range_90k: Range<i32>,
+    followed: bool,
+) -> Range<i32> {
+    if followed
+        && (row.flags & db::RecordingFlags::TrailingZero as i32) != 0
+        && media_range_90k.end == row.media_duration_90k
+        && media_range_90k.start < media_range_90k.end
+    {
+        media_range_90k.end -= 1;
     }
+    media_range_90k
 }
 
 /// Represents a single `s=` (segments) query parameter as supplied to `/view.mp4`.
@@ -283,11 +593,25 @@ mod tests {
 
     use super::Segments;
 
+    #[test]
+    fn requested_bytes() {
+        use super::requested_bytes;
+        use http::HeaderValue;
+        let r = |s| Some(HeaderValue::from_static(s));
+        assert_eq!(requested_bytes(None, 1000), 1000);
+        assert_eq!(requested_bytes(r("bytes=0-99").as_ref(), 1000), 100);
+        assert_eq!(requested_bytes(r("bytes=900-").as_ref(), 1000), 100);
+        assert_eq!(requested_bytes(r("bytes=-10").as_ref(), 1000), 10);
+        assert_eq!(requested_bytes(r("bytes=0-0, 10-19").as_ref(), 1000), 11);
+        assert_eq!(requested_bytes(r("bytes=990-2000").as_ref(), 1000), 10);
+        assert_eq!(requested_bytes(r("items=0-1").as_ref(), 1000), 1000);
+    }
+
     #[tokio::test]
     async fn view_without_segments() {
         testutil::init();
         let mut permissions = db::Permissions::new();
-        permissions.view_video = true;
+        permissions.download_recordings = true;
         let s = Server::new(Some(permissions));
         let cli = reqwest::Client::new();
         let resp = cli
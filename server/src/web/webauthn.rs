@@ -0,0 +1,412 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! WebAuthn (passkey and security key) registration and login: `/api/webauthn/*` and
+//! `/api/users/<id>/webauthn-credentials`.
+//!
+//! Each ceremony takes two requests: one for options including a fresh challenge, then one with
+//! the authenticator's response. Challenges are kept in memory only, so a restart between the
+//! two fails the ceremony. Logins create ordinary sessions, as with `/api/login`.
+
+use base::{bail, err, Error, ErrorKind, FastHashMap, ResultExt as _};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use db::auth;
+use http::{header, Method, Request, Response, StatusCode};
+use ring::rand::SecureRandom as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::json;
+use crate::webauthn;
+
+use super::{
+    into_json_body, parse_json_body, plain_response, require_csrf_if_session, serve_json, Caller,
+    ResponseResult, Service,
+};
+
+/// How long the client has to complete a ceremony after requesting options.
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The maximum number of outstanding challenges. Login options are available without
+/// authentication, so this bounds the memory an attacker can consume.
+const MAX_CHALLENGES: usize = 1024;
+
+const CHALLENGE_LEN: usize = 32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Purpose {
+    Register { user_id: i32 },
+    Login,
+}
+
+/// Outstanding challenges, each usable once.
+#[derive(Default)]
+pub(super) struct Challenges(Mutex<FastHashMap<[u8; CHALLENGE_LEN], (Purpose, Instant)>>);
+
+impl Challenges {
+    fn issue(&self, purpose: Purpose) -> Result<[u8; CHALLENGE_LEN], Error> {
+        let mut challenge = [0u8; CHALLENGE_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut challenge)
+            .map_err(|_| err!(Internal, msg("unable to generate challenge")))?;
+        let now = Instant::now();
+        let mut l = self.0.lock().unwrap();
+        l.retain(|_, (_, expiry)| *expiry > now);
+        if l.len() >= MAX_CHALLENGES {
+            bail!(
+                ResourceExhausted,
+                msg("too many outstanding WebAuthn challenges")
+            );
+        }
+        l.insert(challenge, (purpose, now + TIMEOUT));
+        Ok(challenge)
+    }
+
+    /// Consumes `challenge`, failing unless it was issued for `purpose` and hasn't expired.
+    fn take(&self, challenge: &[u8], purpose: Purpose) -> Result<(), Error> {
+        let found = <[u8; CHALLENGE_LEN]>::try_from(challenge)
+            .ok()
+            .and_then(|c| self.0.lock().unwrap().remove(&c));
+        match found {
+            Some((p, expiry)) if p == purpose && expiry > Instant::now() => Ok(()),
+            _ => bail!(
+                Unauthenticated,
+                msg("unknown or expired WebAuthn challenge")
+            ),
+        }
+    }
+}
+
+/// Returns the `Host` header and the relying party id derived from it, which is the host without
+/// any port, as with session cookies' domains.
+fn host_and_rp_id(hdrs: &http::HeaderMap) -> Result<(&str, &str), Error> {
+    let host = hdrs
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .ok_or_else(|| err!(InvalidArgument, msg("missing Host header")))?;
+    let rp_id = host
+        .split(':')
+        .next()
+        .expect("split yields at least one item");
+    Ok((host, rp_id))
+}
+
+/// Checks that the client data's origin matches the `Host` the request was sent to.
+///
+/// Either scheme is accepted, as TLS is typically terminated by a proxy. Browsers allow WebAuthn
+/// only on `https` origins and `http://localhost`, so this doesn't allow plaintext elsewhere.
+fn check_origin(origin: &str, host: &str) -> Result<(), Error> {
+    if origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        != Some(host)
+    {
+        bail!(
+            Unauthenticated,
+            msg("WebAuthn origin {origin:?} doesn't match Host {host:?}")
+        );
+    }
+    Ok(())
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, Error> {
+    URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|_| err!(InvalidArgument, msg("{field} isn't valid base64url")))
+}
+
+/// Returns the WebAuthn user handle for a user, which is the big-endian user id.
+fn user_handle(user_id: i32) -> [u8; 4] {
+    user_id.to_be_bytes()
+}
+
+fn descriptor(c: &db::WebAuthnCredential) -> json::WebAuthnCredentialDescriptor {
+    json::WebAuthnCredentialDescriptor {
+        type_: "public-key",
+        id: URL_SAFE_NO_PAD.encode(&c.credential_id),
+    }
+}
+
+impl Service {
+    pub(super) async fn webauthn_register_options(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+    ) -> ResponseResult {
+        if *req.method() != Method::POST {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "POST expected",
+            ));
+        }
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::WebAuthnRegisterOptionsRequest = parse_json_body(&b)?;
+        require_csrf_if_session(&caller, r.csrf)?;
+        let Some(user) = caller.user.as_ref() else {
+            bail!(
+                Unauthenticated,
+                msg("must be logged in as a user to register a WebAuthn credential")
+            );
+        };
+        let (_, rp_id) = host_and_rp_id(&parts.headers)?;
+        let challenge = self
+            .webauthn_challenges
+            .issue(Purpose::Register { user_id: user.id })?;
+        let l = self.db.lock();
+        let exclude_credentials = l
+            .webauthn_credentials_by_id()
+            .values()
+            .filter(|c| c.user_id == user.id)
+            .map(descriptor)
+            .collect();
+        serve_json(
+            &parts,
+            &json::WebAuthnCreationOptions {
+                challenge: URL_SAFE_NO_PAD.encode(challenge),
+                rp: json::WebAuthnRelyingParty {
+                    id: rp_id,
+                    name: "Moonfire NVR",
+                },
+                user: json::WebAuthnUserEntity {
+                    id: URL_SAFE_NO_PAD.encode(user_handle(user.id)),
+                    name: &user.name,
+                    display_name: &user.name,
+                },
+                pub_key_cred_params: webauthn::ALGORITHMS
+                    .iter()
+                    .map(|&alg| json::WebAuthnCredentialParameters {
+                        type_: "public-key",
+                        alg,
+                    })
+                    .collect(),
+                timeout: TIMEOUT.as_millis() as u64,
+                exclude_credentials,
+                authenticator_selection: json::WebAuthnAuthenticatorSelection {
+                    resident_key: "preferred",
+                    user_verification: "preferred",
+                },
+            },
+        )
+    }
+
+    pub(super) async fn webauthn_register(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+    ) -> ResponseResult {
+        if *req.method() != Method::POST {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "POST expected",
+            ));
+        }
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::WebAuthnRegisterRequest = parse_json_body(&b)?;
+        require_csrf_if_session(&caller, r.csrf)?;
+        let Some(user) = caller.user.as_ref() else {
+            bail!(
+                Unauthenticated,
+                msg("must be logged in as a user to register a WebAuthn credential")
+            );
+        };
+        let (host, rp_id) = host_and_rp_id(&parts.headers)?;
+        let client_data_json = decode("clientDataJSON", r.client_data_json)?;
+        let client_data = webauthn::parse_client_data(&client_data_json, "webauthn.create")?;
+        check_origin(&client_data.origin, host)?;
+        self.webauthn_challenges.take(
+            &client_data.challenge,
+            Purpose::Register { user_id: user.id },
+        )?;
+        let c = webauthn::parse_attestation_object(
+            &decode("attestationObject", r.attestation_object)?,
+            rp_id,
+        )?;
+        let now_sec = self.db.clocks().realtime().sec;
+        let mut l = self.db.lock();
+        let id = l
+            .add_webauthn_credential(user.id, c.id, c.public_key, c.sign_count, r.name, now_sec)?
+            .id;
+        serve_json(&parts, &json::PutUsersResponse { id })
+    }
+
+    pub(super) async fn webauthn_login_options(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> ResponseResult {
+        if *req.method() != Method::POST {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "POST expected",
+            ));
+        }
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::WebAuthnLoginOptionsRequest = parse_json_body(&b)?;
+        let (_, rp_id) = host_and_rp_id(&parts.headers)?;
+        let challenge = self.webauthn_challenges.issue(Purpose::Login)?;
+
+        // An unknown username gets an empty list rather than an error, so that this doesn't
+        // reveal which users exist.
+        let l = self.db.lock();
+        let allow_credentials = match r.username.and_then(|u| l.get_user(u)) {
+            None => Vec::new(),
+            Some(u) => l
+                .webauthn_credentials_by_id()
+                .values()
+                .filter(|c| c.user_id == u.id)
+                .map(descriptor)
+                .collect(),
+        };
+        serve_json(
+            &parts,
+            &json::WebAuthnRequestOptions {
+                challenge: URL_SAFE_NO_PAD.encode(challenge),
+                rp_id,
+                allow_credentials,
+                timeout: TIMEOUT.as_millis() as u64,
+                user_verification: "preferred",
+            },
+        )
+    }
+
+    pub(super) async fn webauthn_login(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        authreq: auth::Request,
+    ) -> ResponseResult {
+        if *req.method() != Method::POST {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "POST expected",
+            ));
+        }
+        let (parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::WebAuthnLoginRequest = parse_json_body(&b)?;
+        let (host, rp_id) = host_and_rp_id(&parts.headers)?;
+        let client_data_json = decode("clientDataJSON", r.client_data_json)?;
+        let client_data = webauthn::parse_client_data(&client_data_json, "webauthn.get")?;
+        check_origin(&client_data.origin, host)?;
+        self.webauthn_challenges
+            .take(&client_data.challenge, Purpose::Login)?;
+        let credential_id = decode("id", r.id)?;
+        let authenticator_data = decode("authenticatorData", r.authenticator_data)?;
+        let signature = decode("signature", r.signature)?;
+        let user_handle_bytes = r.user_handle.map(|h| decode("userHandle", h)).transpose()?;
+
+        let flags = self.session_flags(&parts.headers);
+        let mut l = self.db.lock();
+        let c = l
+            .get_webauthn_credential(&credential_id)
+            .ok_or_else(|| err!(Unauthenticated, msg("unknown WebAuthn credential")))?;
+        if user_handle_bytes.is_some_and(|h| h != user_handle(c.user_id)) {
+            bail!(
+                Unauthenticated,
+                msg("WebAuthn user handle doesn't match credential")
+            );
+        }
+        let sign_count = webauthn::verify_assertion(
+            &c.public_key,
+            rp_id,
+            &authenticator_data,
+            &client_data_json,
+            &signature,
+        )?;
+        let (sid, _) = l
+            .login_by_webauthn(
+                authreq,
+                &credential_id,
+                sign_count,
+                Some(rp_id.as_bytes().to_vec()),
+                flags,
+            )
+            .err_kind(ErrorKind::Unauthenticated)?;
+        let cookie = self.session_cookie(sid, &parts.headers);
+        Ok(Response::builder()
+            .header(header::SET_COOKIE, cookie)
+            .status(StatusCode::NO_CONTENT)
+            .body(b""[..].into())
+            .unwrap())
+    }
+
+    pub(super) async fn user_webauthn_credentials(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        user_id: i32,
+    ) -> ResponseResult {
+        match *req.method() {
+            Method::GET | Method::HEAD => {}
+            _ => {
+                return Ok(plain_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "GET or HEAD expected",
+                ))
+            }
+        }
+        super::users::require_same_or_admin(&caller, user_id)?;
+        let l = self.db.lock();
+        if !l.users_by_id().contains_key(&user_id) {
+            bail!(NotFound, msg("can't find requested user"));
+        }
+        let credentials = l
+            .webauthn_credentials_by_id()
+            .values()
+            .filter(|c| c.user_id == user_id)
+            .map(json::WebAuthnCredential::from)
+            .collect();
+        serve_json(&req, &json::GetWebAuthnCredentialsResponse { credentials })
+    }
+
+    pub(super) async fn user_webauthn_credential(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        caller: Caller,
+        user_id: i32,
+        id: i32,
+    ) -> ResponseResult {
+        if *req.method() != Method::DELETE {
+            return Ok(plain_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "DELETE expected",
+            ));
+        }
+        super::users::require_same_or_admin(&caller, user_id)?;
+        let (_parts, b) = into_json_body(req, self.max_body_bytes).await?;
+        let r: json::DeleteUser = parse_json_body(&b)?;
+        require_csrf_if_session(&caller, r.csrf)?;
+        let mut l = self.db.lock();
+        if l.webauthn_credentials_by_id().get(&id).map(|c| c.user_id) != Some(user_id) {
+            bail!(NotFound, msg("WebAuthn credential {id} not found"));
+        }
+        l.delete_webauthn_credential(id)?;
+        Ok(plain_response(StatusCode::NO_CONTENT, &b""[..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenges() {
+        let c = Challenges::default();
+        let login = c.issue(Purpose::Login).unwrap();
+        let register = c.issue(Purpose::Register { user_id: 1 }).unwrap();
+
+        // Each challenge is only good for its purpose, and only once.
+        c.take(&register, Purpose::Register { user_id: 2 })
+            .unwrap_err();
+        c.take(&login, Purpose::Login).unwrap();
+        c.take(&login, Purpose::Login).unwrap_err();
+        c.take(&login[..16], Purpose::Login).unwrap_err();
+    }
+
+    #[test]
+    fn origin() {
+        check_origin("https://nvr.example.com", "nvr.example.com").unwrap();
+        check_origin("http://localhost:8080", "localhost:8080").unwrap();
+        check_origin("https://nvr.example.com:8443", "nvr.example.com").unwrap_err();
+        check_origin("https://evil.example.com", "nvr.example.com").unwrap_err();
+        check_origin("nvr.example.com", "nvr.example.com").unwrap_err();
+    }
+}
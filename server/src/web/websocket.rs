@@ -17,6 +17,21 @@ use tracing::Instrument;
 pub type WebSocketStream =
     tokio_tungstenite::WebSocketStream<hyper_util::rt::TokioIo<hyper::upgrade::Upgraded>>;
 
+/// The most bytes which may be buffered for sending on a connection. Sends wait for the buffer
+/// to be written, so this is reached only by a single message larger than any expected frame.
+const MAX_WRITE_BUFFER_SIZE: usize = 32 << 20;
+
+/// The largest message accepted from a client. Clients currently send none but control frames.
+const MAX_MESSAGE_SIZE: usize = 64 << 10;
+
+fn config() -> tungstenite::protocol::WebSocketConfig {
+    let mut config = tungstenite::protocol::WebSocketConfig::default();
+    config.max_write_buffer_size = MAX_WRITE_BUFFER_SIZE;
+    config.max_message_size = Some(MAX_MESSAGE_SIZE);
+    config.max_frame_size = Some(MAX_MESSAGE_SIZE);
+    config
+}
+
 /// Upgrades to WebSocket and runs the supplied stream handler in a separate tokio task.
 ///
 /// Fails on `Origin` mismatch with an HTTP-level error. If the handler returns
@@ -55,7 +70,7 @@ where
             let mut ws = WebSocketStream::from_raw_socket(
                 upgraded,
                 tungstenite::protocol::Role::Server,
-                None,
+                Some(config()),
             )
             .await;
             if let Err(err) = handler(&mut ws).await {
@@ -0,0 +1,631 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Verification of WebAuthn (passkey and security key) responses, as described in the
+//! [Web Authentication spec](https://www.w3.org/TR/webauthn-3/).
+//!
+//! This covers only what a relying party needs: parsing client data, authenticator data, and
+//! COSE public keys, and checking assertion signatures with `ring`. Attestation statements aren't
+//! verified, as with the `none` attestation conveyance preference; a logged-in user may register
+//! whatever authenticator they like. Supported algorithms are ES256, EdDSA (Ed25519), and RS256.
+
+use base::{bail, err, Error};
+use ring::digest::{digest, SHA256};
+
+/// COSE algorithm identifiers offered at registration, in order of preference.
+pub const ALGORITHMS: [i64; 3] = [ALG_ES256, ALG_EDDSA, ALG_RS256];
+
+const ALG_ES256: i64 = -7;
+const ALG_EDDSA: i64 = -8;
+const ALG_RS256: i64 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// The parts of the `CollectedClientData` which the relying party checks.
+#[derive(Debug)]
+pub struct ClientData {
+    pub challenge: Vec<u8>,
+    pub origin: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawClientData {
+    #[serde(rename = "type")]
+    type_: String,
+    challenge: String,
+    origin: String,
+    #[serde(default)]
+    cross_origin: bool,
+}
+
+/// Parses `clientDataJSON`, checking that its type is `expected_type` (`webauthn.create` or
+/// `webauthn.get`). The caller must check the challenge and origin.
+pub fn parse_client_data(json: &[u8], expected_type: &str) -> Result<ClientData, Error> {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+    let raw: RawClientData = serde_json::from_slice(json)
+        .map_err(|e| err!(InvalidArgument, msg("bad clientDataJSON"), source(e)))?;
+    if raw.type_ != expected_type {
+        bail!(
+            InvalidArgument,
+            msg(
+                "clientDataJSON has type {:?}; expected {expected_type:?}",
+                raw.type_
+            )
+        );
+    }
+    if raw.cross_origin {
+        bail!(
+            InvalidArgument,
+            msg("cross-origin WebAuthn requests aren't supported")
+        );
+    }
+    let challenge = URL_SAFE_NO_PAD
+        .decode(&raw.challenge)
+        .map_err(|_| err!(InvalidArgument, msg("bad challenge in clientDataJSON")))?;
+    Ok(ClientData {
+        challenge,
+        origin: raw.origin,
+    })
+}
+
+struct AuthenticatorData<'a> {
+    sign_count: u32,
+
+    /// The credential id and COSE public key, present at registration.
+    attested_credential: Option<(&'a [u8], &'a [u8])>,
+}
+
+/// Parses authenticator data, checking that it's for `rp_id` and that the user was present.
+fn parse_authenticator_data<'a>(
+    data: &'a [u8],
+    rp_id: &str,
+) -> Result<AuthenticatorData<'a>, Error> {
+    let too_short = || err!(InvalidArgument, msg("authenticator data is truncated"));
+    if data.len() < 37 {
+        return Err(too_short());
+    }
+    if data[..32] != *digest(&SHA256, rp_id.as_bytes()).as_ref() {
+        bail!(
+            InvalidArgument,
+            msg("authenticator data is for a different relying party than {rp_id:?}")
+        );
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        bail!(
+            InvalidArgument,
+            msg("authenticator didn't test user presence")
+        );
+    }
+    let sign_count = u32::from_be_bytes(data[33..37].try_into().unwrap());
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // The AAGUID (16 bytes), credential id length (2 bytes), credential id, and public key.
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            return Err(too_short());
+        }
+        let id_len = usize::from(u16::from_be_bytes([rest[16], rest[17]]));
+        let rest = &rest[18..];
+        if rest.len() < id_len {
+            return Err(too_short());
+        }
+        let (id, rest) = rest.split_at(id_len);
+        let (_, after_key) = cbor::decode(rest)?;
+        Some((id, &rest[..rest.len() - after_key.len()]))
+    } else {
+        None
+    };
+    Ok(AuthenticatorData {
+        sign_count,
+        attested_credential,
+    })
+}
+
+/// A credential created at registration, to be stored.
+#[derive(Debug)]
+pub struct NewCredential {
+    pub id: Vec<u8>,
+
+    /// The COSE_Key.
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// Parses the `attestationObject` of a registration response for relying party `rp_id`.
+///
+/// The attestation statement itself is ignored.
+pub fn parse_attestation_object(
+    attestation_object: &[u8],
+    rp_id: &str,
+) -> Result<NewCredential, Error> {
+    let (o, rest) = cbor::decode(attestation_object)?;
+    if !rest.is_empty() {
+        bail!(
+            InvalidArgument,
+            msg("trailing bytes after attestationObject")
+        );
+    }
+    let auth_data = o
+        .get(&cbor::Value::Text("authData"))
+        .and_then(cbor::Value::as_bytes)
+        .ok_or_else(|| err!(InvalidArgument, msg("attestationObject has no authData")))?;
+    let a = parse_authenticator_data(auth_data, rp_id)?;
+    let Some((id, public_key)) = a.attested_credential else {
+        bail!(
+            InvalidArgument,
+            msg("attestationObject has no attested credential")
+        );
+    };
+    PublicKey::parse(public_key)?;
+    Ok(NewCredential {
+        id: id.to_vec(),
+        public_key: public_key.to_vec(),
+        sign_count: a.sign_count,
+    })
+}
+
+/// Verifies an assertion against a credential's stored COSE public key, returning the
+/// authenticator's signature counter. The caller must check the client data separately.
+pub fn verify_assertion(
+    public_key: &[u8],
+    rp_id: &str,
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> Result<u32, Error> {
+    let a = parse_authenticator_data(authenticator_data, rp_id)?;
+    let key = PublicKey::parse(public_key)?;
+    let mut signed = Vec::with_capacity(authenticator_data.len() + 32);
+    signed.extend_from_slice(authenticator_data);
+    signed.extend_from_slice(digest(&SHA256, client_data_json).as_ref());
+    key.verify(&signed, signature)?;
+    Ok(a.sign_count)
+}
+
+/// A public key decoded from a COSE_Key (RFC 9052 section 7, RFC 9053, and RFC 8812).
+#[derive(Debug)]
+enum PublicKey<'a> {
+    /// An uncompressed SEC1 P-256 point.
+    Es256([u8; 65]),
+    Ed25519(&'a [u8]),
+    Rs256 {
+        n: &'a [u8],
+        e: &'a [u8],
+    },
+}
+
+impl<'a> PublicKey<'a> {
+    fn parse(cose: &'a [u8]) -> Result<Self, Error> {
+        let (key, rest) = cbor::decode(cose)?;
+        if !rest.is_empty() {
+            bail!(InvalidArgument, msg("trailing bytes after COSE key"));
+        }
+        let int = |label| {
+            key.get(&cbor::Value::Int(label))
+                .and_then(cbor::Value::as_int)
+        };
+        let bytes = |label| {
+            key.get(&cbor::Value::Int(label))
+                .and_then(cbor::Value::as_bytes)
+                .ok_or_else(|| {
+                    err!(
+                        InvalidArgument,
+                        msg("COSE key is missing parameter {label}")
+                    )
+                })
+        };
+        let (kty, alg) = (int(1), int(3));
+        match (kty, alg) {
+            (Some(2), Some(ALG_ES256)) if int(-1) == Some(1) => {
+                let (x, y) = (bytes(-2)?, bytes(-3)?);
+                if x.len() != 32 || y.len() != 32 {
+                    bail!(InvalidArgument, msg("bad P-256 coordinate length"));
+                }
+                let mut point = [0u8; 65];
+                point[0] = 4;
+                point[1..33].copy_from_slice(x);
+                point[33..].copy_from_slice(y);
+                Ok(PublicKey::Es256(point))
+            }
+            (Some(1), Some(ALG_EDDSA)) if int(-1) == Some(6) => {
+                let x = bytes(-2)?;
+                if x.len() != 32 {
+                    bail!(InvalidArgument, msg("bad Ed25519 key length"));
+                }
+                Ok(PublicKey::Ed25519(x))
+            }
+            (Some(3), Some(ALG_RS256)) => Ok(PublicKey::Rs256 {
+                n: bytes(-1)?,
+                e: bytes(-2)?,
+            }),
+            _ => bail!(
+                InvalidArgument,
+                msg("unsupported COSE key type {kty:?} with algorithm {alg:?}")
+            ),
+        }
+    }
+
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<(), Error> {
+        use ring::signature;
+        let r = match *self {
+            PublicKey::Es256(ref point) => {
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, &point[..])
+                    .verify(msg, signature)
+            }
+            PublicKey::Ed25519(x) => {
+                signature::UnparsedPublicKey::new(&signature::ED25519, x).verify(msg, signature)
+            }
+            PublicKey::Rs256 { n, e } => signature::RsaPublicKeyComponents { n, e }.verify(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                msg,
+                signature,
+            ),
+        };
+        r.map_err(|_| err!(Unauthenticated, msg("bad WebAuthn signature")))
+    }
+}
+
+/// A decoder for the subset of CBOR (RFC 8949) which WebAuthn uses. Notably, WebAuthn requires
+/// definite lengths, so indefinite-length items are rejected.
+mod cbor {
+    use base::{bail, err, Error};
+
+    /// Limits nesting, so that malicious input can't overflow the stack.
+    const MAX_DEPTH: usize = 16;
+
+    #[derive(Debug, PartialEq)]
+    pub enum Value<'a> {
+        Int(i128),
+        Bytes(&'a [u8]),
+        Text(&'a str),
+        Array(Vec<Value<'a>>),
+        Map(Vec<(Value<'a>, Value<'a>)>),
+
+        /// A simple value such as `false`, `true`, or `null`.
+        Simple(u8),
+
+        /// A floating-point number, as its raw bits.
+        Float(u64),
+    }
+
+    impl<'a> Value<'a> {
+        pub fn as_int(&self) -> Option<i64> {
+            match *self {
+                Value::Int(i) => i64::try_from(i).ok(),
+                _ => None,
+            }
+        }
+
+        pub fn as_bytes(&self) -> Option<&'a [u8]> {
+            match *self {
+                Value::Bytes(b) => Some(b),
+                _ => None,
+            }
+        }
+
+        /// Returns the value for `key`, if this is a map which contains it.
+        pub fn get(&self, key: &Value) -> Option<&Value<'a>> {
+            match self {
+                Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+                _ => None,
+            }
+        }
+    }
+
+    fn truncated() -> Error {
+        err!(InvalidArgument, msg("CBOR item is truncated"))
+    }
+
+    /// Decodes a single item, returning it and the bytes following it.
+    pub fn decode(data: &[u8]) -> Result<(Value<'_>, &[u8]), Error> {
+        decode_inner(data, 0)
+    }
+
+    fn decode_inner(data: &[u8], depth: usize) -> Result<(Value<'_>, &[u8]), Error> {
+        if depth > MAX_DEPTH {
+            bail!(InvalidArgument, msg("CBOR is nested too deeply"));
+        }
+        let (&initial, mut rest) = data.split_first().ok_or_else(truncated)?;
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+        let arg = match info {
+            0..=23 => u64::from(info),
+            24..=27 => {
+                let len = 1 << (info - 24);
+                if rest.len() < len {
+                    return Err(truncated());
+                }
+                let (arg, r) = rest.split_at(len);
+                rest = r;
+                arg.iter().fold(0, |acc, &b| (acc << 8) | u64::from(b))
+            }
+            _ => bail!(
+                InvalidArgument,
+                msg("unsupported CBOR additional information {info}")
+            ),
+        };
+        let v = match major {
+            0 => Value::Int(i128::from(arg)),
+            1 => Value::Int(-1 - i128::from(arg)),
+            2 | 3 => {
+                let len = usize::try_from(arg)
+                    .ok()
+                    .filter(|&l| l <= rest.len())
+                    .ok_or_else(truncated)?;
+                let (b, r) = rest.split_at(len);
+                rest = r;
+                if major == 2 {
+                    Value::Bytes(b)
+                } else {
+                    Value::Text(
+                        std::str::from_utf8(b)
+                            .map_err(|_| err!(InvalidArgument, msg("CBOR text isn't UTF-8")))?,
+                    )
+                }
+            }
+            4 => {
+                let mut items = Vec::new();
+                for _ in 0..arg {
+                    let (v, r) = decode_inner(rest, depth + 1)?;
+                    items.push(v);
+                    rest = r;
+                }
+                Value::Array(items)
+            }
+            5 => {
+                let mut entries = Vec::new();
+                for _ in 0..arg {
+                    let (k, r) = decode_inner(rest, depth + 1)?;
+                    let (v, r) = decode_inner(r, depth + 1)?;
+                    entries.push((k, v));
+                    rest = r;
+                }
+                Value::Map(entries)
+            }
+            6 => return decode_inner(rest, depth + 1), // tags are ignored.
+            _ if (25..=27).contains(&info) => Value::Float(arg),
+            _ => Value::Simple(arg as u8),
+        };
+        Ok((v, rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::ErrorKind;
+    use ring::rand::SystemRandom;
+    use ring::signature::{self, KeyPair as _};
+
+    /// Appends a CBOR item header.
+    fn head(out: &mut Vec<u8>, major: u8, arg: u64) {
+        if arg < 24 {
+            out.push((major << 5) | arg as u8);
+        } else if arg < 256 {
+            out.push((major << 5) | 24);
+            out.push(arg as u8);
+        } else {
+            out.push((major << 5) | 25);
+            out.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+    }
+
+    fn int(out: &mut Vec<u8>, i: i64) {
+        if i >= 0 {
+            head(out, 0, i as u64);
+        } else {
+            head(out, 1, (-1 - i) as u64);
+        }
+    }
+
+    fn bytes(out: &mut Vec<u8>, b: &[u8]) {
+        head(out, 2, b.len() as u64);
+        out.extend_from_slice(b);
+    }
+
+    fn text(out: &mut Vec<u8>, t: &str) {
+        head(out, 3, t.len() as u64);
+        out.extend_from_slice(t.as_bytes());
+    }
+
+    fn es256_cose_key(point: &[u8]) -> Vec<u8> {
+        assert_eq!(point.len(), 65);
+        let mut k = Vec::new();
+        head(&mut k, 5, 5);
+        int(&mut k, 1);
+        int(&mut k, 2);
+        int(&mut k, 3);
+        int(&mut k, ALG_ES256);
+        int(&mut k, -1);
+        int(&mut k, 1);
+        int(&mut k, -2);
+        bytes(&mut k, &point[1..33]);
+        int(&mut k, -3);
+        bytes(&mut k, &point[33..]);
+        k
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut d = digest(&SHA256, rp_id.as_bytes()).as_ref().to_vec();
+        d.push(flags);
+        d.extend_from_slice(&sign_count.to_be_bytes());
+        d
+    }
+
+    #[test]
+    fn decode_cbor() {
+        use cbor::Value;
+
+        // Examples from RFC 8949 appendix A.
+        let (v, rest) = cbor::decode(b"\xa2\x01\x02\x03\x82\x04\x05rest").unwrap();
+        assert_eq!(
+            v,
+            Value::Map(vec![
+                (Value::Int(1), Value::Int(2)),
+                (
+                    Value::Int(3),
+                    Value::Array(vec![Value::Int(4), Value::Int(5)])
+                ),
+            ])
+        );
+        assert_eq!(rest, b"rest");
+        assert_eq!(
+            cbor::decode(b"\x39\x01\x00").unwrap().0.as_int(),
+            Some(-257)
+        );
+        assert_eq!(
+            cbor::decode(b"\x3b\xff\xff\xff\xff\xff\xff\xff\xff")
+                .unwrap()
+                .0,
+            Value::Int(-18446744073709551616)
+        );
+        assert_eq!(cbor::decode(b"\x64IETF").unwrap().0, Value::Text("IETF"));
+        assert_eq!(cbor::decode(b"\xf5").unwrap().0, Value::Simple(21));
+        assert_eq!(
+            cbor::decode(b"\xc1\x1a\x51\x4b\x67\xb0").unwrap().0,
+            Value::Int(1363896240)
+        );
+
+        // Indefinite lengths, truncation, and excessive nesting are rejected.
+        cbor::decode(b"\x5f\x42\x01\x02\xff").unwrap_err();
+        cbor::decode(b"\x44\x01\x02").unwrap_err();
+        cbor::decode(b"\x1b\x00").unwrap_err();
+        cbor::decode(&[0x81; 100]).unwrap_err();
+    }
+
+    #[test]
+    fn client_data() {
+        let c = parse_client_data(
+            br#"{"type":"webauthn.get","challenge":"AQID","origin":"https://nvr.example.com"}"#,
+            "webauthn.get",
+        )
+        .unwrap();
+        assert_eq!(c.challenge, [1, 2, 3]);
+        assert_eq!(c.origin, "https://nvr.example.com");
+        parse_client_data(
+            br#"{"type":"webauthn.create","challenge":"AQID","origin":"https://nvr.example.com"}"#,
+            "webauthn.get",
+        )
+        .unwrap_err();
+        parse_client_data(
+            br#"{"type":"webauthn.get","challenge":"AQID","origin":"https://a","crossOrigin":true}"#,
+            "webauthn.get",
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn es256_register_and_assert() {
+        let rng = SystemRandom::new();
+        let alg = &signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let key = signature::EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng).unwrap();
+        let cose_key = es256_cose_key(key.public_key().as_ref());
+
+        // Registration, with the "packed" format's attestation statement ignored.
+        let mut auth_data = authenticator_data(
+            "nvr.example.com",
+            FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA,
+            3,
+        );
+        auth_data.extend_from_slice(&[0; 16]); // AAGUID
+        auth_data.extend_from_slice(&4u16.to_be_bytes());
+        auth_data.extend_from_slice(b"cred");
+        auth_data.extend_from_slice(&cose_key);
+        let mut attestation = Vec::new();
+        head(&mut attestation, 5, 3);
+        text(&mut attestation, "fmt");
+        text(&mut attestation, "packed");
+        text(&mut attestation, "attStmt");
+        head(&mut attestation, 5, 1);
+        text(&mut attestation, "alg");
+        int(&mut attestation, ALG_ES256);
+        text(&mut attestation, "authData");
+        bytes(&mut attestation, &auth_data);
+        let c = parse_attestation_object(&attestation, "nvr.example.com").unwrap();
+        assert_eq!(c.id, b"cred");
+        assert_eq!(c.public_key, cose_key);
+        assert_eq!(c.sign_count, 3);
+        let e = parse_attestation_object(&attestation, "evil.example.com").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArgument);
+
+        // Assertion.
+        let auth_data = authenticator_data("nvr.example.com", FLAG_USER_PRESENT, 4);
+        let client_data =
+            br#"{"type":"webauthn.get","challenge":"AQID","origin":"https://nvr.example.com"}"#;
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(digest(&SHA256, client_data).as_ref());
+        let sig = key.sign(&rng, &signed).unwrap();
+        assert_eq!(
+            verify_assertion(
+                &c.public_key,
+                "nvr.example.com",
+                &auth_data,
+                client_data,
+                sig.as_ref()
+            )
+            .unwrap(),
+            4
+        );
+        let e = verify_assertion(
+            &c.public_key,
+            "nvr.example.com",
+            &auth_data,
+            br#"{"type":"webauthn.get","challenge":"BAUG","origin":"https://nvr.example.com"}"#,
+            sig.as_ref(),
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unauthenticated);
+
+        // User presence is required.
+        let auth_data = authenticator_data("nvr.example.com", 0, 5);
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(digest(&SHA256, client_data).as_ref());
+        let sig = key.sign(&rng, &signed).unwrap();
+        verify_assertion(
+            &c.public_key,
+            "nvr.example.com",
+            &auth_data,
+            client_data,
+            sig.as_ref(),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn ed25519_assert() {
+        let rng = SystemRandom::new();
+        let pkcs8 = signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key = signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let mut cose_key = Vec::new();
+        head(&mut cose_key, 5, 4);
+        int(&mut cose_key, 1);
+        int(&mut cose_key, 1);
+        int(&mut cose_key, 3);
+        int(&mut cose_key, ALG_EDDSA);
+        int(&mut cose_key, -1);
+        int(&mut cose_key, 6);
+        int(&mut cose_key, -2);
+        bytes(&mut cose_key, key.public_key().as_ref());
+        let auth_data = authenticator_data("localhost", FLAG_USER_PRESENT, 0);
+        let client_data =
+            br#"{"type":"webauthn.get","challenge":"AQID","origin":"http://localhost:8080"}"#;
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(digest(&SHA256, client_data).as_ref());
+        let sig = key.sign(&signed);
+        assert_eq!(
+            verify_assertion(
+                &cose_key,
+                "localhost",
+                &auth_data,
+                client_data,
+                sig.as_ref()
+            )
+            .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,651 @@
+// This file is part of Moonfire NVR, a security camera network video recorder.
+// Copyright (C) 2026 The Moonfire NVR Authors; see AUTHORS and LICENSE.txt.
+// SPDX-License-Identifier: GPL-v3.0-or-later WITH GPL-3.0-linking-exception.
+
+//! Streaming ZIP archives of `http_serve::Entity`s.
+//!
+//! Entries are stored uncompressed (video doesn't compress further), so the archive's length is
+//! known before any of it is written. Each entry's CRC-32 isn't known until its data has been
+//! streamed, so it follows the data in a data descriptor, and the archive can only be served
+//! from start to end rather than by byte range. ZIP64 extensions are used only where sizes or
+//! offsets require them.
+//!
+//! An entry may instead be [derived](Derived) from the BLAKE3 digests of the entries before it,
+//! as for a manifest of the archive's contents.
+//!
+//! See the [ZIP file format specification](https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT).
+
+use crate::body::{wrap_error, BoxedError, Chunk};
+use base::{bail, err, Error};
+use byteorder::{LittleEndian, WriteBytesExt};
+use futures::Stream;
+use hyper::body::Buf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const LOCAL_FILE_HEADER_SIG: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x0807_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIG: u32 = 0x0201_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIG: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG: u32 = 0x0706_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIG: u32 = 0x0605_4b50;
+
+/// General purpose flags: sizes and CRC-32 are in a data descriptor (bit 3); the name is UTF-8
+/// (bit 11).
+const FLAGS: u16 = 0x0808;
+
+/// Version 2.0 supports data descriptors; 4.5 supports ZIP64.
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+/// Version made by: Unix (upper byte), specification version 4.5 (lower byte).
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_ZIP64;
+
+/// External attributes: a regular file with mode 0644.
+const EXTERNAL_ATTRIBUTES: u32 = 0o100644 << 16;
+
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// Placeholder for a 32-bit field whose value is in the ZIP64 extra field.
+const U32_OVERFLOW: u32 = 0xFFFF_FFFF;
+
+/// The (non-ZIP64) end of central directory record's length.
+const END_OF_CENTRAL_DIRECTORY_LEN: u64 = 22;
+
+/// The ZIP64 end of central directory record and locator's combined length.
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LEN: u64 = 56 + 20;
+
+/// An archive entry.
+pub struct Entry<E> {
+    pub name: String,
+
+    /// The modification time, as local time.
+    pub modified: time::Tm,
+    pub contents: Contents<E>,
+}
+
+/// An entry's contents.
+pub enum Contents<E> {
+    Entity(E),
+    Derived(Derived),
+}
+
+type DeriveFn = dyn FnOnce(&[blake3::Hash]) -> Vec<u8> + Send;
+
+/// Contents generated when reached in the stream, from the BLAKE3 digests of all preceding
+/// entries' contents. The length must be known in advance.
+pub struct Derived {
+    len: u64,
+    f: Option<Box<DeriveFn>>,
+}
+
+impl Derived {
+    /// Creates contents which `f` will produce. `f` must return exactly `len` bytes.
+    pub fn new(len: u64, f: impl FnOnce(&[blake3::Hash]) -> Vec<u8> + Send + 'static) -> Self {
+        Derived {
+            len,
+            f: Some(Box::new(f)),
+        }
+    }
+}
+
+struct EntryLayout {
+    /// The offset of the entry's local file header.
+    offset: u64,
+    len: u64,
+    zip64: bool,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+/// Converts a local time to MS-DOS time and date fields, clamping to the representable range.
+fn dos_time_date(tm: &time::Tm) -> (u16, u16) {
+    if tm.tm_year < 80 {
+        return (0, (1 << 5) | 1); // 1980-01-01 00:00:00
+    }
+    let time = (tm.tm_hour << 11) | (tm.tm_min << 5) | (tm.tm_sec.min(59) / 2);
+    let date = (std::cmp::min(tm.tm_year - 80, 127) << 9) | ((tm.tm_mon + 1) << 5) | tm.tm_mday;
+    (time as u16, date as u16)
+}
+
+impl EntryLayout {
+    fn local_header_len(&self, name: &str) -> u64 {
+        30 + name.len() as u64 + if self.zip64 { 20 } else { 0 }
+    }
+
+    fn data_descriptor_len(&self) -> u64 {
+        if self.zip64 {
+            24
+        } else {
+            16
+        }
+    }
+
+    /// Returns the total length of the local file header, data, and data descriptor.
+    fn total_len(&self, name: &str) -> u64 {
+        self.local_header_len(name) + self.len + self.data_descriptor_len()
+    }
+
+    fn local_header(&self, name: &str) -> Vec<u8> {
+        let mut v = Vec::with_capacity(self.local_header_len(name) as usize);
+        v.write_u32::<LittleEndian>(LOCAL_FILE_HEADER_SIG).unwrap();
+        v.write_u16::<LittleEndian>(if self.zip64 { VERSION_ZIP64 } else { VERSION })
+            .unwrap();
+        v.write_u16::<LittleEndian>(FLAGS).unwrap();
+        v.write_u16::<LittleEndian>(0).unwrap(); // stored
+        v.write_u16::<LittleEndian>(self.dos_time).unwrap();
+        v.write_u16::<LittleEndian>(self.dos_date).unwrap();
+
+        // The CRC-32 and sizes are in the data descriptor.
+        let size = if self.zip64 { U32_OVERFLOW } else { 0 };
+        v.write_u32::<LittleEndian>(0).unwrap();
+        v.write_u32::<LittleEndian>(size).unwrap();
+        v.write_u32::<LittleEndian>(size).unwrap();
+        v.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+        v.write_u16::<LittleEndian>(if self.zip64 { 20 } else { 0 })
+            .unwrap();
+        v.extend_from_slice(name.as_bytes());
+        if self.zip64 {
+            v.write_u16::<LittleEndian>(ZIP64_EXTRA_ID).unwrap();
+            v.write_u16::<LittleEndian>(16).unwrap();
+            v.write_u64::<LittleEndian>(0).unwrap();
+            v.write_u64::<LittleEndian>(0).unwrap();
+        }
+        v
+    }
+
+    fn data_descriptor(&self, crc: u32) -> Vec<u8> {
+        let mut v = Vec::with_capacity(self.data_descriptor_len() as usize);
+        v.write_u32::<LittleEndian>(DATA_DESCRIPTOR_SIG).unwrap();
+        v.write_u32::<LittleEndian>(crc).unwrap();
+        for _ in 0..2 {
+            if self.zip64 {
+                v.write_u64::<LittleEndian>(self.len).unwrap();
+            } else {
+                v.write_u32::<LittleEndian>(self.len as u32).unwrap();
+            }
+        }
+        v
+    }
+
+    /// Appends the entry's central directory header.
+    fn central_header(&self, name: &str, crc: u32, v: &mut Vec<u8>) {
+        let offset_zip64 = self.offset >= u64::from(U32_OVERFLOW);
+        let mut extra = Vec::new();
+        if self.zip64 {
+            extra.write_u64::<LittleEndian>(self.len).unwrap();
+            extra.write_u64::<LittleEndian>(self.len).unwrap();
+        }
+        if offset_zip64 {
+            extra.write_u64::<LittleEndian>(self.offset).unwrap();
+        }
+        v.write_u32::<LittleEndian>(CENTRAL_DIRECTORY_HEADER_SIG)
+            .unwrap();
+        v.write_u16::<LittleEndian>(VERSION_MADE_BY).unwrap();
+        v.write_u16::<LittleEndian>(if self.zip64 || offset_zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION
+        })
+        .unwrap();
+        v.write_u16::<LittleEndian>(FLAGS).unwrap();
+        v.write_u16::<LittleEndian>(0).unwrap(); // stored
+        v.write_u16::<LittleEndian>(self.dos_time).unwrap();
+        v.write_u16::<LittleEndian>(self.dos_date).unwrap();
+        v.write_u32::<LittleEndian>(crc).unwrap();
+        let size = if self.zip64 {
+            U32_OVERFLOW
+        } else {
+            self.len as u32
+        };
+        v.write_u32::<LittleEndian>(size).unwrap();
+        v.write_u32::<LittleEndian>(size).unwrap();
+        v.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+        v.write_u16::<LittleEndian>(if extra.is_empty() {
+            0
+        } else {
+            4 + extra.len() as u16
+        })
+        .unwrap();
+        v.write_u16::<LittleEndian>(0).unwrap(); // comment length
+        v.write_u16::<LittleEndian>(0).unwrap(); // disk number start
+        v.write_u16::<LittleEndian>(0).unwrap(); // internal attributes
+        v.write_u32::<LittleEndian>(EXTERNAL_ATTRIBUTES).unwrap();
+        v.write_u32::<LittleEndian>(if offset_zip64 {
+            U32_OVERFLOW
+        } else {
+            self.offset as u32
+        })
+        .unwrap();
+        v.extend_from_slice(name.as_bytes());
+        if !extra.is_empty() {
+            v.write_u16::<LittleEndian>(ZIP64_EXTRA_ID).unwrap();
+            v.write_u16::<LittleEndian>(extra.len() as u16).unwrap();
+            v.extend_from_slice(&extra);
+        }
+    }
+}
+
+/// A ZIP archive of entities, served as a single stream.
+pub struct Archive<E> {
+    entries: Vec<Entry<E>>,
+    layouts: Vec<EntryLayout>,
+
+    /// The offset and length of the central directory.
+    central_directory: (u64, u64),
+    len: u64,
+}
+
+impl<E> Archive<E>
+where
+    E: http_serve::Entity<Data = Chunk, Error = BoxedError>,
+{
+    pub fn new(entries: Vec<Entry<E>>) -> Result<Self, Error> {
+        let mut layouts = Vec::with_capacity(entries.len());
+        let mut offset = 0;
+        let mut central_len = 0;
+        for e in &entries {
+            if e.name.len() > usize::from(u16::MAX) {
+                bail!(InvalidArgument, msg("zip entry name too long"));
+            }
+            let (dos_time, dos_date) = dos_time_date(&e.modified);
+            let len = match &e.contents {
+                Contents::Entity(entity) => entity.len(),
+                Contents::Derived(d) => d.len,
+            };
+            let l = EntryLayout {
+                offset,
+                len,
+                zip64: len >= u64::from(U32_OVERFLOW),
+                dos_time,
+                dos_date,
+            };
+            offset += l.total_len(&e.name);
+            let mut central_header = Vec::new();
+            l.central_header(&e.name, 0, &mut central_header);
+            central_len += central_header.len() as u64;
+            layouts.push(l);
+        }
+        let mut archive = Archive {
+            entries,
+            layouts,
+            central_directory: (offset, central_len),
+            len: 0,
+        };
+        archive.len = offset + central_len + archive.end_of_central_directory().len() as u64;
+        Ok(archive)
+    }
+
+    /// Returns the archive's total length.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn needs_zip64_end(&self) -> bool {
+        let (offset, len) = self.central_directory;
+        offset >= u64::from(U32_OVERFLOW)
+            || len >= u64::from(U32_OVERFLOW)
+            || self.entries.len() >= usize::from(u16::MAX)
+    }
+
+    fn end_of_central_directory(&self) -> Vec<u8> {
+        let (offset, len) = self.central_directory;
+        let n = self.entries.len() as u64;
+        let mut v = Vec::with_capacity(
+            (END_OF_CENTRAL_DIRECTORY_LEN + ZIP64_END_OF_CENTRAL_DIRECTORY_LEN) as usize,
+        );
+        let zip64 = self.needs_zip64_end();
+        if zip64 {
+            v.write_u32::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY_SIG)
+                .unwrap();
+            v.write_u64::<LittleEndian>(44).unwrap(); // size of the remaining record
+            v.write_u16::<LittleEndian>(VERSION_MADE_BY).unwrap();
+            v.write_u16::<LittleEndian>(VERSION_ZIP64).unwrap();
+            v.write_u32::<LittleEndian>(0).unwrap(); // this disk
+            v.write_u32::<LittleEndian>(0).unwrap(); // disk with the central directory
+            v.write_u64::<LittleEndian>(n).unwrap();
+            v.write_u64::<LittleEndian>(n).unwrap();
+            v.write_u64::<LittleEndian>(len).unwrap();
+            v.write_u64::<LittleEndian>(offset).unwrap();
+
+            v.write_u32::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG)
+                .unwrap();
+            v.write_u32::<LittleEndian>(0).unwrap(); // disk with the ZIP64 record
+            v.write_u64::<LittleEndian>(offset + len).unwrap();
+            v.write_u32::<LittleEndian>(1).unwrap(); // total disks
+        }
+        let n16 = if zip64 { u16::MAX } else { n as u16 };
+        let u32_or_overflow = |x: u64| {
+            if zip64 {
+                U32_OVERFLOW
+            } else {
+                x as u32
+            }
+        };
+        v.write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY_SIG)
+            .unwrap();
+        v.write_u16::<LittleEndian>(0).unwrap(); // this disk
+        v.write_u16::<LittleEndian>(0).unwrap(); // disk with the central directory
+        v.write_u16::<LittleEndian>(n16).unwrap();
+        v.write_u16::<LittleEndian>(n16).unwrap();
+        v.write_u32::<LittleEndian>(u32_or_overflow(len)).unwrap();
+        v.write_u32::<LittleEndian>(u32_or_overflow(offset))
+            .unwrap();
+        v.write_u16::<LittleEndian>(0).unwrap(); // comment length
+        v
+    }
+
+    /// Returns a stream of the whole archive.
+    pub fn into_stream(self) -> ArchiveStream<E> {
+        let any_derived = self
+            .entries
+            .iter()
+            .any(|e| matches!(e.contents, Contents::Derived(_)));
+        ArchiveStream {
+            crcs: Vec::with_capacity(self.entries.len()),
+            digests: any_derived.then(|| Vec::with_capacity(self.entries.len())),
+            archive: self,
+            state: State::Header(0),
+        }
+    }
+}
+
+enum State {
+    /// Next is the local file header of the given entry.
+    Header(usize),
+
+    /// Streaming the given entry's data.
+    Data {
+        i: usize,
+        data: Pin<Box<dyn Stream<Item = Result<Chunk, BoxedError>> + Send + Sync>>,
+        crc: flate2::Crc,
+
+        /// The digest so far, if any entry is derived from digests.
+        hasher: Option<blake3::Hasher>,
+        remaining: u64,
+    },
+
+    /// Next is the central directory.
+    CentralDirectory,
+    Done,
+}
+
+pub struct ArchiveStream<E> {
+    archive: Archive<E>,
+    crcs: Vec<u32>,
+
+    /// The BLAKE3 digest of each entry streamed so far, if any entry is derived from them.
+    digests: Option<Vec<blake3::Hash>>,
+    state: State,
+}
+
+impl<E> Stream for ArchiveStream<E>
+where
+    E: http_serve::Entity<Data = Chunk, Error = BoxedError>,
+{
+    type Item = Result<Chunk, BoxedError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            match &mut this.state {
+                State::Header(i) => {
+                    let i = *i;
+                    let Some(e) = this.archive.entries.get_mut(i) else {
+                        this.state = State::CentralDirectory;
+                        continue;
+                    };
+                    let l = &this.archive.layouts[i];
+                    let header = l.local_header(&e.name);
+                    let data: Pin<Box<dyn Stream<Item = Result<Chunk, BoxedError>> + Send + Sync>> =
+                        match &mut e.contents {
+                            Contents::Entity(entity) => entity.get_range(0..l.len),
+                            Contents::Derived(d) => {
+                                let f = d.f.take().expect("derived entry is only generated once");
+                                let digests = this.digests.as_deref().expect("digests are kept");
+                                let chunk = Chunk::from(f(digests));
+                                Box::pin(futures::stream::once(
+                                    futures::future::ok::<_, BoxedError>(chunk),
+                                ))
+                            }
+                        };
+                    this.state = State::Data {
+                        i,
+                        data,
+                        crc: flate2::Crc::new(),
+                        hasher: this.digests.as_ref().map(|_| blake3::Hasher::new()),
+                        remaining: l.len,
+                    };
+                    return Poll::Ready(Some(Ok(header.into())));
+                }
+                State::Data {
+                    i,
+                    data,
+                    crc,
+                    hasher,
+                    remaining,
+                } => match data.as_mut().poll_next(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        let len = chunk.remaining() as u64;
+                        if len > *remaining {
+                            let e = err!(
+                                Internal,
+                                msg(
+                                    "zip entry {:?} is longer than expected",
+                                    this.archive.entries[*i].name
+                                )
+                            );
+                            this.state = State::Done;
+                            return Poll::Ready(Some(Err(wrap_error(e))));
+                        }
+                        *remaining -= len;
+                        crc.update(chunk.chunk());
+                        if let Some(h) = hasher {
+                            h.update(chunk.chunk());
+                        }
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    Poll::Ready(Some(Err(e))) => {
+                        this.state = State::Done;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Poll::Ready(None) if *remaining > 0 => {
+                        let e = err!(
+                            Internal,
+                            msg(
+                                "zip entry {:?} ended {} bytes early",
+                                this.archive.entries[*i].name,
+                                remaining
+                            )
+                        );
+                        this.state = State::Done;
+                        return Poll::Ready(Some(Err(wrap_error(e))));
+                    }
+                    Poll::Ready(None) => {
+                        let (i, crc) = (*i, crc.sum());
+                        this.crcs.push(crc);
+                        if let (Some(d), Some(h)) = (this.digests.as_mut(), hasher) {
+                            d.push(h.finalize());
+                        }
+                        this.state = State::Header(i + 1);
+                        let descriptor = this.archive.layouts[i].data_descriptor(crc);
+                        return Poll::Ready(Some(Ok(descriptor.into())));
+                    }
+                },
+                State::CentralDirectory => {
+                    let a = &this.archive;
+                    let mut v = Vec::with_capacity(a.central_directory.1 as usize);
+                    for ((e, l), &crc) in a.entries.iter().zip(&a.layouts).zip(&this.crcs) {
+                        l.central_header(&e.name, crc, &mut v);
+                    }
+                    v.extend_from_slice(&a.end_of_central_directory());
+                    this.state = State::Done;
+                    return Poll::Ready(Some(Ok(v.into())));
+                }
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+    use futures::stream::{self, TryStreamExt};
+    use http::header::HeaderValue;
+    use std::ops::Range;
+    use std::time::SystemTime;
+
+    /// An entity of static bytes, served in small chunks.
+    struct Bytes(&'static [u8]);
+
+    impl http_serve::Entity for Bytes {
+        type Data = Chunk;
+        type Error = BoxedError;
+
+        fn add_headers(&self, _: &mut http::header::HeaderMap) {}
+        fn etag(&self) -> Option<HeaderValue> {
+            None
+        }
+        fn len(&self) -> u64 {
+            self.0.len() as u64
+        }
+        fn get_range(
+            &self,
+            range: Range<u64>,
+        ) -> Pin<Box<dyn Stream<Item = Result<Chunk, BoxedError>> + Send + Sync>> {
+            let data: &'static [u8] = self.0;
+            let data = &data[range.start as usize..range.end as usize];
+            Box::pin(stream::iter(
+                data.chunks(3)
+                    .map(|c| Ok(Chunk::from(c)))
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        fn last_modified(&self) -> Option<SystemTime> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let modified = time::at_utc(time::Timespec {
+            sec: 1_430_006_400,
+            nsec: 0,
+        }); // 2015-04-26 00:00:00
+        let contents: [(&str, &'static [u8]); 3] = [
+            ("a.mp4", b"hello world"),
+            ("empty.mp4", b""),
+            ("b.mp4", b"some more video data"),
+        ];
+        let archive = Archive::new(
+            contents
+                .iter()
+                .map(|&(name, data)| Entry {
+                    name: name.to_owned(),
+                    modified,
+                    contents: Contents::Entity(Bytes(data)),
+                })
+                .collect(),
+        )
+        .unwrap();
+        let len = archive.len();
+        let mut v = Vec::new();
+        archive
+            .into_stream()
+            .try_for_each(|c| {
+                v.extend_from_slice(c.chunk());
+                futures::future::ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(v.len() as u64, len);
+
+        // Walk the central directory from the end record, checking each entry's local header,
+        // data, and CRC-32.
+        let end = &v[v.len() - 22..];
+        assert_eq!(LittleEndian::read_u32(end), END_OF_CENTRAL_DIRECTORY_SIG);
+        assert_eq!(LittleEndian::read_u16(&end[10..]), 3);
+        let mut p = LittleEndian::read_u32(&end[16..]) as usize;
+        assert_eq!(
+            p + LittleEndian::read_u32(&end[12..]) as usize,
+            v.len() - 22
+        );
+        for (name, data) in contents {
+            let h = &v[p..];
+            assert_eq!(LittleEndian::read_u32(h), CENTRAL_DIRECTORY_HEADER_SIG);
+            assert_eq!(LittleEndian::read_u16(&h[12..]), 0); // 00:00:00
+            assert_eq!(LittleEndian::read_u16(&h[14..]), (35 << 9) | (4 << 5) | 26);
+            let crc = LittleEndian::read_u32(&h[16..]);
+            assert_eq!(LittleEndian::read_u32(&h[20..]) as usize, data.len());
+            assert_eq!(LittleEndian::read_u32(&h[24..]) as usize, data.len());
+            let name_len = LittleEndian::read_u16(&h[28..]) as usize;
+            assert_eq!(&h[46..46 + name_len], name.as_bytes());
+            let offset = LittleEndian::read_u32(&h[42..]) as usize;
+            p += 46 + name_len;
+
+            let local = &v[offset..];
+            assert_eq!(LittleEndian::read_u32(local), LOCAL_FILE_HEADER_SIG);
+            let local_name_len = LittleEndian::read_u16(&local[26..]) as usize;
+            assert_eq!(&local[30..30 + local_name_len], name.as_bytes());
+            let data_start = 30 + local_name_len;
+            assert_eq!(&local[data_start..data_start + data.len()], data);
+            let mut expected_crc = flate2::Crc::new();
+            expected_crc.update(data);
+            assert_eq!(crc, expected_crc.sum());
+            let descriptor = &local[data_start + data.len()..];
+            assert_eq!(LittleEndian::read_u32(descriptor), DATA_DESCRIPTOR_SIG);
+            assert_eq!(LittleEndian::read_u32(&descriptor[4..]), crc);
+        }
+    }
+
+    #[tokio::test]
+    async fn derived() {
+        let modified = time::at_utc(time::Timespec { sec: 0, nsec: 0 });
+        let data: &'static [u8] = b"hello world";
+        let archive = Archive::new(vec![
+            Entry {
+                name: "a.mp4".to_owned(),
+                modified,
+                contents: Contents::Entity(Bytes(data)),
+            },
+            Entry {
+                name: "digests".to_owned(),
+                modified,
+                contents: Contents::Derived(Derived::new(64, |digests| {
+                    digests
+                        .iter()
+                        .map(|d| d.to_hex().to_string())
+                        .collect::<String>()
+                        .into_bytes()
+                })),
+            },
+        ])
+        .unwrap();
+        let len = archive.len();
+        let mut v = Vec::new();
+        archive
+            .into_stream()
+            .try_for_each(|c| {
+                v.extend_from_slice(c.chunk());
+                futures::future::ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(v.len() as u64, len);
+        let expected = blake3::hash(data).to_hex();
+        let p = v
+            .windows(expected.len())
+            .position(|w| w == expected.as_bytes())
+            .expect("derived contents should be in the archive");
+        assert_eq!(&v[p - "digests".len()..p], b"digests");
+    }
+
+    #[test]
+    fn dos_time_date_clamps() {
+        let tm = time::at_utc(time::Timespec { sec: 0, nsec: 0 }); // 1970
+        assert_eq!(dos_time_date(&tm), (0, (1 << 5) | 1));
+    }
+}
@@ -43,6 +43,11 @@ interface PermissionCheckboxDefinition {
 
 const PERMISSION_CHECKBOXES: PermissionCheckboxDefinition[] = [
   { propName: "adminUsers", label: "Administer users" },
+  {
+    propName: "downloadRecordings",
+    label: "Download recordings",
+    helpText: "Allow viewing and downloading recorded video.",
+  },
   {
     propName: "readCameraConfigs",
     label: "Read camera configs",
@@ -54,7 +59,11 @@ const PERMISSION_CHECKBOXES: PermissionCheckboxDefinition[] = [
     label: "Update signals",
     helpText: "Allow updating 'signals' such as motion detection state.",
   },
-  { propName: "viewVideo", label: "View video" },
+  {
+    propName: "viewLive",
+    label: "View live",
+    helpText: "Allow watching live streams and their last few seconds.",
+  },
 ];
 
 // A group of form controls that's visually separated from the others.
@@ -186,9 +186,10 @@ export interface ToplevelResponse {
 
 export interface Permissions {
   adminUsers?: boolean;
+  downloadRecordings?: boolean;
   readCameraConfigs?: boolean;
   updateSignals?: boolean;
-  viewVideo?: boolean;
+  viewLive?: boolean;
 }
 
 export interface ToplevelUser {
@@ -392,10 +393,16 @@ export interface Recording {
   endTime90k: number;
 
   /**
-   * a reference to an entry in the videoSampleEntries object.
+   * a reference to an entry in the videoSampleEntries object. If the entry
+   * changes within this recording, this is the first.
    */
   videoSampleEntryId: number;
 
+  /**
+   * points where the video sample entry changes, e.g. on a resolution change.
+   */
+  videoSampleEntryChanges?: VideoSampleEntryChange[];
+
   /**
    * the number of samples (aka frames) of video in this recording.
    */
@@ -410,6 +417,22 @@ export interface Recording {
    * the reason this recording ended, if any/known.
    */
   endReason?: string;
+
+  /**
+   * the number of RTP packets lost while recording, if any.
+   */
+  packetsLost?: number;
+
+  /**
+   * the number of frames likely damaged by lost packets, if any.
+   */
+  damagedFrames?: number;
+}
+
+export interface VideoSampleEntryChange {
+  id: number;
+  startTime90k: number;
+  videoSampleEntryId: number;
 }
 
 export interface VideoSampleEntry {
@@ -17,6 +17,10 @@ export interface Camera {
   uuid: string;
   shortName: string;
   description: string;
+  location?: string;
+  coordinates?: { latitude: number; longitude: number };
+  directionDeg?: number;
+  tags?: string[];
   streams: Partial<Record<StreamType, Stream>>;
 }
 
@@ -14,6 +14,7 @@ export default defineConfig({
   plugins: [
     react(),
     viteCompression(),
+    viteCompression({ algorithm: "brotliCompress" }),
     viteLegacyPlugin({
       targets: ["defaults", "fully supports es6-module"],
     }),